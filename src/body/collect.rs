@@ -0,0 +1,268 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{self, Poll};
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_util::ready;
+use http_body::Body;
+use hyper::rt::{Sleep, Timer};
+use pin_project_lite::pin_project;
+
+use crate::rt::TokioTimer;
+
+/// Buffer an entire body into memory, with a cap on its size and an
+/// optional deadline.
+///
+/// Naively collecting a body received from a peer lets that peer decide how
+/// much memory is allocated, and for how long. This instead fails with a
+/// [`CollectError`] as soon as more than `max_bytes` of data have been
+/// received, or if the body has not completed before `timeout` elapses.
+///
+/// The buffer is allocated for the lower bound of the body's size hint,
+/// such as its `content-length`, or for a size given with
+/// [`CollectWithLimit::expected_size`], both capped at `max_bytes`.
+///
+/// Trailers are discarded.
+///
+/// # Example
+///
+/// ```
+/// # async fn run() -> Result<(), hyper_util::body::CollectError> {
+/// use std::time::Duration;
+/// use bytes::Bytes;
+/// use http_body_util::Full;
+/// use hyper_util::body::collect_with_limit;
+///
+/// let body = Full::new(Bytes::from_static(b"hello"));
+/// let bytes = collect_with_limit(body, 1024, Duration::from_secs(5)).await?;
+/// assert_eq!(bytes, "hello");
+/// # Ok(())
+/// # }
+/// ```
+pub fn collect_with_limit<B, D>(body: B, max_bytes: usize, timeout: D) -> CollectWithLimit<B>
+where
+    B: Body,
+    D: Into<Option<Duration>>,
+{
+    CollectWithLimit {
+        body,
+        buf: BytesMut::new(),
+        max_bytes,
+        sleep: timeout.into().map(|dur| TokioTimer::new().sleep(dur)),
+    }
+}
+
+pin_project! {
+    /// A `Future` returned by [`collect_with_limit`].
+    #[must_use = "futures do nothing unless polled"]
+    pub struct CollectWithLimit<B> {
+        #[pin]
+        body: B,
+        buf: BytesMut,
+        max_bytes: usize,
+        sleep: Option<Pin<Box<dyn Sleep>>>,
+    }
+}
+
+impl<B> CollectWithLimit<B> {
+    /// Allocate the buffer for a body of `size` bytes upfront, capped at
+    /// the limit, rather than growing it as data arrives.
+    ///
+    /// This suits a size the caller knows to expect, like the response size
+    /// of a legacy client `Expectation`, when the body has no
+    /// `content-length`.
+    pub fn expected_size(mut self, size: u64) -> Self {
+        let size = size.min(self.max_bytes as u64) as usize;
+        self.buf.reserve(size.saturating_sub(self.buf.len()));
+        self
+    }
+}
+
+impl<B> fmt::Debug for CollectWithLimit<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CollectWithLimit")
+            .field("buffered", &self.buf.len())
+            .field("max_bytes", &self.max_bytes)
+            .finish()
+    }
+}
+
+impl<B> Future for CollectWithLimit<B>
+where
+    B: Body,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Output = Result<Bytes, CollectError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+
+        if let Some(sleep) = this.sleep.as_mut() {
+            if sleep.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(CollectError::new(Kind::Timeout, None)));
+            }
+        }
+
+        // Refuse early if the body already claims to be too big.
+        if this.body.size_hint().lower() > (*this.max_bytes - this.buf.len()) as u64 {
+            return Poll::Ready(Err(CollectError::new(Kind::Overflow, None)));
+        }
+        if this.buf.capacity() == 0 {
+            // within the limit, as checked above
+            this.buf.reserve(this.body.size_hint().lower() as usize);
+        }
+
+        loop {
+            let frame = match ready!(this.body.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => frame,
+                Some(Err(err)) => {
+                    return Poll::Ready(Err(CollectError::new(Kind::Body, Some(err.into()))))
+                }
+                None => return Poll::Ready(Ok(std::mem::take(this.buf).freeze())),
+            };
+
+            if let Ok(mut data) = frame.into_data() {
+                if data.remaining() > *this.max_bytes - this.buf.len() {
+                    return Poll::Ready(Err(CollectError::new(Kind::Overflow, None)));
+                }
+                while data.has_remaining() {
+                    let chunk = data.chunk();
+                    let len = chunk.len();
+                    this.buf.put_slice(chunk);
+                    data.advance(len);
+                }
+            }
+        }
+    }
+}
+
+/// An error returned by [`collect_with_limit`].
+pub struct CollectError {
+    kind: Kind,
+    source: Option<Box<dyn StdError + Send + Sync>>,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Body,
+    Overflow,
+    Timeout,
+}
+
+impl CollectError {
+    fn new(kind: Kind, source: Option<Box<dyn StdError + Send + Sync>>) -> Self {
+        CollectError { kind, source }
+    }
+
+    /// Returns true if the body was larger than the allowed limit.
+    pub fn is_overflow(&self) -> bool {
+        matches!(self.kind, Kind::Overflow)
+    }
+
+    /// Returns true if the body did not complete before the timeout.
+    pub fn is_timeout(&self) -> bool {
+        matches!(self.kind, Kind::Timeout)
+    }
+
+    /// Returns true if the body itself returned an error.
+    pub fn is_body(&self) -> bool {
+        matches!(self.kind, Kind::Body)
+    }
+}
+
+impl fmt::Debug for CollectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_tuple("hyper_util::body::CollectError");
+        f.field(&self.kind);
+        if let Some(ref cause) = self.source {
+            f.field(cause);
+        }
+        f.finish()
+    }
+}
+
+impl fmt::Display for CollectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.kind {
+            Kind::Body => "error reading a body",
+            Kind::Overflow => "body exceeded the length limit",
+            Kind::Timeout => "body was not received in time",
+        })
+    }
+}
+
+impl StdError for CollectError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| &**e as _)
+    }
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures_util::stream;
+    use http_body::Frame;
+    use http_body_util::{Full, StreamBody};
+
+    use super::collect_with_limit;
+
+    #[tokio::test]
+    async fn collects_within_limit() {
+        let chunks = vec![
+            Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"hello "))),
+            Ok(Frame::data(Bytes::from_static(b"world"))),
+        ];
+        let body = StreamBody::new(stream::iter(chunks));
+
+        let bytes = collect_with_limit(body, 11, None).await.unwrap();
+        assert_eq!(bytes, "hello world");
+    }
+
+    #[tokio::test]
+    async fn preallocates_expected_size() {
+        let chunks = vec![Ok::<_, Infallible>(Frame::data(Bytes::from_static(
+            b"hello",
+        )))];
+        let body = StreamBody::new(stream::iter(chunks));
+
+        let collect = collect_with_limit(body, 1024, None).expected_size(4096);
+        assert_eq!(collect.buf.capacity(), 1024, "capped at the limit");
+        assert_eq!(collect.await.unwrap(), "hello");
+    }
+
+    #[tokio::test]
+    async fn rejects_size_hint_over_limit() {
+        let body = Full::new(Bytes::from_static(b"hello world"));
+
+        let err = collect_with_limit(body, 5, None).await.unwrap_err();
+        assert!(err.is_overflow());
+    }
+
+    #[tokio::test]
+    async fn rejects_streamed_data_over_limit() {
+        let chunks = vec![
+            Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"hello "))),
+            Ok(Frame::data(Bytes::from_static(b"world"))),
+        ];
+        let body = StreamBody::new(stream::iter(chunks));
+
+        let err = collect_with_limit(body, 8, None).await.unwrap_err();
+        assert!(err.is_overflow());
+    }
+
+    #[tokio::test]
+    async fn times_out_on_slow_body() {
+        let body = StreamBody::new(stream::pending::<Result<Frame<Bytes>, Infallible>>());
+
+        let err = collect_with_limit(body, 1024, Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout());
+    }
+}
@@ -0,0 +1,172 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{self, Poll};
+use std::time::Duration;
+
+use http_body::{Body, Frame, SizeHint};
+use hyper::rt::{Sleep, Timer};
+use pin_project_lite::pin_project;
+
+use crate::rt::TokioTimer;
+
+pin_project! {
+    /// A body that fails if no frame arrives for a while.
+    ///
+    /// The overall timeout of a request doesn't suit long streams, such as
+    /// Server-Sent Events, which may rightly last for hours. This instead
+    /// bounds the time between frames: once `timeout` passes without one,
+    /// the body yields an `io::Error` of kind `TimedOut`, and ends.
+    ///
+    /// The clock starts when the body is first polled. Dropping a response
+    /// body of the legacy `Client` before its end closes its HTTP/1
+    /// connection instead of reusing it, and resets its HTTP/2 stream, so
+    /// a stalled connection isn't handed to another request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use bytes::Bytes;
+    /// use http_body_util::Full;
+    /// use hyper_util::body::IdleTimeout;
+    ///
+    /// let body = IdleTimeout::new(Full::new(Bytes::from("data: hi\n\n")), Duration::from_secs(30));
+    /// # drop(body);
+    /// ```
+    pub struct IdleTimeout<B> {
+        #[pin]
+        inner: B,
+        timeout: Duration,
+        sleep: Option<Pin<Box<dyn Sleep>>>,
+        timer: TokioTimer,
+        done: bool,
+    }
+}
+
+// ===== impl IdleTimeout =====
+
+impl<B> IdleTimeout<B> {
+    /// Wrap a body, failing it if no frame arrives for `timeout`.
+    pub fn new(inner: B, timeout: Duration) -> IdleTimeout<B> {
+        IdleTimeout {
+            inner,
+            timeout,
+            sleep: None,
+            timer: TokioTimer::new(),
+            done: false,
+        }
+    }
+
+    /// Get a reference to the inner body.
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    /// Consume this wrapper, returning the inner body.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+
+    /// Returns true if the body timed out.
+    pub fn is_timed_out(&self) -> bool {
+        self.done
+    }
+}
+
+impl<B> Body for IdleTimeout<B>
+where
+    B: Body,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Data = B::Data;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let me = self.project();
+        if *me.done {
+            return Poll::Ready(None);
+        }
+
+        match me.inner.poll_frame(cx) {
+            Poll::Ready(frame) => {
+                if let Some(sleep) = me.sleep.as_mut() {
+                    me.timer.reset(sleep, me.timer.now() + *me.timeout);
+                }
+                return Poll::Ready(frame.map(|res| res.map_err(Into::into)));
+            }
+            Poll::Pending => {}
+        }
+
+        let timeout = *me.timeout;
+        let timer = &*me.timer;
+        let sleep = me.sleep.get_or_insert_with(|| timer.sleep(timeout));
+        if sleep.as_mut().poll(cx).is_ready() {
+            *me.done = true;
+            return Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "no body frame received in time",
+            )
+            .into())));
+        }
+        Poll::Pending
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.done || self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> fmt::Debug for IdleTimeout<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdleTimeout")
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use http_body::Frame;
+    use http_body_util::{BodyExt, StreamBody};
+
+    use super::IdleTimeout;
+
+    #[tokio::test]
+    async fn fails_once_frames_stall() {
+        let (tx, rx) = tokio::sync::mpsc::channel::<Result<Frame<Bytes>, io::Error>>(1);
+        let stream = futures_util::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|frame| (frame, rx))
+        });
+        let mut body = Box::pin(IdleTimeout::new(
+            StreamBody::new(stream),
+            Duration::from_millis(50),
+        ));
+
+        // frames arriving in time keep the body going
+        for _ in 0..3 {
+            tx.send(Ok(Frame::data(Bytes::from("a")))).await.unwrap();
+            let frame = body.frame().await.unwrap().unwrap();
+            assert_eq!(frame.into_data().unwrap(), "a");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        let err = body.frame().await.unwrap().unwrap_err();
+        let err = err.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(body.frame().await.is_none());
+        drop(tx);
+    }
+}
@@ -0,0 +1,38 @@
+//! Body utilities.
+//!
+//! This module contains:
+//!
+//! - [`collect_with_limit`] to buffer a body into memory while bounding
+//!   both its size and the time spent reading it.
+//! - [`IdleTimeout`] to fail a body when no data arrives for a while.
+//! - [`Pausable`] to pause and resume sending a body.
+//! - [`Progress`] to report how much of a body has been transferred.
+//! - [`ReplayBody`] to send a streaming body more than once.
+//! - [`multipart`] to encode and parse `multipart/form-data` bodies.
+//! - [`sse`] to send and receive Server-Sent Events.
+//! - [`Tee`] to copy the data of a body into a sink as it is sent, such as
+//!   to compute a `content-digest` trailer.
+
+#[cfg(feature = "tokio")]
+mod collect;
+#[cfg(feature = "tokio")]
+mod idle;
+#[cfg(feature = "body-multipart")]
+pub mod multipart;
+mod pause;
+mod progress;
+mod replay;
+#[cfg(feature = "body-sse")]
+pub mod sse;
+mod tee;
+
+#[cfg(feature = "tokio")]
+pub use self::collect::{collect_with_limit, CollectError, CollectWithLimit};
+#[cfg(feature = "tokio")]
+pub use self::idle::IdleTimeout;
+pub use self::pause::{Pausable, PauseHandle};
+pub use self::progress::{Progress, ProgressUpdate, ReportProgress};
+pub use self::replay::ReplayBody;
+#[cfg(feature = "body-digest")]
+pub use self::tee::ContentDigest;
+pub use self::tee::{Tee, TeeSink};
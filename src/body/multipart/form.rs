@@ -0,0 +1,373 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use bytes::{Buf, Bytes};
+use futures_util::ready;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+
+use super::{BoxError, CRLF};
+use crate::common::rand::random_u64;
+
+type BoxBody = Pin<Box<dyn Body<Data = Bytes, Error = BoxError> + Send>>;
+
+/// A `multipart/form-data` form, to be sent as a request body.
+///
+/// # Example
+///
+/// ```
+/// use http::header::{HeaderValue, CONTENT_TYPE};
+/// use hyper_util::body::multipart::{Form, Part};
+///
+/// let form = Form::new()
+///     .text("user", "sean")
+///     .part(
+///         "avatar",
+///         Part::bytes(&b"\x89PNG"[..])
+///             .file_name("avatar.png")
+///             .content_type(HeaderValue::from_static("image/png")),
+///     );
+///
+/// let req = http::Request::post("http://example.local/upload")
+///     .header(CONTENT_TYPE, form.content_type())
+///     .body(form.into_body())
+///     .unwrap();
+/// # drop(req);
+/// ```
+pub struct Form {
+    boundary: String,
+    parts: VecDeque<(String, Part)>,
+}
+
+/// A single field of a [`Form`].
+pub struct Part {
+    file_name: Option<String>,
+    headers: HeaderMap,
+    data: PartData,
+}
+
+enum PartData {
+    Bytes(Bytes),
+    Body(BoxBody),
+}
+
+/// The `Body` of an encoded [`Form`].
+///
+/// The body reports an exact `size_hint` as long as every streamed part
+/// also reports one, so HTTP/1 requests can use a `content-length`.
+pub struct FormBody {
+    boundary: String,
+    parts: VecDeque<(String, Part)>,
+    queued: VecDeque<Bytes>,
+    current: Option<BoxBody>,
+    finished: bool,
+}
+
+// ===== impl Form =====
+
+impl Form {
+    /// Create an empty form with a random boundary.
+    pub fn new() -> Form {
+        Form::with_boundary(format!("{:016x}{:016x}", random_u64(), random_u64()))
+    }
+
+    /// Create an empty form using the supplied boundary.
+    ///
+    /// The boundary must not appear anywhere in the content of the parts.
+    pub fn with_boundary(boundary: impl Into<String>) -> Form {
+        Form {
+            boundary: boundary.into(),
+            parts: VecDeque::new(),
+        }
+    }
+
+    /// Get the boundary that separates the parts of this form.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Add a text field.
+    pub fn text(self, name: impl Into<String>, value: impl Into<String>) -> Form {
+        self.part(name, Part::text(value))
+    }
+
+    /// Add a field built from a [`Part`].
+    pub fn part(mut self, name: impl Into<String>, part: Part) -> Form {
+        self.parts.push_back((name.into(), part));
+        self
+    }
+
+    /// The value of the `content-type` header to send with this form.
+    pub fn content_type(&self) -> HeaderValue {
+        HeaderValue::from_str(&format!("multipart/form-data; boundary={}", self.boundary))
+            .expect("boundary is a valid header value")
+    }
+
+    /// Convert this form into a streaming `Body`.
+    pub fn into_body(self) -> FormBody {
+        FormBody {
+            boundary: self.boundary,
+            parts: self.parts,
+            queued: VecDeque::new(),
+            current: None,
+            finished: false,
+        }
+    }
+}
+
+impl Default for Form {
+    fn default() -> Self {
+        Form::new()
+    }
+}
+
+impl fmt::Debug for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Form")
+            .field("boundary", &self.boundary)
+            .field("parts", &self.parts)
+            .finish()
+    }
+}
+
+// ===== impl Part =====
+
+impl Part {
+    /// A part holding some text.
+    pub fn text(value: impl Into<String>) -> Part {
+        Part::new(PartData::Bytes(Bytes::from(value.into())))
+    }
+
+    /// A part holding some bytes.
+    pub fn bytes(value: impl Into<Bytes>) -> Part {
+        Part::new(PartData::Bytes(value.into()))
+    }
+
+    /// A part streamed from another `Body`.
+    ///
+    /// Trailers of the streamed body are discarded.
+    pub fn stream<B>(body: B) -> Part
+    where
+        B: Body + Send + 'static,
+        B::Error: Into<BoxError>,
+    {
+        Part::new(PartData::Body(Box::pin(Erased { inner: body })))
+    }
+
+    fn new(data: PartData) -> Part {
+        Part {
+            file_name: None,
+            headers: HeaderMap::new(),
+            data,
+        }
+    }
+
+    /// Set the file name of this part.
+    pub fn file_name(mut self, name: impl Into<String>) -> Part {
+        self.file_name = Some(name.into());
+        self
+    }
+
+    /// Set the `content-type` of this part.
+    pub fn content_type(self, value: HeaderValue) -> Part {
+        self.header(http::header::CONTENT_TYPE, value)
+    }
+
+    /// Add an extra header to this part.
+    ///
+    /// A `content-disposition` header is always generated from the field
+    /// name and file name, and should not be added here.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Part {
+        self.headers.insert(name, value);
+        self
+    }
+
+    fn data_len(&self) -> Option<u64> {
+        match self.data {
+            PartData::Bytes(ref bytes) => Some(bytes.len() as u64),
+            PartData::Body(ref body) => body.size_hint().exact(),
+        }
+    }
+}
+
+impl fmt::Debug for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Part")
+            .field("file_name", &self.file_name)
+            .field("headers", &self.headers)
+            .finish()
+    }
+}
+
+// ===== impl FormBody =====
+
+impl FormBody {
+    fn encode_head(boundary: &str, name: &str, part: &Part) -> Bytes {
+        let mut head = format!(
+            "--{}\r\ncontent-disposition: form-data; name=\"{}\"",
+            boundary,
+            escape(name)
+        );
+        if let Some(ref file_name) = part.file_name {
+            head.push_str("; filename=\"");
+            head.push_str(&escape(file_name));
+            head.push('"');
+        }
+        head.push_str("\r\n");
+
+        let mut head = head.into_bytes();
+        for (name, value) in &part.headers {
+            head.extend_from_slice(name.as_str().as_bytes());
+            head.extend_from_slice(b": ");
+            head.extend_from_slice(value.as_bytes());
+            head.extend_from_slice(CRLF);
+        }
+        head.extend_from_slice(CRLF);
+        Bytes::from(head)
+    }
+
+    fn closing_len(&self) -> u64 {
+        // "--" boundary "--" CRLF
+        self.boundary.len() as u64 + 6
+    }
+}
+
+impl Body for FormBody {
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(chunk) = this.queued.pop_front() {
+                return Poll::Ready(Some(Ok(Frame::data(chunk))));
+            }
+
+            if let Some(body) = this.current.as_mut() {
+                match ready!(body.as_mut().poll_frame(cx)) {
+                    Some(Ok(frame)) => match frame.into_data() {
+                        Ok(data) if !data.is_empty() => {
+                            return Poll::Ready(Some(Ok(Frame::data(data))));
+                        }
+                        // Empty data frames and trailers are skipped.
+                        _ => {}
+                    },
+                    Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                    None => {
+                        this.current = None;
+                        this.queued.push_back(Bytes::from_static(CRLF));
+                    }
+                }
+                continue;
+            }
+
+            match this.parts.pop_front() {
+                Some((name, part)) => {
+                    let head = FormBody::encode_head(&this.boundary, &name, &part);
+                    this.queued.push_back(head);
+                    match part.data {
+                        PartData::Bytes(bytes) => {
+                            if !bytes.is_empty() {
+                                this.queued.push_back(bytes);
+                            }
+                            this.queued.push_back(Bytes::from_static(CRLF));
+                        }
+                        PartData::Body(body) => this.current = Some(body),
+                    }
+                }
+                None if !this.finished => {
+                    this.finished = true;
+                    this.queued
+                        .push_back(Bytes::from(format!("--{}--\r\n", this.boundary)));
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.finished && self.queued.is_empty() && self.current.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let mut len: u64 = self.queued.iter().map(|b| b.len() as u64).sum();
+        if !self.finished {
+            len += self.closing_len();
+        }
+        if let Some(ref body) = self.current {
+            match body.size_hint().exact() {
+                Some(n) => len += n + CRLF.len() as u64,
+                None => return SizeHint::default(),
+            }
+        }
+        for (name, part) in &self.parts {
+            match part.data_len() {
+                Some(n) => {
+                    let head = FormBody::encode_head(&self.boundary, name, part);
+                    len += head.len() as u64 + n + CRLF.len() as u64;
+                }
+                None => return SizeHint::default(),
+            }
+        }
+        SizeHint::with_exact(len)
+    }
+}
+
+impl fmt::Debug for FormBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FormBody")
+            .field("boundary", &self.boundary)
+            .finish()
+    }
+}
+
+// Percent-encode the characters that would break out of a quoted
+// parameter, the same way browsers do.
+fn escape(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+pin_project! {
+    struct Erased<B> {
+        #[pin]
+        inner: B,
+    }
+}
+
+impl<B> Body for Erased<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match ready!(self.project().inner.poll_frame(cx)) {
+            Some(Ok(frame)) => Poll::Ready(Some(Ok(
+                frame.map_data(|mut data| data.copy_to_bytes(data.remaining()))
+            ))),
+            Some(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,18 @@
+//! `multipart/form-data` bodies.
+//!
+//! This module contains:
+//!
+//! - A [`Form`] to build a streaming `multipart/form-data` body, such as
+//!   for a request sent with the legacy `Client`.
+//! - A [`Multipart`] parser to incrementally read the fields out of a
+//!   received body, with bounded memory use.
+
+pub use self::form::{Form, FormBody, Part};
+pub use self::parse::{parse_boundary, Field, FieldData, Multipart, MultipartError, TempFile};
+
+mod form;
+mod parse;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const CRLF: &[u8] = b"\r\n";
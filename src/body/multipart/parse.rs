@@ -0,0 +1,733 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+
+use bytes::{Buf, Bytes, BytesMut};
+use http::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
+use http_body::Body;
+
+use super::{BoxError, CRLF};
+use crate::common::rand::random_u64;
+
+/// An incremental `multipart/form-data` parser.
+///
+/// Only a small window of the body is buffered while looking for the
+/// boundaries between fields. Each field is limited to
+/// [`max_field_size`](Multipart::max_field_size) bytes, and may optionally be
+/// written to a temporary file once it grows past a threshold, see
+/// [`spill_to_disk`](Multipart::spill_to_disk).
+///
+/// # Example
+///
+/// ```
+/// # async fn run(req: http::Request<hyper::body::Incoming>) -> Result<(), Box<dyn std::error::Error>> {
+/// use http::header::CONTENT_TYPE;
+/// use hyper_util::body::multipart::{parse_boundary, Multipart};
+///
+/// let boundary = req
+///     .headers()
+///     .get(CONTENT_TYPE)
+///     .and_then(parse_boundary)
+///     .ok_or("not a multipart request")?;
+///
+/// let mut multipart = Multipart::new(req.into_body(), boundary);
+/// while let Some(field) = multipart.next_field().await? {
+///     println!("field {:?}", field.name());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Multipart<B> {
+    body: Pin<Box<B>>,
+    buf: BytesMut,
+    // "\r\n--" boundary
+    delimiter: Bytes,
+    state: State,
+    max_field_size: usize,
+    max_header_size: usize,
+    spill: Option<(usize, PathBuf)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Preamble,
+    Headers,
+    Done,
+}
+
+/// A field parsed by [`Multipart`].
+#[derive(Debug)]
+pub struct Field {
+    name: Option<String>,
+    file_name: Option<String>,
+    headers: HeaderMap,
+    data: FieldData,
+}
+
+/// The content of a [`Field`].
+#[derive(Debug)]
+pub enum FieldData {
+    /// The field was small enough to be kept in memory.
+    Memory(Bytes),
+    /// The field was written to a temporary file.
+    File(TempFile),
+}
+
+/// A temporary file holding the content of a [`Field`].
+///
+/// The file is removed when this value is dropped, unless
+/// [`keep`](TempFile::keep) is called.
+#[derive(Debug)]
+pub struct TempFile {
+    path: Option<PathBuf>,
+    len: u64,
+}
+
+/// An error returned while parsing a `multipart/form-data` body.
+pub struct MultipartError {
+    kind: Kind,
+    source: Option<BoxError>,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Body,
+    Incomplete,
+    InvalidHeader,
+    HeadersTooLarge,
+    FieldTooLarge,
+    Io,
+}
+
+/// Get the boundary out of a `multipart/form-data` `content-type` value.
+pub fn parse_boundary(content_type: &HeaderValue) -> Option<String> {
+    let value = content_type.to_str().ok()?;
+    let mut params = value.split(';');
+    let mime = params.next()?.trim();
+    if !mime.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (key, value) = split_param(param)?;
+        if key.eq_ignore_ascii_case("boundary") && !value.is_empty() {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+// ===== impl Multipart =====
+
+impl<B> Multipart<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    /// Create a parser reading fields separated by `boundary` out of `body`.
+    pub fn new(body: B, boundary: impl Into<String>) -> Multipart<B> {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.into().as_bytes());
+        Multipart {
+            body: Box::pin(body),
+            buf: BytesMut::new(),
+            delimiter: Bytes::from(delimiter),
+            state: State::Preamble,
+            max_field_size: 2 * 1024 * 1024,
+            max_header_size: 8 * 1024,
+            spill: None,
+        }
+    }
+
+    /// Set the maximum size of the content of a single field.
+    ///
+    /// This includes fields that were written to disk.
+    ///
+    /// Default is 2MB.
+    pub fn max_field_size(&mut self, max: usize) -> &mut Self {
+        self.max_field_size = max;
+        self
+    }
+
+    /// Set the maximum size of the headers of a single field.
+    ///
+    /// Default is 8KB.
+    pub fn max_header_size(&mut self, max: usize) -> &mut Self {
+        self.max_header_size = max;
+        self
+    }
+
+    /// Write fields larger than `threshold` bytes to temporary files in `dir`,
+    /// instead of keeping them in memory.
+    ///
+    /// Writing to the file is done with blocking IO.
+    ///
+    /// Default is to keep every field in memory.
+    pub fn spill_to_disk(&mut self, threshold: usize, dir: impl Into<PathBuf>) -> &mut Self {
+        self.spill = Some((threshold, dir.into()));
+        self
+    }
+
+    /// Read the next field out of the body.
+    ///
+    /// Returns `Ok(None)` once the closing boundary has been read.
+    pub async fn next_field(&mut self) -> Result<Option<Field>, MultipartError> {
+        if self.state == State::Preamble {
+            // The first boundary doesn't need to be preceded by a CRLF.
+            let dash_boundary = self.delimiter.slice(CRLF.len()..);
+            loop {
+                if let Some(idx) = find(&self.buf, &dash_boundary) {
+                    self.buf.advance(idx + dash_boundary.len());
+                    break;
+                }
+                // Anything before the first boundary is ignored.
+                let keep = dash_boundary.len() - 1;
+                if self.buf.len() > keep {
+                    self.buf.advance(self.buf.len() - keep);
+                }
+                self.fill().await?;
+            }
+            self.after_delimiter().await?;
+        }
+
+        if self.state == State::Done {
+            return Ok(None);
+        }
+
+        let headers = self.read_headers().await?;
+        let data = self.read_data().await?;
+        self.after_delimiter().await?;
+
+        let (name, file_name) = headers
+            .get(CONTENT_DISPOSITION)
+            .map(parse_content_disposition)
+            .unwrap_or((None, None));
+
+        Ok(Some(Field {
+            name,
+            file_name,
+            headers,
+            data,
+        }))
+    }
+
+    async fn read_headers(&mut self) -> Result<HeaderMap, MultipartError> {
+        loop {
+            // No headers at all, just the empty line.
+            if self.buf.starts_with(CRLF) {
+                self.buf.advance(CRLF.len());
+                return Ok(HeaderMap::new());
+            }
+            if let Some(idx) = find(&self.buf, b"\r\n\r\n") {
+                let block = self.buf.split_to(idx);
+                self.buf.advance(4);
+                return parse_headers(&block);
+            }
+            if self.buf.len() > self.max_header_size {
+                return Err(MultipartError::new(Kind::HeadersTooLarge));
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn read_data(&mut self) -> Result<FieldData, MultipartError> {
+        let mut data = FieldWriter::new();
+        loop {
+            if let Some(idx) = find(&self.buf, &self.delimiter) {
+                let chunk = self.buf.split_to(idx).freeze();
+                self.write(&mut data, chunk)?;
+                self.buf.advance(self.delimiter.len());
+                return data.finish();
+            }
+            // Keep enough around that a delimiter split between two
+            // chunks of the body is still noticed.
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let chunk = self.buf.split_to(self.buf.len() - keep).freeze();
+                self.write(&mut data, chunk)?;
+            }
+            self.fill().await?;
+        }
+    }
+
+    fn write(&self, data: &mut FieldWriter, chunk: Bytes) -> Result<(), MultipartError> {
+        if data.len + chunk.len() > self.max_field_size {
+            return Err(MultipartError::new(Kind::FieldTooLarge));
+        }
+        if let FieldWriter {
+            file: None,
+            ref memory,
+            ..
+        } = *data
+        {
+            if let Some((threshold, ref dir)) = self.spill {
+                if memory.len() + chunk.len() > threshold {
+                    data.spill(dir).map_err(MultipartError::io)?;
+                }
+            }
+        }
+        data.write(chunk).map_err(MultipartError::io)
+    }
+
+    // After a delimiter is either "--", meaning this was the last one, or
+    // some optional whitespace and a CRLF before the next field.
+    async fn after_delimiter(&mut self) -> Result<(), MultipartError> {
+        loop {
+            if self.buf.starts_with(b"--") {
+                self.buf.advance(2);
+                self.state = State::Done;
+                return Ok(());
+            }
+            if let Some(idx) = find(&self.buf, CRLF) {
+                if self.buf[..idx].iter().all(|&b| b == b' ' || b == b'\t') {
+                    self.buf.advance(idx + CRLF.len());
+                    self.state = State::Headers;
+                    return Ok(());
+                }
+                return Err(MultipartError::new(Kind::InvalidHeader));
+            }
+            if self.buf.len() > self.max_header_size {
+                return Err(MultipartError::new(Kind::HeadersTooLarge));
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), MultipartError> {
+        loop {
+            let frame = futures_util::future::poll_fn(|cx| self.body.as_mut().poll_frame(cx)).await;
+            match frame {
+                Some(Ok(frame)) => {
+                    if let Ok(mut data) = frame.into_data() {
+                        if data.has_remaining() {
+                            while data.has_remaining() {
+                                let chunk = data.chunk();
+                                let len = chunk.len();
+                                self.buf.extend_from_slice(chunk);
+                                data.advance(len);
+                            }
+                            return Ok(());
+                        }
+                    }
+                }
+                Some(Err(err)) => {
+                    return Err(MultipartError {
+                        kind: Kind::Body,
+                        source: Some(err.into()),
+                    })
+                }
+                None => return Err(MultipartError::new(Kind::Incomplete)),
+            }
+        }
+    }
+}
+
+impl<B> fmt::Debug for Multipart<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Multipart")
+            .field("state", &self.state)
+            .field("max_field_size", &self.max_field_size)
+            .finish()
+    }
+}
+
+struct FieldWriter {
+    memory: BytesMut,
+    file: Option<(File, TempFile)>,
+    len: usize,
+}
+
+impl FieldWriter {
+    fn new() -> FieldWriter {
+        FieldWriter {
+            memory: BytesMut::new(),
+            file: None,
+            len: 0,
+        }
+    }
+
+    fn spill(&mut self, dir: &Path) -> io::Result<()> {
+        let path = dir.join(format!(
+            "hyper-util-multipart-{}-{:016x}",
+            std::process::id(),
+            random_u64()
+        ));
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let tmp = TempFile {
+            path: Some(path),
+            len: 0,
+        };
+        file.write_all(&self.memory)?;
+        self.memory.clear();
+        self.file = Some((file, tmp));
+        Ok(())
+    }
+
+    fn write(&mut self, chunk: Bytes) -> io::Result<()> {
+        self.len += chunk.len();
+        match self.file {
+            Some((ref mut file, _)) => file.write_all(&chunk),
+            None => {
+                self.memory.extend_from_slice(&chunk);
+                Ok(())
+            }
+        }
+    }
+
+    fn finish(self) -> Result<FieldData, MultipartError> {
+        match self.file {
+            Some((mut file, mut tmp)) => {
+                file.flush().map_err(MultipartError::io)?;
+                tmp.len = self.len as u64;
+                Ok(FieldData::File(tmp))
+            }
+            None => Ok(FieldData::Memory(self.memory.freeze())),
+        }
+    }
+}
+
+// ===== impl Field =====
+
+impl Field {
+    /// The `name` parameter of the `content-disposition` header.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The `filename` parameter of the `content-disposition` header.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// The `content-type` of this field, if one was sent.
+    pub fn content_type(&self) -> Option<&HeaderValue> {
+        self.headers.get(CONTENT_TYPE)
+    }
+
+    /// All the headers of this field.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The content of this field.
+    pub fn data(&self) -> &FieldData {
+        &self.data
+    }
+
+    /// Consume this field, returning its content.
+    pub fn into_data(self) -> FieldData {
+        self.data
+    }
+}
+
+// ===== impl TempFile =====
+
+impl TempFile {
+    /// The path of the temporary file.
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().expect("not dropped")
+    }
+
+    /// The length of the content written to the file.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns true if the file is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Keep the file after this value is dropped, returning its path.
+    pub fn keep(mut self) -> PathBuf {
+        self.path.take().expect("not dropped")
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+// ===== impl MultipartError =====
+
+impl MultipartError {
+    fn new(kind: Kind) -> MultipartError {
+        MultipartError { kind, source: None }
+    }
+
+    fn io(err: io::Error) -> MultipartError {
+        MultipartError {
+            kind: Kind::Io,
+            source: Some(err.into()),
+        }
+    }
+
+    /// Returns true if the body ended before the closing boundary.
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self.kind, Kind::Incomplete)
+    }
+
+    /// Returns true if a field or its headers were larger than allowed.
+    pub fn is_too_large(&self) -> bool {
+        matches!(self.kind, Kind::FieldTooLarge | Kind::HeadersTooLarge)
+    }
+
+    /// Returns true if the body itself returned an error.
+    pub fn is_body(&self) -> bool {
+        matches!(self.kind, Kind::Body)
+    }
+}
+
+impl fmt::Debug for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_tuple("hyper_util::body::multipart::MultipartError");
+        f.field(&self.kind);
+        if let Some(ref cause) = self.source {
+            f.field(cause);
+        }
+        f.finish()
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.kind {
+            Kind::Body => "error reading a body",
+            Kind::Incomplete => "multipart body ended before the closing boundary",
+            Kind::InvalidHeader => "invalid multipart field header",
+            Kind::HeadersTooLarge => "multipart field headers too large",
+            Kind::FieldTooLarge => "multipart field too large",
+            Kind::Io => "error writing multipart field to disk",
+        })
+    }
+}
+
+impl StdError for MultipartError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| &**e as _)
+    }
+}
+
+// ===== helpers =====
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn parse_headers(block: &[u8]) -> Result<HeaderMap, MultipartError> {
+    let mut headers = HeaderMap::new();
+    for line in block.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or_else(|| MultipartError::new(Kind::InvalidHeader))?;
+        let name = HeaderName::from_bytes(&line[..colon])
+            .map_err(|_| MultipartError::new(Kind::InvalidHeader))?;
+        let value = HeaderValue::from_bytes(trim(&line[colon + 1..]))
+            .map_err(|_| MultipartError::new(Kind::InvalidHeader))?;
+        headers.append(name, value);
+    }
+    Ok(headers)
+}
+
+fn trim(mut s: &[u8]) -> &[u8] {
+    while let [b' ' | b'\t', rest @ ..] = s {
+        s = rest;
+    }
+    while let [rest @ .., b' ' | b'\t'] = s {
+        s = rest;
+    }
+    s
+}
+
+fn parse_content_disposition(value: &HeaderValue) -> (Option<String>, Option<String>) {
+    let value = match value.to_str() {
+        Ok(value) => value,
+        Err(_) => return (None, None),
+    };
+    let mut name = None;
+    let mut file_name = None;
+    for param in value.split(';').skip(1) {
+        if let Some((key, value)) = split_param(param) {
+            if key.eq_ignore_ascii_case("name") {
+                name = Some(value);
+            } else if key.eq_ignore_ascii_case("filename") {
+                file_name = Some(value);
+            }
+        }
+    }
+    (name, file_name)
+}
+
+// Splits `key=value` or `key="quoted value"`.
+fn split_param(param: &str) -> Option<(&str, String)> {
+    let (key, value) = param.split_once('=')?;
+    let value = value.trim();
+    let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(quoted) => {
+            let mut unescaped = String::with_capacity(quoted.len());
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' {
+                    unescaped.extend(chars.next());
+                } else {
+                    unescaped.push(c);
+                }
+            }
+            unescaped
+        }
+        None => value.to_owned(),
+    };
+    Some((key.trim(), value))
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use futures_util::stream;
+    use http::header::HeaderValue;
+    use http_body::{Body, Frame};
+    use http_body_util::{BodyExt, Full, StreamBody};
+
+    use super::{parse_boundary, FieldData, Multipart};
+    use crate::body::multipart::{Form, Part};
+
+    fn form() -> Form {
+        Form::with_boundary("XyZ")
+            .text("user", "sean")
+            .part(
+                "file",
+                Part::bytes(vec![b'a'; 100])
+                    .file_name("a \"quoted\".txt")
+                    .content_type(HeaderValue::from_static("text/plain")),
+            )
+            .part(
+                "streamed",
+                Part::stream(Full::new(Bytes::from_static(b"streamed data"))),
+            )
+    }
+
+    // Split the encoded form into 1 byte frames, to test that boundaries
+    // are found across chunks.
+    async fn trickle(form: Form) -> impl Body<Data = Bytes, Error = Infallible> {
+        let bytes = form.into_body().collect().await.unwrap().to_bytes();
+        let frames = (0..bytes.len())
+            .map(|i| Ok::<_, Infallible>(Frame::data(bytes.slice(i..i + 1))))
+            .collect::<Vec<_>>();
+        StreamBody::new(stream::iter(frames))
+    }
+
+    fn memory(data: &FieldData) -> &Bytes {
+        match data {
+            FieldData::Memory(bytes) => bytes,
+            FieldData::File(_) => panic!("expected memory field"),
+        }
+    }
+
+    #[test]
+    fn boundary_from_content_type() {
+        assert_eq!(
+            parse_boundary(&Form::with_boundary("abc").content_type()).as_deref(),
+            Some("abc")
+        );
+        assert_eq!(
+            parse_boundary(&HeaderValue::from_static(
+                "multipart/form-data; charset=utf-8; boundary=\"q u\""
+            ))
+            .as_deref(),
+            Some("q u")
+        );
+        assert_eq!(
+            parse_boundary(&HeaderValue::from_static("text/plain; boundary=abc")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn form_size_hint_is_exact() {
+        let body = form().into_body();
+        let hint = body.size_hint().exact().expect("exact size hint");
+        let bytes = body.collect().await.unwrap().to_bytes();
+        assert_eq!(hint, bytes.len() as u64);
+        assert!(bytes.ends_with(b"\r\n--XyZ--\r\n"));
+    }
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let mut multipart = Multipart::new(trickle(form()).await, "XyZ");
+
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.name(), Some("user"));
+        assert_eq!(field.file_name(), None);
+        assert_eq!(memory(field.data()), "sean");
+
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.name(), Some("file"));
+        assert_eq!(field.file_name(), Some("a %22quoted%22.txt"));
+        assert_eq!(field.content_type().unwrap(), "text/plain");
+        assert_eq!(memory(field.data()), &vec![b'a'; 100]);
+
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(field.name(), Some("streamed"));
+        assert_eq!(memory(field.data()), "streamed data");
+
+        assert!(multipart.next_field().await.unwrap().is_none());
+        assert!(multipart.next_field().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn field_too_large() {
+        let mut multipart = Multipart::new(trickle(form()).await, "XyZ");
+        multipart.max_field_size(50);
+
+        assert!(multipart.next_field().await.unwrap().is_some());
+        let err = multipart.next_field().await.unwrap_err();
+        assert!(err.is_too_large());
+    }
+
+    #[tokio::test]
+    async fn incomplete_body() {
+        let body = Full::new(Bytes::from_static(b"--XyZ\r\n\r\nno end"));
+        let mut multipart = Multipart::new(body, "XyZ");
+
+        let err = multipart.next_field().await.unwrap_err();
+        assert!(err.is_incomplete());
+    }
+
+    #[tokio::test]
+    async fn spill_to_disk() {
+        let dir = std::env::temp_dir();
+        let mut multipart = Multipart::new(trickle(form()).await, "XyZ");
+        multipart.spill_to_disk(10, &dir);
+
+        let field = multipart.next_field().await.unwrap().unwrap();
+        assert_eq!(memory(field.data()), "sean");
+
+        let field = multipart.next_field().await.unwrap().unwrap();
+        let path = match field.into_data() {
+            FieldData::File(file) => {
+                assert_eq!(file.len(), 100);
+                assert_eq!(std::fs::read(file.path()).unwrap(), vec![b'a'; 100]);
+                file.path().to_owned()
+            }
+            FieldData::Memory(_) => panic!("expected spilled field"),
+        };
+        // dropping the file removed it
+        assert!(!path.exists());
+    }
+}
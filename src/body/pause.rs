@@ -0,0 +1,264 @@
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{self, Poll, Waker};
+
+use bytes::Buf;
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+
+pin_project! {
+    /// A body whose transmission can be paused and resumed.
+    ///
+    /// While paused, the body doesn't yield any frames, so hyper stops
+    /// writing it: an HTTP/1 connection doesn't write more of the body, and
+    /// an HTTP/2 stream doesn't send more `DATA` frames or take more of the
+    /// connection's flow control window. Other streams on the same HTTP/2
+    /// connection keep going.
+    ///
+    /// Pausing takes effect between frames, so a frame already being sent
+    /// is finished.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bytes::Bytes;
+    /// use http_body_util::Full;
+    /// use hyper_util::body::Pausable;
+    ///
+    /// let (body, handle) = Pausable::new(Full::new(Bytes::from_static(b"hello")));
+    /// // yield the bandwidth to something more important...
+    /// handle.pause();
+    /// // ...and continue once it's done.
+    /// handle.resume();
+    /// # drop(body);
+    /// ```
+    pub struct Pausable<B> {
+        #[pin]
+        inner: B,
+        shared: Arc<Shared>,
+    }
+}
+
+/// Pauses and resumes a [`Pausable`] body.
+///
+/// Handles can be cloned. Once every handle has been dropped, the body is
+/// resumed, so a dropped controller can't stall an upload forever.
+pub struct PauseHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    sent: AtomicU64,
+}
+
+struct State {
+    paused: bool,
+    handles: usize,
+    waker: Option<Waker>,
+}
+
+// ===== impl Pausable =====
+
+impl<B> Pausable<B> {
+    /// Wrap a body, returning it with a handle to pause it.
+    pub fn new(inner: B) -> (Pausable<B>, PauseHandle) {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                paused: false,
+                handles: 1,
+                waker: None,
+            }),
+            sent: AtomicU64::new(0),
+        });
+        let handle = PauseHandle {
+            shared: shared.clone(),
+        };
+        (Pausable { inner, shared }, handle)
+    }
+
+    /// Get a reference to the inner body.
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    /// Consume this wrapper, returning the inner body.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: Body> Body for Pausable<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let me = self.project();
+        {
+            let mut state = me.shared.state.lock().unwrap();
+            if state.paused {
+                match state.waker {
+                    Some(ref waker) if waker.will_wake(cx.waker()) => {}
+                    _ => state.waker = Some(cx.waker().clone()),
+                }
+                return Poll::Pending;
+            }
+        }
+
+        let frame = me.inner.poll_frame(cx);
+        if let Poll::Ready(Some(Ok(ref frame))) = frame {
+            if let Some(data) = frame.data_ref() {
+                me.shared
+                    .sent
+                    .fetch_add(data.remaining() as u64, Ordering::Relaxed);
+            }
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> fmt::Debug for Pausable<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pausable")
+            .field("paused", &self.shared.is_paused())
+            .field("sent", &self.shared.sent())
+            .finish()
+    }
+}
+
+// ===== impl PauseHandle =====
+
+impl PauseHandle {
+    /// Stop the body from yielding frames until it is resumed.
+    pub fn pause(&self) {
+        self.shared.state.lock().unwrap().paused = true;
+    }
+
+    /// Let a paused body continue.
+    pub fn resume(&self) {
+        self.shared.resume();
+    }
+
+    /// Returns true if the body is paused.
+    pub fn is_paused(&self) -> bool {
+        self.shared.is_paused()
+    }
+
+    /// The number of body bytes yielded so far.
+    pub fn sent(&self) -> u64 {
+        self.shared.sent()
+    }
+}
+
+impl Clone for PauseHandle {
+    fn clone(&self) -> PauseHandle {
+        self.shared.state.lock().unwrap().handles += 1;
+        PauseHandle {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Drop for PauseHandle {
+    fn drop(&mut self) {
+        let last = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.handles -= 1;
+            state.handles == 0
+        };
+        if last {
+            self.shared.resume();
+        }
+    }
+}
+
+impl fmt::Debug for PauseHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PauseHandle")
+            .field("paused", &self.shared.is_paused())
+            .field("sent", &self.shared.sent())
+            .finish()
+    }
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    fn resume(&self) {
+        let waker = {
+            let mut state = self.state.lock().unwrap();
+            state.paused = false;
+            state.waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.state.lock().unwrap().paused
+    }
+
+    fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use bytes::Bytes;
+    use futures_util::task::noop_waker_ref;
+    use http_body::Body;
+    use http_body_util::{BodyExt, Full};
+
+    use super::Pausable;
+
+    #[tokio::test]
+    async fn pause_and_resume() {
+        let (mut body, handle) = Pausable::new(Full::new(Bytes::from_static(b"hello")));
+        handle.pause();
+        assert!(handle.is_paused());
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(Pin::new(&mut body).poll_frame(&mut cx).is_pending());
+        assert_eq!(handle.sent(), 0);
+
+        handle.resume();
+        let data = body.collect().await.unwrap().to_bytes();
+        assert_eq!(data, "hello");
+        assert_eq!(handle.sent(), 5);
+    }
+
+    #[test]
+    fn dropping_handles_resumes() {
+        let (mut body, handle) = Pausable::new(Full::new(Bytes::from_static(b"hello")));
+        let other = handle.clone();
+        handle.pause();
+        drop(handle);
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(Pin::new(&mut body).poll_frame(&mut cx).is_pending());
+
+        drop(other);
+        assert!(matches!(
+            Pin::new(&mut body).poll_frame(&mut cx),
+            Poll::Ready(Some(Ok(_)))
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{self, Poll};
+
+use bytes::Buf;
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+
+pin_project! {
+    /// A body that reports how much of its data has been transferred.
+    ///
+    /// Every data frame polled from the inner body is counted, and the
+    /// reporter is notified with a [`ProgressUpdate`]. This works in both
+    /// directions: wrap a request body to track an upload, or the body of a
+    /// response to track a download.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::atomic::{AtomicU64, Ordering};
+    /// use std::sync::Arc;
+    /// use bytes::Bytes;
+    /// use http_body_util::Full;
+    /// use hyper_util::body::Progress;
+    ///
+    /// let body = Progress::new(Full::new(Bytes::from_static(b"hello")), |update: hyper_util::body::ProgressUpdate| {
+    ///     if let Some(total) = update.total() {
+    ///         eprintln!("{}/{} bytes", update.transferred(), total);
+    ///     }
+    /// });
+    /// # drop(body);
+    ///
+    /// // Or share a counter with another task.
+    /// let counter = Arc::new(AtomicU64::new(0));
+    /// let body = Progress::new(Full::new(Bytes::from_static(b"hello")), counter.clone());
+    /// # drop(body);
+    /// assert_eq!(counter.load(Ordering::Relaxed), 0);
+    /// ```
+    pub struct Progress<B, R> {
+        #[pin]
+        inner: B,
+        transferred: u64,
+        total: Option<u64>,
+        reporter: R,
+    }
+}
+
+/// The progress of a [`Progress`] body.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ProgressUpdate {
+    transferred: u64,
+    total: Option<u64>,
+}
+
+/// Receives [`ProgressUpdate`]s from a [`Progress`] body.
+///
+/// This is implemented for closures, for an `Arc<AtomicU64>` storing the
+/// number of bytes transferred, and, with the `tokio` feature, for a
+/// `tokio::sync::watch::Sender`.
+pub trait ReportProgress {
+    /// Called after each data frame.
+    fn report(&mut self, update: ProgressUpdate);
+}
+
+// ===== impl Progress =====
+
+impl<B: Body, R> Progress<B, R> {
+    /// Wrap a body, reporting its progress to `reporter`.
+    ///
+    /// The total is taken from the body's `size_hint`, if it is exact.
+    pub fn new(inner: B, reporter: R) -> Progress<B, R> {
+        let total = inner.size_hint().exact();
+        Progress {
+            inner,
+            transferred: 0,
+            total,
+            reporter,
+        }
+    }
+}
+
+impl<B, R> Progress<B, R> {
+    /// Set the expected total, such as from a `content-length` header.
+    pub fn with_total(mut self, total: u64) -> Progress<B, R> {
+        self.total = Some(total);
+        self
+    }
+
+    /// The number of bytes transferred so far.
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    /// The expected total number of bytes, if known.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+
+    /// Get a reference to the inner body.
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    /// Consume this wrapper, returning the inner body.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B, R> Body for Progress<B, R>
+where
+    B: Body,
+    R: ReportProgress,
+{
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let me = self.project();
+        let frame = me.inner.poll_frame(cx);
+        if let Poll::Ready(Some(Ok(ref frame))) = frame {
+            if let Some(data) = frame.data_ref() {
+                *me.transferred += data.remaining() as u64;
+                me.reporter.report(ProgressUpdate {
+                    transferred: *me.transferred,
+                    total: *me.total,
+                });
+            }
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B, R> fmt::Debug for Progress<B, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("transferred", &self.transferred)
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+// ===== impl ProgressUpdate =====
+
+impl ProgressUpdate {
+    /// The number of bytes transferred so far.
+    pub fn transferred(&self) -> u64 {
+        self.transferred
+    }
+
+    /// The expected total number of bytes, if known.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+}
+
+// ===== impl ReportProgress =====
+
+impl<F> ReportProgress for F
+where
+    F: FnMut(ProgressUpdate),
+{
+    fn report(&mut self, update: ProgressUpdate) {
+        self(update)
+    }
+}
+
+impl ReportProgress for Arc<AtomicU64> {
+    fn report(&mut self, update: ProgressUpdate) {
+        self.store(update.transferred, Ordering::Relaxed);
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl ReportProgress for tokio::sync::watch::Sender<ProgressUpdate> {
+    fn report(&mut self, update: ProgressUpdate) {
+        self.send_replace(update);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use futures_util::stream;
+    use http_body::Frame;
+    use http_body_util::{BodyExt, Full, StreamBody};
+
+    use super::{Progress, ProgressUpdate};
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn reports_each_frame() {
+        let chunks = vec![
+            Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"ab"))),
+            Ok(Frame::data(Bytes::from_static(b"cde"))),
+        ];
+        let mut updates = Vec::new();
+        let body = Progress::new(StreamBody::new(stream::iter(chunks)), |update| {
+            updates.push(update)
+        })
+        .with_total(5);
+        body.collect().await.unwrap();
+
+        assert_eq!(
+            updates.iter().map(|u| u.transferred()).collect::<Vec<_>>(),
+            vec![2, 5]
+        );
+        assert!(updates.iter().all(|u| u.total() == Some(5)));
+    }
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn counter_and_watch() {
+        let counter = Arc::new(AtomicU64::new(0));
+        Progress::new(Full::new(Bytes::from_static(b"hello")), counter.clone())
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(counter.load(Ordering::Relaxed), 5);
+
+        let (tx, rx) = tokio::sync::watch::channel(ProgressUpdate::default());
+        Progress::new(Full::new(Bytes::from_static(b"hello")), tx)
+            .collect()
+            .await
+            .unwrap();
+        assert_eq!(rx.borrow().transferred(), 5);
+        assert_eq!(rx.borrow().total(), Some(5));
+    }
+}
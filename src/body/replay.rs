@@ -0,0 +1,287 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{self, Poll};
+
+use bytes::{Buf, Bytes};
+use http::HeaderMap;
+use http_body::{Body, Frame, SizeHint};
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+/// A body that can be sent again, by cloning it.
+///
+/// The data read from the inner body is kept, up to a limit, so that a
+/// clone can replay it before continuing with the rest of the inner body.
+/// This lets streaming bodies take part in retries: each attempt gets a
+/// clone, and only the first reads from the source.
+///
+/// Once more than the limit has been read, the buffer is dropped and the
+/// body can no longer be replayed. See [`ReplayBody::is_replayable`].
+///
+/// # Example
+///
+/// ```
+/// use bytes::Bytes;
+/// use http_body_util::Full;
+/// use hyper_util::body::ReplayBody;
+///
+/// let body = ReplayBody::new(Full::new(Bytes::from_static(b"hello")), 64 * 1024);
+/// let retry = body.clone();
+/// assert!(retry.is_replayable());
+/// # drop(body);
+/// ```
+pub struct ReplayBody<B> {
+    shared: Arc<Mutex<Shared<B>>>,
+    // The index of the next frame this clone will yield.
+    position: usize,
+    trailers_sent: bool,
+}
+
+struct Shared<B> {
+    inner: Pin<Box<B>>,
+    size_hint: SizeHint,
+    frames: Vec<Bytes>,
+    // Frames dropped from the front of `frames` after passing the limit.
+    dropped: usize,
+    buffered: usize,
+    max_buffer: usize,
+    trailers: Option<HeaderMap>,
+    finished: bool,
+}
+
+/// The error when a [`ReplayBody`] can't be replayed anymore.
+struct Exhausted;
+
+// ===== impl ReplayBody =====
+
+impl<B: Body> ReplayBody<B> {
+    /// Wrap a body, keeping up to `max_buffer` bytes to replay.
+    pub fn new(inner: B, max_buffer: usize) -> ReplayBody<B> {
+        let size_hint = inner.size_hint();
+        ReplayBody {
+            shared: Arc::new(Mutex::new(Shared {
+                inner: Box::pin(inner),
+                size_hint,
+                frames: Vec::new(),
+                dropped: 0,
+                buffered: 0,
+                max_buffer,
+                trailers: None,
+                finished: false,
+            })),
+            position: 0,
+            trailers_sent: false,
+        }
+    }
+}
+
+impl<B> ReplayBody<B> {
+    /// Whether a fresh clone of this body will yield the whole body.
+    ///
+    /// This is false once more than the buffer limit has been read.
+    pub fn is_replayable(&self) -> bool {
+        self.shared.lock().unwrap().dropped == 0
+    }
+}
+
+impl<B> Clone for ReplayBody<B> {
+    /// Clone the body, which starts again from the beginning.
+    fn clone(&self) -> ReplayBody<B> {
+        ReplayBody {
+            shared: self.shared.clone(),
+            position: 0,
+            trailers_sent: false,
+        }
+    }
+}
+
+impl<B> Body for ReplayBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        let mut shared = this.shared.lock().unwrap();
+
+        if this.position < shared.dropped {
+            return Poll::Ready(Some(Err(Exhausted.into())));
+        }
+        if let Some(data) = shared.frames.get(this.position - shared.dropped) {
+            let data = data.clone();
+            this.position += 1;
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+
+        if !shared.finished {
+            // Caught up with the buffer, read from the inner body.
+            let frame = match shared.inner.as_mut().poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => frame,
+                Poll::Ready(Some(Err(err))) => return Poll::Ready(Some(Err(err.into()))),
+                Poll::Ready(None) => {
+                    shared.finished = true;
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            return Poll::Ready(Some(Ok(match frame.into_data() {
+                Ok(mut data) => {
+                    let data = data.copy_to_bytes(data.remaining());
+                    shared.push(data.clone());
+                    this.position += 1;
+                    Frame::data(data)
+                }
+                Err(frame) => match frame.into_trailers() {
+                    Ok(trailers) => {
+                        shared.trailers = Some(trailers.clone());
+                        shared.finished = true;
+                        this.trailers_sent = true;
+                        Frame::trailers(trailers)
+                    }
+                    // Neither data nor trailers, skip it.
+                    Err(_) => {
+                        cx.waker().wake_by_ref();
+                        return Poll::Pending;
+                    }
+                },
+            })));
+        }
+
+        match shared.trailers {
+            Some(ref trailers) if !this.trailers_sent => {
+                this.trailers_sent = true;
+                Poll::Ready(Some(Ok(Frame::trailers(trailers.clone()))))
+            }
+            _ => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        let shared = self.shared.lock().unwrap();
+        shared.finished
+            && self.position >= shared.dropped + shared.frames.len()
+            && (shared.trailers.is_none() || self.trailers_sent)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.shared.lock().unwrap().size_hint
+    }
+}
+
+impl<B> fmt::Debug for ReplayBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        f.debug_struct("ReplayBody")
+            .field("buffered", &shared.buffered)
+            .field("max_buffer", &shared.max_buffer)
+            .field("replayable", &(shared.dropped == 0))
+            .finish()
+    }
+}
+
+// ===== impl Shared =====
+
+impl<B> Shared<B> {
+    fn push(&mut self, data: Bytes) {
+        if self.dropped > 0 {
+            // Already past the limit, only count the frame.
+            self.dropped += 1;
+            return;
+        }
+        self.buffered += data.len();
+        self.frames.push(data);
+        if self.buffered > self.max_buffer {
+            self.dropped = self.frames.len();
+            self.frames = Vec::new();
+            self.buffered = 0;
+        }
+    }
+}
+
+// ===== impl Exhausted =====
+
+impl fmt::Debug for Exhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("hyper_util::body::ReplayBody::Exhausted")
+            .finish()
+    }
+}
+
+impl fmt::Display for Exhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("body was larger than its replay buffer")
+    }
+}
+
+impl StdError for Exhausted {}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::HeaderMap;
+    use http_body::Frame;
+    use http_body_util::{BodyExt, StreamBody};
+
+    use super::ReplayBody;
+
+    fn stream(
+        chunks: &'static [&'static str],
+    ) -> StreamBody<impl futures_util::Stream<Item = Result<Frame<Bytes>, std::io::Error>>> {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-done", "1".parse().unwrap());
+        let frames = chunks
+            .iter()
+            .map(|chunk| Ok(Frame::data(Bytes::from_static(chunk.as_bytes()))))
+            .chain(Some(Ok(Frame::trailers(trailers))));
+        StreamBody::new(futures_util::stream::iter(frames))
+    }
+
+    #[tokio::test]
+    async fn replays_data_and_trailers() {
+        let body = ReplayBody::new(stream(&["hello", " ", "world"]), 64);
+        let retry = body.clone();
+
+        let first = body.collect().await.unwrap();
+        assert_eq!(first.trailers().unwrap()["x-done"], "1");
+        assert_eq!(first.to_bytes(), "hello world");
+
+        assert!(retry.is_replayable());
+        let second = retry.clone().collect().await.unwrap();
+        assert_eq!(second.trailers().unwrap()["x-done"], "1");
+        assert_eq!(second.to_bytes(), "hello world");
+    }
+
+    #[tokio::test]
+    async fn partial_read_continues_from_inner() {
+        let mut body = ReplayBody::new(stream(&["a", "b", "c"]), 64);
+        let retry = body.clone();
+
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "a");
+        drop(body);
+
+        let all = retry.collect().await.unwrap().to_bytes();
+        assert_eq!(all, "abc");
+    }
+
+    #[tokio::test]
+    async fn exhausted_after_limit() {
+        let body = ReplayBody::new(stream(&["hello", "world"]), 8);
+        let retry = body.clone();
+
+        // the first reader still gets everything
+        let first = body.collect().await.unwrap().to_bytes();
+        assert_eq!(first, "helloworld");
+
+        assert!(!retry.is_replayable());
+        assert!(retry.collect().await.is_err());
+    }
+}
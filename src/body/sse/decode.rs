@@ -0,0 +1,346 @@
+use std::fmt;
+use std::pin::Pin;
+use std::task::{self, Poll};
+use std::time::Duration;
+
+use bytes::{Buf, BytesMut};
+use futures_util::{ready, Stream};
+use http_body::Body;
+use pin_project_lite::pin_project;
+
+use super::error::Kind;
+use super::{BoxError, Event, SseError};
+
+pin_project! {
+    /// A `Stream` of the events in a `text/event-stream` body.
+    ///
+    /// An event still incomplete when the body ends is discarded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # async fn run() -> Result<(), hyper_util::body::sse::SseError> {
+    /// use bytes::Bytes;
+    /// use futures_util::StreamExt;
+    /// use http_body_util::Full;
+    /// use hyper_util::body::sse::Decoder;
+    ///
+    /// let body = Full::new(Bytes::from_static(b"event: greeting\ndata: hello\n\n"));
+    /// let mut events = Decoder::new(body);
+    ///
+    /// while let Some(event) = events.next().await {
+    ///     let event = event?;
+    ///     assert_eq!(event.event(), Some("greeting"));
+    ///     assert_eq!(event.data(), "hello");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub struct Decoder<B> {
+        #[pin]
+        body: B,
+        buf: BytesMut,
+        eof: bool,
+        bom_checked: bool,
+        max_event_size: usize,
+        data: String,
+        has_data: bool,
+        event: Option<String>,
+        last_event_id: String,
+        retry: Option<Duration>,
+    }
+}
+
+impl<B> Decoder<B> {
+    /// Create a decoder reading events out of `body`.
+    pub fn new(body: B) -> Decoder<B> {
+        Decoder {
+            body,
+            buf: BytesMut::new(),
+            eof: false,
+            bom_checked: false,
+            max_event_size: 1024 * 1024,
+            data: String::new(),
+            has_data: false,
+            event: None,
+            last_event_id: String::new(),
+            retry: None,
+        }
+    }
+
+    /// Set the maximum size of a single event.
+    ///
+    /// Default is 1MB.
+    pub fn max_event_size(mut self, max: usize) -> Decoder<B> {
+        self.max_event_size = max;
+        self
+    }
+
+    /// The id of the last event received, which should be sent as the
+    /// `last-event-id` header when reconnecting.
+    pub fn last_event_id(&self) -> Option<&str> {
+        if self.last_event_id.is_empty() {
+            None
+        } else {
+            Some(&self.last_event_id)
+        }
+    }
+
+    /// The reconnection time last requested by the server, if any.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+    pub(super) fn resume(mut self, last_event_id: Option<&str>) -> Decoder<B> {
+        self.last_event_id = last_event_id.unwrap_or_default().to_owned();
+        self
+    }
+}
+
+impl<B> Stream for Decoder<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Item = Result<Event, SseError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut me = self.project();
+        loop {
+            if !*me.bom_checked && (me.buf.len() >= 3 || *me.eof) {
+                if me.buf.starts_with(b"\xEF\xBB\xBF") {
+                    me.buf.advance(3);
+                }
+                *me.bom_checked = true;
+            }
+
+            while *me.bom_checked {
+                let line = match next_line(me.buf, *me.eof) {
+                    Some(line) => line,
+                    None => break,
+                };
+                let line = String::from_utf8_lossy(&line);
+                if line.is_empty() {
+                    let event =
+                        dispatch(me.event, me.has_data, me.data, me.last_event_id, *me.retry);
+                    if let Some(event) = event {
+                        return Poll::Ready(Some(Ok(event)));
+                    }
+                    continue;
+                }
+
+                let (field, value) = match line.find(':') {
+                    // comment
+                    Some(0) => continue,
+                    Some(idx) => {
+                        let value = &line[idx + 1..];
+                        (&line[..idx], value.strip_prefix(' ').unwrap_or(value))
+                    }
+                    None => (&*line, ""),
+                };
+                match field {
+                    "event" => *me.event = Some(value.to_owned()),
+                    "data" => {
+                        if *me.has_data {
+                            me.data.push('\n');
+                        }
+                        me.data.push_str(value);
+                        *me.has_data = true;
+                    }
+                    "id" if !value.contains('\0') => {
+                        me.last_event_id.clear();
+                        me.last_event_id.push_str(value);
+                    }
+                    "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                        if let Ok(ms) = value.parse() {
+                            *me.retry = Some(Duration::from_millis(ms));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+
+            if *me.eof {
+                return Poll::Ready(None);
+            }
+
+            if me.buf.len() + me.data.len() > *me.max_event_size {
+                *me.eof = true;
+                me.buf.clear();
+                return Poll::Ready(Some(Err(SseError::new(Kind::EventTooLarge))));
+            }
+
+            match ready!(me.body.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => {
+                    if let Ok(mut data) = frame.into_data() {
+                        while data.has_remaining() {
+                            let chunk = data.chunk();
+                            let len = chunk.len();
+                            me.buf.extend_from_slice(chunk);
+                            data.advance(len);
+                        }
+                    }
+                }
+                Some(Err(err)) => {
+                    *me.eof = true;
+                    me.buf.clear();
+                    return Poll::Ready(Some(Err(SseError::with(Kind::Body, err))));
+                }
+                None => *me.eof = true,
+            }
+        }
+    }
+}
+
+impl<B> fmt::Debug for Decoder<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Decoder")
+            .field("last_event_id", &self.last_event_id)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+// An empty line ends the event, though nothing is dispatched if there
+// were no `data` fields.
+fn dispatch(
+    event: &mut Option<String>,
+    has_data: &mut bool,
+    data: &mut String,
+    last_event_id: &str,
+    retry: Option<Duration>,
+) -> Option<Event> {
+    let event = event.take();
+    if !std::mem::replace(has_data, false) {
+        return None;
+    }
+    let id = if last_event_id.is_empty() {
+        None
+    } else {
+        Some(last_event_id.to_owned())
+    };
+    Some(Event::from_parts(id, event, std::mem::take(data), retry))
+}
+
+// Lines may end with CRLF, LF or a lone CR.
+fn next_line(buf: &mut BytesMut, eof: bool) -> Option<BytesMut> {
+    let idx = buf.iter().position(|&b| b == b'\r' || b == b'\n')?;
+    let end = if buf[idx] == b'\r' {
+        match buf.get(idx + 1) {
+            Some(b'\n') => idx + 2,
+            Some(_) => idx + 1,
+            // might be the first half of a CRLF
+            None if !eof => return None,
+            None => idx + 1,
+        }
+    } else {
+        idx + 1
+    };
+    let mut line = buf.split_to(end);
+    line.truncate(idx);
+    Some(line)
+}
+
+#[cfg(all(test, not(miri)))]
+mod tests {
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures_util::{stream, StreamExt};
+    use http_body::Frame;
+    use http_body_util::{BodyExt, StreamBody};
+
+    use super::Decoder;
+    use crate::body::sse::{Event, SseBody};
+
+    fn chunked(
+        chunks: &[&'static str],
+    ) -> StreamBody<impl futures_util::Stream<Item = Result<Frame<Bytes>, Infallible>>> {
+        let frames = chunks
+            .iter()
+            .map(|chunk| Ok(Frame::data(Bytes::from_static(chunk.as_bytes()))))
+            .collect::<Vec<_>>();
+        StreamBody::new(stream::iter(frames))
+    }
+
+    async fn decode(chunks: &[&'static str]) -> Vec<Event> {
+        Decoder::new(chunked(chunks))
+            .map(|event| event.unwrap())
+            .collect()
+            .await
+    }
+
+    #[tokio::test]
+    async fn decodes_fields_and_line_endings() {
+        let events = decode(&[
+            "\u{feff}: comment\r\nevent: greeting\r",
+            "\ndata: hello\rdata:world\n",
+            "id: 7\nretry: 500\n\n",
+            "data\n\n",
+            "data: incomplete",
+        ])
+        .await;
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event(), Some("greeting"));
+        assert_eq!(events[0].data(), "hello\nworld");
+        assert_eq!(events[0].id(), Some("7"));
+        assert_eq!(events[0].retry(), Some(Duration::from_millis(500)));
+        // the last event id carries over
+        assert_eq!(events[1].event(), None);
+        assert_eq!(events[1].data(), "");
+        assert_eq!(events[1].id(), Some("7"));
+    }
+
+    #[tokio::test]
+    async fn events_without_data_are_not_dispatched() {
+        let events = decode(&["event: nothing\n\nid: 1\n\ndata: x\n\n"]).await;
+        assert_eq!(
+            events,
+            vec![Event::from_parts(Some("1".into()), None, "x".into(), None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn event_too_large() {
+        let mut decoder =
+            Decoder::new(chunked(&["data: 0123456789", "0123456789\n\n"])).max_event_size(8);
+        assert!(decoder.next().await.unwrap().unwrap_err().is_too_large());
+        assert!(decoder.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn encoder_roundtrip() {
+        let sent = vec![
+            Event::new("line one\r\nline two\rline three")
+                .with_event("multi")
+                .with_id("a")
+                .with_retry(Duration::from_secs(1)),
+            Event::new(""),
+        ];
+        let events = stream::iter(sent.clone().into_iter().map(Ok::<_, Infallible>));
+        let body = SseBody::new(events).collect().await.unwrap().to_bytes();
+
+        let received = Decoder::new(http_body_util::Full::new(body))
+            .map(|event| event.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(received[0].data(), "line one\nline two\nline three");
+        assert_eq!(received[0].event(), Some("multi"));
+        assert_eq!(received[0].retry(), Some(Duration::from_secs(1)));
+        assert_eq!(received[1].data(), "");
+        assert_eq!(received[1].id(), Some("a"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn encoder_keep_alive() {
+        let body = SseBody::new(stream::pending::<Result<Event, Infallible>>())
+            .keep_alive(crate::rt::TokioTimer::new(), Duration::from_secs(10));
+        let mut body = Box::pin(body);
+
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), ":\n\n");
+    }
+}
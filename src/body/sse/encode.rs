@@ -0,0 +1,138 @@
+use std::fmt;
+use std::pin::Pin;
+use std::task::{self, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::Stream;
+use http_body::{Body, Frame};
+use hyper::rt::Sleep;
+use pin_project_lite::pin_project;
+
+use super::{BoxError, Event};
+use crate::common::timer::Timer;
+
+pin_project! {
+    /// A `text/event-stream` body, encoding events from a `Stream`.
+    ///
+    /// The response carrying this body should have a `content-type` of
+    /// `text/event-stream`, and usually `cache-control: no-cache`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "tokio")]
+    /// # fn run() {
+    /// use std::convert::Infallible;
+    /// use std::time::Duration;
+    ///
+    /// use hyper_util::body::sse::{Event, SseBody};
+    /// use hyper_util::rt::TokioTimer;
+    ///
+    /// let events = futures_util::stream::iter(vec![
+    ///     Ok::<_, Infallible>(Event::new("hello")),
+    ///     Ok(Event::new("world").with_event("greeting")),
+    /// ]);
+    ///
+    /// let body = SseBody::new(events).keep_alive(TokioTimer::new(), Duration::from_secs(15));
+    ///
+    /// let res = http::Response::builder()
+    ///     .header("content-type", "text/event-stream")
+    ///     .header("cache-control", "no-cache")
+    ///     .body(body)
+    ///     .unwrap();
+    /// # drop(res);
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub struct SseBody<S> {
+        #[pin]
+        events: S,
+        keep_alive: Option<KeepAlive>,
+    }
+}
+
+struct KeepAlive {
+    timer: Timer,
+    interval: Duration,
+    sleep: Pin<Box<dyn Sleep>>,
+}
+
+impl<S> SseBody<S> {
+    /// Create a body sending the events of `events`.
+    pub fn new(events: S) -> SseBody<S> {
+        SseBody {
+            events,
+            keep_alive: None,
+        }
+    }
+
+    /// Send a comment whenever no event has been sent for `interval`.
+    ///
+    /// This keeps proxies and clients from closing an idle connection.
+    pub fn keep_alive<M>(mut self, timer: M, interval: Duration) -> SseBody<S>
+    where
+        M: hyper::rt::Timer + Send + Sync + 'static,
+    {
+        let timer = Timer::new(timer);
+        let sleep = hyper::rt::Timer::sleep(&timer, interval);
+        self.keep_alive = Some(KeepAlive {
+            timer,
+            interval,
+            sleep,
+        });
+        self
+    }
+}
+
+impl<S, E> Body for SseBody<S>
+where
+    S: Stream<Item = Result<Event, E>>,
+    E: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        match this.events.poll_next(cx) {
+            Poll::Ready(Some(Ok(event))) => {
+                if let Some(ka) = this.keep_alive {
+                    ka.reset();
+                }
+                Poll::Ready(Some(Ok(Frame::data(event.encode()))))
+            }
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.into()))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => {
+                if let Some(ka) = this.keep_alive {
+                    if ka.sleep.as_mut().poll(cx).is_ready() {
+                        ka.reset();
+                        return Poll::Ready(Some(Ok(Frame::data(Bytes::from_static(b":\n\n")))));
+                    }
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<S> fmt::Debug for SseBody<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SseBody")
+            .field(
+                "keep_alive",
+                &self.keep_alive.as_ref().map(|ka| ka.interval),
+            )
+            .finish()
+    }
+}
+
+impl KeepAlive {
+    fn reset(&mut self) {
+        self.sleep = hyper::rt::Timer::sleep(&self.timer, self.interval);
+    }
+}
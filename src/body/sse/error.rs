@@ -0,0 +1,100 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use super::BoxError;
+
+/// An error reading server-sent events.
+pub struct SseError {
+    kind: Kind,
+    source: Option<BoxError>,
+}
+
+#[derive(Debug)]
+pub(super) enum Kind {
+    Body,
+    EventTooLarge,
+    #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+    Request,
+    #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+    Status(http::StatusCode),
+    #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+    ContentType,
+}
+
+impl SseError {
+    pub(super) fn new(kind: Kind) -> SseError {
+        SseError { kind, source: None }
+    }
+
+    pub(super) fn with<E: Into<BoxError>>(kind: Kind, source: E) -> SseError {
+        SseError {
+            kind,
+            source: Some(source.into()),
+        }
+    }
+
+    /// Returns true if the underlying body returned an error.
+    pub fn is_body(&self) -> bool {
+        matches!(self.kind, Kind::Body)
+    }
+
+    /// Returns true if a single event was larger than allowed.
+    pub fn is_too_large(&self) -> bool {
+        matches!(self.kind, Kind::EventTooLarge)
+    }
+
+    /// Returns true if the request to the event source failed.
+    #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+    pub fn is_request(&self) -> bool {
+        matches!(self.kind, Kind::Request)
+    }
+
+    /// Returns the status code, if the event source responded with an
+    /// unexpected one.
+    #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+    pub fn status(&self) -> Option<http::StatusCode> {
+        match self.kind {
+            Kind::Status(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the event source responded with a `content-type`
+    /// other than `text/event-stream`.
+    #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+    pub fn is_content_type(&self) -> bool {
+        matches!(self.kind, Kind::ContentType)
+    }
+}
+
+impl fmt::Debug for SseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_tuple("hyper_util::body::sse::SseError");
+        f.field(&self.kind);
+        if let Some(ref cause) = self.source {
+            f.field(cause);
+        }
+        f.finish()
+    }
+}
+
+impl fmt::Display for SseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::Body => f.write_str("error reading a body"),
+            Kind::EventTooLarge => f.write_str("event too large"),
+            #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+            Kind::Request => f.write_str("error sending request to event source"),
+            #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+            Kind::Status(status) => write!(f, "unexpected event source status: {}", status),
+            #[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+            Kind::ContentType => f.write_str("event source is not a text/event-stream"),
+        }
+    }
+}
+
+impl StdError for SseError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| &**e as _)
+    }
+}
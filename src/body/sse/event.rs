@@ -0,0 +1,119 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use bytes::Bytes;
+
+/// A single server-sent event.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Create an event with the given `data`.
+    ///
+    /// Data containing newlines is sent as multiple `data` lines, and
+    /// joined back together when decoded.
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Set the `id` of this event.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the id contains a newline or a NUL character.
+    pub fn with_id(mut self, id: impl Into<String>) -> Event {
+        let id = id.into();
+        assert!(
+            !id.contains(&['\r', '\n', '\0'][..]),
+            "event id cannot contain newlines or NUL"
+        );
+        self.id = Some(id);
+        self
+    }
+
+    /// Set the `event` type of this event.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name contains a newline.
+    pub fn with_event(mut self, event: impl Into<String>) -> Event {
+        let event = event.into();
+        assert!(
+            !event.contains(&['\r', '\n'][..]),
+            "event type cannot contain newlines"
+        );
+        self.event = Some(event);
+        self
+    }
+
+    /// Set the reconnection time the client should use.
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// The `id` of this event.
+    ///
+    /// For decoded events, this is the last id received on the stream,
+    /// even if it was set by an earlier event.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// The `event` type of this event, if one was set.
+    pub fn event(&self) -> Option<&str> {
+        self.event.as_deref()
+    }
+
+    /// The data of this event.
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    /// The reconnection time.
+    ///
+    /// For decoded events, this is the last one received on the stream.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    pub(super) fn from_parts(
+        id: Option<String>,
+        event: Option<String>,
+        data: String,
+        retry: Option<Duration>,
+    ) -> Event {
+        Event {
+            id,
+            event,
+            data,
+            retry,
+        }
+    }
+
+    pub(super) fn encode(&self) -> Bytes {
+        let mut buf = String::with_capacity(self.data.len() + 16);
+        if let Some(ref event) = self.event {
+            let _ = writeln!(buf, "event: {}", event);
+        }
+        if let Some(ref id) = self.id {
+            let _ = writeln!(buf, "id: {}", id);
+        }
+        if let Some(retry) = self.retry {
+            let _ = writeln!(buf, "retry: {}", retry.as_millis());
+        }
+        for line in self.data.replace("\r\n", "\n").split(&['\r', '\n'][..]) {
+            let _ = writeln!(buf, "data: {}", line);
+        }
+        buf.push('\n');
+        Bytes::from(buf)
+    }
+}
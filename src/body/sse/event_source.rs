@@ -0,0 +1,293 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{self, Poll};
+use std::time::Duration;
+
+use futures_util::{ready, Stream};
+use http::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, CACHE_CONTROL, CONTENT_TYPE};
+use http::{Request, StatusCode, Uri};
+use http_body::Body;
+use hyper::body::Incoming;
+use hyper::rt::Sleep;
+
+use super::error::Kind;
+use super::{Decoder, Event, SseError};
+use crate::client::legacy::connect::Connect;
+use crate::client::legacy::{Client, ResponseFuture};
+use crate::common::timer::Timer;
+
+/// A `Stream` of events from a remote event source.
+///
+/// Whenever the connection fails or the response body ends, the request
+/// is sent again after the reconnection time, including a `last-event-id`
+/// header if the server sent event ids. Errors that lead to a reconnect are
+/// still yielded, so they can be logged or used to stop polling.
+///
+/// The stream ends if the server responds with `204 No Content`, and after
+/// yielding an error for any other status than `200 OK` or a `content-type`
+/// other than `text/event-stream`.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(all(feature = "http1", feature = "tokio"))]
+/// # async fn run() {
+/// use bytes::Bytes;
+/// use futures_util::StreamExt;
+/// use http_body_util::Empty;
+/// use hyper_util::body::sse::EventSource;
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::{TokioExecutor, TokioTimer};
+///
+/// let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+/// let uri = "http://example.local/events".parse().unwrap();
+///
+/// let mut events = EventSource::new(client, uri, TokioTimer::new());
+/// while let Some(event) = events.next().await {
+///     match event {
+///         Ok(event) => println!("{}", event.data()),
+///         Err(err) => eprintln!("event source error: {}", err),
+///     }
+/// }
+/// # }
+/// # fn main() {}
+/// ```
+pub struct EventSource<C, B> {
+    client: Client<C, B>,
+    uri: Uri,
+    headers: HeaderMap,
+    timer: Timer,
+    retry: Duration,
+    last_event_id: Option<String>,
+    state: State,
+}
+
+enum State {
+    Idle,
+    Connecting(ResponseFuture),
+    Streaming(Decoder<Incoming>),
+    Waiting(Pin<Box<dyn Sleep>>),
+    Closed,
+}
+
+impl<C, B> EventSource<C, B> {
+    /// Create an event source sending `GET` requests to `uri`.
+    ///
+    /// The `timer` is used to wait between reconnects.
+    pub fn new<M>(client: Client<C, B>, uri: Uri, timer: M) -> EventSource<C, B>
+    where
+        M: hyper::rt::Timer + Send + Sync + 'static,
+    {
+        EventSource {
+            client,
+            uri,
+            headers: HeaderMap::new(),
+            timer: Timer::new(timer),
+            retry: Duration::from_secs(3),
+            last_event_id: None,
+            state: State::Idle,
+        }
+    }
+
+    /// Add a header to send with every request.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> EventSource<C, B> {
+        self.headers.append(name, value);
+        self
+    }
+
+    /// Set the reconnection time to use until the server sends one.
+    ///
+    /// Default is 3 seconds.
+    pub fn retry(mut self, retry: Duration) -> EventSource<C, B> {
+        self.retry = retry;
+        self
+    }
+
+    /// Set the id to resume from, sent as `last-event-id` on the first
+    /// request.
+    pub fn last_event_id(mut self, id: impl Into<String>) -> EventSource<C, B> {
+        self.last_event_id = Some(id.into());
+        self
+    }
+
+    /// The id of the last event received.
+    pub fn get_last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    fn reconnect(&mut self) {
+        if let State::Streaming(ref decoder) = self.state {
+            self.last_event_id = decoder.last_event_id().map(str::to_owned);
+            if let Some(retry) = decoder.retry() {
+                self.retry = retry;
+            }
+        }
+        self.state = State::Waiting(hyper::rt::Timer::sleep(&self.timer, self.retry));
+    }
+}
+
+impl<C, B> Stream for EventSource<C, B>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+    B: Body + Default + Send + 'static + Unpin,
+    B::Data: Send,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Item = Result<Event, SseError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Self::Item>> {
+        let me = self.get_mut();
+        loop {
+            match me.state {
+                State::Idle => {
+                    let mut req = Request::new(B::default());
+                    *req.uri_mut() = me.uri.clone();
+                    *req.headers_mut() = me.headers.clone();
+                    req.headers_mut()
+                        .insert(ACCEPT, HeaderValue::from_static("text/event-stream"));
+                    req.headers_mut()
+                        .insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+                    if let Some(ref id) = me.last_event_id {
+                        if let Ok(value) = HeaderValue::from_str(id) {
+                            req.headers_mut().insert("last-event-id", value);
+                        }
+                    }
+                    me.state = State::Connecting(me.client.request(req));
+                }
+                State::Connecting(ref mut fut) => match ready!(Pin::new(fut).poll(cx)) {
+                    Ok(res) => {
+                        if res.status() == StatusCode::NO_CONTENT {
+                            me.state = State::Closed;
+                            return Poll::Ready(None);
+                        }
+                        if res.status() != StatusCode::OK {
+                            me.state = State::Closed;
+                            let err = SseError::new(Kind::Status(res.status()));
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                        if !is_event_stream(res.headers().get(CONTENT_TYPE)) {
+                            me.state = State::Closed;
+                            let err = SseError::new(Kind::ContentType);
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                        let decoder =
+                            Decoder::new(res.into_body()).resume(me.last_event_id.as_deref());
+                        me.state = State::Streaming(decoder);
+                    }
+                    Err(err) => {
+                        me.reconnect();
+                        return Poll::Ready(Some(Err(SseError::with(Kind::Request, err))));
+                    }
+                },
+                State::Streaming(ref mut decoder) => {
+                    match ready!(Pin::new(decoder).poll_next(cx)) {
+                        Some(Ok(event)) => {
+                            me.last_event_id = event.id().map(str::to_owned);
+                            return Poll::Ready(Some(Ok(event)));
+                        }
+                        Some(Err(err)) => {
+                            me.reconnect();
+                            return Poll::Ready(Some(Err(err)));
+                        }
+                        None => me.reconnect(),
+                    }
+                }
+                State::Waiting(ref mut sleep) => {
+                    ready!(sleep.as_mut().poll(cx));
+                    me.state = State::Idle;
+                }
+                State::Closed => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+// Nothing is pinned structurally, the futures are all boxed.
+impl<C, B> Unpin for EventSource<C, B> {}
+
+impl<C, B> fmt::Debug for EventSource<C, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSource")
+            .field("uri", &self.uri)
+            .field("last_event_id", &self.last_event_id)
+            .finish()
+    }
+}
+
+fn is_event_stream(content_type: Option<&HeaderValue>) -> bool {
+    content_type
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map_or(false, |mime| {
+            mime.trim().eq_ignore_ascii_case("text/event-stream")
+        })
+}
+
+#[cfg(all(test, not(miri), feature = "http1", feature = "tokio"))]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use futures_util::StreamExt;
+    use http_body_util::Empty;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::EventSource;
+    use crate::client::legacy::Client;
+    use crate::rt::{TokioExecutor, TokioTimer};
+
+    async fn read_head(stream: &mut tokio::net::TcpStream) -> String {
+        let mut buf = Vec::new();
+        while !buf.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).await.unwrap();
+            buf.push(byte[0]);
+        }
+        String::from_utf8(buf).unwrap().to_ascii_lowercase()
+    }
+
+    #[tokio::test]
+    async fn reconnects_with_last_event_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let head = read_head(&mut stream).await;
+            assert!(head.contains("accept: text/event-stream"));
+            assert!(!head.contains("last-event-id"));
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\nconnection: close\r\n\r\n\
+                      retry: 10\nid: 1\ndata: first\n\n",
+                )
+                .await
+                .unwrap();
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let head = read_head(&mut stream).await;
+            assert!(head.contains("last-event-id: 1"));
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+        let uri = format!("http://{}/events", addr).parse().unwrap();
+        let mut events =
+            EventSource::new(client, uri, TokioTimer::new()).retry(Duration::from_secs(60));
+
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(event.data(), "first");
+        // the server set a short retry, so this doesn't wait a minute
+        assert!(events.next().await.is_none());
+        assert_eq!(events.get_last_event_id(), Some("1"));
+
+        server.await.unwrap();
+    }
+}
@@ -0,0 +1,26 @@
+//! Server-Sent Events (`text/event-stream`) bodies.
+//!
+//! This module contains:
+//!
+//! - An [`Event`] type, shared by the encoder and the decoder.
+//! - [`SseBody`] to send a stream of events as a response body, with
+//!   optional keep-alive comments.
+//! - A [`Decoder`] to read events out of a received body.
+//! - An `EventSource` that reconnects with `last-event-id` using the
+//!   legacy `Client` (requires the `client-legacy` feature).
+
+pub use self::decode::Decoder;
+pub use self::encode::SseBody;
+pub use self::error::SseError;
+pub use self::event::Event;
+#[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+pub use self::event_source::EventSource;
+
+mod decode;
+mod encode;
+mod error;
+mod event;
+#[cfg(all(feature = "client-legacy", any(feature = "http1", feature = "http2")))]
+mod event_source;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
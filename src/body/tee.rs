@@ -0,0 +1,252 @@
+use std::fmt;
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use bytes::{Buf, Bytes};
+use futures_util::ready;
+use http::HeaderMap;
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+
+pin_project! {
+    /// A body that copies its data into a sink as it is polled.
+    ///
+    /// Every data frame is handed to the [`TeeSink`] before being passed on,
+    /// so an audit log or a digest sees exactly the bytes sent, without
+    /// buffering the body a second time. Once the inner body ends, the sink
+    /// may add trailers, such as a digest of the whole body, which are merged
+    /// into the trailers of the inner body, if any.
+    ///
+    /// Data frames are turned into `Bytes`, which doesn't copy the data of
+    /// bodies already made of `Bytes`.
+    ///
+    /// Trailers only reach the peer over HTTP/2, or over HTTP/1.1 with
+    /// chunked encoding, and hyper only sends HTTP/1.1 trailers that are
+    /// declared in a `trailer` header of the message.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use bytes::Bytes;
+    /// use http_body_util::Full;
+    /// use hyper_util::body::Tee;
+    ///
+    /// let mut sent = Vec::new();
+    /// let body = Tee::new(Full::new(Bytes::from_static(b"hello")), |chunk: &[u8]| {
+    ///     sent.extend_from_slice(chunk)
+    /// });
+    /// # drop(body);
+    /// ```
+    pub struct Tee<B, S> {
+        #[pin]
+        inner: B,
+        sink: S,
+        finished: bool,
+    }
+}
+
+/// Receives the data of a [`Tee`] body.
+///
+/// This is implemented for closures taking each chunk of data.
+pub trait TeeSink {
+    /// Called with the bytes of each data frame.
+    fn data(&mut self, chunk: &[u8]);
+
+    /// Called once the inner body ended, returning trailers to add to it.
+    ///
+    /// This isn't called if the body fails, or is dropped before its end.
+    fn finish(&mut self) -> Option<HeaderMap> {
+        None
+    }
+}
+
+// ===== impl Tee =====
+
+impl<B, S> Tee<B, S> {
+    /// Wrap a body, copying its data into `sink`.
+    pub fn new(inner: B, sink: S) -> Tee<B, S> {
+        Tee {
+            inner,
+            sink,
+            finished: false,
+        }
+    }
+
+    /// Get a reference to the sink.
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    /// Get a reference to the inner body.
+    pub fn get_ref(&self) -> &B {
+        &self.inner
+    }
+
+    /// Consume this wrapper, returning the inner body and the sink.
+    pub fn into_parts(self) -> (B, S) {
+        (self.inner, self.sink)
+    }
+}
+
+#[cfg(feature = "body-digest")]
+impl<B> Tee<B, ContentDigest> {
+    /// Wrap a body, appending the SHA-256 of its data as a `content-digest`
+    /// trailer.
+    pub fn content_digest(inner: B) -> Tee<B, ContentDigest> {
+        Tee::new(inner, ContentDigest::new())
+    }
+}
+
+impl<B, S> Body for Tee<B, S>
+where
+    B: Body,
+    S: TeeSink,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let me = self.project();
+        if *me.finished {
+            return Poll::Ready(None);
+        }
+
+        let frame = match ready!(me.inner.poll_frame(cx)) {
+            Some(Ok(frame)) => frame,
+            Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+            None => {
+                *me.finished = true;
+                return Poll::Ready(
+                    me.sink
+                        .finish()
+                        .map(|trailers| Ok(Frame::trailers(trailers))),
+                );
+            }
+        };
+
+        let frame = frame.map_data(|mut data| data.copy_to_bytes(data.remaining()));
+        let frame = match frame.into_trailers() {
+            Ok(mut trailers) => {
+                *me.finished = true;
+                if let Some(added) = me.sink.finish() {
+                    trailers.extend(added);
+                }
+                return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+            }
+            Err(frame) => frame,
+        };
+        if let Some(data) = frame.data_ref() {
+            me.sink.data(data);
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        // Even an empty body is polled to its end, so the sink can finish.
+        self.finished
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B, S> fmt::Debug for Tee<B, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tee")
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+// ===== impl TeeSink =====
+
+impl<F> TeeSink for F
+where
+    F: FnMut(&[u8]),
+{
+    fn data(&mut self, chunk: &[u8]) {
+        self(chunk)
+    }
+}
+
+/// A [`TeeSink`] computing the SHA-256 digest of a body, as a
+/// `content-digest` trailer (RFC 9530).
+#[cfg(feature = "body-digest")]
+#[derive(Clone, Debug, Default)]
+pub struct ContentDigest {
+    hasher: sha2::Sha256,
+}
+
+#[cfg(feature = "body-digest")]
+impl ContentDigest {
+    /// Create a sink for a SHA-256 digest.
+    pub fn new() -> ContentDigest {
+        ContentDigest::default()
+    }
+}
+
+#[cfg(feature = "body-digest")]
+impl TeeSink for ContentDigest {
+    fn data(&mut self, chunk: &[u8]) {
+        sha2::Digest::update(&mut self.hasher, chunk);
+    }
+
+    fn finish(&mut self) -> Option<HeaderMap> {
+        let digest = sha2::Digest::finalize_reset(&mut self.hasher);
+        let value = format!("sha-256=:{}:", crate::common::base64::encode(&digest));
+        let mut trailers = HeaderMap::new();
+        trailers.insert(
+            http::header::HeaderName::from_static("content-digest"),
+            value.parse().expect("base64 is a valid header value"),
+        );
+        Some(trailers)
+    }
+}
+
+#[cfg(all(test, feature = "body-digest"))]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use futures_util::stream;
+    use http::HeaderMap;
+    use http_body::Frame;
+    use http_body_util::{BodyExt, StreamBody};
+
+    use super::Tee;
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn digest_trailer_merged() {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("x-signed-by", "me".parse().unwrap());
+        let frames = vec![
+            Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"hel"))),
+            Ok(Frame::data(Bytes::from_static(b"lo"))),
+            Ok(Frame::trailers(trailers)),
+        ];
+        let body = Tee::content_digest(StreamBody::new(stream::iter(frames)));
+        let collected = body.collect().await.unwrap();
+        let trailers = collected.trailers().unwrap();
+        assert_eq!(trailers["x-signed-by"], "me");
+        assert_eq!(
+            trailers["content-digest"],
+            "sha-256=:LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=:"
+        );
+        assert_eq!(collected.to_bytes(), "hello");
+
+        // without trailers of its own, and with a closure
+        let mut sent = Vec::new();
+        let frames = vec![Ok::<_, Infallible>(Frame::data(Bytes::from_static(b"ab")))];
+        let body = Tee::new(StreamBody::new(stream::iter(frames)), |chunk: &[u8]| {
+            sent.extend_from_slice(chunk)
+        });
+        let collected = body.collect().await.unwrap();
+        assert!(collected.trailers().is_none());
+        assert_eq!(sent, b"ab");
+    }
+}
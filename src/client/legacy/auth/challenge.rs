@@ -0,0 +1,101 @@
+/// A single challenge of a `WWW-Authenticate` header.
+#[derive(Debug, PartialEq)]
+pub(super) struct Challenge {
+    pub(super) scheme: String,
+    pub(super) params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    pub(super) fn is(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+
+    pub(super) fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &**value)
+    }
+}
+
+// A header may hold several challenges, each followed by comma separated
+// parameters, such as `Digest realm="a", nonce="b", Basic realm="a"`.
+pub(super) fn parse(header: &str) -> Vec<Challenge> {
+    let mut challenges: Vec<Challenge> = Vec::new();
+    let mut rest = header;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+        if rest.is_empty() {
+            return challenges;
+        }
+
+        let end = rest
+            .find(|c: char| c == ',' || c == '=' || c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let token = &rest[..end];
+        let after = rest[end..].trim_start();
+
+        // A token followed by `=` (but not a token68 like `abc==`) is a
+        // parameter of the current challenge.
+        if after.starts_with('=') && !after[1..].starts_with('=') {
+            let (value, remaining) = parse_value(after[1..].trim_start());
+            if let Some(challenge) = challenges.last_mut() {
+                challenge.params.push((token.to_owned(), value));
+            }
+            rest = remaining;
+        } else if end == 0 {
+            // a stray `=`
+            rest = &rest[1..];
+        } else if after.starts_with('=') {
+            // token68, which none of the supported schemes use
+            rest = after.trim_start_matches('=');
+        } else {
+            challenges.push(Challenge {
+                scheme: token.to_owned(),
+                params: Vec::new(),
+            });
+            rest = &rest[end..];
+        }
+    }
+}
+
+fn parse_value(s: &str) -> (String, &str) {
+    if let Some(quoted) = s.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = quoted.char_indices();
+        while let Some((idx, c)) = chars.next() {
+            match c {
+                '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                '"' => return (value, &quoted[idx + 1..]),
+                c => value.push(c),
+            }
+        }
+        (value, "")
+    } else {
+        let end = s
+            .find(|c: char| c == ',' || c.is_ascii_whitespace())
+            .unwrap_or(s.len());
+        (s[..end].to_owned(), &s[end..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn parses_multiple_challenges() {
+        let challenges = parse(
+            r#"Digest realm="test, realm", qop="auth,auth-int", nonce=abc, Basic realm="x\"y", Bearer"#,
+        );
+        assert_eq!(challenges.len(), 3);
+        assert!(challenges[0].is("digest"));
+        assert_eq!(challenges[0].param("realm"), Some("test, realm"));
+        assert_eq!(challenges[0].param("qop"), Some("auth,auth-int"));
+        assert_eq!(challenges[0].param("nonce"), Some("abc"));
+        assert!(challenges[1].is("Basic"));
+        assert_eq!(challenges[1].param("realm"), Some("x\"y"));
+        assert!(challenges[2].is("Bearer"));
+        assert!(challenges[2].params.is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+use md5::Md5;
+use sha2::{Digest as _, Sha256};
+
+use super::challenge::Challenge;
+use crate::common::rand::random_u64;
+
+/// The state of a Digest challenge, reused for later requests to the same
+/// origin until the server sends a new nonce.
+#[derive(Debug)]
+pub(super) struct DigestState {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    sess: bool,
+    qop_auth: bool,
+    nc: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestState {
+    pub(super) fn from_challenge(challenge: &Challenge) -> Option<DigestState> {
+        let (algorithm, sess) = match challenge.param("algorithm") {
+            None => (Algorithm::Md5, false),
+            Some(alg) if alg.eq_ignore_ascii_case("MD5") => (Algorithm::Md5, false),
+            Some(alg) if alg.eq_ignore_ascii_case("MD5-sess") => (Algorithm::Md5, true),
+            Some(alg) if alg.eq_ignore_ascii_case("SHA-256") => (Algorithm::Sha256, false),
+            Some(alg) if alg.eq_ignore_ascii_case("SHA-256-sess") => (Algorithm::Sha256, true),
+            Some(_) => return None,
+        };
+        // Without qop, only the legacy RFC 2069 computation is possible.
+        let qop_auth = match challenge.param("qop") {
+            Some(qop) => {
+                if !qop
+                    .split(',')
+                    .any(|q| q.trim().eq_ignore_ascii_case("auth"))
+                {
+                    return None;
+                }
+                true
+            }
+            None => false,
+        };
+        Some(DigestState {
+            realm: challenge.param("realm").unwrap_or_default().to_owned(),
+            nonce: challenge.param("nonce")?.to_owned(),
+            opaque: challenge.param("opaque").map(str::to_owned),
+            algorithm,
+            sess,
+            qop_auth,
+            nc: 0,
+        })
+    }
+
+    /// Compute the `Authorization` value for one request, using the next
+    /// nonce count.
+    pub(super) fn authorize(
+        &mut self,
+        username: &str,
+        password: &str,
+        method: &str,
+        uri: &str,
+    ) -> String {
+        self.nc += 1;
+        let nc = format!("{:08x}", self.nc);
+        let cnonce = format!("{:016x}", random_u64());
+
+        let mut ha1 = self.hash(&format!("{}:{}:{}", username, self.realm, password));
+        if self.sess {
+            ha1 = self.hash(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = self.hash(&format!("{}:{}", method, uri));
+        let response = if self.qop_auth {
+            self.hash(&format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, self.nonce, nc, cnonce, ha2
+            ))
+        } else {
+            self.hash(&format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+            quote(username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri),
+            self.algorithm_name(),
+            response,
+        );
+        if self.qop_auth {
+            header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+        }
+        if let Some(ref opaque) = self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        header
+    }
+
+    fn algorithm_name(&self) -> &'static str {
+        match (self.algorithm, self.sess) {
+            (Algorithm::Md5, false) => "MD5",
+            (Algorithm::Md5, true) => "MD5-sess",
+            (Algorithm::Sha256, false) => "SHA-256",
+            (Algorithm::Sha256, true) => "SHA-256-sess",
+        }
+    }
+
+    fn hash(&self, input: &str) -> String {
+        match self.algorithm {
+            Algorithm::Md5 => hex(&Md5::digest(input.as_bytes())),
+            Algorithm::Sha256 => hex(&Sha256::digest(input.as_bytes())),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::challenge;
+    use super::DigestState;
+
+    // The example from RFC 2617, section 3.5.
+    #[test]
+    fn rfc_2617_example() {
+        let challenges = challenge::parse(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        );
+        let mut state = DigestState::from_challenge(&challenges[0]).unwrap();
+
+        let header = state.authorize("Mufasa", "Circle Of Life", "GET", "/dir/index.html");
+        assert!(header.contains("nc=00000001"));
+        assert!(header.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+
+        // The cnonce is random, so recompute the expected response with it.
+        let cnonce = header
+            .split("cnonce=\"")
+            .nth(1)
+            .unwrap()
+            .split('"')
+            .next()
+            .unwrap();
+        let ha1 = state.hash("Mufasa:testrealm@host.com:Circle Of Life");
+        assert_eq!(ha1, "939e7578ed9e3c518a452acee763bce9");
+        let ha2 = state.hash("GET:/dir/index.html");
+        assert_eq!(ha2, "39aff3a2bab6126f332b942af96d3366");
+        let expected = state.hash(&format!(
+            "{}:dcd98b7102dd2f0e8b11d0f600bfb0c093:00000001:{}:auth:{}",
+            ha1, cnonce, ha2
+        ));
+        assert!(header.contains(&format!("response=\"{}\"", expected)));
+
+        let header = state.authorize("Mufasa", "Circle Of Life", "GET", "/dir/index.html");
+        assert!(header.contains("nc=00000002"));
+    }
+}
@@ -0,0 +1,500 @@
+//! HTTP authentication for the `Client`.
+//!
+//! An [`AuthLayer`] answers `401 Unauthorized` challenges by sending the
+//! request once more with credentials configured for its origin:
+//!
+//! - a username and password, used for the `Basic` and `Digest` schemes,
+//!   preferring `Digest`.
+//! - a [`TokenSource`] for `Bearer` tokens, which is asked to refresh the
+//!   token when the server rejects it.
+//!
+//! After a successful challenge, later requests to the same origin send
+//! credentials right away, reusing the `Digest` nonce with an increasing
+//! nonce count until the server sends a new one.
+//!
+//! Since the request may be sent twice, its body must be `Clone`.
+//!
+//! # Example
+//!
+//! ```
+//! # #[cfg(all(feature = "tokio", feature = "http1"))]
+//! # fn run() {
+//! use bytes::Bytes;
+//! use http_body_util::Empty;
+//! use hyper_util::client::legacy::auth::{AuthLayer, Credentials};
+//! use hyper_util::client::legacy::Client;
+//! use hyper_util::rt::TokioExecutor;
+//! use tower::Layer;
+//!
+//! let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+//! let client = AuthLayer::new()
+//!     .credentials("http://intranet.local", Credentials::password("user", "hunter2"))
+//!     .layer(client);
+//! # drop(client);
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{self, Poll};
+
+use http::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use http::{Request, Response, StatusCode, Uri};
+use tower::ServiceExt;
+
+use self::challenge::Challenge;
+use self::digest::DigestState;
+
+mod challenge;
+mod digest;
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+// The method and URI of a request, which `Digest` credentials cover.
+type Target = (http::Method, Uri);
+
+/// Provides `Bearer` tokens to an [`AuthLayer`].
+pub trait TokenSource: Send + Sync + 'static {
+    /// The current token, if one is available.
+    fn token(&self) -> Option<String>;
+
+    /// Get a new token, after the server rejected the current one.
+    ///
+    /// Returning `None` gives up, and the `401` response is returned.
+    fn refresh(&self) -> BoxFuture<'_, Option<String>>;
+}
+
+/// The credentials to use for an origin.
+#[derive(Clone)]
+pub struct Credentials(Method);
+
+#[derive(Clone)]
+enum Method {
+    Password { username: String, password: String },
+    Bearer(Arc<dyn TokenSource>),
+}
+
+/// A `Layer` answering authentication challenges.
+#[derive(Clone, Default)]
+pub struct AuthLayer {
+    shared: Arc<Shared>,
+}
+
+/// A `Service` created by an [`AuthLayer`].
+pub struct AuthService<C> {
+    inner: C,
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    credentials: HashMap<String, Credentials>,
+    // What worked last time for an origin, to send credentials right away.
+    cached: Mutex<HashMap<String, Cached>>,
+}
+
+enum Cached {
+    Basic,
+    Digest(DigestState),
+}
+
+// ===== impl Credentials =====
+
+impl Credentials {
+    /// Use a username and password, for `Basic` or `Digest` challenges.
+    pub fn password(username: impl Into<String>, password: impl Into<String>) -> Credentials {
+        Credentials(Method::Password {
+            username: username.into(),
+            password: password.into(),
+        })
+    }
+
+    /// Use tokens from `source`, for `Bearer` challenges.
+    pub fn bearer<T: TokenSource>(source: T) -> Credentials {
+        Credentials(Method::Bearer(Arc::new(source)))
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never print secrets
+        match self.0 {
+            Method::Password { ref username, .. } => f
+                .debug_struct("Password")
+                .field("username", username)
+                .finish(),
+            Method::Bearer(_) => f.debug_struct("Bearer").finish(),
+        }
+    }
+}
+
+// ===== impl AuthLayer =====
+
+impl AuthLayer {
+    /// Create a layer without any credentials.
+    pub fn new() -> AuthLayer {
+        AuthLayer::default()
+    }
+
+    /// Use `credentials` for requests to `origin`, such as
+    /// `https://example.com` or `http://localhost:8080`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `origin` doesn't have a scheme and an authority, or if
+    /// called after the layer has been applied to a service.
+    pub fn credentials(mut self, origin: &str, credentials: Credentials) -> AuthLayer {
+        let uri = origin.parse::<Uri>().expect("origin is a valid Uri");
+        let origin = origin_of(&uri).expect("origin has a scheme and authority");
+        Arc::get_mut(&mut self.shared)
+            .expect("credentials added before layering")
+            .credentials
+            .insert(origin, credentials);
+        self
+    }
+}
+
+impl<C> tower::Layer<C> for AuthLayer {
+    type Service = AuthService<C>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        AuthService {
+            inner,
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for AuthLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthLayer")
+            .field("origins", &self.shared.credentials.keys())
+            .finish()
+    }
+}
+
+// ===== impl AuthService =====
+
+impl<C, B, R> tower_service::Service<Request<B>> for AuthService<C>
+where
+    C: tower_service::Service<Request<B>, Response = Response<R>> + Clone + Send + 'static,
+    C::Future: Send,
+    C::Error: Send,
+    B: Clone + Send + 'static,
+    R: Send + 'static,
+{
+    type Response = Response<R>;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<Response<R>, C::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        // Take the service that was polled ready, leaving a clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let shared = self.shared.clone();
+
+        let found = origin_of(req.uri()).and_then(|origin| {
+            let credentials = shared.credentials.get(&origin)?.clone();
+            Some((origin, credentials))
+        });
+        let (origin, credentials) = match found {
+            Some(found) if !req.headers().contains_key(AUTHORIZATION) => found,
+            _ => return Box::pin(inner.call(req)),
+        };
+
+        let target = (req.method().clone(), req.uri().clone());
+        if let Some(value) = shared.preemptive(&origin, &credentials, &target) {
+            req.headers_mut().insert(AUTHORIZATION, value);
+        }
+        let mut retry = clone_request(&req);
+
+        Box::pin(async move {
+            let res = inner.call(req).await?;
+            if res.status() != StatusCode::UNAUTHORIZED {
+                return Ok(res);
+            }
+
+            let challenges = res
+                .headers()
+                .get_all(WWW_AUTHENTICATE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(challenge::parse)
+                .collect::<Vec<_>>();
+            let target = (retry.method().clone(), retry.uri().clone());
+            let value = match shared
+                .respond(&origin, &credentials, &challenges, &target)
+                .await
+            {
+                Some(value) => value,
+                None => return Ok(res),
+            };
+
+            retry.headers_mut().insert(AUTHORIZATION, value);
+            inner.ready().await?.call(retry).await
+        })
+    }
+}
+
+impl<C: Clone> Clone for AuthService<C> {
+    fn clone(&self) -> AuthService<C> {
+        AuthService {
+            inner: self.inner.clone(),
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for AuthService<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthService")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    fn preemptive(
+        &self,
+        origin: &str,
+        credentials: &Credentials,
+        target: &Target,
+    ) -> Option<HeaderValue> {
+        match credentials.0 {
+            Method::Bearer(ref source) => bearer(source.token()?),
+            Method::Password {
+                ref username,
+                ref password,
+            } => match self.cached.lock().unwrap().get_mut(origin)? {
+                Cached::Basic => basic(username, password),
+                Cached::Digest(ref mut state) => digest(state, username, password, target),
+            },
+        }
+    }
+
+    async fn respond(
+        &self,
+        origin: &str,
+        credentials: &Credentials,
+        challenges: &[Challenge],
+        target: &Target,
+    ) -> Option<HeaderValue> {
+        match credentials.0 {
+            Method::Bearer(ref source) => {
+                if !challenges.is_empty() && !challenges.iter().any(|c| c.is("Bearer")) {
+                    return None;
+                }
+                bearer(source.refresh().await?)
+            }
+            Method::Password {
+                ref username,
+                ref password,
+            } => {
+                let mut cached = self.cached.lock().unwrap();
+                if let Some(mut state) = challenges
+                    .iter()
+                    .filter(|c| c.is("Digest"))
+                    .find_map(DigestState::from_challenge)
+                {
+                    let value = digest(&mut state, username, password, target);
+                    cached.insert(origin.to_owned(), Cached::Digest(state));
+                    value
+                } else if challenges.iter().any(|c| c.is("Basic")) {
+                    cached.insert(origin.to_owned(), Cached::Basic);
+                    basic(username, password)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+fn origin_of(uri: &Uri) -> Option<String> {
+    Some(format!(
+        "{}://{}",
+        uri.scheme_str()?,
+        uri.authority()?.as_str().to_ascii_lowercase()
+    ))
+}
+
+fn clone_request<B: Clone>(req: &Request<B>) -> Request<B> {
+    let mut clone = Request::new(req.body().clone());
+    *clone.method_mut() = req.method().clone();
+    *clone.uri_mut() = req.uri().clone();
+    *clone.version_mut() = req.version();
+    *clone.headers_mut() = req.headers().clone();
+    *clone.extensions_mut() = req.extensions().clone();
+    clone
+}
+
+fn bearer(token: String) -> Option<HeaderValue> {
+    let mut value = HeaderValue::from_str(&format!("Bearer {}", token)).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+fn basic(username: &str, password: &str) -> Option<HeaderValue> {
+    let encoded = crate::common::base64::encode(format!("{}:{}", username, password).as_bytes());
+    let mut value = HeaderValue::from_str(&format!("Basic {}", encoded)).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+fn digest(
+    state: &mut DigestState,
+    username: &str,
+    password: &str,
+    (method, uri): &Target,
+) -> Option<HeaderValue> {
+    let uri = uri.path_and_query().map_or("/", |path| path.as_str());
+    let value = state.authorize(username, password, method.as_str(), uri);
+    let mut value = HeaderValue::from_str(&value).ok()?;
+    value.set_sensitive(true);
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+    use http::{Request, Response, StatusCode};
+    use tower::{Layer, ServiceExt};
+
+    use super::{AuthLayer, Credentials, TokenSource};
+
+    // A server accepting authorization passing `accept`, challenging with
+    // `challenge` otherwise.
+    fn server(
+        challenge: &'static str,
+        accept: fn(&str) -> bool,
+        calls: Arc<AtomicUsize>,
+    ) -> impl tower::Service<
+        Request<()>,
+        Response = Response<()>,
+        Error = Infallible,
+        Future = impl Send,
+    > + Clone
+           + Send
+           + 'static {
+        tower::service_fn(move |req: Request<()>| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let authorized = req
+                .headers()
+                .get(AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .map_or(false, accept);
+            let res = if authorized {
+                Response::new(())
+            } else {
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(WWW_AUTHENTICATE, challenge)
+                    .body(())
+                    .unwrap()
+            };
+            async move { Ok::<_, Infallible>(res) }
+        })
+    }
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn basic_retries_then_sends_preemptively() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let svc = AuthLayer::new()
+            .credentials(
+                "http://example.local",
+                Credentials::password("Aladdin", "open sesame"),
+            )
+            .layer(server(
+                r#"Basic realm="test""#,
+                |auth| auth == "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==",
+                calls.clone(),
+            ));
+
+        let req = || Request::get("http://example.local/a").body(()).unwrap();
+        let res = svc.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let res = svc.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            calls.load(Ordering::SeqCst),
+            3,
+            "sent credentials right away"
+        );
+
+        // other origins don't get credentials
+        let other = Request::get("http://other.local/").body(()).unwrap();
+        let res = svc.oneshot(other).await.unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn digest_prefers_digest_and_counts_nonces() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let svc = AuthLayer::new()
+            .credentials("http://example.local", Credentials::password("u", "p"))
+            .layer(server(
+                r#"Basic realm="r", Digest realm="r", qop="auth", nonce="n""#,
+                |auth| auth.starts_with("Digest ") && auth.contains("uri=\"/a?b\""),
+                calls.clone(),
+            ));
+
+        let req = || Request::get("http://example.local/a?b").body(()).unwrap();
+        let res = svc.clone().oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = svc.oneshot(req()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    struct Tokens(AtomicUsize);
+
+    impl TokenSource for Tokens {
+        fn token(&self) -> Option<String> {
+            Some(format!("t{}", self.0.load(Ordering::SeqCst)))
+        }
+
+        fn refresh(&self) -> super::BoxFuture<'_, Option<String>> {
+            let n = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Box::pin(async move { Some(format!("t{}", n)) })
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn bearer_refreshes_once() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let svc = AuthLayer::new()
+            .credentials(
+                "https://api.local",
+                Credentials::bearer(Tokens(AtomicUsize::new(0))),
+            )
+            .layer(server(
+                "Bearer error=\"invalid_token\"",
+                |auth| auth == "Bearer t1",
+                calls.clone(),
+            ));
+
+        let req = Request::get("https://api.local/").body(()).unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+}
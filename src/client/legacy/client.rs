@@ -4,25 +4,41 @@
 //! For now, to enable people to use hyper 1.0 quicker, this `Client` exists
 //! in much the same way it did in hyper 0.14.
 
+use std::collections::HashMap;
 use std::error::Error as StdError;
 use std::fmt;
 use std::future::Future;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::task::{self, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures_util::future::{self, Either, FutureExt, TryFutureExt};
 use http::uri::Scheme;
-use hyper::header::{HeaderValue, HOST};
+use hyper::header::{HeaderMap, HeaderValue, HOST, RETRY_AFTER, USER_AGENT};
 use hyper::rt::Timer;
-use hyper::{body::Body, Method, Request, Response, Uri, Version};
-use tracing::{debug, trace, warn};
+use hyper::{body::Body, Method, Request, Response, StatusCode, Uri, Version};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::{debug, trace, warn, Instrument};
 
 use super::connect::capture::CaptureConnectionExtension;
 #[cfg(feature = "tokio")]
 use super::connect::HttpConnector;
-use super::connect::{Alpn, Connect, Connected, Connection};
-use super::pool::{self, Ver};
+use super::connect::{
+    resolved, selecting, Alpn, Connect, Connected, Connection, ResolvedAddrs, SelectConnector,
+    TcpProfile,
+};
+use super::hints::{HintStore, Hints};
+use super::host::{self, HostNormalization};
+use super::memory;
+use super::metrics;
+use super::pool::{self, PoolStatsReporter, Ver};
+use super::queue::{self, Queue, QueueOverflow};
+use super::reresolve::{Answers, Tracked};
+use super::spans;
+use super::timings::{self, ConnectTimings, RequestTimings, Timings};
 
 use crate::common::{lazy as hyper_lazy, timer, Exec, Lazy, SyncWrapper};
 
@@ -32,6 +48,22 @@ type BoxSendFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
 ///
 /// `Client` is cheap to clone and cloning is the recommended way to share a `Client`. The
 /// underlying connection pool will be reused.
+///
+/// # Metrics
+///
+/// With the `metrics` feature, the client records these with the
+/// [`metrics`](https://docs.rs/metrics) facade:
+///
+/// - `hyper_util_client_connections_opened_total` and
+///   `hyper_util_client_connections_closed_total`, counters labeled with the
+///   `protocol`, `http1` or `http2`,
+/// - `hyper_util_client_pool_checkouts_total`, a counter of the connections
+///   requests got, labeled with a `result` of `hit` if it was reused from
+///   the pool, or `miss` if it was dialed,
+/// - `hyper_util_client_request_duration_seconds`, a histogram of the time
+///   until the response head, labeled with the `status_class`, such as
+///   `2xx`, or `error` if the request failed, and the `partition` of the
+///   requests of a [`Client::partition`].
 #[cfg_attr(docsrs, doc(cfg(any(feature = "http1", feature = "http2"))))]
 pub struct Client<C, B> {
     config: Config,
@@ -42,12 +74,111 @@ pub struct Client<C, B> {
     #[cfg(feature = "http2")]
     h2_builder: hyper::client::conn::http2::Builder<Exec>,
     pool: pool::Pool<PoolClient<B>, PoolKey>,
+    pool_tagger: Option<PoolTagger>,
+    request_signer: Option<RequestSigner>,
+    default_headers: Option<Arc<HeaderMap>>,
+    memory: Option<Arc<memory::Budget>>,
+    in_use: Option<Arc<Semaphore>>,
+    queue: Option<Arc<Queue>>,
+    serial: Option<SerialHosts>,
+    backoff: Option<BackoffHosts>,
+    hints: Option<Arc<Hints>>,
+    dns: Option<Arc<Answers<PoolKey>>>,
+    pool_stats: Option<Arc<PoolStats>>,
+    timer: Option<timer::Timer>,
+    partitions: Arc<Partitions>,
+    partition: Option<Arc<Partition>>,
+}
+
+// The partitions of a `Client`, created by `Client::partition` as they are
+// first used.
+#[derive(Default)]
+struct Partitions {
+    max_in_use: Option<usize>,
+    by_name: Mutex<HashMap<Arc<str>, Arc<Partition>>>,
+}
+
+// A partition of a `Client`, isolated in the pool by its name.
+struct Partition {
+    name: Arc<str>,
+    in_use: Option<Arc<Semaphore>>,
+}
+
+// The reporting of `Builder::pool_stats_reporter`, started by the first
+// request.
+struct PoolStats {
+    interval: Duration,
+    reporter: Arc<dyn PoolStatsReporter>,
+    started: AtomicBool,
+}
+
+// The request in progress on each host, with `Builder::http1_serial`.
+type SerialHosts = Arc<Mutex<HashMap<PoolKey, Arc<Semaphore>>>>;
+
+// A request's turn on its host with `Builder::http1_serial`, removing the
+// host from `SerialHosts` when no other request holds or waits for it.
+struct SerialTurn {
+    permit: Option<OwnedSemaphorePermit>,
+    host: Arc<Semaphore>,
+    hosts: SerialHosts,
+    key: PoolKey,
+}
+
+// Until when each host asked to be left alone with a `429`, with
+// `Builder::pool_retry_after_backoff`.
+type BackoffHosts = Arc<Mutex<HashMap<PoolKey, Instant>>>;
+
+// Sends a request on a new connection, rather than one from the pool, such
+// as retrying after a reused connection turned out to be stale.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FreshConnection;
+
+/// A `Client` as a `tower::Service`, which is only ready once the client
+/// can use another connection.
+///
+/// With [`Builder::pool_max_in_use`] set, `poll_ready` waits until a
+/// connection is free to take the request, so middleware like
+/// `tower::buffer::Buffer` or a load balancer see the client's backpressure.
+/// Without it, this is always ready, like `Client` itself.
+///
+/// Created with [`Client::into_service`].
+pub struct ClientService<C, B> {
+    client: Client<C, B>,
+    permit: Option<OwnedSemaphorePermit>,
+    acquire: Option<SyncWrapper<Pin<Box<dyn Future<Output = OwnedSemaphorePermit> + Send>>>>,
+}
+
+/// A `Client` sending requests with a relative URI to a base URI.
+///
+/// A request whose URI has no scheme and authority, such as `/users?page=2`,
+/// goes to the scheme and authority of the base, with its path appended to
+/// the path of the base: with a base of `https://api.example.com/v2`, it is
+/// sent to `https://api.example.com/v2/users?page=2`. A request with an
+/// absolute URI is sent unchanged, but only if it has the scheme and
+/// authority of the base, failing with [`Error::is_base_uri_mismatch`]
+/// otherwise. So all the requests share the connections of the base in
+/// the pool.
+///
+/// `CONNECT` requests, whose URI is an authority, are sent unchanged.
+///
+/// Created with [`Client::with_base_uri`].
+pub struct BaseUriClient<C, B> {
+    client: Client<C, B>,
+    base: Uri,
 }
 
 #[derive(Clone, Copy, Debug)]
 struct Config {
     retry_canceled_requests: bool,
     set_host: bool,
+    host_normalization: HostNormalization,
+    tcp_profile: Option<TcpProfile>,
+    connect_race_delay: Option<Duration>,
+    background_connection_quota: Option<usize>,
+    http1_serial: bool,
+    retry_after_backoff: Option<Duration>,
+    max_response_header_size: Option<usize>,
+    max_response_headers: Option<usize>,
     ver: Ver,
 }
 
@@ -55,6 +186,7 @@ struct Config {
 pub struct Error {
     kind: ErrorKind,
     source: Option<Box<dyn StdError + Send + Sync>>,
+    remote_addr: Option<SocketAddr>,
 }
 
 #[derive(Debug)]
@@ -65,6 +197,14 @@ enum ErrorKind {
     UserUnsupportedRequestMethod,
     UserUnsupportedVersion,
     UserAbsoluteUriRequired,
+    UserBaseUriMismatch,
+    UserInvalidHost,
+    UserUnacceptableProtocol,
+    RequestSigner,
+    PoolQueueFull,
+    RequestQueueFull,
+    ResponseHeadersTooLarge,
+    EarlyDataRejected,
     SendRequest,
 }
 
@@ -73,18 +213,63 @@ macro_rules! e {
         Error {
             kind: ErrorKind::$kind,
             source: None,
+            remote_addr: None,
         }
     };
     ($kind:ident, $src:expr) => {
         Error {
             kind: ErrorKind::$kind,
             source: Some($src.into()),
+            remote_addr: None,
         }
     };
 }
 
 // We might change this... :shrug:
-type PoolKey = (http::uri::Scheme, http::uri::Authority);
+type PoolKey = (
+    http::uri::Scheme,
+    http::uri::Authority,
+    Option<PoolTag>,
+    // the partition
+    Option<Arc<str>>,
+    // the connector
+    Option<SelectConnector>,
+);
+
+type PoolTagger = Arc<dyn Fn(&Uri, &http::HeaderMap) -> Option<PoolTag> + Send + Sync>;
+
+// Room for the status line of an HTTP/1 response, on top of the size of its
+// headers, in the read buffer.
+#[cfg(feature = "http1")]
+const H1_STATUS_LINE_ROOM: usize = 1024;
+
+type ResponseHeadCallback = Arc<dyn Fn(&Response<hyper::body::Incoming>) + Send + Sync>;
+
+type RequestSigner = Arc<
+    dyn Fn(&mut http::request::Parts) -> Result<(), Box<dyn StdError + Send + Sync>> + Send + Sync,
+>;
+
+/// A tag attached to pooled connections.
+///
+/// Adding a `PoolTag` to the extensions of a request requires that it is
+/// sent on a connection with the same tag. If there is no such idle
+/// connection, a new one is dialed and given the tag, so that later requests
+/// with the tag stick to it. Requests without a tag never use tagged
+/// connections.
+///
+/// This is useful for backends that authenticate a connection rather than
+/// each request.
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::PoolTag;
+///
+/// let mut req = http::Request::new(());
+/// req.extensions_mut().insert(PoolTag::new("session=abc"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PoolTag(Arc<str>);
 
 /// A `Future` that will resolve to an HTTP Response.
 ///
@@ -96,6 +281,171 @@ pub struct ResponseFuture {
     >,
 }
 
+/// Restricts which protocol a request may be sent over.
+///
+/// Adding this to the extensions of a request makes the `Client` return an
+/// error rather than send the request over a different protocol, such as
+/// falling back to HTTP/1.1 when a server doesn't negotiate HTTP/2 with
+/// ALPN.
+///
+/// The protocol a connection used can be inspected with
+/// [`capture_connection`](super::connect::capture_connection) and
+/// [`Connected::alpn_protocol`].
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::RequireProtocol;
+///
+/// let mut req = http::Request::new(());
+/// req.extensions_mut().insert(RequireProtocol::Http2);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RequireProtocol {
+    /// Only send the request over HTTP/1.
+    Http1,
+    /// Only send the request over HTTP/2.
+    Http2,
+}
+
+/// Whether a request may be sent as TLS 1.3 early data (0-RTT).
+///
+/// Early data saves a round trip on resumed TLS connections, but an
+/// attacker can replay it, so only requests that are safe to replay should
+/// be sent that way. By default, as given by [`EarlyData::of`], those are
+/// the `GET`, `HEAD` and `OPTIONS` requests without a body. Adding this to
+/// the extensions of a request overrides it.
+///
+/// This only matters over connections whose connector reports early data,
+/// with [`Connected::early_data`]. Requests that may not be sent early wait
+/// for the TLS handshake to be done, and fail if the connection closes
+/// first.
+///
+/// The client doesn't send a request again by itself when the server
+/// rejects the early data it was sent in: the request fails with an error
+/// that is [`Error::is_early_data_rejected`], which a
+/// [`RetryLayer`](super::retry::RetryLayer) retries, with the
+/// `client-retry` feature.
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::EarlyData;
+///
+/// let mut req = http::Request::get("https://example.local/").body(()).unwrap();
+/// req.extensions_mut().insert(EarlyData::Deny);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EarlyData {
+    /// The request may be sent as early data.
+    Allow,
+    /// The request waits for the handshake to be done.
+    Deny,
+}
+
+/// The priority of a request waiting for a pooled connection.
+///
+/// Adding this to the extensions of a request sets how it competes for
+/// connections to its host. When a connection becomes idle, it goes to the
+/// requests waiting with `Interactive` priority first, in the order they
+/// started waiting, and only then to `Background` ones. Requests without
+/// this extension are `Interactive`.
+///
+/// `Background` requests can also be kept from dialing new connections
+/// with [`Builder::pool_background_connection_quota`].
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::Priority;
+///
+/// let mut req = http::Request::new(());
+/// req.extensions_mut().insert(Priority::Background);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Priority {
+    /// Served first, such as requests a user waits on.
+    #[default]
+    Interactive,
+    /// Served once no interactive request is waiting, such as prefetching
+    /// or syncing.
+    Background,
+}
+
+/// A connection checked out of the pool of a [`Client`].
+///
+/// This gives direct access to the connection the client would send a
+/// request to a destination on, to send several requests on it, or to
+/// drive the `SendRequest` handle of hyper directly, such as for a
+/// protocol where the server talks first after an upgrade. HTTP/1
+/// connections are used by this alone, while HTTP/2 connections keep
+/// taking requests of the client too.
+///
+/// Once dropped, the connection goes back to the pool, after the last
+/// HTTP/1 response body was read.
+///
+/// Created with [`Client::checkout`].
+pub struct PooledConnection<B>
+where
+    B: Send + 'static,
+{
+    pooled: Option<pool::Pooled<PoolClient<B>, PoolKey>>,
+    exec: Exec,
+}
+
+/// What the caller expects of the response to a request.
+///
+/// Adding this to the extensions of a request hands it to the response, in
+/// its extensions, so the code reading the body, maybe away from the code
+/// sending the request, can prepare for it, such as preallocating a buffer
+/// with [`CollectWithLimit::expected_size`].
+///
+/// HTTP/2 flow control can't be sized per request, since hyper sets the
+/// window of every stream of a connection alike. For large downloads, see
+/// [`Builder::http2_initial_stream_window_size`] and
+/// [`Builder::http2_adaptive_window`] instead.
+///
+/// [`CollectWithLimit::expected_size`]: crate::body::CollectWithLimit::expected_size
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::Expectation;
+///
+/// let mut req = http::Request::new(());
+/// req.extensions_mut()
+///     .insert(Expectation::new().with_response_size(64 * 1024 * 1024));
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Expectation {
+    response_size: Option<u64>,
+}
+
+/// A callback run as soon as the response head of a request arrives.
+///
+/// Adding this to the extensions of a request calls it with the response,
+/// before its body is read, and before the response goes back through any
+/// layers wrapping the client. So latency metrics can tell the time to the
+/// first byte from the total time, even when a middleware buffers the body.
+/// The response already has its [`Timings`], with the `response_start`.
+///
+/// It isn't called if the request fails before a response arrives.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Instant;
+/// use hyper_util::client::legacy::OnResponseHead;
+///
+/// let start = Instant::now();
+/// let mut req = http::Request::new(());
+/// req.extensions_mut().insert(OnResponseHead::new(move |res| {
+///     println!("{} after {:?}", res.status(), start.elapsed());
+/// }));
+/// ```
+#[derive(Clone)]
+pub struct OnResponseHead(ResponseHeadCallback);
+
 // ===== impl Client =====
 
 impl Client<(), ()> {
@@ -198,7 +548,23 @@ where
     /// # }
     /// # fn main() {}
     /// ```
-    pub fn request(&self, mut req: Request<B>) -> ResponseFuture {
+    ///
+    /// # Informational responses
+    ///
+    /// A callback registered on the request with
+    /// [`hyper::ext::on_informational`] is called with each `1xx` response
+    /// received before the final one over HTTP/1, such as `103 Early Hints`
+    /// to start fetching linked resources early. They aren't reported over
+    /// HTTP/2.
+    pub fn request(&self, req: Request<B>) -> ResponseFuture {
+        self.request_with(req, None)
+    }
+
+    fn request_with(
+        &self,
+        mut req: Request<B>,
+        permit: Option<OwnedSemaphorePermit>,
+    ) -> ResponseFuture {
         let is_http_connect = req.method() == Method::CONNECT;
         match req.version() {
             Version::HTTP_11 => (),
@@ -213,14 +579,305 @@ where
             other => return ResponseFuture::error_version(other),
         };
 
-        let pool_key = match extract_domain(req.uri_mut(), is_http_connect) {
+        if let Some(ref defaults) = self.default_headers {
+            let headers = req.headers_mut();
+            for name in defaults.keys() {
+                if !headers.contains_key(name) {
+                    for value in defaults.get_all(name) {
+                        headers.append(name.clone(), value.clone());
+                    }
+                }
+            }
+        }
+
+        if let Err(err) = host::normalize(req.uri_mut(), self.config.host_normalization) {
+            debug!("invalid host in {:?}: {}", req.uri(), err);
+            return ResponseFuture::new(future::err(e!(UserInvalidHost, err)));
+        }
+
+        let tag = req.extensions().get::<PoolTag>().cloned().or_else(|| {
+            self.pool_tagger
+                .as_ref()
+                .and_then(|tagger| tagger(req.uri(), req.headers()))
+        });
+
+        let partition = self.partition.as_ref().map(|p| p.name.clone());
+        let select = req.extensions().get::<SelectConnector>().cloned();
+        let pool_key = match extract_domain(req.uri_mut(), is_http_connect, tag, partition, select)
+        {
             Ok(s) => s,
             Err(err) => {
                 return ResponseFuture::new(future::err(err));
             }
         };
 
-        ResponseFuture::new(self.clone().send_request(req, pool_key))
+        self.start_pool_stats();
+
+        let span = spans::send_request(req.method(), req.uri(), &pool_key);
+        let start = Instant::now();
+        let finish = span.clone();
+        let partition = pool_key.3.clone();
+        let fut = self
+            .clone()
+            .send_request(req, pool_key, permit)
+            .inspect(move |res| {
+                let res = res
+                    .as_ref()
+                    .map(|res| (res.status(), res.version()))
+                    .map_err(|err| &err.kind as &dyn fmt::Debug);
+                metrics::request_finished(
+                    start,
+                    res.as_ref().ok().map(|&(status, _)| status),
+                    partition.as_deref(),
+                );
+                spans::finish_request(&finish, start, res);
+            });
+        ResponseFuture::new(fut.instrument(span))
+    }
+
+    /// Convert this into a `tower::Service` that reports backpressure.
+    ///
+    /// See [`ClientService`].
+    pub fn into_service(self) -> ClientService<C, B> {
+        ClientService {
+            client: self,
+            permit: None,
+            acquire: None,
+        }
+    }
+
+    /// Send requests with a relative URI to `base`.
+    ///
+    /// See [`BaseUriClient`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `base` has no scheme or authority.
+    pub fn with_base_uri(self, base: Uri) -> BaseUriClient<C, B> {
+        assert!(
+            base.scheme().is_some() && base.authority().is_some(),
+            "base URI must be absolute: {:?}",
+            base
+        );
+        BaseUriClient { client: self, base }
+    }
+
+    /// Check out a connection to the destination of `uri`, dialing one if
+    /// none is idle in the pool.
+    ///
+    /// The connection is picked like for a request to `uri` through this
+    /// client, in its partition, if any, but with no [`PoolTag`]. Limits on
+    /// the requests of the client, like [`Builder::pool_max_in_use`],
+    /// don't apply.
+    ///
+    /// See [`PooledConnection`].
+    pub fn checkout(
+        &self,
+        uri: Uri,
+    ) -> impl Future<Output = Result<PooledConnection<B>, Error>> + Send + 'static {
+        let client = self.clone();
+        async move {
+            let mut uri = uri;
+            host::normalize(&mut uri, client.config.host_normalization)
+                .map_err(|err| e!(UserInvalidHost, err))?;
+            let partition = client.partition.as_ref().map(|p| p.name.clone());
+            let pool_key = extract_domain(&mut uri, false, None, partition, None)?;
+            client.start_pool_stats();
+            let pooled = client
+                .connection_for(pool_key, None, Priority::default(), false)
+                .await?;
+            Ok(PooledConnection {
+                pooled: Some(pooled),
+                exec: client.exec.clone(),
+            })
+        }
+    }
+
+    /// Get a client for the partition `name`, such as the ID of a tenant.
+    ///
+    /// The partition shares the connector, configuration and limits of this
+    /// client, but its requests only use connections of the partition, in
+    /// buckets of the pool of their own, so the traffic of one partition
+    /// can't reuse, or wait behind, the connections of another.
+    /// [`Builder::pool_max_in_use_per_partition`] additionally caps the
+    /// connections each partition uses at once.
+    ///
+    /// Clients for the same name share the partition. The pool buckets of a
+    /// partition show as `<name>` in [`Client::pool_dump`], and with the
+    /// `metrics` feature, the request duration of a partition is labeled
+    /// with its `partition`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "tokio")]
+    /// # fn run () {
+    /// use hyper_util::client::legacy::Client;
+    /// use hyper_util::rt::TokioExecutor;
+    ///
+    /// let client = Client::builder(TokioExecutor::new())
+    ///     .pool_max_in_use_per_partition(8)
+    ///     .build_http::<http_body_util::Empty<bytes::Bytes>>();
+    /// let tenant = client.partition("tenant-a");
+    /// # drop(tenant);
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn partition(&self, name: &str) -> Client<C, B>
+    where
+        C: Clone,
+    {
+        let partition =
+            self.partitions
+                .by_name
+                .lock()
+                .unwrap()
+                .entry(name.into())
+                .or_insert_with_key(|name| {
+                    Arc::new(Partition {
+                        name: name.clone(),
+                        in_use: self.partitions.max_in_use.map(|max| {
+                            Arc::new(Semaphore::new(max.clamp(1, Semaphore::MAX_PERMITS)))
+                        }),
+                    })
+                })
+                .clone();
+        Client {
+            partition: Some(partition),
+            ..self.clone()
+        }
+    }
+
+    /// Signal that the network changed, such as after switching from Wi-Fi
+    /// to a cellular connection.
+    ///
+    /// All idle pooled connections are closed, and connections that are in
+    /// use or still being established are closed once done, instead of
+    /// being returned to the pool. The next requests dial new connections,
+    /// resolving their hosts again.
+    ///
+    /// Requests in flight are not interrupted. Connector state, like a
+    /// [`NegativeCache`](super::connect::NegativeCache) set on the
+    /// `HttpConnector`, is not reset.
+    pub fn notify_network_changed(&self) {
+        self.pool.clear();
+    }
+
+    /// Returns how many requests to the origin of `uri` are waiting for a
+    /// connection.
+    ///
+    /// These are requests that found no idle pooled connection, and wait
+    /// for one to be returned or dialed, including those with a
+    /// [`PoolTag`]. A layer above the client can use this to shed load
+    /// before the queue grows.
+    ///
+    /// Requests waiting on [`Builder::pool_max_in_use`] don't count, since
+    /// they aren't waiting for a particular origin yet. Returns `0` if `uri`
+    /// has no scheme and authority.
+    pub fn pressure(&self, uri: &Uri) -> usize {
+        let mut uri = uri.clone();
+        if host::normalize(&mut uri, self.config.host_normalization).is_err() {
+            return 0;
+        }
+        match (uri.scheme(), uri.authority()) {
+            (Some(scheme), Some(auth)) => self
+                .pool
+                .waiters(|(s, a, _, _, _)| s == scheme && a == auth),
+            _ => 0,
+        }
+    }
+
+    /// Returns a future that resolves once no connection of this client is
+    /// in use or being established.
+    ///
+    /// A connection is in use until the response body has been read and the
+    /// connection is back in the pool. Connections still being dialed in the
+    /// background, to be pooled once ready, count as well. Waiting on this
+    /// before exiting makes sure those aren't cut off.
+    ///
+    /// The future resolves at once if nothing is in use. New requests made
+    /// while waiting delay it.
+    pub fn idle(&self) -> impl Future<Output = ()> + Send + 'static {
+        self.pool.idle()
+    }
+
+    /// Take a snapshot of the connection pool, for diagnosing why requests
+    /// do or don't reuse connections.
+    ///
+    /// Pools are keyed by scheme and authority, followed by the
+    /// [`PoolTag`] of tagged connections, the `<name>` of the
+    /// [`Client::partition`] of partitioned ones, and `via <name>` for the
+    /// [`SelectConnector`] of the requests that selected a connector.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "tokio")]
+    /// # fn run () {
+    /// use hyper_util::client::legacy::Client;
+    /// use hyper_util::rt::TokioExecutor;
+    ///
+    /// let client = Client::builder(TokioExecutor::new()).build_http::<http_body_util::Empty<bytes::Bytes>>();
+    /// println!("{}", client.pool_dump());
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn pool_dump(&self) -> pool::PoolDump {
+        self.pool.dump(pool_key_name)
+    }
+
+    /// Save the endpoints this client knows to the
+    /// [`Builder::hint_store`], such as before the process exits.
+    ///
+    /// These are the hints loaded when the client was built, updated with
+    /// the endpoints it connected to since. This does nothing without a
+    /// store.
+    pub fn save_hints(&self) {
+        if let Some(ref hints) = self.hints {
+            hints.save();
+        }
+    }
+
+    /// Dial a connection to each endpoint this client knows, in the
+    /// background, so the first requests to them find one in the pool.
+    ///
+    /// The endpoints are those of the [`Builder::hint_store`]. Connections
+    /// that fail are ignored, and [`Client::idle`] resolves once all are
+    /// done. This does nothing without a store.
+    pub fn preconnect(&self) {
+        let hints = match self.hints {
+            Some(ref hints) => hints.endpoints(),
+            None => return,
+        };
+        self.start_pool_stats();
+        for hint in hints {
+            let (scheme, authority) = hint.into_origin();
+            // counted from now, not from when the executor polls it
+            let active = self.pool.active();
+            let dial = self
+                .connect_to((scheme, authority, None, None, None), None)
+                .map_err(|err| trace!("preconnect error: {}", err))
+                .map(move |_pooled| {
+                    // dropping here places it in the pool
+                    drop(active);
+                });
+            self.exec.execute(dial);
+        }
+    }
+
+    fn start_pool_stats(&self) {
+        let (stats, timer) = match (&self.pool_stats, &self.timer) {
+            (Some(stats), Some(timer)) => (stats, timer),
+            _ => return,
+        };
+        if !stats.started.swap(true, Ordering::Relaxed) {
+            self.exec.execute(report_pool_stats(
+                self.pool.downgrade(),
+                timer.clone(),
+                stats.interval,
+                stats.reporter.clone(),
+            ));
+        }
     }
 
     /*
@@ -262,13 +919,123 @@ where
         self,
         mut req: Request<B>,
         pool_key: PoolKey,
+        permit: Option<OwnedSemaphorePermit>,
     ) -> Result<Response<hyper::body::Incoming>, Error> {
-        let mut pooled = self.connection_for(pool_key).await?;
+        let start = Instant::now();
+        let queued = match self.queue {
+            Some(ref queue) => {
+                let bytes = req.body().size_hint().lower();
+                match queue.admit(usize::try_from(bytes).unwrap_or(usize::MAX)) {
+                    Ok(queued) => Some(queued),
+                    Err(_) => {
+                        debug!("request queue is full, rejecting request");
+                        return Err(e!(RequestQueueFull, "request queue is full"));
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let resolved = req.extensions().get::<ResolvedAddrs>().cloned();
+        let priority = req
+            .extensions()
+            .get::<Priority>()
+            .copied()
+            .unwrap_or_default();
+        let fresh = req.extensions().get::<FreshConnection>().is_some();
+        let backoff_key = self.backoff.as_ref().map(|_| pool_key.clone());
+        let connection = async {
+            if let (Some(ref hosts), Some(ref timer)) = (&self.backoff, &self.timer) {
+                let until = hosts.lock().unwrap().get(&pool_key).copied();
+                if let Some(until) = until.filter(|&until| until > timer.now()) {
+                    trace!("backing off from {:?} as it asked with a 429", pool_key);
+                    timer.sleep_until(until).await;
+                }
+            }
+            // Held until the connection can take another request, the one
+            // of the partition taken first so a busy partition doesn't hold
+            // on to the permits of the client while waiting.
+            let partition_permit = match self.partition.as_ref().and_then(|p| p.in_use.clone()) {
+                Some(in_use) => Some(acquire(in_use).await),
+                None => None,
+            };
+            let permit = match (permit, &self.in_use) {
+                (None, Some(in_use)) => Some(acquire(in_use.clone()).await),
+                (permit, _) => permit,
+            };
+            let permit = (permit, partition_permit);
+            let serial = match self.serial {
+                Some(ref hosts) => Some(serialize(hosts, &pool_key).await),
+                None => None,
+            };
+            let pooled = self
+                .connection_for(pool_key, resolved, priority, fresh)
+                .await?;
+            Ok::<_, Error>((permit, serial, pooled))
+        };
+        let (permit, serial, mut pooled) = match queued {
+            Some(queued) => match queued.wait(connection).await {
+                Ok(connection) => connection?,
+                Err(_) => {
+                    debug!("request dropped from the full request queue");
+                    return Err(e!(RequestQueueFull, "dropped from the request queue"));
+                }
+            },
+            None => connection.await?,
+        };
+        let acquired = Instant::now();
+        // HTTP/2 connections take concurrent requests anyway.
+        let serial = serial.filter(|_| pooled.is_http1());
+        spans::connection_id(&tracing::Span::current(), pooled.conn_info.id);
 
         req.extensions_mut()
             .get_mut::<CaptureConnectionExtension>()
             .map(|conn| conn.set(&pooled.conn_info));
 
+        if let Some(required) = req.extensions().get::<RequireProtocol>() {
+            let acceptable = match required {
+                RequireProtocol::Http1 => pooled.is_http1(),
+                RequireProtocol::Http2 => pooled.is_http2(),
+            };
+            if !acceptable {
+                warn!(
+                    "Connection protocol is not acceptable, request requires {:?}",
+                    required
+                );
+                return Err(e!(UserUnacceptableProtocol));
+            }
+        }
+
+        // Only requests safe to replay go out before the TLS handshake is
+        // done, and the others wait for it.
+        let sent_early = match pooled.conn_info.early_data.clone() {
+            Some(state) if state.is_pending() => {
+                let policy = req
+                    .extensions()
+                    .get::<EarlyData>()
+                    .copied()
+                    .unwrap_or_else(|| EarlyData::of(&req));
+                if policy == EarlyData::Allow {
+                    Some(state)
+                } else {
+                    trace!("waiting for the TLS handshake to send a request");
+                    if !state.handshake().await || pooled.is_closed() {
+                        debug!("connection closed during the TLS handshake");
+                        return Err(e!(Canceled, "connection closed during the TLS handshake"));
+                    }
+                    None
+                }
+            }
+            _ => None,
+        };
+
+        if let Some(ref switch) = pooled.conn_info.tcp_profile {
+            let profile = req.extensions().get::<TcpProfile>().copied();
+            if let Some(profile) = profile.or(self.config.tcp_profile) {
+                switch.set(profile);
+            }
+        }
+
         if pooled.is_http1() {
             if req.version() == Version::HTTP_2 {
                 warn!("Connection is HTTP/1, but request requires HTTP/2");
@@ -301,17 +1068,73 @@ where
             authority_form(req.uri_mut());
         }
 
+        if let Some(ref signer) = self.request_signer {
+            let (mut parts, body) = req.into_parts();
+            signer(&mut parts).map_err(|err| e!(RequestSigner, err))?;
+            req = Request::from_parts(parts, body);
+        }
+
+        let request = RequestTimings {
+            start,
+            connection: if pooled.is_reused() {
+                None
+            } else {
+                Some(pooled.timings.clone())
+            },
+            acquired,
+            request_start: Instant::now(),
+        };
+        let expectation = req.extensions().get::<Expectation>().copied();
+        // An HTTP/1 connection is returned for one request at a time.
+        if pooled.is_http1() {
+            pooled.disposition.renew();
+        }
+        let disposition = pooled.disposition.clone();
+        let on_response_head = req.extensions().get::<OnResponseHead>().cloned();
         let fut = pooled.send_request(req);
         //.send_request_retryable(req)
         //.map_err(ClientError::map_with_reused(pooled.is_reused()));
 
         // If the Connector included 'extra' info, add to Response...
         let extra_info = pooled.conn_info.extra.clone();
-        let fut = fut.map_ok(move |mut res| {
+        let poisoned = pooled.conn_info.poisoned.clone();
+        let config = self.config;
+        let fut = fut.map(move |res| {
+            let mut res = match res {
+                Ok(res) => res,
+                Err(err) => {
+                    let err = match sent_early {
+                        // the server never saw the request
+                        Some(state) if state.is_rejected() => {
+                            debug!("early data rejected, request can be sent again");
+                            poisoned.poison();
+                            e!(EarlyDataRejected, err)
+                        }
+                        _ => config.map_response_headers_too_large(err),
+                    };
+                    return Err(err.with_remote_addr(extra_info.as_ref()));
+                }
+            };
+            if let Err(err) = config.check_response_headers(res.headers()) {
+                return Err(err.with_remote_addr(extra_info.as_ref()));
+            }
             if let Some(extra) = extra_info {
                 extra.set(res.extensions_mut());
             }
-            res
+            let response_start = Instant::now();
+            let timings = Timings::new(request, response_start);
+            if res.body().is_end_stream() {
+                timings.end(response_start);
+            }
+            res.extensions_mut().insert(timings);
+            if let Some(expectation) = expectation {
+                res.extensions_mut().insert(expectation);
+            }
+            res.extensions_mut().insert(disposition);
+            if let Some(on_response_head) = on_response_head {
+                (on_response_head.0)(&res);
+            }
+            Ok(res)
         });
 
         // As of futures@0.1.21, there is a race condition in the mpsc
@@ -327,6 +1150,19 @@ where
 
         let res = fut.await?;
 
+        if pooled.is_http1() {
+            pooled.keep_alive.update(res.headers());
+        }
+        if let (Some(hosts), Some(key)) = (&self.backoff, backoff_key) {
+            if res.status() == StatusCode::TOO_MANY_REQUESTS {
+                let max = self.config.retry_after_backoff.unwrap_or_default();
+                if let Some(delay) = retry_after_secs(res.headers()) {
+                    let now = timer::now(self.timer.as_ref());
+                    back_off(hosts, key, delay.min(max), now);
+                }
+            }
+        }
+
         // If pooled is HTTP/2, we can toss this reference immediately.
         //
         // when pooled is dropped, it will try to insert back into the
@@ -338,21 +1174,39 @@ where
         //
         // It won't be ready if there is a body to stream.
         if pooled.is_http2() || !pooled.is_pool_enabled() || pooled.is_ready() {
+            // a ready HTTP/1 connection has received the whole body
+            if pooled.is_http1() && pooled.is_ready() {
+                if let Some(timings) = res.extensions().get::<Timings>() {
+                    timings.end(Instant::now());
+                }
+            }
             drop(pooled);
         } else if !res.body().is_end_stream() {
             //let (delayed_tx, delayed_rx) = oneshot::channel::<()>();
             //res.body_mut().delayed_eof(delayed_rx);
+            let span = spans::response_body();
+            let start = Instant::now();
+            let timings = res.extensions().get::<Timings>().cloned();
             let on_idle = future::poll_fn(move |cx| pooled.poll_ready(cx)).map(move |_| {
                 // At this point, `pooled` is dropped, and had a chance
                 // to insert into the pool (if conn was idle)
                 //drop(delayed_tx);
+                drop(permit);
+                drop(serial);
+                if let Some(timings) = timings {
+                    timings.end(Instant::now());
+                }
+                spans::finish(&span, start, None);
             });
 
             self.exec.execute(on_idle);
         } else {
             // There's no body to delay, but the connection isn't
             // ready yet. Only re-insert when it's ready
-            let on_idle = future::poll_fn(move |cx| pooled.poll_ready(cx)).map(|_| ());
+            let on_idle = future::poll_fn(move |cx| pooled.poll_ready(cx)).map(|_| {
+                drop(permit);
+                drop(serial);
+            });
 
             self.exec.execute(on_idle);
         }
@@ -363,10 +1217,19 @@ where
     async fn connection_for(
         &self,
         pool_key: PoolKey,
+        resolved: Option<ResolvedAddrs>,
+        priority: Priority,
+        fresh: bool,
     ) -> Result<pool::Pooled<PoolClient<B>, PoolKey>, Error> {
         loop {
-            match self.one_connection_for(pool_key.clone()).await {
-                Ok(pooled) => return Ok(pooled),
+            match self
+                .one_connection_for(pool_key.clone(), resolved.clone(), priority, fresh)
+                .await
+            {
+                Ok(pooled) => {
+                    metrics::checkout(pooled.is_reused());
+                    return Ok(pooled);
+                }
                 Err(ClientConnectError::Normal(err)) => return Err(err),
                 Err(ClientConnectError::CheckoutIsClosed(reason)) => {
                     if !self.config.retry_canceled_requests {
@@ -386,15 +1249,40 @@ where
     async fn one_connection_for(
         &self,
         pool_key: PoolKey,
+        resolved: Option<ResolvedAddrs>,
+        priority: Priority,
+        fresh: bool,
     ) -> Result<pool::Pooled<PoolClient<B>, PoolKey>, ClientConnectError> {
-        // Return a single connection if pooling is not enabled
-        if !self.pool.is_enabled() {
+        // Return a single connection if pooling is not enabled, or a new
+        // one is required
+        if !self.pool.is_enabled() || fresh {
             return self
-                .connect_to(pool_key)
+                .connect_to(pool_key, resolved)
                 .await
                 .map_err(ClientConnectError::Normal);
         }
 
+        let background = priority == Priority::Background;
+        if background {
+            let quota = self.config.background_connection_quota;
+            if quota.map_or(false, |quota| self.pool.connections(&pool_key) >= quota) {
+                trace!(
+                    "background connection quota reached for {:?}, waiting for an idle connection",
+                    pool_key
+                );
+                return self
+                    .pool_checkout(pool_key, background)
+                    .await
+                    .map_err(|err| {
+                        if err.is_canceled() {
+                            ClientConnectError::CheckoutIsClosed(err)
+                        } else {
+                            ClientConnectError::Normal(Error::checkout(err))
+                        }
+                    });
+            }
+        }
+
         // This actually races 2 different futures to try to get a ready
         // connection the fastest, and to reduce connection churn.
         //
@@ -408,18 +1296,51 @@ where
         //   (an idle connection became available first), the started
         //   connection future is spawned into the runtime to complete,
         //   and then be inserted into the pool as an idle connection.
-        let checkout = self.pool.checkout(pool_key.clone());
-        let connect = self.connect_to(pool_key);
-        let is_ver_h2 = self.config.ver == Ver::Http2;
+        // - If this future is dropped instead, a started connection future
+        //   is canceled, unless other checkouts are waiting for it.
+        //
+        // The connect is declared first so it's dropped last, once this
+        // checkout is no longer one of the waiters.
+        let mut connect = Dial {
+            connect: Some(self.connect_to(pool_key.clone(), resolved)),
+            pool: self.pool.clone(),
+            key: pool_key.clone(),
+            exec: self.exec.clone(),
+        };
+
+        // Prefer reusing a connection: while one is in use, give the
+        // checkout a head start, before a connection is dialed.
+        let race_delay = match (self.config.connect_race_delay, &self.timer) {
+            (Some(delay), Some(timer)) if self.pool.is_checked_out(&pool_key) => {
+                Some(timer.sleep(delay))
+            }
+            _ => None,
+        };
+        let mut checkout = self.pool_checkout(pool_key, background);
+        let is_ver_h2 = self.config.ver == Ver::Http2;
+
+        if let Some(sleep) = race_delay {
+            match future::select(&mut checkout, sleep).await {
+                Either::Left((Ok(checked_out), _)) => return Ok(checked_out),
+                Either::Left((Err(err), _)) => {
+                    return if err.is_canceled() {
+                        connect.await.map_err(ClientConnectError::Normal)
+                    } else {
+                        Err(ClientConnectError::Normal(Error::checkout(err)))
+                    };
+                }
+                Either::Right(_) => {}
+            }
+        }
 
         // The order of the `select` is depended on below...
 
-        match future::select(checkout, connect).await {
+        match future::select(&mut checkout, &mut connect).await {
             // Checkout won, connect future may have been started or not.
             //
             // If it has, let it finish and insert back into the pool,
             // so as to not waste the socket...
-            Either::Left((Ok(checked_out), connecting)) => {
+            Either::Left((Ok(checked_out), _)) => {
                 // This depends on the `select` above having the correct
                 // order, such that if the checkout future were ready
                 // immediately, the connect future will never have been
@@ -427,6 +1348,7 @@ where
                 //
                 // If it *wasn't* ready yet, then the connect future will
                 // have been started...
+                let connecting = connect.into_inner();
                 if connecting.started() {
                     let bg = connecting
                         .map_err(|err| {
@@ -456,7 +1378,7 @@ where
                 if err.is_canceled() {
                     connecting.await.map_err(ClientConnectError::Normal)
                 } else {
-                    Err(ClientConnectError::Normal(e!(Connect, err)))
+                    Err(ClientConnectError::Normal(Error::checkout(err)))
                 }
             }
             Either::Right((Err(err), checkout)) => {
@@ -465,7 +1387,7 @@ where
                         if is_ver_h2 && err.is_canceled() {
                             ClientConnectError::CheckoutIsClosed(err)
                         } else {
-                            ClientConnectError::Normal(e!(Connect, err))
+                            ClientConnectError::Normal(Error::checkout(err))
                         }
                     })
                 } else {
@@ -475,10 +1397,33 @@ where
         }
     }
 
+    fn pool_checkout(
+        &self,
+        pool_key: PoolKey,
+        background: bool,
+    ) -> impl Future<Output = Result<pool::Pooled<PoolClient<B>, PoolKey>, pool::Error>> + Unpin
+    {
+        let span = spans::checkout(&pool_key);
+        let start = Instant::now();
+        let finish = span.clone();
+        let checkout = if background {
+            self.pool.checkout_background(pool_key)
+        } else {
+            self.pool.checkout(pool_key)
+        };
+        checkout
+            .inspect(move |res| {
+                let err = res.as_ref().err().map(|err| err as &dyn fmt::Display);
+                spans::finish(&finish, start, err);
+            })
+            .instrument(span)
+    }
+
     #[cfg(any(feature = "http1", feature = "http2"))]
     fn connect_to(
         &self,
         pool_key: PoolKey,
+        resolved: Option<ResolvedAddrs>,
     ) -> impl Lazy<Output = Result<pool::Pooled<PoolClient<B>, PoolKey>, Error>> + Send + Unpin
     {
         let executor = self.exec.clone();
@@ -490,6 +1435,15 @@ where
         let ver = self.config.ver;
         let is_ver_h2 = ver == Ver::Http2;
         let connector = self.connector.clone();
+        let memory = self.memory.clone();
+        let hints = self.hints.clone();
+        let answers = self.dns.clone();
+        // An endpoint known to speak HTTP/2 takes the HTTP/2 connecting
+        // lock up front, so concurrent requests wait for one connection.
+        let is_hint_h2 = !is_ver_h2
+            && hints
+                .as_ref()
+                .map_or(false, |hints| hints.is_http2(&pool_key.0, &pool_key.1));
         let dst = domain_as_uri(pool_key.clone());
         hyper_lazy(move || {
             // Try to take a "connecting lock".
@@ -497,7 +1451,8 @@ where
             // If the pool_key is for HTTP/2, and there is already a
             // connection being established, then this can't take a
             // second lock. The "connect_to" future is Canceled.
-            let connecting = match pool.connecting(&pool_key, ver) {
+            let lock_ver = if is_hint_h2 { Ver::Http2 } else { ver };
+            let connecting = match pool.connecting(&pool_key, lock_ver) {
                 Some(lock) => lock,
                 None => {
                     let canceled = e!(Canceled);
@@ -506,16 +1461,44 @@ where
                     return Either::Right(future::err(canceled));
                 }
             };
+            let span = spans::connect(&dst, &pool_key);
+            let start = Instant::now();
+            let finish = span.clone();
+            let record = span.clone();
+            let mut connecting_io = connector.connect(super::connect::sealed::Internal, dst);
+            let select = pool_key.4.clone();
+            let mut dns_lookup = None;
+            let connecting_io = future::poll_fn(move |cx| {
+                timings::recording(&mut dns_lookup, || {
+                    resolved::overriding(resolved.as_ref(), || {
+                        selecting::selecting(select.as_ref(), || {
+                            Pin::new(&mut connecting_io).poll(cx)
+                        })
+                    })
+                })
+                .map_ok(|io| (io, dns_lookup.take()))
+            });
             Either::Left(
-                connector
-                    .connect(super::connect::sealed::Internal, dst)
+                connecting_io
+                    .instrument(span)
                     .map_err(|src| e!(Connect, src))
-                    .and_then(move |io| {
+                    .inspect(move |res| {
+                        let err = res.as_ref().err().map(|err| err as &dyn fmt::Display);
+                        spans::finish(&finish, start, err);
+                    })
+                    .and_then(move |(io, dns_lookup)| {
                         let connected = io.connected();
+                        let id = connected.id;
+                        let timings = ConnectTimings {
+                            dns_lookup,
+                            connect: Some(start..Instant::now()),
+                            tls_handshake: connected.tls_handshake.clone(),
+                        };
+                        spans::connection_id(&record, id);
                         // If ALPN is h2 and we aren't http2_only already,
                         // then we need to convert our pool checkout into
                         // a single HTTP2 one.
-                        let connecting = if connected.alpn == Alpn::H2 && !is_ver_h2 {
+                        let connecting = if connected.alpn == Alpn::H2 && !is_ver_h2 && !is_hint_h2 {
                             match connecting.alpn_h2(&pool) {
                                 Some(lock) => {
                                     trace!("ALPN negotiated h2, updating pool");
@@ -534,19 +1517,46 @@ where
 
                         #[cfg_attr(not(feature = "http2"), allow(unused))]
                         let is_h2 = is_ver_h2 || connected.alpn == Alpn::H2;
+                        let charge = memory.map(|budget| Arc::new(budget.charge(is_h2)));
+                        let dns = answers.zip(connected.dns.clone()).map(|(answers, answer)| {
+                            answers.track(pool_key.clone(), answer)
+                        });
+                        let (scheme, authority, _, _, _) = pool_key;
+
+                        // Requests waiting for the TLS handshake give up once
+                        // the connection closes.
+                        let early_data = connected.early_data.clone();
+                        let on_close = move || {
+                            if let Some(state) = early_data {
+                                state.close();
+                            }
+                        };
 
                         Either::Left(Box::pin(async move {
                             let tx = if is_h2 {
                                 #[cfg(feature = "http2")] {
+                                    let mut h2_builder = h2_builder;
+                                    if charge.as_ref().map_or(false, |charge| charge.is_reduced()) {
+                                        trace!("over memory budget, using reduced http2 windows");
+                                        h2_builder
+                                            .initial_stream_window_size(memory::REDUCED_H2_WINDOW)
+                                            .initial_connection_window_size(memory::REDUCED_H2_WINDOW)
+                                            .adaptive_window(false);
+                                    }
                                     let (mut tx, conn) =
                                         h2_builder.handshake(io).await.map_err(Error::tx)?;
 
                                     trace!(
-                                        "http2 handshake complete, spawning background dispatcher task"
+                                        "http2 handshake complete, spawning background dispatcher task (connection.id={})",
+                                        id
                                     );
+                                    metrics::connection_opened("http2");
                                     executor.execute(
-                                        conn.map_err(|e| debug!("client connection error: {}", e))
-                                            .map(|_| ()),
+                                        conn.map_err(move |e| debug!("client connection error (connection.id={}): {}", id, e))
+                                            .map(move |_| {
+                                                metrics::connection_closed("http2");
+                                                on_close();
+                                            }),
                                     );
 
                                     // Wait for 'conn' to ready up before we
@@ -562,12 +1572,17 @@ where
                                         h1_builder.handshake(io).await.map_err(Error::tx)?;
 
                                     trace!(
-                                        "http1 handshake complete, spawning background dispatcher task"
+                                        "http1 handshake complete, spawning background dispatcher task (connection.id={})",
+                                        id
                                     );
+                                    metrics::connection_opened("http1");
                                     executor.execute(
                                         conn.with_upgrades()
-                                            .map_err(|e| debug!("client connection error: {}", e))
-                                            .map(|_| ()),
+                                            .map_err(move |e| debug!("client connection error (connection.id={}): {}", id, e))
+                                            .map(move |_| {
+                                                metrics::connection_closed("http1");
+                                                on_close();
+                                            }),
                                     );
 
                                     // Wait for 'conn' to ready up before we
@@ -580,11 +1595,19 @@ where
                                 }
                             };
 
+                            if let Some(hints) = hints {
+                                hints.record(&scheme, &authority, is_h2);
+                            }
                             Ok(pool.pooled(
                                 connecting,
                                 PoolClient {
                                     conn_info: connected,
                                     tx,
+                                    memory: charge,
+                                    timings,
+                                    dns,
+                                    keep_alive: KeepAlive::default(),
+                                    disposition: pool::Disposition::new(),
                                 },
                             ))
                         }))
@@ -645,6 +1668,20 @@ impl<C: Clone, B> Clone for Client<C, B> {
             h2_builder: self.h2_builder.clone(),
             connector: self.connector.clone(),
             pool: self.pool.clone(),
+            pool_tagger: self.pool_tagger.clone(),
+            request_signer: self.request_signer.clone(),
+            default_headers: self.default_headers.clone(),
+            memory: self.memory.clone(),
+            in_use: self.in_use.clone(),
+            queue: self.queue.clone(),
+            serial: self.serial.clone(),
+            backoff: self.backoff.clone(),
+            hints: self.hints.clone(),
+            dns: self.dns.clone(),
+            pool_stats: self.pool_stats.clone(),
+            timer: self.timer.clone(),
+            partitions: self.partitions.clone(),
+            partition: self.partition.clone(),
         }
     }
 }
@@ -655,6 +1692,462 @@ impl<C, B> fmt::Debug for Client<C, B> {
     }
 }
 
+fn pool_key_name((scheme, authority, tag, partition, select): &PoolKey) -> String {
+    let mut name = format!("{}://{}", scheme, authority);
+    if let Some(tag) = tag {
+        name = format!("{} [{}]", name, tag.as_str());
+    }
+    if let Some(partition) = partition {
+        name = format!("{} <{}>", name, partition);
+    }
+    if let Some(select) = select {
+        name = format!("{} via {}", name, select.as_str());
+    }
+    name
+}
+
+// Reports snapshots of the pool every `interval`, until it is dropped.
+async fn report_pool_stats<B>(
+    pool: pool::WeakPool<PoolClient<B>, PoolKey>,
+    timer: timer::Timer,
+    interval: Duration,
+    reporter: Arc<dyn PoolStatsReporter>,
+) {
+    loop {
+        timer.sleep(interval).await;
+        match pool.upgrade() {
+            Some(pool) => reporter.report(&pool.dump(pool_key_name)),
+            None => return,
+        }
+    }
+}
+
+// Waits for the request in progress on the host of `key` to be done.
+async fn serialize(hosts: &SerialHosts, key: &PoolKey) -> SerialTurn {
+    let host = hosts
+        .lock()
+        .unwrap()
+        .entry(key.clone())
+        .or_insert_with(|| Arc::new(Semaphore::new(1)))
+        .clone();
+    let mut turn = SerialTurn {
+        permit: None,
+        host,
+        hosts: hosts.clone(),
+        key: key.clone(),
+    };
+    turn.permit = Some(acquire(turn.host.clone()).await);
+    turn
+}
+
+impl Drop for SerialTurn {
+    fn drop(&mut self) {
+        drop(self.permit.take());
+        let mut hosts = self.hosts.lock().unwrap();
+        // Only `hosts` and this turn hold the semaphore, and new turns
+        // clone it under the lock.
+        if Arc::strong_count(&self.host) == 2 {
+            hosts.remove(&self.key);
+        }
+    }
+}
+
+// A `Retry-After` of a number of seconds.
+fn retry_after_secs(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?;
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+// Hold off new requests to the host of `key` for `delay` from `now`.
+fn back_off(hosts: &BackoffHosts, key: PoolKey, delay: Duration, now: Instant) {
+    let mut hosts = hosts.lock().unwrap();
+    hosts.retain(|_, until| *until > now);
+    let until = hosts.entry(key).or_insert(now);
+    *until = (*until).max(now + delay);
+}
+
+async fn acquire(in_use: Arc<Semaphore>) -> OwnedSemaphorePermit {
+    in_use
+        .acquire_owned()
+        .await
+        .expect("semaphore is never closed")
+}
+
+// ===== impl Config =====
+
+impl Config {
+    // A head hyper couldn't parse for its size, since it has more fields, or
+    // is larger, than the limits allow. `is_parse_too_large` needs hyper's
+    // `server` feature, so this matches its description instead.
+    fn map_response_headers_too_large(&self, err: Error) -> Error {
+        let too_large = err.sources().any(|err| {
+            err.downcast_ref::<hyper::Error>().map_or(false, |err| {
+                err.is_parse() && err.to_string() == "message head is too large"
+            })
+        });
+        if too_large {
+            e!(ResponseHeadersTooLarge, err)
+        } else {
+            err
+        }
+    }
+
+    fn check_response_headers(&self, headers: &HeaderMap) -> Result<(), Error> {
+        if let Some(max) = self.max_response_headers {
+            if headers.len() > max {
+                return Err(e!(ResponseHeadersTooLarge, "too many response headers"));
+            }
+        }
+        if let Some(max) = self.max_response_header_size {
+            let size = headers
+                .iter()
+                .map(|(name, value)| name.as_str().len() + value.len() + 32)
+                .sum::<usize>();
+            if size > max {
+                return Err(e!(ResponseHeadersTooLarge, "response headers too large"));
+            }
+        }
+        Ok(())
+    }
+}
+
+// ===== impl ClientService =====
+
+impl<C, B> tower_service::Service<Request<B>> for ClientService<C, B>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+    B: Body + Send + 'static + Unpin,
+    B::Data: Send,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Response = Response<hyper::body::Incoming>;
+    type Error = Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let in_use = match self.client.in_use {
+            Some(ref in_use) if self.permit.is_none() => in_use,
+            _ => return Poll::Ready(Ok(())),
+        };
+        let acquire = self
+            .acquire
+            .get_or_insert_with(|| SyncWrapper::new(Box::pin(acquire(in_use.clone()))));
+        let permit = futures_util::ready!(acquire.get_mut().as_mut().poll(cx));
+        self.acquire = None;
+        self.permit = Some(permit);
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        self.client.request_with(req, self.permit.take())
+    }
+}
+
+impl<C: Clone, B> Clone for ClientService<C, B> {
+    fn clone(&self) -> ClientService<C, B> {
+        ClientService {
+            client: self.client.clone(),
+            permit: None,
+            acquire: None,
+        }
+    }
+}
+
+impl<C, B> fmt::Debug for ClientService<C, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientService")
+            .field("ready", &self.permit.is_some())
+            .finish()
+    }
+}
+
+// ===== impl PooledConnection =====
+
+impl<B> PooledConnection<B>
+where
+    B: Send + 'static,
+{
+    /// Returns true if this is an HTTP/1 connection.
+    pub fn is_http1(&self) -> bool {
+        self.pooled().is_http1()
+    }
+
+    /// Returns true if this is an HTTP/2 connection.
+    pub fn is_http2(&self) -> bool {
+        self.pooled().is_http2()
+    }
+
+    /// Returns true if the connection was idle in the pool, rather than
+    /// dialed for this checkout.
+    pub fn is_reused(&self) -> bool {
+        self.pooled().is_reused()
+    }
+
+    /// The information the connector gave about the connection.
+    pub fn connected(&self) -> &Connected {
+        &self.pooled().conn_info
+    }
+
+    /// What becomes of the connection once this is dropped.
+    pub fn disposition(&self) -> &pool::Disposition {
+        &self.pooled().disposition
+    }
+
+    /// Poll whether the connection can send another request.
+    pub fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Error>> {
+        self.pooled_mut().poll_ready(cx)
+    }
+
+    /// Wait until the connection can send another request.
+    pub async fn ready(&mut self) -> Result<(), Error> {
+        future::poll_fn(|cx| self.poll_ready(cx)).await
+    }
+
+    /// Send a request on the connection.
+    ///
+    /// The request is sent as is: its URI should be in the form the server
+    /// expects, like the path alone for an HTTP/1 server, and none of the
+    /// headers the client adds, like `host`, are added.
+    pub fn send_request(
+        &mut self,
+        req: Request<B>,
+    ) -> impl Future<Output = Result<Response<hyper::body::Incoming>, Error>>
+    where
+        B: Body,
+    {
+        self.pooled_mut().send_request(req)
+    }
+
+    /// The `SendRequest` handle of an HTTP/1 connection.
+    #[cfg(feature = "http1")]
+    pub fn http1(&mut self) -> Option<&mut hyper::client::conn::http1::SendRequest<B>> {
+        match self.pooled_mut().tx {
+            PoolTx::Http1(ref mut tx) => Some(tx),
+            #[cfg(feature = "http2")]
+            PoolTx::Http2(_) => None,
+        }
+    }
+
+    /// The `SendRequest` handle of an HTTP/2 connection.
+    #[cfg(feature = "http2")]
+    pub fn http2(&mut self) -> Option<&mut hyper::client::conn::http2::SendRequest<B>> {
+        match self.pooled_mut().tx {
+            #[cfg(feature = "http1")]
+            PoolTx::Http1(_) => None,
+            PoolTx::Http2(ref mut tx) => Some(tx),
+        }
+    }
+
+    fn pooled(&self) -> &pool::Pooled<PoolClient<B>, PoolKey> {
+        self.pooled.as_ref().expect("not dropped")
+    }
+
+    fn pooled_mut(&mut self) -> &mut pool::Pooled<PoolClient<B>, PoolKey> {
+        self.pooled.as_mut().expect("not dropped")
+    }
+}
+
+impl<B> Drop for PooledConnection<B>
+where
+    B: Send + 'static,
+{
+    fn drop(&mut self) {
+        let mut pooled = match self.pooled.take() {
+            Some(pooled) => pooled,
+            None => return,
+        };
+        // An HTTP/1 connection still reading a response goes back to the
+        // pool once done, like after a request of the client.
+        if pooled.is_http1() && pooled.is_pool_enabled() && !pooled.is_ready() {
+            let on_idle = future::poll_fn(move |cx| pooled.poll_ready(cx)).map(|_| ());
+            self.exec.execute(on_idle);
+        }
+    }
+}
+
+impl<B> fmt::Debug for PooledConnection<B>
+where
+    B: Send + 'static,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledConnection")
+            .field("http2", &self.is_http2())
+            .field("reused", &self.is_reused())
+            .finish()
+    }
+}
+
+// ===== impl Expectation =====
+
+impl Expectation {
+    /// Create an expectation of nothing in particular.
+    pub fn new() -> Expectation {
+        Expectation::default()
+    }
+
+    /// Expect a response body of about `size` bytes.
+    pub fn with_response_size(mut self, size: u64) -> Expectation {
+        self.response_size = Some(size);
+        self
+    }
+
+    /// The expected size of the response body, if any.
+    pub fn response_size(&self) -> Option<u64> {
+        self.response_size
+    }
+}
+
+// ===== impl EarlyData =====
+
+impl EarlyData {
+    /// Whether `req` is safe to send as early data: a `GET`, `HEAD` or
+    /// `OPTIONS` request without a body.
+    pub fn of<B: Body>(req: &Request<B>) -> EarlyData {
+        let safe = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+            && req.body().is_end_stream();
+        if safe {
+            EarlyData::Allow
+        } else {
+            EarlyData::Deny
+        }
+    }
+}
+
+// ===== impl OnResponseHead =====
+
+impl OnResponseHead {
+    /// Create a callback calling `f` with the response head.
+    pub fn new<F>(f: F) -> OnResponseHead
+    where
+        F: Fn(&Response<hyper::body::Incoming>) + Send + Sync + 'static,
+    {
+        OnResponseHead(Arc::new(f))
+    }
+}
+
+impl fmt::Debug for OnResponseHead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnResponseHead").finish()
+    }
+}
+
+// ===== impl BaseUriClient =====
+
+impl<C, B> BaseUriClient<C, B>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+    B: Body + Send + 'static + Unpin,
+    B::Data: Send,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    /// Send a `GET` request to the supplied `Uri`, resolved against the
+    /// base.
+    pub fn get(&self, uri: Uri) -> ResponseFuture
+    where
+        B: Default,
+    {
+        let mut req = Request::new(B::default());
+        *req.uri_mut() = uri;
+        self.request(req)
+    }
+
+    /// Send a `Request`, resolving its URI against the base.
+    pub fn request(&self, mut req: Request<B>) -> ResponseFuture {
+        if req.method() != Method::CONNECT {
+            match resolve(&self.base, req.uri()) {
+                Ok(Some(uri)) => *req.uri_mut() = uri,
+                Ok(None) => (),
+                Err(err) => return ResponseFuture::new(future::err(err)),
+            }
+        }
+        self.client.request(req)
+    }
+}
+
+impl<C, B> BaseUriClient<C, B> {
+    /// The base URI of the requests.
+    pub fn base_uri(&self) -> &Uri {
+        &self.base
+    }
+
+    /// Get a reference to the inner `Client`.
+    pub fn get_ref(&self) -> &Client<C, B> {
+        &self.client
+    }
+}
+
+impl<C, B> tower_service::Service<Request<B>> for BaseUriClient<C, B>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+    B: Body + Send + 'static + Unpin,
+    B::Data: Send,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Response = Response<hyper::body::Incoming>;
+    type Error = Error;
+    type Future = ResponseFuture;
+
+    fn poll_ready(&mut self, _: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        self.request(req)
+    }
+}
+
+impl<C: Clone, B> Clone for BaseUriClient<C, B> {
+    fn clone(&self) -> BaseUriClient<C, B> {
+        BaseUriClient {
+            client: self.client.clone(),
+            base: self.base.clone(),
+        }
+    }
+}
+
+impl<C, B> fmt::Debug for BaseUriClient<C, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BaseUriClient")
+            .field("base", &self.base)
+            .finish()
+    }
+}
+
+// Resolve `uri` against `base`, returning `None` if it is already absolute
+// to the same origin.
+fn resolve(base: &Uri, uri: &Uri) -> Result<Option<Uri>, Error> {
+    match (uri.scheme(), uri.authority()) {
+        (None, None) => (),
+        (scheme, authority) => {
+            if scheme == base.scheme() && authority == base.authority() {
+                return Ok(None);
+            }
+            debug!("request URI {:?} isn't relative to base {:?}", uri, base);
+            return Err(e!(UserBaseUriMismatch));
+        }
+    }
+
+    let prefix = base.path().trim_end_matches('/');
+    let path = match uri.path_and_query() {
+        Some(path) => path.as_str(),
+        None => "/",
+    };
+    let path_and_query = if path.starts_with('?') {
+        format!("{}/{}", prefix, path)
+    } else {
+        format!("{}{}", prefix, path)
+    };
+    let mut parts = http::uri::Parts::default();
+    parts.scheme = base.scheme().cloned();
+    parts.authority = base.authority().cloned();
+    parts.path_and_query = Some(
+        path_and_query
+            .parse()
+            .expect("path of two valid URIs is valid"),
+    );
+    Ok(Some(Uri::from_parts(parts).expect("resolved URI is valid")))
+}
+
 // ===== impl ResponseFuture =====
 
 impl ResponseFuture {
@@ -694,6 +2187,22 @@ impl Future for ResponseFuture {
 struct PoolClient<B> {
     conn_info: Connected,
     tx: PoolTx<B>,
+    // Shared by the clones of an HTTP/2 connection.
+    memory: Option<Arc<memory::Charge>>,
+    timings: ConnectTimings,
+    dns: Option<Tracked>,
+    keep_alive: KeepAlive,
+    // Shared by the clones of an HTTP/2 connection, and the responses.
+    disposition: pool::Disposition,
+}
+
+// The reuse hints of an HTTP/1 server, from the `Keep-Alive` header of its
+// last response.
+#[derive(Clone, Copy, Debug, Default)]
+struct KeepAlive {
+    timeout: Option<Duration>,
+    // The requests the server still takes on the connection.
+    max: Option<u64>,
 }
 
 enum PoolTx<B> {
@@ -807,7 +2316,10 @@ where
     B: Send + 'static,
 {
     fn is_open(&self) -> bool {
-        self.is_ready()
+        !self.conn_info.is_poisoned()
+            && self.disposition.is_open()
+            && self.is_ready()
+            && self.dns.as_ref().map_or(true, Tracked::is_current)
     }
 
     fn reserve(self) -> pool::Reservation<Self> {
@@ -816,16 +2328,31 @@ where
             PoolTx::Http1(tx) => pool::Reservation::Unique(PoolClient {
                 conn_info: self.conn_info,
                 tx: PoolTx::Http1(tx),
+                memory: self.memory,
+                timings: self.timings,
+                dns: self.dns,
+                keep_alive: self.keep_alive,
+                disposition: self.disposition,
             }),
             #[cfg(feature = "http2")]
             PoolTx::Http2(tx) => {
                 let b = PoolClient {
                     conn_info: self.conn_info.clone(),
                     tx: PoolTx::Http2(tx.clone()),
+                    memory: self.memory.clone(),
+                    timings: self.timings.clone(),
+                    dns: self.dns.clone(),
+                    keep_alive: self.keep_alive,
+                    disposition: self.disposition.clone(),
                 };
                 let a = PoolClient {
                     conn_info: self.conn_info,
                     tx: PoolTx::Http2(tx),
+                    memory: self.memory,
+                    timings: self.timings,
+                    dns: self.dns,
+                    keep_alive: self.keep_alive,
+                    disposition: self.disposition,
                 };
                 pool::Reservation::Shared(a, b)
             }
@@ -835,6 +2362,60 @@ where
     fn can_share(&self) -> bool {
         self.is_http2()
     }
+
+    fn can_idle(&self) -> bool {
+        // An HTTP/2 connection is pooled to be shared, dropping it would
+        // only mean dialing more.
+        self.is_http2()
+            || self.disposition.is_returned()
+            || (self.keep_alive.max != Some(0)
+                && !self
+                    .memory
+                    .as_ref()
+                    .map_or(false, |charge| charge.is_over_budget()))
+    }
+
+    fn idle_timeout(&self) -> Option<Duration> {
+        // Stop reusing a bit before the server's timeout, so it doesn't
+        // close the connection just as a request is sent on it.
+        self.keep_alive.timeout.map(|timeout| {
+            timeout
+                .saturating_sub(Duration::from_secs(1))
+                .max(timeout / 2)
+        })
+    }
+
+    fn disposition(&self) -> Option<&pool::Disposition> {
+        Some(&self.disposition)
+    }
+}
+
+// ===== impl KeepAlive =====
+
+impl KeepAlive {
+    /// Update from a response, such as `Keep-Alive: timeout=5, max=100`.
+    fn update(&mut self, headers: &HeaderMap) {
+        let value = match headers.get("keep-alive").map(HeaderValue::to_str) {
+            Some(Ok(value)) => value,
+            _ => {
+                // The server took one of the requests it allowed.
+                self.max = self.max.map(|max| max.saturating_sub(1));
+                return;
+            }
+        };
+        *self = KeepAlive::default();
+        for param in value.split(',') {
+            let (name, value) = match param.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().parse::<u64>().ok()),
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("timeout") {
+                self.timeout = value.map(Duration::from_secs);
+            } else if name.eq_ignore_ascii_case("max") {
+                self.max = value;
+            }
+        }
+    }
 }
 
 enum ClientConnectError {
@@ -842,6 +2423,70 @@ enum ClientConnectError {
     CheckoutIsClosed(pool::Error),
 }
 
+// The connect future raced against a checkout.
+//
+// If it's dropped while still connecting, which happens when the request
+// future is dropped, the connection is only finished in the background if
+// some other checkout is waiting for it. Otherwise it's canceled, instead
+// of idling a connection nobody asked for.
+struct Dial<F, B>
+where
+    F: Lazy + Send + Unpin + 'static,
+{
+    connect: Option<F>,
+    pool: pool::Pool<PoolClient<B>, PoolKey>,
+    key: PoolKey,
+    exec: Exec,
+}
+
+// ===== impl Dial =====
+
+impl<F, B> Dial<F, B>
+where
+    F: Lazy + Send + Unpin + 'static,
+{
+    fn into_inner(mut self) -> F {
+        self.connect
+            .take()
+            .expect("Dial::into_inner after completion")
+    }
+}
+
+impl<F, B> Future for Dial<F, B>
+where
+    F: Lazy + Send + Unpin + 'static,
+{
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let connect = self.connect.as_mut().expect("Dial polled after completion");
+        let output = futures_util::ready!(Pin::new(connect).poll(cx));
+        self.connect = None;
+        Poll::Ready(output)
+    }
+}
+
+impl<F, B> Drop for Dial<F, B>
+where
+    F: Lazy + Send + Unpin + 'static,
+{
+    fn drop(&mut self) {
+        let connect = match self.connect.take() {
+            Some(connect) if connect.started() => connect,
+            _ => return,
+        };
+        if self.pool.has_waiters(&self.key) {
+            trace!("checkout dropped, finishing connect for other waiters");
+            self.exec.execute(connect.map(|_pooled| {
+                // dropping here should just place it in
+                // the Pool for us...
+            }));
+        } else {
+            trace!("checkout dropped, canceling connect");
+        }
+    }
+}
+
 fn origin_form(uri: &mut Uri) {
     let path = match uri.path_and_query() {
         Some(path) if path.as_str() != "/" => {
@@ -891,10 +2536,16 @@ fn authority_form(uri: &mut Uri) {
     };
 }
 
-fn extract_domain(uri: &mut Uri, is_http_connect: bool) -> Result<PoolKey, Error> {
+fn extract_domain(
+    uri: &mut Uri,
+    is_http_connect: bool,
+    tag: Option<PoolTag>,
+    partition: Option<Arc<str>>,
+    select: Option<SelectConnector>,
+) -> Result<PoolKey, Error> {
     let uri_clone = uri.clone();
     match (uri_clone.scheme(), uri_clone.authority()) {
-        (Some(scheme), Some(auth)) => Ok((scheme.clone(), auth.clone())),
+        (Some(scheme), Some(auth)) => Ok((scheme.clone(), auth.clone(), tag, partition, select)),
         (None, Some(auth)) if is_http_connect => {
             let scheme = match auth.port_u16() {
                 Some(443) => {
@@ -906,7 +2557,7 @@ fn extract_domain(uri: &mut Uri, is_http_connect: bool) -> Result<PoolKey, Error
                     Scheme::HTTP
                 }
             };
-            Ok((scheme, auth.clone()))
+            Ok((scheme, auth.clone(), tag, partition, select))
         }
         _ => {
             debug!("Client requires absolute-form URIs, received: {:?}", uri);
@@ -915,7 +2566,7 @@ fn extract_domain(uri: &mut Uri, is_http_connect: bool) -> Result<PoolKey, Error
     }
 }
 
-fn domain_as_uri((scheme, auth): PoolKey) -> Uri {
+fn domain_as_uri((scheme, auth, _, _, _): PoolKey) -> Uri {
     http::uri::Builder::new()
         .scheme(scheme)
         .authority(auth)
@@ -942,117 +2593,513 @@ fn get_non_default_port(uri: &Uri) -> Option<http::uri::Port<&str>> {
         (Some(80), false) => None,
         _ => uri.port(),
     }
-}
+}
+
+fn is_schema_secure(uri: &Uri) -> bool {
+    uri.scheme_str()
+        .map(|scheme_str| matches!(scheme_str, "wss" | "https"))
+        .unwrap_or_default()
+}
+
+/// A builder to configure a new [`Client`](Client).
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # fn run () {
+/// use std::time::Duration;
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::TokioExecutor;
+///
+/// let client = Client::builder(TokioExecutor::new())
+///     .pool_idle_timeout(Duration::from_secs(30))
+///     .http2_only(true)
+///     .build_http();
+/// # let infer: Client<_, http_body_util::Full<bytes::Bytes>> = client;
+/// # drop(infer);
+/// # }
+/// # fn main() {}
+/// ```
+#[cfg_attr(docsrs, doc(cfg(any(feature = "http1", feature = "http2"))))]
+#[derive(Clone)]
+pub struct Builder {
+    client_config: Config,
+    exec: Exec,
+    #[cfg(feature = "http1")]
+    h1_builder: hyper::client::conn::http1::Builder,
+    #[cfg(feature = "http2")]
+    h2_builder: hyper::client::conn::http2::Builder<Exec>,
+    pool_config: pool::Config,
+    pool_timer: Option<timer::Timer>,
+    pool_tagger: Option<PoolTagger>,
+    request_signer: Option<RequestSigner>,
+    default_headers: HeaderMap,
+    memory: memory::Config,
+    pool_max_in_use: Option<usize>,
+    pool_max_in_use_per_partition: Option<usize>,
+    request_queue: queue::Config,
+    hint_store: Option<Arc<dyn HintStore>>,
+    pool_dns_expiry_grace: Option<Duration>,
+    pool_stats: Option<(Duration, Arc<dyn PoolStatsReporter>)>,
+}
+
+impl Builder {
+    /// Construct a new Builder.
+    pub fn new<E>(executor: E) -> Self
+    where
+        E: hyper::rt::Executor<BoxSendFuture> + Send + Sync + Clone + 'static,
+    {
+        let exec = Exec::new(executor);
+        Self {
+            client_config: Config {
+                retry_canceled_requests: true,
+                set_host: true,
+                host_normalization: HostNormalization::default(),
+                tcp_profile: None,
+                connect_race_delay: None,
+                background_connection_quota: None,
+                http1_serial: false,
+                retry_after_backoff: None,
+                max_response_header_size: None,
+                max_response_headers: None,
+                ver: Ver::Auto,
+            },
+            exec: exec.clone(),
+            #[cfg(feature = "http1")]
+            h1_builder: hyper::client::conn::http1::Builder::new(),
+            #[cfg(feature = "http2")]
+            h2_builder: hyper::client::conn::http2::Builder::new(exec),
+            pool_config: pool::Config {
+                idle_timeout: Some(Duration::from_secs(90)),
+                max_idle_per_host: usize::MAX,
+                max_waiters_per_host: usize::MAX,
+            },
+            pool_timer: None,
+            pool_tagger: None,
+            request_signer: None,
+            default_headers: HeaderMap::new(),
+            memory: memory::Config::new(),
+            pool_max_in_use: None,
+            pool_max_in_use_per_partition: None,
+            request_queue: queue::Config::new(),
+            hint_store: None,
+            pool_dns_expiry_grace: None,
+            pool_stats: None,
+        }
+    }
+    /// Set an optional timeout for idle sockets being kept-alive.
+    /// A `Timer` is required for this to take effect. See `Builder::pool_timer`
+    ///
+    /// Pass `None` to disable timeout.
+    ///
+    /// Default is 90 seconds.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "tokio")]
+    /// # fn run () {
+    /// use std::time::Duration;
+    /// use hyper_util::client::legacy::Client;
+    /// use hyper_util::rt::{TokioExecutor, TokioTimer};
+    ///
+    /// let client = Client::builder(TokioExecutor::new())
+    ///     .pool_idle_timeout(Duration::from_secs(30))
+    ///     .pool_timer(TokioTimer::new())
+    ///     .build_http();
+    ///
+    /// # let infer: Client<_, http_body_util::Full<bytes::Bytes>> = client;
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn pool_idle_timeout<D>(&mut self, val: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.pool_config.idle_timeout = val.into();
+        self
+    }
+
+    #[doc(hidden)]
+    #[deprecated(note = "renamed to `pool_max_idle_per_host`")]
+    pub fn max_idle_per_host(&mut self, max_idle: usize) -> &mut Self {
+        self.pool_config.max_idle_per_host = max_idle;
+        self
+    }
+
+    /// Sets the maximum idle connection per host allowed in the pool.
+    ///
+    /// Default is `usize::MAX` (no limit).
+    pub fn pool_max_idle_per_host(&mut self, max_idle: usize) -> &mut Self {
+        self.pool_config.max_idle_per_host = max_idle;
+        self
+    }
+
+    /// Sets how many connections to a host there must be before requests
+    /// with [`Priority::Background`] stop dialing new ones.
+    ///
+    /// Once a host has this many connections checked out or idle, a
+    /// background request only waits for one of them to become idle, so
+    /// background traffic doesn't grow the pool. Interactive requests
+    /// always dial when no connection is idle. An HTTP/2 connection counts
+    /// once for each request using it.
+    ///
+    /// Pass `None` to let background requests dial like any other.
+    ///
+    /// Default is `None`.
+    pub fn pool_background_connection_quota<Q>(&mut self, quota: Q) -> &mut Self
+    where
+        Q: Into<Option<usize>>,
+    {
+        self.client_config.background_connection_quota = quota.into();
+        self
+    }
+
+    /// Sets the maximum number of requests per host waiting for a connection
+    /// from the pool.
+    ///
+    /// Requests beyond the limit fail immediately with an error for which
+    /// [`Error::is_pool_queue_full`] returns true, instead of queueing up
+    /// behind a slow host.
+    ///
+    /// Default is `usize::MAX` (no limit).
+    pub fn pool_max_waiters_per_host(&mut self, max_waiters: usize) -> &mut Self {
+        self.pool_config.max_waiters_per_host = max_waiters;
+        self
+    }
+
+    /// Set how long a pooled connection may be reused after the DNS record
+    /// its address came from expired.
+    ///
+    /// This takes effect on connections whose connector reported the TTL
+    /// with [`Connected::dns_answer`](super::connect::Connected::dns_answer).
+    /// Once expired, a connection is still reused while fresher answers,
+    /// from newer connections to the same host, include its address, and
+    /// evicted as soon as one doesn't. Without a fresher answer, it is
+    /// evicted when the grace period passed, so the next request resolves
+    /// the name again. This keeps traffic following weighted DNS shifts.
+    ///
+    /// Pass `None` to ignore DNS TTLs.
+    ///
+    /// Default is `None`.
+    pub fn pool_dns_expiry_grace<D>(&mut self, grace: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.pool_dns_expiry_grace = grace.into();
+        self
+    }
+
+    /// Report a snapshot of the pool to `reporter` every `interval`.
+    ///
+    /// The snapshots are the same as [`Client::pool_dump`]. Reporting starts
+    /// with the first request, and stops once the client and its clones are
+    /// dropped.
+    ///
+    /// A `Timer` is required for this to take effect. See `Builder::pool_timer`
+    pub fn pool_stats_reporter<R>(&mut self, interval: Duration, reporter: R) -> &mut Self
+    where
+        R: PoolStatsReporter + 'static,
+    {
+        self.pool_stats = Some((interval, Arc::new(reporter)));
+        self
+    }
+
+    /// Set the [`TcpProfile`] of requests that don't carry one in their
+    /// extensions.
+    ///
+    /// This takes effect on connections from a
+    /// [`TcpProfiled`](super::connect::TcpProfiled) connector.
+    ///
+    /// Default is `None`, which leaves the socket as the connector set it.
+    pub fn tcp_profile<P>(&mut self, profile: P) -> &mut Self
+    where
+        P: Into<Option<TcpProfile>>,
+    {
+        self.client_config.tcp_profile = profile.into();
+        self
+    }
+
+    /// Sets a budget for the memory the connections of the client may hold.
+    ///
+    /// The memory of a connection is estimated from its configuration: the
+    /// read and write buffer limits of HTTP/1, and the connection window and
+    /// send buffer of HTTP/2. While the budget is exceeded, HTTP/1
+    /// connections aren't kept idle in the pool, and new HTTP/2 connections
+    /// are dialed with the smallest flow control windows.
+    ///
+    /// Pass `None` to disable the budget.
+    ///
+    /// Default is `None`.
+    pub fn pool_max_memory<M>(&mut self, max: M) -> &mut Self
+    where
+        M: Into<Option<usize>>,
+    {
+        self.memory.max = max.into();
+        self
+    }
+
+    /// Sets how long a request waits for a pooled connection to become idle
+    /// before dialing a new one.
+    ///
+    /// When no connection is idle, a request races waiting for one against
+    /// dialing a new connection, and a dial that loses is kept in the pool.
+    /// Under bursty load, this opens more connections than needed. With a
+    /// delay, while a connection to the same host is in use, the dial only
+    /// starts if no connection became idle in time, trading some latency for
+    /// fewer connections.
+    ///
+    /// A `Timer` is required for this to take effect. See `Builder::pool_timer`
+    ///
+    /// Pass `None` to dial right away.
+    ///
+    /// Default is `None`.
+    pub fn connect_race_delay<D>(&mut self, delay: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.client_config.connect_race_delay = delay.into();
+        self
+    }
+
+    /// Sets the most connections the client uses for requests at once.
+    ///
+    /// Further requests wait for a connection to be free. An HTTP/1
+    /// connection is in use until the response body has been read, and
+    /// each request on an HTTP/2 connection counts until its response
+    /// headers arrive. Idle pooled connections don't count.
+    ///
+    /// This is the backpressure a [`ClientService`] reports in `poll_ready`.
+    ///
+    /// Pass `None` to disable the limit.
+    ///
+    /// Default is `None`.
+    pub fn pool_max_in_use<M>(&mut self, max: M) -> &mut Self
+    where
+        M: Into<Option<usize>>,
+    {
+        self.pool_max_in_use = max.into();
+        self
+    }
+
+    /// Sets the most connections each partition of the client uses for
+    /// requests at once.
+    ///
+    /// This counts connections like [`Builder::pool_max_in_use`], for the
+    /// requests of each [`Client::partition`] on their own, so one partition
+    /// can't take all the connections the client may use. Requests of the
+    /// client itself, outside any partition, aren't limited.
+    ///
+    /// Pass `None` to disable the limit.
+    ///
+    /// Default is `None`.
+    pub fn pool_max_in_use_per_partition<M>(&mut self, max: M) -> &mut Self
+    where
+        M: Into<Option<usize>>,
+    {
+        self.pool_max_in_use_per_partition = max.into();
+        self
+    }
 
-fn is_schema_secure(uri: &Uri) -> bool {
-    uri.scheme_str()
-        .map(|scheme_str| matches!(scheme_str, "wss" | "https"))
-        .unwrap_or_default()
-}
+    /// Sets the most requests that may wait for a connection at once.
+    ///
+    /// A request waits from when it is sent until it gets a connection,
+    /// including while waiting on [`Builder::pool_max_in_use`]. Once the
+    /// queue is full, what happens to a new request is set by
+    /// [`Builder::request_queue_overflow`], and a request that is refused
+    /// fails with an error for which [`Error::is_request_queue_full`]
+    /// returns true.
+    ///
+    /// This bounds what a stalled upstream makes callers hold on to, while
+    /// [`Builder::pool_max_waiters_per_host`] bounds it per host.
+    ///
+    /// Pass `None` to disable the limit.
+    ///
+    /// Default is `None`.
+    pub fn request_queue_max_requests<M>(&mut self, max: M) -> &mut Self
+    where
+        M: Into<Option<usize>>,
+    {
+        self.request_queue.max_requests = max.into();
+        self
+    }
 
-/// A builder to configure a new [`Client`](Client).
-///
-/// # Example
-///
-/// ```
-/// # #[cfg(feature = "tokio")]
-/// # fn run () {
-/// use std::time::Duration;
-/// use hyper_util::client::legacy::Client;
-/// use hyper_util::rt::TokioExecutor;
-///
-/// let client = Client::builder(TokioExecutor::new())
-///     .pool_idle_timeout(Duration::from_secs(30))
-///     .http2_only(true)
-///     .build_http();
-/// # let infer: Client<_, http_body_util::Full<bytes::Bytes>> = client;
-/// # drop(infer);
-/// # }
-/// # fn main() {}
-/// ```
-#[cfg_attr(docsrs, doc(cfg(any(feature = "http1", feature = "http2"))))]
-#[derive(Clone)]
-pub struct Builder {
-    client_config: Config,
-    exec: Exec,
-    #[cfg(feature = "http1")]
-    h1_builder: hyper::client::conn::http1::Builder,
-    #[cfg(feature = "http2")]
-    h2_builder: hyper::client::conn::http2::Builder<Exec>,
-    pool_config: pool::Config,
-    pool_timer: Option<timer::Timer>,
-}
+    /// Sets the most bytes of request bodies that may wait for a
+    /// connection at once.
+    ///
+    /// The bytes of a request are the lower bound of its body's
+    /// `size_hint`, which is the full size of a buffered body. A request
+    /// larger than the limit on its own is always refused. See
+    /// [`Builder::request_queue_max_requests`].
+    ///
+    /// Pass `None` to disable the limit.
+    ///
+    /// Default is `None`.
+    pub fn request_queue_max_bytes<M>(&mut self, max: M) -> &mut Self
+    where
+        M: Into<Option<usize>>,
+    {
+        self.request_queue.max_bytes = max.into();
+        self
+    }
 
-impl Builder {
-    /// Construct a new Builder.
-    pub fn new<E>(executor: E) -> Self
+    /// Sets what happens to a request that would overflow the queue set by
+    /// [`Builder::request_queue_max_requests`] or
+    /// [`Builder::request_queue_max_bytes`].
+    ///
+    /// Default is [`QueueOverflow::Reject`].
+    pub fn request_queue_overflow(&mut self, overflow: QueueOverflow) -> &mut Self {
+        self.request_queue.overflow = overflow;
+        self
+    }
+
+    /// Provide a callback choosing the [`PoolTag`] of requests that don't
+    /// carry one in their extensions.
+    ///
+    /// The callback is given the request's `Uri` and headers. Returning
+    /// `None` sends the request on an untagged connection.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "tokio")]
+    /// # fn run () {
+    /// use hyper_util::client::legacy::{Client, PoolTag};
+    /// use hyper_util::rt::TokioExecutor;
+    ///
+    /// // Keep each session on its own connections.
+    /// let client = Client::builder(TokioExecutor::new())
+    ///     .pool_tagger(|_uri, headers| {
+    ///         headers
+    ///             .get("x-session")
+    ///             .and_then(|v| v.to_str().ok())
+    ///             .map(PoolTag::new)
+    ///     })
+    ///     .build_http();
+    ///
+    /// # let infer: Client<_, http_body_util::Full<bytes::Bytes>> = client;
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn pool_tagger<F>(&mut self, tagger: F) -> &mut Self
     where
-        E: hyper::rt::Executor<BoxSendFuture> + Send + Sync + Clone + 'static,
+        F: Fn(&Uri, &http::HeaderMap) -> Option<PoolTag> + Send + Sync + 'static,
     {
-        let exec = Exec::new(executor);
-        Self {
-            client_config: Config {
-                retry_canceled_requests: true,
-                set_host: true,
-                ver: Ver::Auto,
-            },
-            exec: exec.clone(),
-            #[cfg(feature = "http1")]
-            h1_builder: hyper::client::conn::http1::Builder::new(),
-            #[cfg(feature = "http2")]
-            h2_builder: hyper::client::conn::http2::Builder::new(exec),
-            pool_config: pool::Config {
-                idle_timeout: Some(Duration::from_secs(90)),
-                max_idle_per_host: usize::MAX,
-            },
-            pool_timer: None,
-        }
+        self.pool_tagger = Some(Arc::new(tagger));
+        self
     }
-    /// Set an optional timeout for idle sockets being kept-alive.
-    /// A `Timer` is required for this to take effect. See `Builder::pool_timer`
+
+    /// Provide a callback to sign requests right before they are sent.
     ///
-    /// Pass `None` to disable timeout.
+    /// The callback is given the request as it will be written, once the
+    /// client has set the `Host` header and the form of the `Uri` for the
+    /// connection, so signature schemes like AWS Signature Version 4 can
+    /// sign exactly what is sent. It runs again if the request is retried.
     ///
-    /// Default is 90 seconds.
+    /// The body isn't available, so schemes signing it need a digest of it
+    /// set in a header beforehand. Returning an error fails the request,
+    /// which [`Error::is_request_signer`] tells apart.
     ///
     /// # Example
     ///
     /// ```
     /// # #[cfg(feature = "tokio")]
     /// # fn run () {
-    /// use std::time::Duration;
+    /// use http::HeaderValue;
     /// use hyper_util::client::legacy::Client;
-    /// use hyper_util::rt::{TokioExecutor, TokioTimer};
+    /// use hyper_util::rt::TokioExecutor;
     ///
     /// let client = Client::builder(TokioExecutor::new())
-    ///     .pool_idle_timeout(Duration::from_secs(30))
-    ///     .pool_timer(TokioTimer::new())
+    ///     .request_signer(|parts| {
+    ///         // sign parts.method, parts.uri and parts.headers...
+    ///         parts
+    ///             .headers
+    ///             .insert("x-signature", HeaderValue::from_static("..."));
+    ///         Ok(())
+    ///     })
     ///     .build_http();
     ///
     /// # let infer: Client<_, http_body_util::Full<bytes::Bytes>> = client;
     /// # }
     /// # fn main() {}
     /// ```
-    pub fn pool_idle_timeout<D>(&mut self, val: D) -> &mut Self
+    pub fn request_signer<F>(&mut self, signer: F) -> &mut Self
     where
-        D: Into<Option<Duration>>,
+        F: Fn(&mut http::request::Parts) -> Result<(), Box<dyn StdError + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
     {
-        self.pool_config.idle_timeout = val.into();
+        self.request_signer = Some(Arc::new(signer));
         self
     }
 
-    #[doc(hidden)]
-    #[deprecated(note = "renamed to `pool_max_idle_per_host`")]
-    pub fn max_idle_per_host(&mut self, max_idle: usize) -> &mut Self {
-        self.pool_config.max_idle_per_host = max_idle;
+    /// Set a store of endpoint hints, to carry what the client learned
+    /// about endpoints across process restarts.
+    ///
+    /// The hints are loaded when the client is built. Requests to an
+    /// endpoint known to speak HTTP/2 wait for a single connection to it
+    /// rather than each dialing one, as they do once a connection negotiated
+    /// HTTP/2 with ALPN. Use [`Client::save_hints`] to save them, and
+    /// [`Client::preconnect`] to dial them ahead of the first requests.
+    ///
+    /// Default is no store.
+    pub fn hint_store<S>(&mut self, store: S) -> &mut Self
+    where
+        S: HintStore + 'static,
+    {
+        self.hint_store = Some(Arc::new(store));
         self
     }
 
-    /// Sets the maximum idle connection per host allowed in the pool.
+    /// Set headers to add to every request.
     ///
-    /// Default is `usize::MAX` (no limit).
-    pub fn pool_max_idle_per_host(&mut self, max_idle: usize) -> &mut Self {
-        self.pool_config.max_idle_per_host = max_idle;
+    /// A default header is only added if the request doesn't already have a
+    /// header with the same name. Calling this again adds to the defaults,
+    /// replacing those with the same name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "tokio")]
+    /// # fn run () {
+    /// use http::header::{HeaderMap, HeaderValue, ACCEPT};
+    /// use hyper_util::client::legacy::Client;
+    /// use hyper_util::rt::TokioExecutor;
+    ///
+    /// let mut headers = HeaderMap::new();
+    /// headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    ///
+    /// let client = Client::builder(TokioExecutor::new())
+    ///     .default_headers(headers)
+    ///     .user_agent(HeaderValue::from_static("my-app/1.0"))
+    ///     .build_http();
+    ///
+    /// # let infer: Client<_, http_body_util::Full<bytes::Bytes>> = client;
+    /// # }
+    /// # fn main() {}
+    /// ```
+    pub fn default_headers(&mut self, headers: HeaderMap) -> &mut Self {
+        let mut name = None;
+        for (key, value) in headers {
+            // `None` means another value for the previous name.
+            if let Some(key) = key {
+                self.default_headers.remove(&key);
+                name = Some(key);
+            }
+            let key = name.clone().expect("HeaderMap yields a name first");
+            self.default_headers.append(key, value);
+        }
+        self
+    }
+
+    /// Set the `User-Agent` header to add to every request that doesn't
+    /// have one.
+    ///
+    /// Default is to not send a `User-Agent`.
+    pub fn user_agent(&mut self, value: HeaderValue) -> &mut Self {
+        self.default_headers.insert(USER_AGENT, value);
         self
     }
 
@@ -1067,6 +3114,7 @@ impl Builder {
     #[cfg_attr(docsrs, doc(cfg(feature = "http1")))]
     pub fn http1_read_buf_exact_size(&mut self, sz: usize) -> &mut Self {
         self.h1_builder.read_buf_exact_size(Some(sz));
+        self.memory.h1_read_buf_exact(sz);
         self
     }
 
@@ -1083,6 +3131,7 @@ impl Builder {
     #[cfg_attr(docsrs, doc(cfg(feature = "http1")))]
     pub fn http1_max_buf_size(&mut self, max: usize) -> &mut Self {
         self.h1_builder.max_buf_size(max);
+        self.memory.h1_max_buf(max);
         self
     }
 
@@ -1260,6 +3309,102 @@ impl Builder {
         self
     }
 
+    /// Set the maximum size of the headers of a response.
+    ///
+    /// The size is counted like HTTP/2's `SETTINGS_MAX_HEADER_LIST_SIZE`,
+    /// which is also advertised to HTTP/2 servers: the length of the name and
+    /// value of each field, plus 32 bytes. A response over the limit fails
+    /// with an error for which [`Error::is_response_headers_too_large`]
+    /// returns true.
+    ///
+    /// For HTTP/1, this also sets
+    /// [`http1_max_buf_size`](Builder::http1_max_buf_size) to the limit with
+    /// room for the status line, and at least 8192 bytes, so a larger head
+    /// isn't buffered before being rejected.
+    ///
+    /// Default is `None`, for no limit.
+    pub fn max_response_header_size<M>(&mut self, max: M) -> &mut Self
+    where
+        M: Into<Option<usize>>,
+    {
+        let max = max.into();
+        #[cfg(feature = "http1")]
+        if let Some(max) = max {
+            self.http1_max_buf_size(max.saturating_add(H1_STATUS_LINE_ROOM).max(8192));
+        }
+        #[cfg(feature = "http2")]
+        if let Some(max) = max {
+            self.h2_builder
+                .max_header_list_size(max.min(u32::MAX as usize) as u32);
+        }
+        self.client_config.max_response_header_size = max;
+        self
+    }
+
+    /// Set the maximum number of header fields of a response.
+    ///
+    /// A response with more fails with an error for which
+    /// [`Error::is_response_headers_too_large`] returns true. For HTTP/1,
+    /// this also sets [`http1_max_headers`](Builder::http1_max_headers) to
+    /// one more, so a head with more fields fails to parse, without hyper
+    /// reading them all.
+    ///
+    /// Default is `None`, for no limit.
+    pub fn max_response_headers<M>(&mut self, max: M) -> &mut Self
+    where
+        M: Into<Option<usize>>,
+    {
+        let max = max.into();
+        #[cfg(feature = "http1")]
+        if let Some(max) = max {
+            self.h1_builder.max_headers(max.saturating_add(1));
+        }
+        self.client_config.max_response_headers = max;
+        self
+    }
+
+    /// Set the most a host's `429 Too Many Requests` holds off requests to
+    /// it.
+    ///
+    /// When a response is `429` with a `Retry-After` of a number of
+    /// seconds, later requests to the same host wait that long, up to
+    /// `max`, before taking a connection from the pool or dialing one. The
+    /// response itself is still returned to the caller.
+    ///
+    /// A `Timer` is required for this to take effect. See `Builder::pool_timer`
+    ///
+    /// Pass `None` to disable.
+    ///
+    /// Default is `None`.
+    pub fn pool_retry_after_backoff<D>(&mut self, max: D) -> &mut Self
+    where
+        D: Into<Option<Duration>>,
+    {
+        self.client_config.retry_after_backoff = max.into();
+        self
+    }
+
+    /// Set whether requests to a host are sent one after the other on a
+    /// single HTTP/1 connection.
+    ///
+    /// Requests wait for the one in progress on their host to be done,
+    /// with its response body read, and then take its connection from the
+    /// pool, instead of dialing another connection. This suits origins
+    /// without HTTP/2 that limit connections, or where dialing is slow,
+    /// at the cost of requests queueing behind each other.
+    ///
+    /// Requests aren't pipelined: hyper only writes a request once the
+    /// previous response is done. HTTP/2 connections still take requests
+    /// concurrently, and this has no effect with the pool disabled.
+    ///
+    /// Default is `false`.
+    #[cfg(feature = "http1")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "http1")))]
+    pub fn http1_serial(&mut self, enabled: bool) -> &mut Self {
+        self.client_config.http1_serial = enabled;
+        self
+    }
+
     /// Set whether HTTP/0.9 responses should be tolerated.
     ///
     /// Default is false.
@@ -1277,6 +3422,10 @@ impl Builder {
     /// as part of the connection process. This will not make the `Client`
     /// utilize ALPN by itself.
     ///
+    /// Upgrading an HTTP/1 connection with `Upgrade: h2c` isn't supported:
+    /// hyper's HTTP/2 client can't take over the stream of the request
+    /// that carried the upgrade, so cleartext origins need prior knowledge.
+    ///
     /// Note that setting this to true prevents HTTP/1 from being allowed.
     ///
     /// Default is false.
@@ -1329,7 +3478,11 @@ impl Builder {
         &mut self,
         sz: impl Into<Option<u32>>,
     ) -> &mut Self {
-        self.h2_builder.initial_connection_window_size(sz.into());
+        let sz = sz.into();
+        self.h2_builder.initial_connection_window_size(sz);
+        if let Some(sz) = sz {
+            self.memory.h2_window = sz;
+        }
         self
     }
 
@@ -1362,6 +3515,7 @@ impl Builder {
     #[cfg_attr(docsrs, doc(cfg(feature = "http2")))]
     pub fn http2_adaptive_window(&mut self, enabled: bool) -> &mut Self {
         self.h2_builder.adaptive_window(enabled);
+        self.memory.h2_adaptive = enabled;
         self
     }
 
@@ -1486,6 +3640,7 @@ impl Builder {
     #[cfg_attr(docsrs, doc(cfg(feature = "http2")))]
     pub fn http2_max_send_buf_size(&mut self, max: usize) -> &mut Self {
         self.h2_builder.max_send_buf_size(max);
+        self.memory.h2_send_buf = max;
         self
     }
 
@@ -1518,6 +3673,15 @@ impl Builder {
         self
     }
 
+    /// Set how the host of each request is normalized.
+    ///
+    /// Default is [`HostNormalization::Standard`].
+    #[inline]
+    pub fn host_normalization(&mut self, val: HostNormalization) -> &mut Self {
+        self.client_config.host_normalization = val;
+        self
+    }
+
     /// Build a client with this configuration and the default `HttpConnector`.
     #[cfg(feature = "tokio")]
     pub fn build_http<B>(&self) -> Client<HttpConnector, B>
@@ -1549,7 +3713,49 @@ impl Builder {
             #[cfg(feature = "http2")]
             h2_builder: self.h2_builder.clone(),
             connector,
-            pool: pool::Pool::new(self.pool_config, exec, timer),
+            pool: pool::Pool::new(self.pool_config, exec, timer.clone()),
+            pool_tagger: self.pool_tagger.clone(),
+            request_signer: self.request_signer.clone(),
+            default_headers: if self.default_headers.is_empty() {
+                None
+            } else {
+                Some(Arc::new(self.default_headers.clone()))
+            },
+            memory: memory::Budget::new(self.memory),
+            in_use: self
+                .pool_max_in_use
+                .map(|max| Arc::new(Semaphore::new(max.clamp(1, Semaphore::MAX_PERMITS)))),
+            queue: Queue::new(self.request_queue),
+            serial: if self.client_config.http1_serial && self.pool_config.is_enabled() {
+                Some(SerialHosts::default())
+            } else {
+                None
+            },
+            backoff: self
+                .client_config
+                .retry_after_backoff
+                .map(|_| BackoffHosts::default()),
+            hints: self
+                .hint_store
+                .clone()
+                .map(|store| Arc::new(Hints::load(store))),
+            dns: self
+                .pool_dns_expiry_grace
+                .map(|grace| Arc::new(Answers::new(grace, timer.clone()))),
+            pool_stats: match (&self.pool_stats, &timer) {
+                (Some((interval, reporter)), Some(_)) => Some(Arc::new(PoolStats {
+                    interval: *interval,
+                    reporter: reporter.clone(),
+                    started: AtomicBool::new(false),
+                })),
+                _ => None,
+            },
+            timer,
+            partitions: Arc::new(Partitions {
+                max_in_use: self.pool_max_in_use_per_partition,
+                by_name: Mutex::default(),
+            }),
+            partition: None,
         }
     }
 }
@@ -1559,10 +3765,37 @@ impl fmt::Debug for Builder {
         f.debug_struct("Builder")
             .field("client_config", &self.client_config)
             .field("pool_config", &self.pool_config)
+            .field("default_headers", &self.default_headers)
             .finish()
     }
 }
 
+// ==== impl PoolTag ====
+
+impl PoolTag {
+    /// Create a new tag.
+    pub fn new(tag: impl Into<String>) -> PoolTag {
+        PoolTag(tag.into().into())
+    }
+
+    /// The tag as a string.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&'_ str> for PoolTag {
+    fn from(tag: &str) -> PoolTag {
+        PoolTag(tag.into())
+    }
+}
+
+impl From<String> for PoolTag {
+    fn from(tag: String) -> PoolTag {
+        PoolTag::new(tag)
+    }
+}
+
 // ==== impl Error ====
 
 impl fmt::Debug for Error {
@@ -1594,10 +3827,152 @@ impl Error {
         matches!(self.kind, ErrorKind::Connect)
     }
 
+    /// Returns true if the request was not sent because the connection
+    /// didn't use the protocol required by [`RequireProtocol`].
+    pub fn is_unacceptable_protocol(&self) -> bool {
+        matches!(self.kind, ErrorKind::UserUnacceptableProtocol)
+    }
+
+    /// Returns true if the request was not sent because its host failed
+    /// [`HostNormalization::Strict`].
+    pub fn is_invalid_host(&self) -> bool {
+        matches!(self.kind, ErrorKind::UserInvalidHost)
+    }
+
+    /// Returns true if the request was not sent because its absolute URI
+    /// didn't have the scheme and authority of the base of a
+    /// [`BaseUriClient`].
+    pub fn is_base_uri_mismatch(&self) -> bool {
+        matches!(self.kind, ErrorKind::UserBaseUriMismatch)
+    }
+
+    /// Returns true if the request was not sent because the callback of
+    /// [`Builder::request_signer`] failed.
+    pub fn is_request_signer(&self) -> bool {
+        matches!(self.kind, ErrorKind::RequestSigner)
+    }
+
+    /// Returns true if the request was not sent because too many requests
+    /// were already waiting for a connection to its host, see
+    /// [`Builder::pool_max_waiters_per_host`].
+    pub fn is_pool_queue_full(&self) -> bool {
+        matches!(self.kind, ErrorKind::PoolQueueFull)
+    }
+
+    /// Returns true if the request was not sent because the request queue
+    /// was full, see [`Builder::request_queue_max_requests`] and
+    /// [`Builder::request_queue_max_bytes`].
+    pub fn is_request_queue_full(&self) -> bool {
+        matches!(self.kind, ErrorKind::RequestQueueFull)
+    }
+
+    /// Returns true if the response was rejected for exceeding
+    /// [`Builder::max_response_header_size`] or
+    /// [`Builder::max_response_headers`], or for an HTTP/1 head too large
+    /// to parse.
+    pub fn is_response_headers_too_large(&self) -> bool {
+        matches!(self.kind, ErrorKind::ResponseHeadersTooLarge)
+    }
+
+    /// Returns true if the request was sent as TLS 1.3 early data, which the
+    /// server rejected, so it never saw the request.
+    pub fn is_early_data_rejected(&self) -> bool {
+        matches!(self.kind, ErrorKind::EarlyDataRejected)
+    }
+
+    /// Returns true if the request may be sent again.
+    ///
+    /// This is the case when the request wasn't sent, because connecting
+    /// failed or the connection closed first, when the server rejected it
+    /// as early data, and when the connection failed before a response
+    /// arrived. Whether retrying is then safe
+    /// still depends on the request being idempotent.
+    pub fn is_retryable(&self) -> bool {
+        match self.kind {
+            ErrorKind::Canceled
+            | ErrorKind::ChannelClosed
+            | ErrorKind::Connect
+            | ErrorKind::EarlyDataRejected => true,
+            ErrorKind::SendRequest => self.sources().any(|err| {
+                if let Some(err) = err.downcast_ref::<hyper::Error>() {
+                    return err.is_canceled() || err.is_incomplete_message();
+                }
+                if let Some(err) = err.downcast_ref::<std::io::Error>() {
+                    return matches!(
+                        err.kind(),
+                        std::io::ErrorKind::ConnectionReset
+                            | std::io::ErrorKind::ConnectionAborted
+                            | std::io::ErrorKind::BrokenPipe
+                    );
+                }
+                false
+            }),
+            _ => false,
+        }
+    }
+
+    /// Returns true if a timeout caused this error, such as the connect
+    /// timeout of the [`HttpConnector`].
+    pub fn is_timeout(&self) -> bool {
+        self.sources().any(|err| {
+            if let Some(err) = err.downcast_ref::<hyper::Error>() {
+                return err.is_timeout();
+            }
+            if let Some(err) = err.downcast_ref::<std::io::Error>() {
+                return err.kind() == std::io::ErrorKind::TimedOut;
+            }
+            false
+        })
+    }
+
+    /// The address of the endpoint that failed, if known.
+    ///
+    /// For connect errors of the [`HttpConnector`], this is the last
+    /// address tried, see [`ConnectFailures`](super::connect::ConnectFailures)
+    /// for all of them. For errors of a connection, it is the remote
+    /// address of its [`HttpInfo`](super::connect::HttpInfo).
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        if self.remote_addr.is_some() {
+            return self.remote_addr;
+        }
+        #[cfg(feature = "tokio")]
+        for err in self.sources() {
+            if let Some(failures) = err.downcast_ref::<super::connect::ConnectFailures>() {
+                return failures.attempts().last().map(|attempt| attempt.addr());
+            }
+        }
+        None
+    }
+
+    fn sources(&self) -> impl Iterator<Item = &(dyn StdError + 'static)> {
+        std::iter::successors(self.source(), |&err| err.source())
+    }
+
+    #[cfg_attr(not(feature = "tokio"), allow(unused_variables))]
+    fn with_remote_addr(mut self, extra: Option<&super::connect::Extra>) -> Self {
+        #[cfg(feature = "tokio")]
+        if let Some(extra) = extra {
+            let mut extensions = http::Extensions::new();
+            extra.set(&mut extensions);
+            self.remote_addr = extensions
+                .get::<super::connect::HttpInfo>()
+                .map(|info| info.remote_addr());
+        }
+        self
+    }
+
     fn is_canceled(&self) -> bool {
         matches!(self.kind, ErrorKind::Canceled)
     }
 
+    fn checkout(src: pool::Error) -> Self {
+        if src.is_pool_queue_full() {
+            e!(PoolQueueFull, src)
+        } else {
+            e!(Connect, src)
+        }
+    }
+
     fn tx(src: hyper::Error) -> Self {
         e!(SendRequest, src)
     }
@@ -0,0 +1,441 @@
+//! Compression of request bodies.
+//!
+//! This module provides a [`CompressionLayer`], which compresses the bodies
+//! of the requests sent through a `Client` with gzip or zstd, and sets
+//! their `Content-Encoding`.
+//!
+//! The server must accept the encoding, which a client usually knows in
+//! advance, since HTTP has no way to negotiate it for requests.
+//!
+//! # Example
+//!
+//! ```
+//! # #[cfg(feature = "tokio")]
+//! # fn run() {
+//! use bytes::Bytes;
+//! use http_body_util::Full;
+//! use hyper_util::client::legacy::compression::{CompressionBody, CompressionLayer};
+//! use hyper_util::client::legacy::Client;
+//! use hyper_util::rt::TokioExecutor;
+//! use tower::{Layer, ServiceExt};
+//!
+//! let client: Client<_, CompressionBody<Full<Bytes>>> =
+//!     Client::builder(TokioExecutor::new()).build_http();
+//! let client = CompressionLayer::zstd().min_size(4096).layer(client);
+//!
+//! let req = http::Request::post("http://example.local/upload")
+//!     .body(Full::new(Bytes::from("a large document")))
+//!     .unwrap();
+//! let future = client.oneshot(req);
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use bytes::{Buf, Bytes};
+use futures_util::ready;
+use http::header::{self, HeaderValue};
+use http::Request;
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// A `Layer` compressing the bodies of requests.
+///
+/// A request is compressed unless:
+///
+/// - it already has a `Content-Encoding`, or
+/// - its body is known to be smaller than
+///   [`min_size`](CompressionLayer::min_size), from its `Content-Length`
+///   header or its size hint.
+///
+/// A compressed request loses its `Content-Length`, so HTTP/1 sends it
+/// chunked. The body is compressed as it is sent, without being buffered,
+/// and output is sent as the encoder produces it.
+#[derive(Clone, Debug)]
+pub struct CompressionLayer {
+    encoding: Encoding,
+    min_size: u64,
+}
+
+/// A `Service` created by a [`CompressionLayer`].
+#[derive(Clone, Debug)]
+pub struct CompressionService<C> {
+    inner: C,
+    layer: CompressionLayer,
+}
+
+pin_project! {
+    /// A request body, compressed if the request was.
+    pub struct CompressionBody<B> {
+        #[pin]
+        inner: B,
+        encoder: Option<Encoder>,
+        trailers: Option<http::HeaderMap>,
+        done: bool,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Zstd,
+}
+
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+// ===== impl CompressionLayer =====
+
+impl CompressionLayer {
+    /// Create a layer compressing requests with gzip.
+    pub fn gzip() -> CompressionLayer {
+        CompressionLayer::new(Encoding::Gzip)
+    }
+
+    /// Create a layer compressing requests with zstd.
+    pub fn zstd() -> CompressionLayer {
+        CompressionLayer::new(Encoding::Zstd)
+    }
+
+    fn new(encoding: Encoding) -> CompressionLayer {
+        CompressionLayer {
+            encoding,
+            min_size: 1024,
+        }
+    }
+
+    /// Set the size in bytes below which a request isn't compressed.
+    ///
+    /// Requests of unknown size are compressed.
+    ///
+    /// Default is 1024 bytes.
+    pub fn min_size(mut self, bytes: u64) -> CompressionLayer {
+        self.min_size = bytes;
+        self
+    }
+
+    fn is_compressible<B: Body>(&self, req: &Request<B>) -> bool {
+        let headers = req.headers();
+        if headers.contains_key(header::CONTENT_ENCODING) || req.body().is_end_stream() {
+            return false;
+        }
+        let size = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .or_else(|| req.body().size_hint().exact());
+        !matches!(size, Some(size) if size < self.min_size)
+    }
+}
+
+impl<C> tower::Layer<C> for CompressionLayer {
+    type Service = CompressionService<C>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        CompressionService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+// ===== impl CompressionService =====
+
+impl<C> CompressionService<C> {
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C, B> tower_service::Service<Request<B>> for CompressionService<C>
+where
+    C: tower_service::Service<Request<CompressionBody<B>>>,
+    B: Body,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let encoder = if self.layer.is_compressible(&req) {
+            Encoder::new(self.layer.encoding).ok()
+        } else {
+            None
+        };
+        let (mut parts, body) = req.into_parts();
+        if encoder.is_some() {
+            parts
+                .headers
+                .insert(header::CONTENT_ENCODING, self.layer.encoding.header_value());
+            parts.headers.remove(header::CONTENT_LENGTH);
+        }
+        let body = CompressionBody {
+            inner: body,
+            encoder,
+            trailers: None,
+            done: false,
+        };
+        self.inner.call(Request::from_parts(parts, body))
+    }
+}
+
+// ===== impl CompressionBody =====
+
+impl<B> CompressionBody<B> {
+    /// Wrap a body, without compressing it.
+    ///
+    /// This allows sending requests that don't go through the layer with
+    /// the same `Client`.
+    pub fn identity(inner: B) -> Self {
+        CompressionBody {
+            inner,
+            encoder: None,
+            trailers: None,
+            done: false,
+        }
+    }
+
+    /// Whether the body is compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.encoder.is_some() || self.done
+    }
+}
+
+impl<B> Body for CompressionBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        loop {
+            if let Some(trailers) = this.trailers.take() {
+                return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+            }
+            if *this.done {
+                return Poll::Ready(None);
+            }
+            let encoder = match this.encoder {
+                Some(encoder) => encoder,
+                None => {
+                    return this.inner.poll_frame(cx).map(|frame| {
+                        frame.map(|frame| {
+                            frame
+                                .map(|frame| frame.map_data(|mut d| d.copy_to_bytes(d.remaining())))
+                                .map_err(Into::into)
+                        })
+                    });
+                }
+            };
+
+            match ready!(this.inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        let out = encoder.write(data)?;
+                        if !out.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(out))));
+                        }
+                        continue;
+                    }
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            *this.trailers = Some(trailers);
+                        }
+                    }
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                None => {}
+            }
+
+            // the body ended, or trailers are next
+            *this.done = true;
+            let out = this.encoder.take().expect("encoder").finish()?;
+            if !out.is_empty() {
+                return Poll::Ready(Some(Ok(Frame::data(out))));
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        if self.encoder.is_none() && !self.done {
+            self.inner.is_end_stream()
+        } else {
+            self.done && self.trailers.is_none()
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.encoder.is_none() && !self.done {
+            self.inner.size_hint()
+        } else {
+            SizeHint::default()
+        }
+    }
+}
+
+impl<B> fmt::Debug for CompressionBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompressionBody")
+            .field("compressed", &self.is_compressed())
+            .finish()
+    }
+}
+
+// ===== impl Encoding =====
+
+impl Encoding {
+    fn header_value(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Zstd => "zstd",
+        })
+    }
+}
+
+// ===== impl Encoder =====
+
+impl Encoder {
+    fn new(encoding: Encoding) -> io::Result<Encoder> {
+        Ok(match encoding {
+            Encoding::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            Encoding::Zstd => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?)
+            }
+        })
+    }
+
+    /// Compress a chunk, returning what the encoder output so far.
+    ///
+    /// Unlike response compression, chunks aren't flushed, since nothing
+    /// waits on an upload's partial output, and flushing hurts the ratio.
+    fn write<D: Buf>(&mut self, mut data: D) -> io::Result<Bytes> {
+        let writer: &mut dyn Write = match self {
+            Encoder::Gzip(e) => e,
+            Encoder::Zstd(e) => e,
+        };
+        while data.has_remaining() {
+            let chunk = data.chunk();
+            writer.write_all(chunk)?;
+            let len = chunk.len();
+            data.advance(len);
+        }
+
+        let out = match self {
+            Encoder::Gzip(e) => e.get_mut(),
+            Encoder::Zstd(e) => e.get_mut(),
+        };
+        Ok(Bytes::from(std::mem::take(out)))
+    }
+
+    /// End the stream, returning the rest of the output.
+    fn finish(self) -> io::Result<Bytes> {
+        let out = match self {
+            Encoder::Gzip(e) => e.finish()?,
+            Encoder::Zstd(e) => e.finish()?,
+        };
+        Ok(Bytes::from(out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::io::Read;
+
+    use bytes::Bytes;
+    use http::{header, Request};
+    use http_body::Frame;
+    use http_body_util::{BodyExt, Full, StreamBody};
+    use tower::{Layer, ServiceExt};
+
+    use super::{CompressionBody, CompressionLayer};
+
+    async fn send<B>(layer: CompressionLayer, req: Request<B>) -> (Option<String>, bool, Bytes)
+    where
+        B: http_body::Body<Data = Bytes, Error = Infallible>,
+    {
+        let service = tower::service_fn(|req: Request<CompressionBody<B>>| async move {
+            let encoding = req
+                .headers()
+                .get(header::CONTENT_ENCODING)
+                .map(|v| v.to_str().unwrap().to_owned());
+            let has_length = req.headers().contains_key(header::CONTENT_LENGTH);
+            let body = req.into_body().collect().await?.to_bytes();
+            Ok::<_, super::BoxError>((encoding, has_length, body))
+        });
+        layer.layer(service).oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn compresses_large_bodies() {
+        let text = "hello, ".repeat(1000);
+
+        let req = Request::post("/")
+            .header(header::CONTENT_LENGTH, text.len())
+            .body(Full::new(Bytes::from(text.clone())))
+            .unwrap();
+        let (encoding, has_length, body) = send(CompressionLayer::gzip(), req).await;
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        assert!(!has_length);
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+
+        let chunks = text
+            .as_bytes()
+            .chunks(100)
+            .map(|c| Ok::<_, Infallible>(Frame::data(Bytes::copy_from_slice(c))))
+            .collect::<Vec<_>>();
+        let req = Request::post("/")
+            .body(StreamBody::new(futures_util::stream::iter(chunks)))
+            .unwrap();
+        let (encoding, _, body) = send(CompressionLayer::zstd(), req).await;
+        assert_eq!(encoding.as_deref(), Some("zstd"));
+        assert_eq!(zstd::decode_all(&body[..]).unwrap(), text.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn skips_small_and_encoded() {
+        let req = Request::post("/")
+            .body(Full::new(Bytes::from("hello")))
+            .unwrap();
+        let (encoding, _, body) = send(CompressionLayer::gzip(), req).await;
+        assert_eq!(encoding, None);
+        assert_eq!(body, "hello");
+
+        let req = Request::post("/")
+            .header(header::CONTENT_ENCODING, "br")
+            .body(Full::new(Bytes::from("x".repeat(2048))))
+            .unwrap();
+        let (encoding, _, body) = send(CompressionLayer::gzip().min_size(0), req).await;
+        assert_eq!(encoding.as_deref(), Some("br"));
+        assert_eq!(body.len(), 2048);
+    }
+}
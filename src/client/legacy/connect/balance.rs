@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::common::rand::random_u64;
+
+/// How an [`HttpConnector`](super::HttpConnector) orders the addresses a
+/// host resolves to.
+///
+/// The connector tries addresses in order, so with the default of keeping
+/// the resolver's order, every new connection goes to the same address as
+/// long as it works. The other strategies spread connections over all of
+/// the addresses:
+///
+/// - [`round_robin`](LoadBalance::round_robin) starts each connection to a
+///   host at the next address.
+/// - [`random`](LoadBalance::random) shuffles the addresses.
+/// - [`weighted`](LoadBalance::weighted) shuffles them, preferring those
+///   with a higher weight.
+///
+/// Addresses after the first are still tried as fallbacks, and happy
+/// eyeballs still applies between IPv6 and IPv4.
+///
+/// The round-robin position is shared between clones.
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::connect::{HttpConnector, LoadBalance};
+///
+/// let mut connector = HttpConnector::new();
+/// connector.set_load_balance(LoadBalance::round_robin());
+/// ```
+#[derive(Clone)]
+pub struct LoadBalance {
+    kind: Kind,
+}
+
+#[derive(Clone)]
+enum Kind {
+    Resolved,
+    RoundRobin(Arc<Mutex<HashMap<String, usize>>>),
+    Random,
+    Weighted(Arc<dyn Fn(&SocketAddr) -> u32 + Send + Sync>),
+}
+
+impl LoadBalance {
+    /// Keep the order of the resolver.
+    ///
+    /// This is the default.
+    pub fn resolved() -> LoadBalance {
+        LoadBalance {
+            kind: Kind::Resolved,
+        }
+    }
+
+    /// Rotate the addresses, so each connection to a host starts with the
+    /// next one.
+    pub fn round_robin() -> LoadBalance {
+        LoadBalance {
+            kind: Kind::RoundRobin(Arc::default()),
+        }
+    }
+
+    /// Shuffle the addresses for each connection.
+    pub fn random() -> LoadBalance {
+        LoadBalance { kind: Kind::Random }
+    }
+
+    /// Shuffle the addresses for each connection, so an address is first
+    /// in proportion to its weight.
+    ///
+    /// Addresses with a weight of `0` are only tried after all others.
+    pub fn weighted<F>(weight: F) -> LoadBalance
+    where
+        F: Fn(&SocketAddr) -> u32 + Send + Sync + 'static,
+    {
+        LoadBalance {
+            kind: Kind::Weighted(Arc::new(weight)),
+        }
+    }
+
+    pub(super) fn order(&self, host: &str, port: u16, addrs: &mut [SocketAddr]) {
+        if addrs.len() < 2 {
+            return;
+        }
+        match self.kind {
+            Kind::Resolved => (),
+            Kind::RoundRobin(ref next) => {
+                let mut next = next.lock().unwrap();
+                let next = next.entry(format!("{}:{}", host, port)).or_insert(0);
+                addrs.rotate_left(*next % addrs.len());
+                *next = next.wrapping_add(1);
+            }
+            Kind::Random => {
+                for i in (1..addrs.len()).rev() {
+                    addrs.swap(i, (random_u64() % (i as u64 + 1)) as usize);
+                }
+            }
+            Kind::Weighted(ref weight) => {
+                // Sorting by an exponential variable with rate `weight`
+                // picks each address first in proportion to its weight.
+                let mut keyed = addrs
+                    .iter()
+                    .map(|addr| {
+                        let key = match weight(addr) {
+                            0 => f64::INFINITY,
+                            w => -unit().ln() / w as f64,
+                        };
+                        (key, *addr)
+                    })
+                    .collect::<Vec<_>>();
+                keyed.sort_by(|a, b| a.0.total_cmp(&b.0));
+                for (slot, (_, addr)) in addrs.iter_mut().zip(keyed) {
+                    *slot = addr;
+                }
+            }
+        }
+    }
+}
+
+impl Default for LoadBalance {
+    fn default() -> LoadBalance {
+        LoadBalance::resolved()
+    }
+}
+
+impl fmt::Debug for LoadBalance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.kind {
+            Kind::Resolved => "Resolved",
+            Kind::RoundRobin(_) => "RoundRobin",
+            Kind::Random => "Random",
+            Kind::Weighted(_) => "Weighted",
+        };
+        f.debug_tuple("LoadBalance").field(&name).finish()
+    }
+}
+
+// A random number in (0, 1].
+fn unit() -> f64 {
+    ((random_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::LoadBalance;
+
+    fn addrs() -> Vec<SocketAddr> {
+        (1..=3)
+            .map(|i| SocketAddr::from(([10, 0, 0, i], 80)))
+            .collect()
+    }
+
+    #[test]
+    fn round_robin_per_host() {
+        let lb = LoadBalance::round_robin();
+        let clone = lb.clone();
+        let firsts = (0..4)
+            .map(|i| {
+                let mut addrs = addrs();
+                let lb = if i % 2 == 0 { &lb } else { &clone };
+                lb.order("a.local", 80, &mut addrs);
+                addrs[0].ip().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(firsts, ["10.0.0.1", "10.0.0.2", "10.0.0.3", "10.0.0.1"]);
+
+        // another host has its own position
+        let mut addrs = addrs();
+        lb.order("b.local", 80, &mut addrs);
+        assert_eq!(addrs[0].ip().to_string(), "10.0.0.1");
+    }
+
+    #[test]
+    fn shuffles_keep_every_addr() {
+        let zero = SocketAddr::from(([10, 0, 0, 1], 80));
+        let weighted = LoadBalance::weighted(move |addr| if *addr == zero { 0 } else { 1 });
+        for lb in [LoadBalance::random(), weighted] {
+            let mut seen_first = std::collections::HashSet::new();
+            for _ in 0..64 {
+                let mut shuffled = addrs();
+                lb.order("a.local", 80, &mut shuffled);
+                seen_first.insert(shuffled[0]);
+                shuffled.sort();
+                assert_eq!(shuffled, addrs());
+            }
+            assert!(seen_first.len() > 1, "{:?} never changed order", lb);
+            if let super::Kind::Weighted(_) = lb.kind {
+                assert!(!seen_first.contains(&zero));
+            }
+        }
+    }
+}
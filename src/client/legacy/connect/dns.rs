@@ -5,6 +5,7 @@
 //! - A [`GaiResolver`](GaiResolver) that is the default resolver for the
 //!   `HttpConnector`.
 //! - The `Name` type used as an argument to custom resolvers.
+//! - A [`ServiceResolver`] to look up `SRV` and `HTTPS` records first.
 //!
 //! # Resolvers are `Service`s
 //!
@@ -34,6 +35,9 @@ use tower_service::Service;
 use tracing::debug;
 
 pub(super) use self::sealed::Resolve;
+pub use self::service::{ServiceRecord, ServiceResolver};
+
+mod service;
 
 /// A domain name to resolve into IP addresses.
 #[derive(Clone, Hash, Eq, PartialEq)]
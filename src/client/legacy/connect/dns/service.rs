@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use bytes::Bytes;
+use tracing::debug;
+
+use super::{resolve, Name, Resolve};
+use crate::common::rand::random_u64;
+
+type BoxError = Box<dyn Error + Send + Sync>;
+type BoxFuture = Pin<Box<dyn Future<Output = Result<Vec<ServiceRecord>, BoxError>> + Send>>;
+
+// The number of addresses to remember hints for.
+const MAX_HINTS: usize = 1024;
+
+/// A `SRV` or `HTTPS` (SVCB) record, naming an endpoint for a service.
+///
+/// Records are returned by the function of a [`ServiceResolver`]; decoding
+/// them from DNS is left to that function, such as with a DNS client
+/// library, or a service registry like Consul.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ServiceRecord {
+    priority: u16,
+    weight: u16,
+    target: Option<Name>,
+    port: Option<u16>,
+    alpn: Vec<Vec<u8>>,
+    ech_config: Option<Bytes>,
+    ip_hints: Vec<IpAddr>,
+}
+
+/// Resolves service records for an [`HttpConnector`](super::super::HttpConnector)
+/// before falling back to its address resolver.
+///
+/// For each connect, the function is called with the host of the
+/// destination. Records are tried in order of priority, choosing between
+/// records of the same priority randomly by weight. The target of each is
+/// resolved with the connector's resolver, unless the record has IP hints.
+/// If the function fails or returns no records, or none of the targets
+/// resolve, the host itself is resolved as usual.
+///
+/// The ALPN and ECH parameters of `HTTPS` records can't be used by the TCP
+/// connector itself. A TLS connector wrapping it can find the record a
+/// connection was made for with [`ServiceResolver::record_for`].
+///
+/// The resolver is shared between clones.
+///
+/// # Example
+///
+/// ```
+/// use std::convert::Infallible;
+/// use hyper_util::client::legacy::connect::dns::{ServiceRecord, ServiceResolver};
+/// use hyper_util::client::legacy::connect::HttpConnector;
+///
+/// let services = ServiceResolver::new(|name| async move {
+///     // Look up `_https._tcp.{name}` here.
+///     let target = format!("backend.{}", name).parse().unwrap();
+///     Ok::<_, Infallible>(vec![ServiceRecord::srv(10, 5, 8443, target)])
+/// });
+/// let mut connector = HttpConnector::new();
+/// connector.set_service_resolver(Some(services));
+/// ```
+#[derive(Clone)]
+pub struct ServiceResolver {
+    resolve: Arc<dyn Fn(Name) -> BoxFuture + Send + Sync>,
+    records: Arc<Mutex<HashMap<SocketAddr, Arc<ServiceRecord>>>>,
+}
+
+impl ServiceRecord {
+    /// Create a `SRV` record.
+    ///
+    /// Records with a lower priority are tried first, and a higher weight
+    /// makes a record more likely to be tried first among those with the
+    /// same priority.
+    pub fn srv(priority: u16, weight: u16, port: u16, target: Name) -> ServiceRecord {
+        ServiceRecord {
+            priority,
+            weight,
+            target: Some(target),
+            port: Some(port),
+            alpn: Vec::new(),
+            ech_config: None,
+            ip_hints: Vec::new(),
+        }
+    }
+
+    /// Create an `HTTPS` record.
+    ///
+    /// A `target` of `None` is the record's own name, written as `.` in
+    /// DNS. Records with a lower priority are tried first; records of the
+    /// same priority are equally likely to be tried first, and a record
+    /// with priority `0` is treated as an alias of `target`.
+    pub fn https(priority: u16, target: Option<Name>) -> ServiceRecord {
+        ServiceRecord {
+            priority,
+            weight: 1,
+            target,
+            port: None,
+            alpn: Vec::new(),
+            ech_config: None,
+            ip_hints: Vec::new(),
+        }
+    }
+
+    /// Set the port of an `HTTPS` record.
+    pub fn with_port(mut self, port: u16) -> ServiceRecord {
+        self.port = Some(port);
+        self
+    }
+
+    /// Set the ALPN protocol IDs from the `alpn` parameter.
+    pub fn with_alpn(mut self, alpn: Vec<Vec<u8>>) -> ServiceRecord {
+        self.alpn = alpn;
+        self
+    }
+
+    /// Set the `ECHConfigList` from the `ech` parameter.
+    pub fn with_ech_config(mut self, ech_config: Bytes) -> ServiceRecord {
+        self.ech_config = Some(ech_config);
+        self
+    }
+
+    /// Set the addresses from the `ipv4hint` and `ipv6hint` parameters,
+    /// which are connected to without resolving the target.
+    pub fn with_ip_hints(mut self, ip_hints: Vec<IpAddr>) -> ServiceRecord {
+        self.ip_hints = ip_hints;
+        self
+    }
+
+    /// The priority of this record.
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// The weight of this record.
+    pub fn weight(&self) -> u16 {
+        self.weight
+    }
+
+    /// The target name, or `None` for the name the record was found at.
+    pub fn target(&self) -> Option<&Name> {
+        self.target.as_ref()
+    }
+
+    /// The port to connect to, if not the port of the destination.
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// The advertised ALPN protocol IDs.
+    pub fn alpn(&self) -> &[Vec<u8>] {
+        &self.alpn
+    }
+
+    /// The advertised `ECHConfigList`.
+    pub fn ech_config(&self) -> Option<&Bytes> {
+        self.ech_config.as_ref()
+    }
+
+    /// The advertised IP addresses.
+    pub fn ip_hints(&self) -> &[IpAddr] {
+        &self.ip_hints
+    }
+}
+
+impl ServiceResolver {
+    /// Create a resolver calling `resolve` with the host of each connect.
+    pub fn new<F, R, E>(resolve: F) -> ServiceResolver
+    where
+        F: Fn(Name) -> R + Send + Sync + 'static,
+        R: Future<Output = Result<Vec<ServiceRecord>, E>> + Send + 'static,
+        E: Into<BoxError>,
+    {
+        ServiceResolver {
+            resolve: Arc::new(move |name| {
+                let fut = resolve(name);
+                Box::pin(async move { fut.await.map_err(Into::into) })
+            }),
+            records: Arc::default(),
+        }
+    }
+
+    /// The record that `addr` was resolved from, if any.
+    ///
+    /// This is meant to be called with the peer address of a connection
+    /// made by the connector, to use the record's parameters.
+    pub fn record_for(&self, addr: &SocketAddr) -> Option<Arc<ServiceRecord>> {
+        self.records.lock().unwrap().get(addr).cloned()
+    }
+
+    // Resolve the addresses of the records for `name`, in the order to try
+    // them, or `None` to fall back to resolving `name` itself.
+    pub(in crate::client::legacy::connect) async fn resolve_addrs<R: Resolve>(
+        &self,
+        resolver: &mut R,
+        name: &Name,
+        port: u16,
+    ) -> Option<Vec<SocketAddr>> {
+        let records = match (self.resolve)(name.clone()).await {
+            Ok(records) => records,
+            Err(err) => {
+                debug!("service records for {} failed: {}", name, err);
+                return None;
+            }
+        };
+
+        let mut addrs = Vec::new();
+        for record in order(records) {
+            let record = Arc::new(record);
+            let port = record.port.unwrap_or(port);
+            let found = if !record.ip_hints.is_empty() {
+                record
+                    .ip_hints
+                    .iter()
+                    .map(|ip| SocketAddr::new(*ip, port))
+                    .collect::<Vec<_>>()
+            } else {
+                let target = record.target.clone().unwrap_or_else(|| name.clone());
+                match resolve(resolver, target).await {
+                    Ok(found) => found
+                        .map(|mut addr| {
+                            addr.set_port(port);
+                            addr
+                        })
+                        .collect(),
+                    Err(err) => {
+                        debug!("service target {:?} failed: {}", record.target, err.into());
+                        continue;
+                    }
+                }
+            };
+
+            let mut records = self.records.lock().unwrap();
+            if records.len() + found.len() > MAX_HINTS {
+                records.clear();
+            }
+            for addr in &found {
+                records.insert(*addr, record.clone());
+            }
+            addrs.extend(found);
+        }
+
+        if addrs.is_empty() {
+            None
+        } else {
+            Some(addrs)
+        }
+    }
+}
+
+impl fmt::Debug for ServiceResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("ServiceResolver")
+    }
+}
+
+// Order by priority, then randomly by weight, as described in RFC 2782.
+fn order(records: Vec<ServiceRecord>) -> Vec<ServiceRecord> {
+    let mut keyed = records
+        .into_iter()
+        .map(|record| {
+            // An exponential variable with rate `weight`, so records are
+            // first in proportion to their weight. Weight `0` goes last.
+            let unit = ((random_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64;
+            let key = match record.weight {
+                0 => f64::INFINITY,
+                weight => -unit.ln() / weight as f64,
+            };
+            (record.priority, key, record)
+        })
+        .collect::<Vec<_>>();
+    keyed.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    keyed.into_iter().map(|(_, _, record)| record).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{order, ServiceRecord};
+
+    #[test]
+    fn orders_by_priority_then_weight() {
+        let name = |s: &str| s.parse().unwrap();
+        for _ in 0..16 {
+            let ordered = order(vec![
+                ServiceRecord::srv(20, 1, 80, name("c")),
+                ServiceRecord::srv(10, 0, 80, name("b")),
+                ServiceRecord::srv(10, 1, 80, name("a")),
+            ]);
+            let targets = ordered
+                .iter()
+                .map(|r| r.target().unwrap().as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(targets, ["a", "b", "c"]);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::net::{SocketAddr, SocketAddrV6};
+
+/// Which IP address families the `HttpConnector` connects with.
+///
+/// This applies to the addresses a host resolves to, and to IP address
+/// literals in the destination.
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::connect::{HttpConnector, IpFamily};
+///
+/// let mut connector = HttpConnector::new();
+/// connector.set_ip_family(IpFamily::PreferV4);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum IpFamily {
+    /// Use both families, preferring the family of the first resolved
+    /// address.
+    #[default]
+    Any,
+    /// Only connect to IPv4 addresses.
+    V4Only,
+    /// Only connect to IPv6 addresses.
+    V6Only,
+    /// Use both families, trying IPv4 addresses first.
+    PreferV4,
+    /// Use both families, trying IPv6 addresses first.
+    PreferV6,
+    /// Only connect over IPv6, reaching IPv4 addresses through their
+    /// IPv4-mapped IPv6 address (`::ffff:a.b.c.d`).
+    ///
+    /// This is for hosts with dual-stack sockets that must not open IPv4
+    /// sockets.
+    V6Mapped,
+}
+
+/// No address of the allowed family, found in the source of the
+/// `HttpConnector`'s error.
+#[derive(Debug)]
+pub(super) struct NoAddress {
+    family: IpFamily,
+    resolved: usize,
+}
+
+impl IpFamily {
+    /// Filter and order resolved addresses by this policy.
+    pub(super) fn apply(self, mut addrs: Vec<SocketAddr>) -> Result<Vec<SocketAddr>, NoAddress> {
+        let resolved = addrs.len();
+        match self {
+            IpFamily::Any => {}
+            IpFamily::V4Only => addrs.retain(SocketAddr::is_ipv4),
+            IpFamily::V6Only => addrs.retain(SocketAddr::is_ipv6),
+            // Sorting is stable, so each family keeps its order.
+            IpFamily::PreferV4 => addrs.sort_by_key(SocketAddr::is_ipv6),
+            IpFamily::PreferV6 => addrs.sort_by_key(SocketAddr::is_ipv4),
+            IpFamily::V6Mapped => {
+                for addr in &mut addrs {
+                    if let SocketAddr::V4(v4) = *addr {
+                        let ip = v4.ip().to_ipv6_mapped();
+                        *addr = SocketAddr::V6(SocketAddrV6::new(ip, v4.port(), 0, 0));
+                    }
+                }
+            }
+        }
+        if addrs.is_empty() && resolved > 0 {
+            return Err(NoAddress {
+                family: self,
+                resolved,
+            });
+        }
+        Ok(addrs)
+    }
+}
+
+impl fmt::Display for NoAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let family = match self.family {
+            IpFamily::V4Only => "IPv4",
+            _ => "IPv6",
+        };
+        write!(
+            f,
+            "none of the {} resolved addresses are {}",
+            self.resolved, family
+        )
+    }
+}
+
+impl StdError for NoAddress {}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::IpFamily;
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec![
+            "[::1]:80".parse().unwrap(),
+            "127.0.0.1:80".parse().unwrap(),
+            "[::2]:80".parse().unwrap(),
+            "127.0.0.2:80".parse().unwrap(),
+        ]
+    }
+
+    fn apply(family: IpFamily) -> Vec<String> {
+        family
+            .apply(addrs())
+            .unwrap()
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn filters_and_orders() {
+        assert_eq!(
+            apply(IpFamily::Any),
+            ["[::1]:80", "127.0.0.1:80", "[::2]:80", "127.0.0.2:80"]
+        );
+        assert_eq!(apply(IpFamily::V4Only), ["127.0.0.1:80", "127.0.0.2:80"]);
+        assert_eq!(apply(IpFamily::V6Only), ["[::1]:80", "[::2]:80"]);
+        assert_eq!(
+            apply(IpFamily::PreferV4),
+            ["127.0.0.1:80", "127.0.0.2:80", "[::1]:80", "[::2]:80"]
+        );
+        assert_eq!(
+            apply(IpFamily::PreferV6),
+            ["[::1]:80", "[::2]:80", "127.0.0.1:80", "127.0.0.2:80"]
+        );
+        assert_eq!(
+            apply(IpFamily::V6Mapped),
+            [
+                "[::1]:80",
+                "[::ffff:127.0.0.1]:80",
+                "[::2]:80",
+                "[::ffff:127.0.0.2]:80"
+            ]
+        );
+    }
+
+    #[test]
+    fn no_address_of_family() {
+        let v6 = vec!["[::1]:80".parse().unwrap()];
+        let err = IpFamily::V4Only.apply(v6).unwrap_err();
+        assert_eq!(err.to_string(), "none of the 1 resolved addresses are IPv4");
+    }
+}
@@ -4,10 +4,12 @@ use std::future::Future;
 use std::io;
 use std::marker::PhantomData;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::RangeInclusive;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{self, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures_util::future::Either;
 use http::uri::{Scheme, Uri};
@@ -15,10 +17,11 @@ use pin_project_lite::pin_project;
 use socket2::TcpKeepalive;
 use tokio::net::{TcpSocket, TcpStream};
 use tokio::time::Sleep;
-use tracing::{debug, trace, warn};
+use tracing::{debug, trace, warn, Instrument};
 
 use super::dns::{self, resolve, GaiResolver, Resolve};
-use super::{Connected, Connection};
+use super::{Connected, Connection, IpFamily, LoadBalance, NegativeCache};
+use crate::client::legacy::spans;
 use crate::rt::TokioIo;
 
 /// A connector for the `http` scheme.
@@ -77,8 +80,19 @@ struct Config {
     send_buffer_size: Option<usize>,
     recv_buffer_size: Option<usize>,
     interface: Option<String>,
+    negative_cache: Option<NegativeCache>,
+    load_balance: LoadBalance,
+    service_resolver: Option<dns::ServiceResolver>,
+    ip_family: IpFamily,
+    tcp_fast_open: bool,
+    local_port_range: Option<LocalPortRange>,
+    bind_address_no_port: bool,
+    socket_hook: Option<SocketHook>,
+    traffic_class: Option<u8>,
 }
 
+type SocketHook = Arc<dyn Fn(&socket2::Socket, &SocketAddr) -> io::Result<()> + Send + Sync>;
+
 #[derive(Default, Debug, Clone, Copy)]
 struct TcpKeepaliveConfig {
     time: Option<Duration>,
@@ -86,6 +100,16 @@ struct TcpKeepaliveConfig {
     retries: Option<u32>,
 }
 
+#[derive(Debug, Clone)]
+struct LocalPortRange {
+    first: u16,
+    last: u16,
+    // Where the next socket starts looking for a free port. Every attempt
+    // takes a different start, so concurrent attempts, like those of Happy
+    // Eyeballs, don't race for the same port.
+    next: Arc<AtomicUsize>,
+}
+
 impl TcpKeepaliveConfig {
     /// Converts into a `socket2::TcpKeealive` if there is any keep alive configuration.
     fn into_tcpkeepalive(self) -> Option<TcpKeepalive> {
@@ -168,6 +192,15 @@ impl<R> HttpConnector<R> {
                 send_buffer_size: None,
                 recv_buffer_size: None,
                 interface: None,
+                negative_cache: None,
+                load_balance: LoadBalance::default(),
+                service_resolver: None,
+                ip_family: IpFamily::default(),
+                tcp_fast_open: false,
+                local_port_range: None,
+                bind_address_no_port: false,
+                socket_hook: None,
+                traffic_class: None,
             }),
             resolver,
         }
@@ -310,6 +343,170 @@ impl<R> HttpConnector<R> {
         self
     }
 
+    /// Set a cache of recently failed addresses, which are then skipped.
+    ///
+    /// If every address of a destination is skipped, the connect fails
+    /// right away with the error of the last failure.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_negative_cache(&mut self, cache: Option<NegativeCache>) -> &mut Self {
+        self.config_mut().negative_cache = cache;
+        self
+    }
+
+    /// Set how the addresses a host resolves to are ordered for each
+    /// connection.
+    ///
+    /// Default is [`LoadBalance::resolved`], keeping the resolver's order.
+    #[inline]
+    pub fn set_load_balance(&mut self, load_balance: LoadBalance) -> &mut Self {
+        self.config_mut().load_balance = load_balance;
+        self
+    }
+
+    /// Set a resolver for `SRV` or `HTTPS` records, which are looked up
+    /// before resolving the host's addresses.
+    ///
+    /// The order of addresses from service records is kept, rather than
+    /// using the [`LoadBalance`] strategy.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_service_resolver(&mut self, resolver: Option<dns::ServiceResolver>) -> &mut Self {
+        self.config_mut().service_resolver = resolver;
+        self
+    }
+
+    /// Set that all sockets use TCP Fast Open, sending the first bytes of
+    /// a request along with the handshake.
+    ///
+    /// This saves a round trip for connections to servers that support it.
+    /// The first connection to a server is a normal handshake that obtains
+    /// a cookie, and if the server or network doesn't support Fast Open,
+    /// the kernel falls back to a normal handshake transparently.
+    ///
+    /// Since connecting only completes with the first write, the connect
+    /// timeout and Happy Eyeballs fallback don't observe the handshake. Data
+    /// sent with the handshake may be replayed by the network, so only
+    /// enable this for servers that tolerate that.
+    ///
+    /// This is only supported on Linux and Android, and has no effect
+    /// elsewhere.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_tcp_fast_open(&mut self, enabled: bool) -> &mut Self {
+        self.config_mut().tcp_fast_open = enabled;
+        self
+    }
+
+    /// Set which IP address families are used, and which is tried first.
+    ///
+    /// If a host resolves to no address of an allowed family, connecting
+    /// fails without trying any address.
+    ///
+    /// Setting only one local address with
+    /// [`set_local_address`](HttpConnector::set_local_address) also limits
+    /// connections to the family of that address.
+    ///
+    /// Default is [`IpFamily::Any`].
+    #[inline]
+    pub fn set_ip_family(&mut self, family: IpFamily) -> &mut Self {
+        self.config_mut().ip_family = family;
+        self
+    }
+
+    /// Set that all sockets are bound to a local port in `range` before
+    /// connection.
+    ///
+    /// This is for networks with firewall rules keyed on source ports. The
+    /// sockets are bound to the local address set with
+    /// [`set_local_address`](HttpConnector::set_local_address), if any. Ports
+    /// already in use are skipped, and connecting fails if every port of the
+    /// range is in use. Enable
+    /// [`set_reuse_address`](HttpConnector::set_reuse_address) to also use
+    /// ports whose previous connections are still in `TIME_WAIT`.
+    ///
+    /// Port 0 is never bound. If `None`, the system picks the port.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_local_port_range(&mut self, range: Option<RangeInclusive<u16>>) -> &mut Self {
+        self.config_mut().local_port_range = range.map(|range| LocalPortRange {
+            first: (*range.start()).max(1),
+            last: *range.end(),
+            next: Arc::new(AtomicUsize::new(0)),
+        });
+        self
+    }
+
+    /// Set that all sockets bound to a local address have
+    /// `IP_BIND_ADDRESS_NO_PORT` set.
+    ///
+    /// The system then picks the local port when connecting instead of when
+    /// binding, so connections to different destinations can share ports,
+    /// and many more connections can be bound to one local address. This
+    /// doesn't apply to sockets bound to a port of
+    /// [`set_local_port_range`](HttpConnector::set_local_port_range).
+    ///
+    /// This is only supported on Linux and Android, and has no effect
+    /// elsewhere.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_bind_address_no_port(&mut self, enabled: bool) -> &mut Self {
+        self.config_mut().bind_address_no_port = enabled;
+        self
+    }
+
+    /// Set the traffic class of all sockets, for QoS marking.
+    ///
+    /// This is the whole byte of `IP_TOS` over IPv4, and of `IPV6_TCLASS`
+    /// over IPv6: a DSCP code point is shifted left by 2, so `EF` (46) is
+    /// `0xb8`. Failing to set it is logged, and doesn't fail connecting.
+    /// Setting the IPv6 traffic class isn't supported on Windows.
+    ///
+    /// If `None`, the traffic class of the system is used.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_traffic_class(&mut self, tclass: Option<u8>) -> &mut Self {
+        self.config_mut().traffic_class = tclass;
+        self
+    }
+
+    /// Set a hook called with every socket and its destination address,
+    /// after the socket is created and before it is bound or connected.
+    ///
+    /// This sets socket options the connector has no setter for, such as
+    /// `IP_TOS`, `IP_FREEBIND` or `SO_MARK`. The options set by the
+    /// connector after binding, the buffer sizes and `SO_REUSEADDR`, still
+    /// apply over those of the hook. If the hook returns an error,
+    /// connecting fails with it, without trying the other addresses.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hyper_util::client::legacy::connect::HttpConnector;
+    ///
+    /// let mut connector = HttpConnector::new();
+    /// connector.set_socket_hook(|socket, addr| {
+    ///     if addr.is_ipv4() {
+    ///         socket.set_tos(0xb8)?;
+    ///     }
+    ///     Ok(())
+    /// });
+    /// ```
+    #[inline]
+    pub fn set_socket_hook<F>(&mut self, hook: F) -> &mut Self
+    where
+        F: Fn(&socket2::Socket, &SocketAddr) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.config_mut().socket_hook = Some(Arc::new(hook));
+        self
+    }
+
     // private
 
     fn config_mut(&mut self) -> &mut Config {
@@ -411,21 +608,51 @@ where
 
         // If the host is already an IP addr (v4 or v6),
         // skip resolving the dns and start connecting right away.
-        let addrs = if let Some(addrs) = dns::SocketAddrs::try_parse(host, port) {
+        let addrs = if let Some(resolved) = super::resolved::current() {
+            dns::SocketAddrs::new(resolved.addrs().to_vec())
+        } else if let Some(addrs) = dns::SocketAddrs::try_parse(host, port) {
             addrs
         } else {
-            let addrs = resolve(&mut self.resolver, dns::Name::new(host.into()))
-                .await
-                .map_err(ConnectError::dns)?;
-            let addrs = addrs
-                .map(|mut addr| {
-                    addr.set_port(port);
-                    addr
-                })
-                .collect();
-            dns::SocketAddrs::new(addrs)
+            let name = dns::Name::new(host.into());
+            let services = match config.service_resolver {
+                Some(ref services) => {
+                    services
+                        .resolve_addrs(&mut self.resolver, &name, port)
+                        .await
+                }
+                None => None,
+            };
+            if let Some(addrs) = services {
+                dns::SocketAddrs::new(addrs)
+            } else {
+                let span = spans::resolve(host);
+                let start = Instant::now();
+                let addrs = resolve(&mut self.resolver, name)
+                    .instrument(span.clone())
+                    .await
+                    .map_err(ConnectError::dns);
+                let err = addrs.as_ref().err().map(|err| err as &dyn fmt::Display);
+                spans::finish(&span, start, err);
+                let addrs = addrs?;
+                #[cfg(any(feature = "http1", feature = "http2"))]
+                crate::client::legacy::timings::record_dns(start..Instant::now());
+                let mut addrs = addrs
+                    .map(|mut addr| {
+                        addr.set_port(port);
+                        addr
+                    })
+                    .collect::<Vec<_>>();
+                config.load_balance.order(host, port, &mut addrs);
+                dns::SocketAddrs::new(addrs)
+            }
         };
 
+        let addrs = config
+            .ip_family
+            .apply(addrs.collect())
+            .map_err(ConnectError::m("no address of the allowed IP family"))?;
+        let addrs = dns::SocketAddrs::new(addrs);
+
         let c = ConnectingTcp::new(addrs, config);
 
         let sock = c.connect().await?;
@@ -526,6 +753,37 @@ impl ConnectError {
         ConnectError::new("dns error", cause)
     }
 
+    fn into_cause(self) -> Box<dyn StdError + Send + Sync> {
+        match self.cause {
+            Some(cause) => cause,
+            None => Box::new(self),
+        }
+    }
+
+    // Both the preferred and the fallback addresses failed, report all of
+    // the attempts.
+    fn merge(self, other: ConnectError) -> ConnectError {
+        let first = match self.cause.map(|cause| cause.downcast::<ConnectFailures>()) {
+            Some(Ok(failures)) => failures,
+            _ => return other,
+        };
+        match other.cause.map(|cause| cause.downcast::<ConnectFailures>()) {
+            Some(Ok(mut second)) => {
+                let mut attempts = first.attempts;
+                attempts.append(&mut second.attempts);
+                ConnectError::new(other.msg, ConnectFailures { attempts })
+            }
+            Some(Err(cause)) => ConnectError {
+                msg: other.msg,
+                cause: Some(cause),
+            },
+            None => ConnectError {
+                msg: other.msg,
+                cause: None,
+            },
+        }
+    }
+
     fn m<S, E>(msg: S) -> impl FnOnce(E) -> ConnectError
     where
         S: Into<Box<str>>,
@@ -566,6 +824,89 @@ impl StdError for ConnectError {
     }
 }
 
+/// The errors of every address tried by a failed `HttpConnector` connect.
+///
+/// This is found in the `source` chain of the connector's error.
+///
+/// # Example
+///
+/// ```
+/// # fn doc(err: &(dyn std::error::Error + 'static)) {
+/// use hyper_util::client::legacy::connect::ConnectFailures;
+///
+/// let mut source = Some(err);
+/// while let Some(err) = source {
+///     if let Some(failures) = err.downcast_ref::<ConnectFailures>() {
+///         for attempt in failures.attempts() {
+///             eprintln!(
+///                 "{} failed after {:?}: {}",
+///                 attempt.addr(),
+///                 attempt.elapsed(),
+///                 attempt.error()
+///             );
+///         }
+///     }
+///     source = err.source();
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ConnectFailures {
+    attempts: Vec<ConnectAttempt>,
+}
+
+/// A single failed attempt in [`ConnectFailures`].
+#[derive(Debug)]
+pub struct ConnectAttempt {
+    addr: SocketAddr,
+    error: Box<dyn StdError + Send + Sync>,
+    elapsed: Duration,
+}
+
+impl ConnectFailures {
+    /// Every attempted address, in the order they failed.
+    pub fn attempts(&self) -> &[ConnectAttempt] {
+        &self.attempts
+    }
+}
+
+impl fmt::Display for ConnectFailures {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, attempt) in self.attempts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{} ({})", attempt.addr, attempt.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl StdError for ConnectFailures {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.attempts.last().map(|attempt| &*attempt.error as _)
+    }
+}
+
+impl ConnectAttempt {
+    /// The address that was tried.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Why connecting to this address failed.
+    pub fn error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        &*self.error
+    }
+
+    /// How long the attempt took before failing.
+    ///
+    /// This is zero for addresses skipped by a [`NegativeCache`].
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+}
+
 struct ConnectingTcp<'a> {
     preferred: ConnectingTcpRemote,
     fallback: Option<ConnectingTcpFallback>,
@@ -626,27 +967,58 @@ impl ConnectingTcpRemote {
 
 impl ConnectingTcpRemote {
     async fn connect(&mut self, config: &Config) -> Result<TcpStream, ConnectError> {
-        let mut err = None;
+        let mut attempts = Vec::new();
         for addr in &mut self.addrs {
+            if let Some(ref cache) = config.negative_cache {
+                if let Some(reason) = cache.check(&addr) {
+                    debug!("skipping recently failed {}", addr);
+                    attempts.push(ConnectAttempt {
+                        addr,
+                        error: io::Error::new(
+                            io::ErrorKind::ConnectionRefused,
+                            format!("recently failed: {}", reason),
+                        )
+                        .into(),
+                        elapsed: Duration::from_secs(0),
+                    });
+                    continue;
+                }
+            }
+
             debug!("connecting to {}", addr);
+            let start = Instant::now();
             match connect(&addr, config, self.connect_timeout)?.await {
                 Ok(tcp) => {
                     debug!("connected to {}", addr);
+                    if let Some(ref cache) = config.negative_cache {
+                        cache.succeeded(&addr);
+                    }
                     return Ok(tcp);
                 }
                 Err(e) => {
                     trace!("connect error for {}: {:?}", addr, e);
-                    err = Some(e);
+                    if let Some(ref cache) = config.negative_cache {
+                        cache.failed(addr, e.to_string());
+                    }
+                    attempts.push(ConnectAttempt {
+                        addr,
+                        error: e.into_cause(),
+                        elapsed: start.elapsed(),
+                    });
                 }
             }
         }
 
-        match err {
-            Some(e) => Err(e),
-            None => Err(ConnectError::new(
+        if attempts.is_empty() {
+            Err(ConnectError::new(
                 "tcp connect error",
                 std::io::Error::new(std::io::ErrorKind::NotConnected, "Network unreachable"),
-            )),
+            ))
+        } else {
+            Err(ConnectError::new(
+                "tcp connect error",
+                ConnectFailures { attempts },
+            ))
         }
     }
 }
@@ -656,7 +1028,18 @@ fn bind_local_address(
     dst_addr: &SocketAddr,
     local_addr_ipv4: &Option<Ipv4Addr>,
     local_addr_ipv6: &Option<Ipv6Addr>,
+    local_port_range: &Option<LocalPortRange>,
 ) -> io::Result<()> {
+    if let Some(range) = local_port_range {
+        let ip = match (*dst_addr, local_addr_ipv4, local_addr_ipv6) {
+            (SocketAddr::V4(_), Some(addr), _) => (*addr).into(),
+            (SocketAddr::V6(_), _, Some(addr)) => (*addr).into(),
+            (SocketAddr::V4(_), _, _) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            (SocketAddr::V6(_), _, _) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        return range.bind(socket, ip);
+    }
+
     match (*dst_addr, local_addr_ipv4, local_addr_ipv6) {
         (SocketAddr::V4(_), Some(addr), _) => {
             socket.bind(&SocketAddr::new((*addr).into(), 0).into())?;
@@ -679,6 +1062,53 @@ fn bind_local_address(
     Ok(())
 }
 
+impl LocalPortRange {
+    fn bind(&self, socket: &socket2::Socket, ip: IpAddr) -> io::Result<()> {
+        if self.first <= self.last {
+            let len = usize::from(self.last - self.first) + 1;
+            let start = self.next.fetch_add(1, Ordering::Relaxed);
+            for i in 0..len {
+                let port = self.first + ((start + i) % len) as u16;
+                match socket.bind(&SocketAddr::new(ip, port).into()) {
+                    Ok(()) => return Ok(()),
+                    Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                        trace!("local port {} in use", port);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "no free port in the local port range",
+        ))
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+fn enable_sockopt(socket: &socket2::Socket, level: i32, name: i32) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    // `c_int` is an `i32` on every target this is built for.
+    let enabled: i32 = 1;
+    // Safety: the fd is an open socket, and the option value points to a
+    // `c_int` of the length passed.
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &enabled as *const i32 as *const _,
+            std::mem::size_of::<i32>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
 fn connect(
     addr: &SocketAddr,
     config: &Config,
@@ -705,6 +1135,12 @@ fn connect(
         }
     }
 
+    if let Some(tclass) = config.traffic_class {
+        if let Err(e) = crate::common::tclass::set(&socket, addr, tclass) {
+            warn!("tcp set_traffic_class error: {}", e);
+        }
+    }
+
     #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
     // That this only works for some socket types, particularly AF_INET sockets.
     if let Some(interface) = &config.interface {
@@ -713,14 +1149,41 @@ fn connect(
             .map_err(ConnectError::m("tcp bind interface error"))?;
     }
 
+    // A port of the range is bound explicitly, so reusing one whose last
+    // connection is in `TIME_WAIT` needs `SO_REUSEADDR` before binding.
+    if config.reuse_address && config.local_port_range.is_some() {
+        if let Err(e) = socket.set_reuse_address(true) {
+            warn!("tcp set_reuse_address error: {}", e);
+        }
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if config.bind_address_no_port {
+        if let Err(e) = enable_sockopt(&socket, libc::IPPROTO_IP, libc::IP_BIND_ADDRESS_NO_PORT) {
+            debug!("tcp bind address no port unavailable: {}", e);
+        }
+    }
+
+    if let Some(hook) = &config.socket_hook {
+        hook(&socket, addr).map_err(ConnectError::m("tcp socket hook error"))?;
+    }
+
     bind_local_address(
         &socket,
         addr,
         &config.local_address_ipv4,
         &config.local_address_ipv6,
+        &config.local_port_range,
     )
     .map_err(ConnectError::m("tcp bind local error"))?;
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if config.tcp_fast_open {
+        if let Err(e) = enable_sockopt(&socket, libc::IPPROTO_TCP, libc::TCP_FASTOPEN_CONNECT) {
+            debug!("tcp fast open unavailable: {}", e);
+        }
+    }
+
     #[cfg(unix)]
     let socket = unsafe {
         // Safety: `from_raw_fd` is only safe to call if ownership of the raw
@@ -799,12 +1262,11 @@ impl ConnectingTcp<'_> {
                         }
                     };
 
-                if result.is_err() {
+                match result {
                     // Fallback to the remaining future (could be preferred or fallback)
                     // if we get an error
-                    future.await
-                } else {
-                    result
+                    Err(first) => future.await.map_err(|second| first.merge(second)),
+                    ok => ok,
                 }
             }
         }
@@ -842,6 +1304,112 @@ mod tests {
         assert_eq!(&*err.msg, super::INVALID_NOT_HTTP);
     }
 
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_tcp_fast_open() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dst = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let server = tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 5];
+            sock.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+
+        let mut connector = HttpConnector::new();
+        connector.set_tcp_fast_open(true);
+        let mut stream = connect(connector, dst).await.unwrap().into_inner();
+        stream.write_all(b"hello").await.unwrap();
+        assert_eq!(&server.await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_local_port_range() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dst: Uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        // find a free port to bind to
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let mut connector = HttpConnector::new();
+        connector.set_local_port_range(Some(port..=port));
+        let stream = connect(connector.clone(), dst.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(stream.local_addr().unwrap().port(), port);
+
+        let err = connect(connector, dst).await.unwrap_err();
+        assert_eq!(&*err.msg, "tcp bind local error");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_traffic_class() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dst: Uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+
+        let mut connector = HttpConnector::new();
+        connector.set_traffic_class(Some(0xb8));
+        let stream = connect(connector, dst).await.unwrap().into_inner();
+        assert_eq!(socket2::SockRef::from(&stream).tos().unwrap(), 0xb8);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_socket_hook() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let dst: Uri = format!("http://{}", addr).parse().unwrap();
+
+        let called = Arc::new(AtomicBool::new(false));
+        let mut connector = HttpConnector::new();
+        let called2 = called.clone();
+        connector.set_socket_hook(move |socket, dst| {
+            assert_eq!(*dst, addr);
+            called2.store(true, Ordering::SeqCst);
+            socket.set_ttl(42)
+        });
+        let stream = connect(connector.clone(), dst.clone())
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(called.load(Ordering::SeqCst));
+        assert_eq!(stream.ttl().unwrap(), 42);
+
+        connector.set_socket_hook(|_, _| Err(io::Error::new(io::ErrorKind::Other, "denied")));
+        let err = connect(connector, dst).await.unwrap_err();
+        assert_eq!(&*err.msg, "tcp socket hook error");
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn test_errors_ip_family() {
+        let dst = "http://[::1]:80/".parse().unwrap();
+        let mut connector = HttpConnector::new();
+        connector.set_ip_family(super::IpFamily::V4Only);
+
+        let err = connect(connector, dst).await.unwrap_err();
+        assert_eq!(&*err.msg, "no address of the allowed IP family");
+    }
+
     #[cfg(any(target_os = "linux", target_os = "macos"))]
     fn get_local_ips() -> (Option<std::net::Ipv4Addr>, Option<std::net::Ipv6Addr>) {
         use std::net::{IpAddr, TcpListener};
@@ -876,6 +1444,98 @@ mod tests {
             .map(|e| e.name.clone())
     }
 
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn negative_cache_skips_failed_addr() {
+        // Grab a free port, then close it so connecting is refused.
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let dst: Uri = format!("http://{}", addr).parse().unwrap();
+
+        let cache = super::NegativeCache::new(std::time::Duration::from_secs(60));
+        let mut connector = HttpConnector::new();
+        connector.set_negative_cache(Some(cache.clone()));
+
+        connect(connector.clone(), dst.clone()).await.unwrap_err();
+        assert_eq!(cache.skipped_attempts(), 0);
+        assert_eq!(cache.len(), 1);
+
+        let err = connect(connector, dst).await.unwrap_err();
+        assert_eq!(cache.skipped_attempts(), 1);
+        assert!(err.to_string().contains("recently failed"), "{}", err);
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn service_records_before_addresses() {
+        use super::dns::{ServiceRecord, ServiceResolver};
+        use std::convert::Infallible;
+        use std::net::TcpListener;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let services = ServiceResolver::new(move |name| async move {
+            let records = if name.as_str() == "svc.invalid" {
+                vec![ServiceRecord::https(1, None)
+                    .with_port(addr.port())
+                    .with_alpn(vec![b"h2".to_vec()])
+                    .with_ip_hints(vec![addr.ip()])]
+            } else {
+                vec![]
+            };
+            Ok::<_, Infallible>(records)
+        });
+        let mut connector = HttpConnector::new();
+        connector.set_service_resolver(Some(services.clone()));
+
+        // The destination port is replaced by the record's.
+        let dst = "http://svc.invalid:1".parse().unwrap();
+        let stream = connect(connector.clone(), dst).await.unwrap();
+        let peer = stream.inner().peer_addr().unwrap();
+        assert_eq!(peer, addr);
+        let record = services.record_for(&peer).unwrap();
+        assert_eq!(record.alpn(), [b"h2".to_vec()]);
+
+        // Without records, the host is resolved as usual.
+        let dst = format!("http://localhost:{}", addr.port()).parse().unwrap();
+        connect(connector, dst).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[cfg_attr(miri, ignore)]
+    async fn reports_every_failed_attempt() {
+        use super::{dns, ConnectFailures, ConnectingTcp};
+        use std::error::Error;
+
+        // Grab a free port, then close it so connecting is refused.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addrs = vec![([127, 0, 0, 1], port).into(), ([127, 0, 0, 2], port).into()];
+
+        let mut connector = HttpConnector::new();
+        connector.set_happy_eyeballs_timeout(None);
+        let connecting = ConnectingTcp::new(dns::SocketAddrs::new(addrs), &connector.config);
+        let err = connecting.connect().await.unwrap_err();
+
+        let failures = err
+            .source()
+            .and_then(|e| e.downcast_ref::<ConnectFailures>())
+            .expect("ConnectFailures");
+        let attempted = failures
+            .attempts()
+            .iter()
+            .map(|a| a.addr().ip().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(attempted, vec!["127.0.0.1", "127.0.0.2"]);
+        assert!(failures.source().is_some());
+    }
+
     #[tokio::test]
     #[cfg_attr(miri, ignore)]
     async fn test_errors_missing_scheme() {
@@ -984,7 +1644,7 @@ mod tests {
         use std::time::{Duration, Instant};
 
         use super::dns;
-        use super::ConnectingTcp;
+        use super::{ConnectingTcp, IpFamily, LoadBalance};
 
         let server4 = TcpListener::bind("127.0.0.1:0").unwrap();
         let addr = server4.local_addr().unwrap();
@@ -1106,6 +1766,15 @@ mod tests {
                         send_buffer_size: None,
                         recv_buffer_size: None,
                         interface: None,
+                        negative_cache: None,
+                        load_balance: LoadBalance::default(),
+                        service_resolver: None,
+                        ip_family: IpFamily::default(),
+                        tcp_fast_open: false,
+                        local_port_range: None,
+                        bind_address_no_port: false,
+                        socket_hook: None,
+                        traffic_class: None,
                     };
                     let connecting_tcp = ConnectingTcp::new(dns::SocketAddrs::new(addrs), &cfg);
                     let start = Instant::now();
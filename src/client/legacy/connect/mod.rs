@@ -63,20 +63,57 @@
 //! [`Write`]: hyper::rt::Write
 //! [`Connection`]: Connection
 use std::fmt;
+use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    Arc,
+};
+use std::time::{Duration, Instant};
 
 use ::http::Extensions;
 
 #[cfg(feature = "tokio")]
-pub use self::http::{HttpConnector, HttpInfo};
+pub use self::balance::LoadBalance;
+#[cfg(feature = "tokio")]
+pub use self::family::IpFamily;
+#[cfg(feature = "tokio")]
+pub use self::http::{ConnectAttempt, ConnectFailures, HttpConnector, HttpInfo};
+#[cfg(feature = "tokio")]
+pub use self::negative_cache::NegativeCache;
+#[cfg(any(feature = "http1", feature = "http2"))]
+pub use self::origin::PerOrigin;
+pub use self::profile::TcpProfile;
+#[cfg(feature = "tokio")]
+pub use self::profile::{TcpProfiled, TcpProfiledStream};
+pub use self::racing::{RacePolicy, Racing};
+pub use self::resolved::ResolvedAddrs;
+pub use self::rotating::{Rotating, RotatingStream};
+pub use self::selecting::{SelectConnector, Selecting};
 
+#[cfg(feature = "tokio")]
+mod balance;
 #[cfg(feature = "tokio")]
 pub mod dns;
 #[cfg(feature = "tokio")]
+mod family;
+#[cfg(feature = "tokio")]
 mod http;
+#[cfg(feature = "tokio")]
+mod negative_cache;
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod origin;
+mod profile;
+mod racing;
+pub(crate) mod resolved;
+mod rotating;
+pub(crate) mod selecting;
 
 pub(crate) mod capture;
 pub use capture::{capture_connection, CaptureConnection};
 
+pub mod proxy;
+
 pub use self::sealed::Connect;
 
 /// Describes a type returned by a connector.
@@ -91,9 +128,47 @@ pub trait Connection {
 /// was used, or if connected to an HTTP proxy.
 #[derive(Debug)]
 pub struct Connected {
+    pub(super) id: u64,
     pub(super) alpn: Alpn,
     pub(super) is_proxied: bool,
     pub(super) extra: Option<Extra>,
+    pub(super) poisoned: PoisonPill,
+    pub(super) tcp_profile: Option<profile::ProfileSwitch>,
+    pub(super) tls_handshake: Option<Range<Instant>>,
+    pub(super) dns: Option<DnsAnswer>,
+    pub(super) early_data: Option<EarlyDataState>,
+}
+
+/// Whether a connection still sends TLS 1.3 early data (0-RTT).
+///
+/// A TLS connector that resumes sessions with early data creates one for
+/// every such connection, sets it on its [`Connected`] with
+/// [`Connected::early_data`], and reports how the handshake ended with
+/// [`accept`](EarlyDataState::accept) or
+/// [`reject`](EarlyDataState::reject). Clones share the state.
+///
+/// Until then, the `Client` only sends the requests that are safe to
+/// replay, as decided by [`EarlyData`](super::EarlyData), and holds back
+/// the others. When the server rejects early data, the connector must
+/// either send it again once the handshake is done, or fail the reads of
+/// the connection, which fails the requests sent early with an error that
+/// is [retryable](super::Error::is_retryable), for a
+/// [`RetryLayer`](super::retry::RetryLayer) to send again.
+#[derive(Clone)]
+pub struct EarlyDataState(Arc<EarlyDataInner>);
+
+struct EarlyDataInner {
+    state: AtomicU8,
+    done: tokio::sync::Notify,
+}
+
+/// The DNS answer a connection's address was picked from.
+#[derive(Clone, Debug)]
+#[cfg_attr(not(any(feature = "http1", feature = "http2")), allow(dead_code))]
+pub(super) struct DnsAnswer {
+    pub(super) remote: IpAddr,
+    pub(super) addrs: Arc<[IpAddr]>,
+    pub(super) ttl: Duration,
 }
 
 pub(super) struct Extra(Box<dyn ExtraInner>);
@@ -101,6 +176,7 @@ pub(super) struct Extra(Box<dyn ExtraInner>);
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub(super) enum Alpn {
     H2,
+    Http1,
     None,
 }
 
@@ -108,9 +184,15 @@ impl Connected {
     /// Create new `Connected` type with empty metadata.
     pub fn new() -> Connected {
         Connected {
+            id: crate::common::id::next_connection_id(),
             alpn: Alpn::None,
             is_proxied: false,
             extra: None,
+            poisoned: PoisonPill::healthy(),
+            tcp_profile: None,
+            tls_handshake: None,
+            dns: None,
+            early_data: None,
         }
     }
 
@@ -170,17 +252,236 @@ impl Connected {
         self.alpn == Alpn::H2
     }
 
+    /// Set that the connected transport negotiated HTTP/1.1 as its next protocol.
+    pub fn negotiated_http1(mut self) -> Connected {
+        self.alpn = Alpn::Http1;
+        self
+    }
+
+    /// Set the protocol the connected transport negotiated with ALPN.
+    ///
+    /// This takes the protocol id as sent on the wire, such as what a TLS
+    /// library reports, so `b"h2"` is the same as calling
+    /// [`negotiated_h2`](Connected::negotiated_h2). Protocols the `Client`
+    /// cannot speak are ignored.
+    pub fn negotiated_alpn(self, protocol: &[u8]) -> Connected {
+        match protocol {
+            b"h2" => self.negotiated_h2(),
+            b"http/1.1" => self.negotiated_http1(),
+            _ => self,
+        }
+    }
+
+    /// The protocol id negotiated with ALPN, if any.
+    pub fn alpn_protocol(&self) -> Option<&'static [u8]> {
+        match self.alpn {
+            Alpn::H2 => Some(b"h2"),
+            Alpn::Http1 => Some(b"http/1.1"),
+            Alpn::None => None,
+        }
+    }
+
+    /// Poison this connection.
+    ///
+    /// A poisoned connection is not reused by the `Client`, and is dropped
+    /// once it is done with the current request. This is useful when a
+    /// connection is known to be broken, such as a tunnel the proxy has
+    /// stopped forwarding, while the transport still looks open.
+    pub fn poison(&self) {
+        self.poisoned.poison();
+    }
+
+    /// Determines if this connection has been poisoned.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.is_poisoned()
+    }
+
+    /// Set when the TLS handshake of the connection started and ended.
+    ///
+    /// TLS connectors can report this, for the `Client` to include it in
+    /// the [`Timings`](super::Timings) of the requests on the connection.
+    pub fn tls_handshake(mut self, start: Instant, end: Instant) -> Connected {
+        self.tls_handshake = Some(start..end);
+        self
+    }
+
+    /// Set the DNS answer the address of the connection was picked from.
+    ///
+    /// `remote` is the address connected to, and `addrs` all addresses of
+    /// the answer, which is valid for `ttl` from when the connection is
+    /// made, by the clock of the pool timer. Connectors that know the TTL
+    /// of their records can report it, for
+    /// [`Builder::pool_dns_expiry_grace`](super::Builder::pool_dns_expiry_grace)
+    /// to stop reusing the connection once the record changed.
+    pub fn dns_answer<I>(mut self, remote: IpAddr, addrs: I, ttl: Duration) -> Connected
+    where
+        I: IntoIterator<Item = IpAddr>,
+    {
+        self.dns = Some(DnsAnswer {
+            remote,
+            addrs: addrs.into_iter().collect(),
+            ttl,
+        });
+        self
+    }
+
+    /// Set that the connection sends TLS 1.3 early data, until `state`
+    /// reports the end of the handshake.
+    pub fn early_data(mut self, state: EarlyDataState) -> Connected {
+        self.early_data = Some(state);
+        self
+    }
+
+    /// Determines if the connection still sends TLS 1.3 early data.
+    pub fn is_early_data(&self) -> bool {
+        self.early_data
+            .as_ref()
+            .map_or(false, EarlyDataState::is_pending)
+    }
+
+    /// The ID of this connection, to correlate its logs.
+    ///
+    /// IDs are allocated when a `Connected` is created, and increase. They
+    /// are unique in the process, shared with the connections of servers.
+    /// The `Client` records it in its spans as `connection.id`.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
     // Don't public expose that `Connected` is `Clone`, unsure if we want to
     // keep that contract...
     pub(super) fn clone(&self) -> Connected {
         Connected {
+            id: self.id,
             alpn: self.alpn,
             is_proxied: self.is_proxied,
             extra: self.extra.clone(),
+            poisoned: self.poisoned.clone(),
+            tcp_profile: self.tcp_profile.clone(),
+            tls_handshake: self.tls_handshake.clone(),
+            dns: self.dns.clone(),
+            early_data: self.early_data.clone(),
         }
     }
 }
 
+// ===== impl PoisonPill =====
+
+// Shared by every clone of a `Connected`, so poisoning the metadata captured
+// from a request reaches the pooled connection too.
+#[derive(Clone)]
+pub(super) struct PoisonPill(Arc<AtomicBool>);
+
+impl PoisonPill {
+    fn healthy() -> PoisonPill {
+        PoisonPill(Arc::new(AtomicBool::new(false)))
+    }
+
+    pub(super) fn poison(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    fn is_poisoned(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for PoisonPill {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_poisoned() {
+            f.write_str("poisoned")
+        } else {
+            f.write_str("healthy")
+        }
+    }
+}
+
+// ===== impl EarlyDataState =====
+
+const EARLY_DATA_PENDING: u8 = 0;
+const EARLY_DATA_ACCEPTED: u8 = 1;
+const EARLY_DATA_REJECTED: u8 = 2;
+const EARLY_DATA_CLOSED: u8 = 3;
+
+impl EarlyDataState {
+    /// Create the state of a connection sending early data.
+    pub fn new() -> EarlyDataState {
+        EarlyDataState(Arc::new(EarlyDataInner {
+            state: AtomicU8::new(EARLY_DATA_PENDING),
+            done: tokio::sync::Notify::new(),
+        }))
+    }
+
+    /// The handshake is done, and the server accepted the early data.
+    pub fn accept(&self) {
+        self.finish(EARLY_DATA_ACCEPTED);
+    }
+
+    /// The handshake is done, and the server rejected the early data.
+    pub fn reject(&self) {
+        self.finish(EARLY_DATA_REJECTED);
+    }
+
+    /// Determines if the handshake is still going, so data written now is
+    /// sent as early data.
+    pub fn is_pending(&self) -> bool {
+        self.0.state.load(Ordering::Acquire) == EARLY_DATA_PENDING
+    }
+
+    /// Determines if the server rejected the early data.
+    pub fn is_rejected(&self) -> bool {
+        self.0.state.load(Ordering::Acquire) == EARLY_DATA_REJECTED
+    }
+
+    fn finish(&self, state: u8) {
+        let _ = self.0.state.compare_exchange(
+            EARLY_DATA_PENDING,
+            state,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+        self.0.done.notify_waiters();
+    }
+
+    /// The connection closed, before the handshake was done if it still
+    /// was pending.
+    #[cfg_attr(not(any(feature = "http1", feature = "http2")), allow(dead_code))]
+    pub(super) fn close(&self) {
+        self.finish(EARLY_DATA_CLOSED);
+    }
+
+    /// Wait for the handshake to be done, returning false if the connection
+    /// closed first.
+    #[cfg_attr(not(any(feature = "http1", feature = "http2")), allow(dead_code))]
+    pub(super) async fn handshake(&self) -> bool {
+        loop {
+            // registered before checking, so a `finish` in between wakes it
+            let done = self.0.done.notified();
+            match self.0.state.load(Ordering::Acquire) {
+                EARLY_DATA_PENDING => done.await,
+                state => return state != EARLY_DATA_CLOSED,
+            }
+        }
+    }
+}
+
+impl Default for EarlyDataState {
+    fn default() -> EarlyDataState {
+        EarlyDataState::new()
+    }
+}
+
+impl fmt::Debug for EarlyDataState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self.0.state.load(Ordering::Acquire) {
+            EARLY_DATA_PENDING => "pending",
+            EARLY_DATA_ACCEPTED => "accepted",
+            EARLY_DATA_REJECTED => "rejected",
+            _ => "closed",
+        })
+    }
+}
+
 // ===== impl Extra =====
 
 impl Extra {
@@ -388,4 +689,22 @@ mod tests {
         assert_eq!(ex2.get::<Ex1>(), Some(&Ex1(99)));
         assert_eq!(ex2.get::<Ex2>(), Some(&Ex2("hiccup")));
     }
+
+    #[test]
+    fn test_connected_alpn() {
+        let c = Connected::new();
+        assert_eq!(c.alpn_protocol(), None);
+
+        let c = c.negotiated_alpn(b"h2");
+        assert!(c.is_negotiated_h2());
+        assert_eq!(c.alpn_protocol(), Some(&b"h2"[..]));
+
+        let c = c.negotiated_alpn(b"http/1.1");
+        assert!(!c.is_negotiated_h2());
+        assert_eq!(c.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        // unknown protocols are ignored
+        let c = c.negotiated_alpn(b"spdy/3");
+        assert_eq!(c.alpn_protocol(), Some(&b"http/1.1"[..]));
+    }
 }
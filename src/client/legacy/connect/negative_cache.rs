@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A cache of addresses that recently failed to connect.
+///
+/// When set on an [`HttpConnector`](super::HttpConnector), addresses that
+/// failed are skipped until `ttl` has elapsed, so repeated dials to a
+/// black-holed IP fail fast instead of waiting for a connect timeout each
+/// time. Other addresses a name resolves to are still tried.
+///
+/// The cache is shared between clones.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use hyper_util::client::legacy::connect::{HttpConnector, NegativeCache};
+///
+/// let cache = NegativeCache::new(Duration::from_secs(30));
+/// let mut connector = HttpConnector::new();
+/// connector.set_negative_cache(Some(cache.clone()));
+///
+/// // later...
+/// println!("skipped {} connect attempts", cache.skipped_attempts());
+/// ```
+#[derive(Clone)]
+pub struct NegativeCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    ttl: Duration,
+    failed: Mutex<HashMap<SocketAddr, Failure>>,
+    skipped: AtomicU64,
+}
+
+struct Failure {
+    at: Instant,
+    reason: String,
+}
+
+impl NegativeCache {
+    /// Create a cache remembering failed addresses for `ttl`.
+    pub fn new(ttl: Duration) -> NegativeCache {
+        NegativeCache {
+            inner: Arc::new(Inner {
+                ttl,
+                failed: Mutex::new(HashMap::new()),
+                skipped: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// The number of connect attempts skipped because the address had
+    /// recently failed.
+    pub fn skipped_attempts(&self) -> u64 {
+        self.inner.skipped.load(Ordering::Relaxed)
+    }
+
+    /// The number of addresses currently considered failed.
+    pub fn len(&self) -> usize {
+        let now = Instant::now();
+        self.inner
+            .failed
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|failure| now.duration_since(failure.at) < self.inner.ttl)
+            .count()
+    }
+
+    /// Returns true if no address is currently considered failed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget all failed addresses.
+    pub fn clear(&self) {
+        self.inner.failed.lock().unwrap().clear();
+    }
+
+    /// If `addr` failed recently, count a skipped attempt and return why.
+    pub(super) fn check(&self, addr: &SocketAddr) -> Option<String> {
+        let mut failed = self.inner.failed.lock().unwrap();
+        match failed.get(addr) {
+            Some(failure) if failure.at.elapsed() < self.inner.ttl => {
+                self.inner.skipped.fetch_add(1, Ordering::Relaxed);
+                Some(failure.reason.clone())
+            }
+            Some(_) => {
+                failed.remove(addr);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub(super) fn failed(&self, addr: SocketAddr, reason: String) {
+        let mut failed = self.inner.failed.lock().unwrap();
+        // Don't let the map grow without bound when dialing many hosts.
+        let ttl = self.inner.ttl;
+        failed.retain(|_, failure| failure.at.elapsed() < ttl);
+        failed.insert(
+            addr,
+            Failure {
+                at: Instant::now(),
+                reason,
+            },
+        );
+    }
+
+    pub(super) fn succeeded(&self, addr: &SocketAddr) {
+        self.inner.failed.lock().unwrap().remove(addr);
+    }
+}
+
+impl fmt::Debug for NegativeCache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NegativeCache")
+            .field("ttl", &self.inner.ttl)
+            .field("skipped_attempts", &self.skipped_attempts())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::NegativeCache;
+
+    #[test]
+    fn remembers_failures_until_ttl() {
+        let addr = ([127, 0, 0, 1], 80).into();
+        let cache = NegativeCache::new(Duration::from_millis(50));
+        assert!(cache.check(&addr).is_none());
+
+        cache.failed(addr, "connection refused".into());
+        assert_eq!(cache.check(&addr).as_deref(), Some("connection refused"));
+        assert_eq!(cache.skipped_attempts(), 1);
+        assert_eq!(cache.len(), 1);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.check(&addr).is_none());
+        assert!(cache.is_empty());
+
+        cache.failed(addr, "connection refused".into());
+        cache.succeeded(&addr);
+        assert!(cache.check(&addr).is_none());
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::task::{self, Poll};
+
+use http::uri::{Authority, Scheme, Uri};
+
+use crate::client::legacy::host::{self, HostNormalization};
+
+/// A connector that picks which connector to use by the origin of the
+/// destination.
+///
+/// This is for configuring some origins differently, with connectors of
+/// the same type, most often for TLS: a client certificate for one host,
+/// pinned roots for another, or skipping verification for a development
+/// host, while every other origin uses the default connector.
+///
+/// Origins are matched by scheme and authority, the same as the `Client`
+/// pools connections by, with the host normalized as with
+/// [`HostNormalization::Standard`]. Every pooled connection is thus dialed
+/// by a single connector, and connections are never reused across
+/// configurations.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # fn run() {
+/// use hyper_util::client::legacy::connect::{HttpConnector, PerOrigin};
+///
+/// let mut dev = HttpConnector::new();
+/// dev.enforce_http(false);
+///
+/// let connector = PerOrigin::new(HttpConnector::new())
+///     .route("https://dev.internal:8443".parse().unwrap(), dev);
+/// # drop(connector);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct PerOrigin<C> {
+    default: C,
+    routes: HashMap<(Scheme, Authority), C>,
+}
+
+impl<C> PerOrigin<C> {
+    /// Create a connector using `default` for every origin.
+    pub fn new(default: C) -> PerOrigin<C> {
+        PerOrigin {
+            default,
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Connect to `origin` with `connector`.
+    ///
+    /// Only the scheme and authority of `origin` are used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `origin` has no scheme or no authority.
+    pub fn route(mut self, origin: Uri, connector: C) -> PerOrigin<C> {
+        let key = origin_of(origin).expect("origin must have a scheme and an authority");
+        self.routes.insert(key, connector);
+        self
+    }
+
+    fn connector_for(&mut self, dst: &Uri) -> &mut C {
+        let key = match origin_of(dst.clone()) {
+            Some(key) => key,
+            None => return &mut self.default,
+        };
+        match self.routes.get_mut(&key) {
+            Some(connector) => connector,
+            None => &mut self.default,
+        }
+    }
+}
+
+fn origin_of(mut uri: Uri) -> Option<(Scheme, Authority)> {
+    host::normalize(&mut uri, HostNormalization::Standard).ok()?;
+    match (uri.scheme(), uri.authority()) {
+        (Some(scheme), Some(auth)) => Some((scheme.clone(), auth.clone())),
+        _ => None,
+    }
+}
+
+impl<C> tower_service::Service<Uri> for PerOrigin<C>
+where
+    C: tower_service::Service<Uri>,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        futures_util::ready!(self.default.poll_ready(cx))?;
+        for connector in self.routes.values_mut() {
+            futures_util::ready!(connector.poll_ready(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        self.connector_for(&dst).call(dst)
+    }
+}
+
+impl<C> fmt::Debug for PerOrigin<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut origins = self
+            .routes
+            .keys()
+            .map(|(scheme, auth)| format!("{}://{}", scheme, auth))
+            .collect::<Vec<_>>();
+        origins.sort();
+        f.debug_struct("PerOrigin")
+            .field("origins", &origins)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::{ready, Ready};
+    use std::task::{Context, Poll};
+
+    use http::Uri;
+    use tower_service::Service;
+
+    use super::PerOrigin;
+
+    #[derive(Clone)]
+    struct Named(&'static str);
+
+    impl Service<Uri> for Named {
+        type Response = &'static str;
+        type Error = Infallible;
+        type Future = Ready<Result<&'static str, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            ready(Ok(self.0))
+        }
+    }
+
+    #[tokio::test]
+    async fn routes_by_origin() {
+        let mut connector = PerOrigin::new(Named("default"))
+            .route("https://Dev.Example.com.".parse().unwrap(), Named("dev"))
+            .route("http://example.com:8080".parse().unwrap(), Named("alt"));
+
+        let mut connect = |dst: &'static str| connector.call(dst.parse().unwrap());
+        assert_eq!(connect("https://dev.example.com:443").await, Ok("dev"));
+        assert_eq!(connect("http://dev.example.com").await, Ok("default"));
+        assert_eq!(connect("http://example.com:8080").await, Ok("alt"));
+        assert_eq!(connect("http://example.com").await, Ok("default"));
+    }
+}
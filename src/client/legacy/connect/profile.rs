@@ -0,0 +1,272 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "tokio")]
+pub use self::tcp::{TcpProfiled, TcpProfiledStream};
+
+/// How the TCP socket of a connection writes requests.
+///
+/// Bulk uploads and low-latency RPCs want opposite socket settings. Insert
+/// this into the extensions of a request to choose the settings used while
+/// it is written, or set a default for a `Client` with
+/// [`Builder::tcp_profile`](crate::client::legacy::Builder::tcp_profile).
+///
+/// This takes effect on connections from a [`TcpProfiled`] connector. On
+/// an HTTP/2 connection, the profile of the most recent request applies to
+/// the whole connection.
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::connect::TcpProfile;
+///
+/// let mut req = http::Request::new(());
+/// req.extensions_mut().insert(TcpProfile::Bulk);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TcpProfile {
+    /// Send every write at once, with `TCP_NODELAY` set.
+    LowLatency,
+    /// Send full segments, with `TCP_NODELAY` unset and, on Linux and
+    /// Android, `TCP_CORK` set while writing. The socket is uncorked when
+    /// hyper flushes, so the end of a write isn't held back.
+    Bulk,
+}
+
+// The profile the `Client` asked of a connection, read by the stream the
+// next time it writes.
+#[derive(Clone, Debug)]
+pub(crate) struct ProfileSwitch(Arc<AtomicU8>);
+
+const UNSET: u8 = 0;
+const LOW_LATENCY: u8 = 1;
+const BULK: u8 = 2;
+
+impl ProfileSwitch {
+    #[cfg(feature = "tokio")]
+    fn new() -> ProfileSwitch {
+        ProfileSwitch(Arc::new(AtomicU8::new(UNSET)))
+    }
+
+    #[cfg_attr(not(any(feature = "http1", feature = "http2")), allow(dead_code))]
+    pub(crate) fn set(&self, profile: TcpProfile) {
+        let value = match profile {
+            TcpProfile::LowLatency => LOW_LATENCY,
+            TcpProfile::Bulk => BULK,
+        };
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "tokio")]
+    fn get(&self) -> Option<TcpProfile> {
+        match self.0.load(Ordering::Relaxed) {
+            LOW_LATENCY => Some(TcpProfile::LowLatency),
+            BULK => Some(TcpProfile::Bulk),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod tcp {
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{self, Poll};
+
+    use http::Uri;
+    use hyper::rt::{Read, ReadBufCursor, Write};
+    use tokio::net::TcpStream;
+    use tower_service::Service;
+    use tracing::warn;
+
+    use super::{ProfileSwitch, TcpProfile};
+    use crate::client::legacy::connect::{Connected, Connection};
+    use crate::rt::TokioIo;
+
+    /// A connector whose TCP connections can switch [`TcpProfile`]s per
+    /// request.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hyper_util::client::legacy::connect::{HttpConnector, TcpProfiled};
+    ///
+    /// let connector = TcpProfiled::new(HttpConnector::new());
+    /// ```
+    #[derive(Clone, Debug)]
+    pub struct TcpProfiled<C> {
+        inner: C,
+    }
+
+    /// A TCP stream from a [`TcpProfiled`] connector.
+    #[derive(Debug)]
+    pub struct TcpProfiledStream {
+        inner: TokioIo<TcpStream>,
+        switch: ProfileSwitch,
+        applied: Option<TcpProfile>,
+        corked: bool,
+    }
+
+    // ===== impl TcpProfiled =====
+
+    impl<C> TcpProfiled<C> {
+        /// Wrap a connector of TCP streams, such as the `HttpConnector`.
+        pub fn new(inner: C) -> TcpProfiled<C> {
+            TcpProfiled { inner }
+        }
+    }
+
+    impl<C> Service<Uri> for TcpProfiled<C>
+    where
+        C: Service<Uri, Response = TokioIo<TcpStream>>,
+        C::Future: Send + 'static,
+    {
+        type Response = TcpProfiledStream;
+        type Error = C::Error;
+        type Future =
+            futures_util::future::MapOk<C::Future, fn(TokioIo<TcpStream>) -> TcpProfiledStream>;
+
+        fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, dst: Uri) -> Self::Future {
+            use futures_util::TryFutureExt;
+
+            self.inner.call(dst).map_ok(TcpProfiledStream::new)
+        }
+    }
+
+    // ===== impl TcpProfiledStream =====
+
+    impl TcpProfiledStream {
+        fn new(inner: TokioIo<TcpStream>) -> TcpProfiledStream {
+            TcpProfiledStream {
+                inner,
+                switch: ProfileSwitch::new(),
+                applied: None,
+                corked: false,
+            }
+        }
+
+        /// Get a reference to the inner stream.
+        pub fn get_ref(&self) -> &TcpStream {
+            self.inner.inner()
+        }
+
+        // Settings are best effort, a failure shouldn't fail the request.
+        fn before_write(&mut self) {
+            let profile = match self.switch.get() {
+                Some(profile) => profile,
+                None => return,
+            };
+            if self.applied != Some(profile) {
+                let nodelay = profile == TcpProfile::LowLatency;
+                if let Err(e) = self.get_ref().set_nodelay(nodelay) {
+                    warn!("tcp set_nodelay error: {}", e);
+                }
+                self.applied = Some(profile);
+            }
+            self.set_cork(profile == TcpProfile::Bulk);
+        }
+
+        fn set_cork(&mut self, cork: bool) {
+            if self.corked == cork {
+                return;
+            }
+            self.corked = cork;
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            if let Err(e) = socket2::SockRef::from(self.get_ref()).set_cork(cork) {
+                warn!("tcp set_cork error: {}", e);
+            }
+        }
+    }
+
+    impl Connection for TcpProfiledStream {
+        fn connected(&self) -> Connected {
+            let mut connected = self.inner.connected();
+            connected.tcp_profile = Some(self.switch.clone());
+            connected
+        }
+    }
+
+    impl Read for TcpProfiledStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut task::Context<'_>,
+            buf: ReadBufCursor<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl Write for TcpProfiledStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut task::Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            self.before_write();
+            Pin::new(&mut self.inner).poll_write(cx, buf)
+        }
+
+        fn poll_write_vectored(
+            mut self: Pin<&mut Self>,
+            cx: &mut task::Context<'_>,
+            bufs: &[io::IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            self.before_write();
+            Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            self.inner.is_write_vectored()
+        }
+
+        fn poll_flush(
+            mut self: Pin<&mut Self>,
+            cx: &mut task::Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            futures_util::ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+            self.set_cork(false);
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut task::Context<'_>,
+        ) -> Poll<io::Result<()>> {
+            self.set_cork(false);
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use tokio::net::TcpListener;
+        use tower_service::Service;
+
+        use super::TcpProfiled;
+        use crate::client::legacy::connect::{Connection, HttpConnector, TcpProfile};
+
+        #[tokio::test]
+        async fn switches_nodelay() {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let dst = format!("http://{}", listener.local_addr().unwrap())
+                .parse()
+                .unwrap();
+            let mut connector = HttpConnector::new();
+            connector.set_nodelay(true);
+            let mut stream = TcpProfiled::new(connector).call(dst).await.unwrap();
+
+            let switch = stream.connected().tcp_profile.expect("switch");
+            switch.set(TcpProfile::Bulk);
+            stream.before_write();
+            assert!(!stream.get_ref().nodelay().unwrap());
+
+            switch.set(TcpProfile::LowLatency);
+            stream.before_write();
+            assert!(stream.get_ref().nodelay().unwrap());
+        }
+    }
+}
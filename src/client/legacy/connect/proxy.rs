@@ -0,0 +1,412 @@
+//! Proxy helpers
+//!
+//! A [`Tunnel`] wraps another connector, such as the
+//! [`HttpConnector`](super::HttpConnector), and connects to the destination
+//! through an HTTP proxy using `CONNECT`. The returned transport talks
+//! directly to the destination, so a TLS connector can be layered on top of
+//! it for `https` destinations.
+//!
+//! # Pooling
+//!
+//! The `Client` pools connections by the scheme and authority of the
+//! destination, so a tunnel is only ever reused for requests to the same
+//! target it was opened for. When the connector may route a destination
+//! through more than one proxy, tag requests with the
+//! [`PoolTag`](super::super::PoolTag) from [`Tunnel::pool_tag`], so tunnels
+//! through different proxies are never handed out for each other.
+//!
+//! A proxy closing the tunnel closes the connection, which takes it out of
+//! the pool. If a tunnel is otherwise known to be broken, it can be removed
+//! with [`Connected::poison`](super::Connected::poison).
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::marker::Unpin;
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use futures_util::future::poll_fn;
+use http::{HeaderMap, HeaderValue, Uri};
+use hyper::rt::{Read, ReadBuf, Write};
+use pin_project_lite::pin_project;
+use tower_service::Service;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+// Proxies send a short response to `CONNECT`, anything bigger is refused.
+const MAX_RESPONSE_HEAD: usize = 8 * 1024;
+
+/// A connector that tunnels through an HTTP proxy with `CONNECT`.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # fn doc() {
+/// use hyper_util::client::legacy::connect::{proxy::Tunnel, HttpConnector};
+///
+/// let proxy = "http://proxy.local:3128".parse().unwrap();
+/// let tunnel = Tunnel::new(proxy, HttpConnector::new());
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Tunnel<C> {
+    headers: HeaderMap,
+    inner: C,
+    proxy_dst: Uri,
+}
+
+/// An error occurred while establishing a tunnel.
+pub struct TunnelError {
+    kind: Kind,
+    source: Option<BoxError>,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Connect,
+    Io,
+    MissingHost,
+    ProxyAuthRequired,
+    ProxyHeadersTooLong,
+    UnexpectedEof,
+    Unsuccessful(u16),
+}
+
+pin_project! {
+    /// A `Future` resolving to a tunneled connection.
+    ///
+    /// This is returned by calling a [`Tunnel`].
+    #[must_use = "futures do nothing unless polled"]
+    #[allow(missing_debug_implementations)]
+    pub struct Tunneling<T> {
+        #[pin]
+        fut: BoxTunneling<T>,
+    }
+}
+
+type BoxTunneling<T> = Pin<Box<dyn Future<Output = Result<T, TunnelError>> + Send>>;
+
+// ===== impl Tunnel =====
+
+impl<C> Tunnel<C> {
+    /// Create a tunnel through the proxy at `proxy_dst`, connecting to the
+    /// proxy with `connector`.
+    pub fn new(proxy_dst: Uri, connector: C) -> Self {
+        Tunnel {
+            headers: HeaderMap::new(),
+            inner: connector,
+            proxy_dst,
+        }
+    }
+
+    /// Send a `Proxy-Authorization` header with every `CONNECT`.
+    pub fn with_auth(mut self, mut auth: HeaderValue) -> Self {
+        auth.set_sensitive(true);
+        self.headers.insert(http::header::PROXY_AUTHORIZATION, auth);
+        self
+    }
+
+    /// Send extra headers with every `CONNECT`.
+    ///
+    /// These are added to any headers already set, replacing those with the
+    /// same name.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        let mut name = None;
+        for (key, value) in headers {
+            // `None` means another value for the previous name.
+            if let Some(key) = key {
+                self.headers.remove(&key);
+                name = Some(key);
+            }
+            let key = name.clone().expect("HeaderMap yields a name first");
+            self.headers.append(key, value);
+        }
+        self
+    }
+
+    /// The proxy this tunnel connects through.
+    pub fn proxy(&self) -> &Uri {
+        &self.proxy_dst
+    }
+
+    /// A [`PoolTag`](super::super::PoolTag) naming the proxy of this tunnel.
+    #[cfg(any(feature = "http1", feature = "http2"))]
+    pub fn pool_tag(&self) -> super::super::PoolTag {
+        let proxy = self
+            .proxy_dst
+            .authority()
+            .map_or("", |authority| authority.as_str());
+        super::super::PoolTag::new(format!("proxy={}", proxy))
+    }
+}
+
+impl<C> Service<Uri> for Tunnel<C>
+where
+    C: Service<Uri>,
+    C::Future: Send + 'static,
+    C::Response: Read + Write + Unpin + Send + 'static,
+    C::Error: Into<BoxError>,
+{
+    type Response = C::Response;
+    type Error = TunnelError;
+    type Future = Tunneling<C::Response>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(TunnelError::connect)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let connecting = self.inner.call(self.proxy_dst.clone());
+        let headers = self.headers.clone();
+
+        Tunneling {
+            fut: Box::pin(async move {
+                let target = target(&dst)?;
+                let conn = connecting.await.map_err(TunnelError::connect)?;
+                tunnel(conn, &target, &headers).await
+            }),
+        }
+    }
+}
+
+fn target(dst: &Uri) -> Result<String, TunnelError> {
+    let host = dst.host().ok_or(TunnelError::new(Kind::MissingHost))?;
+    let port = match dst.port_u16() {
+        Some(port) => port,
+        None if dst.scheme_str() == Some("http") => 80,
+        None => 443,
+    };
+    Ok(format!("{}:{}", host, port))
+}
+
+async fn tunnel<T>(mut conn: T, target: &str, headers: &HeaderMap) -> Result<T, TunnelError>
+where
+    T: Read + Write + Unpin,
+{
+    let mut req = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target).into_bytes();
+    for (name, value) in headers {
+        req.extend_from_slice(name.as_str().as_bytes());
+        req.extend_from_slice(b": ");
+        req.extend_from_slice(value.as_bytes());
+        req.extend_from_slice(b"\r\n");
+    }
+    req.extend_from_slice(b"\r\n");
+
+    let mut written = 0;
+    while written < req.len() {
+        let n = poll_fn(|cx| Pin::new(&mut conn).poll_write(cx, &req[written..]))
+            .await
+            .map_err(TunnelError::io)?;
+        if n == 0 {
+            return Err(TunnelError::io(io::ErrorKind::WriteZero.into()));
+        }
+        written += n;
+    }
+    poll_fn(|cx| Pin::new(&mut conn).poll_flush(cx))
+        .await
+        .map_err(TunnelError::io)?;
+
+    // Read a byte at a time so nothing the destination sends after the
+    // response head is consumed here.
+    let mut head = Vec::with_capacity(128);
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_RESPONSE_HEAD {
+            return Err(TunnelError::new(Kind::ProxyHeadersTooLong));
+        }
+        let mut byte = [0u8];
+        let n = poll_fn(|cx| {
+            let mut buf = ReadBuf::new(&mut byte);
+            futures_util::ready!(Pin::new(&mut conn).poll_read(cx, buf.unfilled()))?;
+            Poll::Ready(Ok::<_, io::Error>(buf.filled().len()))
+        })
+        .await
+        .map_err(TunnelError::io)?;
+        if n == 0 {
+            return Err(TunnelError::new(Kind::UnexpectedEof));
+        }
+        head.push(byte[0]);
+    }
+
+    match status(&head) {
+        Some(200..=299) => Ok(conn),
+        Some(407) => Err(TunnelError::new(Kind::ProxyAuthRequired)),
+        Some(code) => Err(TunnelError::new(Kind::Unsuccessful(code))),
+        None => Err(TunnelError::io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid CONNECT response",
+        ))),
+    }
+}
+
+fn status(head: &[u8]) -> Option<u16> {
+    let line = head.split(|&b| b == b'\n').next()?;
+    let line = std::str::from_utf8(line).ok()?;
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+// ===== impl Tunneling =====
+
+impl<T> Future for Tunneling<T> {
+    type Output = Result<T, TunnelError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        self.project().fut.poll(cx)
+    }
+}
+
+// ===== impl TunnelError =====
+
+impl TunnelError {
+    fn new(kind: Kind) -> TunnelError {
+        TunnelError { kind, source: None }
+    }
+
+    fn connect<E: Into<BoxError>>(err: E) -> TunnelError {
+        TunnelError {
+            kind: Kind::Connect,
+            source: Some(err.into()),
+        }
+    }
+
+    fn io(err: io::Error) -> TunnelError {
+        TunnelError {
+            kind: Kind::Io,
+            source: Some(err.into()),
+        }
+    }
+
+    /// Returns true if connecting to the proxy failed.
+    pub fn is_connect(&self) -> bool {
+        matches!(self.kind, Kind::Connect)
+    }
+
+    /// Returns true if the proxy required authentication.
+    pub fn is_proxy_auth_required(&self) -> bool {
+        matches!(self.kind, Kind::ProxyAuthRequired)
+    }
+
+    /// The status code the proxy refused the tunnel with, if any.
+    pub fn status(&self) -> Option<u16> {
+        match self.kind {
+            Kind::ProxyAuthRequired => Some(407),
+            Kind::Unsuccessful(code) => Some(code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for TunnelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_tuple("hyper_util::client::legacy::connect::proxy::TunnelError");
+        f.field(&self.kind);
+        if let Some(ref source) = self.source {
+            f.field(source);
+        }
+        f.finish()
+    }
+}
+
+impl fmt::Display for TunnelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::Connect => f.write_str("error connecting to proxy"),
+            Kind::Io => f.write_str("io error establishing tunnel"),
+            Kind::MissingHost => f.write_str("tunnel destination has no host"),
+            Kind::ProxyAuthRequired => f.write_str("proxy authorization required"),
+            Kind::ProxyHeadersTooLong => f.write_str("proxy response headers too long"),
+            Kind::UnexpectedEof => f.write_str("unexpected eof establishing tunnel"),
+            Kind::Unsuccessful(code) => write!(f, "proxy refused tunnel with status {}", code),
+        }
+    }
+}
+
+impl StdError for TunnelError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| &**e as _)
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tower_service::Service;
+
+    use super::super::HttpConnector;
+    use super::Tunnel;
+    use crate::rt::TokioIo;
+
+    async fn proxy(response: &'static [u8]) -> (http::Uri, tokio::task::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let uri = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let handle = tokio::spawn(async move {
+            let (mut sock, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            while !head.ends_with(b"\r\n\r\n") {
+                let mut byte = [0u8];
+                sock.read_exact(&mut byte).await.unwrap();
+                head.push(byte[0]);
+            }
+            sock.write_all(response).await.unwrap();
+            let mut echo = [0u8; 5];
+            if sock.read_exact(&mut echo).await.is_ok() {
+                sock.write_all(&echo).await.unwrap();
+            }
+            head
+        });
+        (uri, handle)
+    }
+
+    #[tokio::test]
+    async fn tunnel_connects() {
+        let (proxy_dst, handle) = proxy(b"HTTP/1.1 200 Connection established\r\n\r\n").await;
+        let mut tunnel = Tunnel::new(proxy_dst, HttpConnector::new())
+            .with_auth(http::HeaderValue::from_static("Basic dTpw"));
+
+        let conn = tunnel
+            .call("https://hyper.rs/guide".parse().unwrap())
+            .await
+            .expect("tunnel");
+        let mut conn = TokioIo::new(conn);
+        conn.write_all(b"hello").await.unwrap();
+        let mut echo = [0u8; 5];
+        conn.read_exact(&mut echo).await.unwrap();
+        assert_eq!(&echo, b"hello");
+
+        let head = handle.await.unwrap();
+        assert_eq!(
+            head,
+            &b"CONNECT hyper.rs:443 HTTP/1.1\r\nHost: hyper.rs:443\r\n\
+               proxy-authorization: Basic dTpw\r\n\r\n"[..]
+        );
+    }
+
+    #[tokio::test]
+    async fn tunnel_refused() {
+        let (proxy_dst, _handle) = proxy(b"HTTP/1.1 407 Proxy Auth Required\r\n\r\n").await;
+        let mut tunnel = Tunnel::new(proxy_dst, HttpConnector::new());
+
+        let err = tunnel
+            .call("http://hyper.rs".parse().unwrap())
+            .await
+            .expect_err("refused");
+        assert!(err.is_proxy_auth_required());
+        assert_eq!(err.status(), Some(407));
+    }
+
+    #[test]
+    fn pool_tag_names_proxy() {
+        let a = Tunnel::new("http://a.local:3128".parse().unwrap(), ());
+        let b = Tunnel::new("http://b.local:3128".parse().unwrap(), ());
+        assert_eq!(a.pool_tag(), a.clone().pool_tag());
+        assert_ne!(a.pool_tag(), b.pool_tag());
+    }
+}
@@ -0,0 +1,254 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{self, Poll};
+
+use http::Uri;
+
+/// A connector that dials with several connectors at once, and uses the
+/// connection established first.
+///
+/// This is for destinations that can be reached in more than one way, and
+/// where the fastest isn't known in advance: directly and through a proxy,
+/// or from two local interfaces. Each connector is a strategy, and a
+/// [`RacePolicy`] picks the strategies to race for each destination, by
+/// default all of them. Once one connects, the others are canceled by
+/// dropping their futures.
+///
+/// The strategies must have the same connector type, so strategies with
+/// different connectors need a common type, such as an enum.
+///
+/// # Example
+///
+/// Race two local addresses:
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # fn run() {
+/// use std::net::{IpAddr, Ipv4Addr};
+/// use hyper_util::client::legacy::connect::{HttpConnector, Racing};
+///
+/// let mut wired = HttpConnector::new();
+/// wired.set_local_address(Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10))));
+/// let mut wireless = HttpConnector::new();
+/// wireless.set_local_address(Some(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20))));
+///
+/// let connector = Racing::new(vec![wired, wireless]);
+/// # drop(connector);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Racing<C> {
+    strategies: Vec<C>,
+    policy: Arc<dyn RacePolicy>,
+}
+
+/// Decides which strategies of a [`Racing`] connector to race for a
+/// destination.
+///
+/// It is implemented for closures taking the destination and the number of
+/// strategies.
+pub trait RacePolicy: Send + Sync {
+    /// The indexes of the strategies to race for `dst`, out of
+    /// `strategies`, most preferred first.
+    ///
+    /// Indexes out of range are ignored. If none is left, every strategy
+    /// is raced.
+    fn candidates(&self, dst: &Uri, strategies: usize) -> Vec<usize>;
+}
+
+type Attempt<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
+
+// ===== impl Racing =====
+
+impl<C> Racing<C> {
+    /// Create a connector racing every one of `strategies`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `strategies` is empty.
+    pub fn new(strategies: Vec<C>) -> Racing<C> {
+        assert!(!strategies.is_empty(), "Racing needs a strategy");
+        Racing {
+            strategies,
+            policy: Arc::new(|_: &Uri, strategies: usize| (0..strategies).collect()),
+        }
+    }
+
+    /// Set the policy picking the strategies to race for each destination.
+    pub fn policy<P>(mut self, policy: P) -> Racing<C>
+    where
+        P: RacePolicy + 'static,
+    {
+        self.policy = Arc::new(policy);
+        self
+    }
+
+    fn candidates(&self, dst: &Uri) -> Vec<usize> {
+        let count = self.strategies.len();
+        let mut candidates = self.policy.candidates(dst, count);
+        candidates.retain(|&i| i < count);
+        let mut seen = vec![false; count];
+        candidates.retain(|&i| !std::mem::replace(&mut seen[i], true));
+        if candidates.is_empty() {
+            candidates = (0..count).collect();
+        }
+        candidates
+    }
+}
+
+impl<F> RacePolicy for F
+where
+    F: Fn(&Uri, usize) -> Vec<usize> + Send + Sync,
+{
+    fn candidates(&self, dst: &Uri, strategies: usize) -> Vec<usize> {
+        self(dst, strategies)
+    }
+}
+
+impl<C> tower_service::Service<Uri> for Racing<C>
+where
+    C: tower_service::Service<Uri>,
+    C::Future: Send + 'static,
+    C::Response: Send + 'static,
+    C::Error: Send + 'static,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        for strategy in &mut self.strategies {
+            futures_util::ready!(strategy.poll_ready(cx))?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let mut attempts = self
+            .candidates(&dst)
+            .into_iter()
+            .map(|i| Some(Box::pin(self.strategies[i].call(dst.clone())) as Attempt<_, _>))
+            .collect::<Vec<_>>();
+        let mut errors = attempts.iter().map(|_| None).collect::<Vec<_>>();
+
+        Box::pin(futures_util::future::poll_fn(move |cx| {
+            for (attempt, error) in attempts.iter_mut().zip(errors.iter_mut()) {
+                let result = match attempt {
+                    Some(future) => match future.as_mut().poll(cx) {
+                        Poll::Ready(result) => result,
+                        Poll::Pending => continue,
+                    },
+                    None => continue,
+                };
+                *attempt = None;
+                match result {
+                    // dropping the other attempts cancels them
+                    Ok(conn) => return Poll::Ready(Ok(conn)),
+                    Err(err) => *error = Some(err),
+                }
+            }
+            if attempts.iter().any(Option::is_some) {
+                return Poll::Pending;
+            }
+            // every attempt failed, report the most preferred
+            let err = errors
+                .iter_mut()
+                .find_map(Option::take)
+                .expect("failed attempts have errors");
+            Poll::Ready(Err(err))
+        }))
+    }
+}
+
+impl<C> fmt::Debug for Racing<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Racing")
+            .field("strategies", &self.strategies.len())
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use http::Uri;
+    use tower_service::Service;
+
+    use super::Racing;
+
+    // A strategy connecting after `delay`, or failing without one.
+    #[derive(Clone)]
+    struct Strategy {
+        name: &'static str,
+        delay: Option<Duration>,
+        canceled: Arc<AtomicUsize>,
+    }
+
+    struct Canceled(Arc<AtomicUsize>);
+
+    impl Drop for Canceled {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Service<Uri> for Strategy {
+        type Response = &'static str;
+        type Error = &'static str;
+        type Future = Pin<Box<dyn Future<Output = Result<&'static str, &'static str>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            let strategy = self.clone();
+            Box::pin(async move {
+                let guard = Canceled(strategy.canceled.clone());
+                let delay = strategy.delay.ok_or(strategy.name)?;
+                tokio::time::sleep(delay).await;
+                std::mem::forget(guard);
+                Ok(strategy.name)
+            })
+        }
+    }
+
+    fn strategies(delays: &[Option<u64>]) -> (Racing<Strategy>, Arc<AtomicUsize>) {
+        let names = ["a", "b", "c"];
+        let canceled = Arc::new(AtomicUsize::new(0));
+        let strategies = delays
+            .iter()
+            .zip(names.iter())
+            .map(|(delay, name)| Strategy {
+                name,
+                delay: delay.map(Duration::from_millis),
+                canceled: canceled.clone(),
+            })
+            .collect();
+        (Racing::new(strategies), canceled)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fastest_wins_and_losers_are_canceled() {
+        let (mut racing, canceled) = strategies(&[Some(50), Some(10), None]);
+        let dst = Uri::from_static("http://example.local");
+        assert_eq!(racing.call(dst.clone()).await, Ok("b"));
+        // "c" failed, and "a" was canceled
+        assert_eq!(canceled.load(Ordering::SeqCst), 2);
+
+        let (racing, _) = strategies(&[None, None, Some(10)]);
+        let mut racing = racing.policy(|_: &Uri, _| vec![1, 0]);
+        assert_eq!(racing.call(dst.clone()).await, Err("b"));
+        let mut racing = racing.policy(|_: &Uri, _| vec![7]);
+        assert_eq!(racing.call(dst).await, Ok("c"));
+    }
+}
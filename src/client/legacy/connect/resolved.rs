@@ -0,0 +1,63 @@
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Addresses to connect to instead of resolving the host of a request.
+///
+/// Insert this into the extensions of a request to have the
+/// [`HttpConnector`](super::HttpConnector) connect to these addresses, in
+/// order, without any DNS lookup, for callers with their own service
+/// discovery. The addresses keep their ports, and the IP family and
+/// negative cache settings of the connector still apply.
+///
+/// It applies to the connection dialed for the request. Connections are
+/// still pooled by host, so the request may reuse one dialed earlier to
+/// other addresses; a [`PoolTag`](crate::client::legacy::PoolTag) keeps
+/// them apart. Through a proxy, it applies to the connection to the
+/// proxy.
+///
+/// # Example
+///
+/// ```
+/// use std::net::SocketAddr;
+/// use hyper_util::client::legacy::connect::ResolvedAddrs;
+///
+/// let addr: SocketAddr = "10.0.0.7:8080".parse().unwrap();
+/// let mut req = http::Request::new(());
+/// req.extensions_mut().insert(ResolvedAddrs::new(vec![addr]));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolvedAddrs(Arc<[SocketAddr]>);
+
+impl ResolvedAddrs {
+    /// Create the addresses to connect to, most preferred first.
+    pub fn new(addrs: Vec<SocketAddr>) -> ResolvedAddrs {
+        ResolvedAddrs(addrs.into())
+    }
+
+    /// The addresses to connect to.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.0
+    }
+}
+
+// The `Client` can't hand a connector more than a `Uri`, so the addresses
+// of the request are set here while its connect future is polled.
+thread_local! {
+    static RESOLVED: RefCell<Option<ResolvedAddrs>> = const { RefCell::new(None) };
+}
+
+/// Poll a connect future with `f`, with `addrs` overriding its resolution.
+#[cfg_attr(not(any(feature = "http1", feature = "http2")), allow(dead_code))]
+pub(crate) fn overriding<T>(addrs: Option<&ResolvedAddrs>, f: impl FnOnce() -> T) -> T {
+    let outer = RESOLVED.with(|r| r.replace(addrs.cloned()));
+    let out = f();
+    RESOLVED.with(|r| r.replace(outer));
+    out
+}
+
+/// The addresses overriding the resolution of the connector being polled.
+#[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+pub(crate) fn current() -> Option<ResolvedAddrs> {
+    RESOLVED.with(|r| r.borrow().clone())
+}
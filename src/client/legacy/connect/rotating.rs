@@ -0,0 +1,301 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{self, Poll};
+
+use http::Uri;
+use hyper::rt::{Read, ReadBufCursor, Write};
+
+use super::{Connected, Connection};
+
+/// A connector that can be replaced while in use, retiring the connections
+/// of the connector it replaces.
+///
+/// This is for rotating credentials that are part of the connector, most
+/// often the client certificate of a TLS connector, when certificates are
+/// short-lived. After [`rotate`](Rotating::rotate), new connections are
+/// dialed with the new connector, and connections dialed with the old one
+/// are [poisoned](Connected::poison): each finishes its current request
+/// and is then closed instead of being reused, so the old identity isn't
+/// kept around in the pool.
+///
+/// Clones share the connector, so keep one to rotate it.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # fn run() {
+/// use hyper_util::client::legacy::connect::{HttpConnector, Rotating};
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::TokioExecutor;
+///
+/// let connector = Rotating::new(HttpConnector::new());
+/// let client = Client::builder(TokioExecutor::new())
+///     .build::<_, http_body_util::Empty<bytes::Bytes>>(connector.clone());
+///
+/// // later, with a connector holding the renewed certificate
+/// connector.rotate(HttpConnector::new());
+/// # drop(client);
+/// # }
+/// ```
+pub struct Rotating<C> {
+    shared: Arc<Mutex<Shared<C>>>,
+}
+
+/// A connection dialed by a [`Rotating`] connector.
+pub struct RotatingStream<T> {
+    inner: T,
+    generation: u64,
+    shared: Weak<dyn Track>,
+}
+
+struct Shared<C> {
+    connector: C,
+    generation: u64,
+    // the poison pills of the connections of this generation
+    connections: Vec<Weak<AtomicBool>>,
+}
+
+// Erases the connector type from the streams.
+trait Track: Send + Sync {
+    fn track(&self, generation: u64, connected: &Connected);
+}
+
+// ===== impl Rotating =====
+
+impl<C> Rotating<C> {
+    /// Create a connector dialing with `connector`, until rotated.
+    pub fn new(connector: C) -> Rotating<C> {
+        Rotating {
+            shared: Arc::new(Mutex::new(Shared {
+                connector,
+                generation: 0,
+                connections: Vec::new(),
+            })),
+        }
+    }
+
+    /// Replace the connector, and retire the connections of the previous
+    /// ones.
+    ///
+    /// Connections being dialed with a previous connector are retired once
+    /// established.
+    pub fn rotate(&self, connector: C) {
+        let retired = {
+            let mut shared = self.shared.lock().unwrap();
+            shared.connector = connector;
+            shared.generation += 1;
+            std::mem::take(&mut shared.connections)
+        };
+        for pill in retired.iter().filter_map(Weak::upgrade) {
+            super::PoisonPill(pill).poison();
+        }
+    }
+}
+
+impl<C> Track for Mutex<Shared<C>>
+where
+    C: Send,
+{
+    fn track(&self, generation: u64, connected: &Connected) {
+        let mut shared = self.lock().unwrap();
+        if generation != shared.generation {
+            connected.poison();
+            return;
+        }
+        shared.connections.retain(|pill| pill.strong_count() > 0);
+        shared
+            .connections
+            .push(Arc::downgrade(&connected.poisoned.0));
+    }
+}
+
+impl<C> tower_service::Service<Uri> for Rotating<C>
+where
+    C: tower_service::Service<Uri> + Clone + Send + 'static,
+    C::Future: Send,
+{
+    type Response = RotatingStream<C::Response>;
+    type Error = C::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let (mut connector, generation) = {
+            let shared = self.shared.lock().unwrap();
+            (shared.connector.clone(), shared.generation)
+        };
+        let shared = Arc::downgrade(&self.shared) as Weak<dyn Track>;
+        Box::pin(async move {
+            futures_util::future::poll_fn(|cx| connector.poll_ready(cx)).await?;
+            let inner = connector.call(dst).await?;
+            Ok(RotatingStream {
+                inner,
+                generation,
+                shared,
+            })
+        })
+    }
+}
+
+impl<C> Clone for Rotating<C> {
+    fn clone(&self) -> Rotating<C> {
+        Rotating {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for Rotating<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        f.debug_struct("Rotating")
+            .field("generation", &shared.generation)
+            .finish()
+    }
+}
+
+// ===== impl RotatingStream =====
+
+impl<T> RotatingStream<T> {
+    /// Get a reference to the inner connection.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner connection.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: Connection> Connection for RotatingStream<T> {
+    fn connected(&self) -> Connected {
+        let connected = self.inner.connected();
+        // once the connector is gone, it can't rotate anymore
+        if let Some(shared) = self.shared.upgrade() {
+            shared.track(self.generation, &connected);
+        }
+        connected
+    }
+}
+
+impl<T> Read for RotatingStream<T>
+where
+    T: Read + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T> Write for RotatingStream<T>
+where
+    T: Write + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RotatingStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RotatingStream")
+            .field("inner", &self.inner)
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::{ready, Ready};
+    use std::task::{Context, Poll};
+
+    use http::Uri;
+    use tower_service::Service;
+
+    use super::Rotating;
+    use crate::client::legacy::connect::{Connected, Connection};
+
+    struct Conn;
+
+    impl Connection for Conn {
+        fn connected(&self) -> Connected {
+            Connected::new()
+        }
+    }
+
+    #[derive(Clone)]
+    struct Dial;
+
+    impl Service<Uri> for Dial {
+        type Response = Conn;
+        type Error = Infallible;
+        type Future = Ready<Result<Conn, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            ready(Ok(Conn))
+        }
+    }
+
+    #[tokio::test]
+    async fn rotate_retires_old_connections() {
+        let mut connector = Rotating::new(Dial);
+        let dst = Uri::from_static("https://example.com");
+
+        let old = connector.call(dst.clone()).await.unwrap();
+        let old = old.connected();
+        let dialing = connector.call(dst.clone()).await.unwrap();
+        assert!(!old.is_poisoned());
+
+        connector.rotate(Dial);
+        assert!(old.is_poisoned());
+        // dialed before the rotation, established after
+        assert!(dialing.connected().is_poisoned());
+
+        let new = connector.call(dst).await.unwrap().connected();
+        assert!(!new.is_poisoned());
+    }
+}
@@ -0,0 +1,235 @@
+use std::cell::RefCell;
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{self, Poll};
+
+use http::Uri;
+
+/// A connector that dials with one of several named connectors, picked by
+/// each request.
+///
+/// This keeps one `Client`, and one pool, for requests that go out in
+/// different ways, such as directly, through a proxy, or over a Unix
+/// socket. A request picks a connector by adding a [`SelectConnector`] to
+/// its extensions, and requests without one use the default connector. The
+/// name is part of the key connections are pooled by, so requests only
+/// reuse connections dialed by the connector they picked.
+///
+/// The connectors must have the same type, so connectors of different
+/// types need a common type, such as an enum.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # fn run() {
+/// use std::net::{IpAddr, Ipv4Addr};
+/// use hyper_util::client::legacy::connect::{HttpConnector, SelectConnector, Selecting};
+///
+/// let mut backup = HttpConnector::new();
+/// backup.set_local_address(Some(IpAddr::V4(Ipv4Addr::new(192, 168, 2, 10))));
+///
+/// let connector = Selecting::new(HttpConnector::new()).connector("backup", backup);
+///
+/// let mut req = http::Request::new(());
+/// req.extensions_mut().insert(SelectConnector::new("backup"));
+/// # drop(connector);
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Selecting<C> {
+    default: C,
+    named: Vec<(SelectConnector, C)>,
+}
+
+/// The name of the connector of a [`Selecting`] connector to dial a
+/// request with.
+///
+/// Insert this into the extensions of a request. Connecting fails if the
+/// connector has no connector with the name. With other connectors, the
+/// name still keeps the connections of the request apart in the pool.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SelectConnector(Arc<str>);
+
+// A request named a connector that isn't registered.
+#[derive(Debug)]
+struct UnknownConnector(SelectConnector);
+
+type Connecting<T> =
+    Pin<Box<dyn Future<Output = Result<T, Box<dyn StdError + Send + Sync>>> + Send>>;
+
+// The `Client` can't hand a connector more than a `Uri`, so the connector
+// picked by the request is set here while its connect future is polled,
+// which calls the connector.
+thread_local! {
+    static SELECTED: RefCell<Option<SelectConnector>> = const { RefCell::new(None) };
+}
+
+/// Poll a connect future with `f`, with `name` selected.
+#[cfg_attr(not(any(feature = "http1", feature = "http2")), allow(dead_code))]
+pub(crate) fn selecting<T>(name: Option<&SelectConnector>, f: impl FnOnce() -> T) -> T {
+    let outer = SELECTED.with(|s| s.replace(name.cloned()));
+    let out = f();
+    SELECTED.with(|s| s.replace(outer));
+    out
+}
+
+fn current() -> Option<SelectConnector> {
+    SELECTED.with(|s| s.borrow().clone())
+}
+
+// ===== impl Selecting =====
+
+impl<C> Selecting<C> {
+    /// Create a connector dialing with `default`, for requests that don't
+    /// select another one.
+    pub fn new(default: C) -> Selecting<C> {
+        Selecting {
+            default,
+            named: Vec::new(),
+        }
+    }
+
+    /// Add a connector, for requests selecting `name`.
+    ///
+    /// A connector added with the same name replaces the previous one.
+    pub fn connector(mut self, name: impl Into<SelectConnector>, connector: C) -> Selecting<C> {
+        let name = name.into();
+        self.named.retain(|(other, _)| *other != name);
+        self.named.push((name, connector));
+        self
+    }
+}
+
+impl<C> tower_service::Service<Uri> for Selecting<C>
+where
+    C: tower_service::Service<Uri>,
+    C::Future: Send + 'static,
+    C::Response: Send + 'static,
+    C::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Response = C::Response;
+    type Error = Box<dyn StdError + Send + Sync>;
+    type Future = Connecting<Self::Response>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        futures_util::ready!(self.default.poll_ready(cx)).map_err(Into::into)?;
+        for (_, connector) in &mut self.named {
+            futures_util::ready!(connector.poll_ready(cx)).map_err(Into::into)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        let connector = match current() {
+            Some(name) => match self.named.iter_mut().find(|(other, _)| *other == name) {
+                Some((_, connector)) => connector,
+                None => {
+                    let err = UnknownConnector(name);
+                    return Box::pin(async move { Err(err.into()) });
+                }
+            },
+            None => &mut self.default,
+        };
+        let connecting = connector.call(dst);
+        Box::pin(async move { connecting.await.map_err(Into::into) })
+    }
+}
+
+impl<C> fmt::Debug for Selecting<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Selecting")
+            .field(
+                "connectors",
+                &self.named.iter().map(|(name, _)| name).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+// ===== impl SelectConnector =====
+
+impl SelectConnector {
+    /// Select the connector named `name`.
+    pub fn new(name: impl Into<String>) -> SelectConnector {
+        SelectConnector(name.into().into())
+    }
+
+    /// The name of the connector.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&'_ str> for SelectConnector {
+    fn from(name: &str) -> SelectConnector {
+        SelectConnector(name.into())
+    }
+}
+
+impl From<String> for SelectConnector {
+    fn from(name: String) -> SelectConnector {
+        SelectConnector(name.into())
+    }
+}
+
+// ===== impl UnknownConnector =====
+
+impl fmt::Display for UnknownConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no connector named {:?}", self.0.as_str())
+    }
+}
+
+impl StdError for UnknownConnector {}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use http::Uri;
+    use tower_service::Service;
+
+    use super::{selecting, SelectConnector, Selecting};
+
+    // A connector answering with its name.
+    #[derive(Clone)]
+    struct Named(&'static str);
+
+    impl Service<Uri> for Named {
+        type Response = &'static str;
+        type Error = std::io::Error;
+        type Future = Pin<Box<dyn Future<Output = Result<&'static str, std::io::Error>> + Send>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Uri) -> Self::Future {
+            let name = self.0;
+            Box::pin(async move { Ok(name) })
+        }
+    }
+
+    #[tokio::test]
+    async fn calls_the_selected_connector() {
+        let mut connector = Selecting::new(Named("direct"))
+            .connector("proxy", Named("old"))
+            .connector("proxy", Named("proxy"));
+        let dst = Uri::from_static("http://example.local");
+        let mut call = |name: Option<&str>| {
+            let name = name.map(SelectConnector::new);
+            selecting(name.as_ref(), || connector.call(dst.clone()))
+        };
+
+        assert_eq!(call(None).await.unwrap(), "direct");
+        assert_eq!(call(Some("proxy")).await.unwrap(), "proxy");
+        let err = call(Some("uds")).await.unwrap_err();
+        assert_eq!(err.to_string(), "no connector named \"uds\"");
+    }
+}
@@ -0,0 +1,390 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use http::header::HeaderValue;
+use http::Uri;
+
+use super::CookieStore;
+
+/// An in-memory [`CookieStore`].
+///
+/// Public suffixes are not checked, so a server could set a cookie for a
+/// whole top-level domain.
+#[derive(Debug, Default)]
+pub struct Jar {
+    cookies: Mutex<Vec<Cookie>>,
+}
+
+/// A cookie, as stored in a [`Jar`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    domain: String,
+    host_only: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    expires: Option<SystemTime>,
+}
+
+// ===== impl Jar =====
+
+impl Jar {
+    /// Create an empty jar.
+    pub fn new() -> Jar {
+        Jar::default()
+    }
+
+    /// Add a cookie, replacing any with the same name, domain and path.
+    ///
+    /// This can be used to restore cookies saved from [`Jar::all_cookies`].
+    pub fn insert(&self, cookie: Cookie) {
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|c| {
+            !(c.name == cookie.name && c.domain == cookie.domain && c.path == cookie.path)
+        });
+        if !cookie.is_expired(SystemTime::now()) {
+            cookies.push(cookie);
+        }
+    }
+
+    /// A copy of every cookie that hasn't expired.
+    pub fn all_cookies(&self) -> Vec<Cookie> {
+        let now = SystemTime::now();
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|c| !c.is_expired(now));
+        cookies.clone()
+    }
+
+    /// Remove every cookie.
+    pub fn clear(&self) {
+        self.cookies.lock().unwrap().clear();
+    }
+}
+
+impl CookieStore for Jar {
+    fn set_cookies(&self, uri: &Uri, set_cookies: &mut dyn Iterator<Item = &HeaderValue>) {
+        for value in set_cookies {
+            if let Some(cookie) = value.to_str().ok().and_then(|s| Cookie::parse(s, uri)) {
+                self.insert(cookie);
+            }
+        }
+    }
+
+    fn cookies(&self, uri: &Uri) -> Option<HeaderValue> {
+        let host = uri.host()?.to_ascii_lowercase();
+        let path = match uri.path() {
+            "" => "/",
+            path => path,
+        };
+        let is_secure = uri.scheme_str() == Some("https");
+        let now = SystemTime::now();
+
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|c| !c.is_expired(now));
+        let mut matching = cookies
+            .iter()
+            .filter(|c| {
+                let domain_ok = if c.host_only {
+                    host == c.domain
+                } else {
+                    domain_match(&host, &c.domain)
+                };
+                domain_ok && path_match(path, &c.path) && (is_secure || !c.secure)
+            })
+            .collect::<Vec<_>>();
+        if matching.is_empty() {
+            return None;
+        }
+        // Longer paths are more specific, and go first.
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+
+        let header = matching
+            .iter()
+            .map(|c| format!("{}={}", c.name, c.value))
+            .collect::<Vec<_>>()
+            .join("; ");
+        HeaderValue::from_str(&header).ok()
+    }
+}
+
+// ===== impl Cookie =====
+
+impl Cookie {
+    /// Parse a `Set-Cookie` header value, received in a response to `uri`.
+    ///
+    /// Returns `None` if the cookie is malformed, or not allowed to be set
+    /// by `uri`, such as for another domain.
+    pub fn parse(set_cookie: &str, uri: &Uri) -> Option<Cookie> {
+        let host = uri.host()?.to_ascii_lowercase();
+        let mut attrs = set_cookie.split(';');
+        let (name, value) = attrs.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let mut cookie = Cookie {
+            name: name.to_owned(),
+            value: value.trim().to_owned(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(uri.path()),
+            secure: false,
+            http_only: false,
+            expires: None,
+        };
+        let mut max_age = None;
+
+        for attr in attrs {
+            let (key, value) = match attr.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attr.trim(), ""),
+            };
+            if key.eq_ignore_ascii_case("domain") {
+                let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                if domain.is_empty() {
+                    continue;
+                }
+                if !domain_match(&host, &domain) {
+                    return None;
+                }
+                cookie.host_only = false;
+                cookie.domain = domain;
+            } else if key.eq_ignore_ascii_case("path") {
+                if value.starts_with('/') {
+                    cookie.path = value.to_owned();
+                }
+            } else if key.eq_ignore_ascii_case("max-age") {
+                if let Ok(secs) = value.parse::<i64>() {
+                    max_age = Some(secs);
+                }
+            } else if key.eq_ignore_ascii_case("expires") {
+                if let Ok(at) = httpdate::parse_http_date(value) {
+                    cookie.expires = Some(at);
+                }
+            } else if key.eq_ignore_ascii_case("secure") {
+                cookie.secure = true;
+            } else if key.eq_ignore_ascii_case("httponly") {
+                cookie.http_only = true;
+            }
+        }
+
+        // Max-Age wins over Expires.
+        match max_age {
+            Some(secs) if secs <= 0 => cookie.expires = Some(SystemTime::UNIX_EPOCH),
+            Some(secs) => {
+                cookie.expires = SystemTime::now().checked_add(Duration::from_secs(secs as u64))
+            }
+            None => (),
+        }
+
+        // Only secure origins may set secure cookies.
+        if cookie.secure && uri.scheme_str() != Some("https") {
+            return None;
+        }
+
+        Some(cookie)
+    }
+
+    /// The name of the cookie.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The value of the cookie.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// The domain the cookie is sent to.
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    /// Returns true if the cookie is only sent to exactly its domain, and
+    /// not to subdomains.
+    pub fn is_host_only(&self) -> bool {
+        self.host_only
+    }
+
+    /// The path the cookie is sent to, including sub-paths.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns true if the cookie is only sent over `https`.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// Returns true if the cookie had the `HttpOnly` attribute.
+    pub fn is_http_only(&self) -> bool {
+        self.http_only
+    }
+
+    /// When the cookie expires, or `None` for a session cookie.
+    pub fn expires(&self) -> Option<SystemTime> {
+        self.expires
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.map_or(false, |at| at <= now)
+    }
+}
+
+/// Formats the cookie as a `Set-Cookie` value, which [`Cookie::parse`]
+/// turns back into the same cookie when given a `Uri` for its domain.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}; Path={}", self.name, self.value, self.path)?;
+        if !self.host_only {
+            write!(f, "; Domain={}", self.domain)?;
+        }
+        if let Some(at) = self.expires {
+            write!(f, "; Expires={}", httpdate::fmt_http_date(at))?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        Ok(())
+    }
+}
+
+fn domain_match(host: &str, domain: &str) -> bool {
+    if host == domain {
+        return true;
+    }
+    host.len() > domain.len()
+        && host.ends_with(domain)
+        && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+        && host.parse::<IpAddr>().is_err()
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path.as_bytes()[cookie_path.len()] == b'/'))
+}
+
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_owned(),
+        Some(idx) if path.starts_with('/') => path[..idx].to_owned(),
+        Some(_) => "/".to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::header::HeaderValue;
+    use http::Uri;
+
+    use super::{Cookie, CookieStore, Jar};
+
+    fn set(jar: &Jar, uri: &str, set_cookie: &'static str) {
+        let uri = uri.parse::<Uri>().unwrap();
+        let value = HeaderValue::from_static(set_cookie);
+        jar.set_cookies(&uri, &mut std::iter::once(&value));
+    }
+
+    fn get(jar: &Jar, uri: &str) -> Option<String> {
+        jar.cookies(&uri.parse().unwrap())
+            .map(|v| v.to_str().unwrap().to_owned())
+    }
+
+    #[test]
+    fn domain_rules() {
+        let jar = Jar::new();
+        set(&jar, "http://www.example.com/", "host=1");
+        set(
+            &jar,
+            "http://www.example.com/",
+            "wide=2; Domain=.example.com",
+        );
+        set(
+            &jar,
+            "http://www.example.com/",
+            "other=3; Domain=example.org",
+        );
+
+        assert_eq!(
+            get(&jar, "http://www.example.com/").as_deref(),
+            Some("host=1; wide=2")
+        );
+        assert_eq!(
+            get(&jar, "http://api.example.com/").as_deref(),
+            Some("wide=2")
+        );
+        assert_eq!(get(&jar, "http://notexample.com/"), None);
+        assert_eq!(get(&jar, "http://example.org/"), None);
+    }
+
+    #[test]
+    fn path_rules() {
+        let jar = Jar::new();
+        set(&jar, "http://example.com/docs/index.html", "default=1");
+        set(&jar, "http://example.com/", "docs=2; Path=/docs/api");
+        set(&jar, "http://example.com/", "root=3; Path=/");
+
+        assert_eq!(
+            get(&jar, "http://example.com/docs/api/x").as_deref(),
+            Some("docs=2; default=1; root=3")
+        );
+        assert_eq!(
+            get(&jar, "http://example.com/docs").as_deref(),
+            Some("default=1; root=3")
+        );
+        assert_eq!(
+            get(&jar, "http://example.com/docsx").as_deref(),
+            Some("root=3")
+        );
+    }
+
+    #[test]
+    fn secure_and_expiry() {
+        let jar = Jar::new();
+        // insecure origins can't set secure cookies
+        set(&jar, "http://example.com/", "a=1; Secure");
+        assert!(jar.all_cookies().is_empty());
+
+        set(&jar, "https://example.com/", "a=1; Secure; HttpOnly");
+        assert_eq!(get(&jar, "http://example.com/"), None);
+        assert_eq!(get(&jar, "https://example.com/").as_deref(), Some("a=1"));
+
+        set(&jar, "https://example.com/", "a=gone; Max-Age=0");
+        assert_eq!(get(&jar, "https://example.com/"), None);
+
+        set(
+            &jar,
+            "https://example.com/",
+            "old=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT",
+        );
+        assert!(jar.all_cookies().is_empty());
+    }
+
+    #[test]
+    fn display_roundtrip() {
+        let uri = "https://www.example.com/a/b".parse::<Uri>().unwrap();
+        let cookie = Cookie::parse(
+            "id=x; Domain=example.com; Max-Age=3600; Secure; HttpOnly",
+            &uri,
+        )
+        .unwrap();
+        assert_eq!(cookie.path(), "/a");
+        assert!(!cookie.is_host_only());
+
+        let restored = Cookie::parse(&cookie.to_string(), &uri).unwrap();
+        assert_eq!(restored.name(), "id");
+        assert_eq!(restored.domain(), "example.com");
+        assert_eq!(restored.path(), "/a");
+        assert!(restored.is_secure() && restored.is_http_only());
+        assert!(restored.expires().is_some());
+    }
+}
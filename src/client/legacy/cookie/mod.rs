@@ -0,0 +1,238 @@
+//! Cookie support for the `Client`.
+//!
+//! This module contains:
+//!
+//! - A [`CookieLayer`] that stores the cookies of every response, and adds
+//!   the matching ones to later requests.
+//! - A [`CookieStore`] trait, so cookies can be kept anywhere, such as on
+//!   disk between runs.
+//! - A default in-memory [`Jar`] store, applying the domain, path, secure
+//!   and expiry rules of [RFC 6265].
+//!
+//! # Example
+//!
+//! ```
+//! # #[cfg(feature = "tokio")]
+//! # fn run() {
+//! use std::sync::Arc;
+//! use bytes::Bytes;
+//! use http_body_util::Empty;
+//! use hyper_util::client::legacy::cookie::{CookieLayer, Jar};
+//! use hyper_util::client::legacy::Client;
+//! use hyper_util::rt::TokioExecutor;
+//! use tower::{Layer, ServiceExt};
+//!
+//! let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+//! let jar = Arc::new(Jar::new());
+//! let client = CookieLayer::new(jar.clone()).layer(client);
+//!
+//! let req = http::Request::get("http://example.local/login")
+//!     .body(Empty::new())
+//!     .unwrap();
+//! let future = client.oneshot(req);
+//! # }
+//! # fn main() {}
+//! ```
+//!
+//! [RFC 6265]: https://www.rfc-editor.org/rfc/rfc6265
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{self, Poll};
+
+use futures_util::ready;
+use http::header::{HeaderValue, COOKIE, SET_COOKIE};
+use http::{Request, Response, Uri};
+use pin_project_lite::pin_project;
+
+pub use self::jar::{Cookie, Jar};
+
+mod jar;
+
+/// Storage for cookies received by a `Client`.
+pub trait CookieStore: Send + Sync {
+    /// Store the cookies of the `Set-Cookie` headers of a response to `uri`.
+    fn set_cookies(&self, uri: &Uri, set_cookies: &mut dyn Iterator<Item = &HeaderValue>);
+
+    /// The value of the `Cookie` header to send with a request to `uri`.
+    fn cookies(&self, uri: &Uri) -> Option<HeaderValue>;
+}
+
+/// A `Layer` adding cookie handling to a `Client`.
+///
+/// Requests that already have a `Cookie` header are left untouched, but
+/// the cookies of their responses are still stored.
+pub struct CookieLayer<S> {
+    store: Arc<S>,
+}
+
+/// A `Service` created by a [`CookieLayer`].
+pub struct CookieService<C, S> {
+    inner: C,
+    store: Arc<S>,
+}
+
+pin_project! {
+    /// A `Future` returned by [`CookieService`].
+    #[must_use = "futures do nothing unless polled"]
+    pub struct ResponseFuture<F, S> {
+        #[pin]
+        inner: F,
+        uri: Uri,
+        store: Arc<S>,
+    }
+}
+
+// ===== impl CookieLayer =====
+
+impl<S> CookieLayer<S> {
+    /// Create a layer using `store` for cookies.
+    pub fn new(store: Arc<S>) -> CookieLayer<S> {
+        CookieLayer { store }
+    }
+}
+
+impl<C, S> tower::Layer<C> for CookieLayer<S> {
+    type Service = CookieService<C, S>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        CookieService {
+            inner,
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<S> Clone for CookieLayer<S> {
+    fn clone(&self) -> CookieLayer<S> {
+        CookieLayer {
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for CookieLayer<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieLayer").finish()
+    }
+}
+
+// ===== impl CookieService =====
+
+impl<C, S> CookieService<C, S> {
+    /// Get a reference to the cookie store.
+    pub fn store(&self) -> &Arc<S> {
+        &self.store
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C, S, B, R> tower_service::Service<Request<B>> for CookieService<C, S>
+where
+    C: tower_service::Service<Request<B>, Response = Response<R>>,
+    S: CookieStore,
+{
+    type Response = Response<R>;
+    type Error = C::Error;
+    type Future = ResponseFuture<C::Future, S>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let uri = req.uri().clone();
+        if !req.headers().contains_key(COOKIE) {
+            if let Some(cookies) = self.store.cookies(&uri) {
+                req.headers_mut().insert(COOKIE, cookies);
+            }
+        }
+        ResponseFuture {
+            inner: self.inner.call(req),
+            uri,
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<C: Clone, S> Clone for CookieService<C, S> {
+    fn clone(&self) -> CookieService<C, S> {
+        CookieService {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+        }
+    }
+}
+
+impl<C: fmt::Debug, S> fmt::Debug for CookieService<C, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CookieService")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, S, R, E> Future for ResponseFuture<F, S>
+where
+    F: Future<Output = Result<Response<R>, E>>,
+    S: CookieStore,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let me = self.project();
+        let res = ready!(me.inner.poll(cx))?;
+        let mut set_cookies = res.headers().get_all(SET_COOKIE).iter();
+        me.store.set_cookies(me.uri, &mut set_cookies);
+        Poll::Ready(Ok(res))
+    }
+}
+
+impl<F, S> fmt::Debug for ResponseFuture<F, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use http::header::{COOKIE, SET_COOKIE};
+    use http::{Request, Response};
+    use tower::{Layer, ServiceExt};
+
+    use super::{CookieLayer, Jar};
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn layer_stores_and_sends_cookies() {
+        let jar = Arc::new(Jar::new());
+        let svc =
+            CookieLayer::new(jar.clone()).layer(tower::service_fn(|req: Request<()>| async move {
+                let sent = req.headers().get(COOKIE).cloned();
+                let res = Response::builder()
+                    .header(SET_COOKIE, "session=abc; Path=/")
+                    .body(sent)
+                    .unwrap();
+                Ok::<_, Infallible>(res)
+            }));
+
+        let req = Request::get("http://example.local/").body(()).unwrap();
+        let res = svc.clone().oneshot(req).await.unwrap();
+        assert_eq!(res.into_body(), None);
+
+        let req = Request::get("http://example.local/a").body(()).unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+        assert_eq!(res.into_body().unwrap(), "session=abc");
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use http::uri::{Authority, Scheme};
+use http::Uri;
+
+/// An endpoint the `Client` connected to, and the protocol it spoke.
+///
+/// Hints carry what was learned about endpoints, not connections, so they
+/// can outlive the process. They format as the protocol and the origin,
+/// such as `h2 https://example.com`, and parse back from that.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct EndpointHint {
+    scheme: Scheme,
+    authority: Authority,
+    http2: bool,
+}
+
+/// Where a `Client` loads and saves its [`EndpointHint`]s.
+///
+/// Set with [`Builder::hint_store`](super::Builder::hint_store), the store
+/// is loaded when the client is built, and saved with
+/// [`Client::save_hints`](super::Client::save_hints), typically on
+/// shutdown. A process starting again then knows which endpoints speak
+/// HTTP/2, and can [`preconnect`](super::Client::preconnect) to them.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::Mutex;
+/// use hyper_util::client::legacy::{EndpointHint, HintStore};
+///
+/// struct InMemory(Mutex<Vec<EndpointHint>>);
+///
+/// impl HintStore for InMemory {
+///     fn load(&self) -> Vec<EndpointHint> {
+///         self.0.lock().unwrap().clone()
+///     }
+///
+///     fn save(&self, hints: &[EndpointHint]) {
+///         *self.0.lock().unwrap() = hints.to_vec();
+///     }
+/// }
+/// ```
+pub trait HintStore: Send + Sync {
+    /// Load the hints saved by a previous process.
+    fn load(&self) -> Vec<EndpointHint>;
+
+    /// Save the hints known to the client.
+    fn save(&self, hints: &[EndpointHint]);
+}
+
+/// The error returned when parsing an [`EndpointHint`] fails.
+#[derive(Debug)]
+pub struct InvalidEndpointHint(());
+
+// The hints of a client with a store, shared by its clones.
+pub(crate) struct Hints {
+    store: Arc<dyn HintStore>,
+    known: Mutex<HashMap<(Scheme, Authority), bool>>,
+}
+
+// ===== impl EndpointHint =====
+
+impl EndpointHint {
+    /// Create a hint for the origin of `uri`, if it has a scheme and an
+    /// authority.
+    pub fn new(uri: &Uri, http2: bool) -> Option<EndpointHint> {
+        Some(EndpointHint {
+            scheme: uri.scheme()?.clone(),
+            authority: uri.authority()?.clone(),
+            http2,
+        })
+    }
+
+    /// The origin of the endpoint.
+    pub fn origin(&self) -> Uri {
+        Uri::builder()
+            .scheme(self.scheme.clone())
+            .authority(self.authority.clone())
+            .path_and_query("/")
+            .build()
+            .expect("scheme and authority are valid")
+    }
+
+    /// Whether the endpoint spoke HTTP/2.
+    pub fn is_http2(&self) -> bool {
+        self.http2
+    }
+
+    pub(crate) fn into_origin(self) -> (Scheme, Authority) {
+        (self.scheme, self.authority)
+    }
+}
+
+impl fmt::Display for EndpointHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let protocol = if self.http2 { "h2" } else { "http/1.1" };
+        write!(f, "{} {}://{}", protocol, self.scheme, self.authority)
+    }
+}
+
+impl FromStr for EndpointHint {
+    type Err = InvalidEndpointHint;
+
+    fn from_str(s: &str) -> Result<EndpointHint, InvalidEndpointHint> {
+        let (protocol, origin) = s.split_once(' ').ok_or(InvalidEndpointHint(()))?;
+        let http2 = match protocol {
+            "h2" => true,
+            "http/1.1" => false,
+            _ => return Err(InvalidEndpointHint(())),
+        };
+        let uri = origin.parse::<Uri>().map_err(|_| InvalidEndpointHint(()))?;
+        EndpointHint::new(&uri, http2).ok_or(InvalidEndpointHint(()))
+    }
+}
+
+// ===== impl InvalidEndpointHint =====
+
+impl fmt::Display for InvalidEndpointHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("invalid endpoint hint")
+    }
+}
+
+impl std::error::Error for InvalidEndpointHint {}
+
+// ===== impl Hints =====
+
+impl Hints {
+    pub(crate) fn load(store: Arc<dyn HintStore>) -> Hints {
+        let known = store
+            .load()
+            .into_iter()
+            .map(|hint| ((hint.scheme, hint.authority), hint.http2))
+            .collect();
+        Hints {
+            store,
+            known: Mutex::new(known),
+        }
+    }
+
+    /// Whether the endpoint is known to speak HTTP/2.
+    pub(crate) fn is_http2(&self, scheme: &Scheme, authority: &Authority) -> bool {
+        let known = self.known.lock().unwrap();
+        known
+            .get(&(scheme.clone(), authority.clone()))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// The client connected to the endpoint.
+    pub(crate) fn record(&self, scheme: &Scheme, authority: &Authority, http2: bool) {
+        let mut known = self.known.lock().unwrap();
+        known.insert((scheme.clone(), authority.clone()), http2);
+    }
+
+    pub(crate) fn endpoints(&self) -> Vec<EndpointHint> {
+        let known = self.known.lock().unwrap();
+        known
+            .iter()
+            .map(|((scheme, authority), &http2)| EndpointHint {
+                scheme: scheme.clone(),
+                authority: authority.clone(),
+                http2,
+            })
+            .collect()
+    }
+
+    pub(crate) fn save(&self) {
+        self.store.save(&self.endpoints());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EndpointHint;
+
+    #[test]
+    fn hints_round_trip_through_text() {
+        let hint = EndpointHint::new(&"https://example.com:8443/a".parse().unwrap(), true).unwrap();
+        assert_eq!(hint.to_string(), "h2 https://example.com:8443");
+        assert_eq!(hint.to_string().parse::<EndpointHint>().unwrap(), hint);
+        assert_eq!(hint.origin(), "https://example.com:8443/");
+
+        let hint = "http/1.1 http://example.com"
+            .parse::<EndpointHint>()
+            .unwrap();
+        assert!(!hint.is_http2());
+
+        assert!("h3 https://example.com".parse::<EndpointHint>().is_err());
+        assert!("h2 /path".parse::<EndpointHint>().is_err());
+        assert!(EndpointHint::new(&"/path".parse().unwrap(), false).is_none());
+    }
+}
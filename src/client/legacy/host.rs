@@ -0,0 +1,268 @@
+use std::error::Error as StdError;
+use std::fmt;
+
+use http::uri::{Authority, Scheme, Uri};
+
+/// How the `Client` normalizes the host of a request.
+///
+/// The normalized authority is used for the pool key, the destination
+/// passed to the connector, and the `Host` header, so requests to
+/// `EXAMPLE.com.` and `example.com:80` share connections with requests to
+/// `http://example.com`.
+///
+/// Internationalized hosts must already be in their ASCII (punycode) form,
+/// since a `Uri` can't hold other characters.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "tokio")]
+/// # fn run () {
+/// use hyper_util::client::legacy::{Client, HostNormalization};
+/// use hyper_util::rt::TokioExecutor;
+///
+/// let client = Client::builder(TokioExecutor::new())
+///     .host_normalization(HostNormalization::Strict)
+///     .build_http::<http_body_util::Empty<bytes::Bytes>>();
+/// # drop(client);
+/// # }
+/// # fn main() {}
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum HostNormalization {
+    /// Use the authority as written.
+    Preserve,
+    /// Lowercase the host, remove a trailing dot, and remove the port if
+    /// it is the default for the scheme.
+    ///
+    /// This is the default.
+    #[default]
+    Standard,
+    /// Normalize like [`Standard`](HostNormalization::Standard), and fail
+    /// the request unless the host is an IP address or a valid DNS name,
+    /// with valid punycode labels, and the authority has no userinfo.
+    Strict,
+}
+
+/// The error when a host fails [`HostNormalization::Strict`].
+#[derive(Debug)]
+pub(super) struct InvalidHost(&'static str);
+
+pub(super) fn normalize(uri: &mut Uri, mode: HostNormalization) -> Result<(), InvalidHost> {
+    let auth = match (mode, uri.authority()) {
+        (HostNormalization::Preserve, _) | (_, None) => return Ok(()),
+        (_, Some(auth)) => auth,
+    };
+
+    let userinfo = auth
+        .as_str()
+        .rfind('@')
+        .map_or("", |at| &auth.as_str()[..=at]);
+    if mode == HostNormalization::Strict {
+        if !userinfo.is_empty() {
+            return Err(InvalidHost("userinfo is not allowed"));
+        }
+        validate(auth.host())?;
+    }
+
+    let mut host = auth.host().to_ascii_lowercase();
+    if host.len() > 1 && host.ends_with('.') {
+        host.pop();
+    }
+    let port = match (auth.port_u16(), uri.scheme()) {
+        (Some(80), Some(scheme)) if *scheme == Scheme::HTTP => None,
+        (Some(443), Some(scheme)) if *scheme == Scheme::HTTPS => None,
+        (port, _) => port,
+    };
+
+    let normalized = match port {
+        Some(port) => format!("{}{}:{}", userinfo, host, port),
+        None => format!("{}{}", userinfo, host),
+    };
+    if normalized == auth.as_str() {
+        return Ok(());
+    }
+
+    let mut parts = std::mem::take(uri).into_parts();
+    parts.authority = Some(
+        normalized
+            .parse::<Authority>()
+            .expect("normalized authority is valid"),
+    );
+    *uri = Uri::from_parts(parts).expect("normalized uri is valid");
+    Ok(())
+}
+
+fn validate(host: &str) -> Result<(), InvalidHost> {
+    if host.starts_with('[') || host.parse::<std::net::Ipv4Addr>().is_ok() {
+        // IP literals are already validated by `Uri`.
+        return Ok(());
+    }
+    let name = host.strip_suffix('.').unwrap_or(host);
+    if name.is_empty() || name.len() > 253 {
+        return Err(InvalidHost("invalid host length"));
+    }
+    for label in name.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(InvalidHost("invalid label length"));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(InvalidHost("label starts or ends with a hyphen"));
+        }
+        if !label
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        {
+            return Err(InvalidHost("invalid character in label"));
+        }
+        let is_punycode = label.len() > 4 && label[..4].eq_ignore_ascii_case("xn--");
+        if is_punycode && decode_punycode(&label[4..]).is_none() {
+            return Err(InvalidHost("invalid punycode label"));
+        }
+    }
+    Ok(())
+}
+
+// Decode a punycode label as described in RFC 3492, returning the number
+// of code points, or `None` if it isn't valid.
+fn decode_punycode(input: &str) -> Option<u32> {
+    const BASE: u32 = 36;
+    const T_MIN: u32 = 1;
+    const T_MAX: u32 = 26;
+
+    let (basic, extended) = match input.rfind('-') {
+        Some(dash) => (&input[..dash], &input[dash + 1..]),
+        None => ("", input),
+    };
+    if extended.is_empty() {
+        return None;
+    }
+
+    let mut len = basic.len() as u32;
+    let mut n = 0x80u32;
+    let mut i = 0u32;
+    let mut bias = 72;
+    let mut digits = extended.bytes();
+    loop {
+        let old_i = i;
+        let mut w = 1u32;
+        let mut k = BASE;
+        loop {
+            let digit = match digits.next()? {
+                b @ b'a'..=b'z' => b - b'a',
+                b @ b'A'..=b'Z' => b - b'A',
+                b @ b'0'..=b'9' => b - b'0' + 26,
+                _ => return None,
+            } as u32;
+            i = i.checked_add(digit.checked_mul(w)?)?;
+            let t = if k <= bias {
+                T_MIN
+            } else if k >= bias + T_MAX {
+                T_MAX
+            } else {
+                k - bias
+            };
+            if digit < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+
+        len += 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        char::from_u32(n)?;
+        i += 1;
+
+        if digits.len() == 0 {
+            return Some(len);
+        }
+    }
+}
+
+fn adapt(delta: u32, len: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / 700 } else { delta / 2 };
+    delta += delta / len;
+    let mut k = 0;
+    while delta > ((36 - 1) * 26) / 2 {
+        delta /= 36 - 1;
+        k += 36;
+    }
+    k + (36 * delta) / (delta + 38)
+}
+
+impl fmt::Display for InvalidHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl StdError for InvalidHost {}
+
+#[cfg(test)]
+mod tests {
+    use http::Uri;
+
+    use super::{decode_punycode, normalize, HostNormalization};
+
+    fn norm(uri: &str, mode: HostNormalization) -> Option<String> {
+        let mut uri = uri.parse::<Uri>().unwrap();
+        normalize(&mut uri, mode).ok()?;
+        Some(uri.to_string())
+    }
+
+    #[test]
+    fn standard() {
+        let std = HostNormalization::Standard;
+        assert_eq!(
+            norm("http://EXAMPLE.com.:80/a?b", std).unwrap(),
+            "http://example.com/a?b"
+        );
+        assert_eq!(
+            norm("https://u:p@Example.com:8443/", std).unwrap(),
+            "https://u:p@example.com:8443/"
+        );
+        assert_eq!(norm("https://[::1]:443/", std).unwrap(), "https://[::1]/");
+        assert_eq!(
+            norm("http://EXAMPLE.com./", HostNormalization::Preserve).unwrap(),
+            "http://EXAMPLE.com./"
+        );
+
+        // authority-form, as used by CONNECT, keeps the port
+        let mut uri = "Example.com:443".parse::<Uri>().unwrap();
+        normalize(&mut uri, std).unwrap();
+        assert_eq!(uri.authority().unwrap(), "example.com:443");
+    }
+
+    #[test]
+    fn strict() {
+        let strict = HostNormalization::Strict;
+        assert_eq!(
+            norm("http://XN--bcher-kva.Example./", strict).unwrap(),
+            "http://xn--bcher-kva.example/"
+        );
+        assert_eq!(
+            norm("http://127.0.0.1/", strict).unwrap(),
+            "http://127.0.0.1/"
+        );
+        assert_eq!(norm("http://u@example.com/", strict), None);
+        assert_eq!(norm("http://-bad.example/", strict), None);
+        assert_eq!(norm("http://a_b.example/", strict), None);
+        assert_eq!(norm("http://a..example/", strict), None);
+        assert_eq!(norm("http://xn--9.example/", strict), None);
+        let long = format!("http://{}.example/", "a".repeat(64));
+        assert_eq!(norm(&long, strict), None);
+    }
+
+    #[test]
+    fn punycode() {
+        // "bücher", "münchen", and "例え" from RFC 3492's examples
+        assert_eq!(decode_punycode("bcher-kva"), Some(6));
+        assert_eq!(decode_punycode("mnchen-3ya"), Some(7));
+        assert_eq!(decode_punycode("r8jz45g"), Some(2));
+        assert_eq!(decode_punycode("9"), None);
+        assert_eq!(decode_punycode("abc-"), None);
+    }
+}
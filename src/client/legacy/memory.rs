@@ -0,0 +1,201 @@
+//! Memory accounting for the connections of a `Client`.
+//!
+//! The memory a connection could hold is estimated from how it is
+//! configured, its buffer limits and flow control windows, since hyper
+//! doesn't report what its buffers actually use.
+#![cfg_attr(not(all(feature = "http1", feature = "http2")), allow(dead_code))]
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// hyper's defaults, used until the `Builder` changes them.
+const DEFAULT_H1_MAX_BUF: usize = 8192 + 4096 * 100;
+const DEFAULT_H2_CONN_WINDOW: u32 = 1024 * 1024 * 5;
+const DEFAULT_H2_SEND_BUF: usize = 1024 * 1024;
+// The most the adaptive window grows to.
+const ADAPTIVE_H2_WINDOW: u32 = 1024 * 1024 * 16;
+
+/// The windows dialed instead when over budget, the spec's defaults.
+pub(super) const REDUCED_H2_WINDOW: u32 = 65_535;
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Config {
+    pub(super) max: Option<usize>,
+    pub(super) h1_read_buf: usize,
+    pub(super) h1_write_buf: usize,
+    pub(super) h2_window: u32,
+    pub(super) h2_adaptive: bool,
+    pub(super) h2_send_buf: usize,
+}
+
+/// The memory budget shared by every connection of a `Client`.
+#[derive(Debug)]
+pub(super) struct Budget {
+    config: Config,
+    max: usize,
+    used: AtomicUsize,
+}
+
+/// The memory charged to the budget for one connection, released on drop.
+#[derive(Debug)]
+pub(super) struct Charge {
+    budget: Arc<Budget>,
+    bytes: usize,
+    reduced: bool,
+}
+
+// ===== impl Config =====
+
+impl Config {
+    pub(super) fn new() -> Config {
+        Config {
+            max: None,
+            h1_read_buf: DEFAULT_H1_MAX_BUF,
+            h1_write_buf: DEFAULT_H1_MAX_BUF,
+            h2_window: DEFAULT_H2_CONN_WINDOW,
+            h2_adaptive: false,
+            h2_send_buf: DEFAULT_H2_SEND_BUF,
+        }
+    }
+
+    pub(super) fn h1_read_buf_exact(&mut self, sz: usize) {
+        // This unsets the max buffer size, so writes go back to the default.
+        self.h1_read_buf = sz;
+        self.h1_write_buf = DEFAULT_H1_MAX_BUF;
+    }
+
+    pub(super) fn h1_max_buf(&mut self, max: usize) {
+        self.h1_read_buf = max;
+        self.h1_write_buf = max;
+    }
+
+    fn h1(&self) -> usize {
+        self.h1_read_buf.saturating_add(self.h1_write_buf)
+    }
+
+    fn h2(&self) -> usize {
+        let window = if self.h2_adaptive {
+            ADAPTIVE_H2_WINDOW
+        } else {
+            self.h2_window
+        };
+        (window as usize).saturating_add(self.h2_send_buf)
+    }
+
+    fn h2_reduced(&self) -> usize {
+        (REDUCED_H2_WINDOW as usize).saturating_add(self.h2_send_buf)
+    }
+}
+
+// ===== impl Budget =====
+
+impl Budget {
+    pub(super) fn new(config: Config) -> Option<Arc<Budget>> {
+        config.max.map(|max| {
+            Arc::new(Budget {
+                config,
+                max,
+                used: AtomicUsize::new(0),
+            })
+        })
+    }
+
+    /// Charge a new connection to the budget.
+    ///
+    /// An HTTP/2 connection that doesn't fit gets the reduced windows.
+    pub(super) fn charge(self: &Arc<Self>, is_h2: bool) -> Charge {
+        let (bytes, reduced) = if !is_h2 {
+            (self.config.h1(), false)
+        } else if self.fits(self.config.h2()) {
+            (self.config.h2(), false)
+        } else {
+            (self.config.h2_reduced(), true)
+        };
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+        Charge {
+            budget: self.clone(),
+            bytes,
+            reduced,
+        }
+    }
+
+    pub(super) fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn fits(&self, bytes: usize) -> bool {
+        self.used().saturating_add(bytes) <= self.max
+    }
+
+    fn is_over(&self) -> bool {
+        self.used() > self.max
+    }
+}
+
+// ===== impl Charge =====
+
+impl Charge {
+    /// Whether the connection should be dialed with the reduced windows.
+    pub(super) fn is_reduced(&self) -> bool {
+        self.reduced
+    }
+
+    /// Whether the client is over budget, so idle connections shouldn't
+    /// be kept.
+    pub(super) fn is_over_budget(&self) -> bool {
+        self.budget.is_over()
+    }
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Budget, Config, DEFAULT_H1_MAX_BUF, DEFAULT_H2_SEND_BUF, REDUCED_H2_WINDOW};
+
+    #[test]
+    fn no_budget_without_max() {
+        assert!(Budget::new(Config::new()).is_none());
+    }
+
+    #[test]
+    fn charges_are_released() {
+        let h1 = 2 * DEFAULT_H1_MAX_BUF;
+        let budget = Budget::new(Config {
+            max: Some(h1 + h1 / 2),
+            ..Config::new()
+        })
+        .unwrap();
+
+        let a = budget.charge(false);
+        assert_eq!(budget.used(), h1);
+        assert!(!a.is_over_budget());
+
+        let b = budget.charge(false);
+        assert!(a.is_over_budget());
+        drop(b);
+        assert!(!a.is_over_budget());
+        drop(a);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn h2_is_reduced_when_over_budget() {
+        let budget = Budget::new(Config {
+            max: Some(1024 * 1024),
+            ..Config::new()
+        })
+        .unwrap();
+
+        let charge = budget.charge(true);
+        assert!(charge.is_reduced());
+        assert_eq!(
+            budget.used(),
+            REDUCED_H2_WINDOW as usize + DEFAULT_H2_SEND_BUF
+        );
+    }
+}
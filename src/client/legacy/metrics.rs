@@ -0,0 +1,127 @@
+//! Metrics of the client, with the `metrics` feature.
+//!
+//! They are recorded with the `metrics` facade, so any installed recorder,
+//! such as a Prometheus exporter, gets them. Without the feature, recording
+//! does nothing.
+#![allow(dead_code)]
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use std::time::Instant;
+
+use http::StatusCode;
+
+pub(crate) const CONNECTIONS_OPENED: &str = "hyper_util_client_connections_opened_total";
+pub(crate) const CONNECTIONS_CLOSED: &str = "hyper_util_client_connections_closed_total";
+pub(crate) const POOL_CHECKOUTS: &str = "hyper_util_client_pool_checkouts_total";
+pub(crate) const REQUEST_DURATION: &str = "hyper_util_client_request_duration_seconds";
+
+/// A connection was established, with `protocol` being `"http1"` or
+/// `"http2"`.
+pub(crate) fn connection_opened(protocol: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::increment_counter!(CONNECTIONS_OPENED, "protocol" => protocol);
+}
+
+/// A connection was closed.
+pub(crate) fn connection_closed(protocol: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::increment_counter!(CONNECTIONS_CLOSED, "protocol" => protocol);
+}
+
+/// A request got a connection, either from the pool or by dialing one.
+pub(crate) fn checkout(reused: bool) {
+    #[cfg(feature = "metrics")]
+    ::metrics::increment_counter!(
+        POOL_CHECKOUTS,
+        "result" => if reused { "hit" } else { "miss" },
+    );
+}
+
+/// A request finished, with the status of its response if it got one, and
+/// the partition of the client that sent it, if any.
+pub(crate) fn request_finished(
+    start: Instant,
+    status: Option<StatusCode>,
+    partition: Option<&str>,
+) {
+    #[cfg(feature = "metrics")]
+    match partition {
+        Some(partition) => ::metrics::histogram!(
+            REQUEST_DURATION,
+            start.elapsed().as_secs_f64(),
+            "status_class" => status_class(status),
+            "partition" => partition.to_owned(),
+        ),
+        None => ::metrics::histogram!(
+            REQUEST_DURATION,
+            start.elapsed().as_secs_f64(),
+            "status_class" => status_class(status),
+        ),
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn status_class(status: Option<StatusCode>) -> &'static str {
+    match status.map(|status| status.as_u16() / 100) {
+        Some(1) => "1xx",
+        Some(2) => "2xx",
+        Some(3) => "3xx",
+        Some(4) => "4xx",
+        Some(5) => "5xx",
+        Some(_) => "other",
+        None => "error",
+    }
+}
+
+#[cfg(all(
+    test,
+    not(miri),
+    feature = "metrics",
+    feature = "http1",
+    feature = "tokio"
+))]
+mod tests {
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+
+    use crate::client::legacy::Client;
+    use crate::common::recorder;
+    use crate::rt::{TokioExecutor, TokioIo};
+
+    #[tokio::test]
+    async fn records_requests() {
+        let recorded = recorder::install();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (io, _) = listener.accept().await.unwrap();
+            let svc = hyper::service::service_fn(|_| async {
+                Ok::<_, hyper::Error>(hyper::Response::new(Full::new(Bytes::from("hello"))))
+            });
+            let _ = hyper::server::conn::http1::Builder::new()
+                .keep_alive(false)
+                .serve_connection(TokioIo::new(io), svc)
+                .await;
+        });
+
+        let client: Client<_, Full<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+        let uri = format!("http://127.0.0.1:{}/", addr.port())
+            .parse()
+            .unwrap();
+        let res = client.get(uri).await.unwrap();
+        res.into_body().collect().await.unwrap();
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+
+        for key in [
+            "hyper_util_client_connections_opened_total{protocol=http1}",
+            "hyper_util_client_connections_closed_total{protocol=http1}",
+            "hyper_util_client_pool_checkouts_total{result=miss}",
+            "hyper_util_client_request_duration_seconds{status_class=2xx}",
+        ] {
+            assert!(recorded.contains(key), "{} missing", key);
+        }
+    }
+}
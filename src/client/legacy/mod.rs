@@ -1,10 +1,46 @@
 #[cfg(any(feature = "http1", feature = "http2"))]
 mod client;
 #[cfg(any(feature = "http1", feature = "http2"))]
-pub use client::{Builder, Client, Error, ResponseFuture};
+pub use client::{
+    BaseUriClient, Builder, Client, ClientService, EarlyData, Error, Expectation, OnResponseHead,
+    PoolTag, PooledConnection, Priority, RequireProtocol, ResponseFuture,
+};
+#[cfg(any(feature = "http1", feature = "http2"))]
+pub use hints::{EndpointHint, HintStore, InvalidEndpointHint};
+#[cfg(any(feature = "http1", feature = "http2"))]
+pub use host::HostNormalization;
+#[cfg(any(feature = "http1", feature = "http2"))]
+pub use pool::{Disposition, HostDump, PoolDump, PoolStatsReporter};
+#[cfg(any(feature = "http1", feature = "http2"))]
+pub use queue::QueueOverflow;
+#[cfg(any(feature = "http1", feature = "http2"))]
+pub use timings::Timings;
 
+#[cfg(feature = "client-auth")]
+pub mod auth;
+#[cfg(feature = "client-compression")]
+pub mod compression;
 pub mod connect;
+#[cfg(feature = "client-cookies")]
+pub mod cookie;
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod hints;
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod host;
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod memory;
+mod metrics;
 #[doc(hidden)]
 // Publicly available, but just for legacy purposes. A better pool will be
 // designed.
 pub mod pool;
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod queue;
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod reresolve;
+#[cfg(feature = "client-retry")]
+pub mod retry;
+mod spans;
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod timings;
+pub mod trace_context;
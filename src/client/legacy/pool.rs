@@ -1,6 +1,6 @@
 #![allow(dead_code)]
 
-use std::collections::{HashMap, HashSet, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::convert::Infallible;
 use std::error::Error as StdError;
 use std::fmt::{self, Debug};
@@ -8,6 +8,7 @@ use std::future::Future;
 use std::hash::Hash;
 use std::ops::{Deref, DerefMut};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex, Weak};
 use std::task::{self, Poll};
 
@@ -15,18 +16,21 @@ use std::time::{Duration, Instant};
 
 use futures_channel::oneshot;
 use futures_util::ready;
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 use hyper::rt::Sleep;
 use hyper::rt::Timer as _;
 
-use crate::common::{exec, exec::Exec, timer::Timer};
+use crate::common::timer::{self, Timer};
+use crate::common::{exec, exec::Exec};
 
 // FIXME: allow() required due to `impl Trait` leaking types to this lint
 #[allow(missing_debug_implementations)]
 pub struct Pool<T, K: Key> {
     // If the pool is disabled, this is None.
     inner: Option<Arc<Mutex<PoolInner<T, K>>>>,
+    activity: Activity,
+    checked_out: CheckedOut<K>,
 }
 
 // Before using a pooled connection, make sure the sender is not dead.
@@ -41,6 +45,19 @@ pub trait Poolable: Unpin + Send + Sized + 'static {
     /// Allows for HTTP/2 to return a shared reservation.
     fn reserve(self) -> Reservation<Self>;
     fn can_share(&self) -> bool;
+    /// Whether this connection may be kept idle in the pool.
+    fn can_idle(&self) -> bool {
+        true
+    }
+    /// How long this connection may be idle, if less than the pool's
+    /// idle timeout.
+    fn idle_timeout(&self) -> Option<Duration> {
+        None
+    }
+    /// What the application wants done with this connection.
+    fn disposition(&self) -> Option<&Disposition> {
+        None
+    }
 }
 
 pub trait Key: Eq + Hash + Clone + Debug + Unpin + Send + 'static {}
@@ -83,7 +100,8 @@ struct PoolInner<T, K: Eq + Hash> {
     connecting: HashSet<K>,
     // These are internal Conns sitting in the event loop in the KeepAlive
     // state, waiting to receive a new Request to send on the socket.
-    idle: HashMap<K, Vec<Idle<T>>>,
+    idle: HashMap<K, VecDeque<Idle<T>>>,
+    expiring: Expiring<K>,
     max_idle_per_host: usize,
     // These are outstanding Checkouts that are waiting for a socket to be
     // able to send a Request one. This is used when "racing" for a new
@@ -94,15 +112,30 @@ struct PoolInner<T, K: Eq + Hash> {
     // this list is checked for any parked Checkouts, and tries to notify
     // them that the Conn could be used instead of waiting for a brand new
     // connection.
-    waiters: HashMap<K, VecDeque<oneshot::Sender<T>>>,
+    waiters: HashMap<K, VecDeque<Waiter<T>>>,
+    max_waiters_per_host: usize,
     // A oneshot channel is used to allow the interval to be notified when
     // the Pool completely drops. That way, the interval can cancel immediately.
     idle_interval_ref: Option<oneshot::Sender<Infallible>>,
+    // Set once the interval is dropped, whether by panicking or by a faulty
+    // executor, so the next `put` can spawn it again.
+    idle_interval_dead: Arc<AtomicBool>,
+    idle_interval_restarts: u64,
+    // Bumped by `Pool::clear`. Connections from an older generation are
+    // not put back into the pool.
+    generation: u64,
     exec: Exec,
     timer: Option<Timer>,
     timeout: Option<Duration>,
 }
 
+// A `Pool` that may have been dropped.
+pub(crate) struct WeakPool<T, K: Key> {
+    inner: Option<Weak<Mutex<PoolInner<T, K>>>>,
+    activity: Weak<Mutex<ActivityState>>,
+    checked_out: Weak<Mutex<HashMap<K, usize>>>,
+}
+
 // This is because `Weak::new()` *allocates* space for `T`, even if it
 // doesn't need it!
 struct WeakOpt<T>(Option<Weak<T>>);
@@ -111,6 +144,7 @@ struct WeakOpt<T>(Option<Weak<T>>);
 pub struct Config {
     pub idle_timeout: Option<Duration>,
     pub max_idle_per_host: usize,
+    pub max_waiters_per_host: usize,
 }
 
 impl Config {
@@ -131,9 +165,17 @@ impl<T, K: Key> Pool<T, K> {
             Some(Arc::new(Mutex::new(PoolInner {
                 connecting: HashSet::new(),
                 idle: HashMap::new(),
+                expiring: Expiring {
+                    deadlines: BTreeMap::new(),
+                    next_id: 0,
+                },
                 idle_interval_ref: None,
+                idle_interval_dead: Arc::new(AtomicBool::new(false)),
+                idle_interval_restarts: 0,
+                generation: 0,
                 max_idle_per_host: config.max_idle_per_host,
                 waiters: HashMap::new(),
+                max_waiters_per_host: config.max_waiters_per_host,
                 exec,
                 timer,
                 timeout: config.idle_timeout,
@@ -142,13 +184,161 @@ impl<T, K: Key> Pool<T, K> {
             None
         };
 
-        Pool { inner }
+        Pool {
+            inner,
+            activity: Activity::new(),
+            checked_out: CheckedOut(Arc::new(Mutex::new(HashMap::new()))),
+        }
     }
 
     pub(crate) fn is_enabled(&self) -> bool {
         self.inner.is_some()
     }
 
+    /// Drop all idle connections, and don't pool connections that are
+    /// currently in use or being established once they are done.
+    pub(crate) fn clear(&self) {
+        if let Some(ref enabled) = self.inner {
+            let mut inner = enabled.lock().unwrap();
+            debug!("clearing pool of {} hosts", inner.idle.len());
+            inner.generation += 1;
+            inner.idle.clear();
+            inner.expiring.deadlines.clear();
+        }
+    }
+
+    /// Whether any checkout is waiting for a connection to `key`.
+    pub(crate) fn has_waiters(&self, key: &K) -> bool {
+        self.inner.as_ref().map_or(false, |enabled| {
+            enabled
+                .lock()
+                .unwrap()
+                .waiters
+                .get(key)
+                .map_or(false, |waiters| waiters.iter().any(|tx| !tx.is_canceled()))
+        })
+    }
+
+    /// Whether any connection to `key` is checked out.
+    pub(crate) fn is_checked_out(&self, key: &K) -> bool {
+        self.checked_out.0.lock().unwrap().contains_key(key)
+    }
+
+    /// How many connections to `key` are checked out or idle.
+    ///
+    /// An HTTP/2 connection counts once for each request using it.
+    pub(crate) fn connections(&self, key: &K) -> usize {
+        let checked_out = self
+            .checked_out
+            .0
+            .lock()
+            .unwrap()
+            .get(key)
+            .copied()
+            .unwrap_or(0);
+        let idle = self.inner.as_ref().map_or(0, |enabled| {
+            enabled
+                .lock()
+                .unwrap()
+                .idle
+                .get(key)
+                .map_or(0, |list| list.len())
+        });
+        checked_out + idle
+    }
+
+    /// How many checkouts are waiting for a connection to a key matching
+    /// `matches`.
+    pub(crate) fn waiters(&self, matches: impl Fn(&K) -> bool) -> usize {
+        self.inner.as_ref().map_or(0, |enabled| {
+            enabled
+                .lock()
+                .unwrap()
+                .waiters
+                .iter()
+                .filter(|(key, _)| matches(key))
+                .map(|(_, waiters)| waiters.iter().filter(|tx| !tx.is_canceled()).count())
+                .sum()
+        })
+    }
+
+    /// Returns a future that resolves once no connection is checked out or
+    /// being established.
+    pub fn idle(&self) -> WhenIdle {
+        WhenIdle {
+            activity: self.activity.clone(),
+        }
+    }
+
+    /// Count as a connection being established until the guard is
+    /// dropped, for a connect that hasn't started yet.
+    pub(crate) fn active(&self) -> Active {
+        self.activity.enter()
+    }
+
+    /// Take a snapshot of the state of the pool, naming each key with
+    /// `name`.
+    pub(crate) fn dump(&self, name: impl Fn(&K) -> String) -> PoolDump {
+        let in_use = self.activity.0.lock().unwrap().active;
+        let inner = match self.inner {
+            Some(ref enabled) => enabled.lock().unwrap(),
+            None => {
+                return PoolDump {
+                    enabled: false,
+                    in_use,
+                    hosts: Vec::new(),
+                    idle_interval_restarts: 0,
+                }
+            }
+        };
+
+        let now = inner.now();
+        let mut hosts = HashMap::<&K, HostDump>::new();
+        let new_host = |key| HostDump {
+            key: name(key),
+            idle: Vec::new(),
+            waiters: 0,
+            connecting: false,
+        };
+        for (key, list) in &inner.idle {
+            hosts.entry(key).or_insert_with(|| new_host(key)).idle = list
+                .iter()
+                .map(|entry| now.saturating_duration_since(entry.idle_at))
+                .collect();
+        }
+        for (key, waiters) in &inner.waiters {
+            hosts.entry(key).or_insert_with(|| new_host(key)).waiters =
+                waiters.iter().filter(|tx| !tx.is_canceled()).count();
+        }
+        for key in &inner.connecting {
+            hosts.entry(key).or_insert_with(|| new_host(key)).connecting = true;
+        }
+
+        let mut hosts = hosts.into_values().collect::<Vec<_>>();
+        hosts.sort_by(|a, b| a.key.cmp(&b.key));
+        PoolDump {
+            enabled: true,
+            in_use,
+            hosts,
+            idle_interval_restarts: inner.idle_interval_restarts,
+        }
+    }
+
+    fn generation(&self) -> u64 {
+        self.inner
+            .as_ref()
+            .map_or(0, |enabled| enabled.lock().unwrap().generation)
+    }
+
+    /// A reference to this pool that doesn't keep it alive.
+    pub(crate) fn downgrade(&self) -> WeakPool<T, K> {
+        WeakPool {
+            inner: self.inner.as_ref().map(Arc::downgrade),
+            activity: Arc::downgrade(&self.activity.0),
+            checked_out: Arc::downgrade(&self.checked_out.0),
+        }
+    }
+
     #[cfg(test)]
     pub(super) fn no_timer(&self) {
         // Prevent an actual interval from being created for this pool...
@@ -169,9 +359,18 @@ impl<T: Poolable, K: Key> Pool<T, K> {
             key,
             pool: self.clone(),
             waiter: None,
+            background: false,
         }
     }
 
+    /// Like `checkout`, but while it waits, connections that become idle
+    /// go to waiting checkouts that aren't `background` first.
+    pub(crate) fn checkout_background(&self, key: K) -> Checkout<T, K> {
+        let mut checkout = self.checkout(key);
+        checkout.background = true;
+        checkout
+    }
+
     /// Ensure that there is only ever 1 connecting task for HTTP/2
     /// connections. This does nothing for HTTP/1.
     pub fn connecting(&self, key: &K, ver: Ver) -> Option<Connecting<T, K>> {
@@ -181,7 +380,9 @@ impl<T: Poolable, K: Key> Pool<T, K> {
                 return if inner.connecting.insert(key.clone()) {
                     let connecting = Connecting {
                         key: key.clone(),
+                        generation: inner.generation,
                         pool: WeakOpt::downgrade(enabled),
+                        _active: self.activity.enter(),
                     };
                     Some(connecting)
                 } else {
@@ -194,9 +395,11 @@ impl<T: Poolable, K: Key> Pool<T, K> {
         // else
         Some(Connecting {
             key: key.clone(),
+            generation: self.generation(),
             // in HTTP/1's case, there is never a lock, so we don't
             // need to do anything in Drop.
             pool: WeakOpt::none(),
+            _active: self.activity.enter(),
         })
     }
 
@@ -232,7 +435,9 @@ impl<T: Poolable, K: Key> Pool<T, K> {
                 #[cfg(feature = "http2")]
                 Reservation::Shared(to_insert, to_return) => {
                     let mut inner = enabled.lock().unwrap();
-                    inner.put(connecting.key.clone(), to_insert, enabled);
+                    if inner.generation == connecting.generation {
+                        inner.put(connecting.key.clone(), to_insert, enabled);
+                    }
                     // Do this here instead of Drop for Connecting because we
                     // already have a lock, no need to lock the mutex twice.
                     inner.connected(&connecting.key);
@@ -260,9 +465,12 @@ impl<T: Poolable, K: Key> Pool<T, K> {
         };
         Pooled {
             key: connecting.key.clone(),
+            generation: connecting.generation,
             is_reused: false,
             pool: pool_ref,
             value: Some(value),
+            _active: self.activity.enter(),
+            _checked_out: self.checked_out.enter(&connecting.key),
         }
     }
 
@@ -285,9 +493,12 @@ impl<T: Poolable, K: Key> Pool<T, K> {
 
         Pooled {
             is_reused: true,
+            generation: self.generation(),
             key: key.clone(),
             pool: pool_ref,
             value: Some(value),
+            _active: self.activity.enter(),
+            _checked_out: self.checked_out.enter(key),
         }
     }
 }
@@ -295,12 +506,14 @@ impl<T: Poolable, K: Key> Pool<T, K> {
 /// Pop off this list, looking for a usable connection that hasn't expired.
 struct IdlePopper<'a, T, K> {
     key: &'a K,
-    list: &'a mut Vec<Idle<T>>,
+    list: &'a mut VecDeque<Idle<T>>,
+    expiring: &'a mut Expiring<K>,
 }
 
-impl<'a, T: Poolable + 'a, K: Debug> IdlePopper<'a, T, K> {
-    fn pop(self, expiration: &Expiration) -> Option<Idle<T>> {
-        while let Some(entry) = self.list.pop() {
+impl<'a, T: Poolable + 'a, K: Key> IdlePopper<'a, T, K> {
+    fn pop(self, expiration: &Expiration) -> Option<T> {
+        while let Some(entry) = self.list.pop_back() {
+            self.expiring.untrack(&entry);
             // If the connection has been closed, or is older than our idle
             // timeout, simply drop it and keep looking...
             if !entry.value.is_open() {
@@ -313,7 +526,7 @@ impl<'a, T: Poolable + 'a, K: Debug> IdlePopper<'a, T, K> {
             //
             // In that case, we could just break out of the loop and drop the
             // whole list...
-            if expiration.expires(entry.idle_at) {
+            if expiration.expires(&entry) {
                 trace!("removing expired connection for {:?}", self.key);
                 continue;
             }
@@ -321,19 +534,19 @@ impl<'a, T: Poolable + 'a, K: Debug> IdlePopper<'a, T, K> {
             let value = match entry.value.reserve() {
                 #[cfg(feature = "http2")]
                 Reservation::Shared(to_reinsert, to_checkout) => {
-                    self.list.push(Idle {
-                        idle_at: Instant::now(),
-                        value: to_reinsert,
-                    });
+                    let idle = self.expiring.track(
+                        self.key,
+                        to_reinsert,
+                        expiration.now,
+                        expiration.timeout,
+                    );
+                    self.list.push_back(idle);
                     to_checkout
                 }
                 Reservation::Unique(unique) => unique,
             };
 
-            return Some(Idle {
-                idle_at: entry.idle_at,
-                value,
-            });
+            return Some(value);
         }
 
         None
@@ -350,7 +563,7 @@ impl<T: Poolable, K: Key> PoolInner<T, K> {
         let mut remove_waiters = false;
         let mut value = Some(value);
         if let Some(waiters) = self.waiters.get_mut(&key) {
-            while let Some(tx) = waiters.pop_front() {
+            while let Some(tx) = pop_waiter(waiters) {
                 if !tx.is_canceled() {
                     let reserved = value.take().expect("value already sent");
                     let reserved = match reserved.reserve() {
@@ -386,21 +599,26 @@ impl<T: Poolable, K: Key> PoolInner<T, K> {
         match value {
             Some(value) => {
                 // borrow-check scope...
-                {
+                let timeout = {
+                    let now = self.now();
                     let idle_list = self.idle.entry(key.clone()).or_default();
                     if self.max_idle_per_host <= idle_list.len() {
                         trace!("max idle per host for {:?}, dropping connection", key);
                         return;
                     }
+                    if !value.can_idle() {
+                        trace!("can't idle connection for {:?}, dropping it", key);
+                        return;
+                    }
 
                     debug!("pooling idle connection for {:?}", key);
-                    idle_list.push(Idle {
-                        value,
-                        idle_at: Instant::now(),
-                    });
-                }
+                    let idle = self.expiring.track(&key, value, now, self.timeout);
+                    let timeout = idle.deadline.map(|deadline| deadline - now);
+                    idle_list.push_back(idle);
+                    timeout
+                };
 
-                self.spawn_idle_interval(__pool_ref);
+                self.spawn_idle_interval(timeout, __pool_ref);
             }
             None => trace!("put; found waiter for {:?}", key),
         }
@@ -417,11 +635,25 @@ impl<T: Poolable, K: Key> PoolInner<T, K> {
         self.waiters.remove(key);
     }
 
-    fn spawn_idle_interval(&mut self, pool_ref: &Arc<Mutex<PoolInner<T, K>>>) {
+    /// Start reaping idle connections every `timeout`, the idle timeout of
+    /// a connection just pooled, if it has one.
+    fn spawn_idle_interval(
+        &mut self,
+        timeout: Option<Duration>,
+        pool_ref: &Arc<Mutex<PoolInner<T, K>>>,
+    ) {
         if self.idle_interval_ref.is_some() {
-            return;
+            if !self.idle_interval_dead.load(Ordering::Acquire) {
+                return;
+            }
+            self.idle_interval_restarts += 1;
+            warn!(
+                "pool idle interval stopped unexpectedly, restarting it ({} restarts)",
+                self.idle_interval_restarts
+            );
+            self.idle_interval_ref = None;
         }
-        let dur = if let Some(dur) = self.timeout {
+        let dur = if let Some(dur) = timeout {
             dur
         } else {
             return;
@@ -433,14 +665,16 @@ impl<T: Poolable, K: Key> PoolInner<T, K> {
         };
         let (tx, rx) = oneshot::channel();
         self.idle_interval_ref = Some(tx);
+        self.idle_interval_dead = Arc::new(AtomicBool::new(false));
 
         let interval = IdleTask {
             timer: timer.clone(),
             duration: dur,
-            deadline: Instant::now(),
-            fut: timer.sleep_until(Instant::now()), // ready at first tick
+            deadline: timer.now(),
+            fut: timer.sleep_until(timer.now()), // ready at first tick
             pool: WeakOpt::downgrade(pool_ref),
             pool_drop_notifier: rx,
+            _alive: Alive(self.idle_interval_dead.clone()),
         };
 
         self.exec.execute(interval);
@@ -448,6 +682,11 @@ impl<T: Poolable, K: Key> PoolInner<T, K> {
 }
 
 impl<T, K: Eq + Hash> PoolInner<T, K> {
+    /// The current time, by the timer if there is one.
+    fn now(&self) -> Instant {
+        timer::now(self.timer.as_ref())
+    }
+
     /// Any `FutureResponse`s that were created will have made a `Checkout`,
     /// and possibly inserted into the pool that it is waiting for an idle
     /// connection. If a user ever dropped that future, we need to clean out
@@ -466,32 +705,50 @@ impl<T, K: Eq + Hash> PoolInner<T, K> {
 
 impl<T: Poolable, K: Key> PoolInner<T, K> {
     /// This should *only* be called by the IdleTask
+    ///
+    /// The lists of the expired connections are swept once, which evicts
+    /// the closed connections in them too. Other closed connections are
+    /// left for checkouts to skip, or until they expire.
     fn clear_expired(&mut self) {
-        let dur = self.timeout.expect("interval assumes timeout");
-
-        let now = Instant::now();
-        //self.last_idle_check_at = now;
-
-        self.idle.retain(|key, values| {
-            values.retain(|entry| {
-                if !entry.value.is_open() {
-                    trace!("idle interval evicting closed for {:?}", key);
-                    return false;
-                }
-
-                // Avoid `Instant::sub` to avoid issues like rust-lang/rust#86470.
-                if now.saturating_duration_since(entry.idle_at) > dur {
-                    trace!("idle interval evicting expired for {:?}", key);
-                    return false;
-                }
+        let now = self.now();
 
-                // Otherwise, keep this value...
-                true
-            });
+        let mut expired = HashSet::new();
+        while let Some(&(deadline, id)) = self.expiring.deadlines.keys().next() {
+            // The deadlines are ordered, so none of the rest passed either.
+            if deadline >= now {
+                break;
+            }
+            let key = self
+                .expiring
+                .deadlines
+                .remove(&(deadline, id))
+                .expect("first deadline");
+            expired.insert(key);
+        }
 
-            // returning false evicts this key/val
-            !values.is_empty()
-        });
+        for key in expired {
+            let expiring = &mut self.expiring;
+            let mut empty = false;
+            if let Some(list) = self.idle.get_mut(&key) {
+                list.retain(|entry| {
+                    // its deadline was just removed
+                    if entry.deadline.map_or(false, |deadline| deadline < now) {
+                        trace!("idle interval evicting expired for {:?}", key);
+                        return false;
+                    }
+                    if !entry.value.is_open() {
+                        trace!("idle interval evicting closed for {:?}", key);
+                        expiring.untrack(entry);
+                        return false;
+                    }
+                    true
+                });
+                empty = list.is_empty();
+            }
+            if empty {
+                self.idle.remove(&key);
+            }
+        }
     }
 }
 
@@ -499,6 +756,8 @@ impl<T, K: Key> Clone for Pool<T, K> {
     fn clone(&self) -> Pool<T, K> {
         Pool {
             inner: self.inner.clone(),
+            activity: self.activity.clone(),
+            checked_out: self.checked_out.clone(),
         }
     }
 }
@@ -509,7 +768,11 @@ pub struct Pooled<T: Poolable, K: Key> {
     value: Option<T>,
     is_reused: bool,
     key: K,
+    generation: u64,
     pool: WeakOpt<Mutex<PoolInner<T, K>>>,
+    // Dropped after the value is returned to the pool.
+    _active: Active,
+    _checked_out: CheckedOutKey<K>,
 }
 
 impl<T: Poolable, K: Key> Pooled<T, K> {
@@ -521,6 +784,28 @@ impl<T: Poolable, K: Key> Pooled<T, K> {
         self.pool.0.is_some()
     }
 
+    /// Return the connection to the pool once it is ready again, even if
+    /// the client wouldn't have kept it.
+    pub fn return_to_pool(&self) {
+        if let Some(disposition) = self.as_ref().disposition() {
+            disposition.return_to_pool();
+        }
+    }
+
+    /// Stop reusing the connection.
+    pub fn close(&self) {
+        if let Some(disposition) = self.as_ref().disposition() {
+            disposition.close();
+        }
+    }
+
+    /// Stop reusing the connection, because it is broken.
+    pub fn poison(&self, reason: impl Into<String>) {
+        if let Some(disposition) = self.as_ref().disposition() {
+            disposition.poison(reason);
+        }
+    }
+
     fn as_ref(&self) -> &T {
         self.value.as_ref().expect("not dropped")
     }
@@ -554,6 +839,10 @@ impl<T: Poolable, K: Key> Drop for Pooled<T, K> {
 
             if let Some(pool) = self.pool.upgrade() {
                 if let Ok(mut inner) = pool.lock() {
+                    if inner.generation != self.generation {
+                        trace!("pool cleared, dropping pooled ({:?})", self.key);
+                        return;
+                    }
                     inner.put(self.key.clone(), value, &pool);
                 }
             } else if !value.can_share() {
@@ -574,6 +863,72 @@ impl<T: Poolable, K: Key> fmt::Debug for Pooled<T, K> {
 struct Idle<T> {
     idle_at: Instant,
     value: T,
+    id: u64,
+    deadline: Option<Instant>,
+}
+
+// The deadlines of the idle connections, ordered, so the idle interval
+// only visits those that expired, rather than every idle connection.
+struct Expiring<K> {
+    deadlines: BTreeMap<(Instant, u64), K>,
+    next_id: u64,
+}
+
+impl<K: Clone> Expiring<K> {
+    // Make an entry for a connection idle since `now`, expiring by the
+    // pool's `timeout` or its own, whichever is shorter.
+    fn track<T: Poolable>(
+        &mut self,
+        key: &K,
+        value: T,
+        now: Instant,
+        timeout: Option<Duration>,
+    ) -> Idle<T> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let timeout = match (timeout, value.idle_timeout()) {
+            (Some(timeout), Some(own)) => Some(timeout.min(own)),
+            (timeout, own) => timeout.or(own),
+        };
+        let deadline = timeout.and_then(|timeout| now.checked_add(timeout));
+        if let Some(deadline) = deadline {
+            self.deadlines.insert((deadline, id), key.clone());
+        }
+        Idle {
+            idle_at: now,
+            value,
+            id,
+            deadline,
+        }
+    }
+
+    // The entry left the idle list.
+    fn untrack<T>(&mut self, entry: &Idle<T>) {
+        if let Some(deadline) = entry.deadline {
+            self.deadlines.remove(&(deadline, entry.id));
+        }
+    }
+}
+
+struct Waiter<T> {
+    tx: oneshot::Sender<T>,
+    background: bool,
+}
+
+impl<T> Waiter<T> {
+    fn is_canceled(&self) -> bool {
+        self.tx.is_canceled()
+    }
+}
+
+// The longest waiting checkout that isn't background, or else the longest
+// waiting background one.
+fn pop_waiter<T>(waiters: &mut VecDeque<Waiter<T>>) -> Option<oneshot::Sender<T>> {
+    let next = waiters
+        .iter()
+        .position(|waiter| !waiter.background)
+        .unwrap_or(0);
+    waiters.remove(next).map(|waiter| waiter.tx)
 }
 
 // FIXME: allow() required due to `impl Trait` leaking types to this lint
@@ -582,6 +937,7 @@ pub struct Checkout<T, K: Key> {
     key: K,
     pool: Pool<T, K>,
     waiter: Option<oneshot::Receiver<T>>,
+    background: bool,
 }
 
 #[derive(Debug)]
@@ -590,12 +946,17 @@ pub enum Error {
     PoolDisabled,
     CheckoutNoLongerWanted,
     CheckedOutClosedValue,
+    PoolQueueFull,
 }
 
 impl Error {
     pub(super) fn is_canceled(&self) -> bool {
         matches!(self, Error::CheckedOutClosedValue)
     }
+
+    pub(super) fn is_pool_queue_full(&self) -> bool {
+        matches!(self, Error::PoolQueueFull)
+    }
 }
 
 impl fmt::Display for Error {
@@ -604,6 +965,7 @@ impl fmt::Display for Error {
             Error::PoolDisabled => "pool is disabled",
             Error::CheckedOutClosedValue => "checked out connection was closed",
             Error::CheckoutNoLongerWanted => "request was canceled",
+            Error::PoolQueueFull => "too many requests waiting for a connection",
         })
     }
 }
@@ -637,18 +999,31 @@ impl<T: Poolable, K: Key> Checkout<T, K> {
         }
     }
 
-    fn checkout(&mut self, cx: &mut task::Context<'_>) -> Option<Pooled<T, K>> {
+    fn checkout(&mut self, cx: &mut task::Context<'_>) -> Result<Option<Pooled<T, K>>, Error> {
         let entry = {
-            let mut inner = self.pool.inner.as_ref()?.lock().unwrap();
-            let expiration = Expiration::new(inner.timeout);
-            let maybe_entry = inner.idle.get_mut(&self.key).and_then(|list| {
-                trace!("take? {:?}: expiration = {:?}", self.key, expiration.0);
+            let mut inner = match self.pool.inner {
+                Some(ref inner) => inner.lock().unwrap(),
+                None => return Ok(None),
+            };
+            let expiration = Expiration::new(inner.timeout, inner.now());
+            let PoolInner {
+                ref mut idle,
+                ref mut expiring,
+                ..
+            } = *inner;
+            let maybe_entry = idle.get_mut(&self.key).and_then(|list| {
+                trace!(
+                    "take? {:?}: expiration = {:?}",
+                    self.key,
+                    expiration.timeout
+                );
                 // A block to end the mutable borrow on list,
                 // so the map below can check is_empty()
                 {
                     let popper = IdlePopper {
                         key: &self.key,
                         list,
+                        expiring,
                     };
                     popper.pop(&expiration)
                 }
@@ -667,13 +1042,23 @@ impl<T: Poolable, K: Key> Checkout<T, K> {
             }
 
             if entry.is_none() && self.waiter.is_none() {
+                let waiting = inner.waiters.get(&self.key).map_or(0, |waiters| {
+                    waiters.iter().filter(|tx| !tx.is_canceled()).count()
+                });
+                if waiting >= inner.max_waiters_per_host {
+                    trace!("checkout queue full: {:?}", self.key);
+                    return Err(Error::PoolQueueFull);
+                }
                 let (tx, mut rx) = oneshot::channel();
                 trace!("checkout waiting for idle connection: {:?}", self.key);
                 inner
                     .waiters
                     .entry(self.key.clone())
                     .or_insert_with(VecDeque::new)
-                    .push_back(tx);
+                    .push_back(Waiter {
+                        tx,
+                        background: self.background,
+                    });
 
                 // register the waker with this oneshot
                 assert!(Pin::new(&mut rx).poll(cx).is_pending());
@@ -683,7 +1068,7 @@ impl<T: Poolable, K: Key> Checkout<T, K> {
             entry
         };
 
-        entry.map(|e| self.pool.reuse(&self.key, e.value))
+        Ok(entry.map(|value| self.pool.reuse(&self.key, value)))
     }
 }
 
@@ -695,7 +1080,7 @@ impl<T: Poolable, K: Key> Future for Checkout<T, K> {
             return Poll::Ready(Ok(pooled));
         }
 
-        if let Some(pooled) = self.checkout(cx) {
+        if let Some(pooled) = self.checkout(cx)? {
             Poll::Ready(Ok(pooled))
         } else if !self.pool.is_enabled() {
             Poll::Ready(Err(Error::PoolDisabled))
@@ -722,7 +1107,9 @@ impl<T, K: Key> Drop for Checkout<T, K> {
 #[allow(missing_debug_implementations)]
 pub struct Connecting<T: Poolable, K: Key> {
     key: K,
+    generation: u64,
     pool: WeakOpt<Mutex<PoolInner<T, K>>>,
+    _active: Active,
 }
 
 impl<T: Poolable, K: Key> Connecting<T, K> {
@@ -747,22 +1134,396 @@ impl<T: Poolable, K: Key> Drop for Connecting<T, K> {
     }
 }
 
-struct Expiration(Option<Duration>);
+// Counts the connections checked out of the pool or being established, so
+// `Pool::idle` knows when there are none.
+#[derive(Clone)]
+struct Activity(Arc<Mutex<ActivityState>>);
 
-impl Expiration {
-    fn new(dur: Option<Duration>) -> Expiration {
-        Expiration(dur)
+struct ActivityState {
+    active: usize,
+    waiters: Vec<task::Waker>,
+}
+
+// Marks one connection as active until dropped.
+pub(crate) struct Active(Activity);
+
+// Counts the connections checked out of the pool, by key.
+struct CheckedOut<K>(Arc<Mutex<HashMap<K, usize>>>);
+
+// Marks one connection to a key as checked out until dropped.
+struct CheckedOutKey<K: Key>(CheckedOut<K>, K);
+
+impl<K: Key> CheckedOut<K> {
+    fn enter(&self, key: &K) -> CheckedOutKey<K> {
+        *self.0.lock().unwrap().entry(key.clone()).or_insert(0) += 1;
+        CheckedOutKey(self.clone(), key.clone())
+    }
+}
+
+impl<K> Clone for CheckedOut<K> {
+    fn clone(&self) -> CheckedOut<K> {
+        CheckedOut(self.0.clone())
     }
+}
 
-    fn expires(&self, instant: Instant) -> bool {
-        match self.0 {
-            // Avoid `Instant::elapsed` to avoid issues like rust-lang/rust#86470.
-            Some(timeout) => Instant::now().saturating_duration_since(instant) > timeout,
-            None => false,
+impl<K: Key> Drop for CheckedOutKey<K> {
+    fn drop(&mut self) {
+        // No need to panic on drop, that could abort!
+        if let Ok(mut counts) = (self.0).0.lock() {
+            if let Some(count) = counts.get_mut(&self.1) {
+                *count -= 1;
+                if *count == 0 {
+                    counts.remove(&self.1);
+                }
+            }
         }
     }
 }
 
+/// A future that resolves once a pool is idle.
+///
+/// See [`Pool::idle`].
+#[must_use = "futures do nothing unless polled"]
+pub struct WhenIdle {
+    activity: Activity,
+}
+
+impl Activity {
+    fn new() -> Activity {
+        Activity(Arc::new(Mutex::new(ActivityState {
+            active: 0,
+            waiters: Vec::new(),
+        })))
+    }
+
+    fn enter(&self) -> Active {
+        self.0.lock().unwrap().active += 1;
+        Active(self.clone())
+    }
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        // No need to panic on drop, that could abort!
+        let waiters = match (self.0).0.lock() {
+            Ok(mut state) => {
+                state.active -= 1;
+                if state.active > 0 {
+                    return;
+                }
+                std::mem::take(&mut state.waiters)
+            }
+            Err(_) => return,
+        };
+        for waker in waiters {
+            waker.wake();
+        }
+    }
+}
+
+impl Future for WhenIdle {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.activity.0.lock().unwrap();
+        if state.active == 0 {
+            return Poll::Ready(());
+        }
+        if !state.waiters.iter().any(|w| w.will_wake(cx.waker())) {
+            state.waiters.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+impl fmt::Debug for WhenIdle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WhenIdle").finish()
+    }
+}
+
+/// What becomes of the connection a request was sent on.
+///
+/// The `Client` puts a `Disposition` in the extensions of every response.
+/// By default, the client decides whether the connection is reused, such
+/// as by the `Keep-Alive` header of the server, or the memory budget. When
+/// the application knows better, such as after a protocol error in the
+/// middle of the body, it can decide instead:
+///
+/// - [`return_to_pool`](Disposition::return_to_pool) keeps the connection
+///   once the response is done, where the client would have dropped it,
+/// - [`close`](Disposition::close) stops reusing the connection,
+/// - [`poison`](Disposition::poison) stops reusing it too, as broken, with
+///   the reason logged.
+///
+/// An HTTP/2 connection is shared by requests, so closing it stops it from
+/// taking new requests, while those in progress finish. Closing or
+/// poisoning a connection wins over returning it.
+///
+/// The client decides whether to keep an HTTP/1 connection once the
+/// response body is read, so a connection is only returned if asked before
+/// then. A response without a body is done before the client gives it to
+/// the caller, so its connection is returned from an
+/// [`OnResponseHead`](super::OnResponseHead) callback.
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::Disposition;
+///
+/// fn on_garbled_body<B>(res: &http::Response<B>) {
+///     if let Some(disposition) = res.extensions().get::<Disposition>() {
+///         disposition.poison("garbled chunked encoding");
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Disposition {
+    state: Arc<Mutex<DispositionState>>,
+}
+
+#[derive(Debug)]
+enum DispositionState {
+    Auto,
+    Return,
+    Close,
+    Poison(String),
+}
+
+impl Disposition {
+    pub(super) fn new() -> Disposition {
+        Disposition {
+            state: Arc::new(Mutex::new(DispositionState::Auto)),
+        }
+    }
+
+    /// Return the connection to the pool once the response is done, even if
+    /// the client wouldn't have kept it.
+    ///
+    /// This has no effect on a connection already done with the response.
+    pub fn return_to_pool(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let DispositionState::Auto = *state {
+            *state = DispositionState::Return;
+        }
+    }
+
+    /// Stop reusing the connection.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let DispositionState::Auto | DispositionState::Return = *state {
+            *state = DispositionState::Close;
+        }
+    }
+
+    /// Stop reusing the connection, because it is broken.
+    pub fn poison(&self, reason: impl Into<String>) {
+        let reason = reason.into();
+        debug!("connection poisoned: {}", reason);
+        *self.state.lock().unwrap() = DispositionState::Poison(reason);
+    }
+
+    /// The reason the connection was poisoned, if it was.
+    pub fn poison_reason(&self) -> Option<String> {
+        match *self.state.lock().unwrap() {
+            DispositionState::Poison(ref reason) => Some(reason.clone()),
+            _ => None,
+        }
+    }
+
+    /// Whether the connection may be reused.
+    pub(super) fn is_open(&self) -> bool {
+        matches!(
+            *self.state.lock().unwrap(),
+            DispositionState::Auto | DispositionState::Return
+        )
+    }
+
+    /// Whether the connection must be returned to the pool.
+    pub(super) fn is_returned(&self) -> bool {
+        matches!(*self.state.lock().unwrap(), DispositionState::Return)
+    }
+
+    /// Forget a return of the previous request, before sending another one.
+    pub(super) fn renew(&self) {
+        let mut state = self.state.lock().unwrap();
+        if let DispositionState::Return = *state {
+            *state = DispositionState::Auto;
+        }
+    }
+}
+
+impl fmt::Debug for Disposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Disposition")
+            .field(&*self.state.lock().unwrap())
+            .finish()
+    }
+}
+
+/// Receives snapshots of the pool of a `Client` at a regular interval.
+///
+/// Set with
+/// [`Builder::pool_stats_reporter`](super::Builder::pool_stats_reporter).
+/// Each process of a deployment can forward its snapshots to a collector,
+/// such as over a Unix socket or into shared memory, to tune pooling, like
+/// the idle timeout, across the whole fleet.
+///
+/// Reports are made from a task of the client's executor, so `report`
+/// should return quickly, handing the snapshot off rather than doing IO.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::mpsc::SyncSender;
+/// use std::sync::Mutex;
+/// use hyper_util::client::legacy::{PoolDump, PoolStatsReporter};
+///
+/// struct Forward(Mutex<SyncSender<PoolDump>>);
+///
+/// impl PoolStatsReporter for Forward {
+///     fn report(&self, dump: &PoolDump) {
+///         // dropped if the collector is behind
+///         let _ = self.0.lock().unwrap().try_send(dump.clone());
+///     }
+/// }
+/// ```
+pub trait PoolStatsReporter: Send + Sync {
+    /// Receive a snapshot of the pool.
+    fn report(&self, dump: &PoolDump);
+}
+
+/// A snapshot of the state of a connection pool.
+///
+/// The `Debug` output has every field, while `Display` is a report meant
+/// for people, such as on an admin page.
+#[derive(Clone, Debug)]
+pub struct PoolDump {
+    enabled: bool,
+    in_use: usize,
+    hosts: Vec<HostDump>,
+    idle_interval_restarts: u64,
+}
+
+/// The pooled connections to one host in a [`PoolDump`].
+#[derive(Clone, Debug)]
+pub struct HostDump {
+    key: String,
+    idle: Vec<Duration>,
+    waiters: usize,
+    connecting: bool,
+}
+
+impl PoolDump {
+    /// Whether pooling is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// The number of connections checked out or being established.
+    pub fn in_use(&self) -> usize {
+        self.in_use
+    }
+
+    /// The hosts with idle connections, waiting requests or a connection
+    /// being established, ordered by key.
+    pub fn hosts(&self) -> &[HostDump] {
+        &self.hosts
+    }
+
+    /// How many times the task expiring idle connections stopped, because
+    /// it panicked or the executor dropped it, and was spawned again.
+    pub fn idle_interval_restarts(&self) -> u64 {
+        self.idle_interval_restarts
+    }
+}
+
+impl fmt::Display for PoolDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.enabled {
+            return write!(f, "pool disabled, {} in use", self.in_use);
+        }
+        let idle = self.hosts.iter().map(|host| host.idle.len()).sum::<usize>();
+        write!(
+            f,
+            "pool: {} hosts, {} idle, {} in use",
+            self.hosts.len(),
+            idle,
+            self.in_use
+        )?;
+        if self.idle_interval_restarts > 0 {
+            write!(
+                f,
+                ", idle interval restarted {} times",
+                self.idle_interval_restarts
+            )?;
+        }
+        for host in &self.hosts {
+            write!(f, "\n{}", host)?;
+        }
+        Ok(())
+    }
+}
+
+impl HostDump {
+    /// The pool key, such as `http://example.com:80`.
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// How long each idle connection has been idle, oldest first.
+    pub fn idle(&self) -> &[Duration] {
+        &self.idle
+    }
+
+    /// The number of requests waiting for an idle connection.
+    pub fn waiters(&self) -> usize {
+        self.waiters
+    }
+
+    /// Whether an HTTP/2 connection is being established, which other
+    /// requests wait on rather than dialing their own.
+    pub fn is_connecting(&self) -> bool {
+        self.connecting
+    }
+}
+
+impl fmt::Display for HostDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "  {}: {} idle", self.key, self.idle.len())?;
+        if !self.idle.is_empty() {
+            f.write_str(" (")?;
+            for (i, age) in self.idle.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "{:.1?}", age)?;
+            }
+            f.write_str(")")?;
+        }
+        write!(f, ", {} waiting", self.waiters)?;
+        if self.connecting {
+            f.write_str(", connecting")?;
+        }
+        Ok(())
+    }
+}
+
+struct Expiration {
+    timeout: Option<Duration>,
+    now: Instant,
+}
+
+impl Expiration {
+    fn new(timeout: Option<Duration>, now: Instant) -> Expiration {
+        Expiration { timeout, now }
+    }
+
+    fn expires<T>(&self, entry: &Idle<T>) -> bool {
+        entry.deadline.map_or(false, |deadline| self.now > deadline)
+    }
+}
+
 pin_project_lite::pin_project! {
     struct IdleTask<T, K: Key> {
         timer: Timer,
@@ -775,6 +1536,18 @@ pin_project_lite::pin_project! {
         // but Err(Canceled) will be received when the Pool is dropped.
         #[pin]
         pool_drop_notifier: oneshot::Receiver<Infallible>,
+        _alive: Alive,
+    }
+}
+
+// Marks the IdleTask dead when it is dropped. It only ends on its own once
+// the pool is gone, so if the pool sees this, the task panicked or the
+// executor dropped it.
+struct Alive(Arc<AtomicBool>);
+
+impl Drop for Alive {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Release);
     }
 }
 
@@ -798,8 +1571,9 @@ impl<T: Poolable + 'static, K: Key> Future for IdleTask<T, K> {
             // If the poll missed the deadline by a lot, set the deadline
             // from the current time instead
             *this.deadline += *this.duration;
-            if *this.deadline < Instant::now() - Duration::from_millis(5) {
-                *this.deadline = Instant::now() + *this.duration;
+            let now = this.timer.now();
+            if *this.deadline < now - Duration::from_millis(5) {
+                *this.deadline = now + *this.duration;
             }
             *this.fut = this.timer.sleep_until(*this.deadline);
 
@@ -815,6 +1589,20 @@ impl<T: Poolable + 'static, K: Key> Future for IdleTask<T, K> {
     }
 }
 
+impl<T, K: Key> WeakPool<T, K> {
+    pub(crate) fn upgrade(&self) -> Option<Pool<T, K>> {
+        let inner = match self.inner {
+            Some(ref inner) => Some(inner.upgrade()?),
+            None => None,
+        };
+        Some(Pool {
+            inner,
+            activity: Activity(self.activity.upgrade()?),
+            checked_out: CheckedOut(self.checked_out.upgrade()?),
+        })
+    }
+}
+
 impl<T> WeakOpt<T> {
     fn none() -> Self {
         WeakOpt(None)
@@ -869,7 +1657,9 @@ mod tests {
     fn c<T: Poolable, K: Key>(key: K) -> Connecting<T, K> {
         Connecting {
             key,
+            generation: 0,
             pool: WeakOpt::none(),
+            _active: super::Activity::new().enter(),
         }
     }
 
@@ -886,6 +1676,7 @@ mod tests {
             super::Config {
                 idle_timeout: Some(Duration::from_millis(100)),
                 max_idle_per_host: max_idle,
+                max_waiters_per_host: usize::MAX,
             },
             TokioExecutor::new(),
             Option::<timer::Timer>::None,
@@ -908,6 +1699,25 @@ mod tests {
         };
     }
 
+    #[tokio::test]
+    async fn test_pool_clear_drops_idle_and_in_use() {
+        let pool = pool_no_timer();
+        let key = host_key("foo");
+        let idle = pool.pooled(pool.connecting(&key, super::Ver::Auto).unwrap(), Uniq(41));
+        drop(idle);
+        let in_use = pool.pooled(pool.connecting(&key, super::Ver::Auto).unwrap(), Uniq(42));
+
+        pool.clear();
+        assert!(!pool.locked().idle.contains_key(&key));
+
+        drop(in_use);
+        assert!(!pool.locked().idle.contains_key(&key));
+
+        let pooled = pool.pooled(pool.connecting(&key, super::Ver::Auto).unwrap(), Uniq(43));
+        drop(pooled);
+        assert_eq!(pool.locked().idle.get(&key).map(|list| list.len()), Some(1));
+    }
+
     /// Helper to check if the future is ready after polling once.
     struct PollOnce<'a, F>(&'a mut F);
 
@@ -984,6 +1794,7 @@ mod tests {
             super::Config {
                 idle_timeout: Some(Duration::from_millis(10)),
                 max_idle_per_host: std::usize::MAX,
+                max_waiters_per_host: usize::MAX,
             },
             TokioExecutor::new(),
             Some(TokioTimer::new()),
@@ -1008,6 +1819,128 @@ mod tests {
         assert!(pool.locked().idle.get(&key).is_none());
     }
 
+    #[tokio::test]
+    async fn test_pool_timer_clock_expires() {
+        let timer = crate::rt::ManualTimer::new();
+        let pool = Pool::new(
+            super::Config {
+                idle_timeout: Some(Duration::from_secs(90)),
+                max_idle_per_host: std::usize::MAX,
+                max_waiters_per_host: usize::MAX,
+            },
+            TokioExecutor::new(),
+            Some(timer.clone()),
+        );
+
+        let key = host_key("foo");
+        pool.pooled(c(key.clone()), Uniq(41));
+        pool.pooled(c(key.clone()), Uniq(5));
+        tokio::task::yield_now().await;
+
+        timer.advance(Duration::from_secs(60));
+        tokio::task::yield_now().await;
+        assert_eq!(
+            pool.locked().idle.get(&key).map(|entries| entries.len()),
+            Some(2)
+        );
+
+        // expired by the clock of the timer, without waiting for real
+        timer.advance(Duration::from_secs(60));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(pool.locked().idle.get(&key).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_pool_timer_reaps_in_deadline_order() {
+        let timer = crate::rt::ManualTimer::new();
+        let pool = Pool::new(
+            super::Config {
+                idle_timeout: Some(Duration::from_secs(90)),
+                max_idle_per_host: std::usize::MAX,
+                max_waiters_per_host: usize::MAX,
+            },
+            TokioExecutor::new(),
+            Some(timer.clone()),
+        );
+
+        let foo = host_key("foo");
+        let bar = host_key("bar");
+        pool.pooled(c(foo.clone()), Uniq(1));
+        timer.advance(Duration::from_secs(60));
+        pool.pooled(c(bar.clone()), Uniq(2));
+        pool.pooled(c(foo.clone()), Uniq(3));
+        assert_eq!(pool.locked().expiring.deadlines.len(), 3);
+
+        timer.advance(Duration::from_secs(60));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        {
+            let inner = pool.locked();
+            assert_eq!(inner.expiring.deadlines.len(), 2);
+            assert_eq!(inner.idle[&foo].len(), 1);
+            assert_eq!(inner.idle[&foo][0].value, Uniq(3));
+            assert_eq!(inner.idle[&bar].len(), 1);
+        }
+
+        // a checkout stops tracking the connection it takes, until it is
+        // put back
+        let pooled = pool.checkout(bar).await.unwrap();
+        assert_eq!(pool.locked().expiring.deadlines.len(), 1);
+        drop(pooled);
+        assert_eq!(pool.locked().expiring.deadlines.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_pool_timer_restarts_when_dropped() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        use crate::common::exec::BoxSendFuture;
+
+        // Drops the first future it is given, as a faulty executor would.
+        #[derive(Clone)]
+        struct DropFirst(Arc<AtomicUsize>);
+
+        impl hyper::rt::Executor<BoxSendFuture> for DropFirst {
+            fn execute(&self, fut: BoxSendFuture) {
+                if self.0.fetch_add(1, Ordering::SeqCst) > 0 {
+                    tokio::spawn(fut);
+                }
+            }
+        }
+
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let pool = Pool::new(
+            super::Config {
+                idle_timeout: Some(Duration::from_millis(10)),
+                max_idle_per_host: std::usize::MAX,
+                max_waiters_per_host: usize::MAX,
+            },
+            DropFirst(spawned.clone()),
+            Some(TokioTimer::new()),
+        );
+
+        let key = host_key("foo");
+        pool.pooled(c(key.clone()), Uniq(41));
+        assert_eq!(spawned.load(Ordering::SeqCst), 1);
+
+        pool.pooled(c(key.clone()), Uniq(5));
+        assert_eq!(spawned.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.dump(|_| String::new()).idle_interval_restarts(), 1);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        tokio::task::yield_now().await;
+
+        assert!(pool.locked().idle.get(&key).is_none());
+
+        // the restarted interval is alive, so isn't spawned again
+        pool.pooled(c(key.clone()), Uniq(99));
+        assert_eq!(spawned.load(Ordering::SeqCst), 2);
+    }
+
     #[tokio::test]
     async fn test_pool_checkout_task_unparked() {
         use futures_util::future::join;
@@ -1055,9 +1988,76 @@ mod tests {
         assert!(pool.locked().waiters.get(&key).is_none());
     }
 
+    #[tokio::test]
+    async fn test_pool_serves_background_waiters_last() {
+        let pool = pool_no_timer::<Uniq<i32>, KeyImpl>();
+        let key = host_key("foo");
+
+        let mut background = pool.checkout_background(key.clone());
+        let mut interactive = pool.checkout(key.clone());
+        PollOnce(&mut background).await;
+        PollOnce(&mut interactive).await;
+
+        // the interactive checkout started waiting last, but is served first
+        pool.pooled(c(key.clone()), Uniq(41));
+        let interactive = interactive.await.unwrap();
+        assert_eq!(*interactive, Uniq(41));
+
+        pool.pooled(c(key.clone()), Uniq(5));
+        assert_eq!(*background.await.unwrap(), Uniq(5));
+        drop(interactive);
+    }
+
+    #[tokio::test]
+    async fn test_pool_checkout_queue_full() {
+        let pool = Pool::<Uniq<i32>, KeyImpl>::new(
+            super::Config {
+                idle_timeout: Some(Duration::from_millis(100)),
+                max_idle_per_host: usize::MAX,
+                max_waiters_per_host: 1,
+            },
+            TokioExecutor::new(),
+            Option::<timer::Timer>::None,
+        );
+        let key = host_key("foo");
+
+        let mut checkout1 = pool.checkout(key.clone());
+        PollOnce(&mut checkout1).await;
+        assert_eq!(pool.locked().waiters.get(&key).unwrap().len(), 1);
+
+        let err = pool.checkout(key.clone()).await.unwrap_err();
+        assert!(err.is_pool_queue_full());
+
+        // another key has its own queue
+        let mut checkout2 = pool.checkout(host_key("bar"));
+        PollOnce(&mut checkout2).await;
+
+        // once the waiter is gone, there is room again
+        drop(checkout1);
+        let mut checkout3 = pool.checkout(key.clone());
+        PollOnce(&mut checkout3).await;
+        assert_eq!(pool.locked().waiters.get(&key).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_pool_counts_waiters() {
+        let pool = pool_no_timer::<Uniq<i32>, KeyImpl>();
+        let foo = host_key("foo");
+        let bar = host_key("bar");
+
+        let mut checkout1 = pool.checkout(foo.clone());
+        let mut checkout2 = pool.checkout(bar.clone());
+        PollOnce(&mut checkout1).await;
+        PollOnce(&mut checkout2).await;
+        assert_eq!(pool.waiters(|key| *key == foo), 1);
+        assert_eq!(pool.waiters(|_| true), 2);
+
+        drop(checkout1);
+        assert_eq!(pool.waiters(|key| *key == foo), 0);
+    }
+
     #[derive(Debug)]
     struct CanClose {
-        #[allow(unused)]
         val: i32,
         closed: bool,
     }
@@ -1090,4 +2090,126 @@ mod tests {
 
         assert!(!pool.locked().idle.contains_key(&key));
     }
+
+    #[tokio::test]
+    async fn test_pool_timer_evicts_closed_with_expired() {
+        let timer = crate::rt::ManualTimer::new();
+        let pool = Pool::new(
+            super::Config {
+                idle_timeout: Some(Duration::from_secs(90)),
+                max_idle_per_host: std::usize::MAX,
+                max_waiters_per_host: usize::MAX,
+            },
+            TokioExecutor::new(),
+            Some(timer.clone()),
+        );
+
+        let key = host_key("foo");
+        let conn = |val| CanClose { val, closed: false };
+        pool.pooled(c(key.clone()), conn(1));
+        timer.advance(Duration::from_secs(60));
+        pool.pooled(c(key.clone()), conn(2));
+        pool.pooled(c(key.clone()), conn(3));
+        pool.locked().idle.get_mut(&key).unwrap()[2].value.closed = true;
+
+        timer.advance(Duration::from_secs(60));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        let inner = pool.locked();
+        assert_eq!(inner.expiring.deadlines.len(), 1);
+        assert_eq!(inner.idle[&key].len(), 1);
+        assert_eq!(inner.idle[&key][0].value.val, 2);
+    }
+
+    // A connection whose server hinted how long it may be idle.
+    #[derive(Debug)]
+    struct Hinted(Duration);
+
+    impl Poolable for Hinted {
+        fn is_open(&self) -> bool {
+            true
+        }
+
+        fn reserve(self) -> Reservation<Self> {
+            Reservation::Unique(self)
+        }
+
+        fn can_share(&self) -> bool {
+            false
+        }
+
+        fn idle_timeout(&self) -> Option<Duration> {
+            Some(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pool_timer_reaps_hinted_without_idle_timeout() {
+        let timer = crate::rt::ManualTimer::new();
+        let pool = Pool::new(
+            super::Config {
+                idle_timeout: None,
+                max_idle_per_host: std::usize::MAX,
+                max_waiters_per_host: usize::MAX,
+            },
+            TokioExecutor::new(),
+            Some(timer.clone()),
+        );
+
+        let key = host_key("foo");
+        pool.pooled(c(key.clone()), Hinted(Duration::from_secs(5)));
+        assert_eq!(pool.locked().idle[&key].len(), 1);
+
+        timer.advance(Duration::from_secs(10));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        let inner = pool.locked();
+        assert!(inner.idle.get(&key).is_none());
+        assert!(inner.expiring.deadlines.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_pool_idle() {
+        use futures_util::FutureExt;
+
+        let pool = pool_no_timer();
+        let key = host_key("foo");
+        assert!(pool.idle().now_or_never().is_some());
+
+        let connecting = pool.connecting(&key, super::Ver::Auto).unwrap();
+        let mut idle = pool.idle();
+        assert!((&mut idle).now_or_never().is_none());
+
+        let pooled = pool.pooled(connecting, Uniq(41));
+        assert!((&mut idle).now_or_never().is_none());
+
+        drop(pooled);
+        assert!(idle.now_or_never().is_some());
+        assert!(pool.locked().idle.contains_key(&key));
+    }
+
+    #[tokio::test]
+    async fn test_pool_dump() {
+        let pool = pool_no_timer();
+        let key = host_key("foo");
+        pool.pooled(c(key.clone()), Uniq(41));
+        pool.pooled(c(key.clone()), Uniq(5));
+        let _h2 = pool
+            .connecting(&host_key("bar"), super::Ver::Http2)
+            .unwrap();
+
+        let dump = pool.dump(|key| key.1.to_string());
+        assert_eq!(dump.in_use(), 1);
+        let hosts = dump.hosts();
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(hosts[0].key(), "bar");
+        assert!(hosts[0].is_connecting());
+        assert_eq!(hosts[1].key(), "foo");
+        assert_eq!(hosts[1].idle().len(), 2);
+        assert!(dump
+            .to_string()
+            .starts_with("pool: 2 hosts, 2 idle, 1 in use\n  bar: 0 idle, 0 waiting, connecting\n  foo: 2 idle ("));
+    }
 }
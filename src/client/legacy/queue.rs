@@ -0,0 +1,191 @@
+//! The admission queue of a `Client`, with `Builder::request_queue_max_requests`
+//! and `Builder::request_queue_max_bytes`.
+//!
+//! A request is queued from when it is sent until it gets a connection. The
+//! bytes of a queued request are the lower bound of its body's size hint,
+//! since the caller keeps a buffered body in memory while it waits.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use futures_channel::oneshot;
+use futures_util::future::{self, Either};
+use tracing::trace;
+
+/// What the `Client` does with a request that would overflow its queue.
+///
+/// See [`Builder::request_queue_overflow`](super::Builder::request_queue_overflow).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum QueueOverflow {
+    /// Fail the new request.
+    ///
+    /// This is the default.
+    #[default]
+    Reject,
+    /// Fail the requests that have waited longest until the new one fits,
+    /// since their callers are the most likely to have given up on them.
+    DropOldest,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Config {
+    pub(super) max_requests: Option<usize>,
+    pub(super) max_bytes: Option<usize>,
+    pub(super) overflow: QueueOverflow,
+}
+
+/// The requests of a `Client` waiting for a connection.
+#[derive(Debug)]
+pub(super) struct Queue {
+    config: Config,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u64,
+    bytes: usize,
+    entries: VecDeque<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: u64,
+    bytes: usize,
+    // Dropped to fail the request, with `QueueOverflow::DropOldest`.
+    evict: oneshot::Sender<()>,
+}
+
+/// A request in the queue, leaving it on drop.
+#[derive(Debug)]
+pub(super) struct Queued {
+    queue: Arc<Queue>,
+    id: u64,
+    evicted: oneshot::Receiver<()>,
+}
+
+/// The request overflowed the queue.
+#[derive(Debug)]
+pub(super) struct Overflowed;
+
+// ===== impl Config =====
+
+impl Config {
+    pub(super) fn new() -> Config {
+        Config {
+            max_requests: None,
+            max_bytes: None,
+            overflow: QueueOverflow::Reject,
+        }
+    }
+}
+
+// ===== impl Queue =====
+
+impl Queue {
+    /// Returns `None` if the queue is unbounded.
+    pub(super) fn new(config: Config) -> Option<Arc<Queue>> {
+        if config.max_requests.is_none() && config.max_bytes.is_none() {
+            return None;
+        }
+        Some(Arc::new(Queue {
+            config,
+            state: Mutex::new(State::default()),
+        }))
+    }
+
+    /// Queue a request with a body of `bytes`.
+    pub(super) fn admit(self: &Arc<Queue>, bytes: usize) -> Result<Queued, Overflowed> {
+        let max_requests = self.config.max_requests.unwrap_or(usize::MAX);
+        let max_bytes = self.config.max_bytes.unwrap_or(usize::MAX);
+        if bytes > max_bytes || max_requests == 0 {
+            return Err(Overflowed);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        while state.entries.len() >= max_requests || state.bytes + bytes > max_bytes {
+            if self.config.overflow == QueueOverflow::Reject {
+                return Err(Overflowed);
+            }
+            let oldest = state
+                .entries
+                .pop_front()
+                .expect("over limits implies queued");
+            state.bytes -= oldest.bytes;
+            drop(oldest.evict);
+            trace!("request queue full, dropping oldest request");
+        }
+
+        let id = state.next_id;
+        state.next_id += 1;
+        state.bytes += bytes;
+        let (evict, evicted) = oneshot::channel();
+        state.entries.push_back(Entry { id, bytes, evict });
+        Ok(Queued {
+            queue: self.clone(),
+            id,
+            evicted,
+        })
+    }
+}
+
+// ===== impl Queued =====
+
+impl Queued {
+    /// Wait for `fut`, unless this request is dropped from the queue first.
+    pub(super) async fn wait<F: Future>(mut self, fut: F) -> Result<F::Output, Overflowed> {
+        futures_util::pin_mut!(fut);
+        match future::select(fut, &mut self.evicted).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(_) => Err(Overflowed),
+        }
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        let mut state = self.queue.state.lock().unwrap();
+        if let Some(pos) = state.entries.iter().position(|entry| entry.id == self.id) {
+            let entry = state.entries.remove(pos).expect("position is in bounds");
+            state.bytes -= entry.bytes;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Queue, QueueOverflow};
+
+    fn queue(
+        max_requests: usize,
+        max_bytes: usize,
+        overflow: QueueOverflow,
+    ) -> std::sync::Arc<Queue> {
+        Queue::new(Config {
+            max_requests: Some(max_requests),
+            max_bytes: Some(max_bytes),
+            overflow,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn reject_or_drop_oldest() {
+        let reject = queue(2, 100, QueueOverflow::Reject);
+        let a = reject.admit(10).unwrap();
+        let _b = reject.admit(10).unwrap();
+        assert!(reject.admit(10).is_err(), "too many requests");
+        drop(a);
+        assert!(reject.admit(91).is_err(), "too many bytes");
+        let _c = reject.admit(90).unwrap();
+
+        let drop_oldest = queue(2, 100, QueueOverflow::DropOldest);
+        let a = drop_oldest.admit(60).unwrap();
+        let b = drop_oldest.admit(10).unwrap();
+        let _c = drop_oldest.admit(50).unwrap();
+        assert!(a.wait(futures_util::future::pending::<()>()).await.is_err());
+        assert!(b.wait(async {}).await.is_ok());
+        assert!(drop_oldest.admit(101).is_err(), "never fits");
+    }
+}
@@ -0,0 +1,166 @@
+//! Re-resolution of pooled connections, with `Builder::pool_dns_expiry_grace`.
+//!
+//! Connectors report the DNS answer a connection was dialed from. Each new
+//! connection to a host updates the latest answer of that host, which the
+//! pooled connections to it compare their own answer against. Answers are
+//! dated by the pool timer, so a mocked clock is honored.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::connect::DnsAnswer;
+use crate::common::timer;
+
+/// The latest DNS answer of each host.
+pub(super) struct Answers<K> {
+    grace: Duration,
+    timer: Option<timer::Timer>,
+    hosts: Mutex<HashMap<K, Arc<Mutex<Latest>>>>,
+}
+
+/// The DNS answer of a pooled connection.
+#[derive(Clone)]
+pub(super) struct Tracked {
+    remote: IpAddr,
+    expires: Instant,
+    latest: Arc<Mutex<Latest>>,
+    grace: Duration,
+    timer: Option<timer::Timer>,
+}
+
+// The freshest answer of a host.
+struct Latest {
+    addrs: Arc<[IpAddr]>,
+    expires: Instant,
+}
+
+// ===== impl Answers =====
+
+impl<K: Eq + Hash> Answers<K> {
+    pub(super) fn new(grace: Duration, timer: Option<timer::Timer>) -> Answers<K> {
+        Answers {
+            grace,
+            timer,
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A connection to `key` was dialed from `answer`, just now.
+    pub(super) fn track(&self, key: K, answer: DnsAnswer) -> Tracked {
+        let expires = timer::now(self.timer.as_ref()) + answer.ttl;
+        let mut hosts = self.hosts.lock().unwrap();
+        let latest = hosts
+            .entry(key)
+            .or_insert_with(|| {
+                Arc::new(Mutex::new(Latest {
+                    addrs: answer.addrs.clone(),
+                    expires,
+                }))
+            })
+            .clone();
+        {
+            let mut latest = latest.lock().unwrap();
+            if expires > latest.expires {
+                *latest = Latest {
+                    addrs: answer.addrs,
+                    expires,
+                };
+            }
+        }
+        Tracked {
+            remote: answer.remote,
+            expires,
+            latest,
+            grace: self.grace,
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+// ===== impl Tracked =====
+
+impl Tracked {
+    /// Whether the connection may still be reused.
+    pub(super) fn is_current(&self) -> bool {
+        self.is_current_at(timer::now(self.timer.as_ref()))
+    }
+
+    // Until its own answer expires, it may. After, a fresher answer of the
+    // host must still contain its address, and it is reused for at most the
+    // grace period past the expiry of the freshest answer it is in.
+    fn is_current_at(&self, now: Instant) -> bool {
+        if now < self.expires {
+            return true;
+        }
+        let latest = self.latest.lock().unwrap();
+        let expires = if latest.expires > self.expires {
+            if !latest.addrs.contains(&self.remote) {
+                return false;
+            }
+            latest.expires
+        } else {
+            self.expires
+        };
+        now < expires + self.grace
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    use super::Answers;
+    use crate::client::legacy::connect::DnsAnswer;
+    use crate::common::timer::Timer;
+    use crate::rt::ManualTimer;
+
+    fn answer(remote: [u8; 4], addrs: &[[u8; 4]], ttl: u64) -> DnsAnswer {
+        DnsAnswer {
+            remote: IpAddr::from(remote),
+            addrs: addrs.iter().map(|&addr| IpAddr::from(addr)).collect(),
+            ttl: Duration::from_secs(ttl),
+        }
+    }
+
+    #[test]
+    fn reused_until_grace_past_expiry() {
+        let clock = ManualTimer::new();
+        let answers = Answers::new(Duration::from_secs(10), Some(Timer::new(clock.clone())));
+        let old = answers.track("a", answer([10, 0, 0, 1], &[[10, 0, 0, 1]], 0));
+
+        clock.advance(Duration::from_secs(5));
+        assert!(old.is_current());
+        clock.advance(Duration::from_secs(5));
+        assert!(!old.is_current());
+    }
+
+    #[test]
+    fn fresh_answers_extend_or_evict() {
+        let clock = ManualTimer::new();
+        let answers = Answers::new(Duration::from_secs(10), Some(Timer::new(clock.clone())));
+        let a = answers.track("a", answer([10, 0, 0, 1], &[[10, 0, 0, 1]], 0));
+        let b = answers.track("a", answer([10, 0, 0, 2], &[[10, 0, 0, 2]], 0));
+
+        // a newer answer still listing the address extends its reuse
+        answers.track(
+            "a",
+            answer([10, 0, 0, 1], &[[10, 0, 0, 1], [10, 0, 0, 3]], 60),
+        );
+        clock.advance(Duration::from_secs(1));
+        assert!(a.is_current());
+
+        // and one that doesn't evicts it right away
+        assert!(!b.is_current());
+
+        // other hosts are unaffected
+        let c = answers.track("c", answer([10, 0, 0, 2], &[[10, 0, 0, 2]], 0));
+        assert!(c.is_current());
+        clock.advance(Duration::from_secs(64));
+        assert!(a.is_current());
+        assert!(!c.is_current());
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// Balances are kept in thousandths of a retry.
+const SCALE: u64 = 1000;
+
+/// Limits retries to a share of the requests sent.
+///
+/// Every request adds `ratio` to the budget, and every retry takes one
+/// from it, so under steady load at most `ratio` retries are made per
+/// request. The budget holds at most `reserve` retries, which is also what
+/// it starts with, to allow a burst of retries after a quiet period.
+///
+/// Clones share the same budget, so a single `Budget` can cover several
+/// layers, such as a [`RetryLayer`](super::RetryLayer) and a
+/// [`HedgeLayer`](super::HedgeLayer). A request passing through several
+/// layers with the same budget only adds to it once.
+#[derive(Clone)]
+pub struct Budget {
+    inner: Arc<Inner>,
+}
+
+// Marks a request whose budget was already deposited to by a layer.
+#[derive(Clone)]
+struct Deposited(Arc<Inner>);
+
+struct Inner {
+    balance: AtomicU64,
+    max: u64,
+    deposit: u64,
+}
+
+impl Budget {
+    /// Create a budget holding up to `reserve` retries, earning `ratio`
+    /// retries per request.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `ratio` isn't between `0.0` and `1000.0`.
+    pub fn new(reserve: u32, ratio: f32) -> Budget {
+        assert!(
+            (0.0..=1000.0).contains(&ratio),
+            "ratio must be between 0 and 1000"
+        );
+        let max = reserve as u64 * SCALE;
+        Budget {
+            inner: Arc::new(Inner {
+                balance: AtomicU64::new(max),
+                max,
+                deposit: (ratio * SCALE as f32) as u64,
+            }),
+        }
+    }
+
+    /// The number of retries currently available.
+    pub fn available(&self) -> u32 {
+        (self.inner.balance.load(Ordering::Relaxed) / SCALE) as u32
+    }
+
+    // Deposit for a request, unless an outer layer already did.
+    pub(super) fn deposit_once(&self, extensions: &mut http::Extensions) {
+        if let Some(Deposited(inner)) = extensions.get() {
+            if Arc::ptr_eq(inner, &self.inner) {
+                return;
+            }
+        }
+        extensions.insert(Deposited(self.inner.clone()));
+        self.deposit();
+    }
+
+    pub(super) fn deposit(&self) {
+        let inner = &*self.inner;
+        let _ = inner
+            .balance
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |balance| {
+                Some((balance + inner.deposit).min(inner.max))
+            });
+    }
+
+    pub(super) fn withdraw(&self) -> bool {
+        self.inner
+            .balance
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |balance| {
+                balance.checked_sub(SCALE)
+            })
+            .is_ok()
+    }
+}
+
+impl fmt::Debug for Budget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Budget")
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Budget;
+
+    #[test]
+    fn earns_retries_per_request() {
+        let budget = Budget::new(2, 0.5);
+        assert!(budget.withdraw());
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+
+        budget.deposit();
+        assert!(!budget.withdraw(), "half a retry isn't enough");
+        budget.deposit();
+        assert!(budget.withdraw());
+
+        for _ in 0..10 {
+            budget.deposit();
+        }
+        assert_eq!(budget.available(), 2, "capped at the reserve");
+    }
+
+    #[test]
+    fn deposits_once_per_request() {
+        let budget = Budget::new(1, 1.0);
+        let other = Budget::new(1, 1.0);
+        assert!(budget.withdraw());
+        assert!(other.withdraw());
+
+        let mut extensions = http::Extensions::new();
+        budget.deposit_once(&mut extensions);
+        budget.deposit_once(&mut extensions.clone());
+        other.deposit_once(&mut extensions);
+        assert_eq!(budget.available(), 1);
+        assert_eq!(other.available(), 1);
+    }
+}
@@ -0,0 +1,375 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{self, Poll};
+use std::time::Duration;
+
+use http::{Request, Response};
+use http_body::Body;
+
+use super::{BoxError, Budget};
+use crate::body::ReplayBody;
+use crate::common::timer::Timer;
+
+/// A `Layer` sending a second copy of slow requests, and using the first
+/// response.
+///
+/// A request that has no response after a delay is sent again, without
+/// canceling the first attempt. Whichever response arrives first is
+/// returned, and the other attempts are canceled by dropping them. This
+/// trades extra load for a shorter tail latency.
+///
+/// Hedges should be limited with a [`Budget`], which can be shared with a
+/// [`RetryLayer`](super::RetryLayer), so that retries and hedges together
+/// never add more than a set share of load to an upstream. Like retries,
+/// only idempotent requests are hedged, unless configured otherwise.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(all(feature = "tokio", feature = "http1"))]
+/// # fn run() {
+/// use std::time::Duration;
+/// use bytes::Bytes;
+/// use http_body_util::Full;
+/// use hyper_util::body::ReplayBody;
+/// use hyper_util::client::legacy::retry::{Budget, HedgeLayer, RetryLayer};
+/// use hyper_util::client::legacy::Client;
+/// use hyper_util::rt::{TokioExecutor, TokioTimer};
+/// use tower::Layer;
+///
+/// let client: Client<_, ReplayBody<ReplayBody<Full<Bytes>>>> =
+///     Client::builder(TokioExecutor::new()).build_http();
+/// let budget = Budget::new(10, 0.1);
+/// let client = HedgeLayer::new(TokioTimer::new(), Duration::from_millis(100))
+///     .budget(budget.clone())
+///     .layer(client);
+/// let client = RetryLayer::new(TokioTimer::new())
+///     .budget(budget)
+///     .layer(client);
+/// # drop(client);
+/// # }
+/// # fn main() {}
+/// ```
+#[derive(Clone)]
+pub struct HedgeLayer {
+    policy: Arc<Policy>,
+}
+
+/// A `Service` created by a [`HedgeLayer`].
+pub struct HedgeService<C> {
+    inner: C,
+    policy: Arc<Policy>,
+}
+
+#[derive(Clone)]
+struct Policy {
+    timer: Timer,
+    delay: Duration,
+    max_hedges: usize,
+    non_idempotent: bool,
+    max_buffer: usize,
+    budget: Option<Budget>,
+}
+
+// ===== impl HedgeLayer =====
+
+impl HedgeLayer {
+    /// Create a layer hedging requests without a response after `delay`,
+    /// using `timer` to wait.
+    ///
+    /// By default, a request is hedged at most once, buffering up to
+    /// 64 KiB of its body, and without a budget.
+    pub fn new<M>(timer: M, delay: Duration) -> HedgeLayer
+    where
+        M: hyper::rt::Timer + Send + Sync + 'static,
+    {
+        HedgeLayer {
+            policy: Arc::new(Policy {
+                timer: Timer::new(timer),
+                delay,
+                max_hedges: 1,
+                non_idempotent: false,
+                max_buffer: 64 * 1024,
+                budget: None,
+            }),
+        }
+    }
+
+    /// Set the maximum number of hedges of a request, not counting the
+    /// first attempt.
+    ///
+    /// Each hedge is sent `delay` after the previous attempt.
+    pub fn max_hedges(mut self, hedges: usize) -> HedgeLayer {
+        self.policy_mut().max_hedges = hedges;
+        self
+    }
+
+    /// Set whether requests with methods that aren't idempotent, such as
+    /// `POST`, are hedged.
+    ///
+    /// Default is `false`.
+    pub fn hedge_non_idempotent(mut self, enabled: bool) -> HedgeLayer {
+        self.policy_mut().non_idempotent = enabled;
+        self
+    }
+
+    /// Set how much of a request body is kept to send it again.
+    ///
+    /// Requests with larger bodies aren't hedged. Default is 64 KiB.
+    pub fn max_buffer(mut self, max: usize) -> HedgeLayer {
+        self.policy_mut().max_buffer = max;
+        self
+    }
+
+    /// Limit hedges with a [`Budget`].
+    pub fn budget(mut self, budget: Budget) -> HedgeLayer {
+        self.policy_mut().budget = Some(budget);
+        self
+    }
+
+    fn policy_mut(&mut self) -> &mut Policy {
+        Arc::make_mut(&mut self.policy)
+    }
+}
+
+impl<C> tower::Layer<C> for HedgeLayer {
+    type Service = HedgeService<C>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        HedgeService {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for HedgeLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.policy.fmt(f)
+    }
+}
+
+// ===== impl HedgeService =====
+
+impl<C, B, R> tower_service::Service<Request<B>> for HedgeService<C>
+where
+    C: tower_service::Service<Request<ReplayBody<B>>, Response = Response<R>>
+        + Clone
+        + Send
+        + 'static,
+    C::Future: Send,
+    C::Error: StdError + Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<BoxError>,
+    R: Send + 'static,
+{
+    type Response = Response<R>;
+    type Error = C::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<R>, C::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Take the service that was polled ready, leaving a clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let policy = self.policy.clone();
+
+        let (mut parts, body) = req.into_parts();
+        let body = ReplayBody::new(body, policy.max_buffer);
+        let replay = body.clone();
+        let hedgeable = policy.non_idempotent || parts.method.is_idempotent();
+        if let Some(ref budget) = policy.budget {
+            budget.deposit_once(&mut parts.extensions);
+        }
+        let request = move || {
+            let mut req = Request::new(body.clone());
+            *req.method_mut() = parts.method.clone();
+            *req.uri_mut() = parts.uri.clone();
+            *req.version_mut() = parts.version;
+            *req.headers_mut() = parts.headers.clone();
+            *req.extensions_mut() = parts.extensions.clone();
+            req
+        };
+
+        let mut attempts = vec![Some(Box::pin(inner.call(request())))];
+        let mut hedges = if hedgeable { policy.max_hedges } else { 0 };
+        let mut sleep = if hedges > 0 {
+            Some(hyper::rt::Timer::sleep(&policy.timer, policy.delay))
+        } else {
+            None
+        };
+        // The timer fired, and a hedge waits for the service to be ready.
+        let mut hedging = false;
+        let mut error = None;
+
+        Box::pin(futures_util::future::poll_fn(move |cx| loop {
+            for attempt in attempts.iter_mut() {
+                let result = match attempt {
+                    Some(future) => match future.as_mut().poll(cx) {
+                        Poll::Ready(result) => result,
+                        Poll::Pending => continue,
+                    },
+                    None => continue,
+                };
+                *attempt = None;
+                match result {
+                    // dropping the other attempts cancels them
+                    Ok(res) => return Poll::Ready(Ok(res)),
+                    Err(err) => {
+                        error.get_or_insert(err);
+                    }
+                }
+            }
+            if attempts.iter().all(Option::is_none) {
+                let err = error.take().expect("failed attempts have errors");
+                return Poll::Ready(Err(err));
+            }
+
+            if let Some(ref mut timer) = sleep {
+                if timer.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                sleep = None;
+                hedges -= 1;
+                hedging =
+                    replay.is_replayable() && policy.budget.as_ref().map_or(true, Budget::withdraw);
+                if !hedging {
+                    return Poll::Pending;
+                }
+            }
+            if !hedging {
+                return Poll::Pending;
+            }
+            match inner.poll_ready(cx) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(err)) => {
+                    // keep waiting for the attempts already sent
+                    hedging = false;
+                    error.get_or_insert(err);
+                    continue;
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+            hedging = false;
+            attempts.push(Some(Box::pin(inner.call(request()))));
+            if hedges > 0 {
+                sleep = Some(hyper::rt::Timer::sleep(&policy.timer, policy.delay));
+            }
+        }))
+    }
+}
+
+impl<C: Clone> Clone for HedgeService<C> {
+    fn clone(&self) -> HedgeService<C> {
+        HedgeService {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for HedgeService<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HedgeService")
+            .field("inner", &self.inner)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+// ===== impl Policy =====
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HedgeLayer")
+            .field("delay", &self.delay)
+            .field("max_hedges", &self.max_hedges)
+            .field("non_idempotent", &self.non_idempotent)
+            .field("max_buffer", &self.max_buffer)
+            .field("budget", &self.budget)
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(miri), feature = "tokio"))]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use http::{Method, Request, Response};
+    use http_body_util::Full;
+    use tower::util::BoxCloneService;
+    use tower::{Layer, ServiceExt};
+
+    use super::HedgeLayer;
+    use crate::body::ReplayBody;
+    use crate::client::legacy::retry::Budget;
+    use crate::rt::TokioTimer;
+
+    type Req = Request<ReplayBody<Full<Bytes>>>;
+
+    // A service whose first call is slow, answering with the call's index.
+    fn service(calls: Arc<AtomicUsize>) -> BoxCloneService<Req, Response<usize>, io::Error> {
+        BoxCloneService::new(tower::service_fn(move |_: Req| {
+            let n = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let delay = if n == 0 { 1000 } else { 10 };
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                Ok(Response::new(n))
+            }
+        }))
+    }
+
+    fn request(method: Method) -> Request<Full<Bytes>> {
+        Request::builder()
+            .method(method)
+            .uri("http://example.local/")
+            .body(Full::new(Bytes::new()))
+            .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn hedge_wins_over_slow_attempt() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let layer = HedgeLayer::new(TokioTimer::new(), Duration::from_millis(50));
+        let svc = layer.layer(service(calls.clone()));
+
+        let res = svc.oneshot(request(Method::GET)).await.unwrap();
+        assert_eq!(*res.body(), 1, "the hedge answered first");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        calls.store(0, Ordering::SeqCst);
+        let svc = layer.layer(service(calls.clone()));
+        let res = svc.oneshot(request(Method::POST)).await.unwrap();
+        assert_eq!(*res.body(), 0, "POST isn't hedged");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_limits_hedges() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let budget = Budget::new(1, 0.0);
+        let layer =
+            HedgeLayer::new(TokioTimer::new(), Duration::from_millis(50)).budget(budget.clone());
+
+        let svc = layer.layer(service(calls.clone()));
+        svc.oneshot(request(Method::GET)).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2, "one hedge in the budget");
+        assert_eq!(budget.available(), 0);
+
+        calls.store(0, Ordering::SeqCst);
+        let svc = layer.layer(service(calls.clone()));
+        let res = svc.oneshot(request(Method::GET)).await.unwrap();
+        assert_eq!(*res.body(), 0, "budget exhausted");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,554 @@
+//! Retrying failed requests.
+//!
+//! A [`RetryLayer`] sends a request again when it fails with a retryable
+//! error, such as failing to connect, or when the response has a retryable
+//! status, by default `429`, `502`, `503` and `504`. Between attempts it
+//! waits with exponential backoff and jitter, or for as long as the
+//! response's `Retry-After` header asks. A [`HedgeLayer`] sends another
+//! copy of a request that is slow to get a response. A [`Budget`] can be
+//! shared by both to cap the share of requests that are sent again.
+//!
+//! Only idempotent requests are retried, unless configured otherwise.
+//! Request bodies are wrapped in a [`ReplayBody`], so streaming bodies can
+//! be sent again as long as they fit in its buffer; the inner service must
+//! accept `ReplayBody<B>` bodies.
+//!
+//! # Example
+//!
+//! ```
+//! # #[cfg(all(feature = "tokio", feature = "http1"))]
+//! # fn run() {
+//! use std::time::Duration;
+//! use bytes::Bytes;
+//! use http_body_util::Full;
+//! use hyper_util::body::ReplayBody;
+//! use hyper_util::client::legacy::retry::{Budget, RetryLayer};
+//! use hyper_util::client::legacy::Client;
+//! use hyper_util::rt::{TokioExecutor, TokioTimer};
+//! use tower::Layer;
+//!
+//! let client: Client<_, ReplayBody<Full<Bytes>>> =
+//!     Client::builder(TokioExecutor::new()).build_http();
+//! let client = RetryLayer::new(TokioTimer::new())
+//!     .max_attempts(4)
+//!     .backoff(Duration::from_millis(50), Duration::from_secs(5))
+//!     .budget(Budget::new(10, 0.2))
+//!     .layer(client);
+//! # drop(client);
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{self, Poll};
+use std::time::{Duration, SystemTime};
+
+use http::header::{HeaderMap, RETRY_AFTER};
+use http::{Request, Response, StatusCode};
+use http_body::Body;
+use tower::ServiceExt;
+
+pub use self::budget::Budget;
+pub use self::hedge::{HedgeLayer, HedgeService};
+use crate::body::ReplayBody;
+use crate::common::timer::Timer;
+
+mod budget;
+mod hedge;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+type StatusFn = dyn Fn(StatusCode) -> bool + Send + Sync;
+type ErrorFn = dyn Fn(&(dyn StdError + 'static)) -> bool + Send + Sync;
+
+/// A `Layer` retrying failed requests.
+#[derive(Clone)]
+pub struct RetryLayer {
+    policy: Arc<Policy>,
+}
+
+/// A `Service` created by a [`RetryLayer`].
+pub struct RetryService<C> {
+    inner: C,
+    policy: Arc<Policy>,
+}
+
+#[derive(Clone)]
+struct Policy {
+    timer: Timer,
+    max_attempts: usize,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    statuses: Arc<StatusFn>,
+    errors: Arc<ErrorFn>,
+    non_idempotent: bool,
+    max_buffer: usize,
+    budget: Option<Budget>,
+    stale_408: bool,
+}
+
+// ===== impl RetryLayer =====
+
+impl RetryLayer {
+    /// Create a layer, using `timer` to wait between attempts.
+    ///
+    /// By default, a request is sent at most 3 times, with a backoff from
+    /// 100 milliseconds up to 10 seconds, buffering up to 64 KiB of its
+    /// body, and without a budget.
+    pub fn new<M>(timer: M) -> RetryLayer
+    where
+        M: hyper::rt::Timer + Send + Sync + 'static,
+    {
+        RetryLayer {
+            policy: Arc::new(Policy {
+                timer: Timer::new(timer),
+                max_attempts: 3,
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(10),
+                statuses: Arc::new(|status| {
+                    matches!(
+                        status,
+                        StatusCode::TOO_MANY_REQUESTS
+                            | StatusCode::BAD_GATEWAY
+                            | StatusCode::SERVICE_UNAVAILABLE
+                            | StatusCode::GATEWAY_TIMEOUT
+                    )
+                }),
+                errors: Arc::new(is_retryable_error),
+                non_idempotent: false,
+                max_buffer: 64 * 1024,
+                budget: None,
+                stale_408: false,
+            }),
+        }
+    }
+
+    /// Set the maximum number of attempts, including the first one.
+    pub fn max_attempts(mut self, attempts: usize) -> RetryLayer {
+        self.policy_mut().max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Set the backoff between attempts.
+    ///
+    /// The delay starts at `initial` and doubles with each attempt, up to
+    /// `max`, and a random jitter of up to half the delay is taken off.
+    /// A `Retry-After` longer than `max` isn't waited for, and the response
+    /// is returned instead.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> RetryLayer {
+        let policy = self.policy_mut();
+        policy.initial_backoff = initial;
+        policy.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Set which response statuses are retried.
+    pub fn retry_statuses<F>(mut self, f: F) -> RetryLayer
+    where
+        F: Fn(StatusCode) -> bool + Send + Sync + 'static,
+    {
+        self.policy_mut().statuses = Arc::new(f);
+        self
+    }
+
+    /// Set which errors are retried.
+    ///
+    /// By default, client errors that are
+    /// [retryable](super::Error::is_retryable), and connections reset or
+    /// refused, anywhere in the error's source chain, are retried.
+    pub fn retry_errors<F>(mut self, f: F) -> RetryLayer
+    where
+        F: Fn(&(dyn StdError + 'static)) -> bool + Send + Sync + 'static,
+    {
+        self.policy_mut().errors = Arc::new(f);
+        self
+    }
+
+    /// Set whether requests with methods that aren't idempotent, such as
+    /// `POST`, are retried.
+    ///
+    /// Default is `false`.
+    pub fn retry_non_idempotent(mut self, enabled: bool) -> RetryLayer {
+        self.policy_mut().non_idempotent = enabled;
+        self
+    }
+
+    /// Set how much of a request body is kept to send it again.
+    ///
+    /// Requests with larger bodies aren't retried. Default is 64 KiB.
+    pub fn max_buffer(mut self, max: usize) -> RetryLayer {
+        self.policy_mut().max_buffer = max;
+        self
+    }
+
+    /// Set whether a `408 Request Timeout` on a reused connection is sent
+    /// again once, right away, on a new connection.
+    ///
+    /// A server may time out an idle connection with a `408` just as a
+    /// request is sent on it, so the `408` isn't about the request, which
+    /// the server never processed. This retry happens whatever the method,
+    /// without counting as an attempt or against the budget. A `408` on a
+    /// new connection is handled by the retried statuses as usual.
+    ///
+    /// Default is `false`.
+    pub fn retry_stale_408(mut self, enabled: bool) -> RetryLayer {
+        self.policy_mut().stale_408 = enabled;
+        self
+    }
+
+    /// Limit retries with a [`Budget`].
+    pub fn budget(mut self, budget: Budget) -> RetryLayer {
+        self.policy_mut().budget = Some(budget);
+        self
+    }
+
+    fn policy_mut(&mut self) -> &mut Policy {
+        Arc::make_mut(&mut self.policy)
+    }
+}
+
+impl<C> tower::Layer<C> for RetryLayer {
+    type Service = RetryService<C>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        RetryService {
+            inner,
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for RetryLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.policy.fmt(f)
+    }
+}
+
+// ===== impl RetryService =====
+
+impl<C, B, R> tower_service::Service<Request<B>> for RetryService<C>
+where
+    C: tower_service::Service<Request<ReplayBody<B>>, Response = Response<R>>
+        + Clone
+        + Send
+        + 'static,
+    C::Future: Send,
+    C::Error: StdError + Send + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<BoxError>,
+    R: Send + 'static,
+{
+    type Response = Response<R>;
+    type Error = C::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<R>, C::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Take the service that was polled ready, leaving a clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let policy = self.policy.clone();
+
+        let (mut parts, body) = req.into_parts();
+        let body = ReplayBody::new(body, policy.max_buffer);
+        let retryable = policy.non_idempotent || parts.method.is_idempotent();
+        if let Some(ref budget) = policy.budget {
+            budget.deposit_once(&mut parts.extensions);
+        }
+
+        Box::pin(async move {
+            let mut attempt = 1;
+            let mut fresh = false;
+            loop {
+                let mut req = Request::new(body.clone());
+                *req.method_mut() = parts.method.clone();
+                *req.uri_mut() = parts.uri.clone();
+                *req.version_mut() = parts.version;
+                *req.headers_mut() = parts.headers.clone();
+                *req.extensions_mut() = parts.extensions.clone();
+                #[cfg(any(feature = "http1", feature = "http2"))]
+                if fresh {
+                    req.extensions_mut().insert(super::client::FreshConnection);
+                }
+
+                let result = inner.call(req).await;
+                if policy.stale_408 && !fresh && body.is_replayable() && is_stale_408(&result) {
+                    fresh = true;
+                    drop(result);
+                    inner.ready().await?;
+                    continue;
+                }
+                if !retryable || attempt >= policy.max_attempts || !body.is_replayable() {
+                    return result;
+                }
+                let delay = match policy.delay(&result, attempt) {
+                    Some(delay) => delay,
+                    None => return result,
+                };
+                if let Some(ref budget) = policy.budget {
+                    if !budget.withdraw() {
+                        return result;
+                    }
+                }
+                drop(result);
+
+                hyper::rt::Timer::sleep(&policy.timer, delay).await;
+                attempt += 1;
+                inner.ready().await?;
+            }
+        })
+    }
+}
+
+impl<C: Clone> Clone for RetryService<C> {
+    fn clone(&self) -> RetryService<C> {
+        RetryService {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+impl<C: fmt::Debug> fmt::Debug for RetryService<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryService")
+            .field("inner", &self.inner)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+// ===== impl Policy =====
+
+impl Policy {
+    // How long to wait before the next attempt, if it should be retried.
+    fn delay<R, E>(&self, result: &Result<Response<R>, E>, attempt: usize) -> Option<Duration>
+    where
+        E: StdError + 'static,
+    {
+        match result {
+            Ok(res) if (self.statuses)(res.status()) => match retry_after(res.headers()) {
+                Some(delay) if delay > self.max_backoff => None,
+                Some(delay) => Some(delay),
+                None => Some(self.backoff(attempt)),
+            },
+            Ok(_) => None,
+            Err(err) if (self.errors)(err) => Some(self.backoff(attempt)),
+            Err(_) => None,
+        }
+    }
+
+    fn backoff(&self, attempt: usize) -> Duration {
+        let exp = (attempt - 1).min(31) as u32;
+        let delay = self
+            .initial_backoff
+            .checked_mul(1 << exp)
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff));
+        // Take off up to half, so that clients don't retry in lockstep.
+        let half = delay.as_nanos() as u64 / 2;
+        let jitter = crate::common::rand::random_u64() % (half + 1);
+        delay - Duration::from_nanos(jitter)
+    }
+}
+
+impl fmt::Debug for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryLayer")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("non_idempotent", &self.non_idempotent)
+            .field("max_buffer", &self.max_buffer)
+            .field("budget", &self.budget)
+            .field("stale_408", &self.stale_408)
+            .finish()
+    }
+}
+
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    // A date in the past means right away.
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+// A `408` on a reused connection, likely timed out while idle.
+fn is_stale_408<R, E>(result: &Result<Response<R>, E>) -> bool {
+    match result {
+        #[cfg(any(feature = "http1", feature = "http2"))]
+        Ok(res) if res.status() == StatusCode::REQUEST_TIMEOUT => res
+            .extensions()
+            .get::<super::Timings>()
+            .map_or(false, super::Timings::is_reused),
+        _ => false,
+    }
+}
+
+fn is_retryable_error(err: &(dyn StdError + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        #[cfg(any(feature = "http1", feature = "http2"))]
+        if let Some(err) = err.downcast_ref::<super::Error>() {
+            if err.is_retryable() {
+                return true;
+            }
+        }
+        if let Some(err) = err.downcast_ref::<std::io::Error>() {
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::ConnectionRefused
+                    | std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+            ) {
+                return true;
+            }
+        }
+        source = err.source();
+    }
+    false
+}
+
+#[cfg(all(test, not(miri), feature = "tokio"))]
+mod tests {
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use http::header::RETRY_AFTER;
+    use http::{Method, Request, Response, StatusCode};
+    use http_body_util::{BodyExt, Full};
+    use tower::{Layer, ServiceExt};
+
+    use super::{retry_after, Budget, RetryLayer};
+    use crate::body::ReplayBody;
+    use crate::rt::TokioTimer;
+
+    type Req = Request<ReplayBody<Full<Bytes>>>;
+
+    fn layer() -> RetryLayer {
+        RetryLayer::new(TokioTimer::new())
+            .backoff(Duration::from_millis(1), Duration::from_millis(10))
+    }
+
+    #[tokio::test]
+    async fn retries_status_and_replays_body() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let svc = layer().layer(tower::service_fn(move |req: Req| {
+            let n = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let body = req.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(body, "hello");
+                let status = if n < 2 {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::OK
+                };
+                Ok::<_, io::Error>(Response::builder().status(status).body(()).unwrap())
+            }
+        }));
+
+        let req = Request::put("http://example.local/")
+            .body(Full::new(Bytes::from_static(b"hello")))
+            .unwrap();
+        let res = svc.oneshot(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn skips_non_idempotent_and_other_errors() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let svc = layer().layer(tower::service_fn(move |req: Req| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let kind = if req.method() == Method::GET {
+                    io::ErrorKind::InvalidData
+                } else {
+                    io::ErrorKind::ConnectionRefused
+                };
+                Err::<Response<()>, _>(io::Error::new(kind, "failed"))
+            }
+        }));
+
+        let post = Request::post("http://example.local/")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        svc.clone().oneshot(post).await.unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let get = Request::get("http://example.local/")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        svc.oneshot(get).await.unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn budget_and_retry_after_limit_retries() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let svc = layer()
+            .budget(Budget::new(1, 0.0))
+            .layer(tower::service_fn(move |_: Req| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    Ok::<_, io::Error>(
+                        Response::builder()
+                            .status(StatusCode::TOO_MANY_REQUESTS)
+                            .header(RETRY_AFTER, "0")
+                            .body(())
+                            .unwrap(),
+                    )
+                }
+            }));
+
+        let req = || {
+            Request::get("http://example.local/")
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+        svc.clone().oneshot(req()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2, "one retry in the budget");
+        svc.oneshot(req()).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3, "budget exhausted");
+    }
+
+    #[test]
+    fn parses_retry_after() {
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert(RETRY_AFTER, "120".parse().unwrap());
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+        headers.insert(
+            RETRY_AFTER,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn backoff_grows_with_jitter() {
+        let layer = layer();
+        for attempt in 1..8 {
+            let max = Duration::from_millis(1 << (attempt - 1)).min(Duration::from_millis(10));
+            let delay = layer.policy.backoff(attempt);
+            assert!(delay <= max && delay >= max / 2, "{:?}", delay);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Spans for the phases of a request, with the `tracing` feature.
+//!
+//! Field names follow the OpenTelemetry semantic conventions for HTTP
+//! clients where one exists. Without the feature, these are all
+//! `Span::none()`, and recording does nothing.
+#![allow(dead_code)]
+#![cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+
+use std::fmt;
+use std::time::Instant;
+
+use http::{Method, StatusCode, Uri, Version};
+use tracing::Span;
+
+/// The whole request, until the response head is received.
+pub(crate) fn send_request(method: &Method, uri: &Uri, pool_key: &dyn fmt::Debug) -> Span {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!(
+        "send_request",
+        otel.kind = "client",
+        http.request.method = %method,
+        url.full = %uri,
+        server.address = uri.host(),
+        server.port = uri.port_u16(),
+        pool.key = ?pool_key,
+        connection.id = tracing::field::Empty,
+        network.protocol.version = tracing::field::Empty,
+        http.response.status_code = tracing::field::Empty,
+        error.type = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+    );
+    #[cfg(not(feature = "tracing"))]
+    Span::none()
+}
+
+/// Resolving the host of a destination.
+pub(crate) fn resolve(host: &str) -> Span {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!(
+        "resolve",
+        server.address = host,
+        error = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+    );
+    #[cfg(not(feature = "tracing"))]
+    Span::none()
+}
+
+/// Establishing a connection with the connector.
+pub(crate) fn connect(dst: &Uri, pool_key: &dyn fmt::Debug) -> Span {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!(
+        "connect",
+        server.address = dst.host(),
+        server.port = dst.port_u16(),
+        pool.key = ?pool_key,
+        connection.id = tracing::field::Empty,
+        error = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+    );
+    #[cfg(not(feature = "tracing"))]
+    Span::none()
+}
+
+/// Waiting for an idle connection from the pool.
+pub(crate) fn checkout(pool_key: &dyn fmt::Debug) -> Span {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!(
+        "pool.checkout",
+        pool.key = ?pool_key,
+        error = tracing::field::Empty,
+        duration_ms = tracing::field::Empty,
+    );
+    #[cfg(not(feature = "tracing"))]
+    Span::none()
+}
+
+/// Receiving an HTTP/1 response body, until the connection is idle.
+pub(crate) fn response_body() -> Span {
+    #[cfg(feature = "tracing")]
+    return tracing::debug_span!("response_body", duration_ms = tracing::field::Empty);
+    #[cfg(not(feature = "tracing"))]
+    Span::none()
+}
+
+/// Record the ID of the connection a phase used.
+pub(crate) fn connection_id(span: &Span, id: u64) {
+    #[cfg(feature = "tracing")]
+    span.record("connection.id", id);
+}
+
+/// Record how long a phase took, and its error if it failed.
+pub(crate) fn finish(span: &Span, start: Instant, error: Option<&dyn fmt::Display>) {
+    #[cfg(feature = "tracing")]
+    {
+        if let Some(error) = error {
+            span.record("error", tracing::field::display(error));
+        }
+        span.record("duration_ms", elapsed_ms(start));
+    }
+}
+
+/// Record the outcome of `send_request`.
+pub(crate) fn finish_request(
+    span: &Span,
+    start: Instant,
+    response: Result<(StatusCode, Version), &dyn fmt::Debug>,
+) {
+    #[cfg(feature = "tracing")]
+    {
+        match response {
+            Ok((status, version)) => {
+                span.record("http.response.status_code", status.as_u16());
+                let version = match version {
+                    Version::HTTP_09 => "0.9",
+                    Version::HTTP_10 => "1.0",
+                    Version::HTTP_11 => "1.1",
+                    Version::HTTP_2 => "2",
+                    Version::HTTP_3 => "3",
+                    _ => "unknown",
+                };
+                span.record("network.protocol.version", version);
+            }
+            Err(error_type) => {
+                span.record("error.type", tracing::field::debug(error_type));
+            }
+        }
+        span.record("duration_ms", elapsed_ms(start));
+    }
+}
+
+#[cfg(feature = "tracing")]
+fn elapsed_ms(start: Instant) -> u64 {
+    start.elapsed().as_millis() as u64
+}
+
+#[cfg(all(
+    test,
+    not(miri),
+    feature = "tracing",
+    feature = "http1",
+    feature = "tokio"
+))]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    use crate::client::legacy::Client;
+    use crate::rt::{TokioExecutor, TokioIo};
+
+    // Collects the names of new spans, and the fields recorded later.
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Collected>);
+
+    #[derive(Default)]
+    struct Collected {
+        next: AtomicU64,
+        names: Mutex<Vec<&'static str>>,
+        recorded: Mutex<Vec<String>>,
+    }
+
+    impl Subscriber for Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            self.0.names.lock().unwrap().push(span.metadata().name());
+            Id::from_u64(self.0.next.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &Id, values: &Record<'_>) {
+            struct Names<'a>(&'a mut Vec<String>);
+            impl tracing::field::Visit for Names<'_> {
+                fn record_debug(&mut self, field: &tracing::field::Field, _: &dyn std::fmt::Debug) {
+                    self.0.push(field.name().to_owned());
+                }
+            }
+            values.record(&mut Names(&mut self.0.recorded.lock().unwrap()));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn emits_request_phases() {
+        let spans = Spans::default();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        tracing::subscriber::with_default(spans.clone(), || {
+            rt.block_on(async {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                tokio::spawn(async move {
+                    let (io, _) = listener.accept().await.unwrap();
+                    let svc = hyper::service::service_fn(|_| async {
+                        Ok::<_, hyper::Error>(hyper::Response::new(Full::new(Bytes::from("hello"))))
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(io), svc)
+                        .await;
+                });
+
+                let client: Client<_, Full<Bytes>> =
+                    Client::builder(TokioExecutor::new()).build_http();
+                let uri = format!("http://localhost:{}/", addr.port())
+                    .parse()
+                    .unwrap();
+                let res = client.get(uri).await.unwrap();
+                res.into_body().collect().await.unwrap();
+                tokio::task::yield_now().await;
+            });
+        });
+
+        let names = spans.0.names.lock().unwrap();
+        for name in ["send_request", "pool.checkout", "connect", "resolve"] {
+            assert!(names.contains(&name), "{} missing in {:?}", name, names);
+        }
+        let recorded = spans.0.recorded.lock().unwrap();
+        for field in [
+            "connection.id",
+            "http.response.status_code",
+            "network.protocol.version",
+            "duration_ms",
+        ] {
+            assert!(
+                recorded.iter().any(|f| f == field),
+                "{} missing in {:?}",
+                field,
+                recorded
+            );
+        }
+    }
+}
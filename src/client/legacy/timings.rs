@@ -0,0 +1,202 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// When the phases of a request happened.
+///
+/// The `Client` puts `Timings` in the extensions of every response, much
+/// like the resource timing of browsers:
+///
+/// ```text
+/// start ─ queue ─ acquired ─ request_start ─ response_start ─ response_end
+///           ├ dns_lookup
+///           ├ connect
+///           └ tls_handshake
+/// ```
+///
+/// The queue is the time spent getting a connection, waiting for the pool
+/// or for [`pool_max_in_use`](super::Builder::pool_max_in_use), and dialing
+/// a new connection if needed. The connection phases are only known for
+/// requests sent on a new connection, and TLS only if the connector
+/// reports it with [`Connected::tls_handshake`].
+///
+/// Clones share the end of the response, set once it is known.
+///
+/// [`Connected::tls_handshake`]: super::connect::Connected::tls_handshake
+///
+/// # Example
+///
+/// ```
+/// use hyper_util::client::legacy::Timings;
+///
+/// fn log<B>(res: &http::Response<B>) {
+///     if let Some(timings) = res.extensions().get::<Timings>() {
+///         println!(
+///             "waited {:?}, first byte after {:?}",
+///             timings.acquired() - timings.start(),
+///             timings.response_start() - timings.request_start(),
+///         );
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Timings {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    start: Instant,
+    connection: Option<ConnectTimings>,
+    acquired: Instant,
+    request_start: Instant,
+    response_start: Instant,
+    response_end: Mutex<Option<Instant>>,
+}
+
+// What a new connection took, kept with it in the pool.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ConnectTimings {
+    pub(crate) dns_lookup: Option<Range<Instant>>,
+    pub(crate) connect: Option<Range<Instant>>,
+    pub(crate) tls_handshake: Option<Range<Instant>>,
+}
+
+// The request timestamps known by the `Client` before the response.
+pub(crate) struct RequestTimings {
+    pub(crate) start: Instant,
+    pub(crate) connection: Option<ConnectTimings>,
+    pub(crate) acquired: Instant,
+    pub(crate) request_start: Instant,
+}
+
+// ===== impl Timings =====
+
+impl Timings {
+    pub(crate) fn new(request: RequestTimings, response_start: Instant) -> Timings {
+        Timings {
+            inner: Arc::new(Inner {
+                start: request.start,
+                connection: request.connection,
+                acquired: request.acquired,
+                request_start: request.request_start,
+                response_start,
+                response_end: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// When the request was given to the `Client`.
+    pub fn start(&self) -> Instant {
+        self.inner.start
+    }
+
+    /// Whether the request was sent on a connection from the pool, so no
+    /// connection phase happened for it.
+    pub fn is_reused(&self) -> bool {
+        self.inner.connection.is_none()
+    }
+
+    /// When the connector resolved the host of the new connection, if it
+    /// had to.
+    ///
+    /// Only the resolution done by the [`HttpConnector`] is known.
+    ///
+    /// [`HttpConnector`]: super::connect::HttpConnector
+    pub fn dns_lookup(&self) -> Option<Range<Instant>> {
+        self.connection()?.dns_lookup.clone()
+    }
+
+    /// When the new connection was dialed.
+    ///
+    /// This covers all the connector did, including resolving the host
+    /// and the TLS handshake.
+    pub fn connect(&self) -> Option<Range<Instant>> {
+        self.connection()?.connect.clone()
+    }
+
+    /// When the TLS handshake of the new connection happened, if the
+    /// connector reported it.
+    pub fn tls_handshake(&self) -> Option<Range<Instant>> {
+        self.connection()?.tls_handshake.clone()
+    }
+
+    /// When the request got a connection, ending its time in the queue.
+    pub fn acquired(&self) -> Instant {
+        self.inner.acquired
+    }
+
+    /// When the request was handed to the connection to be written.
+    ///
+    /// hyper doesn't report when the request is done being written, and
+    /// its body may be written while the response is received.
+    pub fn request_start(&self) -> Instant {
+        self.inner.request_start
+    }
+
+    /// When the head of the response was received.
+    pub fn response_start(&self) -> Instant {
+        self.inner.response_start
+    }
+
+    /// When the body of the response was received to its end, once it
+    /// has.
+    ///
+    /// This is known for responses without a body, and on HTTP/1
+    /// connections, which are only ready for another request after the
+    /// body is received. It stays `None` on HTTP/2.
+    pub fn response_end(&self) -> Option<Instant> {
+        *self.inner.response_end.lock().unwrap()
+    }
+
+    pub(crate) fn end(&self, at: Instant) {
+        self.inner.response_end.lock().unwrap().get_or_insert(at);
+    }
+
+    fn connection(&self) -> Option<&ConnectTimings> {
+        self.inner.connection.as_ref()
+    }
+}
+
+impl fmt::Debug for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since = |at: Instant| at.saturating_duration_since(self.inner.start);
+        let phase = |range: Option<Range<Instant>>| range.map(|r| since(r.start)..since(r.end));
+        f.debug_struct("Timings")
+            .field("dns_lookup", &phase(self.dns_lookup()))
+            .field("connect", &phase(self.connect()))
+            .field("tls_handshake", &phase(self.tls_handshake()))
+            .field("acquired", &since(self.acquired()))
+            .field("request_start", &since(self.request_start()))
+            .field("response_start", &since(self.response_start()))
+            .field("response_end", &self.response_end().map(since))
+            .finish()
+    }
+}
+
+// ===== recording =====
+
+// Connectors can't return timings in their connection types, so those of
+// the `HttpConnector` are recorded here while a connect future is polled.
+thread_local! {
+    static RECORDING: RefCell<Option<Option<Range<Instant>>>> = const { RefCell::new(None) };
+}
+
+/// Poll a connect future with `f`, recording its DNS lookup into `dns`.
+pub(crate) fn recording<T>(dns: &mut Option<Range<Instant>>, f: impl FnOnce() -> T) -> T {
+    let outer = RECORDING.with(|r| r.replace(Some(dns.take())));
+    let out = f();
+    *dns = RECORDING.with(|r| r.replace(outer)).flatten();
+    out
+}
+
+/// The connector being polled resolved a host.
+#[cfg_attr(not(feature = "tokio"), allow(dead_code))]
+pub(crate) fn record_dns(lookup: Range<Instant>) {
+    RECORDING.with(|r| {
+        if let Some(ref mut dns) = *r.borrow_mut() {
+            *dns = Some(lookup);
+        }
+    });
+}
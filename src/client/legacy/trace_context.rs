@@ -0,0 +1,196 @@
+//! W3C trace context propagation.
+//!
+//! This module provides a [`TraceContextLayer`], which writes the
+//! `traceparent`, `tracestate` and `baggage` headers of the requests sent
+//! through a `Client`, so the servers they reach continue the trace. The
+//! server side is [`server::trace_context`](crate::server::trace_context),
+//! with the `server` feature.
+//!
+//! The [`TraceContext`] of a request is the one in its extensions, or else
+//! the one returned by [`TraceContextLayer::current`], which is where an
+//! OpenTelemetry propagator hooks in, with the context of the current span.
+//!
+//! # Example
+//!
+//! ```
+//! # #[cfg(feature = "tokio")]
+//! # fn run() {
+//! use bytes::Bytes;
+//! use http_body_util::Empty;
+//! use hyper_util::client::legacy::trace_context::{TraceContext, TraceContextLayer};
+//! use hyper_util::client::legacy::Client;
+//! use hyper_util::rt::TokioExecutor;
+//! use tower::{Layer, ServiceExt};
+//!
+//! let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+//! let client = TraceContextLayer::new().layer(client);
+//!
+//! let cx = TraceContext::new([0x4b; 16], [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7], 1)
+//!     .unwrap();
+//! let mut req = http::Request::get("http://example.local/").body(Empty::new()).unwrap();
+//! req.extensions_mut().insert(cx);
+//! let future = client.oneshot(req);
+//! # }
+//! # fn main() {}
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+use std::task::{self, Poll};
+
+use http::Request;
+
+pub use crate::common::trace_context::TraceContext;
+
+type Current = Arc<dyn Fn() -> Option<TraceContext> + Send + Sync>;
+
+/// A `Layer` writing the trace context of requests to their headers.
+///
+/// A request that already has a `traceparent` header is sent as it is.
+#[derive(Clone, Default)]
+pub struct TraceContextLayer {
+    current: Option<Current>,
+}
+
+/// A `Service` created by a [`TraceContextLayer`].
+#[derive(Clone, Debug)]
+pub struct TraceContextService<C> {
+    inner: C,
+    layer: TraceContextLayer,
+}
+
+// ===== impl TraceContextLayer =====
+
+impl TraceContextLayer {
+    /// Create a layer propagating the trace context in the extensions of
+    /// each request.
+    pub fn new() -> TraceContextLayer {
+        TraceContextLayer::default()
+    }
+
+    /// Set a function returning the trace context of the requests without
+    /// one in their extensions, such as that of the current span.
+    pub fn current<F>(mut self, f: F) -> TraceContextLayer
+    where
+        F: Fn() -> Option<TraceContext> + Send + Sync + 'static,
+    {
+        self.current = Some(Arc::new(f));
+        self
+    }
+
+    fn inject<B>(&self, req: &mut Request<B>) {
+        if req.headers().contains_key("traceparent") {
+            return;
+        }
+        let cx = match req.extensions().get::<TraceContext>() {
+            Some(cx) => Some(cx.clone()),
+            None => self.current.as_ref().and_then(|current| current()),
+        };
+        if let Some(cx) = cx {
+            cx.inject(req.headers_mut());
+        }
+    }
+}
+
+impl<C> tower::Layer<C> for TraceContextLayer {
+    type Service = TraceContextService<C>;
+
+    fn layer(&self, inner: C) -> Self::Service {
+        TraceContextService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for TraceContextLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TraceContextLayer")
+            .field("current", &self.current.is_some())
+            .finish()
+    }
+}
+
+// ===== impl TraceContextService =====
+
+impl<C> TraceContextService<C> {
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C, B> tower_service::Service<Request<B>> for TraceContextService<C>
+where
+    C: tower_service::Service<Request<B>>,
+{
+    type Response = C::Response;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn poll_ready(&mut self, cx: &mut task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        self.layer.inject(&mut req);
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::Ready;
+    use std::task::{Context, Poll};
+
+    use http::{HeaderMap, Request};
+    use tower::{Layer, ServiceExt};
+    use tower_service::Service;
+
+    use super::{TraceContext, TraceContextLayer};
+
+    // A service answering with the headers of the request.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<()>> for Echo {
+        type Response = HeaderMap;
+        type Error = Infallible;
+        type Future = Ready<Result<HeaderMap, Infallible>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<()>) -> Self::Future {
+            std::future::ready(Ok(req.headers().clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn injects_the_context_of_requests() {
+        let current = TraceContext::new([1; 16], [2; 8], 0).unwrap();
+        let service = TraceContextLayer::new()
+            .current(move || Some(current.clone()))
+            .layer(Echo);
+
+        let headers = service.clone().oneshot(Request::new(())).await.unwrap();
+        assert_eq!(
+            headers["traceparent"],
+            "00-01010101010101010101010101010101-0202020202020202-00"
+        );
+
+        let mut req = Request::new(());
+        let cx = TraceContext::new([3; 16], [4; 8], 1).unwrap();
+        req.extensions_mut().insert(cx.clone());
+        let headers = service.clone().oneshot(req).await.unwrap();
+        assert_eq!(TraceContext::extract(&headers), Some(cx));
+
+        let mut req = Request::new(());
+        req.headers_mut()
+            .insert("traceparent", "kept".parse().unwrap());
+        let headers = service.oneshot(req).await.unwrap();
+        assert_eq!(headers["traceparent"], "kept");
+    }
+}
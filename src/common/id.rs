@@ -0,0 +1,10 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Allocate an ID for a new connection, of the client or of a server.
+///
+/// IDs increase, and are unique in the process, so one is enough to tell
+/// the logs of a connection apart from the others.
+pub(crate) fn next_connection_id() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
@@ -1,12 +1,27 @@
 #![allow(missing_docs)]
 
+#[cfg(any(feature = "body-digest", feature = "client-auth"))]
+pub(crate) mod base64;
 pub(crate) mod exec;
+#[cfg(any(
+    feature = "client-legacy",
+    all(feature = "server", any(feature = "http1", feature = "http2"))
+))]
+pub(crate) mod id;
 #[cfg(feature = "client")]
 mod lazy;
+#[cfg(any(feature = "body-multipart", feature = "client-legacy"))]
+pub(crate) mod rand;
+#[cfg(all(test, feature = "metrics"))]
+pub(crate) mod recorder;
 pub(crate) mod rewind;
 #[cfg(feature = "client")]
 mod sync;
+#[cfg(any(feature = "client-legacy", all(feature = "server", feature = "tokio")))]
+pub(crate) mod tclass;
 pub(crate) mod timer;
+#[cfg(any(feature = "client-legacy", feature = "server"))]
+pub(crate) mod trace_context;
 
 #[cfg(feature = "client")]
 pub(crate) use exec::Exec;
@@ -0,0 +1,107 @@
+//! A `metrics` recorder for tests, collecting the keys of what is recorded.
+
+use std::sync::{Arc, Mutex};
+
+use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName};
+use metrics::{Recorder, SharedString, Unit};
+
+/// The keys recorded to, formatted as `name{label=value,...}`, shared by
+/// every test of the process since the recorder is global.
+#[derive(Clone)]
+pub(crate) struct Recorded(Arc<Mutex<Vec<String>>>);
+
+impl Recorded {
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|recorded| recorded == key)
+    }
+}
+
+/// Install the recorder, unless it already is.
+pub(crate) fn install() -> Recorded {
+    static RECORDED: Mutex<Option<Recorded>> = Mutex::new(None);
+
+    let mut recorded = RECORDED.lock().unwrap();
+    recorded
+        .get_or_insert_with(|| {
+            let recorded = Recorded(Arc::new(Mutex::new(Vec::new())));
+            metrics::set_boxed_recorder(Box::new(TestRecorder(recorded.clone())))
+                .expect("only this installs a recorder");
+            recorded
+        })
+        .clone()
+}
+
+struct TestRecorder(Recorded);
+
+struct Handle(Recorded, String);
+
+impl TestRecorder {
+    fn handle(&self, key: &Key) -> Arc<Handle> {
+        let labels = key
+            .labels()
+            .map(|label| format!("{}={}", label.key(), label.value()))
+            .collect::<Vec<_>>();
+        Arc::new(Handle(
+            self.0.clone(),
+            format!("{}{{{}}}", key.name(), labels.join(",")),
+        ))
+    }
+}
+
+impl Recorder for TestRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        Counter::from_arc(self.handle(key))
+    }
+
+    fn register_gauge(&self, key: &Key) -> Gauge {
+        Gauge::from_arc(self.handle(key))
+    }
+
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        Histogram::from_arc(self.handle(key))
+    }
+}
+
+impl Handle {
+    fn record(&self) {
+        (self.0).0.lock().unwrap().push(self.1.clone());
+    }
+}
+
+impl CounterFn for Handle {
+    fn increment(&self, _: u64) {
+        self.record();
+    }
+
+    fn absolute(&self, _: u64) {
+        self.record();
+    }
+}
+
+impl GaugeFn for Handle {
+    fn increment(&self, _: f64) {
+        self.record();
+    }
+
+    fn decrement(&self, _: f64) {
+        self.record();
+    }
+
+    fn set(&self, _: f64) {
+        self.record();
+    }
+}
+
+impl HistogramFn for Handle {
+    fn record(&self, _: f64) {
+        Handle::record(self);
+    }
+}
@@ -38,9 +38,15 @@ impl<T> Rewind<T> {
         self.pre = Some(bs);
     }
 
-    // pub(crate) fn into_inner(self) -> (T, Bytes) {
-    //     (self.inner, self.pre.unwrap_or_else(Bytes::new))
-    // }
+    #[allow(dead_code)]
+    pub(crate) fn into_inner(self) -> (T, Bytes) {
+        (self.inner, self.pre.unwrap_or_default())
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.inner
+    }
 
     // pub(crate) fn get_mut(&mut self) -> &mut T {
     //     &mut self.inner
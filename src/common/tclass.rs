@@ -0,0 +1,78 @@
+use std::io;
+use std::net::SocketAddr;
+
+use socket2::Socket;
+
+// Set the traffic class of a socket connected to `peer`: `IP_TOS` over
+// IPv4, and `IPV6_TCLASS` over IPv6. IPv4 traffic of a dual-stack socket,
+// with an IPv4-mapped peer, uses `IP_TOS` too.
+pub(crate) fn set(socket: &Socket, peer: &SocketAddr, tclass: u8) -> io::Result<()> {
+    match peer {
+        SocketAddr::V4(_) => set_v4(socket, tclass),
+        SocketAddr::V6(addr) => {
+            set_v6(socket, tclass)?;
+            if addr.ip().to_ipv4_mapped().is_some() {
+                set_v4(socket, tclass)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(not(any(
+    target_os = "fuchsia",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "illumos",
+    target_os = "haiku",
+)))]
+fn set_v4(socket: &Socket, tclass: u8) -> io::Result<()> {
+    socket.set_tos(tclass.into())
+}
+
+#[cfg(any(
+    target_os = "fuchsia",
+    target_os = "redox",
+    target_os = "solaris",
+    target_os = "illumos",
+    target_os = "haiku",
+))]
+fn set_v4(_: &Socket, _: u8) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+))]
+fn set_v6(socket: &Socket, tclass: u8) -> io::Result<()> {
+    socket.set_tclass_v6(tclass.into())
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "fuchsia",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+)))]
+fn set_v6(_: &Socket, _: u8) -> io::Result<()> {
+    Err(unsupported())
+}
+
+#[allow(dead_code)]
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Other,
+        "traffic class is not supported on this platform",
+    )
+}
@@ -35,4 +35,17 @@ impl hyper::rt::Timer for Timer {
     fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Sleep>> {
         self.0.sleep_until(deadline)
     }
+
+    fn now(&self) -> Instant {
+        self.0.now()
+    }
+
+    fn reset(&self, sleep: &mut Pin<Box<dyn Sleep>>, new_deadline: Instant) {
+        self.0.reset(sleep, new_deadline)
+    }
+}
+
+// The current time by `timer`, if any, so that a mocked clock is honored.
+pub(crate) fn now(timer: Option<&Timer>) -> Instant {
+    timer.map_or_else(Instant::now, hyper::rt::Timer::now)
 }
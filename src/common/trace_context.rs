@@ -0,0 +1,259 @@
+use std::fmt;
+
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+
+const TRACEPARENT: HeaderName = HeaderName::from_static("traceparent");
+const TRACESTATE: HeaderName = HeaderName::from_static("tracestate");
+const BAGGAGE: HeaderName = HeaderName::from_static("baggage");
+
+const SAMPLED: u8 = 0x01;
+
+/// The W3C trace context of a request, with its baggage.
+///
+/// This is the `traceparent` header, parsed, with the `tracestate` and
+/// `baggage` headers kept as they were received. Several fields of one of
+/// those are joined with commas.
+///
+/// # Example
+///
+/// ```
+/// # #[cfg(feature = "server")]
+/// # fn run() {
+/// use http::HeaderMap;
+/// use hyper_util::server::trace_context::TraceContext;
+///
+/// let mut headers = HeaderMap::new();
+/// headers.insert(
+///     "traceparent",
+///     "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".parse().unwrap(),
+/// );
+/// let cx = TraceContext::extract(&headers).unwrap();
+/// assert!(cx.is_sampled());
+/// assert_eq!(cx.parent_id(), [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+/// # }
+/// # fn main() {}
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    trace_id: [u8; 16],
+    parent_id: [u8; 8],
+    flags: u8,
+    trace_state: Option<HeaderValue>,
+    baggage: Option<HeaderValue>,
+}
+
+impl TraceContext {
+    /// Create a trace context, without state or baggage.
+    ///
+    /// Returns `None` if either ID is all zeroes, which is invalid.
+    pub fn new(trace_id: [u8; 16], parent_id: [u8; 8], flags: u8) -> Option<TraceContext> {
+        if trace_id == [0; 16] || parent_id == [0; 8] {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id,
+            parent_id,
+            flags,
+            trace_state: None,
+            baggage: None,
+        })
+    }
+
+    /// Read the trace context of a request or response.
+    ///
+    /// Returns `None` if there is no `traceparent`, or if it is invalid, in
+    /// which case `tracestate` is ignored too. Versions after `00` are read
+    /// as `00`, ignoring what they add.
+    pub fn extract(headers: &HeaderMap) -> Option<TraceContext> {
+        let mut traceparent = headers.get_all(TRACEPARENT).iter();
+        let value = traceparent.next()?;
+        if traceparent.next().is_some() {
+            return None;
+        }
+        let mut cx = parse_traceparent(value.as_bytes())?;
+        cx.trace_state = join(headers, &TRACESTATE);
+        cx.baggage = join(headers, &BAGGAGE);
+        Some(cx)
+    }
+
+    /// Write this trace context to the headers of a request or response,
+    /// replacing what they had.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        let traceparent = HeaderValue::from_str(&self.to_string()).expect("hex is a valid value");
+        headers.insert(TRACEPARENT, traceparent);
+        match self.trace_state {
+            Some(ref state) => headers.insert(TRACESTATE, state.clone()),
+            None => headers.remove(TRACESTATE),
+        };
+        match self.baggage {
+            Some(ref baggage) => headers.insert(BAGGAGE, baggage.clone()),
+            None => headers.remove(BAGGAGE),
+        };
+    }
+
+    /// The ID of the whole trace.
+    pub fn trace_id(&self) -> [u8; 16] {
+        self.trace_id
+    }
+
+    /// The ID of the span the request was sent from.
+    pub fn parent_id(&self) -> [u8; 8] {
+        self.parent_id
+    }
+
+    /// The trace flags.
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+
+    /// Whether the caller may have recorded the trace.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & SAMPLED != 0
+    }
+
+    /// The `tracestate`, vendor-specific data of the trace.
+    pub fn trace_state(&self) -> Option<&HeaderValue> {
+        self.trace_state.as_ref()
+    }
+
+    /// The `baggage`, application-defined properties of the trace.
+    pub fn baggage(&self) -> Option<&HeaderValue> {
+        self.baggage.as_ref()
+    }
+
+    /// The same trace, continued from the span `parent_id`.
+    ///
+    /// This is the context to send the requests made while handling a
+    /// request with, from the span handling it. An all zeroes ID is ignored.
+    pub fn with_parent_id(mut self, parent_id: [u8; 8]) -> TraceContext {
+        if parent_id != [0; 8] {
+            self.parent_id = parent_id;
+        }
+        self
+    }
+
+    /// Set the `tracestate`.
+    pub fn with_trace_state(mut self, state: impl Into<Option<HeaderValue>>) -> TraceContext {
+        self.trace_state = state.into();
+        self
+    }
+
+    /// Set the `baggage`.
+    pub fn with_baggage(mut self, baggage: impl Into<Option<HeaderValue>>) -> TraceContext {
+        self.baggage = baggage.into();
+        self
+    }
+}
+
+/// Formats the `traceparent`, at version `00`.
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("00-")?;
+        for b in self.trace_id {
+            write!(f, "{:02x}", b)?;
+        }
+        f.write_str("-")?;
+        for b in self.parent_id {
+            write!(f, "{:02x}", b)?;
+        }
+        write!(f, "-{:02x}", self.flags)
+    }
+}
+
+// `version-trace_id-parent_id-flags`, lowercase hex, where versions after
+// `00` may append fields.
+fn parse_traceparent(value: &[u8]) -> Option<TraceContext> {
+    if value.len() < 55 || value[2] != b'-' || value[35] != b'-' || value[52] != b'-' {
+        return None;
+    }
+    let mut version = [0; 1];
+    hex(&value[..2], &mut version)?;
+    match version[0] {
+        0xff => return None,
+        0x00 if value.len() != 55 => return None,
+        _ if value.len() > 55 && value[55] != b'-' => return None,
+        _ => (),
+    }
+    let mut trace_id = [0; 16];
+    let mut parent_id = [0; 8];
+    let mut flags = [0; 1];
+    hex(&value[3..35], &mut trace_id)?;
+    hex(&value[36..52], &mut parent_id)?;
+    hex(&value[53..55], &mut flags)?;
+    TraceContext::new(trace_id, parent_id, flags[0])
+}
+
+fn hex(src: &[u8], dst: &mut [u8]) -> Option<()> {
+    fn digit(b: u8) -> Option<u8> {
+        match b {
+            b'0'..=b'9' => Some(b - b'0'),
+            b'a'..=b'f' => Some(b - b'a' + 10),
+            _ => None,
+        }
+    }
+    for (pair, out) in src.chunks(2).zip(dst) {
+        *out = digit(pair[0])? << 4 | digit(pair[1])?;
+    }
+    Some(())
+}
+
+// The fields of a list header, joined into one value.
+fn join(headers: &HeaderMap, name: &HeaderName) -> Option<HeaderValue> {
+    let mut values = headers.get_all(name).iter();
+    let first = values.next()?;
+    let rest = values.collect::<Vec<_>>();
+    if rest.is_empty() {
+        return Some(first.clone());
+    }
+    let mut joined = first.as_bytes().to_vec();
+    for value in rest {
+        joined.extend_from_slice(b",");
+        joined.extend_from_slice(value.as_bytes());
+    }
+    HeaderValue::from_bytes(&joined).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HeaderMap, HeaderValue};
+
+    use super::TraceContext;
+
+    #[test]
+    fn extracts_and_injects() {
+        let extract = |traceparent: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("traceparent", HeaderValue::from_static(traceparent));
+            headers.append("tracestate", HeaderValue::from_static("a=1"));
+            headers.append("tracestate", HeaderValue::from_static("b=2"));
+            TraceContext::extract(&headers)
+        };
+
+        let cx = extract("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert!(cx.is_sampled());
+        assert_eq!(cx.trace_state().unwrap(), "a=1,b=2");
+        assert!(cx.baggage().is_none());
+        // future versions may append fields
+        assert!(extract("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-x").is_some());
+        for invalid in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert_eq!(extract(invalid), None, "{}", invalid);
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert("baggage", HeaderValue::from_static("stale=1"));
+        cx.with_parent_id([0xab; 8]).inject(&mut headers);
+        assert_eq!(
+            headers["traceparent"],
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-abababababababab-01"
+        );
+        assert_eq!(headers["tracestate"], "a=1,b=2");
+        assert!(!headers.contains_key("baggage"));
+    }
+}
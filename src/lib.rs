@@ -6,13 +6,18 @@
 //! This crate is less-stable than [`hyper`](https://docs.rs/hyper). However,
 //! does respect Rust's semantic version regarding breaking changes.
 
+pub mod body;
 #[cfg(feature = "client")]
 pub mod client;
 mod common;
+#[cfg(feature = "proxy")]
+pub mod proxy;
 pub mod rt;
 #[cfg(feature = "server")]
 pub mod server;
 #[cfg(feature = "service")]
 pub mod service;
+#[cfg(feature = "test-util")]
+pub mod testing;
 
 mod error;
@@ -0,0 +1,380 @@
+//! Reverse proxy utilities.
+//!
+//! This module provides [`forward`], which sends a request received by a
+//! server on to an upstream server with a [`Client`], and returns the
+//! response to send back.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use std::convert::Infallible;
+//!
+//! use http::{Request, Uri};
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::client::legacy::Client;
+//! use hyper_util::proxy::forward;
+//! use hyper_util::rt::TokioExecutor;
+//!
+//! let client: Client<_, Incoming> = Client::builder(TokioExecutor::new()).build_http();
+//! let upstream = Uri::from_static("http://127.0.0.1:8080");
+//!
+//! let service = service_fn(move |req: Request<Incoming>| {
+//!     let client = client.clone();
+//!     let upstream = upstream.clone();
+//!     async move { Ok::<_, Infallible>(forward(req, &client, &upstream).await) }
+//! });
+//! ```
+
+use std::error::Error as StdError;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::uri::{self, PathAndQuery, Uri};
+use http::{Request, Response, StatusCode, Version};
+use http_body::{Body, Frame, SizeHint};
+use hyper::body::Incoming;
+use tracing::debug;
+
+use crate::client::legacy::connect::Connect;
+use crate::client::legacy::{Client, Error};
+use crate::rt::TokioIo;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+// Headers that only apply to a single connection, so aren't forwarded, in
+// addition to the ones `Connection` lists.
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The body of a response from [`forward`].
+///
+/// This is the upstream response body, or empty when the proxy responds
+/// itself.
+#[derive(Debug)]
+pub struct ProxyBody {
+    inner: Option<Incoming>,
+}
+
+/// Forward a request to `upstream`, and return the response for it.
+///
+/// The request URI gets the scheme and authority of `upstream`, and its
+/// path is appended to the path of `upstream`: with an upstream of
+/// `http://backend/api`, a request for `/users?page=2` is sent to
+/// `http://backend/api/users?page=2`. The `Host` header is set to the
+/// upstream's.
+///
+/// Hop-by-hop headers are removed from the request and the response, both
+/// the standard ones and those listed in `Connection`. `TE: trailers` is
+/// kept, so trailers can still be asked for. Bodies are streamed in both
+/// directions.
+///
+/// An upgrade request, such as a WebSocket handshake, is forwarded with its
+/// `Upgrade` header. If upstream switches protocols, the two upgraded
+/// connections are bridged on a spawned tokio task. This needs the server
+/// connection to be served with upgrades, such as with
+/// [`serve_connection_with_upgrades`](crate::server::conn::auto::Builder::serve_connection_with_upgrades).
+///
+/// Nothing is added about the client. To tell upstream who it is, add a
+/// hop with [`forwarded::append`](crate::server::forwarded::append) first.
+///
+/// If the client fails, the response is a `504 Gateway Timeout` when it
+/// timed out, and a `502 Bad Gateway` otherwise.
+pub async fn forward<C, B>(
+    mut req: Request<B>,
+    client: &Client<C, B>,
+    upstream: &Uri,
+) -> Response<ProxyBody>
+where
+    C: Connect + Clone + Send + Sync + 'static,
+    B: Body + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    let uri = match upstream_uri(upstream, req.uri()) {
+        Ok(uri) => uri,
+        Err(err) => {
+            debug!("invalid upstream uri {:?}: {}", upstream, err);
+            return status(StatusCode::BAD_GATEWAY);
+        }
+    };
+
+    let upgrade = upgrade_protocol(req.headers());
+    let server_upgrade = upgrade.as_ref().map(|_| hyper::upgrade::on(&mut req));
+
+    *req.uri_mut() = uri;
+    *req.version_mut() = Version::HTTP_11;
+    let headers = req.headers_mut();
+    let wants_trailers = wants_trailers(headers);
+    remove_hop_by_hop(headers);
+    headers.remove(header::HOST);
+    if wants_trailers {
+        headers.insert(header::TE, HeaderValue::from_static("trailers"));
+    }
+    if let Some(protocol) = upgrade {
+        headers.insert(header::CONNECTION, HeaderValue::from_static("upgrade"));
+        headers.insert(header::UPGRADE, protocol);
+    }
+
+    let mut res = match client.request(req).await {
+        Ok(res) => res,
+        Err(err) => return error_response(&err),
+    };
+
+    if res.status() == StatusCode::SWITCHING_PROTOCOLS {
+        let server_upgrade = match server_upgrade {
+            Some(upgrade) => upgrade,
+            None => {
+                debug!("upstream switched protocols without an upgrade request");
+                return status(StatusCode::BAD_GATEWAY);
+            }
+        };
+        let client_upgrade = hyper::upgrade::on(&mut res);
+        tokio::spawn(async move {
+            match futures_util::future::try_join(server_upgrade, client_upgrade).await {
+                Ok((server, client)) => {
+                    let mut server = TokioIo::new(server);
+                    let mut client = TokioIo::new(client);
+                    if let Err(err) = tokio::io::copy_bidirectional(&mut server, &mut client).await
+                    {
+                        debug!("proxied upgrade error: {}", err);
+                    }
+                }
+                Err(err) => debug!("proxy upgrade failed: {}", err),
+            }
+        });
+        return res.map(ProxyBody::upstream);
+    }
+
+    remove_hop_by_hop(res.headers_mut());
+    res.map(ProxyBody::upstream)
+}
+
+fn upstream_uri(upstream: &Uri, uri: &Uri) -> Result<Uri, BoxError> {
+    let scheme = upstream.scheme().ok_or("upstream uri has no scheme")?;
+    let authority = upstream
+        .authority()
+        .ok_or("upstream uri has no authority")?;
+
+    let base = upstream.path().trim_end_matches('/');
+    let path = uri.path();
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}{}?{}", base, path, query),
+        None => format!("{}{}", base, path),
+    };
+
+    Ok(uri::Builder::new()
+        .scheme(scheme.clone())
+        .authority(authority.clone())
+        .path_and_query(PathAndQuery::try_from(path_and_query)?)
+        .build()?)
+}
+
+fn upgrade_protocol(headers: &HeaderMap) -> Option<HeaderValue> {
+    let is_upgrade = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case("upgrade"));
+    if is_upgrade {
+        headers.get(header::UPGRADE).cloned()
+    } else {
+        None
+    }
+}
+
+fn wants_trailers(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::TE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|v| v.trim().eq_ignore_ascii_case("trailers"))
+}
+
+fn remove_hop_by_hop(headers: &mut HeaderMap) {
+    let listed: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in listed {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(*name);
+    }
+}
+
+fn error_response(err: &Error) -> Response<ProxyBody> {
+    debug!("proxy client error: {}", err);
+    let mut source: Option<&(dyn StdError + 'static)> = Some(err);
+    while let Some(err) = source {
+        let timed_out = if let Some(err) = err.downcast_ref::<io::Error>() {
+            err.kind() == io::ErrorKind::TimedOut
+        } else if let Some(err) = err.downcast_ref::<hyper::Error>() {
+            err.is_timeout()
+        } else {
+            false
+        };
+        if timed_out {
+            return status(StatusCode::GATEWAY_TIMEOUT);
+        }
+        source = err.source();
+    }
+    status(StatusCode::BAD_GATEWAY)
+}
+
+fn status(status: StatusCode) -> Response<ProxyBody> {
+    let mut res = Response::new(ProxyBody { inner: None });
+    *res.status_mut() = status;
+    res
+}
+
+// ===== impl ProxyBody =====
+
+impl ProxyBody {
+    fn upstream(inner: Incoming) -> ProxyBody {
+        ProxyBody { inner: Some(inner) }
+    }
+}
+
+impl Body for ProxyBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.inner {
+            Some(ref mut inner) => Pin::new(inner).poll_frame(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.inner {
+            Some(ref inner) => inner.is_end_stream(),
+            None => true,
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.inner {
+            Some(ref inner) => inner.size_hint(),
+            None => SizeHint::with_exact(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+
+    use bytes::Bytes;
+    use http::{header, HeaderMap, Request, Response, StatusCode, Uri};
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::body::Incoming;
+    use hyper::service::service_fn;
+    use tokio::net::TcpListener;
+
+    use super::{forward, remove_hop_by_hop, upstream_uri};
+    use crate::client::legacy::Client;
+    use crate::rt::{TokioExecutor, TokioIo};
+
+    #[test]
+    fn rewrites_uri() {
+        let upstream = Uri::from_static("http://backend:8080/api/");
+        let uri = Uri::from_static("/users?page=2");
+        assert_eq!(
+            upstream_uri(&upstream, &uri).unwrap(),
+            "http://backend:8080/api/users?page=2"
+        );
+        let upstream = Uri::from_static("https://backend");
+        let uri = Uri::from_static("http://proxy/");
+        assert_eq!(upstream_uri(&upstream, &uri).unwrap(), "https://backend/");
+        assert!(upstream_uri(&Uri::from_static("/relative"), &uri).is_err());
+    }
+
+    #[test]
+    fn removes_hop_by_hop() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, "keep-alive, x-secret".parse().unwrap());
+        headers.insert("keep-alive", "timeout=5".parse().unwrap());
+        headers.insert("x-secret", "1".parse().unwrap());
+        headers.insert(header::TRANSFER_ENCODING, "chunked".parse().unwrap());
+        headers.insert(header::ACCEPT, "*/*".parse().unwrap());
+        remove_hop_by_hop(&mut headers);
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(header::ACCEPT));
+    }
+
+    #[tokio::test]
+    async fn forwards_and_maps_errors() {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|req: Request<Incoming>| async move {
+                let host = req.headers()[header::HOST].clone();
+                assert!(!req.headers().contains_key("x-secret"));
+                let res = Response::builder()
+                    .header(header::CONNECTION, "x-internal")
+                    .header("x-internal", "1")
+                    .body(Full::new(Bytes::from(format!(
+                        "{} {}",
+                        host.to_str().unwrap(),
+                        req.uri()
+                    ))))
+                    .unwrap();
+                Ok::<_, Infallible>(res)
+            });
+            hyper::server::conn::http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+                .unwrap();
+        });
+
+        let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new()).build_http();
+        let upstream: Uri = format!("http://{}/base", addr).parse().unwrap();
+        let req = Request::builder()
+            .uri("/path?q=1")
+            .header(header::HOST, "proxy.example")
+            .header(header::CONNECTION, "x-secret")
+            .header("x-secret", "1")
+            .body(Empty::new())
+            .unwrap();
+        let res = forward(req, &client, &upstream).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key("x-internal"));
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, format!("{} /base/path?q=1", addr));
+
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let upstream: Uri = format!("http://{}", closed).parse().unwrap();
+        let res = forward(Request::new(Empty::new()), &client, &upstream).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+    }
+}
@@ -0,0 +1,150 @@
+//! A timer whose clock only moves when told to.
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use hyper::rt::{Sleep, Timer};
+
+/// A [`Timer`] whose clock is advanced by hand.
+///
+/// Its clock starts at the time it is created, and only moves forward with
+/// [`advance`](ManualTimer::advance), waking the sleeps whose deadline
+/// passed. Setting it as the timer of a `Client` or a server connection
+/// makes their expiry deterministic in tests, without sleeping for real:
+/// the idle connections of the pool, for instance, expire by the time of
+/// the timer.
+///
+/// Clones share the same clock.
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use hyper::rt::Timer;
+/// use hyper_util::rt::ManualTimer;
+///
+/// let timer = ManualTimer::new();
+/// let start = timer.now();
+/// timer.advance(Duration::from_secs(90));
+/// assert_eq!(timer.now() - start, Duration::from_secs(90));
+/// ```
+#[derive(Clone, Default)]
+pub struct ManualTimer {
+    clock: Arc<Clock>,
+}
+
+#[derive(Debug)]
+struct Clock {
+    now: Mutex<Instant>,
+    wakers: Mutex<Vec<Waker>>,
+}
+
+// A sleep of a `ManualTimer`.
+struct ManualSleep {
+    clock: Arc<Clock>,
+    deadline: Instant,
+}
+
+// ===== impl ManualTimer =====
+
+impl ManualTimer {
+    /// Create a timer, starting at the current time.
+    pub fn new() -> ManualTimer {
+        ManualTimer::default()
+    }
+
+    /// Move the clock forward by `duration`, waking the sleeps that are
+    /// due.
+    pub fn advance(&self, duration: Duration) {
+        *self.clock.now.lock().unwrap() += duration;
+        let wakers = std::mem::take(&mut *self.clock.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl Timer for ManualTimer {
+    fn sleep(&self, duration: Duration) -> Pin<Box<dyn Sleep>> {
+        self.sleep_until(self.now() + duration)
+    }
+
+    fn sleep_until(&self, deadline: Instant) -> Pin<Box<dyn Sleep>> {
+        Box::pin(ManualSleep {
+            clock: self.clock.clone(),
+            deadline,
+        })
+    }
+
+    fn now(&self) -> Instant {
+        *self.clock.now.lock().unwrap()
+    }
+}
+
+impl fmt::Debug for ManualTimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ManualTimer")
+            .field("now", &self.now())
+            .finish()
+    }
+}
+
+// ===== impl Clock =====
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock {
+            now: Mutex::new(Instant::now()),
+            wakers: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+// ===== impl ManualSleep =====
+
+impl Future for ManualSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // Registered before reading the clock, so an advance in between
+        // still wakes this sleep.
+        let mut wakers = self.clock.wakers.lock().unwrap();
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        drop(wakers);
+        if *self.clock.now.lock().unwrap() >= self.deadline {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Sleep for ManualSleep {}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures_util::FutureExt;
+    use hyper::rt::Timer;
+
+    use super::ManualTimer;
+
+    #[test]
+    fn sleeps_wake_when_advanced() {
+        let timer = ManualTimer::new();
+        let mut sleep = timer.sleep(Duration::from_secs(10));
+        assert!(sleep.as_mut().now_or_never().is_none());
+
+        timer.advance(Duration::from_secs(9));
+        assert!(sleep.as_mut().now_or_never().is_none());
+
+        timer.clone().advance(Duration::from_secs(1));
+        assert!(sleep.as_mut().now_or_never().is_some());
+    }
+}
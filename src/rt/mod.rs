@@ -3,5 +3,9 @@
 #[cfg(feature = "tokio")]
 pub mod tokio;
 
+mod manual;
+
+pub use self::manual::ManualTimer;
+
 #[cfg(feature = "tokio")]
 pub use self::tokio::{TokioExecutor, TokioIo, TokioTimer};
@@ -227,6 +227,11 @@ impl Timer for TokioTimer {
         })
     }
 
+    fn now(&self) -> Instant {
+        // follows the clock of a paused runtime, in tests
+        tokio::time::Instant::now().into_std()
+    }
+
     fn reset(&self, sleep: &mut Pin<Box<dyn Sleep>>, new_deadline: Instant) {
         if let Some(sleep) = sleep.as_mut().downcast_mut_pin::<TokioSleep>() {
             sleep.reset(new_deadline)
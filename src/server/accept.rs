@@ -0,0 +1,564 @@
+//! Accepting connections.
+//!
+//! The [`Accept`] trait is a source of connections, such as a listening
+//! socket, implemented for TCP and Unix listeners, for TLS with
+//! [`Handshake`], and for the in-memory transport of the testing module.
+//! So the code accepting connections is written once, for any of them.
+//!
+//! A naive accept loop either returns on the first error, taking the server
+//! down because one client reset its connection, or retries right away,
+//! spinning on `EMFILE` until a file descriptor frees up. [`Acceptor`] skips
+//! errors of a single connection, and pauses with exponential backoff on
+//! errors of the listener, such as running out of file descriptors.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use std::time::Duration;
+//! use hyper_util::server::accept::Acceptor;
+//!
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await?;
+//! let mut acceptor = Acceptor::new(listener)
+//!     .backoff(Duration::from_millis(5), Duration::from_secs(1))
+//!     .on_error(|err, pause| eprintln!("accept error: {err}, pausing {pause:?}"));
+//! loop {
+//!     let (stream, _) = acceptor.accept().await;
+//!     // serve `stream`...
+//! #   drop(stream);
+//! }
+//! # }
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::future;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
+use tokio::time::Sleep;
+
+type OnError = Arc<dyn Fn(&io::Error, Option<Duration>) + Send + Sync>;
+
+// Sets the traffic class of an accepted connection.
+type MarkTrafficClass<L> = fn(&<L as Accept>::Io, &<L as Accept>::Peer, u8);
+
+/// A source of connections, such as a listening socket.
+///
+/// # Example
+///
+/// ```
+/// use std::io;
+/// use std::task::{Context, Poll};
+/// use hyper_util::server::accept::Accept;
+/// use tokio::io::DuplexStream;
+/// use tokio::sync::mpsc::UnboundedReceiver;
+///
+/// // accepts the streams sent by the other side of a channel
+/// struct Channel(UnboundedReceiver<DuplexStream>);
+///
+/// impl Accept for Channel {
+///     type Io = DuplexStream;
+///     type Peer = ();
+///
+///     fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(DuplexStream, ())>> {
+///         match self.0.poll_recv(cx) {
+///             Poll::Ready(Some(stream)) => Poll::Ready(Ok((stream, ()))),
+///             Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::NotConnected.into())),
+///             Poll::Pending => Poll::Pending,
+///         }
+///     }
+/// }
+/// ```
+pub trait Accept {
+    /// The connections accepted.
+    type Io;
+    /// What is known of the peer of a connection, such as its address.
+    type Peer;
+
+    /// Poll for the next connection.
+    ///
+    /// An error of a single connection, such as `ECONNABORTED`, doesn't stop
+    /// the next connections from being accepted.
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(Self::Io, Self::Peer)>>;
+}
+
+/// Accept the next connection of `listener`.
+pub fn accept<L>(listener: &mut L) -> impl Future<Output = io::Result<(L::Io, L::Peer)>> + '_
+where
+    L: Accept + ?Sized,
+{
+    future::poll_fn(move |cx| listener.poll_accept(cx))
+}
+
+/// Accepts connections from a listener, handling errors by a policy.
+///
+/// Errors of a single connection, such as `ECONNABORTED`, are skipped.
+/// Any other error pauses accepting, starting at the minimum backoff and
+/// doubling with every error in a row up to the maximum, until a connection
+/// is accepted again.
+///
+/// An `Acceptor` is itself an [`Accept`], which never fails.
+pub struct Acceptor<L: Accept = TcpListener> {
+    listener: L,
+    min_backoff: Duration,
+    max_backoff: Duration,
+    backoff: Option<Duration>,
+    pause: Option<Pin<Box<Sleep>>>,
+    on_error: Option<OnError>,
+    traffic_class: Option<(u8, MarkTrafficClass<L>)>,
+    stats: AcceptStats,
+}
+
+/// Counters of the errors an [`Acceptor`] handled.
+///
+/// The counters are shared between clones.
+#[derive(Clone, Debug, Default)]
+pub struct AcceptStats {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    connection_errors: AtomicU64,
+    listener_errors: AtomicU64,
+    // In nanoseconds.
+    paused: AtomicU64,
+}
+
+/// Accepts connections once a handshake on them, such as TLS, is done.
+///
+/// The handshakes are spawned on the tokio runtime, so a slow client
+/// doesn't hold up the others. A failed handshake is an error of a single
+/// connection, of kind `ConnectionAborted`, which an [`Acceptor`] skips.
+///
+/// # Example
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// use std::time::Duration;
+/// use hyper_util::server::accept::{Acceptor, Handshake};
+/// use tokio::net::TcpStream;
+///
+/// # async fn tls_accept(stream: TcpStream) -> std::io::Result<TcpStream> { Ok(stream) }
+/// let listener = tokio::net::TcpListener::bind("0.0.0.0:8443").await?;
+/// let tls = Handshake::new(listener, |stream| tls_accept(stream))
+///     .timeout(Duration::from_secs(10));
+/// let mut acceptor = Acceptor::new(tls);
+/// loop {
+///     let (stream, _) = acceptor.accept().await;
+///     // serve `stream`...
+/// #   drop(stream);
+/// }
+/// # }
+/// ```
+pub struct Handshake<L: Accept, F, T> {
+    listener: L,
+    handshake: F,
+    timeout: Option<Duration>,
+    handshaking: JoinSet<(io::Result<T>, L::Peer)>,
+}
+
+// ===== impl Accept =====
+
+impl Accept for TcpListener {
+    type Io = TcpStream;
+    type Peer = SocketAddr;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(TcpStream, SocketAddr)>> {
+        TcpListener::poll_accept(self, cx)
+    }
+}
+
+#[cfg(unix)]
+impl Accept for tokio::net::UnixListener {
+    type Io = tokio::net::UnixStream;
+    type Peer = tokio::net::unix::SocketAddr;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(Self::Io, Self::Peer)>> {
+        tokio::net::UnixListener::poll_accept(self, cx)
+    }
+}
+
+impl<L: Accept + ?Sized> Accept for &mut L {
+    type Io = L::Io;
+    type Peer = L::Peer;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(Self::Io, Self::Peer)>> {
+        (**self).poll_accept(cx)
+    }
+}
+
+// ===== impl Acceptor =====
+
+impl<L: Accept> Acceptor<L> {
+    /// Create an acceptor for `listener`.
+    ///
+    /// The backoff defaults to start at 5 milliseconds, up to 1 second.
+    pub fn new(listener: L) -> Acceptor<L> {
+        Acceptor {
+            listener,
+            min_backoff: Duration::from_millis(5),
+            max_backoff: Duration::from_secs(1),
+            backoff: None,
+            pause: None,
+            on_error: None,
+            traffic_class: None,
+            stats: AcceptStats::default(),
+        }
+    }
+
+    /// Set the pause after the first error of the listener, and the most
+    /// it doubles up to.
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Acceptor<L> {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self
+    }
+
+    /// Set a callback for every accept error.
+    ///
+    /// It is called with the error and the pause taken before accepting
+    /// again, or `None` if the error was of a single connection and was
+    /// skipped.
+    pub fn on_error<F>(mut self, f: F) -> Acceptor<L>
+    where
+        F: Fn(&io::Error, Option<Duration>) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(f));
+        self
+    }
+
+    /// The counters of the errors this acceptor handled.
+    pub fn stats(&self) -> AcceptStats {
+        self.stats.clone()
+    }
+
+    /// Get a reference to the listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+
+    /// Accept the next connection.
+    ///
+    /// This never fails: errors are handled by the policy of the acceptor,
+    /// and accepting continues.
+    pub async fn accept(&mut self) -> (L::Io, L::Peer) {
+        future::poll_fn(|cx| self.poll_accepted(cx)).await
+    }
+
+    fn poll_accepted(&mut self, cx: &mut Context<'_>) -> Poll<(L::Io, L::Peer)> {
+        loop {
+            if let Some(ref mut pause) = self.pause {
+                futures_util::ready!(pause.as_mut().poll(cx));
+                self.pause = None;
+            }
+            match futures_util::ready!(self.listener.poll_accept(cx)) {
+                Ok(accepted) => {
+                    self.backoff = None;
+                    if let Some((tclass, mark)) = self.traffic_class {
+                        mark(&accepted.0, &accepted.1, tclass);
+                    }
+                    return Poll::Ready(accepted);
+                }
+                Err(err) => {
+                    if let Some(pause) = self.handle_error(&err) {
+                        self.pause = Some(Box::pin(tokio::time::sleep(pause)));
+                    }
+                }
+            }
+        }
+    }
+
+    // Count the error, and return how long to pause, if at all.
+    fn handle_error(&mut self, err: &io::Error) -> Option<Duration> {
+        let pause = if is_connection_error(err) {
+            self.stats
+                .inner
+                .connection_errors
+                .fetch_add(1, Ordering::Relaxed);
+            None
+        } else {
+            self.stats
+                .inner
+                .listener_errors
+                .fetch_add(1, Ordering::Relaxed);
+            let pause = match self.backoff {
+                Some(prev) => (prev * 2).min(self.max_backoff),
+                None => self.min_backoff,
+            };
+            self.stats
+                .inner
+                .paused
+                .fetch_add(pause.as_nanos() as u64, Ordering::Relaxed);
+            self.backoff = Some(pause);
+            Some(pause)
+        };
+        if let Some(ref on_error) = self.on_error {
+            on_error(err, pause);
+        }
+        pause
+    }
+}
+
+impl Acceptor<TcpListener> {
+    /// Set the traffic class of the accepted connections, for QoS marking.
+    ///
+    /// This is the whole byte of `IP_TOS` over IPv4, and of `IPV6_TCLASS`
+    /// over IPv6: a DSCP code point is shifted left by 2, so `AF41` (34) is
+    /// `0x88`. Failing to set it doesn't fail accepting the connection.
+    pub fn traffic_class(mut self, tclass: u8) -> Acceptor<TcpListener> {
+        self.traffic_class = Some((tclass, set_traffic_class));
+        self
+    }
+}
+
+impl<L: Accept> Accept for Acceptor<L> {
+    type Io = L::Io;
+    type Peer = L::Peer;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(Self::Io, Self::Peer)>> {
+        self.poll_accepted(cx).map(Ok)
+    }
+}
+
+impl<L: Accept + fmt::Debug> fmt::Debug for Acceptor<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acceptor")
+            .field("listener", &self.listener)
+            .field("min_backoff", &self.min_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field(
+                "traffic_class",
+                &self.traffic_class.map(|(tclass, _)| tclass),
+            )
+            .finish()
+    }
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn set_traffic_class(stream: &TcpStream, addr: &SocketAddr, tclass: u8) {
+    let socket = socket2::SockRef::from(stream);
+    if let Err(err) = crate::common::tclass::set(&socket, addr, tclass) {
+        #[cfg(feature = "tracing")]
+        tracing::debug!("accepted set_traffic_class error: {}", err);
+    }
+}
+
+// ===== impl Handshake =====
+
+impl<L, F, Fut, T> Handshake<L, F, T>
+where
+    L: Accept,
+    F: FnMut(L::Io) -> Fut,
+    Fut: Future<Output = io::Result<T>>,
+{
+    /// Run `handshake` on every connection of `listener`, accepting what it
+    /// returns.
+    pub fn new(listener: L, handshake: F) -> Handshake<L, F, T> {
+        Handshake {
+            listener,
+            handshake,
+            timeout: None,
+            handshaking: JoinSet::new(),
+        }
+    }
+
+    /// Fail the handshakes that take longer than `timeout`.
+    ///
+    /// Default is no timeout.
+    pub fn timeout(mut self, timeout: Duration) -> Handshake<L, F, T> {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Get a reference to the listener.
+    pub fn get_ref(&self) -> &L {
+        &self.listener
+    }
+}
+
+impl<L, F, Fut, T> Accept for Handshake<L, F, T>
+where
+    L: Accept,
+    L::Peer: Send + 'static,
+    F: FnMut(L::Io) -> Fut,
+    Fut: Future<Output = io::Result<T>> + Send + 'static,
+    T: Send + 'static,
+{
+    type Io = T;
+    type Peer = L::Peer;
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(T, L::Peer)>> {
+        // start the handshakes of every connection accepted so far
+        loop {
+            match self.listener.poll_accept(cx) {
+                Poll::Ready(Ok((io, peer))) => {
+                    let handshake = (self.handshake)(io);
+                    let timeout = self.timeout;
+                    self.handshaking.spawn(async move {
+                        let res = match timeout {
+                            Some(timeout) => match tokio::time::timeout(timeout, handshake).await {
+                                Ok(res) => res,
+                                Err(elapsed) => {
+                                    Err(io::Error::new(io::ErrorKind::TimedOut, elapsed))
+                                }
+                            },
+                            None => handshake.await,
+                        };
+                        (res, peer)
+                    });
+                }
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => break,
+            }
+        }
+        match futures_util::ready!(self.handshaking.poll_join_next(cx)) {
+            Some(Ok((Ok(io), peer))) => Poll::Ready(Ok((io, peer))),
+            Some(Ok((Err(err), _))) => {
+                Poll::Ready(Err(io::Error::new(io::ErrorKind::ConnectionAborted, err)))
+            }
+            Some(Err(err)) => {
+                Poll::Ready(Err(io::Error::new(io::ErrorKind::ConnectionAborted, err)))
+            }
+            // the listener will wake the task
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<L: Accept + fmt::Debug, F, T> fmt::Debug for Handshake<L, F, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handshake")
+            .field("listener", &self.listener)
+            .field("timeout", &self.timeout)
+            .field("handshaking", &self.handshaking.len())
+            .finish()
+    }
+}
+
+// Errors of the connection being accepted, that don't affect the next one.
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::Interrupted
+    )
+}
+
+// ===== impl AcceptStats =====
+
+impl AcceptStats {
+    /// The number of errors of single connections, which were skipped.
+    pub fn connection_errors(&self) -> u64 {
+        self.inner.connection_errors.load(Ordering::Relaxed)
+    }
+
+    /// The number of errors of the listener, such as `EMFILE`.
+    pub fn listener_errors(&self) -> u64 {
+        self.inner.listener_errors.load(Ordering::Relaxed)
+    }
+
+    /// The total time accepting paused after errors of the listener.
+    pub fn paused(&self) -> Duration {
+        Duration::from_nanos(self.inner.paused.load(Ordering::Relaxed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use super::{Acceptor, Handshake};
+
+    #[tokio::test]
+    async fn backs_off_on_listener_errors() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let seen2 = seen.clone();
+        let mut acceptor = Acceptor::new(listener)
+            .backoff(Duration::from_millis(10), Duration::from_millis(30))
+            .on_error(move |_, pause| seen2.lock().unwrap().push(pause));
+        let stats = acceptor.stats();
+
+        let emfile = io::Error::from_raw_os_error(24);
+        let aborted = io::Error::from(io::ErrorKind::ConnectionAborted);
+        assert_eq!(acceptor.handle_error(&aborted), None);
+        for _ in 0..3 {
+            acceptor.handle_error(&emfile);
+        }
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                None,
+                Some(Duration::from_millis(10)),
+                Some(Duration::from_millis(20)),
+                Some(Duration::from_millis(30)),
+            ]
+        );
+        assert_eq!(stats.connection_errors(), 1);
+        assert_eq!(stats.listener_errors(), 3);
+        assert_eq!(stats.paused(), Duration::from_millis(60));
+
+        // a connection accepted resets the backoff
+        let _client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        acceptor.accept().await;
+        assert_eq!(
+            acceptor.handle_error(&emfile),
+            Some(Duration::from_millis(10))
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn marks_traffic_class() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut acceptor = Acceptor::new(listener).traffic_class(0x88);
+
+        let _client = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = acceptor.accept().await;
+        assert_eq!(socket2::SockRef::from(&stream).tos().unwrap(), 0x88);
+    }
+
+    #[tokio::test]
+    async fn skips_failed_handshakes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handshake = Handshake::new(listener, |mut stream: tokio::net::TcpStream| async move {
+            let mut byte = [0];
+            tokio::io::AsyncReadExt::read_exact(&mut stream, &mut byte).await?;
+            match byte[0] {
+                b'y' => Ok(stream),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bad hello")),
+            }
+        });
+        let mut acceptor = Acceptor::new(handshake);
+        let stats = acceptor.stats();
+
+        let mut bad = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut bad, b"n")
+            .await
+            .unwrap();
+        let mut good = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut good, b"y")
+            .await
+            .unwrap();
+
+        let (_, peer) = acceptor.accept().await;
+        assert_eq!(peer, good.local_addr().unwrap());
+        assert_eq!(stats.connection_errors(), 1);
+    }
+}
@@ -0,0 +1,303 @@
+//! Request body timeouts.
+//!
+//! This module provides a [`RequestBodyTimeout`] service, which fails the
+//! body of a request once no data has arrived for a while, and answers
+//! `408 Request Timeout` for it, so a stalled upload doesn't hold a handler
+//! and its buffers forever.
+//!
+//! The auto connection builder hands services hyper's own body type, which
+//! it can't wrap, so this is a service to put in front of the handler
+//! rather than a builder option.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//! use std::time::Duration;
+//!
+//! use bytes::Bytes;
+//! use http::{Request, Response};
+//! use http_body_util::{BodyExt, Full};
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::server::body_timeout::{RequestBody, RequestBodyTimeout};
+//!
+//! let service = RequestBodyTimeout::new(
+//!     service_fn(|req: Request<RequestBody<Incoming>>| async move {
+//!         let upload = match req.into_body().collect().await {
+//!             Ok(body) => body.to_bytes(),
+//!             Err(_) => Bytes::new(),
+//!         };
+//!         Ok::<_, Infallible>(Response::new(Full::new(upload)))
+//!     }),
+//!     Duration::from_secs(30),
+//! );
+//! ```
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use http::header::{self, HeaderValue};
+use http::{Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use hyper::service::Service;
+use pin_project_lite::pin_project;
+
+use crate::body::IdleTimeout;
+
+/// A service failing request bodies that stop making progress.
+///
+/// Once `timeout` passes without a frame of the request body, the body
+/// yields an `io::Error` of kind `TimedOut` to the inner service. If the
+/// inner service hasn't responded yet, its future is dropped, and
+/// `408 Request Timeout` is answered with `Connection: close` instead,
+/// whatever it would have responded. The clock starts when the inner
+/// service first reads the body.
+#[derive(Clone, Debug)]
+pub struct RequestBodyTimeout<S> {
+    inner: S,
+    timeout: Duration,
+}
+
+pin_project! {
+    /// The body of a request given to the inner service of a
+    /// [`RequestBodyTimeout`].
+    pub struct RequestBody<B> {
+        #[pin]
+        inner: IdleTimeout<B>,
+        stalled: Arc<Stalled>,
+    }
+}
+
+pin_project! {
+    /// The response future of a [`RequestBodyTimeout`] service.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: Option<F>,
+        stalled: Arc<Stalled>,
+    }
+}
+
+pin_project! {
+    /// A response body, empty if the request body timed out.
+    #[derive(Debug)]
+    pub struct TimeoutBody<B> {
+        #[pin]
+        inner: Option<B>,
+    }
+}
+
+// Set by the request body once it timed out, to wake the response future.
+#[derive(Default)]
+struct Stalled(Mutex<(bool, Option<Waker>)>);
+
+// ===== impl RequestBodyTimeout =====
+
+impl<S> RequestBodyTimeout<S> {
+    /// Wrap a service, failing request bodies with no frame for `timeout`.
+    pub fn new(inner: S, timeout: Duration) -> Self {
+        RequestBodyTimeout { inner, timeout }
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RequestBodyTimeout<S>
+where
+    S: Service<Request<RequestBody<ReqBody>>, Response = Response<ResBody>>,
+{
+    type Response = Response<TimeoutBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        let stalled = Arc::new(Stalled::default());
+        let req = req.map(|body| RequestBody {
+            inner: IdleTimeout::new(body, self.timeout),
+            stalled: stalled.clone(),
+        });
+        ResponseFuture {
+            inner: Some(self.inner.call(req)),
+            stalled,
+        }
+    }
+}
+
+// ===== impl RequestBody =====
+
+impl<B> Body for RequestBody<B>
+where
+    B: Body,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Data = B::Data;
+    type Error = Box<dyn StdError + Send + Sync>;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut me = self.project();
+        let frame = me.inner.as_mut().poll_frame(cx);
+        if let Poll::Ready(Some(Err(_))) = frame {
+            if me.inner.is_timed_out() {
+                me.stalled.set();
+            }
+        }
+        frame
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> fmt::Debug for RequestBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestBody")
+            .field("timed_out", &self.inner.is_timed_out())
+            .finish()
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<TimeoutBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        let res = match this.inner.as_mut().as_pin_mut() {
+            Some(inner) => inner.poll(cx),
+            None => panic!("ResponseFuture polled after completion"),
+        };
+        if this.stalled.is_set(cx) {
+            this.inner.set(None);
+            let mut res = Response::new(TimeoutBody { inner: None });
+            *res.status_mut() = StatusCode::REQUEST_TIMEOUT;
+            res.headers_mut()
+                .insert(header::CONNECTION, HeaderValue::from_static("close"));
+            res.headers_mut()
+                .insert(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+            return Poll::Ready(Ok(res));
+        }
+        match res {
+            Poll::Ready(res) => {
+                this.inner.set(None);
+                Poll::Ready(res.map(|res| res.map(|body| TimeoutBody { inner: Some(body) })))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish()
+    }
+}
+
+// ===== impl TimeoutBody =====
+
+impl<B: Body> Body for TimeoutBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().inner.as_pin_mut() {
+            Some(inner) => inner.poll_frame(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().map_or(true, Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner
+            .as_ref()
+            .map_or_else(|| SizeHint::with_exact(0), Body::size_hint)
+    }
+}
+
+// ===== impl Stalled =====
+
+impl Stalled {
+    fn set(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.0 = true;
+        if let Some(waker) = state.1.take() {
+            waker.wake();
+        }
+    }
+
+    // Whether the body timed out, registering to be woken when it does.
+    fn is_set(&self, cx: &mut Context<'_>) -> bool {
+        let mut state = self.0.lock().unwrap();
+        if !state.0 {
+            match state.1 {
+                Some(ref waker) if waker.will_wake(cx.waker()) => {}
+                _ => state.1 = Some(cx.waker().clone()),
+            }
+        }
+        state.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use http::{header, Request, Response, StatusCode};
+    use http_body::Frame;
+    use http_body_util::{BodyExt, Full, StreamBody};
+    use hyper::service::{service_fn, Service};
+
+    use super::{RequestBody, RequestBodyTimeout};
+
+    type Upload = StreamBody<futures_util::stream::Pending<Result<Frame<Bytes>, Infallible>>>;
+
+    #[tokio::test]
+    async fn stalled_upload_gets_408() {
+        let service = RequestBodyTimeout::new(
+            service_fn(|req: Request<RequestBody<Upload>>| async move {
+                // a handler turning the body error into its own response
+                let status = match req.into_body().collect().await {
+                    Ok(_) => StatusCode::OK,
+                    Err(_) => StatusCode::BAD_REQUEST,
+                };
+                let mut res = Response::new(Full::new(Bytes::new()));
+                *res.status_mut() = status;
+                Ok::<_, Infallible>(res)
+            }),
+            Duration::from_millis(20),
+        );
+
+        let req = Request::new(StreamBody::new(futures_util::stream::pending()));
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::REQUEST_TIMEOUT);
+        assert_eq!(res.headers()[header::CONNECTION], "close");
+        assert!(res.body().inner.is_none());
+    }
+}
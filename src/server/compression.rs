@@ -0,0 +1,763 @@
+//! Compression of response bodies.
+//!
+//! This module provides a [`Compression`] service, which compresses the
+//! bodies of the responses of another service with gzip, brotli or zstd,
+//! picked from the `Accept-Encoding` header of the request.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//!
+//! use bytes::Bytes;
+//! use http::{Request, Response};
+//! use http_body_util::Full;
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::server::compression::Compression;
+//!
+//! let service = Compression::new(service_fn(|_req: Request<Incoming>| async {
+//!     Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("hello"))))
+//! }))
+//! .min_size(1024)
+//! .zstd(false);
+//! ```
+
+use std::error::Error as StdError;
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, Bytes};
+use futures_util::ready;
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use hyper::service::Service;
+use pin_project_lite::pin_project;
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+// brotli's best quality is far too slow to compress responses on the fly.
+const BROTLI_QUALITY: u32 = 4;
+const BROTLI_LGWIN: u32 = 22;
+const ZSTD_LEVEL: i32 = 3;
+
+/// A service that compresses the response bodies of another service.
+///
+/// A response is compressed when:
+///
+/// - the request accepts one of the enabled encodings,
+/// - the response has an allowed `Content-Type`, see
+///   [`content_types`](Compression::content_types),
+/// - its body isn't known to be smaller than
+///   [`min_size`](Compression::min_size), and
+/// - it isn't already encoded, or marked `Cache-Control: no-transform`, and
+/// - it isn't a range, with `206 Partial Content` or a `Content-Range`,
+///   whose bytes are those of the identity body.
+///
+/// A compressed response loses its `Content-Length` and `Accept-Ranges`
+/// headers, since they no longer describe the body. A strong `ETag` gets
+/// the encoding as a suffix, such as `"abc-gzip"`, since the bytes differ
+/// from those of the identity body. Every body frame is flushed through
+/// the encoder, so streamed responses aren't held back.
+#[derive(Clone, Debug)]
+pub struct Compression<S> {
+    inner: S,
+    config: Arc<Config>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    gzip: bool,
+    br: bool,
+    zstd: bool,
+    min_size: u64,
+    content_types: Vec<String>,
+}
+
+pin_project! {
+    /// The response future of a [`Compression`] service.
+    #[derive(Debug)]
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        encoding: Option<Encoding>,
+        config: Arc<Config>,
+    }
+}
+
+pin_project! {
+    /// A response body, compressed if the response was.
+    pub struct CompressionBody<B> {
+        #[pin]
+        inner: B,
+        encoder: Option<Encoder>,
+        trailers: Option<HeaderMap>,
+        done: bool,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Encoding {
+    Gzip,
+    Br,
+    Zstd,
+}
+
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Br(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+// ===== impl Compression =====
+
+impl<S> Compression<S> {
+    /// Wrap a service, compressing its responses.
+    ///
+    /// All encodings are enabled, the minimum size is 32 bytes, and
+    /// textual content types are compressed: `text/*`, JSON, JavaScript,
+    /// XML, SVG and WebAssembly.
+    pub fn new(inner: S) -> Self {
+        Compression {
+            inner,
+            config: Arc::new(Config {
+                gzip: true,
+                br: true,
+                zstd: true,
+                min_size: 32,
+                content_types: [
+                    "text/",
+                    "application/json",
+                    "application/javascript",
+                    "application/xml",
+                    "application/wasm",
+                    "image/svg+xml",
+                ]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+            }),
+        }
+    }
+
+    /// Set whether responses can be compressed with gzip.
+    ///
+    /// Default is `true`.
+    pub fn gzip(mut self, enabled: bool) -> Self {
+        Arc::make_mut(&mut self.config).gzip = enabled;
+        self
+    }
+
+    /// Set whether responses can be compressed with brotli.
+    ///
+    /// Default is `true`.
+    pub fn br(mut self, enabled: bool) -> Self {
+        Arc::make_mut(&mut self.config).br = enabled;
+        self
+    }
+
+    /// Set whether responses can be compressed with zstd.
+    ///
+    /// Default is `true`.
+    pub fn zstd(mut self, enabled: bool) -> Self {
+        Arc::make_mut(&mut self.config).zstd = enabled;
+        self
+    }
+
+    /// Set the size in bytes below which a response isn't compressed.
+    ///
+    /// The size is known from the `Content-Length` header or the size hint
+    /// of the body. Responses of unknown size are compressed.
+    ///
+    /// Default is 32 bytes.
+    pub fn min_size(mut self, bytes: u64) -> Self {
+        Arc::make_mut(&mut self.config).min_size = bytes;
+        self
+    }
+
+    /// Set the content types that are compressed, replacing the defaults.
+    ///
+    /// A type ending in `/`, like `text/`, allows every subtype. Parameters
+    /// of the `Content-Type`, like `charset`, are ignored. Responses
+    /// without a `Content-Type` aren't compressed.
+    pub fn content_types<I>(mut self, types: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Arc::make_mut(&mut self.config).content_types = types
+            .into_iter()
+            .map(|t| t.into().to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Compression<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Body,
+{
+    type Response = Response<CompressionBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        let encoding = if req.method() == Method::HEAD {
+            None
+        } else {
+            self.config.negotiate(req.headers())
+        };
+        ResponseFuture {
+            inner: self.inner.call(req),
+            encoding,
+            config: self.config.clone(),
+        }
+    }
+}
+
+// ===== impl Config =====
+
+impl Config {
+    fn is_enabled(&self, encoding: Encoding) -> bool {
+        match encoding {
+            Encoding::Gzip => self.gzip,
+            Encoding::Br => self.br,
+            Encoding::Zstd => self.zstd,
+        }
+    }
+
+    // Picks the enabled encoding with the highest quality, preferring
+    // brotli, then zstd, then gzip, when they tie.
+    fn negotiate(&self, headers: &HeaderMap) -> Option<Encoding> {
+        // qualities are in thousandths, as they have at most 3 decimals
+        let mut listed = [None; 3];
+        let mut any = None;
+        for value in headers.get_all(header::ACCEPT_ENCODING) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            for item in value.split(',') {
+                let mut params = item.split(';');
+                let coding = params.next().unwrap_or("").trim();
+                let quality = match params
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .map(parse_quality)
+                    .next()
+                {
+                    Some(Some(q)) => q,
+                    Some(None) => continue,
+                    None => 1000,
+                };
+                let slot = if coding == "*" {
+                    &mut any
+                } else if coding.eq_ignore_ascii_case("gzip")
+                    || coding.eq_ignore_ascii_case("x-gzip")
+                {
+                    &mut listed[0]
+                } else if coding.eq_ignore_ascii_case("br") {
+                    &mut listed[1]
+                } else if coding.eq_ignore_ascii_case("zstd") {
+                    &mut listed[2]
+                } else {
+                    continue;
+                };
+                *slot = Some(quality);
+            }
+        }
+
+        let mut best = None;
+        let mut best_quality = 0;
+        for (encoding, quality) in [
+            (Encoding::Br, listed[1]),
+            (Encoding::Zstd, listed[2]),
+            (Encoding::Gzip, listed[0]),
+        ] {
+            let quality = quality.or(any).unwrap_or(0);
+            if self.is_enabled(encoding) && quality > best_quality {
+                best = Some(encoding);
+                best_quality = quality;
+            }
+        }
+        best
+    }
+
+    fn is_compressible<B: Body>(&self, res: &Response<B>) -> bool {
+        let status = res.status();
+        if status.is_informational()
+            || status == StatusCode::NO_CONTENT
+            || status == StatusCode::NOT_MODIFIED
+            || status == StatusCode::PARTIAL_CONTENT
+        {
+            return false;
+        }
+
+        let headers = res.headers();
+        if headers.contains_key(header::CONTENT_ENCODING)
+            || headers.contains_key(header::CONTENT_RANGE)
+        {
+            return false;
+        }
+        let no_transform = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|d| d.trim().eq_ignore_ascii_case("no-transform"));
+        if no_transform {
+            return false;
+        }
+
+        let content_type = match headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(ct) => ct
+                .split(';')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_ascii_lowercase(),
+            None => return false,
+        };
+        let allowed = self.content_types.iter().any(|t| {
+            if t.ends_with('/') {
+                content_type.starts_with(t.as_str())
+            } else {
+                content_type == *t
+            }
+        });
+        if !allowed {
+            return false;
+        }
+
+        let size = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .or_else(|| res.body().size_hint().exact());
+        !matches!(size, Some(size) if size < self.min_size)
+    }
+}
+
+fn parse_quality(q: &str) -> Option<u16> {
+    let q = q.trim();
+    let (int, frac) = match q.find('.') {
+        Some(dot) => (&q[..dot], &q[dot + 1..]),
+        None => (q, ""),
+    };
+    if frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut thousandths = 0;
+    for (i, b) in frac.bytes().enumerate() {
+        thousandths += u16::from(b - b'0') * [100, 10, 1][i];
+    }
+    match int {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body,
+{
+    type Output = Result<Response<CompressionBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let res = ready!(this.inner.poll(cx))?;
+
+        if !this.config.is_compressible(&res) {
+            return Poll::Ready(Ok(res.map(CompressionBody::identity)));
+        }
+        let (mut parts, body) = res.into_parts();
+
+        let varies = parts
+            .headers
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|v| v.trim() == "*" || v.trim().eq_ignore_ascii_case("accept-encoding"));
+        if !varies {
+            parts
+                .headers
+                .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+
+        let encoder = this.encoding.and_then(|encoding| {
+            let encoder = Encoder::new(encoding).ok()?;
+            parts
+                .headers
+                .insert(header::CONTENT_ENCODING, encoding.header_value());
+            parts.headers.remove(header::CONTENT_LENGTH);
+            parts.headers.remove(header::ACCEPT_RANGES);
+            if let Some(etag) = parts
+                .headers
+                .get(header::ETAG)
+                .and_then(|etag| encoding.strong_etag(etag))
+            {
+                parts.headers.insert(header::ETAG, etag);
+            }
+            Some(encoder)
+        });
+
+        Poll::Ready(Ok(Response::from_parts(
+            parts,
+            CompressionBody {
+                inner: body,
+                encoder,
+                trailers: None,
+                done: false,
+            },
+        )))
+    }
+}
+
+// ===== impl CompressionBody =====
+
+impl<B> CompressionBody<B> {
+    fn identity(inner: B) -> Self {
+        CompressionBody {
+            inner,
+            encoder: None,
+            trailers: None,
+            done: false,
+        }
+    }
+
+    /// Whether the body is compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.encoder.is_some() || self.done
+    }
+}
+
+impl<B> Body for CompressionBody<B>
+where
+    B: Body,
+    B::Error: Into<BoxError>,
+{
+    type Data = Bytes;
+    type Error = BoxError;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        loop {
+            if let Some(trailers) = this.trailers.take() {
+                return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+            }
+            if *this.done {
+                return Poll::Ready(None);
+            }
+            let encoder = match this.encoder {
+                Some(encoder) => encoder,
+                None => {
+                    return this.inner.poll_frame(cx).map(|frame| {
+                        frame.map(|frame| {
+                            frame
+                                .map(|frame| frame.map_data(|mut d| d.copy_to_bytes(d.remaining())))
+                                .map_err(Into::into)
+                        })
+                    });
+                }
+            };
+
+            match ready!(this.inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => match frame.into_data() {
+                    Ok(data) => {
+                        let out = encoder.write(data)?;
+                        if !out.is_empty() {
+                            return Poll::Ready(Some(Ok(Frame::data(out))));
+                        }
+                        continue;
+                    }
+                    Err(frame) => {
+                        if let Ok(trailers) = frame.into_trailers() {
+                            *this.trailers = Some(trailers);
+                        }
+                    }
+                },
+                Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                None => {}
+            }
+
+            // the body ended, or trailers are next
+            *this.done = true;
+            let out = this.encoder.take().expect("encoder").finish()?;
+            if !out.is_empty() {
+                return Poll::Ready(Some(Ok(Frame::data(out))));
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        if self.encoder.is_none() && !self.done {
+            self.inner.is_end_stream()
+        } else {
+            self.done && self.trailers.is_none()
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        if self.encoder.is_none() && !self.done {
+            self.inner.size_hint()
+        } else {
+            SizeHint::default()
+        }
+    }
+}
+
+impl<B> std::fmt::Debug for CompressionBody<B> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompressionBody")
+            .field("compressed", &self.is_compressed())
+            .finish()
+    }
+}
+
+// ===== impl Encoding =====
+
+impl Encoding {
+    fn header_value(self) -> HeaderValue {
+        HeaderValue::from_static(match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Br => "br",
+            Encoding::Zstd => "zstd",
+        })
+    }
+
+    // A strong `ETag` suffixed with the encoding. Weak ones are left as
+    // they are, since the encoded body is still semantically equivalent.
+    fn strong_etag(self, etag: &HeaderValue) -> Option<HeaderValue> {
+        let tag = etag.as_bytes();
+        if tag.len() < 2 || tag[0] != b'"' || tag[tag.len() - 1] != b'"' {
+            return None;
+        }
+        let mut suffixed = tag[..tag.len() - 1].to_vec();
+        suffixed.push(b'-');
+        suffixed.extend_from_slice(self.header_value().as_bytes());
+        suffixed.push(b'"');
+        HeaderValue::from_bytes(&suffixed).ok()
+    }
+}
+
+// ===== impl Encoder =====
+
+impl Encoder {
+    fn new(encoding: Encoding) -> io::Result<Encoder> {
+        Ok(match encoding {
+            Encoding::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::default(),
+            )),
+            Encoding::Br => Encoder::Br(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                BROTLI_QUALITY,
+                BROTLI_LGWIN,
+            ))),
+            Encoding::Zstd => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?)
+            }
+        })
+    }
+
+    /// Compress and flush a chunk, returning what was output.
+    fn write<D: Buf>(&mut self, mut data: D) -> io::Result<Bytes> {
+        let writer: &mut dyn Write = match self {
+            Encoder::Gzip(e) => e,
+            Encoder::Br(e) => &mut **e,
+            Encoder::Zstd(e) => e,
+        };
+        while data.has_remaining() {
+            let chunk = data.chunk();
+            writer.write_all(chunk)?;
+            let len = chunk.len();
+            data.advance(len);
+        }
+        writer.flush()?;
+
+        let out = match self {
+            Encoder::Gzip(e) => e.get_mut(),
+            Encoder::Br(e) => e.get_mut(),
+            Encoder::Zstd(e) => e.get_mut(),
+        };
+        Ok(Bytes::from(std::mem::take(out)))
+    }
+
+    /// End the stream, returning the rest of the output.
+    fn finish(self) -> io::Result<Bytes> {
+        let out = match self {
+            Encoder::Gzip(e) => e.finish()?,
+            Encoder::Br(e) => e.into_inner(),
+            Encoder::Zstd(e) => e.finish()?,
+        };
+        Ok(Bytes::from(out))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::io::Read;
+
+    use bytes::Bytes;
+    use http::{header, HeaderMap, HeaderValue, Request, Response, StatusCode};
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::service::{service_fn, Service};
+
+    use super::{Compression, Config, Encoding};
+
+    const TEXT: &str = "hello, hello, hello, hello, hello, hello, hello, hello";
+
+    fn negotiate(accept: &str) -> Option<Encoding> {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT_ENCODING, accept.parse().unwrap());
+        let config: &Config = &Compression::new(()).config;
+        config.negotiate(&headers)
+    }
+
+    #[test]
+    fn negotiates_encoding() {
+        assert_eq!(negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("gzip, br, zstd"), Some(Encoding::Br));
+        assert_eq!(negotiate("gzip;q=1.0, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(negotiate("*;q=0.1, br;q=0"), Some(Encoding::Zstd));
+        assert_eq!(negotiate("gzip;q=0, identity"), None);
+        assert_eq!(negotiate("gzip;q=2"), None);
+    }
+
+    async fn respond(
+        service: &Compression<
+            impl Service<Request<Empty<Bytes>>, Response = Response<Full<Bytes>>, Error = Infallible>,
+        >,
+        accept: &str,
+    ) -> (Option<String>, Bytes) {
+        let req = Request::builder()
+            .header(header::ACCEPT_ENCODING, accept)
+            .body(Empty::new())
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        let encoding = res
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .map(|v| v.to_str().unwrap().to_owned());
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        (encoding, body)
+    }
+
+    #[tokio::test]
+    async fn compresses_responses() {
+        let service = Compression::new(service_fn(|_req: Request<Empty<Bytes>>| async {
+            let res = Response::builder()
+                .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+                .body(Full::new(Bytes::from(TEXT)))
+                .unwrap();
+            Ok::<_, Infallible>(res)
+        }));
+
+        let (encoding, body) = respond(&service, "gzip").await;
+        assert_eq!(encoding.as_deref(), Some("gzip"));
+        let mut text = String::new();
+        flate2::read::GzDecoder::new(&body[..])
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, TEXT);
+
+        let (encoding, body) = respond(&service, "br").await;
+        assert_eq!(encoding.as_deref(), Some("br"));
+        let mut text = String::new();
+        brotli::Decompressor::new(&body[..], 4096)
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, TEXT);
+
+        let (encoding, body) = respond(&service, "zstd").await;
+        assert_eq!(encoding.as_deref(), Some("zstd"));
+        assert_eq!(zstd::decode_all(&body[..]).unwrap(), TEXT.as_bytes());
+
+        let (encoding, body) = respond(&service, "identity").await;
+        assert_eq!(encoding, None);
+        assert_eq!(body, TEXT);
+    }
+
+    #[tokio::test]
+    async fn skips_small_and_disallowed() {
+        let service = Compression::new(service_fn(|req: Request<Empty<Bytes>>| async move {
+            let content_type = req.uri().path().trim_start_matches('/').replace('-', "/");
+            let mut res = Response::builder()
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::ETAG, "\"v1\"")
+                .body(Full::new(Bytes::from(TEXT)))
+                .unwrap();
+            match req.uri().query() {
+                Some("weak") => {
+                    res.headers_mut()
+                        .insert(header::ETAG, HeaderValue::from_static("W/\"v1\""));
+                }
+                Some("range") => {
+                    *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+                    res.headers_mut().insert(
+                        header::CONTENT_RANGE,
+                        HeaderValue::from_static("bytes 0-52/53"),
+                    );
+                }
+                _ => (),
+            }
+            Ok::<_, Infallible>(res)
+        }));
+
+        let call = |path: &'static str| {
+            let req = Request::builder()
+                .uri(path)
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Empty::new())
+                .unwrap();
+            service.call(req)
+        };
+        let res = call("/image-png").await.unwrap();
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        assert!(!res.headers().contains_key(header::VARY));
+
+        let res = call("/application-json").await.unwrap();
+        assert_eq!(res.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(res.headers()[header::VARY], "accept-encoding");
+        assert_eq!(res.headers()[header::ETAG], "\"v1-gzip\"");
+
+        let res = call("/text-plain?weak").await.unwrap();
+        assert_eq!(res.headers()[header::ETAG], "W/\"v1\"");
+
+        let res = call("/text-plain?range").await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+        assert_eq!(res.headers()[header::ETAG], "\"v1\"");
+
+        let service = service.min_size(1024);
+        let req = Request::builder()
+            .uri("/text-html")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Empty::new())
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+    }
+}
@@ -14,7 +14,7 @@ use http::{Request, Response};
 use http_body::Body;
 use hyper::{
     body::Incoming,
-    rt::{Read, ReadBuf, Timer, Write},
+    rt::{Read, ReadBuf, Sleep, Timer, Write},
     service::Service,
 };
 
@@ -24,12 +24,26 @@ use hyper::server::conn::http1;
 #[cfg(feature = "http2")]
 use hyper::{rt::bounds::Http2ServerConnExec, server::conn::http2};
 
-#[cfg(any(not(feature = "http2"), not(feature = "http1")))]
+#[cfg(any(
+    not(feature = "http2"),
+    not(feature = "http1"),
+    not(feature = "tracing")
+))]
 use std::marker::PhantomData;
 
 use pin_project_lite::pin_project;
 
+use super::layered::Layered;
+use super::limit::Limits;
+use super::stats::WithStats;
+#[cfg(feature = "http1")]
+use super::timeout::ResponseTimeout;
 use crate::common::rewind::Rewind;
+use crate::common::timer;
+use crate::server::metrics;
+
+pub use super::fair::WriteWeight;
+pub use super::stats::ConnectionStats;
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
@@ -52,6 +66,24 @@ pub trait HttpServerConnExec<A, B: Body> {}
 impl<A, B: Body, T> HttpServerConnExec<A, B> for T {}
 
 /// Http1 or Http2 connection builder.
+///
+/// # Metrics
+///
+/// With the `metrics` feature, connections record these with the
+/// [`metrics`](https://docs.rs/metrics) facade, like the legacy client:
+///
+/// - `hyper_util_server_connections_total`, a counter labeled with the
+///   `protocol`, `http1` or `http2`, and `hyper_util_server_connections_active`,
+///   a gauge,
+/// - `hyper_util_server_handshake_failures_total`, a counter labeled with the
+///   `stage`: `version` for detecting the HTTP version, and `sni` or `detect`
+///   for [`sni::peek`](crate::server::sni::peek) and
+///   [`mux::detect`](crate::server::mux::detect),
+/// - `hyper_util_server_bytes_read_total` and
+///   `hyper_util_server_bytes_written_total`, counters,
+/// - for HTTP/1, `hyper_util_server_requests_active`, a gauge, and
+///   `hyper_util_server_request_duration_seconds`, a histogram of the time
+///   until the response head, labeled with the `status_class`, such as `2xx`.
 #[derive(Clone, Debug)]
 pub struct Builder<E> {
     #[cfg(feature = "http1")]
@@ -60,6 +92,15 @@ pub struct Builder<E> {
     http2: http2::Builder<E>,
     #[cfg(any(feature = "http1", feature = "http2"))]
     version: Option<Version>,
+    sniff_buf_size: usize,
+    sniff_timeout: Option<Duration>,
+    timer: Option<timer::Timer>,
+    connection_stats: bool,
+    request_header_timeout: Option<Duration>,
+    response_header_timeout: Option<Duration>,
+    max_connection_age: Option<Duration>,
+    max_connection_bytes: Option<u64>,
+    fair_write_quantum: Option<usize>,
     #[cfg(not(feature = "http2"))]
     _executor: E,
 }
@@ -88,6 +129,15 @@ impl<E> Builder<E> {
             http2: http2::Builder::new(executor),
             #[cfg(any(feature = "http1", feature = "http2"))]
             version: None,
+            sniff_buf_size: H2_PREFACE.len(),
+            sniff_timeout: None,
+            timer: None,
+            connection_stats: false,
+            request_header_timeout: None,
+            response_header_timeout: None,
+            max_connection_age: None,
+            max_connection_bytes: None,
+            fair_write_quantum: None,
             #[cfg(not(feature = "http2"))]
             _executor: executor,
         }
@@ -115,6 +165,27 @@ impl<E> Builder<E> {
         self
     }
 
+    /// Configure the builder for serving gRPC.
+    ///
+    /// This only accepts HTTP/2, which gRPC requires, and raises the
+    /// maximum size of received headers to 16MB, the limit of other gRPC
+    /// servers, since gRPC metadata travels in headers. Other settings can
+    /// still be changed afterward.
+    ///
+    /// To serve gRPC alongside HTTP/1 routes instead, leave this out, and
+    /// turn away gRPC requests over HTTP/1 with
+    /// [`grpc::require_http2`](crate::server::grpc::require_http2).
+    ///
+    /// # Panics
+    ///
+    /// This panics if [`http1_only`](Builder::http1_only) or
+    /// [`http2_only`](Builder::http2_only) was called already.
+    #[cfg(feature = "http2")]
+    pub fn grpc_defaults(mut self) -> Self {
+        self.http2().max_header_list_size(16 * 1024 * 1024);
+        self.http2_only()
+    }
+
     /// Only accepts HTTP/1
     ///
     /// Does not do anything if used with [`serve_connection_with_upgrades`]
@@ -125,6 +196,173 @@ impl<E> Builder<E> {
         self
     }
 
+    /// Set how many bytes are read at most while detecting the HTTP
+    /// version of a connection.
+    ///
+    /// The version is known once the 24 bytes of the HTTP/2 preface have
+    /// been read, or once they don't match. A larger buffer lets the first
+    /// read take in more of the request, which is then handed to the
+    /// connection. Sizes smaller than the preface are raised to it.
+    ///
+    /// This doesn't apply with [`http1_only`](Builder::http1_only) or
+    /// [`http2_only`](Builder::http2_only), since nothing is detected.
+    ///
+    /// Default is 24 bytes.
+    pub fn sniff_buffer_size(mut self, size: usize) -> Self {
+        self.sniff_buf_size = size.max(H2_PREFACE.len());
+        self
+    }
+
+    /// Set how long a client has to send enough bytes to detect the HTTP
+    /// version of a connection.
+    ///
+    /// If the client hasn't by then, the connection fails with an
+    /// [`io::Error`] of kind [`TimedOut`](io::ErrorKind::TimedOut). This
+    /// needs a timer, set with `Http1Builder::timer` or `Http2Builder::timer`,
+    /// and does nothing without one.
+    ///
+    /// With a timeout, a client closing the connection without sending any
+    /// bytes also fails it, with an [`io::Error`] of kind
+    /// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof). Otherwise, such a
+    /// connection is closed cleanly by HTTP/1.
+    ///
+    /// This doesn't apply with [`http1_only`](Builder::http1_only) or
+    /// [`http2_only`](Builder::http2_only), since nothing is detected.
+    ///
+    /// Default is `None`.
+    pub fn sniff_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.sniff_timeout = timeout.into();
+        self
+    }
+
+    /// Set whether connections keep [`ConnectionStats`], handed to the
+    /// service in the extensions of every request.
+    ///
+    /// Default is `false`.
+    pub fn connection_stats(mut self, enabled: bool) -> Self {
+        self.connection_stats = enabled;
+        self
+    }
+
+    /// Set how long an HTTP/1 client has to send the headers of a request,
+    /// from its first byte.
+    ///
+    /// If the client hasn't by then, it is sent `408 Request Timeout` and
+    /// the connection fails with an [`io::Error`] of kind
+    /// [`TimedOut`](io::ErrorKind::TimedOut). Unlike
+    /// [`Http1Builder::header_read_timeout`], which closes the connection
+    /// without a response, the time waiting for the next request on a kept
+    /// alive connection doesn't count.
+    ///
+    /// This needs a timer, set with `Http1Builder::timer` or
+    /// `Http2Builder::timer`, and does nothing without one. It doesn't apply
+    /// to HTTP/2, whose streams are limited with
+    /// [`Http2Builder::max_concurrent_streams`] instead.
+    ///
+    /// Default is `None`.
+    pub fn request_header_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.request_header_timeout = timeout.into();
+        self
+    }
+
+    /// Set how long the service has to respond to an HTTP/1 request,
+    /// meaning to produce the response's headers.
+    ///
+    /// If it hasn't by then, the service's future is dropped and the client
+    /// is sent `503 Service Unavailable` instead, so a connection doesn't
+    /// stay stuck behind a slow handler. Streaming the response body isn't
+    /// limited.
+    ///
+    /// This needs a timer, set with `Http1Builder::timer` or
+    /// `Http2Builder::timer`, and does nothing without one. It doesn't apply
+    /// to HTTP/2, whose streams are limited with
+    /// [`Http2Builder::max_concurrent_streams`] instead.
+    ///
+    /// Default is `None`.
+    pub fn response_header_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.response_header_timeout = timeout.into();
+        self
+    }
+
+    /// Set how long a connection is served before it is shut down
+    /// gracefully.
+    ///
+    /// The connection stops taking new requests, and closes once those in
+    /// flight are answered: HTTP/1 connections close after the current
+    /// response, and HTTP/2 connections are sent a `GOAWAY`. Clients then
+    /// reconnect, which also renews the session of a TLS stream, so this
+    /// suits policies bounding how long keys are used.
+    ///
+    /// This needs a timer, set with `Http1Builder::timer` or
+    /// `Http2Builder::timer`, and does nothing without one.
+    ///
+    /// Default is `None`.
+    pub fn max_connection_age(mut self, age: impl Into<Option<Duration>>) -> Self {
+        self.max_connection_age = age.into();
+        self
+    }
+
+    /// Set how many bytes a connection reads and writes in total before it
+    /// is shut down gracefully.
+    ///
+    /// This shuts the connection down like
+    /// [`max_connection_age`](Builder::max_connection_age). A response being
+    /// written when the limit is reached is finished, so a connection may
+    /// go past it.
+    ///
+    /// Default is `None`.
+    pub fn max_connection_bytes(mut self, max: impl Into<Option<u64>>) -> Self {
+        self.max_connection_bytes = max.into();
+        self
+    }
+
+    /// Set how many bytes a connection writes before yielding to the other
+    /// tasks of the executor, so one connection streaming a large response
+    /// doesn't starve the others.
+    ///
+    /// A connection writes `quantum` times its [`WriteWeight`] before
+    /// yielding, which every request gets in its extensions to adjust the
+    /// share of its connection.
+    ///
+    /// Default is `None`, writing until the IO is full.
+    pub fn fair_write_quantum(mut self, quantum: impl Into<Option<usize>>) -> Self {
+        self.fair_write_quantum = quantum.into();
+        self
+    }
+
+    fn with_timer(&self, timeout: Option<Duration>) -> Option<(timer::Timer, Duration)> {
+        match (&self.timer, timeout) {
+            (Some(timer), Some(dur)) => Some((timer.clone(), dur)),
+            _ => None,
+        }
+    }
+
+    // The stats also count the bytes for `max_connection_bytes`.
+    fn new_stats(&self, id: u64) -> Option<ConnectionStats> {
+        if self.connection_stats || self.max_connection_bytes.is_some() {
+            Some(ConnectionStats::new(id))
+        } else {
+            None
+        }
+    }
+
+    fn new_limits(&self, stats: &Option<ConnectionStats>) -> Limits {
+        let bytes = match (stats, self.max_connection_bytes) {
+            (Some(stats), Some(max)) => Some((stats.clone(), max)),
+            _ => None,
+        };
+        Limits::new(self.with_timer(self.max_connection_age), bytes)
+    }
+
+    fn service_stats(&self, stats: &Option<ConnectionStats>) -> Option<ConnectionStats> {
+        stats.clone().filter(|_| self.connection_stats)
+    }
+
+    fn new_share(&self) -> Option<(usize, WriteWeight)> {
+        self.fair_write_quantum
+            .map(|quantum| (quantum, WriteWeight::new()))
+    }
+
     /// Bind a connection together with a [`Service`].
     pub fn serve_connection<I, S, B>(&self, io: I, service: S) -> Connection<'_, I, S, E>
     where
@@ -136,28 +374,51 @@ impl<E> Builder<E> {
         I: Read + Write + Unpin + 'static,
         E: HttpServerConnExec<S::Future, B>,
     {
+        let id = crate::common::id::next_connection_id();
+        let stats = self.new_stats(id);
+        let limits = self.new_limits(&stats);
+        let share = self.new_share();
+        let weight = share.as_ref().map(|(_, weight)| weight.clone());
+        let timeout = self.with_timer(self.request_header_timeout);
+        let io = Layered::new(io, share, stats.clone(), timeout);
+        let service = WithStats::new(service, self.service_stats(&stats), weight);
         let state = match self.version {
             #[cfg(feature = "http1")]
             Some(Version::H1) => {
+                let phase = io.phase();
                 let io = Rewind::new_buffered(io, Bytes::new());
+                let service = ResponseTimeout::new(
+                    service,
+                    self.with_timer(self.response_header_timeout),
+                    phase,
+                );
+                metrics::connection_protocol("http1");
                 let conn = self.http1.serve_connection(io, service);
                 ConnState::H1 { conn }
             }
             #[cfg(feature = "http2")]
             Some(Version::H2) => {
+                if let Some(phase) = io.phase() {
+                    phase.disable();
+                }
                 let io = Rewind::new_buffered(io, Bytes::new());
+                metrics::connection_protocol("http2");
                 let conn = self.http2.serve_connection(io, service);
                 ConnState::H2 { conn }
             }
             #[cfg(any(feature = "http1", feature = "http2"))]
             _ => ConnState::ReadVersion {
-                read_version: read_version(io),
+                read_version: read_version(io, self),
                 builder: Cow::Borrowed(self),
                 service: Some(service),
             },
         };
 
-        Connection { state }
+        Connection {
+            state,
+            span: ConnSpan::new(id),
+            limits,
+        }
     }
 
     /// Bind a connection together with a [`Service`], with the ability to
@@ -177,12 +438,22 @@ impl<E> Builder<E> {
         I: Read + Write + Unpin + Send + 'static,
         E: HttpServerConnExec<S::Future, B>,
     {
+        let id = crate::common::id::next_connection_id();
+        let stats = self.new_stats(id);
+        let limits = self.new_limits(&stats);
+        let share = self.new_share();
+        let weight = share.as_ref().map(|(_, weight)| weight.clone());
+        let timeout = self.with_timer(self.request_header_timeout);
+        let io = Layered::new(io, share, stats.clone(), timeout);
+        let service = WithStats::new(service, self.service_stats(&stats), weight);
         UpgradeableConnection {
             state: UpgradeableConnState::ReadVersion {
-                read_version: read_version(io),
+                read_version: read_version(io, self),
                 builder: Cow::Borrowed(self),
                 service: Some(service),
             },
+            span: ConnSpan::new(id),
+            limits,
         }
     }
 }
@@ -204,16 +475,54 @@ impl Version {
     }
 }
 
-fn read_version<I>(io: I) -> ReadVersion<I>
+// The span a connection is served in, with the `tracing` feature.
+#[derive(Clone, Debug)]
+struct ConnSpan(#[cfg(feature = "tracing")] tracing::Span);
+
+#[cfg(feature = "tracing")]
+type Entered<'a> = tracing::span::Entered<'a>;
+#[cfg(not(feature = "tracing"))]
+type Entered<'a> = PhantomData<&'a ()>;
+
+impl ConnSpan {
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn new(id: u64) -> ConnSpan {
+        ConnSpan(
+            #[cfg(feature = "tracing")]
+            tracing::debug_span!("serve_connection", otel.kind = "server", connection.id = id),
+        )
+    }
+
+    fn enter(&self) -> Entered<'_> {
+        #[cfg(feature = "tracing")]
+        return self.0.enter();
+        #[cfg(not(feature = "tracing"))]
+        PhantomData
+    }
+}
+
+fn version_failed(err: io::Error) -> io::Error {
+    // being cancelled, by a graceful shutdown, isn't a failure
+    if err.kind() != io::ErrorKind::Interrupted {
+        metrics::handshake_failed("version");
+    }
+    err
+}
+
+fn read_version<I, E>(io: I, builder: &Builder<E>) -> ReadVersion<I>
 where
     I: Read + Unpin,
 {
+    let timeout = builder.with_timer(builder.sniff_timeout);
     ReadVersion {
         io: Some(io),
-        buf: [MaybeUninit::uninit(); 24],
+        buf: vec![MaybeUninit::uninit(); builder.sniff_buf_size].into_boxed_slice(),
         filled: 0,
         version: Version::H2,
         cancelled: false,
+        eof_is_error: timeout.is_some(),
+        timeout,
+        sleep: None,
         _pin: PhantomPinned,
     }
 }
@@ -221,11 +530,17 @@ where
 pin_project! {
     struct ReadVersion<I> {
         io: Option<I>,
-        buf: [MaybeUninit<u8>; 24],
+        buf: Box<[MaybeUninit<u8>]>,
         // the amount of `buf` thats been filled
         filled: usize,
         version: Version,
         cancelled: bool,
+        // whether closing before sending any bytes is an error, rather than
+        // left to HTTP/1
+        eof_is_error: bool,
+        // the timer and timeout, until the sleep is started on first poll
+        timeout: Option<(timer::Timer, Duration)>,
+        sleep: Option<Pin<Box<dyn Sleep>>>,
         // Make this future `!Unpin` for compatibility with async trait methods.
         #[pin]
         _pin: PhantomPinned,
@@ -250,6 +565,18 @@ where
             return Poll::Ready(Err(io::Error::new(io::ErrorKind::Interrupted, "Cancelled")));
         }
 
+        if let Some((timer, dur)) = this.timeout.take() {
+            *this.sleep = Some(timer.sleep(dur));
+        }
+        if let Some(ref mut sleep) = this.sleep {
+            if sleep.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out detecting the HTTP version",
+                )));
+            }
+        }
+
         let mut buf = ReadBuf::uninit(&mut *this.buf);
         // SAFETY: `this.filled` tracks how many bytes have been read (and thus initialized) and
         // we're only advancing by that many.
@@ -263,10 +590,16 @@ where
             ready!(Pin::new(this.io.as_mut().unwrap()).poll_read(cx, buf.unfilled()))?;
             *this.filled = buf.filled().len();
 
+            if buf.filled().is_empty() && *this.eof_is_error {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed before sending any bytes",
+                )));
+            }
+
             // We starts as H2 and switch to H1 when we don't get the preface.
-            if buf.filled().len() == len
-                || buf.filled()[len..] != H2_PREFACE[len..buf.filled().len()]
-            {
+            let end = buf.filled().len().min(H2_PREFACE.len());
+            if buf.filled().len() == len || buf.filled()[len..end] != H2_PREFACE[len..end] {
                 *this.version = Version::H1;
                 break;
             }
@@ -289,6 +622,8 @@ pin_project! {
     {
         #[pin]
         state: ConnState<'a, I, S, E>,
+        span: ConnSpan,
+        limits: Limits,
     }
 }
 
@@ -309,13 +644,15 @@ impl<'a, T> std::ops::Deref for Cow<'a, T> {
 }
 
 #[cfg(feature = "http1")]
-type Http1Connection<I, S> = hyper::server::conn::http1::Connection<Rewind<I>, S>;
+type Http1Connection<I, S> =
+    hyper::server::conn::http1::Connection<Rewind<Layered<I>>, ResponseTimeout<WithStats<S>>>;
 
 #[cfg(not(feature = "http1"))]
 type Http1Connection<I, S> = (PhantomData<I>, PhantomData<S>);
 
 #[cfg(feature = "http2")]
-type Http2Connection<I, S, E> = hyper::server::conn::http2::Connection<Rewind<I>, S, E>;
+type Http2Connection<I, S, E> =
+    hyper::server::conn::http2::Connection<Rewind<Layered<I>>, WithStats<S>, E>;
 
 #[cfg(not(feature = "http2"))]
 type Http2Connection<I, S, E> = (PhantomData<I>, PhantomData<S>, PhantomData<E>);
@@ -328,9 +665,9 @@ pin_project! {
     {
         ReadVersion {
             #[pin]
-            read_version: ReadVersion<I>,
+            read_version: ReadVersion<Layered<I>>,
             builder: Cow<'a, Builder<E>>,
-            service: Option<S>,
+            service: Option<WithStats<S>>,
         },
         H1 {
             #[pin]
@@ -395,6 +732,8 @@ where
                 #[cfg(any(not(feature = "http1"), not(feature = "http2")))]
                 _ => unreachable!(),
             },
+            span: self.span,
+            limits: self.limits,
         }
     }
 }
@@ -412,6 +751,8 @@ where
     type Output = Result<()>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let span = self.span.clone();
+        let _entered = span.enter();
         loop {
             let mut this = self.as_mut().project();
 
@@ -421,16 +762,26 @@ where
                     builder,
                     service,
                 } => {
-                    let (version, io) = ready!(read_version.poll(cx))?;
+                    let (version, io) = ready!(read_version.poll(cx)).map_err(version_failed)?;
                     let service = service.take().unwrap();
                     match version {
                         #[cfg(feature = "http1")]
                         Version::H1 => {
+                            let service = ResponseTimeout::new(
+                                service,
+                                builder.with_timer(builder.response_header_timeout),
+                                io.get_ref().phase(),
+                            );
+                            metrics::connection_protocol("http1");
                             let conn = builder.http1.serve_connection(io, service);
                             this.state.set(ConnState::H1 { conn });
                         }
                         #[cfg(feature = "http2")]
                         Version::H2 => {
+                            if let Some(phase) = io.get_ref().phase() {
+                                phase.disable();
+                            }
+                            metrics::connection_protocol("http2");
                             let conn = builder.http2.serve_connection(io, service);
                             this.state.set(ConnState::H2 { conn });
                         }
@@ -439,12 +790,22 @@ where
                     }
                 }
                 #[cfg(feature = "http1")]
-                ConnStateProj::H1 { conn } => {
-                    return conn.poll(cx).map_err(Into::into);
+                ConnStateProj::H1 { mut conn } => {
+                    let res = conn.as_mut().poll(cx).map_err(Into::into);
+                    if res.is_pending() && this.limits.poll_reached(cx) {
+                        conn.graceful_shutdown();
+                        continue;
+                    }
+                    return res;
                 }
                 #[cfg(feature = "http2")]
-                ConnStateProj::H2 { conn } => {
-                    return conn.poll(cx).map_err(Into::into);
+                ConnStateProj::H2 { mut conn } => {
+                    let res = conn.as_mut().poll(cx).map_err(Into::into);
+                    if res.is_pending() && this.limits.poll_reached(cx) {
+                        conn.graceful_shutdown();
+                        continue;
+                    }
+                    return res;
                 }
                 #[cfg(any(not(feature = "http1"), not(feature = "http2")))]
                 _ => unreachable!(),
@@ -461,11 +822,16 @@ pin_project! {
     {
         #[pin]
         state: UpgradeableConnState<'a, I, S, E>,
+        span: ConnSpan,
+        limits: Limits,
     }
 }
 
 #[cfg(feature = "http1")]
-type Http1UpgradeableConnection<I, S> = hyper::server::conn::http1::UpgradeableConnection<I, S>;
+type Http1UpgradeableConnection<I, S> = hyper::server::conn::http1::UpgradeableConnection<
+    Rewind<Layered<I>>,
+    ResponseTimeout<WithStats<S>>,
+>;
 
 #[cfg(not(feature = "http1"))]
 type Http1UpgradeableConnection<I, S> = (PhantomData<I>, PhantomData<S>);
@@ -478,13 +844,13 @@ pin_project! {
     {
         ReadVersion {
             #[pin]
-            read_version: ReadVersion<I>,
+            read_version: ReadVersion<Layered<I>>,
             builder: Cow<'a, Builder<E>>,
-            service: Option<S>,
+            service: Option<WithStats<S>>,
         },
         H1 {
             #[pin]
-            conn: Http1UpgradeableConnection<Rewind<I>, S>,
+            conn: Http1UpgradeableConnection<I, S>,
         },
         H2 {
             #[pin]
@@ -545,6 +911,8 @@ where
                 #[cfg(any(not(feature = "http1"), not(feature = "http2")))]
                 _ => unreachable!(),
             },
+            span: self.span,
+            limits: self.limits,
         }
     }
 }
@@ -562,6 +930,8 @@ where
     type Output = Result<()>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let span = self.span.clone();
+        let _entered = span.enter();
         loop {
             let mut this = self.as_mut().project();
 
@@ -571,16 +941,26 @@ where
                     builder,
                     service,
                 } => {
-                    let (version, io) = ready!(read_version.poll(cx))?;
+                    let (version, io) = ready!(read_version.poll(cx)).map_err(version_failed)?;
                     let service = service.take().unwrap();
                     match version {
                         #[cfg(feature = "http1")]
                         Version::H1 => {
+                            let service = ResponseTimeout::new(
+                                service,
+                                builder.with_timer(builder.response_header_timeout),
+                                io.get_ref().phase(),
+                            );
+                            metrics::connection_protocol("http1");
                             let conn = builder.http1.serve_connection(io, service).with_upgrades();
                             this.state.set(UpgradeableConnState::H1 { conn });
                         }
                         #[cfg(feature = "http2")]
                         Version::H2 => {
+                            if let Some(phase) = io.get_ref().phase() {
+                                phase.disable();
+                            }
+                            metrics::connection_protocol("http2");
                             let conn = builder.http2.serve_connection(io, service);
                             this.state.set(UpgradeableConnState::H2 { conn });
                         }
@@ -589,12 +969,22 @@ where
                     }
                 }
                 #[cfg(feature = "http1")]
-                UpgradeableConnStateProj::H1 { conn } => {
-                    return conn.poll(cx).map_err(Into::into);
+                UpgradeableConnStateProj::H1 { mut conn } => {
+                    let res = conn.as_mut().poll(cx).map_err(Into::into);
+                    if res.is_pending() && this.limits.poll_reached(cx) {
+                        conn.graceful_shutdown();
+                        continue;
+                    }
+                    return res;
                 }
                 #[cfg(feature = "http2")]
-                UpgradeableConnStateProj::H2 { conn } => {
-                    return conn.poll(cx).map_err(Into::into);
+                UpgradeableConnStateProj::H2 { mut conn } => {
+                    let res = conn.as_mut().poll(cx).map_err(Into::into);
+                    if res.is_pending() && this.limits.poll_reached(cx) {
+                        conn.graceful_shutdown();
+                        continue;
+                    }
+                    return res;
                 }
                 #[cfg(any(not(feature = "http1"), not(feature = "http2")))]
                 _ => unreachable!(),
@@ -715,6 +1105,10 @@ impl<E> Http1Builder<'_, E> {
 
     /// Set the maximum buffer size for the connection.
     ///
+    /// This bounds both the read buffer and how much of a response is
+    /// buffered before it's written, so it's also the HTTP/1 counterpart of
+    /// [`Http2Builder::max_send_buf_size`].
+    ///
     /// Default is ~400kb.
     ///
     /// # Panics
@@ -740,6 +1134,8 @@ impl<E> Http1Builder<'_, E> {
     where
         M: Timer + Send + Sync + 'static,
     {
+        let timer = timer::Timer::new(timer);
+        self.inner.timer = Some(timer.clone());
         self.inner.http1.timer(timer);
         self
     }
@@ -846,8 +1242,8 @@ impl<E> Http2Builder<'_, E> {
     /// Sets whether to use an adaptive flow control.
     ///
     /// Enabling this will override the limits set in
-    /// `http2_initial_stream_window_size` and
-    /// `http2_initial_connection_window_size`.
+    /// [`initial_stream_window_size`](Http2Builder::initial_stream_window_size) and
+    /// [`initial_connection_window_size`](Http2Builder::initial_connection_window_size).
     pub fn adaptive_window(&mut self, enabled: bool) -> &mut Self {
         self.inner.http2.adaptive_window(enabled);
         self
@@ -935,6 +1331,8 @@ impl<E> Http2Builder<'_, E> {
     where
         M: Timer + Send + Sync + 'static,
     {
+        let timer = timer::Timer::new(timer);
+        self.inner.timer = Some(timer.clone());
         self.inner.http2.timer(timer);
         self
     }
@@ -974,6 +1372,114 @@ impl<E> Http2Builder<'_, E> {
     }
 }
 
+/// A [`Service`] serving HTTP/1 and HTTP/2 connections with different
+/// services.
+///
+/// Requests on an HTTP/2 connection go to the `h2` service, and any other
+/// to the `h1` one. The version of a request is that of its connection, so
+/// this splits traffic like gRPC and REST on one port by protocol, without
+/// looking at paths or headers.
+///
+/// # Example
+///
+/// ```
+/// # use std::convert::Infallible;
+/// # use http_body_util::Full;
+/// # use hyper::{body::{Bytes, Incoming}, service::service_fn, Request, Response};
+/// use hyper_util::server::conn::auto::ByProtocol;
+///
+/// async fn rest(_: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+///     Ok(Response::new(Full::from("rest")))
+/// }
+///
+/// async fn grpc(_: Request<Incoming>) -> Result<Response<Full<Bytes>>, Infallible> {
+///     Ok(Response::new(Full::from("grpc")))
+/// }
+///
+/// let service = ByProtocol::new(service_fn(rest), service_fn(grpc));
+/// // builder.serve_connection(io, service)
+/// # drop(service);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ByProtocol<H1, H2> {
+    h1: H1,
+    h2: H2,
+}
+
+impl<H1, H2> ByProtocol<H1, H2> {
+    /// Serve HTTP/1 requests with `h1`, and HTTP/2 requests with `h2`.
+    pub fn new(h1: H1, h2: H2) -> Self {
+        ByProtocol { h1, h2 }
+    }
+}
+
+impl<H1, H2, B> Service<Request<Incoming>> for ByProtocol<H1, H2>
+where
+    H1: Service<Request<Incoming>, Response = Response<B>>,
+    H1::Error: Into<Box<dyn StdError + Send + Sync>>,
+    H2: Service<Request<Incoming>, Response = Response<B>>,
+    H2::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Response = Response<B>;
+    type Error = Error;
+    type Future = ByProtocolFuture<H1::Future, H2::Future>;
+
+    fn call(&self, req: Request<Incoming>) -> Self::Future {
+        if req.version() == http::Version::HTTP_2 {
+            ByProtocolFuture {
+                inner: ByProtocolInner::H2 {
+                    future: self.h2.call(req),
+                },
+            }
+        } else {
+            ByProtocolFuture {
+                inner: ByProtocolInner::H1 {
+                    future: self.h1.call(req),
+                },
+            }
+        }
+    }
+}
+
+pin_project! {
+    /// The response future of [`ByProtocol`].
+    pub struct ByProtocolFuture<F1, F2> {
+        #[pin]
+        inner: ByProtocolInner<F1, F2>,
+    }
+}
+
+pin_project! {
+    #[project = ByProtocolInnerProj]
+    enum ByProtocolInner<F1, F2> {
+        H1 {
+            #[pin]
+            future: F1,
+        },
+        H2 {
+            #[pin]
+            future: F2,
+        },
+    }
+}
+
+impl<F1, F2, T, E1, E2> Future for ByProtocolFuture<F1, F2>
+where
+    F1: Future<Output = std::result::Result<T, E1>>,
+    E1: Into<Box<dyn StdError + Send + Sync>>,
+    F2: Future<Output = std::result::Result<T, E2>>,
+    E2: Into<Box<dyn StdError + Send + Sync>>,
+{
+    type Output = Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().inner.project() {
+            ByProtocolInnerProj::H1 { future } => future.poll(cx).map_err(Into::into),
+            ByProtocolInnerProj::H2 { future } => future.poll(cx).map_err(Into::into),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
@@ -1010,6 +1516,39 @@ mod tests {
         // builder.serve_connection(io, service);
     }
 
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn grpc_defaults() {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new()).grpc_defaults();
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let builder = builder.clone();
+                tokio::spawn(async move {
+                    let _ = builder
+                        .serve_connection(TokioIo::new(stream), service_fn(hello))
+                        .await;
+                });
+            }
+        });
+
+        // metadata larger than the default limit of 16KB
+        let request = Request::builder()
+            .header("x-large-metadata", "a".repeat(32 * 1024))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let response = connect_h2(addr).await.send_request(request).await;
+        assert!(response.unwrap().status().is_success());
+
+        let request = Request::new(Empty::<Bytes>::new());
+        let response = connect_h1(addr).await.send_request(request).await;
+        assert!(response.is_err());
+    }
+
     #[cfg(not(miri))]
     #[tokio::test]
     async fn http1() {
@@ -1098,6 +1637,46 @@ mod tests {
             .expect_err("should fail");
     }
 
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn http2_flow_control_large_response() {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let body = Bytes::from(vec![b'x'; 4 * 1024 * 1024]);
+        let response_body = body.clone();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut builder = auto::Builder::new(TokioExecutor::new());
+            builder
+                .http2()
+                .initial_stream_window_size(1024 * 1024)
+                .initial_connection_window_size(2 * 1024 * 1024)
+                .adaptive_window(true)
+                .max_send_buf_size(256 * 1024)
+                .http1()
+                .max_buf_size(64 * 1024);
+            let service = service_fn(move |_req| {
+                let body = response_body.clone();
+                async move { Ok::<_, Infallible>(Response::new(Full::new(body))) }
+            });
+            builder
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+                .unwrap();
+        });
+
+        let mut sender = connect_h2(addr).await;
+        let response = sender
+            .send_request(Request::new(Empty::<Bytes>::new()))
+            .await
+            .unwrap();
+        let received = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(received, body);
+    }
+
     #[cfg(not(miri))]
     #[tokio::test]
     async fn graceful_shutdown() {
@@ -1132,6 +1711,321 @@ mod tests {
         assert_eq!(connection_error.kind(), std::io::ErrorKind::Interrupted);
     }
 
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn sniff_errors() {
+        use crate::rt::TokioTimer;
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut builder = auto::Builder::new(TokioExecutor::new())
+            .sniff_buffer_size(4096)
+            .sniff_timeout(Duration::from_millis(50));
+        builder.http1().timer(TokioTimer::new());
+
+        // connect, and send nothing
+        let _idle = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let err = builder
+            .serve_connection(TokioIo::new(stream), service_fn(hello))
+            .await
+            .unwrap_err();
+        let err = err.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        // connect, and close without sending anything
+        drop(TcpStream::connect(addr).await.unwrap());
+        let (stream, _) = listener.accept().await.unwrap();
+        let err = builder
+            .serve_connection(TokioIo::new(stream), service_fn(hello))
+            .await
+            .unwrap_err();
+        let err = err.downcast_ref::<std::io::Error>().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        // without a timeout, that closes cleanly
+        drop(TcpStream::connect(addr).await.unwrap());
+        let (stream, _) = listener.accept().await.unwrap();
+        auto::Builder::new(TokioExecutor::new())
+            .serve_connection(TokioIo::new(stream), service_fn(hello))
+            .await
+            .unwrap();
+
+        // a larger buffer still detects both versions
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let builder = builder.clone();
+                tokio::spawn(async move {
+                    builder
+                        .serve_connection(TokioIo::new(stream), service_fn(hello))
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+        for h2 in [false, true] {
+            let request = Request::new(Empty::<Bytes>::new());
+            let response = if h2 {
+                connect_h2(addr).await.send_request(request).await
+            } else {
+                connect_h1(addr).await.send_request(request).await
+            };
+            let body = response.unwrap().into_body().collect().await.unwrap();
+            assert_eq!(body.to_bytes(), BODY);
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn connection_stats() {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new()).connection_stats(true);
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let builder = builder.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(|req: Request<body::Incoming>| async move {
+                        let stats = req.extensions().get::<auto::ConnectionStats>().unwrap();
+                        assert!(stats.bytes_read() > 0);
+                        let body = stats.requests().to_string();
+                        let mut res = Response::new(Full::new(Bytes::from(body)));
+                        res.headers_mut()
+                            .insert("x-connection-id", stats.id().into());
+                        Ok::<_, Infallible>(res)
+                    });
+                    builder
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+
+        let mut ids = Vec::new();
+        let mut body = |res: hyper::Result<Response<body::Incoming>>| {
+            let res = res.unwrap();
+            ids.push(res.headers()["x-connection-id"].clone());
+            async { res.into_body().collect().await.unwrap().to_bytes() }
+        };
+
+        let mut h1 = connect_h1(addr).await;
+        for n in ["1", "2"] {
+            let res = h1.send_request(Request::new(Empty::<Bytes>::new())).await;
+            assert_eq!(body(res).await, n);
+        }
+
+        let mut h2 = connect_h2(addr).await;
+        for n in ["1", "2"] {
+            let res = h2.send_request(Request::new(Empty::<Bytes>::new())).await;
+            assert_eq!(body(res).await, n);
+        }
+
+        // one per connection
+        assert_eq!(ids[0], ids[1]);
+        assert_eq!(ids[2], ids[3]);
+        assert_ne!(ids[0], ids[2]);
+    }
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn by_protocol() {
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let builder = auto::Builder::new(TokioExecutor::new());
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let builder = builder.clone();
+                tokio::spawn(async move {
+                    let reply = |body: &'static str| {
+                        service_fn(move |_: Request<body::Incoming>| async move {
+                            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(body))))
+                        })
+                    };
+                    let service = auto::ByProtocol::new(reply("h1"), reply("h2"));
+                    builder
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+
+        for h2 in [false, true] {
+            let request = Request::new(Empty::<Bytes>::new());
+            let response = if h2 {
+                connect_h2(addr).await.send_request(request).await
+            } else {
+                connect_h1(addr).await.send_request(request).await
+            };
+            let body = response.unwrap().into_body().collect().await.unwrap();
+            assert_eq!(body.to_bytes(), if h2 { "h2" } else { "h1" });
+        }
+    }
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn header_timeouts() {
+        use crate::rt::TokioTimer;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut builder = auto::Builder::new(TokioExecutor::new())
+            .request_header_timeout(Duration::from_millis(50))
+            .response_header_timeout(Duration::from_millis(50));
+        builder.http1().timer(TokioTimer::new());
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let builder = builder.clone();
+                tokio::spawn(async move {
+                    let service = service_fn(|req: Request<body::Incoming>| async move {
+                        if req.uri().path() == "/slow" {
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                        }
+                        hello(req).await
+                    });
+                    let _ = builder
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        // the headers never finish
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await.unwrap();
+        assert!(buf.starts_with(b"HTTP/1.1 408 "));
+
+        // waiting between requests doesn't count
+        let mut h1 = connect_h1(addr).await;
+        let response = h1.send_request(Request::new(Empty::<Bytes>::new())).await;
+        assert!(response.unwrap().status().is_success());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let request = Request::get("/slow").body(Empty::<Bytes>::new()).unwrap();
+        let response = h1.send_request(request).await.unwrap();
+        assert_eq!(response.status(), http::StatusCode::SERVICE_UNAVAILABLE);
+
+        // it doesn't apply to HTTP/2
+        let request = Request::get("/slow").body(Empty::<Bytes>::new()).unwrap();
+        let response = tokio::time::timeout(
+            Duration::from_millis(200),
+            connect_h2(addr).await.send_request(request),
+        )
+        .await;
+        assert!(response.is_err());
+    }
+
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn connection_limits() {
+        use crate::rt::TokioTimer;
+
+        async fn serve(mut builder: auto::Builder<TokioExecutor>) -> SocketAddr {
+            builder.http1().timer(TokioTimer::new());
+            let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .await
+                .unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let builder = builder.clone();
+                    tokio::spawn(async move {
+                        builder
+                            .serve_connection(TokioIo::new(stream), service_fn(hello))
+                            .await
+                            .unwrap();
+                    });
+                }
+            });
+            addr
+        }
+
+        // the first response is finished, then the connection closes
+        let builder = auto::Builder::new(TokioExecutor::new()).max_connection_bytes(1);
+        let mut h1 = connect_h1(serve(builder).await).await;
+        let response = h1.send_request(Request::new(Empty::<Bytes>::new())).await;
+        let body = response.unwrap().into_body().collect().await.unwrap();
+        assert_eq!(body.to_bytes(), BODY);
+        tokio::time::timeout(Duration::from_millis(500), h1.ready())
+            .await
+            .expect("connection should close")
+            .unwrap_err();
+
+        // an idle connection closes once too old
+        let builder =
+            auto::Builder::new(TokioExecutor::new()).max_connection_age(Duration::from_millis(50));
+        let mut h2 = connect_h2::<Empty<Bytes>>(serve(builder).await).await;
+        let response = h2.send_request(Request::new(Empty::new())).await;
+        assert!(response.unwrap().status().is_success());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(h2.ready().await.is_err());
+    }
+
+    #[cfg(all(not(miri), feature = "metrics"))]
+    #[tokio::test]
+    async fn records_metrics() {
+        use crate::rt::TokioTimer;
+
+        let recorded = crate::common::recorder::install();
+        let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        // with a sniff timeout, closing before any bytes fails the handshake
+        let mut builder =
+            auto::Builder::new(TokioExecutor::new()).sniff_timeout(Duration::from_secs(5));
+        builder.http1().timer(TokioTimer::new());
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let builder = builder.clone();
+                tokio::spawn(async move {
+                    let _ = builder
+                        .serve_connection(TokioIo::new(stream), service_fn(hello))
+                        .await;
+                });
+            }
+        });
+
+        let mut h1 = connect_h1(addr).await;
+        let response = h1.send_request(Request::new(Empty::<Bytes>::new())).await;
+        response.unwrap().into_body().collect().await.unwrap();
+        let mut h2 = connect_h2(addr).await;
+        let response = h2.send_request(Request::new(Empty::<Bytes>::new())).await;
+        response.unwrap().into_body().collect().await.unwrap();
+        drop(TcpStream::connect(addr).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        for key in [
+            "hyper_util_server_connections_total{protocol=http1}",
+            "hyper_util_server_connections_total{protocol=http2}",
+            "hyper_util_server_connections_active{}",
+            "hyper_util_server_handshake_failures_total{stage=version}",
+            "hyper_util_server_requests_active{}",
+            "hyper_util_server_request_duration_seconds{status_class=2xx}",
+            "hyper_util_server_bytes_read_total{}",
+            "hyper_util_server_bytes_written_total{}",
+        ] {
+            assert!(recorded.contains(key), "{} missing", key);
+        }
+    }
+
     async fn connect_h1<B>(addr: SocketAddr) -> client::conn::http1::SendRequest<B>
     where
         B: Body + Send + 'static,
@@ -0,0 +1,207 @@
+//! Fair writes of the connections of the auto connection driver.
+
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use hyper::rt::{Read, ReadBufCursor, Write};
+
+/// The share of writes of the connection a request arrived on.
+///
+/// With
+/// [`Builder::fair_write_quantum`](super::auto::Builder::fair_write_quantum)
+/// set, every request gets a `WriteWeight` in its extensions. A connection
+/// writes `quantum × weight` bytes before yielding to the other tasks of
+/// the executor, so raising the weight of a connection gives it a larger
+/// share of the bandwidth when connections compete. Clones share the
+/// weight, which applies to the whole connection from when it is set.
+///
+/// The weight starts at 1.
+///
+/// # Example
+///
+/// ```
+/// use http::Request;
+/// use hyper_util::server::conn::auto::WriteWeight;
+///
+/// fn prioritize<B>(req: &Request<B>) {
+///     if req.uri().path().starts_with("/live/") {
+///         if let Some(weight) = req.extensions().get::<WriteWeight>() {
+///             weight.set(4);
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct WriteWeight {
+    weight: Arc<AtomicU32>,
+}
+
+// ===== impl WriteWeight =====
+
+impl WriteWeight {
+    pub(super) fn new() -> WriteWeight {
+        WriteWeight {
+            weight: Arc::new(AtomicU32::new(1)),
+        }
+    }
+
+    /// The weight of the connection.
+    pub fn get(&self) -> u32 {
+        self.weight.load(Ordering::Relaxed)
+    }
+
+    /// Set the weight of the connection, at least 1.
+    pub fn set(&self, weight: u32) {
+        self.weight.store(weight.max(1), Ordering::Relaxed);
+    }
+}
+
+impl fmt::Debug for WriteWeight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("WriteWeight").field(&self.get()).finish()
+    }
+}
+
+// An IO yielding to the executor after writing its share of bytes, with
+// `Builder::fair_write_quantum`.
+//
+// Writing into a fast socket rarely returns `Pending`, so a connection
+// streaming a large response would otherwise keep its task, and the
+// connections polled by it, running until the response is done.
+pub(super) struct Fair<I> {
+    inner: I,
+    // the quantum and the weight, when enabled
+    share: Option<(usize, WriteWeight)>,
+    // written since the task last yielded
+    written: usize,
+}
+
+impl<I> Fair<I> {
+    pub(super) fn new(inner: I, share: Option<(usize, WriteWeight)>) -> Fair<I> {
+        Fair {
+            inner,
+            share,
+            written: 0,
+        }
+    }
+
+    // Yields once the share is written, waking the task to go on later.
+    fn poll_share(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let share = match self.share {
+            Some((quantum, ref weight)) => quantum.saturating_mul(weight.get() as usize),
+            None => return Poll::Ready(()),
+        };
+        if self.written < share {
+            return Poll::Ready(());
+        }
+        self.written = 0;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+
+    fn wrote(&mut self, res: Poll<io::Result<usize>>) -> Poll<io::Result<usize>> {
+        match res {
+            Poll::Ready(Ok(n)) => self.written += n,
+            // the task yields anyway
+            Poll::Pending => self.written = 0,
+            Poll::Ready(Err(_)) => (),
+        }
+        res
+    }
+}
+
+impl<I: fmt::Debug> fmt::Debug for Fair<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fair")
+            .field("inner", &self.inner)
+            .field("share", &self.share)
+            .finish()
+    }
+}
+
+impl<I> Read for Fair<I>
+where
+    I: Read + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if res.is_pending() {
+            self.written = 0;
+        }
+        res
+    }
+}
+
+impl<I> Write for Fair<I>
+where
+    I: Write + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        futures_util::ready!(self.poll_share(cx));
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.wrote(res)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        futures_util::ready!(self.poll_share(cx));
+        let res = Pin::new(&mut self.inner).poll_write_vectored(cx, bufs);
+        self.wrote(res)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tests {
+    use std::pin::Pin;
+    use std::task::Context;
+
+    use futures_util::task::noop_waker_ref;
+    use hyper::rt::Write;
+
+    use super::{Fair, WriteWeight};
+    use crate::rt::TokioIo;
+
+    #[test]
+    fn yields_after_weighted_share() {
+        let weight = WriteWeight::new();
+        let mut io = Fair::new(TokioIo::new(Vec::new()), Some((4, weight.clone())));
+        let mut cx = Context::from_waker(noop_waker_ref());
+        let mut write = |io: &mut Fair<_>| Pin::new(io).poll_write(&mut cx, b"abcd").is_ready();
+
+        assert!(write(&mut io));
+        assert!(!write(&mut io), "share written");
+        assert!(write(&mut io));
+
+        weight.set(2);
+        assert!(write(&mut io));
+        assert!(!write(&mut io), "share written");
+        assert_eq!(io.inner.inner().len(), 12);
+    }
+}
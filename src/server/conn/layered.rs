@@ -0,0 +1,109 @@
+//! The IO of a connection of the auto connection driver.
+
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::rt::{Read, ReadBufCursor, Write};
+
+use super::fair::{Fair, WriteWeight};
+use super::stats::{ConnectionStats, Counted};
+use super::timeout::{HeaderTimeout, Phase};
+use crate::common::timer;
+use crate::server::metrics;
+
+// An IO with the fair writes, the counting, and the header timeout of the
+// builder, or the plain IO when none of them is enabled.
+pub(super) enum Layered<I> {
+    Plain(I),
+    Layers(HeaderTimeout<Counted<Fair<I>>>),
+}
+
+impl<I> Layered<I> {
+    pub(super) fn new(
+        inner: I,
+        share: Option<(usize, WriteWeight)>,
+        stats: Option<ConnectionStats>,
+        timeout: Option<(timer::Timer, Duration)>,
+    ) -> Layered<I> {
+        if share.is_none() && stats.is_none() && timeout.is_none() && !metrics::ENABLED {
+            return Layered::Plain(inner);
+        }
+        Layered::Layers(HeaderTimeout::new(
+            Counted::new(Fair::new(inner, share), stats),
+            timeout,
+        ))
+    }
+
+    pub(super) fn phase(&self) -> Option<Phase> {
+        match self {
+            Layered::Plain(_) => None,
+            Layered::Layers(io) => io.phase(),
+        }
+    }
+}
+
+impl<I> Read for Layered<I>
+where
+    I: Read + Write + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Layered::Plain(io) => Pin::new(io).poll_read(cx, buf),
+            Layered::Layers(io) => Pin::new(io).poll_read(cx, buf),
+        }
+    }
+}
+
+impl<I> Write for Layered<I>
+where
+    I: Write + Unpin,
+{
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Layered::Plain(io) => Pin::new(io).poll_write(cx, buf),
+            Layered::Layers(io) => Pin::new(io).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Layered::Plain(io) => Pin::new(io).poll_write_vectored(cx, bufs),
+            Layered::Layers(io) => Pin::new(io).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Layered::Plain(io) => io.is_write_vectored(),
+            Layered::Layers(io) => io.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Layered::Plain(io) => Pin::new(io).poll_flush(cx),
+            Layered::Layers(io) => Pin::new(io).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Layered::Plain(io) => Pin::new(io).poll_shutdown(cx),
+            Layered::Layers(io) => Pin::new(io).poll_shutdown(cx),
+        }
+    }
+}
@@ -0,0 +1,66 @@
+//! Lifetime limits of the connections of the auto connection driver.
+
+use std::fmt;
+use std::pin::Pin;
+use std::task::Context;
+use std::time::Duration;
+
+use hyper::rt::{Sleep, Timer};
+
+use super::stats::ConnectionStats;
+use crate::common::timer;
+
+// Tells a connection to shut down gracefully once it is too old, or has
+// transferred too many bytes.
+pub(super) struct Limits {
+    // the timer and maximum age, until the sleep is started on first poll
+    age: Option<(timer::Timer, Duration)>,
+    sleep: Option<Pin<Box<dyn Sleep>>>,
+    bytes: Option<(ConnectionStats, u64)>,
+    reached: bool,
+}
+
+impl Limits {
+    pub(super) fn new(
+        age: Option<(timer::Timer, Duration)>,
+        bytes: Option<(ConnectionStats, u64)>,
+    ) -> Limits {
+        Limits {
+            age,
+            sleep: None,
+            bytes,
+            reached: false,
+        }
+    }
+
+    // Returns true the first time a limit is reached.
+    pub(super) fn poll_reached(&mut self, cx: &mut Context<'_>) -> bool {
+        if self.reached {
+            return false;
+        }
+        if let Some((timer, age)) = self.age.take() {
+            self.sleep = Some(timer.sleep(age));
+        }
+        let too_old = match self.sleep {
+            Some(ref mut sleep) => sleep.as_mut().poll(cx).is_ready(),
+            None => false,
+        };
+        let too_busy = match self.bytes {
+            Some((ref stats, max)) => stats.bytes_read() + stats.bytes_written() >= max,
+            None => false,
+        };
+        if too_old || too_busy {
+            self.reached = true;
+            self.sleep = None;
+        }
+        self.reached
+    }
+}
+
+impl fmt::Debug for Limits {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Limits")
+            .field("reached", &self.reached)
+            .finish()
+    }
+}
@@ -2,3 +2,18 @@
 
 #[cfg(any(feature = "http1", feature = "http2"))]
 pub mod auto;
+
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod fair;
+
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod layered;
+
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod limit;
+
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod stats;
+
+#[cfg(any(feature = "http1", feature = "http2"))]
+mod timeout;
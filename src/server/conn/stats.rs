@@ -0,0 +1,266 @@
+//! Per-connection statistics of the auto connection driver.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use http::{Request, Response};
+use hyper::body::Incoming;
+use hyper::rt::{Read, ReadBuf, ReadBufCursor, Write};
+use hyper::service::{HttpService, Service};
+
+use super::fair::WriteWeight;
+use crate::server::metrics;
+
+/// Statistics of the connection a request arrived on.
+///
+/// When enabled with
+/// [`Builder::connection_stats`](super::auto::Builder::connection_stats),
+/// every request gets a `ConnectionStats` in its extensions. Clones share
+/// the counters, which keep updating as the connection is used.
+///
+/// # Example
+///
+/// Close a connection after 1000 requests:
+///
+/// ```
+/// use http::{header, Request, Response};
+/// use hyper_util::server::conn::auto::ConnectionStats;
+///
+/// fn respond<B>(req: &Request<B>, res: &mut Response<()>) {
+///     if let Some(stats) = req.extensions().get::<ConnectionStats>() {
+///         if stats.requests() >= 1000 {
+///             res.headers_mut()
+///                 .insert(header::CONNECTION, "close".parse().unwrap());
+///         }
+///     }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct ConnectionStats {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    id: u64,
+    started: Instant,
+    requests: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+// ===== impl ConnectionStats =====
+
+impl ConnectionStats {
+    pub(super) fn new(id: u64) -> ConnectionStats {
+        ConnectionStats {
+            inner: Arc::new(Inner {
+                id,
+                started: Instant::now(),
+                requests: AtomicU64::new(0),
+                bytes_read: AtomicU64::new(0),
+                bytes_written: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// The ID of the connection, to correlate its logs.
+    ///
+    /// IDs increase, and are unique in the process, shared with the
+    /// connections of the legacy client. With the `tracing` feature, the
+    /// connection is served in a span with it as `connection.id`.
+    pub fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    /// When the connection was accepted by the builder.
+    pub fn started(&self) -> Instant {
+        self.inner.started
+    }
+
+    /// How long the connection has been open.
+    pub fn age(&self) -> Duration {
+        self.inner.started.elapsed()
+    }
+
+    /// How many requests the connection has received, including the
+    /// current one.
+    pub fn requests(&self) -> u64 {
+        self.inner.requests.load(Ordering::Relaxed)
+    }
+
+    /// How many bytes have been read from the connection.
+    pub fn bytes_read(&self) -> u64 {
+        self.inner.bytes_read.load(Ordering::Relaxed)
+    }
+
+    /// How many bytes have been written to the connection.
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.bytes_written.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Debug for ConnectionStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectionStats")
+            .field("id", &self.id())
+            .field("age", &self.age())
+            .field("requests", &self.requests())
+            .field("bytes_read", &self.bytes_read())
+            .field("bytes_written", &self.bytes_written())
+            .finish()
+    }
+}
+
+// An IO counting the bytes read and written, when stats or metrics are
+// enabled.
+pub(super) struct Counted<I> {
+    inner: I,
+    stats: Option<ConnectionStats>,
+    metrics: metrics::Connection,
+}
+
+impl<I> Counted<I> {
+    pub(super) fn new(inner: I, stats: Option<ConnectionStats>) -> Counted<I> {
+        Counted {
+            inner,
+            stats,
+            metrics: metrics::Connection::new(),
+        }
+    }
+
+    fn count(&self, counter: fn(&Inner) -> &AtomicU64, n: usize) {
+        if let Some(ref stats) = self.stats {
+            counter(&stats.inner).fetch_add(n as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<I: fmt::Debug> fmt::Debug for Counted<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Counted")
+            .field("inner", &self.inner)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+impl<I> Read for Counted<I>
+where
+    I: Read + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.stats.is_none() && !metrics::ENABLED {
+            return Pin::new(&mut self.inner).poll_read(cx, buf);
+        }
+        // SAFETY: The bytes the inner read fills are initialized, and only
+        // those are advanced over.
+        let n = unsafe {
+            let mut inner = ReadBuf::uninit(buf.as_mut());
+            match Pin::new(&mut self.inner).poll_read(cx, inner.unfilled()) {
+                Poll::Ready(Ok(())) => inner.filled().len(),
+                other => return other,
+            }
+        };
+        unsafe {
+            buf.advance(n);
+        }
+        self.count(|s| &s.bytes_read, n);
+        self.metrics.read(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I> Write for Counted<I>
+where
+    I: Write + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures_util::ready!(Pin::new(&mut self.inner).poll_write(cx, buf))?;
+        self.count(|s| &s.bytes_written, n);
+        self.metrics.written(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let n = futures_util::ready!(Pin::new(&mut self.inner).poll_write_vectored(cx, bufs))?;
+        self.count(|s| &s.bytes_written, n);
+        self.metrics.written(n);
+        Poll::Ready(Ok(n))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// A service counting requests and handing them the stats and the write
+// weight, when enabled.
+//
+// hyper calls a service with `&mut self` through `HttpService`, which is
+// all the connection types can name, so the service is kept in a
+// `RefCell` to call it from `Service::call`.
+pub(super) struct WithStats<S> {
+    inner: RefCell<S>,
+    stats: Option<ConnectionStats>,
+    weight: Option<WriteWeight>,
+}
+
+impl<S> WithStats<S> {
+    pub(super) fn new(
+        inner: S,
+        stats: Option<ConnectionStats>,
+        weight: Option<WriteWeight>,
+    ) -> WithStats<S> {
+        WithStats {
+            inner: RefCell::new(inner),
+            stats,
+            weight,
+        }
+    }
+}
+
+impl<S> Service<Request<Incoming>> for WithStats<S>
+where
+    S: HttpService<Incoming>,
+{
+    type Response = Response<S::ResBody>;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, mut req: Request<Incoming>) -> Self::Future {
+        if let Some(ref stats) = self.stats {
+            stats.inner.requests.fetch_add(1, Ordering::Relaxed);
+            req.extensions_mut().insert(stats.clone());
+        }
+        if let Some(ref weight) = self.weight {
+            req.extensions_mut().insert(weight.clone());
+        }
+        self.inner.borrow_mut().call(req)
+    }
+}
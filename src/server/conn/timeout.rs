@@ -0,0 +1,364 @@
+//! Header timeouts of the auto connection driver, for HTTP/1.
+//!
+//! The io and the service of a connection share a `Phase`, so the io knows
+//! whether a request's headers are still being read:
+//!
+//! - `IDLE` until the first bytes of a request are read, which starts the
+//!   request header timer and moves to `HEAD`,
+//! - `BUSY` once the service is called, until the response body is done,
+//! - `OFF` for HTTP/2 and upgraded connections, where it doesn't apply.
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::rt::{Read, ReadBuf, ReadBufCursor, Sleep, Timer, Write};
+
+use crate::common::timer;
+
+const IDLE: u8 = 0;
+const HEAD: u8 = 1;
+#[cfg_attr(not(feature = "http1"), allow(dead_code))]
+const BUSY: u8 = 2;
+const OFF: u8 = 3;
+
+const REQUEST_TIMEOUT: &[u8] =
+    b"HTTP/1.1 408 Request Timeout\r\nconnection: close\r\ncontent-length: 0\r\n\r\n";
+
+#[derive(Clone, Debug)]
+pub(super) struct Phase(Arc<AtomicU8>);
+
+impl Phase {
+    fn get(&self) -> u8 {
+        self.0.load(Ordering::Acquire)
+    }
+
+    fn swap(&self, from: u8, to: u8) -> bool {
+        self.0
+            .compare_exchange(from, to, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    pub(super) fn disable(&self) {
+        self.0.store(OFF, Ordering::Release);
+    }
+}
+
+// An IO answering `408 Request Timeout` when a request's headers take too
+// long to arrive, when enabled.
+pub(super) struct HeaderTimeout<I> {
+    inner: I,
+    timeout: Option<(timer::Timer, Duration, Phase)>,
+    sleep: Option<Pin<Box<dyn Sleep>>>,
+    // how much of the 408 response has been written, once timed out
+    responded: Option<usize>,
+}
+
+impl<I> HeaderTimeout<I> {
+    pub(super) fn new(inner: I, timeout: Option<(timer::Timer, Duration)>) -> HeaderTimeout<I> {
+        HeaderTimeout {
+            inner,
+            timeout: timeout.map(|(timer, dur)| (timer, dur, Phase(Arc::new(AtomicU8::new(IDLE))))),
+            sleep: None,
+            responded: None,
+        }
+    }
+
+    pub(super) fn phase(&self) -> Option<Phase> {
+        self.timeout.as_ref().map(|(_, _, phase)| phase.clone())
+    }
+}
+
+impl<I> HeaderTimeout<I>
+where
+    I: Write + Unpin,
+{
+    fn poll_respond(&mut self, cx: &mut Context<'_>, mut written: usize) -> Poll<io::Result<()>> {
+        while written < REQUEST_TIMEOUT.len() {
+            let res = Pin::new(&mut self.inner).poll_write(cx, &REQUEST_TIMEOUT[written..]);
+            let n = match res {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => {
+                    self.responded = Some(written);
+                    return Poll::Pending;
+                }
+            };
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            written += n;
+        }
+        self.responded = Some(written);
+        futures_util::ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+        Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timed out reading request headers",
+        )))
+    }
+}
+
+impl<I> Read for HeaderTimeout<I>
+where
+    I: Read + Write + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        mut buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if let Some(written) = this.responded {
+            return this.poll_respond(cx, written);
+        }
+        let (timer, dur, phase) = match this.timeout {
+            Some(ref timeout) => timeout,
+            None => return Pin::new(&mut this.inner).poll_read(cx, buf),
+        };
+
+        match phase.get() {
+            IDLE => this.sleep = None,
+            HEAD => {
+                let expired = this
+                    .sleep
+                    .as_mut()
+                    .map_or(false, |sleep| sleep.as_mut().poll(cx).is_ready());
+                if expired {
+                    phase.disable();
+                    return this.poll_respond(cx, 0);
+                }
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+            _ => {
+                this.sleep = None;
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+        }
+
+        // SAFETY: The bytes the inner read fills are initialized, and only
+        // those are advanced over.
+        let n = unsafe {
+            let mut inner = ReadBuf::uninit(buf.as_mut());
+            match Pin::new(&mut this.inner).poll_read(cx, inner.unfilled()) {
+                Poll::Ready(Ok(())) => inner.filled().len(),
+                other => return other,
+            }
+        };
+        unsafe {
+            buf.advance(n);
+        }
+        if n > 0 && phase.swap(IDLE, HEAD) {
+            this.sleep = Some(timer.sleep(*dur));
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I> Write for HeaderTimeout<I>
+where
+    I: Write + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(feature = "http1")]
+pub(super) use self::response::ResponseTimeout;
+
+#[cfg(feature = "http1")]
+mod response {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use std::time::Duration;
+
+    use http::{Method, Request, Response, StatusCode};
+    use http_body::{Body, Frame, SizeHint};
+    use hyper::body::Incoming;
+    use hyper::rt::{Sleep, Timer};
+    use hyper::service::Service;
+    use pin_project_lite::pin_project;
+
+    use super::{Phase, BUSY, HEAD, IDLE};
+    use crate::common::timer;
+    use crate::server::metrics;
+
+    // A service answering `503 Service Unavailable` when the inner service
+    // takes too long to respond, when enabled, and keeping the phase of the
+    // connection and the metrics of its requests.
+    pub(in crate::server::conn) struct ResponseTimeout<S> {
+        inner: S,
+        timeout: Option<(timer::Timer, Duration)>,
+        phase: Option<Phase>,
+    }
+
+    impl<S> ResponseTimeout<S> {
+        pub(in crate::server::conn) fn new(
+            inner: S,
+            timeout: Option<(timer::Timer, Duration)>,
+            phase: Option<Phase>,
+        ) -> ResponseTimeout<S> {
+            ResponseTimeout {
+                inner,
+                timeout,
+                phase,
+            }
+        }
+    }
+
+    impl<S, B> Service<Request<Incoming>> for ResponseTimeout<S>
+    where
+        S: Service<Request<Incoming>, Response = Response<B>>,
+    {
+        type Response = Response<TimeoutBody<B>>;
+        type Error = S::Error;
+        type Future = ResponseFuture<S::Future>;
+
+        fn call(&self, req: Request<Incoming>) -> Self::Future {
+            if let Some(ref phase) = self.phase {
+                if !phase.swap(HEAD, BUSY) {
+                    phase.swap(IDLE, BUSY);
+                }
+            }
+            ResponseFuture {
+                connect: req.method() == Method::CONNECT,
+                inner: self.inner.call(req),
+                sleep: self.timeout.as_ref().map(|(timer, dur)| timer.sleep(*dur)),
+                phase: self.phase.clone(),
+                metrics: Some(metrics::Request::new()),
+            }
+        }
+    }
+
+    pin_project! {
+        pub(in crate::server::conn) struct ResponseFuture<F> {
+            #[pin]
+            inner: F,
+            sleep: Option<Pin<Box<dyn Sleep>>>,
+            phase: Option<Phase>,
+            connect: bool,
+            metrics: Option<metrics::Request>,
+        }
+    }
+
+    impl<F, B, E> Future for ResponseFuture<F>
+    where
+        F: Future<Output = Result<Response<B>, E>>,
+    {
+        type Output = Result<Response<TimeoutBody<B>>, E>;
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let this = self.project();
+            let mut res = match this.inner.poll(cx) {
+                Poll::Ready(Ok(res)) => res.map(|body| TimeoutBody {
+                    inner: Some(body),
+                    idle: None,
+                    metrics: None,
+                }),
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => {
+                    let expired = this
+                        .sleep
+                        .as_mut()
+                        .map_or(false, |sleep| sleep.as_mut().poll(cx).is_ready());
+                    if !expired {
+                        return Poll::Pending;
+                    }
+                    let mut res = Response::new(TimeoutBody {
+                        inner: None,
+                        idle: None,
+                        metrics: None,
+                    });
+                    *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    res
+                }
+            };
+
+            if let Some(metrics) = this.metrics.take() {
+                metrics.responded(res.status());
+                res.body_mut().metrics = Some(metrics);
+            }
+
+            let upgrade = res.status() == StatusCode::SWITCHING_PROTOCOLS
+                || (*this.connect && res.status().is_success());
+            match this.phase.take() {
+                Some(phase) if upgrade => phase.disable(),
+                phase => res.body_mut().idle = phase.map(IdleOnDrop),
+            }
+            Poll::Ready(Ok(res))
+        }
+    }
+
+    pin_project! {
+        // The body of a response, which is empty if it timed out.
+        pub(in crate::server::conn) struct TimeoutBody<B> {
+            #[pin]
+            inner: Option<B>,
+            idle: Option<IdleOnDrop>,
+            metrics: Option<metrics::Request>,
+        }
+    }
+
+    impl<B: Body> Body for TimeoutBody<B> {
+        type Data = B::Data;
+        type Error = B::Error;
+
+        fn poll_frame(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+            match self.project().inner.as_pin_mut() {
+                Some(inner) => inner.poll_frame(cx),
+                None => Poll::Ready(None),
+            }
+        }
+
+        fn is_end_stream(&self) -> bool {
+            self.inner.as_ref().map_or(true, Body::is_end_stream)
+        }
+
+        fn size_hint(&self) -> SizeHint {
+            self.inner
+                .as_ref()
+                .map_or_else(|| SizeHint::with_exact(0), Body::size_hint)
+        }
+    }
+
+    // Once the response body is done, the connection waits for the next
+    // request.
+    struct IdleOnDrop(Phase);
+
+    impl Drop for IdleOnDrop {
+        fn drop(&mut self) {
+            self.0.swap(BUSY, IDLE);
+        }
+    }
+}
@@ -0,0 +1,590 @@
+//! `Forwarded` and `X-Forwarded-*` headers.
+//!
+//! This module provides:
+//!
+//! - [`parse`] to read the hops a request went through, from the RFC 7239
+//!   `Forwarded` header, or the legacy `X-Forwarded-For`,
+//!   `X-Forwarded-Proto` and `X-Forwarded-Host` headers.
+//! - [`append`] and [`append_legacy`] to add a hop when forwarding a
+//!   request.
+//! - [`TrustedProxies`] to find the real client of a request, trusting only
+//!   the hops added by known proxies, and [`WithRealClient`] to hand it to
+//!   a service.
+//!
+//! # Example
+//!
+//! ```
+//! use std::net::IpAddr;
+//!
+//! use http::HeaderMap;
+//! use hyper_util::server::forwarded::TrustedProxies;
+//!
+//! let trusted = TrustedProxies::new().trust("10.0.0.0".parse().unwrap(), 8);
+//!
+//! let mut headers = HeaderMap::new();
+//! headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.2".parse().unwrap());
+//!
+//! let peer: IpAddr = "10.0.0.1".parse().unwrap();
+//! let client = trusted.resolve(peer, &headers);
+//! assert_eq!(client.addr(), "203.0.113.7".parse::<IpAddr>().unwrap());
+//! ```
+
+use std::fmt::Write as _;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::Request;
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// One hop of a forwarded request: an element of the `Forwarded` header.
+///
+/// Nodes are kept as they're written in the header, such as `192.0.2.60`,
+/// `[2001:db8::17]:4711`, `unknown` or an obfuscated `_hidden`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ForwardedElement {
+    for_node: Option<String>,
+    by_node: Option<String>,
+    host: Option<String>,
+    proto: Option<String>,
+}
+
+/// The networks of the proxies whose forwarding headers are trusted.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    nets: Vec<(IpAddr, u8)>,
+}
+
+/// The real client of a request, found by [`TrustedProxies::resolve`].
+///
+/// The `proto` and `host` are the ones the client used, as reported by the
+/// trusted proxy it connected to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RealClient {
+    addr: IpAddr,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+/// A service that inserts the [`RealClient`] into the extensions of every
+/// request on a connection.
+#[derive(Clone, Debug)]
+pub struct WithRealClient<S> {
+    inner: S,
+    peer: IpAddr,
+    trusted: Arc<TrustedProxies>,
+}
+
+// ===== impl ForwardedElement =====
+
+impl ForwardedElement {
+    /// Create an empty element.
+    pub fn new() -> Self {
+        ForwardedElement::default()
+    }
+
+    /// Set the node the request came from, such as the address of the
+    /// client.
+    pub fn with_for(mut self, node: impl Into<String>) -> Self {
+        self.for_node = Some(node.into());
+        self
+    }
+
+    /// Set the node that received the request, such as the proxy.
+    pub fn with_by(mut self, node: impl Into<String>) -> Self {
+        self.by_node = Some(node.into());
+        self
+    }
+
+    /// Set the `Host` the request was received with.
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    /// Set the protocol the request was received with, like `https`.
+    pub fn with_proto(mut self, proto: impl Into<String>) -> Self {
+        self.proto = Some(proto.into());
+        self
+    }
+
+    /// The node the request came from.
+    pub fn for_node(&self) -> Option<&str> {
+        self.for_node.as_deref()
+    }
+
+    /// The IP address the request came from, if the node is one.
+    pub fn for_ip(&self) -> Option<IpAddr> {
+        self.for_node.as_deref().and_then(node_ip)
+    }
+
+    /// The node that received the request.
+    pub fn by_node(&self) -> Option<&str> {
+        self.by_node.as_deref()
+    }
+
+    /// The `Host` the request was received with.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+
+    /// The protocol the request was received with.
+    pub fn proto(&self) -> Option<&str> {
+        self.proto.as_deref()
+    }
+
+    fn is_empty(&self) -> bool {
+        self == &ForwardedElement::default()
+    }
+}
+
+/// Parse the hops of a request, from the client to the last proxy.
+///
+/// The `Forwarded` header is used if there is one. Otherwise, there's an
+/// element for each address of `X-Forwarded-For`, with the first one
+/// getting `X-Forwarded-Proto` and `X-Forwarded-Host`, which describe the
+/// request of the client.
+///
+/// Malformed parts of the headers are skipped.
+pub fn parse(headers: &HeaderMap) -> Vec<ForwardedElement> {
+    if headers.contains_key(header::FORWARDED) {
+        return headers
+            .get_all(header::FORWARDED)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| split_quoted(v, ','))
+            .map(parse_element)
+            .filter(|el| !el.is_empty())
+            .collect();
+    }
+
+    let mut elements: Vec<ForwardedElement> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|node| !node.is_empty())
+        .map(|node| ForwardedElement::new().with_for(node))
+        .collect();
+    let proto = first_value(headers, &X_FORWARDED_PROTO);
+    let host = first_value(headers, &X_FORWARDED_HOST);
+    if elements.is_empty() && (proto.is_some() || host.is_some()) {
+        elements.push(ForwardedElement::new());
+    }
+    if let Some(first) = elements.first_mut() {
+        first.proto = proto;
+        first.host = host;
+    }
+    elements
+}
+
+/// Append a hop to the `Forwarded` header.
+pub fn append(headers: &mut HeaderMap, element: &ForwardedElement) {
+    let mut value = String::new();
+    let pairs = [
+        ("for", element.for_node.as_deref()),
+        ("by", element.by_node.as_deref()),
+        ("host", element.host.as_deref()),
+        ("proto", element.proto.as_deref()),
+    ];
+    for (key, val) in pairs.iter().filter_map(|(k, v)| v.map(|v| (k, v))) {
+        if !value.is_empty() {
+            value.push(';');
+        }
+        let val = match val.parse::<Ipv6Addr>() {
+            Ok(ip) => format!("[{}]", ip),
+            Err(_) => val.to_owned(),
+        };
+        let _ = write!(value, "{}=", key);
+        write_value(&mut value, &val);
+    }
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        headers.append(header::FORWARDED, value);
+    }
+}
+
+/// Append a hop to the `X-Forwarded-For` header, and set
+/// `X-Forwarded-Proto` and `X-Forwarded-Host` if they aren't already, since
+/// they describe the request of the client.
+pub fn append_legacy(headers: &mut HeaderMap, element: &ForwardedElement) {
+    if let Some(ref node) = element.for_node {
+        let node = match node_ip(node) {
+            Some(ip) => ip.to_string(),
+            None => node.clone(),
+        };
+        let value = match headers.get(X_FORWARDED_FOR).and_then(|v| v.to_str().ok()) {
+            Some(existing) => format!("{}, {}", existing, node),
+            None => node,
+        };
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(X_FORWARDED_FOR, value);
+        }
+    }
+    for (name, value) in [
+        (X_FORWARDED_PROTO, &element.proto),
+        (X_FORWARDED_HOST, &element.host),
+    ] {
+        if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.entry(name).or_insert(value);
+        }
+    }
+}
+
+fn first_value(headers: &HeaderMap, name: &HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_owned())
+        .filter(|v| !v.is_empty())
+}
+
+fn parse_element(el: &str) -> ForwardedElement {
+    let mut element = ForwardedElement::new();
+    for pair in split_quoted(el, ';') {
+        let (key, value) = match pair.split_once('=') {
+            Some((key, value)) => (key.trim(), unquote(value.trim())),
+            None => continue,
+        };
+        let slot = if key.eq_ignore_ascii_case("for") {
+            &mut element.for_node
+        } else if key.eq_ignore_ascii_case("by") {
+            &mut element.by_node
+        } else if key.eq_ignore_ascii_case("host") {
+            &mut element.host
+        } else if key.eq_ignore_ascii_case("proto") {
+            &mut element.proto
+        } else {
+            continue;
+        };
+        *slot = Some(value);
+    }
+    element
+}
+
+// Splits on `delim`, except inside quoted strings.
+fn split_quoted(s: &str, delim: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == delim && !quoted => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(s[start..].trim());
+    parts.retain(|p| !p.is_empty());
+    parts
+}
+
+fn unquote(value: &str) -> String {
+    let inner = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => inner,
+        None => return value.to_owned(),
+    };
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(c) = chars.next() {
+                out.push(c);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn write_value(out: &mut String, value: &str) {
+    let is_token = !value.is_empty()
+        && value
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    if is_token {
+        out.push_str(value);
+        return;
+    }
+    out.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out.push('"');
+}
+
+// The IP of a node like `192.0.2.60`, `192.0.2.60:80`, `2001:db8::1` or
+// `[2001:db8::1]:80`.
+fn node_ip(node: &str) -> Option<IpAddr> {
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    node.strip_prefix('[')
+        .and_then(|n| n.strip_suffix(']'))
+        .and_then(|n| n.parse::<Ipv6Addr>().ok())
+        .map(IpAddr::V6)
+}
+
+// ===== impl TrustedProxies =====
+
+impl TrustedProxies {
+    /// Trust no proxies.
+    pub fn new() -> Self {
+        TrustedProxies::default()
+    }
+
+    /// Trust the proxies in the network of `addr` with a prefix of
+    /// `prefix_len` bits, like `10.0.0.0` and `8`.
+    ///
+    /// IPv4-mapped IPv6 addresses match IPv4 networks, and a mapped
+    /// network, like `::ffff:10.0.0.0` and `104`, is the IPv4 one.
+    pub fn trust(mut self, addr: IpAddr, prefix_len: u8) -> Self {
+        let net = canonical(addr);
+        let len = match (addr, net) {
+            (IpAddr::V6(_), IpAddr::V4(_)) => prefix_len.saturating_sub(96).min(32),
+            (IpAddr::V4(_), _) => prefix_len.min(32),
+            (IpAddr::V6(_), _) => prefix_len.min(128),
+        };
+        self.nets.push((net, len));
+        self
+    }
+
+    /// Whether `addr` is the address of a trusted proxy.
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        let addr = canonical(addr);
+        self.nets.iter().any(|&(net, len)| in_net(addr, net, len))
+    }
+
+    /// Find the real client of a request, received from `peer`.
+    ///
+    /// Starting from `peer`, hops are followed back toward the client for
+    /// as long as they're from trusted proxies. The client is the first
+    /// untrusted address, or the first hop if every one is trusted. A hop
+    /// without an IP address, such as `unknown`, stops the search at the
+    /// proxy that reported it.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> RealClient {
+        let mut client = RealClient {
+            addr: peer,
+            proto: None,
+            host: None,
+        };
+        if !self.is_trusted(peer) {
+            return client;
+        }
+        for element in parse(headers).into_iter().rev() {
+            let addr = match element.for_ip() {
+                Some(addr) => addr,
+                None => break,
+            };
+            client = RealClient {
+                addr,
+                proto: element.proto,
+                host: element.host,
+            };
+            if !self.is_trusted(addr) {
+                break;
+            }
+        }
+        client
+    }
+}
+
+fn canonical(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => addr,
+        },
+        v4 => v4,
+    }
+}
+
+fn in_net(addr: IpAddr, net: IpAddr, len: u8) -> bool {
+    match (addr, net) {
+        (IpAddr::V4(a), IpAddr::V4(n)) => {
+            let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+            u32::from(a) & mask == u32::from(n) & mask
+        }
+        (IpAddr::V6(a), IpAddr::V6(n)) => {
+            let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+            u128::from(a) & mask == u128::from(n) & mask
+        }
+        _ => false,
+    }
+}
+
+// ===== impl RealClient =====
+
+impl RealClient {
+    /// The IP address of the client.
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// The protocol the client used, if a trusted proxy reported it.
+    pub fn proto(&self) -> Option<&str> {
+        self.proto.as_deref()
+    }
+
+    /// The `Host` the client used, if a trusted proxy reported it.
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+}
+
+// ===== impl WithRealClient =====
+
+impl<S> WithRealClient<S> {
+    /// Wrap the service of a connection from `peer`.
+    pub fn new(inner: S, peer: IpAddr, trusted: Arc<TrustedProxies>) -> Self {
+        WithRealClient {
+            inner,
+            peer,
+            trusted,
+        }
+    }
+}
+
+impl<S, B> hyper::service::Service<Request<B>> for WithRealClient<S>
+where
+    S: hyper::service::Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, mut req: Request<B>) -> Self::Future {
+        let client = self.trusted.resolve(self.peer, req.headers());
+        req.extensions_mut().insert(client);
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use http::{header, HeaderMap};
+
+    use super::{append, append_legacy, parse, ForwardedElement, TrustedProxies};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_forwarded() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::FORWARDED,
+            "for=192.0.2.60;proto=http;by=203.0.113.43, for=\"[2001:db8:cafe::17]:4711\""
+                .parse()
+                .unwrap(),
+        );
+        headers.append(
+            header::FORWARDED,
+            "for=unknown;host=\"a,b\"".parse().unwrap(),
+        );
+        let elements = parse(&headers);
+        assert_eq!(elements.len(), 3);
+        assert_eq!(elements[0].for_ip(), Some(ip("192.0.2.60")));
+        assert_eq!(elements[0].proto(), Some("http"));
+        assert_eq!(elements[0].by_node(), Some("203.0.113.43"));
+        assert_eq!(elements[1].for_ip(), Some(ip("2001:db8:cafe::17")));
+        assert_eq!(elements[2].for_node(), Some("unknown"));
+        assert_eq!(elements[2].for_ip(), None);
+        assert_eq!(elements[2].host(), Some("a,b"));
+    }
+
+    #[test]
+    fn parses_legacy() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.7, 10.0.0.2".parse().unwrap());
+        headers.insert("x-forwarded-proto", "https".parse().unwrap());
+        let elements = parse(&headers);
+        assert_eq!(elements.len(), 2);
+        assert_eq!(elements[0].proto(), Some("https"));
+        assert_eq!(elements[1].proto(), None);
+    }
+
+    #[test]
+    fn appends() {
+        let mut headers = HeaderMap::new();
+        let el = ForwardedElement::new()
+            .with_for("2001:db8::1")
+            .with_proto("https")
+            .with_host("example.com");
+        append(&mut headers, &el);
+        assert_eq!(
+            headers[header::FORWARDED],
+            "for=\"[2001:db8::1]\";host=example.com;proto=https"
+        );
+        let parsed = parse(&headers);
+        assert_eq!(parsed[0].for_node(), Some("[2001:db8::1]"));
+        assert_eq!(parsed[0].for_ip(), el.for_ip());
+
+        headers.insert("x-forwarded-for", "203.0.113.7".parse().unwrap());
+        headers.insert("x-forwarded-proto", "http".parse().unwrap());
+        append_legacy(&mut headers, &el);
+        assert_eq!(headers["x-forwarded-for"], "203.0.113.7, 2001:db8::1");
+        assert_eq!(headers["x-forwarded-proto"], "http");
+        assert_eq!(headers["x-forwarded-host"], "example.com");
+    }
+
+    #[test]
+    fn resolves_through_trusted_proxies() {
+        let trusted = TrustedProxies::new()
+            .trust(ip("10.0.0.0"), 8)
+            .trust(ip("fd00::"), 8);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "198.51.100.1, 203.0.113.7, 10.1.2.3".parse().unwrap(),
+        );
+
+        // the spoofed first hop isn't trusted, since 203.0.113.7 isn't
+        let client = trusted.resolve(ip("10.0.0.1"), &headers);
+        assert_eq!(client.addr(), ip("203.0.113.7"));
+
+        // an untrusted peer's headers are ignored
+        let client = trusted.resolve(ip("192.0.2.1"), &headers);
+        assert_eq!(client.addr(), ip("192.0.2.1"));
+
+        let client = trusted.resolve(ip("::ffff:10.0.0.1"), &headers);
+        assert_eq!(client.addr(), ip("203.0.113.7"));
+
+        headers.insert("x-forwarded-for", "unknown, 10.1.2.3".parse().unwrap());
+        let client = trusted.resolve(ip("fd00::1"), &headers);
+        assert_eq!(client.addr(), ip("10.1.2.3"));
+    }
+
+    #[test]
+    fn trusts_mapped_networks() {
+        let host = TrustedProxies::new().trust(ip("::ffff:10.0.0.1"), 128);
+        assert!(host.is_trusted(ip("10.0.0.1")));
+        assert!(host.is_trusted(ip("::ffff:10.0.0.1")));
+        assert!(!host.is_trusted(ip("10.0.0.2")));
+        assert!(!host.is_trusted(ip("192.0.2.1")));
+
+        let net = TrustedProxies::new().trust(ip("::ffff:10.0.0.0"), 104);
+        assert!(net.is_trusted(ip("10.200.0.1")));
+        assert!(!net.is_trusted(ip("11.0.0.1")));
+    }
+}
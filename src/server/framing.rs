@@ -0,0 +1,325 @@
+//! Response framing.
+//!
+//! This module provides [`frame_response`], which sets the framing headers
+//! of a response from the size of its body, and drops the body of a
+//! response that can't have one, and a [`Framing`] service doing so for
+//! every response of another service.
+//!
+//! A response is framed as follows:
+//!
+//! - `1xx` and `204 No Content` responses have no body, and no
+//!   `Content-Length` or `Transfer-Encoding` header.
+//! - Responses to `HEAD` and `304 Not Modified` responses have no body,
+//!   but keep a `Content-Length` header describing the body they would
+//!   have had. Responses to `HEAD` get one if the body size is known.
+//! - Other responses get a `Content-Length` header if the body size is
+//!   known and there is no `Transfer-Encoding` header, and are left to
+//!   chunked encoding otherwise. A `Content-Length` header sent along with
+//!   `Transfer-Encoding` is removed.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//!
+//! use bytes::Bytes;
+//! use http::{Request, Response};
+//! use http_body_util::Full;
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::server::framing::Framing;
+//!
+//! let service = Framing::new(service_fn(|_req: Request<Incoming>| async {
+//!     Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("hello"))))
+//! }))
+//! .debug_assertions(true);
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use hyper::service::Service;
+use pin_project_lite::pin_project;
+
+/// A service that frames the responses of another service.
+///
+/// See the [module documentation](self) for how responses are framed.
+///
+/// With debug assertions on, a response whose status and body don't go
+/// together panics instead of being fixed, to catch the mistake in the
+/// handler: a body on a `1xx`, `204` or `304` response, a framing header
+/// on a `1xx` or `204` response, both `Content-Length` and
+/// `Transfer-Encoding`, or a `Content-Length` other than the known size of
+/// the body.
+#[derive(Clone, Debug)]
+pub struct Framing<S> {
+    inner: S,
+    debug_assertions: bool,
+}
+
+pin_project! {
+    /// The response future of a [`Framing`] service.
+    #[derive(Debug)]
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        method: Method,
+        debug_assertions: bool,
+    }
+}
+
+pin_project! {
+    /// A response body, empty if the response can't have one.
+    #[derive(Debug)]
+    pub struct FramedBody<B> {
+        #[pin]
+        inner: Option<B>,
+    }
+}
+
+/// Frame a response to a request with `method`.
+///
+/// See the [module documentation](self) for how responses are framed.
+pub fn frame_response<B: Body>(method: &Method, res: Response<B>) -> Response<FramedBody<B>> {
+    frame(method, res, false)
+}
+
+fn frame<B: Body>(method: &Method, res: Response<B>, strict: bool) -> Response<FramedBody<B>> {
+    let (mut parts, body) = res.into_parts();
+    let status = parts.status;
+    let headers = &mut parts.headers;
+    let size = body.size_hint().exact();
+    let length = content_length(headers);
+    let chunked = headers.contains_key(header::TRANSFER_ENCODING);
+
+    if strict {
+        check(status, headers, &body);
+    }
+
+    let bodiless = status.is_informational() || status == StatusCode::NO_CONTENT;
+    let body = if bodiless {
+        headers.remove(header::CONTENT_LENGTH);
+        headers.remove(header::TRANSFER_ENCODING);
+        None
+    } else if status == StatusCode::NOT_MODIFIED {
+        headers.remove(header::TRANSFER_ENCODING);
+        None
+    } else {
+        if chunked {
+            headers.remove(header::CONTENT_LENGTH);
+        } else if let Some(size) = size {
+            if length != Some(size) {
+                headers.insert(header::CONTENT_LENGTH, HeaderValue::from(size));
+            }
+        }
+        if *method == Method::HEAD {
+            None
+        } else {
+            Some(body)
+        }
+    };
+    Response::from_parts(parts, FramedBody { inner: body })
+}
+
+// Panic if the status, framing headers and body of a response don't go
+// together.
+fn check<B: Body>(status: StatusCode, headers: &HeaderMap, body: &B) {
+    let empty = body.is_end_stream() || body.size_hint().exact() == Some(0);
+    if status.is_informational() || status == StatusCode::NO_CONTENT {
+        assert!(empty, "{} response with a body", status);
+        assert!(
+            !headers.contains_key(header::CONTENT_LENGTH)
+                && !headers.contains_key(header::TRANSFER_ENCODING),
+            "{} response with framing headers",
+            status
+        );
+        return;
+    }
+    if status == StatusCode::NOT_MODIFIED {
+        assert!(empty, "{} response with a body", status);
+        return;
+    }
+
+    assert!(
+        !(headers.contains_key(header::CONTENT_LENGTH)
+            && headers.contains_key(header::TRANSFER_ENCODING)),
+        "response with both content-length and transfer-encoding"
+    );
+    if let (Some(length), Some(size)) = (content_length(headers), body.size_hint().exact()) {
+        assert_eq!(
+            length, size,
+            "response content-length doesn't match its body size"
+        );
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+// ===== impl Framing =====
+
+impl<S> Framing<S> {
+    /// Wrap a service, framing its responses.
+    pub fn new(inner: S) -> Self {
+        Framing {
+            inner,
+            debug_assertions: cfg!(debug_assertions),
+        }
+    }
+
+    /// Set whether a response whose status and body don't go together
+    /// panics, rather than being fixed.
+    ///
+    /// Default is `true` in debug builds, and `false` in release builds.
+    pub fn debug_assertions(mut self, enabled: bool) -> Self {
+        self.debug_assertions = enabled;
+        self
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Framing<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Body,
+{
+    type Response = Response<FramedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        ResponseFuture {
+            method: req.method().clone(),
+            inner: self.inner.call(req),
+            debug_assertions: self.debug_assertions,
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body,
+{
+    type Output = Result<Response<FramedBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let method = &*this.method;
+        let strict = *this.debug_assertions;
+        this.inner.poll(cx).map_ok(|res| frame(method, res, strict))
+    }
+}
+
+// ===== impl FramedBody =====
+
+impl<B> FramedBody<B> {
+    /// Returns true if the body was dropped, since the response can't have
+    /// one.
+    pub fn is_stripped(&self) -> bool {
+        self.inner.is_none()
+    }
+}
+
+impl<B: Body> Body for FramedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().inner.as_pin_mut() {
+            Some(inner) => inner.poll_frame(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().map_or(true, Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner
+            .as_ref()
+            .map_or_else(|| SizeHint::with_exact(0), Body::size_hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::{header, Method, Response, StatusCode};
+    use http_body_util::{Empty, Full};
+
+    use super::{frame, frame_response};
+
+    fn response(status: StatusCode, body: &'static str) -> Response<Full<Bytes>> {
+        let mut res = Response::new(Full::new(Bytes::from(body)));
+        *res.status_mut() = status;
+        res
+    }
+
+    #[test]
+    fn frames_by_method_and_status() {
+        let res = frame_response(&Method::GET, response(StatusCode::OK, "hello"));
+        assert_eq!(res.headers()[header::CONTENT_LENGTH], "5");
+        assert!(!res.body().is_stripped());
+
+        let res = frame_response(&Method::HEAD, response(StatusCode::OK, "hello"));
+        assert_eq!(res.headers()[header::CONTENT_LENGTH], "5");
+        assert!(res.body().is_stripped());
+
+        let mut res = response(StatusCode::NO_CONTENT, "oops");
+        res.headers_mut()
+            .insert(header::CONTENT_LENGTH, "4".parse().unwrap());
+        let res = frame_response(&Method::GET, res);
+        assert!(!res.headers().contains_key(header::CONTENT_LENGTH));
+        assert!(res.body().is_stripped());
+
+        let mut res = response(StatusCode::NOT_MODIFIED, "");
+        res.headers_mut()
+            .insert(header::CONTENT_LENGTH, "42".parse().unwrap());
+        let res = frame_response(&Method::GET, res);
+        assert_eq!(res.headers()[header::CONTENT_LENGTH], "42");
+        assert!(res.body().is_stripped());
+
+        let mut res = response(StatusCode::OK, "hello");
+        res.headers_mut()
+            .insert(header::TRANSFER_ENCODING, "chunked".parse().unwrap());
+        res.headers_mut()
+            .insert(header::CONTENT_LENGTH, "5".parse().unwrap());
+        let res = frame_response(&Method::GET, res);
+        assert!(!res.headers().contains_key(header::CONTENT_LENGTH));
+
+        // an empty 204 passes the assertions
+        let mut res = Response::new(Empty::<Bytes>::new());
+        *res.status_mut() = StatusCode::NO_CONTENT;
+        frame(&Method::GET, res, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "content-length doesn't match")]
+    fn asserts_content_length() {
+        let mut res = response(StatusCode::OK, "hello");
+        res.headers_mut()
+            .insert(header::CONTENT_LENGTH, "6".parse().unwrap());
+        frame(&Method::GET, res, true);
+    }
+}
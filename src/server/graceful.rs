@@ -19,13 +19,45 @@ use tokio::sync::watch;
 /// A graceful shutdown utility
 pub struct GracefulShutdown {
     tx: watch::Sender<()>,
+    // Separate from `tx`, so a held signal doesn't hold up the shutdown.
+    drain: watch::Sender<bool>,
+}
+
+/// Tells a service that its connection is draining.
+///
+/// Once a shutdown is signaled, watched connections finish their requests
+/// in flight and then close. A handler that knows can help them along, by
+/// setting `Connection: close`, ending a stream promptly, or rejecting new
+/// work with a `503 Service Unavailable`.
+///
+/// Get one with [`GracefulShutdown::drain_signal`], or from the extensions
+/// of the requests given to a [`WithDrainSignal`] service.
+#[derive(Clone)]
+pub struct DrainSignal {
+    rx: watch::Receiver<bool>,
+}
+
+/// A service that inserts a [`DrainSignal`] into the extensions of every
+/// request.
+#[derive(Clone, Debug)]
+pub struct WithDrainSignal<S> {
+    inner: S,
+    signal: DrainSignal,
 }
 
 impl GracefulShutdown {
     /// Create a new graceful shutdown helper.
     pub fn new() -> Self {
         let (tx, _) = watch::channel(());
-        Self { tx }
+        let (drain, _) = watch::channel(false);
+        Self { tx, drain }
+    }
+
+    /// Get a signal that tells when the watched connections are draining.
+    pub fn drain_signal(&self) -> DrainSignal {
+        DrainSignal {
+            rx: self.drain.subscribe(),
+        }
     }
 
     /// Wrap a future for graceful shutdown watching.
@@ -43,8 +75,11 @@ impl GracefulShutdown {
     /// This returns a `Future` which will complete once all watched
     /// connections have shutdown.
     pub async fn shutdown(self) {
-        let Self { tx } = self;
+        let Self { tx, drain } = self;
 
+        // tell the services first, so they know by the time their
+        // connections start to drain
+        let _ = drain.send(true);
         // signal all the watched futures about the change
         let _ = tx.send(());
         // and then wait for all of them to complete
@@ -64,6 +99,61 @@ impl Default for GracefulShutdown {
     }
 }
 
+// ===== impl DrainSignal =====
+
+impl DrainSignal {
+    /// Returns true once the connections are draining.
+    ///
+    /// This is also true if the [`GracefulShutdown`] was dropped, since that
+    /// shuts the watched connections down too.
+    pub fn is_draining(&self) -> bool {
+        *self.rx.borrow() || self.rx.has_changed().is_err()
+    }
+
+    /// Returns a future that resolves once the connections are draining.
+    pub fn draining(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut rx = self.rx.clone();
+        async move {
+            while !*rx.borrow_and_update() {
+                if rx.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Debug for DrainSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DrainSignal")
+            .field("draining", &self.is_draining())
+            .finish()
+    }
+}
+
+// ===== impl WithDrainSignal =====
+
+impl<S> WithDrainSignal<S> {
+    /// Wrap a service, giving its requests `signal`.
+    pub fn new(inner: S, signal: DrainSignal) -> Self {
+        Self { inner, signal }
+    }
+}
+
+impl<S, B> hyper::service::Service<http::Request<B>> for WithDrainSignal<S>
+where
+    S: hyper::service::Service<http::Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, mut req: http::Request<B>) -> Self::Future {
+        req.extensions_mut().insert(self.signal.clone());
+        self.inner.call(req)
+    }
+}
+
 pin_project! {
     struct GracefulConnectionFuture<C, F: Future> {
         #[pin]
@@ -407,6 +497,37 @@ mod test {
         }
     }
 
+    #[cfg(not(miri))]
+    #[tokio::test]
+    async fn test_drain_signal() {
+        use bytes::Bytes;
+        use http_body_util::Empty;
+        use hyper::service::Service;
+
+        let graceful = GracefulShutdown::new();
+        let service = WithDrainSignal::new(
+            hyper::service::service_fn(|req: http::Request<Empty<Bytes>>| async move {
+                let mut res = http::Response::new(Empty::<Bytes>::new());
+                if let Some(signal) = req.extensions().get::<DrainSignal>() {
+                    res.extensions_mut().insert(signal.clone());
+                }
+                Ok::<_, std::convert::Infallible>(res)
+            }),
+            graceful.drain_signal(),
+        );
+        let res = service.call(http::Request::default()).await.unwrap();
+        let signal = res.extensions().get::<DrainSignal>().expect("signal");
+        assert!(!signal.is_draining());
+        let draining = signal.draining();
+
+        // a held signal doesn't hold up the shutdown
+        tokio::time::timeout(std::time::Duration::from_millis(100), graceful.shutdown())
+            .await
+            .expect("shutdown");
+        assert!(signal.is_draining());
+        draining.await;
+    }
+
     #[cfg(not(miri))]
     #[tokio::test]
     async fn test_graceful_shutdown_timeout() {
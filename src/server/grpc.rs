@@ -0,0 +1,208 @@
+//! gRPC helpers.
+//!
+//! gRPC ends every response with a `grpc-status` trailer. This module
+//! provides:
+//!
+//! - [`trailers`] to build the trailers of a status,
+//! - [`WithTrailers`] to send trailers after a body,
+//! - [`trailers_only`] to respond with only a status, and
+//! - [`require_http2`] to turn away gRPC requests that didn't come over
+//!   HTTP/2, on servers that also serve HTTP/1.
+//!
+//! See also [`auto::Builder::grpc_defaults`](crate::server::conn::auto::Builder::grpc_defaults).
+
+use std::fmt::Write as _;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Request, Response, StatusCode, Version};
+use http_body::{Body, Frame, SizeHint};
+use pin_project_lite::pin_project;
+
+const GRPC_STATUS: HeaderName = HeaderName::from_static("grpc-status");
+const GRPC_MESSAGE: HeaderName = HeaderName::from_static("grpc-message");
+
+/// The `OK` status code.
+pub const OK: u32 = 0;
+/// The `INTERNAL` status code.
+pub const INTERNAL: u32 = 13;
+/// The `UNAVAILABLE` status code.
+pub const UNAVAILABLE: u32 = 14;
+
+pin_project! {
+    /// A body that sends trailers after the frames of another body.
+    ///
+    /// If the inner body sends trailers of its own, they are merged, with
+    /// the given trailers taking precedence.
+    #[derive(Debug)]
+    pub struct WithTrailers<B> {
+        #[pin]
+        inner: B,
+        trailers: Option<HeaderMap>,
+    }
+}
+
+/// Build the trailers of a gRPC status.
+///
+/// The message is percent-encoded, as gRPC requires, and left out if
+/// empty.
+pub fn trailers(code: u32, message: &str) -> HeaderMap {
+    let mut trailers = HeaderMap::new();
+    trailers.insert(GRPC_STATUS, HeaderValue::from(code));
+    if !message.is_empty() {
+        let encoded = encode_message(message);
+        trailers.insert(
+            GRPC_MESSAGE,
+            HeaderValue::from_str(&encoded).expect("percent-encoded is valid"),
+        );
+    }
+    trailers
+}
+
+/// Build a "Trailers-Only" response, which carries the status in its
+/// headers and has no body.
+///
+/// This is how gRPC responds with an error before sending any message.
+/// The body must be empty, with `Body::is_end_stream` returning `true`, so
+/// the headers end the HTTP/2 stream.
+pub fn trailers_only<B: Default>(code: u32, message: &str) -> Response<B> {
+    let mut res = Response::new(B::default());
+    let headers = res.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/grpc"),
+    );
+    headers.extend(trailers(code, message));
+    res
+}
+
+/// Turn away a gRPC request that didn't come over HTTP/2.
+///
+/// Returns a response to send instead, `505 HTTP Version Not Supported`,
+/// for requests with a `application/grpc` content type over an older
+/// version. Other requests are allowed, so routes that aren't gRPC can
+/// still be served over HTTP/1.
+pub fn require_http2<B, R: Default>(req: &Request<B>) -> Option<Response<R>> {
+    if req.version() == Version::HTTP_2 || !is_grpc(req) {
+        return None;
+    }
+    let mut res = Response::new(R::default());
+    *res.status_mut() = StatusCode::HTTP_VERSION_NOT_SUPPORTED;
+    Some(res)
+}
+
+fn is_grpc<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |ct| {
+            ct == "application/grpc" || ct.starts_with("application/grpc+")
+        })
+}
+
+fn encode_message(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for b in message.bytes() {
+        if (0x20..=0x7e).contains(&b) && b != b'%' {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", b);
+        }
+    }
+    encoded
+}
+
+// ===== impl WithTrailers =====
+
+impl<B> WithTrailers<B> {
+    /// Send `trailers` after `inner`.
+    pub fn new(inner: B, trailers: HeaderMap) -> Self {
+        WithTrailers {
+            inner,
+            trailers: Some(trailers),
+        }
+    }
+}
+
+impl<B: Body> Body for WithTrailers<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        if this.trailers.is_none() {
+            return Poll::Ready(None);
+        }
+        match futures_util::ready!(this.inner.poll_frame(cx)) {
+            Some(Ok(frame)) => match frame.into_trailers() {
+                Ok(mut inner) => {
+                    inner.extend(this.trailers.take().expect("trailers"));
+                    Poll::Ready(Some(Ok(Frame::trailers(inner))))
+                }
+                Err(frame) => Poll::Ready(Some(Ok(frame))),
+            },
+            Some(Err(err)) => Poll::Ready(Some(Err(err))),
+            None => Poll::Ready(this.trailers.take().map(|t| Ok(Frame::trailers(t)))),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.trailers.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::{Request, StatusCode, Version};
+    use http_body_util::{BodyExt, Empty, Full};
+
+    use super::{require_http2, trailers, trailers_only, WithTrailers, INTERNAL, OK};
+
+    #[test]
+    fn builds_trailers() {
+        let t = trailers(INTERNAL, "bad 100% ✓");
+        assert_eq!(t["grpc-status"], "13");
+        assert_eq!(t["grpc-message"], "bad 100%25 %E2%9C%93");
+        assert!(!trailers(OK, "").contains_key("grpc-message"));
+
+        let res = trailers_only::<Empty<Bytes>>(INTERNAL, "oops");
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "application/grpc");
+        assert_eq!(res.headers()["grpc-status"], "13");
+    }
+
+    #[tokio::test]
+    async fn sends_trailers_after_body() {
+        let body = WithTrailers::new(Full::new(Bytes::from("msg")), trailers(OK, ""));
+        let collected = body.collect().await.unwrap();
+        assert_eq!(collected.trailers().unwrap()["grpc-status"], "0");
+        assert_eq!(collected.to_bytes(), "msg");
+    }
+
+    #[test]
+    fn requires_http2_for_grpc() {
+        let req = Request::builder()
+            .header("content-type", "application/grpc+proto")
+            .body(())
+            .unwrap();
+        let res = require_http2::<_, Empty<Bytes>>(&req).unwrap();
+        assert_eq!(res.status(), StatusCode::HTTP_VERSION_NOT_SUPPORTED);
+
+        let req = Request::builder()
+            .version(Version::HTTP_2)
+            .header("content-type", "application/grpc")
+            .body(())
+            .unwrap();
+        assert!(require_http2::<_, Empty<Bytes>>(&req).is_none());
+        assert!(require_http2::<_, Empty<Bytes>>(&Request::new(())).is_none());
+    }
+}
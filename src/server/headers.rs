@@ -0,0 +1,339 @@
+//! Strict header validation.
+//!
+//! This module provides a [`HeaderValidation`] service, which rejects the
+//! requests whose headers are malformed or ambiguous with
+//! `400 Bad Request`, before they reach another service:
+//!
+//! - Field values must be made of visible characters, spaces and tabs,
+//!   without leading or trailing whitespace (RFC 9110, section 5.5).
+//!   Field names are already checked when parsing.
+//! - A request must not have more than one `Host` header (RFC 9112,
+//!   section 3.2), or `Content-Length` headers with different values (RFC
+//!   9110, section 8.6). Identical `Content-Length` values are merged into
+//!   one.
+//! - Each field, name and value, must fit in a maximum size.
+//!
+//! Headers that must appear at most once can be added with
+//! [`HeaderValidation::singleton`].
+//!
+//! With the `metrics` feature, each rejected request increments
+//! `hyper_util_server_rejected_headers_total`, labeled with the `reason`:
+//! `value`, `duplicate` or `size`. With the `tracing` feature, it is also
+//! logged at the debug level.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//!
+//! use bytes::Bytes;
+//! use http::{header, Request, Response};
+//! use http_body_util::Full;
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::server::headers::HeaderValidation;
+//!
+//! let service = HeaderValidation::new(service_fn(|_req: Request<Incoming>| async {
+//!     Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("hello"))))
+//! }))
+//! .singleton(header::AUTHORIZATION)
+//! .max_field_size(4096);
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use hyper::service::Service;
+use pin_project_lite::pin_project;
+
+use super::metrics;
+
+/// A service that rejects the requests with malformed or ambiguous
+/// headers, and passes the others to another service.
+///
+/// See the [module documentation](self) for the checks. Rejected requests
+/// don't reach the inner service, and get an empty body.
+#[derive(Clone, Debug)]
+pub struct HeaderValidation<S> {
+    inner: S,
+    config: Arc<Config>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    singletons: Vec<HeaderName>,
+    max_field_size: Option<usize>,
+}
+
+pin_project! {
+    /// The response future of a [`HeaderValidation`] service.
+    #[derive(Debug)]
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: Option<F>,
+        rejection: Option<Response<()>>,
+    }
+}
+
+pin_project! {
+    /// A response body, empty if the request was rejected.
+    #[derive(Debug)]
+    pub struct ValidatedBody<B> {
+        #[pin]
+        inner: Option<B>,
+    }
+}
+
+// ===== impl HeaderValidation =====
+
+impl<S> HeaderValidation<S> {
+    /// Wrap a service, with the default checks.
+    pub fn new(inner: S) -> Self {
+        HeaderValidation {
+            inner,
+            config: Arc::new(Config {
+                singletons: vec![header::HOST],
+                max_field_size: Some(8 * 1024),
+            }),
+        }
+    }
+
+    /// Reject the requests with more than one `name` header.
+    ///
+    /// `Host` is always a singleton.
+    pub fn singleton(mut self, name: HeaderName) -> Self {
+        let config = Arc::make_mut(&mut self.config);
+        if !config.singletons.contains(&name) {
+            config.singletons.push(name);
+        }
+        self
+    }
+
+    /// Set the maximum size of each field, its name and value together, in
+    /// bytes.
+    ///
+    /// Pass `None` to disable the limit.
+    ///
+    /// Default is 8 KiB.
+    pub fn max_field_size(mut self, max: impl Into<Option<usize>>) -> Self {
+        Arc::make_mut(&mut self.config).max_field_size = max.into();
+        self
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for HeaderValidation<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<ValidatedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn call(&self, mut req: Request<ReqBody>) -> Self::Future {
+        match self.config.validate(req.headers_mut()) {
+            Ok(()) => ResponseFuture {
+                inner: Some(self.inner.call(req)),
+                rejection: None,
+            },
+            Err(reason) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!("rejecting request headers: {}", reason);
+                metrics::headers_rejected(reason);
+                let mut res = Response::new(());
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                res.headers_mut()
+                    .insert(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+                ResponseFuture {
+                    inner: None,
+                    rejection: Some(res),
+                }
+            }
+        }
+    }
+}
+
+// ===== impl Config =====
+
+impl Config {
+    // Check, and normalize, the headers of a request, returning the reason
+    // to reject it.
+    fn validate(&self, headers: &mut HeaderMap) -> Result<(), &'static str> {
+        for (name, value) in headers.iter() {
+            if !is_valid_value(value.as_bytes()) {
+                return Err("value");
+            }
+            if let Some(max) = self.max_field_size {
+                if name.as_str().len() + value.len() > max {
+                    return Err("size");
+                }
+            }
+        }
+        for name in &self.singletons {
+            if headers.get_all(name).iter().nth(1).is_some() {
+                return Err("duplicate");
+            }
+        }
+        normalize_content_length(headers)
+    }
+}
+
+// Field values are visible characters, spaces and tabs, not starting or
+// ending with whitespace.
+fn is_valid_value(value: &[u8]) -> bool {
+    let is_ws = |b: &u8| *b == b' ' || *b == b'\t';
+    value
+        .iter()
+        .all(|&b| b == b'\t' || (b' '..=b'~').contains(&b))
+        && !value.first().map_or(false, is_ws)
+        && !value.last().map_or(false, is_ws)
+}
+
+// Merge identical `Content-Length` values, including in lists, rejecting
+// different ones.
+fn normalize_content_length(headers: &mut HeaderMap) -> Result<(), &'static str> {
+    let mut length = None;
+    let mut fields = 0;
+    for value in headers.get_all(header::CONTENT_LENGTH) {
+        fields += 1;
+        let value = value.to_str().map_err(|_| "value")?;
+        for part in value.split(',') {
+            let part = part.trim();
+            match length {
+                None => length = Some(part.to_owned()),
+                Some(ref length) if length == part => (),
+                Some(_) => return Err("duplicate"),
+            }
+        }
+    }
+    if let Some(length) = length {
+        if fields > 1 || length.len() != headers[header::CONTENT_LENGTH].len() {
+            let value = HeaderValue::from_str(&length).map_err(|_| "value")?;
+            headers.insert(header::CONTENT_LENGTH, value);
+        }
+    }
+    Ok(())
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<ValidatedBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Some(rejection) = this.rejection.take() {
+            return Poll::Ready(Ok(rejection.map(|()| ValidatedBody { inner: None })));
+        }
+        match this.inner.as_pin_mut() {
+            Some(inner) => inner
+                .poll(cx)
+                .map_ok(|res| res.map(|body| ValidatedBody { inner: Some(body) })),
+            None => panic!("ResponseFuture polled after completion"),
+        }
+    }
+}
+
+// ===== impl ValidatedBody =====
+
+impl<B: Body> Body for ValidatedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().inner.as_pin_mut() {
+            Some(inner) => inner.poll_frame(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().map_or(true, Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner
+            .as_ref()
+            .map_or_else(|| SizeHint::with_exact(0), Body::size_hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use http::{header, HeaderValue, Request, Response, StatusCode};
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::service::{service_fn, Service};
+
+    use super::HeaderValidation;
+
+    #[tokio::test]
+    async fn rejects_and_normalizes() {
+        let service = HeaderValidation::new(service_fn(|req: Request<Empty<Bytes>>| async move {
+            let length = req.headers().get_all(header::CONTENT_LENGTH).iter().count();
+            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(length.to_string()))))
+        }))
+        .singleton(header::AUTHORIZATION)
+        .max_field_size(32);
+
+        let status = |headers: &[(&'static str, &'static [u8])]| {
+            let mut req = Request::new(Empty::new());
+            for &(name, value) in headers {
+                req.headers_mut()
+                    .append(name, HeaderValue::from_bytes(value).unwrap());
+            }
+            let res = service.call(req);
+            async move { res.await.unwrap().status() }
+        };
+
+        assert_eq!(status(&[("x-ok", b"a b\tc")]).await, StatusCode::OK);
+        assert_eq!(
+            status(&[("x-obs", b"caf\xc3\xa9")]).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(status(&[("x-ws", b"a ")]).await, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            status(&[("x-big", &[b'a'; 30])]).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(&[("host", b"a"), ("host", b"b")]).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(&[("authorization", b"a"), ("authorization", b"a")]).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(&[("content-length", b"5"), ("content-length", b"6")]).await,
+            StatusCode::BAD_REQUEST
+        );
+
+        let mut req = Request::new(Empty::new());
+        req.headers_mut()
+            .append(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+        req.headers_mut()
+            .append(header::CONTENT_LENGTH, HeaderValue::from_static("0, 0"));
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "1", "merged into one");
+    }
+}
@@ -0,0 +1,831 @@
+//! Request deduplication by `Idempotency-Key`.
+//!
+//! A client retrying a request that isn't idempotent, such as a `POST`
+//! creating a payment, can't tell whether the first attempt was done when
+//! it failed. Sending an `Idempotency-Key` header, unique to the request
+//! and sent again with its retries, the [`Idempotency`] service runs the
+//! request once, and answers its retries:
+//!
+//! - A retry after the response was sent gets a copy of it, with an
+//!   `idempotent-replayed: true` header.
+//! - A retry while the request is still in progress gets `409 Conflict`.
+//! - A request reusing the key of another, with a different fingerprint,
+//!   gets `422 Unprocessable Content`.
+//! - A key that isn't a visible ASCII string gets `400 Bad Request`.
+//!
+//! Keys are scoped to the method and path of the request, and to what
+//! [`Idempotency::scope`] returns, such as the principal a request is
+//! authenticated as, so clients can't get the responses of others.
+//! Requests without the header go to the inner service as usual.
+//!
+//! The fingerprint of a request is a hash of its query, and its
+//! `Content-Type`, `Content-Length` and `Content-Digest` headers. The body
+//! isn't read, so a client can send a `Content-Digest` to have a reused key
+//! with another body rejected.
+//!
+//! The response body is streamed to the client while it is copied, up to a
+//! maximum size. A response that is larger, fails, has trailers, or is a
+//! server error (`5xx`), isn't kept, so a retry runs again.
+//!
+//! Responses are kept by an [`IdempotencyStore`], in memory with
+//! [`MemoryStore`] by default, or shared by the instances of a service.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//! use std::time::Duration;
+//!
+//! use bytes::Bytes;
+//! use http::{Request, Response};
+//! use http_body_util::Full;
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::server::idempotency::{Idempotency, MemoryStore};
+//!
+//! #[derive(Clone)]
+//! struct User(String);
+//!
+//! let service = Idempotency::new(service_fn(|_req: Request<Incoming>| async {
+//!     Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("created"))))
+//! }))
+//! .store(MemoryStore::new(Duration::from_secs(60 * 60)))
+//! .scope(|req| req.extensions.get::<User>().map(|user| user.0.clone()))
+//! .max_body_size(64 * 1024);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, Bytes, BytesMut};
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::request::Parts;
+use http::{Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use hyper::service::Service;
+use pin_project_lite::pin_project;
+
+const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+const CONTENT_DIGEST: HeaderName = HeaderName::from_static("content-digest");
+
+type Scope = Arc<dyn Fn(&Parts) -> Option<String> + Send + Sync>;
+
+/// A service running the requests with an `Idempotency-Key` once, and
+/// answering their retries with the same response.
+///
+/// See the [module documentation](self) for how retries are answered.
+pub struct Idempotency<S> {
+    inner: S,
+    store: Arc<dyn IdempotencyStore>,
+    scope: Option<Scope>,
+    max_body_size: usize,
+}
+
+/// Where an [`Idempotency`] service keeps the responses to the requests
+/// with an `Idempotency-Key`.
+///
+/// The keys passed to a store are scoped: they are made of the method, the
+/// path, the `Idempotency-Key` of the request, and its scope if there is
+/// one. A store keeps the fingerprint of the request that started a key,
+/// and answers [`KeyState::Mismatch`] to a request with another. A store
+/// shared by the instances of a service, such as in a database, must mark
+/// a key in progress atomically, so two retries racing to different
+/// instances don't both run the request.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+/// use std::sync::Mutex;
+/// use hyper_util::server::idempotency::{CachedResponse, IdempotencyStore, KeyState};
+///
+/// #[derive(Default)]
+/// struct Forever(Mutex<HashMap<String, (u64, Option<CachedResponse>)>>);
+///
+/// impl IdempotencyStore for Forever {
+///     fn start(&self, key: &str, fingerprint: u64) -> KeyState {
+///         let mut keys = self.0.lock().unwrap();
+///         match keys.get(key) {
+///             Some(&(started, _)) if started != fingerprint => KeyState::Mismatch,
+///             Some((_, Some(res))) => KeyState::Completed(res.clone()),
+///             Some((_, None)) => KeyState::InProgress,
+///             None => {
+///                 keys.insert(key.to_owned(), (fingerprint, None));
+///                 KeyState::Started
+///             }
+///         }
+///     }
+///
+///     fn complete(&self, key: &str, res: CachedResponse) {
+///         if let Some(entry) = self.0.lock().unwrap().get_mut(key) {
+///             entry.1 = Some(res);
+///         }
+///     }
+///
+///     fn remove(&self, key: &str) {
+///         self.0.lock().unwrap().remove(key);
+///     }
+/// }
+/// ```
+pub trait IdempotencyStore: Send + Sync {
+    /// Look up a key, marking it in progress for a request with
+    /// `fingerprint` if it is unknown.
+    ///
+    /// The fingerprint is stable, so it can be kept in a database.
+    fn start(&self, key: &str, fingerprint: u64) -> KeyState;
+
+    /// Keep the response to the request of a key in progress.
+    fn complete(&self, key: &str, res: CachedResponse);
+
+    /// Forget a key in progress, whose response isn't kept, so a retry
+    /// runs again.
+    fn remove(&self, key: &str);
+}
+
+/// What an [`IdempotencyStore`] knows of a key.
+#[derive(Clone, Debug)]
+pub enum KeyState {
+    /// The key was unknown, and is now in progress.
+    Started,
+    /// The request of the key is in progress.
+    InProgress,
+    /// The request of the key was answered with a response.
+    Completed(CachedResponse),
+    /// The key was started by a request with another fingerprint.
+    Mismatch,
+}
+
+/// A response kept by an [`IdempotencyStore`].
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+/// An [`IdempotencyStore`] in memory, forgetting keys after a while.
+///
+/// The default forgets keys after 24 hours.
+pub struct MemoryStore {
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    // the keys by when they expire, which may have been removed since
+    expiries: VecDeque<(Instant, String)>,
+}
+
+struct Entry {
+    expires_at: Instant,
+    fingerprint: u64,
+    res: Option<CachedResponse>,
+}
+
+pin_project! {
+    /// The response future of an [`Idempotency`] service.
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: Option<F>,
+        answer: Option<Response<Bytes>>,
+        recording: Option<Recording>,
+    }
+}
+
+pin_project! {
+    /// A response body, copied as it is sent if it may be replayed.
+    pub struct IdempotentBody<B> {
+        #[pin]
+        inner: Option<B>,
+        answer: Option<Bytes>,
+        recording: Option<Recording>,
+    }
+}
+
+// The copy of a response in progress, forgetting the key if dropped
+// before it completes.
+struct Recording {
+    store: Arc<dyn IdempotencyStore>,
+    key: String,
+    max_body_size: usize,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: BytesMut,
+    completed: bool,
+}
+
+// ===== impl Idempotency =====
+
+impl<S> Idempotency<S> {
+    /// Wrap a service, keeping responses in a [`MemoryStore`] with the
+    /// default expiry.
+    pub fn new(inner: S) -> Self {
+        Idempotency {
+            inner,
+            store: Arc::new(MemoryStore::default()),
+            scope: None,
+            max_body_size: 1024 * 1024,
+        }
+    }
+
+    /// Keep responses in `store`.
+    pub fn store<St>(mut self, store: St) -> Self
+    where
+        St: IdempotencyStore + 'static,
+    {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Scope keys to what `f` returns for a request, such as the principal
+    /// it is authenticated as, from its extensions.
+    ///
+    /// Requests `f` returns `None` for go to the inner service as if they
+    /// had no key.
+    ///
+    /// Default scopes keys to the method and path only.
+    pub fn scope<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.scope = Some(Arc::new(f));
+        self
+    }
+
+    /// Set the maximum size of a response body that is kept, in bytes.
+    ///
+    /// Larger responses are still sent, but not kept.
+    ///
+    /// Default is 1 MiB.
+    pub fn max_body_size(mut self, max: usize) -> Self {
+        self.max_body_size = max;
+        self
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S: Clone> Clone for Idempotency<S> {
+    fn clone(&self) -> Self {
+        Idempotency {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+            scope: self.scope.clone(),
+            max_body_size: self.max_body_size,
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Idempotency<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Idempotency")
+            .field("inner", &self.inner)
+            .field("scope", &self.scope.is_some())
+            .field("max_body_size", &self.max_body_size)
+            .finish()
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for Idempotency<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Body,
+{
+    type Response = Response<IdempotentBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn call(&self, mut req: Request<ReqBody>) -> Self::Future {
+        let key = match req.headers().get(IDEMPOTENCY_KEY) {
+            Some(key) => key,
+            None => return ResponseFuture::inner(self.inner.call(req), None),
+        };
+        let key = match key.to_str() {
+            Ok(key) if !key.is_empty() => {
+                format!("{} {} {}", req.method(), req.uri().path(), key)
+            }
+            _ => return ResponseFuture::answer(empty(StatusCode::BAD_REQUEST)),
+        };
+        let key = match self.scope {
+            Some(ref scope) => {
+                let (parts, body) = req.into_parts();
+                let scope = scope(&parts);
+                req = Request::from_parts(parts, body);
+                match scope {
+                    // the length keeps a scope from running into the key
+                    Some(scope) => format!("{}:{} {}", scope.len(), scope, key),
+                    None => return ResponseFuture::inner(self.inner.call(req), None),
+                }
+            }
+            None => key,
+        };
+        match self.store.start(&key, fingerprint(&req)) {
+            KeyState::Started => {
+                let recording = Recording {
+                    store: self.store.clone(),
+                    key,
+                    max_body_size: self.max_body_size,
+                    status: StatusCode::OK,
+                    headers: HeaderMap::new(),
+                    body: BytesMut::new(),
+                    completed: false,
+                };
+                ResponseFuture::inner(self.inner.call(req), Some(recording))
+            }
+            KeyState::InProgress => ResponseFuture::answer(empty(StatusCode::CONFLICT)),
+            KeyState::Mismatch => ResponseFuture::answer(empty(StatusCode::UNPROCESSABLE_ENTITY)),
+            KeyState::Completed(res) => {
+                let mut answer = Response::new(res.body);
+                *answer.status_mut() = res.status;
+                *answer.headers_mut() = res.headers;
+                answer
+                    .headers_mut()
+                    .insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+                ResponseFuture::answer(answer)
+            }
+        }
+    }
+}
+
+// An FNV-1a hash of the parts of a request a retry must repeat, other than
+// the method and path, which are in its key.
+fn fingerprint<B>(req: &Request<B>) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325_u64;
+    let mut write = |bytes: &[u8]| {
+        for &b in bytes.iter().chain(b"\n") {
+            hash ^= u64::from(b);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    };
+    write(req.uri().query().unwrap_or("").as_bytes());
+    for name in [header::CONTENT_TYPE, header::CONTENT_LENGTH, CONTENT_DIGEST] {
+        for value in req.headers().get_all(name) {
+            write(value.as_bytes());
+        }
+        write(b"");
+    }
+    hash
+}
+
+fn empty(status: StatusCode) -> Response<Bytes> {
+    let mut res = Response::new(Bytes::new());
+    *res.status_mut() = status;
+    res.headers_mut()
+        .insert(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+    res
+}
+
+// ===== impl CachedResponse =====
+
+impl CachedResponse {
+    /// Create a response, such as when loading it from a database.
+    pub fn new(status: StatusCode, headers: HeaderMap, body: Bytes) -> CachedResponse {
+        CachedResponse {
+            status,
+            headers,
+            body,
+        }
+    }
+
+    /// The status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// The headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The body of the response.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+}
+
+// ===== impl MemoryStore =====
+
+impl MemoryStore {
+    /// Create a store forgetting keys `ttl` after they started.
+    pub fn new(ttl: Duration) -> MemoryStore {
+        MemoryStore {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> MemoryStore {
+        MemoryStore::new(Duration::from_secs(24 * 60 * 60))
+    }
+}
+
+impl IdempotencyStore for MemoryStore {
+    fn start(&self, key: &str, fingerprint: u64) -> KeyState {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.expire(now);
+        if let Some(entry) = entries.by_key.get(key) {
+            return match entry.res {
+                _ if entry.fingerprint != fingerprint => KeyState::Mismatch,
+                Some(ref res) => KeyState::Completed(res.clone()),
+                None => KeyState::InProgress,
+            };
+        }
+        let expires_at = now + self.ttl;
+        entries.by_key.insert(
+            key.to_owned(),
+            Entry {
+                expires_at,
+                fingerprint,
+                res: None,
+            },
+        );
+        entries.expiries.push_back((expires_at, key.to_owned()));
+        KeyState::Started
+    }
+
+    fn complete(&self, key: &str, res: CachedResponse) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.by_key.get_mut(key) {
+            entry.res = Some(res);
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().by_key.remove(key);
+    }
+}
+
+impl fmt::Debug for MemoryStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryStore")
+            .field("ttl", &self.ttl)
+            .field("keys", &self.entries.lock().unwrap().by_key.len())
+            .finish()
+    }
+}
+
+impl Entries {
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(expires_at, _)) = self.expiries.front() {
+            if expires_at > now {
+                break;
+            }
+            let (_, key) = self.expiries.pop_front().expect("front exists");
+            // the key may have been removed, and started again since
+            if self
+                .by_key
+                .get(&key)
+                .map_or(false, |entry| entry.expires_at == expires_at)
+            {
+                self.by_key.remove(&key);
+            }
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F> ResponseFuture<F> {
+    fn inner(inner: F, recording: Option<Recording>) -> Self {
+        ResponseFuture {
+            inner: Some(inner),
+            answer: None,
+            recording,
+        }
+    }
+
+    fn answer(answer: Response<Bytes>) -> Self {
+        ResponseFuture {
+            inner: None,
+            answer: Some(answer),
+            recording: None,
+        }
+    }
+}
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body,
+{
+    type Output = Result<Response<IdempotentBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Some(answer) = this.answer.take() {
+            return Poll::Ready(Ok(answer.map(|body| IdempotentBody {
+                inner: None,
+                answer: Some(body).filter(|body| !body.is_empty()),
+                recording: None,
+            })));
+        }
+        let inner = match this.inner.as_pin_mut() {
+            Some(inner) => inner,
+            None => panic!("ResponseFuture polled after completion"),
+        };
+        // an error drops the recording, forgetting the key
+        let res = futures_util::ready!(inner.poll(cx))?;
+        let recording = this
+            .recording
+            .take()
+            .filter(|_| !res.status().is_server_error())
+            .map(|mut recording| {
+                recording.status = res.status();
+                recording.headers = res.headers().clone();
+                recording
+            });
+        let mut body = IdempotentBody {
+            inner: None,
+            answer: None,
+            recording,
+        };
+        let res = res.map(|inner| {
+            if inner.is_end_stream() {
+                body.complete();
+            }
+            body.inner = Some(inner);
+            body
+        });
+        Poll::Ready(Ok(res))
+    }
+}
+
+impl<F> fmt::Debug for ResponseFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture")
+            .field("recording", &self.recording)
+            .finish()
+    }
+}
+
+// ===== impl IdempotentBody =====
+
+impl<B> IdempotentBody<B> {
+    fn complete(&mut self) {
+        if let Some(recording) = self.recording.take() {
+            recording.complete();
+        }
+    }
+}
+
+impl<B: Body> Body for IdempotentBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let mut inner = match this.inner.as_pin_mut() {
+            Some(inner) => inner,
+            None => return Poll::Ready(this.answer.take().map(|data| Ok(Frame::data(data)))),
+        };
+        let frame = match futures_util::ready!(inner.as_mut().poll_frame(cx)) {
+            Some(Ok(frame)) => frame.map_data(|mut data| data.copy_to_bytes(data.remaining())),
+            Some(Err(err)) => {
+                *this.recording = None;
+                return Poll::Ready(Some(Err(err)));
+            }
+            None => {
+                if let Some(recording) = this.recording.take() {
+                    recording.complete();
+                }
+                return Poll::Ready(None);
+            }
+        };
+        match frame.data_ref() {
+            Some(data) => {
+                let fits = this
+                    .recording
+                    .as_mut()
+                    .map_or(true, |recording| recording.record(data));
+                if !fits {
+                    *this.recording = None;
+                }
+                // hyper stops polling a body once it says it ended
+                if inner.is_end_stream() {
+                    if let Some(recording) = this.recording.take() {
+                        recording.complete();
+                    }
+                }
+            }
+            // trailers aren't replayed
+            None => *this.recording = None,
+        }
+        Poll::Ready(Some(Ok(frame)))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.inner {
+            Some(ref inner) => inner.is_end_stream(),
+            None => self.answer.is_none(),
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.inner {
+            Some(ref inner) => inner.size_hint(),
+            None => SizeHint::with_exact(self.answer.as_ref().map_or(0, |data| data.len() as u64)),
+        }
+    }
+}
+
+impl<B: fmt::Debug> fmt::Debug for IdempotentBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdempotentBody")
+            .field("inner", &self.inner)
+            .field("recording", &self.recording)
+            .finish()
+    }
+}
+
+// ===== impl Recording =====
+
+impl Recording {
+    // Copy some data, returning false if the body got too large.
+    fn record(&mut self, data: &[u8]) -> bool {
+        if self.body.len() + data.len() > self.max_body_size {
+            return false;
+        }
+        self.body.extend_from_slice(data);
+        true
+    }
+
+    fn complete(mut self) {
+        self.completed = true;
+        let res = CachedResponse {
+            status: self.status,
+            headers: std::mem::take(&mut self.headers),
+            body: std::mem::take(&mut self.body).freeze(),
+        };
+        self.store.complete(&self.key, res);
+    }
+}
+
+impl Drop for Recording {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.store.remove(&self.key);
+        }
+    }
+}
+
+impl fmt::Debug for Recording {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Recording")
+            .field("key", &self.key)
+            .field("recorded", &self.body.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use bytes::Bytes;
+    use http::{Request, Response, StatusCode};
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::service::{service_fn, Service};
+
+    use super::{Idempotency, IdempotentBody};
+
+    #[tokio::test]
+    async fn replays_completed_responses() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let service = Idempotency::new(service_fn(move |req: Request<Empty<Bytes>>| {
+            let call = counted.fetch_add(1, Ordering::SeqCst);
+            let mut res = Response::new(Full::new(Bytes::from(format!("call {}", call))));
+            if req.uri().path() == "/fail" {
+                *res.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            }
+            async move { Ok::<_, Infallible>(res) }
+        }))
+        .max_body_size(16);
+
+        let request = |path: &str, key: Option<&str>| {
+            let mut req = Request::post(path).body(Empty::new()).unwrap();
+            if let Some(key) = key {
+                req.headers_mut()
+                    .insert("idempotency-key", key.parse().unwrap());
+            }
+            service.call(req)
+        };
+        let body = |res: Response<IdempotentBody<Full<Bytes>>>| async move {
+            let replayed = res.headers().contains_key("idempotent-replayed");
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            (body, replayed)
+        };
+
+        let first = request("/pay", Some("a")).await.unwrap();
+        // in progress until the body is sent
+        let conflict = request("/pay", Some("a")).await.unwrap();
+        assert_eq!(conflict.status(), StatusCode::CONFLICT);
+        assert_eq!(body(first).await, (Bytes::from("call 0"), false));
+        let retry = request("/pay", Some("a")).await.unwrap();
+        assert_eq!(body(retry).await, (Bytes::from("call 0"), true));
+
+        // scoped to the path
+        let other = request("/refund", Some("a")).await.unwrap();
+        assert_eq!(body(other).await, (Bytes::from("call 1"), false));
+        let unkeyed = request("/pay", None).await.unwrap();
+        assert_eq!(body(unkeyed).await, (Bytes::from("call 2"), false));
+
+        // server errors aren't kept
+        for call in 3..5 {
+            let res = request("/fail", Some("b")).await.unwrap();
+            assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(body(res).await.0, format!("call {}", call));
+        }
+
+        // a dropped response forgets the key
+        drop(request("/pay", Some("c")).await.unwrap());
+        let retry = request("/pay", Some("c")).await.unwrap();
+        assert_eq!(body(retry).await, (Bytes::from("call 6"), false));
+        assert_eq!(calls.load(Ordering::SeqCst), 7);
+    }
+
+    #[tokio::test]
+    async fn scopes_keys_and_rejects_mismatches() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let service =
+            Idempotency::new(service_fn(move |_req: Request<Empty<Bytes>>| {
+                let call = counted.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(call.to_string()))))
+                }
+            }))
+            .scope(|req| {
+                req.headers
+                    .get("x-user")
+                    .and_then(|user| user.to_str().ok())
+                    .map(str::to_owned)
+            });
+
+        let request = |uri: &str, user: Option<&str>| {
+            let mut req = Request::post(uri)
+                .header("idempotency-key", "a")
+                .body(Empty::new())
+                .unwrap();
+            if let Some(user) = user {
+                req.headers_mut().insert("x-user", user.parse().unwrap());
+            }
+            service.call(req)
+        };
+        let body = |res: Response<IdempotentBody<Full<Bytes>>>| async move {
+            let status = res.status();
+            let body = res.into_body().collect().await.unwrap().to_bytes();
+            (status, body)
+        };
+
+        assert_eq!(
+            body(request("/pay?n=1", Some("ann")).await.unwrap())
+                .await
+                .1,
+            "0"
+        );
+        // another user's key is another key
+        assert_eq!(
+            body(request("/pay?n=1", Some("bob")).await.unwrap())
+                .await
+                .1,
+            "1"
+        );
+        assert_eq!(
+            body(request("/pay?n=1", Some("ann")).await.unwrap())
+                .await
+                .1,
+            "0"
+        );
+        // the same key for another request
+        assert_eq!(
+            body(request("/pay?n=2", Some("ann")).await.unwrap()).await,
+            (StatusCode::UNPROCESSABLE_ENTITY, Bytes::new())
+        );
+        // unscoped requests aren't deduplicated
+        for call in 2..4 {
+            let res = request("/pay?n=1", None).await.unwrap();
+            assert_eq!(body(res).await.1, call.to_string());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+}
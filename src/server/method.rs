@@ -0,0 +1,323 @@
+//! Method policies.
+//!
+//! This module provides a [`MethodPolicy`] service, which answers the
+//! requests whose method an operator doesn't want to reach another
+//! service:
+//!
+//! - `TRACE` is rejected, since echoing requests can leak credentials.
+//! - `OPTIONS *`, which asks about the server rather than a resource, is
+//!   answered with the allowed methods.
+//! - Methods outside of an allowed list are rejected with
+//!   `405 Method Not Allowed` and an `Allow` header.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//!
+//! use bytes::Bytes;
+//! use http::{Method, Request, Response};
+//! use http_body_util::Full;
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::server::method::MethodPolicy;
+//!
+//! let service = MethodPolicy::new(service_fn(|_req: Request<Incoming>| async {
+//!     Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("hello"))))
+//! }))
+//! .allow([Method::GET, Method::POST]);
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::header::{self, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use hyper::service::Service;
+use pin_project_lite::pin_project;
+
+/// A service that answers the requests with a disallowed method, and
+/// passes the others to another service.
+///
+/// By default, `TRACE` is rejected and `OPTIONS *` is answered, and every
+/// other method is allowed. Answered requests don't reach the inner
+/// service, and get an empty body.
+#[derive(Clone, Debug)]
+pub struct MethodPolicy<S> {
+    inner: S,
+    config: Arc<Config>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    reject_trace: bool,
+    answer_options: bool,
+    allowed: Option<Vec<Method>>,
+    allow_header: Option<HeaderValue>,
+}
+
+pin_project! {
+    /// The response future of a [`MethodPolicy`] service.
+    #[derive(Debug)]
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: Option<F>,
+        answer: Option<Response<()>>,
+    }
+}
+
+pin_project! {
+    /// A response body, empty if the policy answered the request.
+    #[derive(Debug)]
+    pub struct PolicyBody<B> {
+        #[pin]
+        inner: Option<B>,
+    }
+}
+
+// ===== impl MethodPolicy =====
+
+impl<S> MethodPolicy<S> {
+    /// Wrap a service, with the default policy.
+    pub fn new(inner: S) -> Self {
+        MethodPolicy {
+            inner,
+            config: Arc::new(Config {
+                reject_trace: true,
+                answer_options: true,
+                allowed: None,
+                allow_header: None,
+            }),
+        }
+    }
+
+    /// Set whether `TRACE` requests are rejected.
+    ///
+    /// They are rejected with `405 Method Not Allowed` if there is an
+    /// allowed list, or else `501 Not Implemented`, even if the list
+    /// includes `TRACE`.
+    ///
+    /// Default is `true`.
+    pub fn reject_trace(mut self, enabled: bool) -> Self {
+        Arc::make_mut(&mut self.config).reject_trace = enabled;
+        self
+    }
+
+    /// Set whether `OPTIONS *` requests are answered.
+    ///
+    /// They are answered with `200 OK`, and an `Allow` header if there is
+    /// an allowed list.
+    ///
+    /// Default is `true`.
+    pub fn answer_options(mut self, enabled: bool) -> Self {
+        Arc::make_mut(&mut self.config).answer_options = enabled;
+        self
+    }
+
+    /// Set the methods that are allowed, rejecting the others with
+    /// `405 Method Not Allowed`.
+    ///
+    /// `HEAD` is allowed along with `GET`. `OPTIONS *` is answered even if
+    /// `OPTIONS` isn't allowed.
+    ///
+    /// Default is to allow every method.
+    pub fn allow<I>(mut self, methods: I) -> Self
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        let config = Arc::make_mut(&mut self.config);
+        let mut allowed = Vec::new();
+        for method in methods {
+            if method == Method::GET && !allowed.contains(&Method::HEAD) {
+                allowed.push(Method::HEAD);
+            }
+            if !allowed.contains(&method) {
+                allowed.push(method);
+            }
+        }
+        let header = allowed
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        config.allow_header = HeaderValue::from_str(&header).ok();
+        config.allowed = Some(allowed);
+        self
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for MethodPolicy<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+{
+    type Response = Response<PolicyBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        match self.config.answer(&req) {
+            Some(answer) => ResponseFuture {
+                inner: None,
+                answer: Some(answer),
+            },
+            None => ResponseFuture {
+                inner: Some(self.inner.call(req)),
+                answer: None,
+            },
+        }
+    }
+}
+
+// ===== impl Config =====
+
+impl Config {
+    // The response to send instead of calling the service, if any.
+    fn answer<B>(&self, req: &Request<B>) -> Option<Response<()>> {
+        let method = req.method();
+        let status = if *method == Method::TRACE && self.reject_trace {
+            if self.allowed.is_some() {
+                StatusCode::METHOD_NOT_ALLOWED
+            } else {
+                StatusCode::NOT_IMPLEMENTED
+            }
+        } else if *method == Method::OPTIONS && self.answer_options && req.uri() == "*" {
+            StatusCode::OK
+        } else if self.allowed.as_ref().map_or(false, |a| !a.contains(method)) {
+            StatusCode::METHOD_NOT_ALLOWED
+        } else {
+            return None;
+        };
+
+        let mut res = Response::new(());
+        *res.status_mut() = status;
+        if status != StatusCode::NOT_IMPLEMENTED {
+            if let Some(ref allow) = self.allow_header {
+                res.headers_mut().insert(header::ALLOW, allow.clone());
+            }
+        }
+        res.headers_mut()
+            .insert(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
+        Some(res)
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+{
+    type Output = Result<Response<PolicyBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if let Some(answer) = this.answer.take() {
+            return Poll::Ready(Ok(answer.map(|()| PolicyBody { inner: None })));
+        }
+        match this.inner.as_pin_mut() {
+            Some(inner) => inner
+                .poll(cx)
+                .map_ok(|res| res.map(|body| PolicyBody { inner: Some(body) })),
+            None => panic!("ResponseFuture polled after completion"),
+        }
+    }
+}
+
+// ===== impl PolicyBody =====
+
+impl<B: Body> Body for PolicyBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        match self.project().inner.as_pin_mut() {
+            Some(inner) => inner.poll_frame(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.as_ref().map_or(true, Body::is_end_stream)
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner
+            .as_ref()
+            .map_or_else(|| SizeHint::with_exact(0), Body::size_hint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use http::{header, Method, Request, Response, StatusCode};
+    use http_body_util::{Empty, Full};
+    use hyper::service::{service_fn, Service};
+
+    use super::MethodPolicy;
+
+    fn service() -> MethodPolicy<
+        impl Service<Request<Empty<Bytes>>, Response = Response<Full<Bytes>>, Error = Infallible>,
+    > {
+        MethodPolicy::new(service_fn(|_req: Request<Empty<Bytes>>| async {
+            Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("hello"))))
+        }))
+    }
+
+    fn request(method: Method, uri: &str) -> Request<Empty<Bytes>> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Empty::new())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn default_policy() {
+        let service = service();
+
+        let res = service.call(request(Method::TRACE, "/")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_IMPLEMENTED);
+
+        let res = service.call(request(Method::OPTIONS, "*")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key(header::ALLOW));
+
+        let res = service.call(request(Method::DELETE, "/")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res.body().inner.is_some());
+    }
+
+    #[tokio::test]
+    async fn allowed_methods() {
+        let service = service().allow([Method::GET, Method::POST]);
+
+        for method in [Method::GET, Method::HEAD, Method::POST] {
+            let res = service.call(request(method, "/")).await.unwrap();
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+
+        for method in [Method::PUT, Method::TRACE] {
+            let res = service.call(request(method, "/")).await.unwrap();
+            assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+            assert_eq!(res.headers()[header::ALLOW], "HEAD, GET, POST");
+        }
+
+        let res = service.call(request(Method::OPTIONS, "*")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::ALLOW], "HEAD, GET, POST");
+    }
+}
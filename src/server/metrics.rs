@@ -0,0 +1,125 @@
+//! Metrics of the servers, with the `metrics` feature.
+//!
+//! They are recorded with the `metrics` facade, like those of the client.
+//! Without the feature, recording does nothing and the guards are empty.
+#![allow(dead_code)]
+#![cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+
+use std::time::Instant;
+
+use http::StatusCode;
+
+pub(crate) const CONNECTIONS: &str = "hyper_util_server_connections_total";
+pub(crate) const CONNECTIONS_ACTIVE: &str = "hyper_util_server_connections_active";
+pub(crate) const HANDSHAKE_FAILURES: &str = "hyper_util_server_handshake_failures_total";
+pub(crate) const REQUESTS_ACTIVE: &str = "hyper_util_server_requests_active";
+pub(crate) const REQUEST_DURATION: &str = "hyper_util_server_request_duration_seconds";
+pub(crate) const BYTES_READ: &str = "hyper_util_server_bytes_read_total";
+pub(crate) const BYTES_WRITTEN: &str = "hyper_util_server_bytes_written_total";
+pub(crate) const REJECTED_HEADERS: &str = "hyper_util_server_rejected_headers_total";
+
+/// Whether anything is recorded.
+pub(crate) const ENABLED: bool = cfg!(feature = "metrics");
+
+/// The protocol of a connection is known, `"http1"` or `"http2"`.
+pub(crate) fn connection_protocol(protocol: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::increment_counter!(CONNECTIONS, "protocol" => protocol);
+}
+
+/// Reading the start of a connection failed, at `stage`: `"version"`
+/// when detecting the HTTP version, `"sni"` when peeking at a TLS
+/// ClientHello, or `"detect"` when detecting the protocol.
+pub(crate) fn handshake_failed(stage: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::increment_counter!(HANDSHAKE_FAILURES, "stage" => stage);
+}
+
+/// A request was rejected for its headers, with `reason`: `"value"`,
+/// `"duplicate"` or `"size"`.
+pub(crate) fn headers_rejected(reason: &'static str) {
+    #[cfg(feature = "metrics")]
+    ::metrics::increment_counter!(REJECTED_HEADERS, "reason" => reason);
+}
+
+/// An open connection, counting its bytes.
+pub(crate) struct Connection {
+    #[cfg(feature = "metrics")]
+    bytes_read: ::metrics::Counter,
+    #[cfg(feature = "metrics")]
+    bytes_written: ::metrics::Counter,
+}
+
+impl Connection {
+    pub(crate) fn new() -> Connection {
+        #[cfg(feature = "metrics")]
+        ::metrics::increment_gauge!(CONNECTIONS_ACTIVE, 1.0);
+        Connection {
+            #[cfg(feature = "metrics")]
+            bytes_read: ::metrics::register_counter!(BYTES_READ),
+            #[cfg(feature = "metrics")]
+            bytes_written: ::metrics::register_counter!(BYTES_WRITTEN),
+        }
+    }
+
+    pub(crate) fn read(&self, n: usize) {
+        #[cfg(feature = "metrics")]
+        self.bytes_read.increment(n as u64);
+    }
+
+    pub(crate) fn written(&self, n: usize) {
+        #[cfg(feature = "metrics")]
+        self.bytes_written.increment(n as u64);
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        ::metrics::decrement_gauge!(CONNECTIONS_ACTIVE, 1.0);
+    }
+}
+
+/// A request being handled, until its response is done.
+pub(crate) struct Request {
+    start: Instant,
+}
+
+impl Request {
+    pub(crate) fn new() -> Request {
+        #[cfg(feature = "metrics")]
+        ::metrics::increment_gauge!(REQUESTS_ACTIVE, 1.0);
+        Request {
+            start: Instant::now(),
+        }
+    }
+
+    /// The response head is ready.
+    pub(crate) fn responded(&self, status: StatusCode) {
+        #[cfg(feature = "metrics")]
+        ::metrics::histogram!(
+            REQUEST_DURATION,
+            self.start.elapsed().as_secs_f64(),
+            "status_class" => status_class(status),
+        );
+    }
+}
+
+impl Drop for Request {
+    fn drop(&mut self) {
+        #[cfg(feature = "metrics")]
+        ::metrics::decrement_gauge!(REQUESTS_ACTIVE, 1.0);
+    }
+}
+
+#[cfg(feature = "metrics")]
+fn status_class(status: StatusCode) -> &'static str {
+    match status.as_u16() / 100 {
+        1 => "1xx",
+        2 => "2xx",
+        3 => "3xx",
+        4 => "4xx",
+        5 => "5xx",
+        _ => "other",
+    }
+}
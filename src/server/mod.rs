@@ -1,6 +1,25 @@
 //! Server utilities.
 
+#[cfg(feature = "tokio")]
+pub mod accept;
+#[cfg(feature = "tokio")]
+pub mod body_timeout;
+#[cfg(feature = "server-compression")]
+pub mod compression;
 pub mod conn;
+pub mod forwarded;
+pub mod framing;
+pub mod grpc;
+pub mod headers;
+pub mod idempotency;
+pub mod method;
+mod metrics;
+pub mod mux;
+pub mod trace_context;
+pub mod trailers;
 
 #[cfg(feature = "server-graceful")]
 pub mod graceful;
+
+#[cfg(feature = "server-sni")]
+pub mod sni;
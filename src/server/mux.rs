@@ -0,0 +1,359 @@
+//! Serving several protocols on one port.
+//!
+//! This module provides [`detect`], which reads the first bytes of a
+//! connection to tell which protocol the client speaks, and [`Mux`] to route
+//! a connection to a handler for it. The bytes read are replayed to the
+//! handler, like the auto connection builder does when it detects the HTTP
+//! version.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use hyper_util::rt::TokioIo;
+//! use hyper_util::server::mux::{Mux, Protocol};
+//!
+//! let mux = Mux::new()
+//!     .route(Protocol::Tls, "tls")
+//!     .route(Protocol::Http, "http")
+//!     .fallback("reject");
+//!
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:8000").await?;
+//! loop {
+//!     let (stream, _) = listener.accept().await?;
+//!     let (handler, protocol, io) = mux.accept(TokioIo::new(stream)).await?;
+//!     // hand `io` to `handler`...
+//! #   drop((handler, protocol, io));
+//! }
+//! # }
+//! ```
+
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use bytes::Bytes;
+use hyper::rt::{Read, ReadBuf, ReadBufCursor, Write};
+
+use crate::common::rewind::Rewind;
+
+// Enough for the longest signature, the PROXY protocol v2 one, and an HTTP
+// method.
+const MAX_PEEK: usize = 24;
+
+const PROXY_V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+const SIGNATURES: &[(&[u8], Protocol)] = &[
+    // a handshake record, with a 3.x version
+    (b"\x16\x03", Protocol::Tls),
+    (b"PROXY ", Protocol::ProxyV1),
+    (PROXY_V2_SIGNATURE, Protocol::ProxyV2),
+    (b"SSH-", Protocol::Ssh),
+];
+
+/// The protocol a connection starts with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Protocol {
+    /// A TLS handshake.
+    Tls,
+    /// Plaintext HTTP/1, or HTTP/2 with prior knowledge.
+    Http,
+    /// The PROXY protocol, version 1.
+    ProxyV1,
+    /// The PROXY protocol, version 2.
+    ProxyV2,
+    /// The identification string of SSH.
+    Ssh,
+    /// None of the others.
+    Unknown,
+}
+
+/// Routes connections to handlers by the protocol they start with.
+#[derive(Clone, Debug)]
+pub struct Mux<T> {
+    routes: Vec<(Protocol, T)>,
+    fallback: Option<T>,
+}
+
+/// A connection whose first bytes have been read, to detect its protocol.
+///
+/// Reading from it first yields the bytes that were read, then continues
+/// with the inner connection.
+#[derive(Debug)]
+pub struct Prefixed<I> {
+    inner: Rewind<I>,
+}
+
+/// Detect the protocol of a connection.
+///
+/// This reads until the protocol is known, at most 24 bytes. A connection
+/// that closes before sending enough to tell is [`Protocol::Unknown`], and
+/// one that closes before sending anything is an error of kind
+/// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof).
+///
+/// A client that connects and sends nothing keeps this waiting, so it
+/// should be given a timeout.
+pub async fn detect<I>(io: I) -> io::Result<(Protocol, Prefixed<I>)>
+where
+    I: Read + Unpin,
+{
+    let res = Detect {
+        io: Some(io),
+        buf: [0; MAX_PEEK],
+        filled: 0,
+    }
+    .await;
+    if res.is_err() {
+        super::metrics::handshake_failed("detect");
+    }
+    res
+}
+
+fn classify(buf: &[u8], done: bool) -> Option<Protocol> {
+    let mut pending = false;
+    for &(signature, protocol) in SIGNATURES {
+        if buf.len() >= signature.len() {
+            if buf.starts_with(signature) {
+                return Some(protocol);
+            }
+        } else if signature.starts_with(buf) {
+            pending = true;
+        }
+    }
+
+    // an HTTP request line starts with an uppercase method and a space,
+    // which includes the `PRI` of the HTTP/2 preface
+    match buf.iter().position(|&b| b == b' ') {
+        Some(i) if i > 0 && buf[..i].iter().all(u8::is_ascii_uppercase) => {
+            return Some(Protocol::Http)
+        }
+        None if buf.iter().all(u8::is_ascii_uppercase) => pending = true,
+        _ => {}
+    }
+
+    if pending && !done {
+        None
+    } else {
+        Some(Protocol::Unknown)
+    }
+}
+
+struct Detect<I> {
+    io: Option<I>,
+    buf: [u8; MAX_PEEK],
+    filled: usize,
+}
+
+impl<I> Future for Detect<I>
+where
+    I: Read + Unpin,
+{
+    type Output = io::Result<(Protocol, Prefixed<I>)>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        let protocol = loop {
+            let mut buf = ReadBuf::new(&mut this.buf[this.filled..]);
+            futures_util::ready!(Pin::new(this.io.as_mut().expect("polled after ready"))
+                .poll_read(cx, buf.unfilled()))?;
+            let n = buf.filled().len();
+            if n == 0 {
+                if this.filled == 0 {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before sending any bytes",
+                    )));
+                }
+                break classify(&this.buf[..this.filled], true).expect("done");
+            }
+            this.filled += n;
+            let done = this.filled == MAX_PEEK;
+            if let Some(protocol) = classify(&this.buf[..this.filled], done) {
+                break protocol;
+            }
+        };
+
+        let io = this.io.take().expect("polled after ready");
+        let buf = Bytes::copy_from_slice(&this.buf[..this.filled]);
+        Poll::Ready(Ok((
+            protocol,
+            Prefixed {
+                inner: Rewind::new_buffered(io, buf),
+            },
+        )))
+    }
+}
+
+// ===== impl Mux =====
+
+impl<T> Mux<T> {
+    /// Create a mux without any routes.
+    pub fn new() -> Self {
+        Mux {
+            routes: Vec::new(),
+            fallback: None,
+        }
+    }
+
+    /// Route connections that start with `protocol` to `target`.
+    pub fn route(mut self, protocol: Protocol, target: T) -> Self {
+        self.routes.retain(|(p, _)| *p != protocol);
+        self.routes.push((protocol, target));
+        self
+    }
+
+    /// Route connections of every other protocol to `target`.
+    pub fn fallback(mut self, target: T) -> Self {
+        self.fallback = Some(target);
+        self
+    }
+
+    /// Find the target for a protocol.
+    pub fn get(&self, protocol: Protocol) -> Option<&T> {
+        self.routes
+            .iter()
+            .find(|(p, _)| *p == protocol)
+            .map(|(_, t)| t)
+            .or(self.fallback.as_ref())
+    }
+
+    /// Detect the protocol of a connection, and find its target.
+    ///
+    /// See [`detect`] for how the protocol is detected.
+    pub async fn accept<I>(&self, io: I) -> io::Result<(Option<&T>, Protocol, Prefixed<I>)>
+    where
+        I: Read + Unpin,
+    {
+        let (protocol, io) = detect(io).await?;
+        Ok((self.get(protocol), protocol, io))
+    }
+}
+
+impl<T> Default for Mux<T> {
+    fn default() -> Self {
+        Mux::new()
+    }
+}
+
+// ===== impl Prefixed =====
+
+impl<I> Prefixed<I> {
+    /// Get a reference to the inner connection.
+    pub fn get_ref(&self) -> &I {
+        self.inner.get_ref()
+    }
+
+    /// Consume this, returning the inner connection and the bytes read
+    /// from it that haven't been replayed yet.
+    pub fn into_inner(self) -> (I, Bytes) {
+        self.inner.into_inner()
+    }
+}
+
+impl<I> Read for Prefixed<I>
+where
+    I: Read + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<I> Write for Prefixed<I>
+where
+    I: Write + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+
+    use super::{classify, detect, Mux, Protocol};
+    use crate::rt::TokioIo;
+
+    #[test]
+    fn classifies() {
+        assert_eq!(
+            classify(b"\x16\x03\x01\x02\x00", false),
+            Some(Protocol::Tls)
+        );
+        assert_eq!(classify(b"GET / HTTP/1.1\r\n", false), Some(Protocol::Http));
+        assert_eq!(classify(b"PRI * HTTP/2.0", false), Some(Protocol::Http));
+        assert_eq!(classify(b"PROXY TCP4 ", false), Some(Protocol::ProxyV1));
+        assert_eq!(
+            classify(b"\r\n\r\n\0\r\nQUIT\n\x21", false),
+            Some(Protocol::ProxyV2)
+        );
+        assert_eq!(classify(b"SSH-2.0-OpenSSH", false), Some(Protocol::Ssh));
+        assert_eq!(classify(b"hello", false), Some(Protocol::Unknown));
+
+        // not enough to tell yet
+        assert_eq!(classify(b"\x16", false), None);
+        assert_eq!(classify(b"PROX", false), None);
+        assert_eq!(classify(b"\r\n\r\n", false), None);
+        assert_eq!(classify(b"PROX", true), Some(Protocol::Unknown));
+    }
+
+    #[tokio::test]
+    async fn detects_and_replays() {
+        let (client, server) = tokio::io::duplex(64);
+        let write = tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            let mut client = client;
+            client.write_all(b"SS").await.unwrap();
+            tokio::task::yield_now().await;
+            client.write_all(b"H-2.0-test\r\n").await.unwrap();
+            client
+        });
+
+        let mux = Mux::new().route(Protocol::Ssh, "ssh").fallback("other");
+        let (target, protocol, io) = mux.accept(TokioIo::new(server)).await.unwrap();
+        assert_eq!((target, protocol), (Some(&"ssh"), Protocol::Ssh));
+        assert_eq!(mux.get(Protocol::Tls), Some(&"other"));
+
+        let client = write.await.unwrap();
+        drop(client);
+        let mut replayed = Vec::new();
+        TokioIo::new(io).read_to_end(&mut replayed).await.unwrap();
+        assert_eq!(replayed, b"SSH-2.0-test\r\n");
+
+        let (client, server) = tokio::io::duplex(64);
+        drop(client);
+        let err = detect(TokioIo::new(server)).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
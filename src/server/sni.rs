@@ -0,0 +1,494 @@
+//! Route TLS connections by the server name they ask for.
+//!
+//! This reads the TLS `ClientHello` at the start of a connection to find the
+//! server name (SNI) the client sent, without terminating TLS. The bytes read
+//! are replayed by the returned [`Peeked`] stream, so it can be handed to a
+//! TLS acceptor, or proxied to a backend, as if nothing had been read.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use hyper_util::server::sni::SniRouter;
+//!
+//! let router = SniRouter::new()
+//!     .route("api.example.com", "api")
+//!     .route("*.example.com", "web")
+//!     .fallback("default");
+//!
+//! let listener = tokio::net::TcpListener::bind("0.0.0.0:443").await?;
+//! loop {
+//!     let (stream, _) = listener.accept().await?;
+//!     let (backend, stream) = router.accept(stream).await?;
+//!     // hand `stream` to the TLS acceptor of `backend`...
+//! #   drop((backend, stream));
+//! }
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::task::{self, Poll};
+
+use bytes::{Buf, Bytes};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+type BoxError = Box<dyn StdError + Send + Sync>;
+
+const CONTENT_TYPE_HANDSHAKE: u8 = 22;
+const HANDSHAKE_CLIENT_HELLO: u8 = 1;
+const EXTENSION_SERVER_NAME: u16 = 0;
+const NAME_TYPE_HOST_NAME: u8 = 0;
+// The most a record can hold, with room for the expansion allowed for
+// compressed records.
+const MAX_RECORD_LEN: usize = (1 << 14) + 1024;
+// A `ClientHello` can span records, but legitimate ones are far smaller.
+const MAX_HELLO_LEN: usize = 64 * 1024;
+
+/// Routes connections to targets by their server name.
+///
+/// Names are matched case-insensitively. A name starting with `*.` matches
+/// exactly one more label, so `*.example.com` matches `www.example.com`, but
+/// not `example.com` or `a.www.example.com`. Exact names win over wildcards.
+#[derive(Clone, Debug)]
+pub struct SniRouter<T> {
+    exact: HashMap<String, T>,
+    wildcard: HashMap<String, T>,
+    fallback: Option<T>,
+}
+
+/// A stream whose `ClientHello` has been read, to find its server name.
+///
+/// Reading from it first yields the bytes that were read, then continues
+/// with the inner stream.
+#[derive(Debug)]
+pub struct Peeked<I> {
+    io: I,
+    buf: Bytes,
+    server_name: Option<String>,
+}
+
+/// An error reading the `ClientHello` of a connection.
+pub struct Error {
+    kind: Kind,
+    source: Option<BoxError>,
+}
+
+#[derive(Debug)]
+enum Kind {
+    Io,
+    NotTls,
+    Malformed,
+    TooLarge,
+}
+
+/// Read the `ClientHello` at the start of `io`, to find its server name.
+pub async fn peek<I>(io: I) -> Result<Peeked<I>, Error>
+where
+    I: AsyncRead + Unpin,
+{
+    let res = read_hello(io).await;
+    if res.is_err() {
+        super::metrics::handshake_failed("sni");
+    }
+    res
+}
+
+async fn read_hello<I>(mut io: I) -> Result<Peeked<I>, Error>
+where
+    I: AsyncRead + Unpin,
+{
+    let mut raw = Vec::new();
+    let mut hello = Vec::new();
+    loop {
+        let start = raw.len();
+        raw.resize(start + 5, 0);
+        io.read_exact(&mut raw[start..]).await.map_err(Error::io)?;
+        if raw[start] != CONTENT_TYPE_HANDSHAKE {
+            return Err(Error::new(Kind::NotTls));
+        }
+        let len = usize::from(u16::from_be_bytes([raw[start + 3], raw[start + 4]]));
+        if len > MAX_RECORD_LEN {
+            return Err(Error::new(Kind::Malformed));
+        }
+        if raw.len() + len > MAX_HELLO_LEN {
+            return Err(Error::new(Kind::TooLarge));
+        }
+
+        let start = raw.len();
+        raw.resize(start + len, 0);
+        io.read_exact(&mut raw[start..]).await.map_err(Error::io)?;
+        hello.extend_from_slice(&raw[start..]);
+
+        if hello.len() < 4 {
+            continue;
+        }
+        if hello[0] != HANDSHAKE_CLIENT_HELLO {
+            return Err(Error::new(Kind::Malformed));
+        }
+        let hello_len =
+            4 + ((hello[1] as usize) << 16 | (hello[2] as usize) << 8 | hello[3] as usize);
+        if hello_len > MAX_HELLO_LEN {
+            return Err(Error::new(Kind::TooLarge));
+        }
+        if hello.len() >= hello_len {
+            let server_name =
+                server_name(&hello[4..hello_len]).ok_or_else(|| Error::new(Kind::Malformed))?;
+            return Ok(Peeked {
+                io,
+                buf: Bytes::from(raw),
+                server_name,
+            });
+        }
+    }
+}
+
+// Parse the body of a `ClientHello` down to its server name, returning
+// `None` if it's malformed.
+fn server_name(hello: &[u8]) -> Option<Option<String>> {
+    let mut hello = Reader(hello);
+    // legacy_version, random
+    hello.take(2 + 32)?;
+    // legacy_session_id, cipher_suites, legacy_compression_methods
+    hello.take_u8_len()?;
+    hello.take_u16_len()?;
+    hello.take_u8_len()?;
+    if hello.0.is_empty() {
+        // extensions are optional
+        return Some(None);
+    }
+
+    let mut extensions = Reader(hello.take_u16_len()?);
+    while !extensions.0.is_empty() {
+        let kind = extensions.u16()?;
+        let mut data = Reader(extensions.take_u16_len()?);
+        if kind != EXTENSION_SERVER_NAME {
+            continue;
+        }
+        let mut names = Reader(data.take_u16_len()?);
+        while !names.0.is_empty() {
+            let name_type = names.u8()?;
+            let name = names.take_u16_len()?;
+            if name_type == NAME_TYPE_HOST_NAME {
+                let name = std::str::from_utf8(name).ok()?;
+                return Some(Some(normalize(name)));
+            }
+        }
+        return Some(None);
+    }
+    Some(None)
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(n);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn take_u8_len(&mut self) -> Option<&'a [u8]> {
+        let len = self.u8()?;
+        self.take(usize::from(len))
+    }
+
+    fn take_u16_len(&mut self) -> Option<&'a [u8]> {
+        let len = self.u16()?;
+        self.take(usize::from(len))
+    }
+}
+
+// ===== impl SniRouter =====
+
+impl<T> SniRouter<T> {
+    /// Create a router without any routes.
+    pub fn new() -> Self {
+        SniRouter {
+            exact: HashMap::new(),
+            wildcard: HashMap::new(),
+            fallback: None,
+        }
+    }
+
+    /// Route connections for `name` to `target`.
+    ///
+    /// `name` is a host name, or a wildcard like `*.example.com`.
+    pub fn route(mut self, name: &str, target: T) -> Self {
+        let name = normalize(name);
+        match name.strip_prefix("*.") {
+            Some(parent) => self.wildcard.insert(parent.to_owned(), target),
+            None => self.exact.insert(name, target),
+        };
+        self
+    }
+
+    /// Route connections with no matching server name, or with none at
+    /// all, to `target`.
+    pub fn fallback(mut self, target: T) -> Self {
+        self.fallback = Some(target);
+        self
+    }
+
+    /// Find the target for a server name.
+    pub fn get(&self, server_name: Option<&str>) -> Option<&T> {
+        server_name
+            .map(normalize)
+            .and_then(|name| {
+                self.exact.get(&name).or_else(|| {
+                    let (_, parent) = name.split_once('.')?;
+                    self.wildcard.get(parent)
+                })
+            })
+            .or(self.fallback.as_ref())
+    }
+
+    /// Read the `ClientHello` of `io`, and find the target for its server
+    /// name.
+    ///
+    /// The target is `None` if nothing matches and there is no fallback.
+    pub async fn accept<I>(&self, io: I) -> Result<(Option<&T>, Peeked<I>), Error>
+    where
+        I: AsyncRead + Unpin,
+    {
+        let peeked = peek(io).await?;
+        Ok((self.get(peeked.server_name()), peeked))
+    }
+}
+
+impl<T> Default for SniRouter<T> {
+    fn default() -> Self {
+        SniRouter::new()
+    }
+}
+
+// ===== impl Peeked =====
+
+impl<I> Peeked<I> {
+    /// The server name the client asked for, lowercased.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Get a reference to the inner stream.
+    pub fn get_ref(&self) -> &I {
+        &self.io
+    }
+
+    /// Get a mutable reference to the inner stream.
+    pub fn get_mut(&mut self) -> &mut I {
+        &mut self.io
+    }
+
+    /// Consume this, returning the inner stream and the bytes read from it
+    /// that haven't been replayed yet.
+    pub fn into_inner(self) -> (I, Bytes) {
+        (self.io, self.buf)
+    }
+}
+
+impl<I: AsyncRead + Unpin> AsyncRead for Peeked<I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if !self.buf.is_empty() {
+            let n = self.buf.len().min(buf.remaining());
+            buf.put_slice(&self.buf[..n]);
+            self.buf.advance(n);
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<I: AsyncWrite + Unpin> AsyncWrite for Peeked<I> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.io.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+// ===== impl Error =====
+
+impl Error {
+    fn new(kind: Kind) -> Error {
+        Error { kind, source: None }
+    }
+
+    fn io(err: io::Error) -> Error {
+        Error {
+            kind: Kind::Io,
+            source: Some(err.into()),
+        }
+    }
+
+    /// Returns true if the connection doesn't start with a TLS handshake.
+    pub fn is_not_tls(&self) -> bool {
+        matches!(self.kind, Kind::NotTls)
+    }
+
+    /// Returns true if reading from the connection failed, including if it
+    /// closed before the `ClientHello` was complete.
+    pub fn is_io(&self) -> bool {
+        matches!(self.kind, Kind::Io)
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut f = f.debug_tuple("hyper_util::server::sni::Error");
+        f.field(&self.kind);
+        if let Some(ref source) = self.source {
+            f.field(source);
+        }
+        f.finish()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::Io => f.write_str("io error reading client hello"),
+            Kind::NotTls => f.write_str("connection is not tls"),
+            Kind::Malformed => f.write_str("malformed client hello"),
+            Kind::TooLarge => f.write_str("client hello too large"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.source.as_ref().map(|e| &**e as _)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::{peek, SniRouter};
+
+    // A `ClientHello` with just a server name extension.
+    fn client_hello(name: Option<&str>) -> Vec<u8> {
+        let mut body = vec![3, 3];
+        body.extend_from_slice(&[0; 32]);
+        body.extend_from_slice(&[0, 0, 2, 0x13, 0x01, 1, 0]);
+        if let Some(name) = name {
+            let name = name.as_bytes();
+            let list_len = 3 + name.len() as u16;
+            let mut ext = Vec::new();
+            ext.extend_from_slice(&[0, 0]);
+            ext.extend_from_slice(&(list_len + 2).to_be_bytes());
+            ext.extend_from_slice(&list_len.to_be_bytes());
+            ext.push(0);
+            ext.extend_from_slice(&(name.len() as u16).to_be_bytes());
+            ext.extend_from_slice(name);
+            body.extend_from_slice(&(ext.len() as u16).to_be_bytes());
+            body.extend_from_slice(&ext);
+        }
+        let mut hello = vec![1, 0];
+        hello.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        hello.extend_from_slice(&body);
+        hello
+    }
+
+    fn records(hello: &[u8], split: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for chunk in hello.chunks(split) {
+            out.extend_from_slice(&[22, 3, 1]);
+            out.extend_from_slice(&(chunk.len() as u16).to_be_bytes());
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn peeks_server_name_and_replays() {
+        let hello = client_hello(Some("WWW.Example.com."));
+        for split in [hello.len(), 10] {
+            let mut sent = records(&hello, split);
+            sent.extend_from_slice(b"rest");
+            let (mut client, server) = tokio::io::duplex(4096);
+            client.write_all(&sent).await.unwrap();
+            drop(client);
+
+            let mut peeked = peek(server).await.unwrap();
+            assert_eq!(peeked.server_name(), Some("www.example.com"));
+            let mut read = Vec::new();
+            peeked.read_to_end(&mut read).await.unwrap();
+            assert_eq!(read, sent);
+        }
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let err = peek(&b"GET / HTTP/1.1\r\n\r\n"[..]).await.unwrap_err();
+        assert!(err.is_not_tls(), "{:?}", err);
+
+        let mut sent = records(&client_hello(Some("example.com")), 1000);
+        sent.truncate(20);
+        let err = peek(&sent[..]).await.unwrap_err();
+        assert!(err.is_io(), "{:?}", err);
+
+        let sent = records(&client_hello(None), 1000);
+        let peeked = peek(&sent[..]).await.unwrap();
+        assert_eq!(peeked.server_name(), None);
+    }
+
+    #[test]
+    fn routes() {
+        let router = SniRouter::new()
+            .route("api.example.com", 1)
+            .route("*.Example.com", 2)
+            .fallback(3);
+        assert_eq!(router.get(Some("api.example.com")), Some(&1));
+        assert_eq!(router.get(Some("www.example.com")), Some(&2));
+        assert_eq!(router.get(Some("a.www.example.com")), Some(&3));
+        assert_eq!(router.get(Some("example.com")), Some(&3));
+        assert_eq!(router.get(None), Some(&3));
+        assert_eq!(SniRouter::<()>::new().get(Some("example.com")), None);
+    }
+}
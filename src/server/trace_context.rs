@@ -0,0 +1,75 @@
+//! W3C trace context propagation.
+//!
+//! This module provides [`ExtractTraceContext`], which reads the
+//! `traceparent`, `tracestate` and `baggage` headers of each request into a
+//! [`TraceContext`] in its extensions, so a service can continue the trace
+//! of the caller, such as by setting it as the parent of its span with an
+//! OpenTelemetry propagator. The client side is
+//! [`client::legacy::trace_context`](crate::client::legacy::trace_context),
+//! with the `client-legacy` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//!
+//! use bytes::Bytes;
+//! use http::{Request, Response};
+//! use http_body_util::Full;
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::server::trace_context::{ExtractTraceContext, TraceContext};
+//!
+//! let service = ExtractTraceContext::new(service_fn(|req: Request<Incoming>| async move {
+//!     let sampled = req
+//!         .extensions()
+//!         .get::<TraceContext>()
+//!         .map_or(false, TraceContext::is_sampled);
+//!     Ok::<_, Infallible>(Response::new(Full::new(Bytes::from(sampled.to_string()))))
+//! }));
+//! ```
+
+use http::Request;
+use hyper::service::Service;
+
+pub use crate::common::trace_context::TraceContext;
+
+/// A service that reads the trace context of each request into its
+/// extensions, and passes it to another service.
+///
+/// Requests without a valid `traceparent` get no [`TraceContext`]. The
+/// headers are left on the request.
+#[derive(Clone, Debug)]
+pub struct ExtractTraceContext<S> {
+    inner: S,
+}
+
+// ===== impl ExtractTraceContext =====
+
+impl<S> ExtractTraceContext<S> {
+    /// Wrap a service.
+    pub fn new(inner: S) -> Self {
+        ExtractTraceContext { inner }
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, B> Service<Request<B>> for ExtractTraceContext<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn call(&self, mut req: Request<B>) -> Self::Future {
+        if let Some(cx) = TraceContext::extract(req.headers()) {
+            req.extensions_mut().insert(cx);
+        }
+        self.inner.call(req)
+    }
+}
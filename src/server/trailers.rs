@@ -0,0 +1,384 @@
+//! Response trailers.
+//!
+//! Trailers are only delivered when the client can take them: always over
+//! HTTP/2, and over HTTP/1.1 only if the request sent `TE: trailers`, the
+//! response declares them in a `trailer` header, and its body is sent
+//! chunked. Anything else is silently dropped. This module provides:
+//!
+//! - [`attach`] to send trailers after the body of a response,
+//! - [`declare`] to declare the trailers a body sends on its own, and
+//! - [`AllowTrailers`], a service sending only the trailers of an
+//!   allowlist, and only to clients that take them, fixing the headers of
+//!   HTTP/1.1 responses to get them through.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//!
+//! use bytes::Bytes;
+//! use http::{HeaderMap, HeaderName, Request, Response};
+//! use http_body_util::Full;
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::server::trailers::{self, AllowTrailers};
+//!
+//! let checksum = HeaderName::from_static("x-checksum");
+//! let name = checksum.clone();
+//! let service = AllowTrailers::new(service_fn(move |_req: Request<Incoming>| {
+//!     let mut trailers = HeaderMap::new();
+//!     trailers.insert(name.clone(), "1234".parse().unwrap());
+//!     let res = Response::new(Full::new(Bytes::from("hello")));
+//!     async move { Ok::<_, Infallible>(trailers::attach(res, trailers)) }
+//! }))
+//! .allow(checksum);
+//! ```
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use http::{Request, Response, StatusCode, Version};
+use http_body::{Body, Frame, SizeHint};
+use hyper::service::Service;
+use pin_project_lite::pin_project;
+
+pub use super::grpc::WithTrailers;
+
+/// A service that only sends the trailers of an allowlist, to clients that
+/// take them.
+///
+/// Trailers are dropped if the request came over HTTP/1.1 without
+/// `TE: trailers`, or over HTTP/1.0. Otherwise, the trailers whose name is
+/// allowed are sent, and others are dropped. Over HTTP/1.1, only the
+/// allowed trailers declared in the `trailer` header of the response are
+/// sent, and a response declaring some is sent chunked, dropping its
+/// `content-length`, so they get through.
+///
+/// No trailer is allowed by default.
+#[derive(Clone, Debug)]
+pub struct AllowTrailers<S> {
+    inner: S,
+    allowed: Arc<Vec<HeaderName>>,
+}
+
+pin_project! {
+    /// The response future of an [`AllowTrailers`] service.
+    #[derive(Debug)]
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        version: Version,
+        accepts: bool,
+        allowed: Arc<Vec<HeaderName>>,
+    }
+}
+
+pin_project! {
+    /// A response body sending only the allowed trailers.
+    #[derive(Debug)]
+    pub struct AllowedBody<B> {
+        #[pin]
+        inner: B,
+        // the trailers to send, all dropped if empty
+        allowed: Vec<HeaderName>,
+        chunked: bool,
+    }
+}
+
+/// Send `trailers` after the body of `res`, declaring them in its
+/// `trailer` header.
+///
+/// If the body sends trailers of its own, they are merged, with `trailers`
+/// taking precedence.
+pub fn attach<B>(res: Response<B>, trailers: HeaderMap) -> Response<WithTrailers<B>> {
+    let (mut parts, body) = res.into_parts();
+    declare_names(&mut parts.headers, trailers.keys());
+    Response::from_parts(parts, WithTrailers::new(body, trailers))
+}
+
+/// Declare the trailers the body of `res` sends, in its `trailer` header.
+pub fn declare<B, I>(res: &mut Response<B>, names: I)
+where
+    I: IntoIterator<Item = HeaderName>,
+{
+    let names = names.into_iter().collect::<Vec<_>>();
+    declare_names(res.headers_mut(), names.iter());
+}
+
+fn declare_names<'a>(headers: &mut HeaderMap, names: impl Iterator<Item = &'a HeaderName>) {
+    let mut declared = declared(headers);
+    for name in names {
+        if !declared.contains(name) {
+            declared.push(name.clone());
+        }
+    }
+    set_declared(headers, &declared);
+}
+
+// The names in the `trailer` header.
+fn declared(headers: &HeaderMap) -> Vec<HeaderName> {
+    headers
+        .get_all(header::TRAILER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|line| line.split(','))
+        .filter_map(|name| name.trim().parse().ok())
+        .collect()
+}
+
+fn set_declared(headers: &mut HeaderMap, names: &[HeaderName]) {
+    if names.is_empty() {
+        headers.remove(header::TRAILER);
+        return;
+    }
+    let value = names
+        .iter()
+        .map(HeaderName::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    headers.insert(
+        header::TRAILER,
+        HeaderValue::from_str(&value).expect("header names are a valid value"),
+    );
+}
+
+fn te_trailers<B>(req: &Request<B>) -> bool {
+    req.headers()
+        .get_all(header::TE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|line| line.split(','))
+        .any(|token| token.trim().eq_ignore_ascii_case("trailers"))
+}
+
+// ===== impl AllowTrailers =====
+
+impl<S> AllowTrailers<S> {
+    /// Wrap a service, dropping all its trailers until some are allowed.
+    pub fn new(inner: S) -> Self {
+        AllowTrailers {
+            inner,
+            allowed: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Allow the trailer `name`.
+    pub fn allow(mut self, name: HeaderName) -> Self {
+        let allowed = Arc::make_mut(&mut self.allowed);
+        if !allowed.contains(&name) {
+            allowed.push(name);
+        }
+        self
+    }
+
+    /// Get a reference to the inner service.
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for AllowTrailers<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    ResBody: Body,
+{
+    type Response = Response<AllowedBody<ResBody>>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        let version = req.version();
+        let accepts = match version {
+            Version::HTTP_2 | Version::HTTP_3 => true,
+            Version::HTTP_11 => te_trailers(&req),
+            _ => false,
+        };
+        ResponseFuture {
+            inner: self.inner.call(req),
+            version,
+            accepts,
+            allowed: self.allowed.clone(),
+        }
+    }
+}
+
+// ===== impl ResponseFuture =====
+
+impl<F, B, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<B>, E>>,
+    B: Body,
+{
+    type Output = Result<Response<AllowedBody<B>>, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let res = futures_util::ready!(this.inner.poll(cx))?;
+        let (mut parts, body) = res.into_parts();
+
+        let http1 = *this.version < Version::HTTP_2;
+        let allowed = if !*this.accepts {
+            Vec::new()
+        } else if http1 {
+            declared(&parts.headers)
+                .into_iter()
+                .filter(|name| this.allowed.contains(name))
+                .collect()
+        } else {
+            this.allowed.to_vec()
+        };
+
+        let chunked = http1 && !allowed.is_empty() && can_have_body(parts.status);
+        if http1 {
+            set_declared(&mut parts.headers, if chunked { &allowed } else { &[] });
+        }
+        if chunked {
+            parts.headers.remove(header::CONTENT_LENGTH);
+            if !parts.headers.contains_key(header::TRANSFER_ENCODING) {
+                parts.headers.insert(
+                    header::TRANSFER_ENCODING,
+                    HeaderValue::from_static("chunked"),
+                );
+            }
+        }
+
+        let body = AllowedBody {
+            inner: body,
+            allowed: if http1 && !chunked {
+                Vec::new()
+            } else {
+                allowed
+            },
+            chunked,
+        };
+        Poll::Ready(Ok(Response::from_parts(parts, body)))
+    }
+}
+
+fn can_have_body(status: StatusCode) -> bool {
+    !(status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED)
+}
+
+// ===== impl AllowedBody =====
+
+impl<B: Body> Body for AllowedBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        loop {
+            let frame = match futures_util::ready!(this.inner.as_mut().poll_frame(cx)) {
+                Some(Ok(frame)) => frame,
+                other => return Poll::Ready(other),
+            };
+            match frame.into_trailers() {
+                Ok(mut trailers) => {
+                    let names = trailers.keys().cloned().collect::<Vec<_>>();
+                    for name in names {
+                        if !this.allowed.contains(&name) {
+                            trailers.remove(name);
+                        }
+                    }
+                    if !trailers.is_empty() {
+                        return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+                    }
+                    // dropped, keep polling to the end of the body
+                }
+                Err(frame) => return Poll::Ready(Some(Ok(frame))),
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let hint = self.inner.size_hint();
+        if self.chunked {
+            // an exact size would be sent as a `content-length`
+            let mut chunked = SizeHint::new();
+            chunked.set_lower(hint.lower());
+            chunked
+        } else {
+            hint
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use bytes::Bytes;
+    use http::{HeaderMap, HeaderName, Request, Response, Version};
+    use http_body::Body;
+    use http_body_util::{BodyExt, Empty, Full};
+    use hyper::service::Service;
+
+    use super::{attach, AllowTrailers, AllowedBody, WithTrailers};
+
+    async fn respond(
+        req: Request<Empty<Bytes>>,
+        allowed: &'static str,
+    ) -> Response<AllowedBody<WithTrailers<Full<Bytes>>>> {
+        let service = AllowTrailers::new(hyper::service::service_fn(
+            |_req: Request<Empty<Bytes>>| async {
+                let mut trailers = HeaderMap::new();
+                trailers.insert("x-checksum", "1234".parse().unwrap());
+                trailers.insert("x-debug", "secret".parse().unwrap());
+                let mut res = Response::new(Full::new(Bytes::from("hello")));
+                res.headers_mut()
+                    .insert("content-length", "5".parse().unwrap());
+                Ok::<_, Infallible>(attach(res, trailers))
+            },
+        ))
+        .allow(HeaderName::from_static(allowed));
+        service.call(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn sends_allowed_trailers_to_accepting_clients() {
+        let req = Request::builder()
+            .header("te", "trailers")
+            .body(Empty::new())
+            .unwrap();
+        let res = respond(req, "x-checksum").await;
+        assert_eq!(res.headers()["trailer"], "x-checksum");
+        assert_eq!(res.headers()["transfer-encoding"], "chunked");
+        assert!(!res.headers().contains_key("content-length"));
+        assert_eq!(res.body().size_hint().exact(), None);
+        let collected = res.into_body().collect().await.unwrap();
+        let trailers = collected.trailers().unwrap();
+        assert_eq!(trailers["x-checksum"], "1234");
+        assert!(!trailers.contains_key("x-debug"));
+
+        // HTTP/1.1 without `TE: trailers`
+        let res = respond(Request::new(Empty::new()), "x-checksum").await;
+        assert!(!res.headers().contains_key("trailer"));
+        assert_eq!(res.headers()["content-length"], "5");
+        let collected = res.into_body().collect().await.unwrap();
+        assert!(collected.trailers().is_none());
+        assert_eq!(collected.to_bytes(), "hello");
+
+        // HTTP/2 needs no `TE: trailers`
+        let req = Request::builder()
+            .version(Version::HTTP_2)
+            .body(Empty::new())
+            .unwrap();
+        let res = respond(req, "x-debug").await;
+        let collected = res.into_body().collect().await.unwrap();
+        let trailers = collected.trailers().unwrap();
+        assert_eq!(trailers["x-debug"], "secret");
+        assert!(!trailers.contains_key("x-checksum"));
+    }
+}
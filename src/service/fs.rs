@@ -0,0 +1,751 @@
+//! Serving files from the file system.
+//!
+//! This module provides [`ServeDir`] and [`ServeFile`], services that
+//! respond to `GET` and `HEAD` requests with files, supporting:
+//!
+//! - validators, with `ETag` and `Last-Modified` headers,
+//! - conditional requests, with `If-Match`, `If-None-Match`,
+//!   `If-Modified-Since`, `If-Unmodified-Since` and `If-Range`,
+//! - single byte ranges, with `Range`.
+//!
+//! Request paths are only ever resolved inside the served directory.
+//!
+//! Files are always read into buffers and sent as body frames; there is no
+//! `sendfile` fast path.
+//!
+//! # Example
+//!
+//! ```
+//! use hyper_util::service::fs::ServeDir;
+//!
+//! let service = ServeDir::new("assets").index_file(Some("index.html"));
+//! ```
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+
+use bytes::{Bytes, BytesMut};
+use http::header::{self, HeaderMap, HeaderValue};
+use http::{Method, Request, Response, StatusCode};
+use http_body::{Body, Frame, SizeHint};
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeekExt, ReadBuf};
+
+const DEFAULT_BUF_SIZE: usize = 64 * 1024;
+
+/// A service serving the files of a directory.
+///
+/// The path of a request is resolved inside the directory, after
+/// percent-decoding. Paths with `..` segments, backslashes or NUL bytes
+/// get a `404 Not Found`, whether or not they'd stay inside.
+///
+/// A request for a directory is redirected to the path with a trailing
+/// slash, which then serves the [index file](ServeDir::index_file).
+#[derive(Clone, Debug)]
+pub struct ServeDir {
+    root: PathBuf,
+    config: Arc<Config>,
+}
+
+/// A service serving a single file, whatever the path of the request.
+#[derive(Clone, Debug)]
+pub struct ServeFile {
+    path: PathBuf,
+    config: Arc<Config>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    index_file: Option<String>,
+    buf_size: usize,
+}
+
+/// The response future of [`ServeDir`] and [`ServeFile`].
+pub struct ResponseFuture {
+    inner: Pin<Box<dyn Future<Output = io::Result<Response<FileBody>>> + Send>>,
+}
+
+/// The body of a response from [`ServeDir`] or [`ServeFile`].
+pub struct FileBody {
+    kind: Kind,
+}
+
+enum Kind {
+    Empty,
+    File {
+        file: File,
+        remaining: u64,
+        buf_size: usize,
+    },
+}
+
+// ===== impl ServeDir =====
+
+impl ServeDir {
+    /// Serve the files in the directory `root`.
+    ///
+    /// The index file is `index.html`.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ServeDir {
+            root: root.into(),
+            config: Arc::new(Config::new()),
+        }
+    }
+
+    /// Set the file served for requests of a directory, or `None` to
+    /// respond `404 Not Found` to them.
+    ///
+    /// Default is `index.html`.
+    pub fn index_file(mut self, name: Option<&str>) -> Self {
+        Arc::make_mut(&mut self.config).index_file = name.map(String::from);
+        self
+    }
+
+    /// Set how many bytes are read from a file at a time.
+    ///
+    /// Default is 64 KiB.
+    pub fn buf_size(mut self, size: usize) -> Self {
+        Arc::make_mut(&mut self.config).buf_size = size.max(1);
+        self
+    }
+}
+
+impl<B> hyper::service::Service<Request<B>> for ServeDir {
+    type Response = Response<FileBody>;
+    type Error = io::Error;
+    type Future = ResponseFuture;
+
+    fn call(&self, req: Request<B>) -> Self::Future {
+        let config = self.config.clone();
+        let resolved = resolve(&self.root, req.uri().path());
+        let (parts, _) = req.into_parts();
+        ResponseFuture::new(async move {
+            if let Some(res) = check_method(&parts.method) {
+                return Ok(res);
+            }
+            let mut path = match resolved {
+                Some(path) => path,
+                None => return Ok(status(StatusCode::NOT_FOUND)),
+            };
+            match tokio::fs::metadata(&path).await {
+                Ok(meta) if meta.is_dir() => {
+                    if !parts.uri.path().ends_with('/') {
+                        return Ok(redirect_to_dir(&parts.uri));
+                    }
+                    match config.index_file {
+                        Some(ref index) => path.push(index),
+                        None => return Ok(status(StatusCode::NOT_FOUND)),
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => return not_found_or(err),
+            }
+            serve(&path, &parts.method, &parts.headers, &config).await
+        })
+    }
+}
+
+// ===== impl ServeFile =====
+
+impl ServeFile {
+    /// Serve the file at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        ServeFile {
+            path: path.into(),
+            config: Arc::new(Config::new()),
+        }
+    }
+
+    /// Set how many bytes are read from the file at a time.
+    ///
+    /// Default is 64 KiB.
+    pub fn buf_size(mut self, size: usize) -> Self {
+        Arc::make_mut(&mut self.config).buf_size = size.max(1);
+        self
+    }
+}
+
+impl<B> hyper::service::Service<Request<B>> for ServeFile {
+    type Response = Response<FileBody>;
+    type Error = io::Error;
+    type Future = ResponseFuture;
+
+    fn call(&self, req: Request<B>) -> Self::Future {
+        let config = self.config.clone();
+        let path = self.path.clone();
+        let (parts, _) = req.into_parts();
+        ResponseFuture::new(async move {
+            if let Some(res) = check_method(&parts.method) {
+                return Ok(res);
+            }
+            serve(&path, &parts.method, &parts.headers, &config).await
+        })
+    }
+}
+
+// ===== impl Config =====
+
+impl Config {
+    fn new() -> Config {
+        Config {
+            index_file: Some("index.html".into()),
+            buf_size: DEFAULT_BUF_SIZE,
+        }
+    }
+}
+
+// ===== serving =====
+
+// Resolves a request path inside `root`, refusing anything that could
+// climb out of it.
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let decoded = percent_decode(path)?;
+    let mut resolved = root.to_path_buf();
+    for segment in decoded.split('/') {
+        if segment.contains('\\') || segment.contains('\0') || segment == ".." {
+            return None;
+        }
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+        // reject anything else the platform would give meaning, like a
+        // drive prefix on Windows
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if name == OsStr::new(segment) => {}
+            _ => return None,
+        }
+        resolved.push(segment);
+    }
+    Some(resolved)
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    fn hex(b: u8) -> Option<u8> {
+        match b {
+            b'0'..=b'9' => Some(b - b'0'),
+            b'a'..=b'f' => Some(b - b'a' + 10),
+            b'A'..=b'F' => Some(b - b'A' + 10),
+            _ => None,
+        }
+    }
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hi = hex(*bytes.get(i + 1)?)?;
+            let lo = hex(*bytes.get(i + 2)?)?;
+            decoded.push(hi << 4 | lo);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn check_method(method: &Method) -> Option<Response<FileBody>> {
+    if method == Method::GET || method == Method::HEAD {
+        return None;
+    }
+    let mut res = status(StatusCode::METHOD_NOT_ALLOWED);
+    res.headers_mut()
+        .insert(header::ALLOW, HeaderValue::from_static("GET, HEAD"));
+    Some(res)
+}
+
+fn redirect_to_dir(uri: &http::Uri) -> Response<FileBody> {
+    // A path starting with `//` would be a redirect to another host.
+    let path = format!("/{}", uri.path().trim_start_matches('/'));
+    let location = match uri.query() {
+        Some(query) => format!("{}/?{}", path, query),
+        None => format!("{}/", path),
+    };
+    let mut res = status(StatusCode::MOVED_PERMANENTLY);
+    if let Ok(location) = HeaderValue::from_str(&location) {
+        res.headers_mut().insert(header::LOCATION, location);
+    }
+    res
+}
+
+fn status(status: StatusCode) -> Response<FileBody> {
+    let mut res = Response::new(FileBody::empty());
+    *res.status_mut() = status;
+    res
+}
+
+fn not_found_or(err: io::Error) -> io::Result<Response<FileBody>> {
+    match err.kind() {
+        io::ErrorKind::NotFound => Ok(status(StatusCode::NOT_FOUND)),
+        io::ErrorKind::PermissionDenied => Ok(status(StatusCode::FORBIDDEN)),
+        _ => Err(err),
+    }
+}
+
+async fn serve(
+    path: &Path,
+    method: &Method,
+    headers: &HeaderMap,
+    config: &Config,
+) -> io::Result<Response<FileBody>> {
+    let mut file = match File::open(path).await {
+        Ok(file) => file,
+        Err(err) => return not_found_or(err),
+    };
+    let meta = file.metadata().await?;
+    if !meta.is_file() {
+        return Ok(status(StatusCode::NOT_FOUND));
+    }
+    let len = meta.len();
+    let validators = Validators::new(meta.modified().ok(), len);
+
+    let mut res = status(StatusCode::OK);
+    let res_headers = res.headers_mut();
+    res_headers.insert(header::CONTENT_TYPE, content_type(path));
+    res_headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    validators.insert(res_headers);
+
+    match validators.precondition(headers) {
+        Precondition::Failed => {
+            *res.status_mut() = StatusCode::PRECONDITION_FAILED;
+            return Ok(res);
+        }
+        Precondition::NotModified => {
+            res.headers_mut().remove(header::CONTENT_TYPE);
+            *res.status_mut() = StatusCode::NOT_MODIFIED;
+            return Ok(res);
+        }
+        Precondition::Passed => {}
+    }
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|v| v.to_str().ok())
+        .filter(|_| validators.if_range(headers))
+        .and_then(|v| parse_range(v, len));
+    let (start, end) = match range {
+        Some(Ok((start, end))) => {
+            *res.status_mut() = StatusCode::PARTIAL_CONTENT;
+            let content_range = format!("bytes {}-{}/{}", start, end, len);
+            res.headers_mut().insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&content_range).expect("valid header"),
+            );
+            (start, end + 1)
+        }
+        Some(Err(())) => {
+            *res.status_mut() = StatusCode::RANGE_NOT_SATISFIABLE;
+            let content_range = format!("bytes */{}", len);
+            res.headers_mut().insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&content_range).expect("valid header"),
+            );
+            res.headers_mut().remove(header::CONTENT_TYPE);
+            return Ok(res);
+        }
+        None => (0, len),
+    };
+
+    res.headers_mut()
+        .insert(header::CONTENT_LENGTH, HeaderValue::from(end - start));
+    if method == Method::HEAD {
+        return Ok(res);
+    }
+    if start > 0 {
+        file.seek(io::SeekFrom::Start(start)).await?;
+    }
+    *res.body_mut() = FileBody {
+        kind: Kind::File {
+            file,
+            remaining: end - start,
+            buf_size: config.buf_size,
+        },
+    };
+    Ok(res)
+}
+
+// Parses a `Range` header of a single byte range, into the first and last
+// byte positions. `None` means the header is ignored, and the whole file
+// served, which is what multiple ranges get too.
+fn parse_range(value: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+    let spec = value.trim().strip_prefix("bytes=")?;
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    if first.is_empty() {
+        let suffix = last.parse::<u64>().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(Err(()));
+        }
+        return Some(Ok((len.saturating_sub(suffix), len - 1)));
+    }
+    let first = first.parse::<u64>().ok()?;
+    let last = if last.is_empty() {
+        u64::MAX
+    } else {
+        last.parse::<u64>().ok()?
+    };
+    if last < first {
+        return None;
+    }
+    if first >= len {
+        return Some(Err(()));
+    }
+    Some(Ok((first, last.min(len - 1))))
+}
+
+fn content_type(path: &Path) -> HeaderValue {
+    let ext = path
+        .extension()
+        .and_then(OsStr::to_str)
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    HeaderValue::from_static(match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    })
+}
+
+// ===== validators =====
+
+struct Validators {
+    etag: Option<String>,
+    // truncated to seconds, as it's sent
+    modified: Option<SystemTime>,
+}
+
+enum Precondition {
+    Passed,
+    NotModified,
+    Failed,
+}
+
+impl Validators {
+    fn new(modified: Option<SystemTime>, len: u64) -> Validators {
+        let modified =
+            modified.and_then(|m| httpdate::parse_http_date(&httpdate::fmt_http_date(m)).ok());
+        let etag = modified
+            .and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|since| format!("\"{:x}-{:x}\"", since.as_secs(), len));
+        Validators { etag, modified }
+    }
+
+    fn insert(&self, headers: &mut HeaderMap) {
+        if let Some(ref etag) = self.etag {
+            if let Ok(value) = HeaderValue::from_str(etag) {
+                headers.insert(header::ETAG, value);
+            }
+        }
+        if let Some(modified) = self.modified {
+            if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(modified)) {
+                headers.insert(header::LAST_MODIFIED, value);
+            }
+        }
+    }
+
+    // Evaluates the preconditions in the order of RFC 9110, section 13.2.2.
+    fn precondition(&self, headers: &HeaderMap) -> Precondition {
+        if let Some(if_match) = header_str(headers, header::IF_MATCH) {
+            if !self.matches(if_match, false) {
+                return Precondition::Failed;
+            }
+        } else if let Some(since) = header_date(headers, header::IF_UNMODIFIED_SINCE) {
+            if matches!(self.modified, Some(m) if m > since) {
+                return Precondition::Failed;
+            }
+        }
+
+        if let Some(if_none_match) = header_str(headers, header::IF_NONE_MATCH) {
+            if self.matches(if_none_match, true) {
+                return Precondition::NotModified;
+            }
+        } else if let Some(since) = header_date(headers, header::IF_MODIFIED_SINCE) {
+            if matches!(self.modified, Some(m) if m <= since) {
+                return Precondition::NotModified;
+            }
+        }
+        Precondition::Passed
+    }
+
+    // Whether a `Range` should be honored, given `If-Range`.
+    fn if_range(&self, headers: &HeaderMap) -> bool {
+        let value = match header_str(headers, header::IF_RANGE) {
+            Some(value) => value.trim(),
+            None => return true,
+        };
+        if value.starts_with('"') {
+            return self.etag.as_deref() == Some(value);
+        }
+        match httpdate::parse_http_date(value) {
+            Ok(date) => self.modified == Some(date),
+            Err(_) => false,
+        }
+    }
+
+    // Compares against a list of entity tags, weakly or strongly. The
+    // entity tags here are always strong.
+    fn matches(&self, list: &str, weak: bool) -> bool {
+        let etag = match self.etag {
+            Some(ref etag) => etag.as_str(),
+            None => return list.trim() == "*",
+        };
+        list.split(',').map(str::trim).any(|tag| {
+            if tag == "*" {
+                return true;
+            }
+            match tag.strip_prefix("W/") {
+                Some(tag) => weak && tag == etag,
+                None => tag == etag,
+            }
+        })
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn header_date(headers: &HeaderMap, name: header::HeaderName) -> Option<SystemTime> {
+    header_str(headers, name).and_then(|v| httpdate::parse_http_date(v).ok())
+}
+
+// ===== impl ResponseFuture =====
+
+impl ResponseFuture {
+    fn new<F>(future: F) -> ResponseFuture
+    where
+        F: Future<Output = io::Result<Response<FileBody>>> + Send + 'static,
+    {
+        ResponseFuture {
+            inner: Box::pin(future),
+        }
+    }
+}
+
+impl Future for ResponseFuture {
+    type Output = io::Result<Response<FileBody>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.inner.as_mut().poll(cx)
+    }
+}
+
+impl fmt::Debug for ResponseFuture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResponseFuture").finish()
+    }
+}
+
+// ===== impl FileBody =====
+
+impl FileBody {
+    fn empty() -> FileBody {
+        FileBody { kind: Kind::Empty }
+    }
+}
+
+impl Body for FileBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let (file, remaining, buf_size) = match self.kind {
+            Kind::Empty => return Poll::Ready(None),
+            Kind::File {
+                ref mut file,
+                ref mut remaining,
+                buf_size,
+            } => (file, remaining, buf_size),
+        };
+        if *remaining == 0 {
+            self.kind = Kind::Empty;
+            return Poll::Ready(None);
+        }
+
+        let cap = (*remaining).min(buf_size as u64) as usize;
+        let mut buf = BytesMut::zeroed(cap);
+        let mut read_buf = ReadBuf::new(&mut buf);
+        futures_util::ready!(Pin::new(file).poll_read(cx, &mut read_buf))?;
+        let n = read_buf.filled().len();
+        if n == 0 {
+            self.kind = Kind::Empty;
+            return Poll::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "file ended before its length",
+            ))));
+        }
+        *remaining -= n as u64;
+        buf.truncate(n);
+        Poll::Ready(Some(Ok(Frame::data(buf.freeze()))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        match self.kind {
+            Kind::Empty => true,
+            Kind::File { remaining, .. } => remaining == 0,
+        }
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self.kind {
+            Kind::Empty => SizeHint::with_exact(0),
+            Kind::File { remaining, .. } => SizeHint::with_exact(remaining),
+        }
+    }
+}
+
+impl fmt::Debug for FileBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut builder = f.debug_struct("FileBody");
+        if let Kind::File { remaining, .. } = self.kind {
+            builder.field("remaining", &remaining);
+        }
+        builder.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use http::{header, Request, StatusCode};
+    use http_body_util::BodyExt;
+    use hyper::service::Service;
+
+    use super::{parse_range, resolve, ServeDir};
+
+    #[test]
+    fn resolves_inside_root() {
+        let root = Path::new("/srv");
+        assert_eq!(
+            resolve(root, "/a/./b%20c/"),
+            Some(Path::new("/srv/a/b c").to_path_buf())
+        );
+        assert_eq!(resolve(root, "/a/../../etc/passwd"), None);
+        assert_eq!(resolve(root, "/%2e%2e/etc/passwd"), None);
+        assert_eq!(resolve(root, "/a%5c..%5cb"), None);
+        assert_eq!(resolve(root, "/a%00"), None);
+        assert_eq!(resolve(root, "/%zz"), None);
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some(Ok((0, 4))));
+        assert_eq!(parse_range("bytes=5-", 10), Some(Ok((5, 9))));
+        assert_eq!(parse_range("bytes=-3", 10), Some(Ok((7, 9))));
+        assert_eq!(parse_range("bytes=8-20", 10), Some(Ok((8, 9))));
+        assert_eq!(parse_range("bytes=10-", 10), Some(Err(())));
+        assert_eq!(parse_range("bytes=0-1,3-4", 10), None);
+        assert_eq!(parse_range("bytes=4-2", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+    }
+
+    #[tokio::test]
+    async fn serves_ranges_and_conditionals() {
+        let dir = std::env::temp_dir().join(format!("hyper-util-fs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("hello.txt"), "hello world").unwrap();
+        std::fs::write(dir.join("sub").join("index.html"), "<p>hi</p>").unwrap();
+        let service = ServeDir::new(&dir);
+
+        let res = service
+            .call(Request::get("/hello.txt").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_LENGTH], "11");
+        let etag = res.headers()[header::ETAG].clone();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello world");
+
+        let req = Request::get("/hello.txt")
+            .header(header::RANGE, "bytes=6-")
+            .body(())
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes 6-10/11");
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "world");
+
+        let req = Request::get("/hello.txt")
+            .header(header::IF_NONE_MATCH, etag.clone())
+            .body(())
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+
+        let req = Request::get("/hello.txt")
+            .header(header::IF_MATCH, "\"other\"")
+            .body(())
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+
+        let req = Request::get("/hello.txt")
+            .header(header::RANGE, "bytes=20-")
+            .body(())
+            .unwrap();
+        let res = service.call(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(res.headers()[header::CONTENT_RANGE], "bytes */11");
+
+        let res = service
+            .call(Request::get("/sub").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers()[header::LOCATION], "/sub/");
+        let res = service
+            .call(Request::get("//sub").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers()[header::LOCATION], "/sub/");
+        let res = service
+            .call(Request::get("/sub/").body(()).unwrap())
+            .await
+            .unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "<p>hi</p>");
+
+        let res = service
+            .call(Request::get("/../hello.txt").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
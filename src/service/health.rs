@@ -0,0 +1,254 @@
+//! Health check endpoints.
+//!
+//! This module provides [`Health`], which answers liveness and readiness
+//! probes, such as those of a load balancer or orchestrator, in front of
+//! another service.
+//!
+//! - The liveness endpoint, `/livez` by default, always responds
+//!   `200 OK`, as long as the server is up to respond.
+//! - The readiness endpoint, `/readyz` by default, responds `200 OK` when
+//!   ready, and `503 Service Unavailable` when not, so that traffic is
+//!   moved elsewhere.
+//!
+//! Readiness is set with a [`ReadinessHandle`], and, with the
+//! `server-graceful` feature, turns off by itself once a graceful shutdown
+//! begins.
+//!
+//! # Example
+//!
+//! ```
+//! use std::convert::Infallible;
+//!
+//! use bytes::Bytes;
+//! use http::{Request, Response};
+//! use http_body_util::Full;
+//! use hyper::body::Incoming;
+//! use hyper::service::service_fn;
+//! use hyper_util::service::health::Health;
+//!
+//! let health = Health::new();
+//! let readiness = health.readiness_handle();
+//!
+//! let service = health.wrap(service_fn(|_req: Request<Incoming>| async {
+//!     Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("hello"))))
+//! }));
+//!
+//! // while warming up a cache, say
+//! readiness.set_ready(false);
+//! ```
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures_util::future::{self, Either, Ready};
+use http::{Request, Response, StatusCode};
+
+#[cfg(feature = "server-graceful")]
+use crate::server::graceful::DrainSignal;
+
+/// Liveness and readiness endpoints.
+#[derive(Clone, Debug)]
+pub struct Health {
+    ready: Arc<AtomicBool>,
+    liveness_path: Arc<str>,
+    readiness_path: Arc<str>,
+    #[cfg(feature = "server-graceful")]
+    drain: Option<DrainSignal>,
+}
+
+/// A handle to set whether a [`Health`] reports ready.
+#[derive(Clone)]
+pub struct ReadinessHandle {
+    ready: Arc<AtomicBool>,
+}
+
+/// A service that answers health checks, and passes other requests on to
+/// an inner service.
+#[derive(Clone, Debug)]
+pub struct WithHealth<S> {
+    inner: S,
+    health: Health,
+}
+
+// ===== impl Health =====
+
+impl Health {
+    /// Create health endpoints at `/livez` and `/readyz`, starting ready.
+    pub fn new() -> Self {
+        Health {
+            ready: Arc::new(AtomicBool::new(true)),
+            liveness_path: "/livez".into(),
+            readiness_path: "/readyz".into(),
+            #[cfg(feature = "server-graceful")]
+            drain: None,
+        }
+    }
+
+    /// Set the path of the liveness endpoint.
+    pub fn liveness_path(mut self, path: &str) -> Self {
+        self.liveness_path = path.into();
+        self
+    }
+
+    /// Set the path of the readiness endpoint.
+    pub fn readiness_path(mut self, path: &str) -> Self {
+        self.readiness_path = path.into();
+        self
+    }
+
+    /// Report not ready once connections are draining for a graceful
+    /// shutdown.
+    ///
+    /// Get the signal with
+    /// [`GracefulShutdown::drain_signal`](crate::server::graceful::GracefulShutdown::drain_signal).
+    #[cfg(feature = "server-graceful")]
+    pub fn drain_signal(mut self, signal: DrainSignal) -> Self {
+        self.drain = Some(signal);
+        self
+    }
+
+    /// Get a handle to set whether this reports ready.
+    pub fn readiness_handle(&self) -> ReadinessHandle {
+        ReadinessHandle {
+            ready: self.ready.clone(),
+        }
+    }
+
+    /// Returns whether this reports ready.
+    pub fn is_ready(&self) -> bool {
+        #[cfg(feature = "server-graceful")]
+        if matches!(self.drain, Some(ref drain) if drain.is_draining()) {
+            return false;
+        }
+        self.ready.load(Ordering::Acquire)
+    }
+
+    /// Respond to a request, if it's for a health endpoint.
+    ///
+    /// This is for routing health checks in a service of one's own, and
+    /// what [`WithHealth`] does.
+    pub fn respond<B, R>(&self, req: &Request<B>) -> Option<Response<R>>
+    where
+        R: From<&'static str>,
+    {
+        let path = req.uri().path();
+        let (status, body) = if path == &*self.liveness_path {
+            (StatusCode::OK, "ok\n")
+        } else if path != &*self.readiness_path {
+            return None;
+        } else if self.is_ready() {
+            (StatusCode::OK, "ready\n")
+        } else {
+            (StatusCode::SERVICE_UNAVAILABLE, "not ready\n")
+        };
+        let mut res = Response::new(R::from(body));
+        *res.status_mut() = status;
+        Some(res)
+    }
+
+    /// Wrap a service, answering health checks in front of it.
+    pub fn wrap<S>(self, inner: S) -> WithHealth<S> {
+        WithHealth {
+            inner,
+            health: self,
+        }
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ===== impl ReadinessHandle =====
+
+impl ReadinessHandle {
+    /// Set whether to report ready.
+    pub fn set_ready(&self, ready: bool) {
+        self.ready.store(ready, Ordering::Release);
+    }
+
+    /// Returns whether this was set ready.
+    ///
+    /// This doesn't account for a graceful shutdown, unlike
+    /// [`Health::is_ready`].
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire)
+    }
+}
+
+impl fmt::Debug for ReadinessHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReadinessHandle")
+            .field("ready", &self.is_ready())
+            .finish()
+    }
+}
+
+// ===== impl WithHealth =====
+
+impl<S, B, R> hyper::service::Service<Request<B>> for WithHealth<S>
+where
+    S: hyper::service::Service<Request<B>, Response = Response<R>>,
+    R: From<&'static str>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<S::Response, S::Error>>, S::Future>;
+
+    fn call(&self, req: Request<B>) -> Self::Future {
+        match self.health.respond(&req) {
+            Some(res) => Either::Left(future::ready(Ok(res))),
+            None => Either::Right(self.inner.call(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::{Request, StatusCode};
+    use http_body_util::Full;
+
+    use super::Health;
+
+    fn status(health: &Health, path: &str) -> Option<StatusCode> {
+        let req = Request::get(path).body(()).unwrap();
+        health
+            .respond::<_, Full<Bytes>>(&req)
+            .map(|res| res.status())
+    }
+
+    #[test]
+    fn reports_readiness() {
+        let health = Health::new().readiness_path("/ready");
+        let handle = health.readiness_handle();
+
+        assert_eq!(status(&health, "/livez"), Some(StatusCode::OK));
+        assert_eq!(status(&health, "/ready"), Some(StatusCode::OK));
+        assert_eq!(status(&health, "/readyz"), None);
+
+        handle.set_ready(false);
+        assert_eq!(status(&health, "/livez"), Some(StatusCode::OK));
+        assert_eq!(
+            status(&health, "/ready"),
+            Some(StatusCode::SERVICE_UNAVAILABLE)
+        );
+    }
+
+    #[cfg(feature = "server-graceful")]
+    #[tokio::test]
+    async fn not_ready_once_draining() {
+        use crate::server::graceful::GracefulShutdown;
+
+        let shutdown = GracefulShutdown::new();
+        let health = Health::new().drain_signal(shutdown.drain_signal());
+        assert!(health.is_ready());
+
+        shutdown.shutdown().await;
+        assert!(!health.is_ready());
+        assert!(health.readiness_handle().is_ready());
+    }
+}
@@ -1,5 +1,13 @@
 //! Service utilities.
 
+#[cfg(feature = "service-fs")]
+pub mod fs;
+#[cfg(feature = "service-health")]
+pub mod health;
+mod ready;
+
+pub use self::ready::{TowerToHyperReadyService, TowerToHyperReadyServiceFuture};
+
 use pin_project_lite::pin_project;
 use std::{
     future::Future,
@@ -0,0 +1,318 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use pin_project_lite::pin_project;
+
+/// A tower service converted into a hyper service, waiting for it to be
+/// ready before every call.
+///
+/// [`TowerToHyperService`](super::TowerToHyperService) clones the service
+/// for every request and drives each clone to readiness on its own, so
+/// a service that applies backpressure through `poll_ready`, such as
+/// `tower::limit::ConcurrencyLimit`, only sees it once per clone. This
+/// instead keeps a single service, shared by the requests of a connection:
+///
+/// - requests wait in order for the service to be ready, and are only
+///   called once it is, and
+/// - at most [`concurrency_limit`](Self::concurrency_limit) requests are
+///   in flight at once, which matters for HTTP/2 connections.
+///
+/// Create one for every connection, so the limit applies per connection.
+pub struct TowerToHyperReadyService<S> {
+    shared: Arc<Mutex<Shared<S>>>,
+}
+
+pin_project! {
+    /// Response future for [`TowerToHyperReadyService`].
+    pub struct TowerToHyperReadyServiceFuture<S, R>
+    where
+        S: tower_service::Service<R>,
+    {
+        #[pin]
+        state: State<S::Future, R>,
+        slot: Slot<S>,
+    }
+}
+
+pin_project! {
+    #[project = StateProj]
+    enum State<F, R> {
+        Waiting { req: Option<R> },
+        Called { #[pin] future: F },
+    }
+}
+
+struct Shared<S> {
+    service: S,
+    limit: usize,
+    in_flight: usize,
+    next_id: u64,
+    // requests waiting to be called, in order
+    queue: VecDeque<(u64, Option<Waker>)>,
+}
+
+// A request's place in the queue and in the count of those in flight,
+// given up when done or dropped.
+struct Slot<S> {
+    shared: Arc<Mutex<Shared<S>>>,
+    state: SlotState,
+}
+
+enum SlotState {
+    New,
+    Queued(u64),
+    InFlight,
+    Done,
+}
+
+// ===== impl TowerToHyperReadyService =====
+
+impl<S> TowerToHyperReadyService<S> {
+    /// Create a new `TowerToHyperReadyService` from a tower service.
+    pub fn new(tower_service: S) -> Self {
+        TowerToHyperReadyService {
+            shared: Arc::new(Mutex::new(Shared {
+                service: tower_service,
+                limit: usize::MAX,
+                in_flight: 0,
+                next_id: 0,
+                queue: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Set the most requests in flight at once, waiting on the service.
+    ///
+    /// Further requests wait in order. Values lower than 1 are raised to 1.
+    ///
+    /// Default is no limit.
+    pub fn concurrency_limit(self, limit: usize) -> Self {
+        self.shared.lock().unwrap().limit = limit.max(1);
+        self
+    }
+}
+
+impl<S, R> hyper::service::Service<R> for TowerToHyperReadyService<S>
+where
+    S: tower_service::Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = TowerToHyperReadyServiceFuture<S, R>;
+
+    fn call(&self, req: R) -> Self::Future {
+        TowerToHyperReadyServiceFuture {
+            state: State::Waiting { req: Some(req) },
+            slot: Slot {
+                shared: self.shared.clone(),
+                state: SlotState::New,
+            },
+        }
+    }
+}
+
+impl<S> Clone for TowerToHyperReadyService<S> {
+    fn clone(&self) -> Self {
+        TowerToHyperReadyService {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for TowerToHyperReadyService<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        f.debug_struct("TowerToHyperReadyService")
+            .field("in_flight", &shared.in_flight)
+            .field("queued", &shared.queue.len())
+            .finish()
+    }
+}
+
+// ===== impl TowerToHyperReadyServiceFuture =====
+
+impl<S, R> Future for TowerToHyperReadyServiceFuture<S, R>
+where
+    S: tower_service::Service<R>,
+{
+    type Output = Result<S::Response, S::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            match this.state.as_mut().project() {
+                StateProj::Waiting { req } => {
+                    let future = match futures_util::ready!(this.slot.poll_call(cx, req)) {
+                        Ok(future) => future,
+                        Err(err) => return Poll::Ready(Err(err)),
+                    };
+                    this.state.set(State::Called { future });
+                }
+                StateProj::Called { future } => {
+                    let res = futures_util::ready!(future.poll(cx));
+                    this.slot.release();
+                    return Poll::Ready(res);
+                }
+            }
+        }
+    }
+}
+
+impl<S, R> fmt::Debug for TowerToHyperReadyServiceFuture<S, R>
+where
+    S: tower_service::Service<R>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TowerToHyperReadyServiceFuture")
+            .finish_non_exhaustive()
+    }
+}
+
+// ===== impl Slot =====
+
+impl<S> Slot<S> {
+    fn poll_call<R>(
+        &mut self,
+        cx: &mut Context<'_>,
+        req: &mut Option<R>,
+    ) -> Poll<Result<S::Future, S::Error>>
+    where
+        S: tower_service::Service<R>,
+    {
+        let mut shared = self.shared.lock().unwrap();
+        let id = match self.state {
+            SlotState::Queued(id) => id,
+            _ => {
+                let id = shared.next_id;
+                shared.next_id += 1;
+                shared.queue.push_back((id, None));
+                self.state = SlotState::Queued(id);
+                id
+            }
+        };
+
+        let first = shared.queue.front().map(|&(first, _)| first) == Some(id);
+        if !first || shared.in_flight >= shared.limit {
+            if let Some(entry) = shared.queue.iter_mut().find(|(queued, _)| *queued == id) {
+                entry.1 = Some(cx.waker().clone());
+            }
+            return Poll::Pending;
+        }
+
+        // The first in line waits on the service itself, which wakes it.
+        let ready = shared.service.poll_ready(cx);
+        if let Poll::Ready(res) = ready {
+            shared.queue.pop_front();
+            shared.wake_next();
+            self.state = SlotState::Done;
+            res?;
+            self.state = SlotState::InFlight;
+            shared.in_flight += 1;
+            let req = req.take().expect("polled after ready");
+            return Poll::Ready(Ok(shared.service.call(req)));
+        }
+        Poll::Pending
+    }
+
+    fn release(&mut self) {
+        let mut shared = self.shared.lock().unwrap();
+        match std::mem::replace(&mut self.state, SlotState::Done) {
+            SlotState::Queued(id) => {
+                let was_first = shared.queue.front().map(|&(first, _)| first) == Some(id);
+                shared.queue.retain(|&(queued, _)| queued != id);
+                if was_first {
+                    shared.wake_next();
+                }
+            }
+            SlotState::InFlight => {
+                shared.in_flight -= 1;
+                shared.wake_next();
+            }
+            SlotState::New | SlotState::Done => {}
+        }
+    }
+}
+
+impl<S> Drop for Slot<S> {
+    fn drop(&mut self) {
+        if !matches!(self.state, SlotState::New | SlotState::Done) {
+            self.release();
+        }
+    }
+}
+
+impl<S> Shared<S> {
+    fn wake_next(&mut self) {
+        if let Some((_, Some(waker))) = self.queue.front_mut() {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::future::Future;
+    use std::task::{Context, Poll};
+
+    use hyper::service::Service as _;
+    use tokio::sync::oneshot;
+
+    use super::TowerToHyperReadyService;
+
+    fn poll<F: Future + Unpin>(fut: &mut F) -> Poll<F::Output> {
+        let waker = futures_util::task::noop_waker();
+        std::pin::Pin::new(fut).poll(&mut Context::from_waker(&waker))
+    }
+
+    #[tokio::test]
+    async fn calls_in_order_up_to_the_limit() {
+        let service = tower::service_fn(|rx: oneshot::Receiver<u8>| async move {
+            Ok::<_, Infallible>(rx.await.unwrap())
+        });
+        let service = TowerToHyperReadyService::new(service).concurrency_limit(1);
+
+        let (tx1, rx1) = oneshot::channel();
+        let (tx2, rx2) = oneshot::channel();
+        let mut first = Box::pin(service.call(rx1));
+        let mut second = Box::pin(service.call(rx2));
+        assert!(poll(&mut first).is_pending());
+        assert!(poll(&mut second).is_pending());
+        assert!(format!("{:?}", service).contains("queued: 1"));
+
+        // the second isn't called until the first is done
+        tx2.send(2).unwrap();
+        assert!(poll(&mut second).is_pending());
+
+        tx1.send(1).unwrap();
+        assert!(matches!(poll(&mut first), Poll::Ready(Ok(1))));
+        assert_eq!(second.await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn dropped_requests_give_up_their_place() {
+        let service = tower::service_fn(|rx: oneshot::Receiver<u8>| async move {
+            Ok::<_, Infallible>(rx.await.unwrap())
+        });
+        let service = TowerToHyperReadyService::new(service).concurrency_limit(1);
+
+        // one in flight, one waiting in line
+        let (_tx1, rx1) = oneshot::channel();
+        let (_tx2, rx2) = oneshot::channel();
+        let mut first = Box::pin(service.call(rx1));
+        let mut second = Box::pin(service.call(rx2));
+        assert!(poll(&mut first).is_pending());
+        assert!(poll(&mut second).is_pending());
+        drop(second);
+        drop(first);
+
+        let (tx3, rx3) = oneshot::channel();
+        tx3.send(3).unwrap();
+        let mut third = Box::pin(service.call(rx3));
+        assert!(matches!(poll(&mut third), Poll::Ready(Ok(3))));
+    }
+}
@@ -0,0 +1,772 @@
+//! An in-process HTTP server for tests.
+//!
+//! This module provides [`TestServer`], to test code that makes requests
+//! without a server of its own. It serves HTTP/1 and HTTP/2 with the
+//! [auto connection builder](crate::server::conn::auto::Builder), either on
+//! an ephemeral port of localhost or over in-memory streams. It answers with
+//! programmed responses, and captures the requests it received, with their
+//! bodies, for assertions.
+//!
+//! A [`Shape`] delays, stalls or cuts the responses to a path, to exercise
+//! the timeouts and retries of a client. With a
+//! [`ManualTimer`](crate::rt::ManualTimer) set as the timer of the server,
+//! the delays only pass when the test advances the clock.
+//!
+//! # Example
+//!
+//! ```
+//! # #[cfg(all(feature = "client-legacy", feature = "http1"))]
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use bytes::Bytes;
+//! use http::{Response, StatusCode};
+//! use http_body_util::Empty;
+//! use hyper_util::client::legacy::Client;
+//! use hyper_util::rt::TokioExecutor;
+//! use hyper_util::testing::TestServer;
+//!
+//! let server = TestServer::bind().await?;
+//! server.enqueue(Response::new(Bytes::from("hello")));
+//!
+//! let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+//! let res = client.get(server.uri("/greeting")).await?;
+//! assert_eq!(res.status(), StatusCode::OK);
+//!
+//! let req = server.next_request().await;
+//! assert_eq!(req.uri().path(), "/greeting");
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http::{Request, Response, Uri};
+use http_body::{Body, Frame, SizeHint};
+use hyper::body::Incoming;
+use hyper::rt::{Sleep, Timer};
+use hyper::service::service_fn;
+use tokio::io::DuplexStream;
+use tokio::net::TcpListener;
+use tokio::sync::{mpsc, Notify};
+use tokio::task::JoinHandle;
+
+use crate::body::collect_with_limit;
+use crate::common::timer;
+use crate::rt::{TokioExecutor, TokioIo, TokioTimer};
+use crate::server::conn::auto;
+
+type Handler = Arc<dyn Fn(&Request<Bytes>) -> Response<Bytes> + Send + Sync>;
+
+// The buffer size of in-memory connections.
+const DUPLEX_BUFFER: usize = 64 * 1024;
+
+/// An in-process HTTP server for tests.
+///
+/// Each request is answered with the next response of
+/// [`enqueue`](TestServer::enqueue), or else by the handler of
+/// [`respond_with`](TestServer::respond_with), or else with an empty
+/// `200 OK`. Requests are captured with their bodies collected, after the
+/// response is chosen.
+///
+/// The server stops accepting connections when dropped.
+pub struct TestServer {
+    shared: Arc<Shared>,
+    addr: Option<SocketAddr>,
+    accept: Option<JoinHandle<()>>,
+}
+
+/// How a [`TestServer`] delays or breaks the responses to a path.
+///
+/// Delays are measured with the timer of the server, set with
+/// [`TestServer::set_timer`].
+///
+/// # Example
+///
+/// ```
+/// use std::time::Duration;
+/// use hyper_util::testing::Shape;
+///
+/// // answer after 2 seconds, and stop after 10 bytes of the body
+/// let shape = Shape::new()
+///     .delay(Duration::from_secs(2))
+///     .disconnect_after(10);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Shape {
+    delay: Option<Duration>,
+    stall: Option<(usize, Duration)>,
+    disconnect: Option<usize>,
+    disconnect_before_response: bool,
+}
+
+/// A connector dialing in-memory connections to a [`TestServer`], with the
+/// legacy client.
+///
+/// Every destination is the test server.
+#[cfg(feature = "client-legacy")]
+#[derive(Clone, Debug)]
+pub struct TestConnector {
+    shared: Arc<Shared>,
+}
+
+/// An in-memory connection to a [`TestServer`], dialed by a
+/// [`TestConnector`].
+#[cfg(feature = "client-legacy")]
+#[derive(Debug)]
+pub struct TestStream {
+    inner: TokioIo<DuplexStream>,
+}
+
+/// A listener of in-memory connections, dialed with a [`DuplexDialer`].
+///
+/// This is an [`Accept`](crate::server::accept::Accept), to test the code
+/// serving a listener without a socket.
+///
+/// # Example
+///
+/// ```
+/// # async fn run() -> std::io::Result<()> {
+/// use hyper_util::server::accept;
+/// use hyper_util::testing::DuplexListener;
+///
+/// let mut listener = DuplexListener::new();
+/// let dialer = listener.dialer();
+///
+/// let _client = dialer.connect()?;
+/// let (_server, ()) = accept::accept(&mut listener).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct DuplexListener {
+    tx: mpsc::UnboundedSender<DuplexStream>,
+    rx: mpsc::UnboundedReceiver<DuplexStream>,
+}
+
+/// Dials in-memory connections to a [`DuplexListener`].
+#[derive(Clone, Debug)]
+pub struct DuplexDialer {
+    tx: mpsc::UnboundedSender<DuplexStream>,
+}
+
+struct Shared {
+    builder: auto::Builder<TokioExecutor>,
+    timer: Mutex<timer::Timer>,
+    state: Mutex<State>,
+    arrived: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    requests: Vec<Request<Bytes>>,
+    // the requests already returned by `next_request`
+    taken: usize,
+    responses: VecDeque<Response<Bytes>>,
+    handler: Option<Handler>,
+    shapes: HashMap<String, Shape>,
+}
+
+// The body of the responses of a `TestServer`, sent as shaped.
+struct TestBody {
+    steps: VecDeque<Step>,
+    // announced, including what a disconnect cuts
+    remaining: u64,
+}
+
+enum Step {
+    Data(Bytes),
+    Stall(Pin<Box<dyn Sleep>>),
+    // lets the connection flush the data before disconnecting
+    Yield,
+    Disconnect,
+}
+
+// ===== impl TestServer =====
+
+impl TestServer {
+    /// Start a server listening on an ephemeral port of `127.0.0.1`.
+    ///
+    /// This must be called within a tokio runtime.
+    pub async fn bind() -> io::Result<TestServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await?;
+        let addr = listener.local_addr()?;
+        let shared = Shared::new();
+        let accept = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, _)) => shared.serve(stream),
+                        Err(_) => continue,
+                    }
+                }
+            }
+        });
+        Ok(TestServer {
+            shared,
+            addr: Some(addr),
+            accept: Some(accept),
+        })
+    }
+
+    /// Create a server only reachable in memory, with
+    /// [`connect`](TestServer::connect).
+    pub fn in_memory() -> TestServer {
+        TestServer {
+            shared: Shared::new(),
+            addr: None,
+            accept: None,
+        }
+    }
+
+    /// The address the server listens on, if it was bound.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    /// The URI of `path_and_query` on this server.
+    ///
+    /// The authority is the address of the server, or `localhost` if it is
+    /// in memory.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `path_and_query` isn't a valid path and query.
+    pub fn uri(&self, path_and_query: &str) -> Uri {
+        let authority = match self.addr {
+            Some(addr) => addr.to_string(),
+            None => "localhost".to_owned(),
+        };
+        Uri::builder()
+            .scheme("http")
+            .authority(authority)
+            .path_and_query(path_and_query)
+            .build()
+            .expect("valid path and query")
+    }
+
+    /// Open an in-memory connection to the server.
+    ///
+    /// The server side of the connection is served on a spawned task, so
+    /// this must be called within a tokio runtime.
+    pub fn connect(&self) -> TokioIo<DuplexStream> {
+        TokioIo::new(self.shared.connect())
+    }
+
+    /// A connector for the legacy client, dialing in-memory connections to
+    /// the server.
+    #[cfg(feature = "client-legacy")]
+    pub fn connector(&self) -> TestConnector {
+        TestConnector {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Answer the next request without a response in the queue with `res`.
+    pub fn enqueue(&self, res: Response<Bytes>) {
+        self.shared.state().responses.push_back(res);
+    }
+
+    /// Answer the requests with `f`, once the queue of responses is empty.
+    pub fn respond_with<F>(&self, f: F)
+    where
+        F: Fn(&Request<Bytes>) -> Response<Bytes> + Send + Sync + 'static,
+    {
+        self.shared.state().handler = Some(Arc::new(f));
+    }
+
+    /// Delay or break the responses to requests for `path`, matched
+    /// exactly, whichever way they are chosen.
+    ///
+    /// A shape set for the same path replaces the previous one.
+    pub fn shape(&self, path: impl Into<String>, shape: Shape) {
+        self.shared.state().shapes.insert(path.into(), shape);
+    }
+
+    /// Set the timer measuring the delays of the shapes.
+    ///
+    /// Default is a [`TokioTimer`].
+    pub fn set_timer<M>(&self, timer: M)
+    where
+        M: Timer + Send + Sync + 'static,
+    {
+        *self.shared.timer.lock().unwrap() = timer::Timer::new(timer);
+    }
+
+    /// The requests received so far, in order.
+    pub fn requests(&self) -> Vec<Request<Bytes>> {
+        self.shared.state().requests.clone()
+    }
+
+    /// Wait for the next request not returned by `next_request` yet.
+    pub async fn next_request(&self) -> Request<Bytes> {
+        loop {
+            let arrived = self.shared.arrived.notified();
+            {
+                let mut state = self.shared.state();
+                if let Some(req) = state.requests.get(state.taken).cloned() {
+                    state.taken += 1;
+                    return req;
+                }
+            }
+            arrived.await;
+        }
+    }
+}
+
+impl fmt::Debug for TestServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TestServer")
+            .field("addr", &self.addr)
+            .field("requests", &self.shared.state().requests.len())
+            .finish()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        if let Some(accept) = self.accept.take() {
+            accept.abort();
+        }
+    }
+}
+
+// ===== impl Shared =====
+
+impl Shared {
+    fn new() -> Arc<Shared> {
+        Arc::new(Shared {
+            builder: auto::Builder::new(TokioExecutor::new()),
+            timer: Mutex::new(timer::Timer::new(TokioTimer::new())),
+            state: Mutex::new(State::default()),
+            arrived: Notify::new(),
+        })
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn connect(self: &Arc<Self>) -> DuplexStream {
+        let (client, server) = tokio::io::duplex(DUPLEX_BUFFER);
+        self.serve(server);
+        client
+    }
+
+    // Serve a connection on a spawned task.
+    fn serve<I>(self: &Arc<Self>, io: I)
+    where
+        I: tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + Unpin + 'static,
+    {
+        let shared = self.clone();
+        tokio::spawn(async move {
+            let service = service_fn(|req| shared.clone().handle(req));
+            // errors only end the connection
+            let _ = shared
+                .builder
+                .serve_connection(TokioIo::new(io), service)
+                .await;
+        });
+    }
+
+    async fn handle(
+        self: Arc<Self>,
+        req: Request<Incoming>,
+    ) -> Result<Response<TestBody>, Box<dyn StdError + Send + Sync>> {
+        let (parts, body) = req.into_parts();
+        let body = collect_with_limit(body, usize::MAX, None).await?;
+        let req = Request::from_parts(parts, body);
+
+        let (res, shape) = {
+            let mut state = self.state();
+            let res = match state.responses.pop_front() {
+                Some(res) => res,
+                None => match state.handler {
+                    Some(ref handler) => handler(&req),
+                    None => Response::new(Bytes::new()),
+                },
+            };
+            let shape = state.shapes.get(req.uri().path()).cloned();
+            state.requests.push(req);
+            (res, shape.unwrap_or_default())
+        };
+        // The sleeps start before the request can be seen, so a test
+        // advancing a manual timer after `next_request` passes them.
+        let timer = self.timer.lock().unwrap().clone();
+        let delay = shape.delay.map(|delay| timer.sleep(delay));
+        let res = res.map(|body| TestBody::new(body, &shape, &timer));
+        self.arrived.notify_waiters();
+
+        if let Some(delay) = delay {
+            delay.await;
+        }
+        if shape.disconnect_before_response {
+            return Err(disconnected().into());
+        }
+        Ok(res)
+    }
+}
+
+impl fmt::Debug for Shared {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Shared").finish()
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, "test server disconnected")
+}
+
+// ===== impl Shape =====
+
+impl Shape {
+    /// A shape answering right away, in full.
+    pub fn new() -> Shape {
+        Shape::default()
+    }
+
+    /// Wait for `delay` before sending the response.
+    pub fn delay(mut self, delay: Duration) -> Shape {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Send `bytes` of the body, and the rest no sooner than `delay` after
+    /// the response is chosen.
+    ///
+    /// The delay starts along with the one of [`delay`](Shape::delay), so
+    /// it must be the longer one to stall the body.
+    pub fn stall_after(mut self, bytes: usize, delay: Duration) -> Shape {
+        self.stall = Some((bytes, delay));
+        self
+    }
+
+    /// Close the connection after sending `bytes` of the body, and after
+    /// any stall.
+    ///
+    /// The response still announces the full length of the body. Over
+    /// HTTP/2, the stream is reset rather than the connection closed.
+    pub fn disconnect_after(mut self, bytes: usize) -> Shape {
+        self.disconnect = Some(bytes);
+        self
+    }
+
+    /// Close the connection without sending a response, after any delay.
+    ///
+    /// Over HTTP/2, the stream is reset rather than the connection closed.
+    pub fn disconnect_before_response(mut self) -> Shape {
+        self.disconnect_before_response = true;
+        self
+    }
+}
+
+// ===== impl TestBody =====
+
+impl TestBody {
+    fn new(mut data: Bytes, shape: &Shape, timer: &timer::Timer) -> TestBody {
+        let remaining = data.len() as u64;
+        let mut steps = VecDeque::new();
+        let mut sent = 0;
+        if let Some((bytes, delay)) = shape.stall {
+            if shape.disconnect.map_or(true, |cut| bytes <= cut) {
+                let bytes = bytes.min(data.len());
+                steps.push_back(Step::Data(data.split_to(bytes)));
+                steps.push_back(Step::Stall(timer.sleep(delay)));
+                sent = bytes;
+            }
+        }
+        if let Some(cut) = shape.disconnect {
+            let bytes = cut.saturating_sub(sent).min(data.len());
+            steps.push_back(Step::Data(data.split_to(bytes)));
+            steps.push_back(Step::Yield);
+            steps.push_back(Step::Disconnect);
+        } else {
+            steps.push_back(Step::Data(data));
+        }
+        steps.retain(|step| !matches!(step, Step::Data(data) if data.is_empty()));
+        TestBody { steps, remaining }
+    }
+}
+
+impl Body for TestBody {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        loop {
+            match self.steps.front_mut() {
+                Some(Step::Stall(sleep)) => futures_util::ready!(sleep.as_mut().poll(cx)),
+                Some(Step::Yield) => {
+                    self.steps.pop_front();
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                _ => (),
+            }
+            match self.steps.pop_front() {
+                Some(Step::Data(data)) => {
+                    self.remaining -= data.len() as u64;
+                    return Poll::Ready(Some(Ok(Frame::data(data))));
+                }
+                Some(Step::Stall(_)) | Some(Step::Yield) => continue,
+                Some(Step::Disconnect) => {
+                    self.steps.clear();
+                    return Poll::Ready(Some(Err(disconnected())));
+                }
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.remaining)
+    }
+}
+
+// ===== impl TestConnector =====
+
+#[cfg(feature = "client-legacy")]
+impl tower_service::Service<Uri> for TestConnector {
+    type Response = TestStream;
+    type Error = std::convert::Infallible;
+    type Future = std::future::Ready<Result<TestStream, std::convert::Infallible>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _: Uri) -> Self::Future {
+        std::future::ready(Ok(TestStream {
+            inner: TokioIo::new(self.shared.connect()),
+        }))
+    }
+}
+
+// ===== impl TestStream =====
+
+#[cfg(feature = "client-legacy")]
+impl crate::client::legacy::connect::Connection for TestStream {
+    fn connected(&self) -> crate::client::legacy::connect::Connected {
+        crate::client::legacy::connect::Connected::new()
+    }
+}
+
+#[cfg(feature = "client-legacy")]
+impl hyper::rt::Read for TestStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: hyper::rt::ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+#[cfg(feature = "client-legacy")]
+impl hyper::rt::Write for TestStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+// ===== impl DuplexListener =====
+
+impl DuplexListener {
+    /// Create a listener.
+    pub fn new() -> DuplexListener {
+        let (tx, rx) = mpsc::unbounded_channel();
+        DuplexListener { tx, rx }
+    }
+
+    /// A dialer of connections to this listener.
+    pub fn dialer(&self) -> DuplexDialer {
+        DuplexDialer {
+            tx: self.tx.clone(),
+        }
+    }
+}
+
+impl Default for DuplexListener {
+    fn default() -> DuplexListener {
+        DuplexListener::new()
+    }
+}
+
+impl crate::server::accept::Accept for DuplexListener {
+    type Io = DuplexStream;
+    type Peer = ();
+
+    fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<(DuplexStream, ())>> {
+        // the listener holds a sender, so the channel never closes
+        self.rx
+            .poll_recv(cx)
+            .map(|stream| Ok((stream.expect("listener holds a sender"), ())))
+    }
+}
+
+// ===== impl DuplexDialer =====
+
+impl DuplexDialer {
+    /// Dial a connection, returning the client side of it.
+    ///
+    /// This fails with `ConnectionRefused` once the listener is dropped.
+    pub fn connect(&self) -> io::Result<DuplexStream> {
+        let (client, server) = tokio::io::duplex(DUPLEX_BUFFER);
+        self.tx
+            .send(server)
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        Ok(client)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use http::{Request, Response, StatusCode};
+    use http_body_util::{BodyExt, Empty, Full};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::TestServer;
+
+    #[tokio::test]
+    async fn captures_requests_and_answers_in_order() {
+        let server = TestServer::bind().await.unwrap();
+        server.enqueue(Response::new(Bytes::from("first")));
+        server.respond_with(|req| {
+            let mut res = Response::new(req.body().clone());
+            *res.status_mut() = StatusCode::CREATED;
+            res
+        });
+
+        let mut tcp = tokio::net::TcpStream::connect(server.addr().unwrap())
+            .await
+            .unwrap();
+        tcp.write_all(b"GET /a HTTP/1.1\r\nhost: test\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = String::new();
+        tcp.read_to_string(&mut buf).await.unwrap();
+        assert!(buf.starts_with("HTTP/1.1 200 OK"), "{}", buf);
+        assert!(buf.ends_with("first"), "{}", buf);
+
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(server.connect())
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let req = Request::post(server.uri("/b"))
+            .body(Full::new(Bytes::from("echo")))
+            .unwrap();
+        let res = sender.send_request(req).await.unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        assert_eq!(res.into_body().collect().await.unwrap().to_bytes(), "echo");
+
+        assert_eq!(server.next_request().await.uri().path(), "/a");
+        let req = server.next_request().await;
+        assert_eq!(req.uri().path(), "/b");
+        assert_eq!(req.body(), "echo");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn shapes_delay_stall_and_disconnect() {
+        use std::time::Duration;
+
+        use futures_util::FutureExt;
+
+        use super::Shape;
+        use crate::rt::ManualTimer;
+
+        let timer = ManualTimer::new();
+        let server = TestServer::in_memory();
+        server.set_timer(timer.clone());
+        server.respond_with(|_| Response::new(Bytes::from("abcdef")));
+        server.shape("/slow", Shape::new().delay(Duration::from_secs(5)));
+        server.shape(
+            "/stall",
+            Shape::new()
+                .stall_after(2, Duration::from_secs(5))
+                .disconnect_after(4),
+        );
+        server.shape("/gone", Shape::new().disconnect_before_response());
+
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(server.connect())
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let get = |path| {
+            Request::get(server.uri(path))
+                .body(Empty::<Bytes>::new())
+                .unwrap()
+        };
+
+        let mut res = Box::pin(sender.send_request(get("/slow")));
+        server.next_request().await;
+        tokio::task::yield_now().await;
+        assert!(res.as_mut().now_or_never().is_none(), "delayed");
+        timer.advance(Duration::from_secs(5));
+        let body = res.await.unwrap().into_body().collect().await.unwrap();
+        assert_eq!(body.to_bytes(), "abcdef");
+
+        let mut body = sender
+            .send_request(get("/stall"))
+            .await
+            .unwrap()
+            .into_body();
+        let frame = body.frame().await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "ab");
+        let mut frame = Box::pin(body.frame());
+        tokio::task::yield_now().await;
+        assert!(frame.as_mut().now_or_never().is_none(), "stalled");
+        timer.advance(Duration::from_secs(5));
+        let frame = frame.await.unwrap().unwrap();
+        assert_eq!(frame.into_data().unwrap(), "cd");
+        assert!(body.frame().await.unwrap().is_err(), "cut short");
+
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(server.connect())
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        assert!(sender.send_request(get("/gone")).await.is_err());
+    }
+
+    #[cfg(all(feature = "client-legacy", feature = "http1"))]
+    #[tokio::test]
+    async fn connector_dials_in_memory() {
+        use crate::client::legacy::Client;
+        use crate::rt::TokioExecutor;
+
+        let server = TestServer::in_memory();
+        let client =
+            Client::builder(TokioExecutor::new()).build::<_, Empty<Bytes>>(server.connector());
+        let res = client.get(server.uri("/c?d=e")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(server.next_request().await.uri().query(), Some("d=e"));
+    }
+}
@@ -4,6 +4,7 @@ use std::io::{Read, Write};
 use std::net::{SocketAddr, TcpListener};
 use std::pin::Pin;
 use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
 use std::task::Poll;
 use std::thread;
 use std::time::Duration;
@@ -18,8 +19,8 @@ use http_body_util::{Empty, Full, StreamBody};
 use hyper::body::Bytes;
 use hyper::body::Frame;
 use hyper::Request;
-use hyper_util::client::legacy::connect::{capture_connection, HttpConnector};
-use hyper_util::client::legacy::Client;
+use hyper_util::client::legacy::connect::{capture_connection, HttpConnector, ResolvedAddrs};
+use hyper_util::client::legacy::{Client, EndpointHint, HintStore};
 use hyper_util::rt::{TokioExecutor, TokioIo};
 
 use test_utils::{DebugConnector, DebugStream};
@@ -908,3 +909,1710 @@ fn capture_connection_on_client() {
     rt.block_on(client.request(req)).expect("200 OK");
     assert!(captured_conn.connection_metadata().is_some());
 }
+
+#[cfg(not(miri))]
+#[test]
+fn pool_tag_affinity() {
+    use hyper_util::client::legacy::PoolTag;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let send = |path: &str, tag: Option<&str>| {
+        let mut req = Request::builder()
+            .uri(&*format!("http://{}{}", addr, path))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        if let Some(tag) = tag {
+            req.extensions_mut().insert(PoolTag::new(tag));
+        }
+        rt.block_on(client.request(req)).expect("200 OK");
+        // let the connection go back to the pool
+        thread::sleep(Duration::from_millis(50));
+        connects.load(Ordering::SeqCst)
+    };
+
+    assert_eq!(send("/a", None), 1);
+    assert_eq!(
+        send("/b", Some("session=abc")),
+        2,
+        "tag requires a new connection"
+    );
+    assert_eq!(send("/c", Some("session=abc")), 2, "same tag reuses it");
+    assert_eq!(
+        send("/d", None),
+        2,
+        "untagged reuses the untagged connection"
+    );
+    assert_eq!(send("/e", Some("session=def")), 3);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn notify_network_changed_redials() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let get = || {
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        client.request(req)
+    };
+    let send = || {
+        rt.block_on(get()).expect("200 OK");
+        // let the connection go back to the pool
+        thread::sleep(Duration::from_millis(50));
+        connects.load(Ordering::SeqCst)
+    };
+
+    assert_eq!(send(), 1);
+    assert_eq!(send(), 1, "idle connection reused");
+
+    client.notify_network_changed();
+    assert_eq!(send(), 2, "idle connection dropped");
+
+    // A connection in use while the network changes isn't pooled.
+    let res = rt.block_on(get()).expect("200 OK");
+    client.notify_network_changed();
+    drop(res);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(send(), 3);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn poisoned_connection_is_not_reused() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let send = || {
+        let mut req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let captured = capture_connection(&mut req);
+        rt.block_on(client.request(req)).expect("200 OK");
+        // let the connection go back to the pool
+        thread::sleep(Duration::from_millis(50));
+        (captured, connects.load(Ordering::SeqCst))
+    };
+
+    let (captured, n) = send();
+    assert_eq!(n, 1);
+    assert_eq!(send().1, 1, "idle connection reused");
+
+    let connected = captured.connection_metadata();
+    let connected = connected.as_ref().expect("connected");
+    assert!(!connected.is_poisoned());
+    connected.poison();
+    assert_eq!(send().1, 2, "poisoned connection dropped");
+}
+
+#[cfg(not(miri))]
+#[test]
+fn connect_race_delay_prefers_reuse() {
+    use hyper_util::rt::TokioTimer;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new())
+        .pool_timer(TokioTimer::new())
+        .connect_race_delay(Duration::from_secs(5))
+        .build(connector);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    thread::sleep(Duration::from_millis(100));
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let send = || {
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        client.request(req)
+    };
+
+    // the second waits for the connection in use by the first, instead of
+    // dialing
+    rt.block_on(async {
+        let first = tokio::spawn(send());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        send().await.expect("200 OK");
+        first.await.unwrap().expect("200 OK");
+    });
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn background_requests_within_quota() {
+    use hyper_util::client::legacy::Priority;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new())
+        .pool_background_connection_quota(1)
+        .build(connector);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    thread::sleep(Duration::from_millis(100));
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let send = |priority| {
+        let mut req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        req.extensions_mut().insert(priority);
+        client.request(req)
+    };
+
+    // a background request waits for the connection in use, instead of
+    // dialing past the quota, while an interactive one dials
+    rt.block_on(async {
+        let first = tokio::spawn(send(Priority::Interactive));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        send(Priority::Background).await.expect("200 OK");
+        first.await.unwrap().expect("200 OK");
+    });
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+    rt.block_on(async {
+        let first = tokio::spawn(send(Priority::Interactive));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        send(Priority::Interactive).await.expect("200 OK");
+        first.await.unwrap().expect("200 OK");
+    });
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn request_queue_overflow() {
+    use hyper_util::client::legacy::QueueOverflow;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    thread::sleep(Duration::from_millis(100));
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    for overflow in [QueueOverflow::Reject, QueueOverflow::DropOldest] {
+        let client = Client::builder(TokioExecutor::new())
+            .pool_max_in_use(1)
+            .request_queue_max_requests(1)
+            .request_queue_overflow(overflow)
+            .build(HttpConnector::new());
+        let send = || {
+            let req = Request::builder()
+                .uri(&*format!("http://{}/a", addr))
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+            client.request(req)
+        };
+
+        rt.block_on(async {
+            // the first waits for a connection, and the second for the
+            // connection of the first to be free
+            let first = tokio::spawn(send());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let second = tokio::spawn(send());
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let third = send().await;
+
+            let (refused, sent) = match overflow {
+                QueueOverflow::Reject => (third.err(), second.await.unwrap()),
+                _ => (second.await.unwrap().err(), third),
+            };
+            assert!(refused.expect("refused").is_request_queue_full());
+            sent.expect("200 OK");
+            first.await.unwrap().expect("200 OK");
+        });
+    }
+}
+
+#[cfg(not(miri))]
+#[test]
+fn pool_stats_reported_until_dropped() {
+    use hyper_util::client::legacy::{PoolDump, PoolStatsReporter};
+    use hyper_util::rt::TokioTimer;
+
+    struct Collect(Arc<Mutex<Vec<PoolDump>>>);
+
+    impl PoolStatsReporter for Collect {
+        fn report(&self, dump: &PoolDump) {
+            self.0.lock().unwrap().push(dump.clone());
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let reports = Arc::new(Mutex::new(Vec::new()));
+
+    let client = Client::builder(TokioExecutor::new())
+        .pool_timer(TokioTimer::new())
+        .pool_stats_reporter(Duration::from_millis(10), Collect(reports.clone()))
+        .build(HttpConnector::new());
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        while sock.read(&mut buf).map_or(false, |n| n > 0) {
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("write");
+        }
+    });
+
+    rt.block_on(async {
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        client.request(req).await.expect("200 OK");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let last = reports.lock().unwrap().last().cloned().expect("reported");
+        assert_eq!(last.hosts().len(), 1);
+        assert_eq!(last.hosts()[0].idle().len(), 1);
+
+        drop(client);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let reported = reports.lock().unwrap().len();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(reports.lock().unwrap().len(), reported);
+    });
+}
+
+#[cfg(not(miri))]
+#[test]
+fn keep_alive_hints_limit_reuse() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                let mut max = 2;
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    max -= 1;
+                    let res = format!(
+                        "HTTP/1.1 200 OK\r\nKeep-Alive: timeout=1, max={}\r\nContent-Length: 0\r\n\r\n",
+                        max
+                    );
+                    sock.write_all(res.as_bytes()).expect("write");
+                }
+            });
+        }
+    });
+
+    let send = || {
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        rt.block_on(client.request(req)).expect("200 OK");
+        connects.load(Ordering::SeqCst)
+    };
+
+    assert_eq!(send(), 1);
+    assert_eq!(send(), 1, "reused while the server takes more requests");
+    assert_eq!(send(), 2, "not reused after the server's max");
+    thread::sleep(Duration::from_millis(600));
+    assert_eq!(send(), 3, "not reused near the server's timeout");
+}
+
+#[cfg(not(miri))]
+#[test]
+fn retry_after_backs_off_host() {
+    use hyper_util::rt::TokioTimer;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let client = Client::builder(TokioExecutor::new())
+        .pool_timer(TokioTimer::new())
+        .pool_retry_after_backoff(Duration::from_millis(200))
+        .build(HttpConnector::new());
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        let mut res: &[u8] =
+            b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 10\r\nContent-Length: 0\r\n\r\n";
+        while sock.read(&mut buf).map_or(false, |n| n > 0) {
+            sock.write_all(res).expect("write");
+            res = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        }
+    });
+
+    let send = || {
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        rt.block_on(client.request(req)).expect("response")
+    };
+
+    assert_eq!(send().status(), 429);
+    let start = std::time::Instant::now();
+    assert_eq!(send().status(), 200);
+    let waited = start.elapsed();
+    assert!(waited >= Duration::from_millis(200), "waited {:?}", waited);
+    assert!(waited < Duration::from_secs(2), "waited {:?}", waited);
+}
+
+#[cfg(all(not(miri), feature = "client-retry"))]
+#[test]
+fn stale_408_retried_on_new_connection() {
+    use hyper_util::client::legacy::retry::RetryLayer;
+    use hyper_util::rt::TokioTimer;
+    use tower::{Layer, ServiceExt};
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+    let client = RetryLayer::new(TokioTimer::new())
+        .retry_stale_408(true)
+        .layer(client);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                // the first request, then a timeout as the second arrives
+                sock.read(&mut buf).expect("read");
+                sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .expect("write");
+                if sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    sock.write_all(
+                        b"HTTP/1.1 408 Request Timeout\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+                    )
+                    .expect("write");
+                }
+            });
+        }
+    });
+
+    let send = || {
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        rt.block_on(client.clone().oneshot(req)).expect("response")
+    };
+
+    assert_eq!(send().status(), 200);
+    assert_eq!(send().status(), 200, "408 retried");
+    assert_eq!(connects.load(Ordering::SeqCst), 2);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn over_memory_budget_is_not_pooled() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    // Two HTTP/1 connections with 8kb read and write buffers fit.
+    let client = Client::builder(TokioExecutor::new())
+        .http1_max_buf_size(8192)
+        .pool_max_memory(2 * 2 * 8192)
+        .build(connector);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let get = || {
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        client.request(req)
+    };
+
+    let (a, b, c) = rt
+        .block_on(future::try_join3(get(), get(), get()))
+        .expect("200 OK");
+    assert_eq!(connects.load(Ordering::SeqCst), 3);
+    drop((a, b, c));
+    // let the connections go back to the pool
+    thread::sleep(Duration::from_millis(50));
+
+    // The first connection was returned over budget and dropped, the
+    // other two were kept.
+    rt.block_on(future::try_join3(get(), get(), get()))
+        .expect("200 OK");
+    assert_eq!(connects.load(Ordering::SeqCst), 4);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn tcp_profiled_connector() {
+    use hyper_util::client::legacy::connect::{TcpProfile, TcpProfiled};
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let client = Client::builder(TokioExecutor::new())
+        .tcp_profile(TcpProfile::LowLatency)
+        .build(TcpProfiled::new(HttpConnector::new()));
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        while sock.read(&mut buf).map_or(false, |n| n > 0) {
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("write");
+        }
+    });
+
+    for profile in [None, Some(TcpProfile::Bulk)] {
+        let mut req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Full::new(Bytes::from_static(b"upload")))
+            .unwrap();
+        if let Some(profile) = profile {
+            req.extensions_mut().insert(profile);
+        }
+        let res = rt.block_on(client.request(req)).expect("200 OK");
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        rt.block_on(res.into_body().collect()).expect("body");
+    }
+}
+
+#[cfg(not(miri))]
+#[test]
+fn host_normalization_shares_connections() {
+    use hyper_util::client::legacy::HostNormalization;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while let Ok(n) = sock.read(&mut buf) {
+                    if n == 0 {
+                        break;
+                    }
+                    let _ = tx.send(s(&buf[..n]).to_owned());
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let send = |client: &Client<DebugConnector, Empty<Bytes>>, host: &str| {
+        let req = Request::builder()
+            .uri(&*format!("http://{}:{}/", host, addr.port()))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        let res = rt.block_on(client.request(req));
+        // let the connection go back to the pool
+        thread::sleep(Duration::from_millis(50));
+        res
+    };
+
+    send(&client, "LocalHost.").expect("200 OK");
+    let req = rx.recv().unwrap();
+    assert!(
+        req.contains(&format!("host: localhost:{}\r\n", addr.port())),
+        "{}",
+        req
+    );
+    send(&client, "localhost").expect("200 OK");
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+    let strict = Client::builder(TokioExecutor::new())
+        .host_normalization(HostNormalization::Strict)
+        .build(DebugConnector::new());
+    let err = send(&strict, "bad_host").unwrap_err();
+    assert!(err.is_invalid_host(), "{:?}", err);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn default_headers_do_not_clobber() {
+    use hyper::header::{HeaderMap, HeaderValue, ACCEPT, USER_AGENT};
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let mut defaults = HeaderMap::new();
+    defaults.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    defaults.append("x-multi", HeaderValue::from_static("1"));
+    defaults.append("x-multi", HeaderValue::from_static("2"));
+    let client = Client::builder(TokioExecutor::new())
+        .default_headers(defaults)
+        .user_agent(HeaderValue::from_static("test-agent/1.0"))
+        .build(DebugConnector::new());
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        while let Ok(n) = sock.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = tx.send(s(&buf[..n]).to_owned());
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("write");
+        }
+    });
+
+    let req = Request::builder()
+        .uri(&*format!("http://{}/a", addr))
+        .header(USER_AGENT, "explicit")
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    rt.block_on(client.request(req)).expect("200 OK");
+
+    let head = rx.recv().unwrap();
+    assert!(head.contains("user-agent: explicit\r\n"), "{}", head);
+    assert!(!head.contains("test-agent"), "{}", head);
+    assert!(head.contains("accept: application/json\r\n"), "{}", head);
+    assert!(head.contains("x-multi: 1\r\nx-multi: 2\r\n"), "{}", head);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn request_signer_sees_final_headers() {
+    use hyper::header::{HeaderValue, HOST};
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let client = Client::builder(TokioExecutor::new())
+        .request_signer(|parts| {
+            if parts.uri.path() == "/deny" {
+                return Err("no credentials".into());
+            }
+            let string_to_sign = format!(
+                "{} {} {:?}",
+                parts.method,
+                parts.uri,
+                parts.headers.get(HOST).expect("host is set")
+            );
+            parts
+                .headers
+                .insert("x-signature", HeaderValue::from_str(&string_to_sign)?);
+            Ok(())
+        })
+        .build(DebugConnector::new());
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        while let Ok(n) = sock.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let _ = tx.send(s(&buf[..n]).to_owned());
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("write");
+        }
+    });
+
+    let req = Request::builder()
+        .uri(&*format!("http://{}/a?b", addr))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    rt.block_on(client.request(req)).expect("200 OK");
+
+    let head = rx.recv().unwrap();
+    let expected = format!("x-signature: GET /a?b \"{}\"\r\n", addr);
+    assert!(head.contains(&expected), "{}", head);
+
+    let req = Request::builder()
+        .uri(&*format!("http://{}/deny", addr))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let err = rt.block_on(client.request(req)).unwrap_err();
+    assert!(err.is_request_signer(), "{:?}", err);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn require_protocol_rejects_fallback() {
+    use hyper_util::client::legacy::RequireProtocol;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+
+    let client = Client::builder(TokioExecutor::new()).build(connector);
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut buf = [0; 4096];
+        while sock.read(&mut buf).map_or(false, |n| n > 0) {
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("write");
+        }
+    });
+
+    let request = |required| {
+        let mut req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        req.extensions_mut().insert(required);
+        client.request(req)
+    };
+
+    // the connector doesn't negotiate h2, so this would have used HTTP/1
+    let err = rt.block_on(request(RequireProtocol::Http2)).unwrap_err();
+    assert!(err.is_unacceptable_protocol(), "{:?}", err);
+
+    rt.block_on(request(RequireProtocol::Http1))
+        .expect("200 OK");
+}
+
+#[cfg(not(miri))]
+#[test]
+fn dropped_request_cancels_dial_unless_waited_on() {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use http::Response;
+    use hyper::service::service_fn;
+    use tokio::net::TcpListener;
+
+    // Dials slowly, counting the dials that finish.
+    #[derive(Clone)]
+    struct SlowConnector {
+        inner: DebugConnector,
+        finished: Arc<AtomicUsize>,
+    }
+
+    impl tower_service::Service<hyper::Uri> for SlowConnector {
+        type Response = test_utils::DebugStream;
+        type Error = <DebugConnector as tower_service::Service<hyper::Uri>>::Error;
+        type Future =
+            Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+        fn poll_ready(&mut self, cx: &mut std::task::Context<'_>) -> Poll<Result<(), Self::Error>> {
+            self.inner.poll_ready(cx)
+        }
+
+        fn call(&mut self, dst: hyper::Uri) -> Self::Future {
+            let dial = self.inner.call(dst);
+            let finished = self.finished.clone();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                let stream = dial.await;
+                finished.fetch_add(1, Ordering::SeqCst);
+                stream
+            })
+        }
+    }
+
+    let _ = pretty_env_logger::try_init();
+    let rt = runtime();
+    let listener = rt
+        .block_on(TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))))
+        .unwrap();
+    let addr = listener.local_addr().unwrap();
+    rt.spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.expect("accept");
+            tokio::spawn(
+                hyper::server::conn::http2::Builder::new(TokioExecutor::new()).serve_connection(
+                    TokioIo::new(stream),
+                    service_fn(|_req| async move {
+                        Ok::<_, hyper::Error>(Response::new(Empty::<Bytes>::new()))
+                    }),
+                ),
+            );
+        }
+    });
+
+    let finished = Arc::new(AtomicUsize::new(0));
+    let client: Client<_, Empty<Bytes>> = Client::builder(TokioExecutor::new())
+        .http2_only(true)
+        .build(SlowConnector {
+            inner: DebugConnector::new(),
+            finished: finished.clone(),
+        });
+    let url = format!("http://{}/a", addr)
+        .parse::<::hyper::Uri>()
+        .unwrap();
+
+    // Nobody else needs the connection, so the dial is canceled.
+    rt.block_on(async {
+        let res = tokio::time::timeout(Duration::from_millis(20), client.get(url.clone())).await;
+        res.expect_err("timeout");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    });
+    assert_eq!(finished.load(Ordering::SeqCst), 0);
+
+    // The second request waits on the first one's HTTP/2 dial, so it is
+    // finished for it.
+    let (first, second) = rt.block_on(async {
+        let first = tokio::time::timeout(Duration::from_millis(20), client.get(url.clone()));
+        future::join(first, client.get(url)).await
+    });
+    first.expect_err("timeout");
+    second.expect("200 OK");
+    assert_eq!(finished.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn service_waits_for_connection_in_use() {
+    use tower_service::Service;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let (respond_tx, respond_rx) = std::sync::mpsc::channel::<()>();
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        while sock.read(&mut buf).map_or(false, |n| n > 0) {
+            respond_rx.recv().expect("respond");
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("write");
+        }
+    });
+
+    let mut service = Client::builder(TokioExecutor::new())
+        .pool_max_in_use(1)
+        .build_http::<Empty<Bytes>>()
+        .into_service();
+    let req = || {
+        Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::new())
+            .unwrap()
+    };
+
+    rt.block_on(async {
+        future::poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        let first = tokio::spawn(service.call(req()));
+
+        // The only connection is in use by the first request.
+        let ready = future::poll_fn(|cx| Poll::Ready(service.poll_ready(cx).is_ready())).await;
+        assert!(!ready);
+
+        respond_tx.send(()).unwrap();
+        first.await.unwrap().expect("200 OK");
+        let ready = future::poll_fn(|cx| service.poll_ready(cx));
+        tokio::time::timeout(Duration::from_secs(1), ready)
+            .await
+            .expect("ready once the connection is free")
+            .unwrap();
+    });
+}
+
+#[cfg(not(miri))]
+#[test]
+fn timings_record_request_phases() {
+    use hyper_util::client::legacy::Timings;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let client =
+        Client::builder(TokioExecutor::new()).build::<_, Empty<Bytes>>(DebugConnector::new());
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        while let Ok(n) = sock.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                .expect("write");
+        }
+    });
+
+    let uri: hyper::Uri = format!("http://localhost:{}/", addr.port())
+        .parse()
+        .unwrap();
+    let timings = rt.block_on(async {
+        let res = client.get(uri.clone()).await.expect("200 OK");
+        let timings = res.extensions().get::<Timings>().expect("timings").clone();
+        res.into_body().collect().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        timings
+    });
+    assert!(!timings.is_reused());
+    let dns_lookup = timings.dns_lookup().expect("dns lookup");
+    let connect = timings.connect().expect("connect");
+    assert!(timings.start() <= connect.start && connect.start <= dns_lookup.start);
+    assert!(dns_lookup.end <= connect.end && connect.end <= timings.acquired());
+    assert_eq!(timings.tls_handshake(), None);
+    assert!(timings.acquired() <= timings.request_start());
+    assert!(timings.request_start() <= timings.response_start());
+    let response_end = timings.response_end().expect("body was read");
+    assert!(timings.response_start() <= response_end);
+
+    let res = rt.block_on(client.get(uri)).expect("200 OK");
+    let timings = res.extensions().get::<Timings>().expect("timings");
+    assert!(timings.is_reused());
+    assert_eq!(timings.connect(), None);
+    assert_eq!(timings.dns_lookup(), None);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn error_reports_retryability_and_endpoint() {
+    let _ = pretty_env_logger::try_init();
+    let rt = runtime();
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+
+    // nothing listens once the listener is dropped
+    let refused = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let uri = format!("http://{}/", refused).parse().unwrap();
+    let err = rt.block_on(client.get(uri)).unwrap_err();
+    assert!(err.is_connect(), "{:?}", err);
+    assert!(err.is_retryable(), "{:?}", err);
+    assert!(!err.is_timeout(), "{:?}", err);
+    assert_eq!(err.remote_addr(), Some(refused));
+
+    // the connection closes before a response
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        let _ = sock.read(&mut buf);
+    });
+    let uri = format!("http://{}/", addr).parse().unwrap();
+    let err = rt.block_on(client.get(uri)).unwrap_err();
+    assert!(!err.is_connect(), "{:?}", err);
+    assert!(err.is_retryable(), "{:?}", err);
+    assert_eq!(err.remote_addr(), Some(addr));
+}
+
+#[cfg(not(miri))]
+#[test]
+fn http1_serial_shares_one_connection() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let client = Client::builder(TokioExecutor::new())
+        .http1_serial(true)
+        .build(connector);
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    thread::sleep(Duration::from_millis(20));
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let send = || {
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        client.request(req).and_then(|res| async move {
+            res.into_body().collect().await.unwrap();
+            Ok(())
+        })
+    };
+
+    // the requests queue for the connection instead of dialing
+    rt.block_on(async {
+        let tasks = (0..3).map(|_| tokio::spawn(send())).collect::<Vec<_>>();
+        for task in tasks {
+            task.await.unwrap().expect("200 OK");
+        }
+    });
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+}
+
+#[derive(Clone, Default)]
+struct SharedHints(Arc<Mutex<Vec<EndpointHint>>>);
+
+impl HintStore for SharedHints {
+    fn load(&self) -> Vec<EndpointHint> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn save(&self, hints: &[EndpointHint]) {
+        *self.0.lock().unwrap() = hints.to_vec();
+    }
+}
+
+#[cfg(not(miri))]
+#[test]
+fn preconnect_uses_saved_hints() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+
+    let store = SharedHints::default();
+    let hint = format!("http/1.1 http://{}", addr).parse().unwrap();
+    store.0.lock().unwrap().push(hint);
+    let client = Client::builder(TokioExecutor::new())
+        .hint_store(store.clone())
+        .build::<_, Empty<Bytes>>(connector);
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        while sock.read(&mut buf).map_or(false, |n| n > 0) {
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("write");
+        }
+    });
+
+    rt.block_on(async {
+        client.preconnect();
+        client.idle().await;
+        assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+        let req = Request::builder()
+            .uri(&*format!("http://{}/a", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        client.request(req).await.unwrap();
+    });
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+    store.0.lock().unwrap().clear();
+    client.save_hints();
+    let saved = store.load();
+    assert_eq!(saved.len(), 1);
+    assert!(!saved[0].is_http2());
+    assert_eq!(saved[0].origin(), &*format!("http://{}/", addr));
+}
+
+#[cfg(not(miri))]
+#[test]
+fn resolved_addrs_skip_dns() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+    let client = Client::builder(TokioExecutor::new()).build_http::<Empty<Bytes>>();
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        let n = sock.read(&mut buf).expect("read");
+        assert!(buf[..n].windows(18).any(|w| w == b"host: mesh.invalid"));
+        sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .expect("write");
+    });
+
+    // the name doesn't resolve, the addresses are used instead
+    let mut req = Request::builder()
+        .uri("http://mesh.invalid/a")
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    req.extensions_mut().insert(ResolvedAddrs::new(vec![addr]));
+    let res = rt.block_on(client.request(req)).unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn response_header_limits() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read");
+            let mut head = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n".to_vec();
+            if buf[..n].starts_with(b"GET /many ") {
+                for i in 0..50 {
+                    head.extend_from_slice(format!("x-{}: a\r\n", i).as_bytes());
+                }
+            } else if buf[..n].starts_with(b"GET /huge ") {
+                head.extend_from_slice(b"x-a: ");
+                head.extend_from_slice(&[b'a'; 32 * 1024]);
+                head.extend_from_slice(b"\r\n");
+            } else {
+                head.extend_from_slice(b"x-a: aaaaaaaa\r\nx-b: bbbbbbbb\r\n");
+            }
+            head.extend_from_slice(b"\r\n");
+            // the client may close before reading it all
+            let _ = sock.write_all(&head);
+        }
+    });
+    let uri = |path: &str| -> hyper::Uri { format!("http://{}{}", addr, path).parse().unwrap() };
+
+    let client = Client::builder(TokioExecutor::new())
+        .max_response_headers(2)
+        .build_http::<Empty<Bytes>>();
+    let err = rt.block_on(client.get(uri("/"))).unwrap_err();
+    assert!(err.is_response_headers_too_large(), "{:?}", err);
+    // hyper fails to parse a head with far more fields
+    let err = rt.block_on(client.get(uri("/many"))).unwrap_err();
+    assert!(err.is_response_headers_too_large(), "{:?}", err);
+
+    let client = Client::builder(TokioExecutor::new())
+        .max_response_header_size(100)
+        .build_http::<Empty<Bytes>>();
+    let err = rt.block_on(client.get(uri("/"))).unwrap_err();
+    assert!(err.is_response_headers_too_large(), "{:?}", err);
+    assert!(!err.is_retryable(), "{:?}", err);
+    // or larger than the read buffer bounded by the limit
+    let err = rt.block_on(client.get(uri("/huge"))).unwrap_err();
+    assert!(err.is_response_headers_too_large(), "{:?}", err);
+    let parse = std::error::Error::source(&err)
+        .and_then(|err| err.source())
+        .and_then(|err| err.downcast_ref::<hyper::Error>())
+        .expect("hyper error");
+    assert!(parse.is_parse_too_large(), "{:?}", parse);
+
+    let client = Client::builder(TokioExecutor::new())
+        .max_response_headers(3)
+        .max_response_header_size(200)
+        .build_http::<Empty<Bytes>>();
+    let res = rt.block_on(client.get(uri("/"))).unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn informational_responses_reach_callback() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        sock.read(&mut buf).expect("read");
+        sock.write_all(
+            b"HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n\
+              HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        )
+        .expect("write");
+    });
+
+    let client = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let hints = Arc::new(Mutex::new(Vec::new()));
+    let mut req = Request::builder()
+        .uri(&*format!("http://{}/a", addr))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let seen = hints.clone();
+    hyper::ext::on_informational(&mut req, move |res| {
+        let link = res.headers()["link"].to_str().unwrap().to_owned();
+        seen.lock().unwrap().push((res.status(), link));
+    });
+
+    let res = rt.block_on(client.request(req)).unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+    assert_eq!(
+        *hints.lock().unwrap(),
+        [(
+            hyper::StatusCode::from_u16(103).unwrap(),
+            "</style.css>; rel=preload".to_owned()
+        )]
+    );
+}
+
+#[cfg(not(miri))]
+#[test]
+fn base_uri_resolves_relative_requests() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        for _ in 0..2 {
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).expect("read");
+            let line = s(&buf[..n]).lines().next().unwrap().to_owned();
+            tx.send(line).unwrap();
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .expect("write");
+        }
+    });
+
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+    let client = Client::builder(TokioExecutor::new())
+        .build::<_, Empty<Bytes>>(connector)
+        .with_base_uri(format!("http://{}/v2/", addr).parse().unwrap());
+
+    let res = rt
+        .block_on(client.get("/users?page=2".parse().unwrap()))
+        .unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+    assert_eq!(rx.recv().unwrap(), "GET /v2/users?page=2 HTTP/1.1");
+
+    // an absolute URI to the base is sent as is, on the same connection
+    let res = rt
+        .block_on(client.get(format!("http://{}/health", addr).parse().unwrap()))
+        .unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::OK);
+    assert_eq!(rx.recv().unwrap(), "GET /health HTTP/1.1");
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+    let err = rt
+        .block_on(client.get("https://example.com/users".parse().unwrap()))
+        .unwrap_err();
+    assert!(err.is_base_uri_mismatch(), "{:?}", err);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn expectation_reaches_response() {
+    use hyper_util::body::collect_with_limit;
+    use hyper_util::client::legacy::Expectation;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        sock.read(&mut buf).expect("read");
+        sock.write_all(
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n",
+        )
+        .expect("write");
+    });
+
+    let client = Client::builder(TokioExecutor::new()).build(HttpConnector::new());
+    let mut req = Request::builder()
+        .uri(&*format!("http://{}/a", addr))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    let expectation = Expectation::new().with_response_size(5);
+    req.extensions_mut().insert(expectation);
+
+    let body = rt.block_on(async {
+        let res = client.request(req).await.unwrap();
+        assert_eq!(res.extensions().get::<Expectation>(), Some(&expectation));
+        let size = expectation.response_size().unwrap();
+        collect_with_limit(res.into_body(), 1024, None)
+            .expected_size(size)
+            .await
+            .unwrap()
+    });
+    assert_eq!(body, "hello");
+}
+
+#[cfg(not(miri))]
+#[test]
+fn partitions_isolate_pooled_connections() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+    let client = Client::builder(TokioExecutor::new())
+        .pool_max_in_use_per_partition(1)
+        .build::<_, Empty<Bytes>>(connector);
+    let uri = || -> hyper::Uri { format!("http://{}/", addr).parse().unwrap() };
+
+    let get = |client: &Client<DebugConnector, Empty<Bytes>>| {
+        let res = rt.block_on(client.get(uri())).unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+        // let the connection go back to the pool
+        thread::sleep(Duration::from_millis(50));
+    };
+    get(&client.partition("tenant-a"));
+    get(&client.partition("tenant-a"));
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+
+    get(&client.partition("tenant-b"));
+    get(&client);
+    assert_eq!(connects.load(Ordering::SeqCst), 3, "a connection each");
+
+    let dump = client.pool_dump().to_string();
+    assert!(dump.contains("<tenant-a>"), "{}", dump);
+    assert!(dump.contains("<tenant-b>"), "{}", dump);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn checkout_sends_on_pooled_connection() {
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        while sock.read(&mut buf).map_or(false, |n| n > 0) {
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")
+                .expect("write");
+        }
+    });
+
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+    let client = Client::builder(TokioExecutor::new()).build::<_, Empty<Bytes>>(connector);
+    let uri: hyper::Uri = format!("http://{}/", addr).parse().unwrap();
+
+    rt.block_on(async {
+        let mut conn = client.checkout(uri.clone()).await.unwrap();
+        assert!(conn.is_http1());
+        assert!(!conn.is_reused());
+        for path in ["/a", "/b"] {
+            conn.ready().await.unwrap();
+            let req = Request::builder()
+                .uri(path)
+                .header("host", addr.to_string())
+                .body(Empty::new())
+                .unwrap();
+            let res = conn.send_request(req).await.unwrap();
+            assert_eq!(res.into_body().collect().await.unwrap().to_bytes(), "hello");
+        }
+        drop(conn);
+
+        // back in the pool for the requests of the client
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let res = client.get(uri).await.unwrap();
+        assert_eq!(res.status(), hyper::StatusCode::OK);
+    });
+    assert_eq!(connects.load(Ordering::SeqCst), 1);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn selected_connectors_have_their_own_connections() {
+    use hyper_util::client::legacy::connect::{SelectConnector, Selecting};
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let direct = DebugConnector::new();
+    let direct_connects = direct.connects.clone();
+    let backup = DebugConnector::new();
+    let backup_connects = backup.connects.clone();
+    let client = Client::builder(TokioExecutor::new())
+        .build::<_, Empty<Bytes>>(Selecting::new(direct).connector("backup", backup));
+
+    let get = |select: Option<&str>| {
+        let mut req = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        if let Some(select) = select {
+            req.extensions_mut().insert(SelectConnector::new(select));
+        }
+        let res = rt.block_on(client.request(req));
+        // let the connection go back to the pool
+        thread::sleep(Duration::from_millis(50));
+        res
+    };
+    get(None).unwrap();
+    get(Some("backup")).unwrap();
+    get(Some("backup")).unwrap();
+    get(None).unwrap();
+    assert_eq!(direct_connects.load(Ordering::SeqCst), 1);
+    assert_eq!(backup_connects.load(Ordering::SeqCst), 1);
+
+    let err = get(Some("uds")).unwrap_err();
+    assert!(err.is_connect(), "{:?}", err);
+    let dump = client.pool_dump().to_string();
+    assert!(dump.contains("via backup"), "{}", dump);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn on_response_head_runs_before_the_body() {
+    use hyper_util::client::legacy::{OnResponseHead, Timings};
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let (body_tx, body_rx) = std::sync::mpsc::channel::<()>();
+    thread::spawn(move || {
+        let mut sock = server.accept().unwrap().0;
+        let mut buf = [0; 4096];
+        sock.read(&mut buf).expect("read");
+        sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\n")
+            .expect("write");
+        body_rx.recv().expect("body");
+        sock.write_all(b"hello").expect("write");
+    });
+
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let seen = heads.clone();
+    let client = Client::builder(TokioExecutor::new()).build(DebugConnector::new());
+    let mut req = Request::builder()
+        .uri(format!("http://{}/", addr))
+        .body(Empty::<Bytes>::new())
+        .unwrap();
+    req.extensions_mut().insert(OnResponseHead::new(move |res| {
+        assert!(res.extensions().get::<Timings>().is_some(), "timings");
+        seen.lock().unwrap().push(res.status());
+    }));
+
+    rt.block_on(async {
+        let res = client.request(req).await.unwrap();
+        assert_eq!(heads.lock().unwrap().len(), 1, "called before the body");
+        body_tx.send(()).unwrap();
+        let body = res.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "hello");
+    });
+    let heads = heads.lock().unwrap();
+    assert_eq!(heads[0], hyper::StatusCode::OK);
+}
+
+#[cfg(not(miri))]
+#[test]
+fn disposition_overrides_connection_reuse() {
+    use hyper_util::client::legacy::{Disposition, OnResponseHead};
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                while sock.read(&mut buf).map_or(false, |n| n > 0) {
+                    // asks the client not to reuse the connection
+                    sock.write_all(
+                        b"HTTP/1.1 200 OK\r\nKeep-Alive: timeout=30, max=0\r\nContent-Length: 0\r\n\r\n",
+                    )
+                    .expect("write");
+                }
+            });
+        }
+    });
+
+    let connector = DebugConnector::new();
+    let connects = connector.connects.clone();
+    let client = Client::builder(TokioExecutor::new()).build::<_, Empty<Bytes>>(connector);
+    let url = format!("http://{}/", addr).parse::<::hyper::Uri>().unwrap();
+    let get = |dispose: fn(&Disposition)| {
+        let mut req = Request::builder()
+            .uri(url.clone())
+            .body(Empty::<Bytes>::new())
+            .unwrap();
+        // the response has no body, so the client is done with the
+        // connection before returning the response
+        req.extensions_mut().insert(OnResponseHead::new(move |res| {
+            dispose(res.extensions().get::<Disposition>().expect("disposition"))
+        }));
+        rt.block_on(client.request(req)).unwrap();
+        // let the connection go back to the pool
+        thread::sleep(Duration::from_millis(50));
+    };
+
+    get(|_| ());
+    get(Disposition::return_to_pool);
+    assert_eq!(connects.load(Ordering::SeqCst), 2, "not reused by default");
+    get(|disposition| disposition.poison("garbled"));
+    assert_eq!(connects.load(Ordering::SeqCst), 2, "returned");
+    get(|_| ());
+    assert_eq!(connects.load(Ordering::SeqCst), 3, "poisoned");
+}
+
+#[cfg(not(miri))]
+#[test]
+fn early_data_holds_back_unsafe_requests() {
+    use hyper_util::client::legacy::connect::EarlyDataState;
+
+    let _ = pretty_env_logger::try_init();
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    let rt = runtime();
+
+    let state = EarlyDataState::new();
+    let rejected = EarlyDataState::new();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let (seen2, rejected2) = (seen.clone(), rejected.clone());
+    thread::spawn(move || {
+        for sock in server.incoming() {
+            let mut sock = sock.unwrap();
+            let (seen, rejected) = (seen2.clone(), rejected2.clone());
+            thread::spawn(move || {
+                let mut buf = [0; 4096];
+                loop {
+                    let n = sock.read(&mut buf).expect("read");
+                    if n == 0 {
+                        return;
+                    }
+                    let head = String::from_utf8_lossy(&buf[..n]);
+                    let line = head.lines().next().unwrap_or("").to_owned();
+                    if line.starts_with("GET /reject") {
+                        // the connector fails the connection
+                        rejected.reject();
+                        return;
+                    }
+                    seen.lock().unwrap().push(line);
+                    sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .expect("write");
+                }
+            });
+        }
+    });
+
+    let mut connector = DebugConnector::new();
+    connector.early_data = Some(state.clone());
+    let client = Client::builder(TokioExecutor::new()).build::<_, Full<Bytes>>(connector);
+    let url = |path: &str| {
+        format!("http://{}{}", addr, path)
+            .parse::<hyper::Uri>()
+            .unwrap()
+    };
+
+    rt.block_on(client.get(url("/safe"))).unwrap();
+    let post = Request::post(url("/unsafe"))
+        .body(Full::new(Bytes::from("body")))
+        .unwrap();
+    let post = rt.spawn(client.request(post));
+    thread::sleep(Duration::from_millis(100));
+    assert_eq!(*seen.lock().unwrap(), ["GET /safe HTTP/1.1"]);
+    state.accept();
+    rt.block_on(post).unwrap().unwrap();
+    assert_eq!(seen.lock().unwrap()[1], "POST /unsafe HTTP/1.1");
+
+    let mut connector = DebugConnector::new();
+    connector.early_data = Some(rejected);
+    let client = Client::builder(TokioExecutor::new()).build::<_, Full<Bytes>>(connector);
+    let err = rt.block_on(client.get(url("/reject"))).unwrap_err();
+    assert!(err.is_early_data_rejected(), "{:?}", err);
+    assert!(err.is_retryable(), "{:?}", err);
+
+    // a handshake that never ends, on a connection the server closes
+    let closing = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closing_addr = closing.local_addr().unwrap();
+    thread::spawn(move || {
+        for sock in closing.incoming() {
+            drop(sock);
+        }
+    });
+    let mut connector = DebugConnector::new();
+    connector.early_data = Some(EarlyDataState::new());
+    let client = Client::builder(TokioExecutor::new()).build::<_, Full<Bytes>>(connector);
+    let post = Request::post(format!("http://{}/unsafe", closing_addr))
+        .body(Full::new(Bytes::from("body")))
+        .unwrap();
+    let err = rt
+        .block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), client.request(post)).await
+        })
+        .expect("not held back forever")
+        .unwrap_err();
+    assert!(err.is_retryable(), "{:?}", err);
+}
@@ -13,7 +13,7 @@ use tokio::net::TcpStream;
 use hyper::rt::ReadBufCursor;
 
 use hyper_util::client::legacy::connect::HttpConnector;
-use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::client::legacy::connect::{Connected, Connection, EarlyDataState};
 use hyper_util::rt::TokioIo;
 
 #[derive(Clone)]
@@ -23,6 +23,7 @@ pub struct DebugConnector {
     pub connects: Arc<AtomicUsize>,
     pub is_proxy: bool,
     pub alpn_h2: bool,
+    pub early_data: Option<EarlyDataState>,
 }
 
 impl DebugConnector {
@@ -39,6 +40,7 @@ impl DebugConnector {
             connects: Arc::new(AtomicUsize::new(0)),
             is_proxy: false,
             alpn_h2: false,
+            early_data: None,
         }
     }
 
@@ -63,11 +65,13 @@ impl tower_service::Service<Uri> for DebugConnector {
         let closes = self.closes.clone();
         let is_proxy = self.is_proxy;
         let is_alpn_h2 = self.alpn_h2;
+        let early_data = self.early_data.clone();
         Box::pin(self.http.call(dst).map_ok(move |tcp| DebugStream {
             tcp,
             on_drop: closes,
             is_alpn_h2,
             is_proxy,
+            early_data,
         }))
     }
 }
@@ -77,6 +81,7 @@ pub struct DebugStream {
     on_drop: mpsc::Sender<()>,
     is_alpn_h2: bool,
     is_proxy: bool,
+    early_data: Option<EarlyDataState>,
 }
 
 impl Drop for DebugStream {
@@ -87,7 +92,10 @@ impl Drop for DebugStream {
 
 impl Connection for DebugStream {
     fn connected(&self) -> Connected {
-        let connected = self.tcp.connected().proxy(self.is_proxy);
+        let mut connected = self.tcp.connected().proxy(self.is_proxy);
+        if let Some(ref early_data) = self.early_data {
+            connected = connected.early_data(early_data.clone());
+        }
 
         if self.is_alpn_h2 {
             connected.negotiated_h2()